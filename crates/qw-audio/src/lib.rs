@@ -70,6 +70,8 @@ pub struct AudioSystem {
     next_id: u32,
     listener_pos: [f32; 3],
     listener_right: [f32; 3],
+    // The volume cvar, over everything that plays.
+    volume: f32,
     voices: HashMap<SoundId, Voice>,
 }
 
@@ -81,6 +83,7 @@ impl AudioSystem {
             next_id: 1,
             listener_pos: [0.0; 3],
             listener_right: [1.0, 0.0, 0.0],
+            volume: 1.0,
             voices: HashMap::new(),
         }
     }
@@ -123,6 +126,14 @@ impl AudioSystem {
         self.listener_right = normalize(right).unwrap_or([1.0, 0.0, 0.0]);
     }
 
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = volume.clamp(0.0, 1.0);
+    }
+
+    pub fn volume(&self) -> f32 {
+        self.volume
+    }
+
     pub fn mix(&mut self, frames: usize) -> Vec<i16> {
         let channels = self.config.channels as usize;
         let mut output = vec![0f32; frames * channels];
@@ -160,9 +171,9 @@ impl AudioSystem {
                     sample_l
                 };
 
-                output[out_index] += sample_l * left_gain;
+                output[out_index] += sample_l * left_gain * self.volume;
                 if channels > 1 {
-                    output[out_index + 1] += sample_r * right_gain;
+                    output[out_index + 1] += sample_r * right_gain * self.volume;
                 }
 
                 voice.cursor += 1;
@@ -337,6 +348,28 @@ mod tests {
         assert_ne!(first, second);
     }
 
+    #[test]
+    fn volume_scales_the_mix() {
+        let mut audio = AudioSystem::new(AudioConfig::default());
+        let clip = Arc::new(AudioClip {
+            sample_rate: audio.config().sample_rate,
+            channels: 1,
+            samples: vec![1000],
+        });
+        let params = PlayParams {
+            attenuation: 0.0,
+            ..PlayParams::default()
+        };
+        audio.play_clip(clip.clone(), params).unwrap();
+        let full = audio.mix(1);
+        audio.set_volume(0.5);
+        audio.play_clip(clip, params).unwrap();
+        let half = audio.mix(1);
+        assert_eq!(i32::from(half[0]) * 2, i32::from(full[0]));
+        audio.set_volume(3.0);
+        assert_eq!(audio.volume(), 1.0);
+    }
+
     #[test]
     fn decodes_pcm16_wav() {
         let mut data = Vec::new();
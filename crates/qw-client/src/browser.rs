@@ -0,0 +1,451 @@
+// Server browser: asks master servers for addresses, then every listed server
+// for its status at once over one socket.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::time::{Duration, Instant};
+
+use qw_common::{
+    A2C_PRINT, C2M_MASTER_SERVER_LIST, M2C_MASTER_REPLY, Transport, UdpTransport,
+    build_out_of_band, out_of_band_payload, value_for_key,
+};
+
+// Long enough for a distant server, short enough that a dead one doesn't hold
+// up the list.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlayerInfo {
+    pub userid: i32,
+    pub frags: i32,
+    pub time: i32,
+    pub ping: i32,
+    pub name: String,
+    pub skin: String,
+    pub topcolor: i32,
+    pub bottomcolor: i32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerInfo {
+    pub addr: SocketAddr,
+    pub info: String,
+    pub players: Vec<PlayerInfo>,
+    pub ping: Duration,
+}
+
+impl ServerInfo {
+    pub fn hostname(&self) -> String {
+        value_for_key(&self.info, "hostname").unwrap_or_else(|| self.addr.to_string())
+    }
+
+    pub fn map(&self) -> String {
+        value_for_key(&self.info, "map").unwrap_or_default()
+    }
+
+    // Servers without a *gamedir are running plain QuakeWorld.
+    pub fn gamedir(&self) -> String {
+        value_for_key(&self.info, "*gamedir").unwrap_or_else(|| "qw".to_string())
+    }
+
+    pub fn maxclients(&self) -> usize {
+        value_for_key(&self.info, "maxclients")
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SortKey {
+    #[default]
+    Ping,
+    Players,
+    Map,
+    Name,
+}
+
+impl SortKey {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "ping" => Some(SortKey::Ping),
+            "players" => Some(SortKey::Players),
+            "map" => Some(SortKey::Map),
+            "name" => Some(SortKey::Name),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ServerFilter {
+    // Part of the map name.
+    pub map: Option<String>,
+    pub gamedir: Option<String>,
+    pub min_players: usize,
+    pub hide_full: bool,
+}
+
+impl ServerFilter {
+    pub fn matches(&self, server: &ServerInfo) -> bool {
+        if let Some(map) = &self.map
+            && !server
+                .map()
+                .to_ascii_lowercase()
+                .contains(&map.to_ascii_lowercase())
+        {
+            return false;
+        }
+        if let Some(gamedir) = &self.gamedir
+            && !server.gamedir().eq_ignore_ascii_case(gamedir)
+        {
+            return false;
+        }
+        let players = server.players.len();
+        if players < self.min_players {
+            return false;
+        }
+        !(self.hide_full && server.maxclients() > 0 && players >= server.maxclients())
+    }
+}
+
+pub struct ServerBrowser<T: Transport = UdpTransport> {
+    transport: T,
+    masters: Vec<SocketAddr>,
+    started: Instant,
+    // Status requests still in flight, by when they went out.
+    pending: HashMap<SocketAddr, Instant>,
+    servers: HashMap<SocketAddr, ServerInfo>,
+}
+
+impl ServerBrowser<UdpTransport> {
+    pub fn open(masters: Vec<SocketAddr>) -> io::Result<Self> {
+        Ok(Self::new(UdpTransport::bind("0.0.0.0:0")?, masters))
+    }
+}
+
+impl<T: Transport> ServerBrowser<T> {
+    pub fn new(transport: T, masters: Vec<SocketAddr>) -> Self {
+        Self {
+            transport,
+            masters,
+            started: Instant::now(),
+            pending: HashMap::new(),
+            servers: HashMap::new(),
+        }
+    }
+
+    // Starts over: forgets the last results and asks every master again.
+    pub fn refresh(&mut self) -> io::Result<()> {
+        self.started = Instant::now();
+        self.pending.clear();
+        self.servers.clear();
+        for master in &self.masters {
+            let packet = build_out_of_band(&[C2M_MASTER_SERVER_LIST, b'\n']);
+            self.transport.send_to(packet, *master)?;
+        }
+        Ok(())
+    }
+
+    // Adds a server to the list without a master, as for a LAN address.
+    pub fn query(&mut self, addr: SocketAddr) -> io::Result<()> {
+        if self.pending.contains_key(&addr) || self.servers.contains_key(&addr) {
+            return Ok(());
+        }
+        let packet = build_out_of_band(b"status\n");
+        self.transport.send_to(packet, addr)?;
+        self.pending.insert(addr, Instant::now());
+        Ok(())
+    }
+
+    // Takes whatever has arrived without waiting, and drops servers that
+    // haven't answered in time. A listed server that can't be sent to is
+    // skipped, and what went wrong comes back for the console.
+    pub fn poll(&mut self) -> io::Result<Vec<String>> {
+        let mut skipped = Vec::new();
+        let mut buf = [0u8; 8192];
+        while let Some((packet, from)) = self.transport.recv_from(&mut buf, Duration::ZERO)? {
+            let Some(payload) = out_of_band_payload(&packet) else {
+                continue;
+            };
+            match payload.first() {
+                Some(&M2C_MASTER_REPLY) if self.masters.contains(&from) => {
+                    for addr in parse_master_reply(&payload[1..]) {
+                        if let Err(err) = self.query(addr) {
+                            skipped.push(format!("can't query {addr}: {err}"));
+                        }
+                    }
+                }
+                Some(&A2C_PRINT) => {
+                    let Some(sent) = self.pending.remove(&from) else {
+                        continue;
+                    };
+                    if let Some((info, players)) = parse_status(&payload[1..]) {
+                        let server = ServerInfo {
+                            addr: from,
+                            info,
+                            players,
+                            ping: sent.elapsed(),
+                        };
+                        self.servers.insert(from, server);
+                    }
+                }
+                _ => {}
+            }
+        }
+        self.pending
+            .retain(|_, sent| sent.elapsed() < QUERY_TIMEOUT);
+        Ok(skipped)
+    }
+
+    // Done once the masters have had their chance and no server is still
+    // being waited on.
+    pub fn is_finished(&self) -> bool {
+        self.started.elapsed() >= QUERY_TIMEOUT && self.pending.is_empty()
+    }
+
+    pub fn servers(&self, filter: &ServerFilter, sort: SortKey) -> Vec<&ServerInfo> {
+        let mut servers: Vec<&ServerInfo> = self
+            .servers
+            .values()
+            .filter(|server| filter.matches(server))
+            .collect();
+        match sort {
+            SortKey::Ping => servers.sort_by_key(|server| server.ping),
+            SortKey::Players => {
+                servers.sort_by_key(|server| std::cmp::Reverse(server.players.len()))
+            }
+            SortKey::Map => servers.sort_by_key(|server| server.map()),
+            SortKey::Name => servers.sort_by_key(|server| server.hostname().to_ascii_lowercase()),
+        }
+        servers
+    }
+}
+
+// Six bytes a server: the address, then the port in network order.
+fn parse_master_reply(data: &[u8]) -> Vec<SocketAddr> {
+    let data = data.strip_prefix(b"\n").unwrap_or(data);
+    data.chunks_exact(6)
+        .map(|entry| {
+            let ip = Ipv4Addr::new(entry[0], entry[1], entry[2], entry[3]);
+            let port = u16::from_be_bytes([entry[4], entry[5]]);
+            SocketAddr::V4(SocketAddrV4::new(ip, port))
+        })
+        .filter(|addr| addr.port() != 0)
+        .collect()
+}
+
+// SVC_Status: the serverinfo on the first line, then a line per player.
+fn parse_status(data: &[u8]) -> Option<(String, Vec<PlayerInfo>)> {
+    let end = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+    let text = String::from_utf8_lossy(&data[..end]);
+    let mut lines = text.lines();
+    let info = lines.next()?.to_string();
+    if !info.starts_with('\\') {
+        return None;
+    }
+    let players = lines.filter_map(parse_player).collect();
+    Some((info, players))
+}
+
+// `userid frags time ping "name" "skin" topcolor bottomcolor`
+fn parse_player(line: &str) -> Option<PlayerInfo> {
+    let tokens = qw_common::com_tokenize(line);
+    let number = |index: usize| tokens.get(index)?.parse::<i32>().ok();
+    Some(PlayerInfo {
+        userid: number(0)?,
+        frags: number(1)?,
+        time: number(2)?,
+        ping: number(3)?,
+        name: tokens.get(4)?.clone(),
+        skin: tokens.get(5).cloned().unwrap_or_default(),
+        topcolor: number(6).unwrap_or(0),
+        bottomcolor: number(7).unwrap_or(0),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::borrow::Cow;
+
+    fn status_reply(info: &str, players: &[&str]) -> Vec<u8> {
+        let mut text = format!("n{info}\n");
+        for player in players {
+            text.push_str(player);
+            text.push('\n');
+        }
+        build_out_of_band(text.as_bytes())
+    }
+
+    fn master_reply(servers: &[SocketAddr]) -> Vec<u8> {
+        let mut reply = vec![M2C_MASTER_REPLY, b'\n'];
+        for addr in servers {
+            let SocketAddr::V4(addr) = addr else {
+                unreachable!();
+            };
+            reply.extend_from_slice(&addr.ip().octets());
+            reply.extend_from_slice(&addr.port().to_be_bytes());
+        }
+        build_out_of_band(&reply)
+    }
+
+    // Answers one request on each fake, if one has arrived.
+    fn serve(socket: &mut UdpTransport, reply: &[u8], expect: &[u8]) {
+        let mut buf = [0u8; 1024];
+        if let Some((packet, from)) = socket.recv_from(&mut buf, Duration::ZERO).unwrap() {
+            assert_eq!(out_of_band_payload(&packet), Some(expect));
+            socket.send_to(reply.to_vec(), from).unwrap();
+        }
+    }
+
+    #[test]
+    fn queries_a_master_then_each_server() {
+        let mut master = UdpTransport::bind("127.0.0.1:0").unwrap();
+        let mut dm = UdpTransport::bind("127.0.0.1:0").unwrap();
+        let mut ctf = UdpTransport::bind("127.0.0.1:0").unwrap();
+        let dead = UdpTransport::bind("127.0.0.1:0").unwrap();
+        let servers = [
+            dm.local_addr().unwrap(),
+            ctf.local_addr().unwrap(),
+            dead.local_addr().unwrap(),
+        ];
+        let master_reply = master_reply(&servers);
+        let dm_reply = status_reply(
+            "\\hostname\\Deathmatch\\map\\dm6\\maxclients\\2",
+            &[
+                "12 30 5 48 \"player one\" \"base\" 4 13",
+                "13 -1 2 120 \"two\" \"\" 0 0",
+            ],
+        );
+        let ctf_reply = status_reply("\\hostname\\Flags\\map\\e1m2\\*gamedir\\ctf", &[]);
+
+        let mut browser = ServerBrowser::open(vec![master.local_addr().unwrap()]).unwrap();
+        browser.refresh().unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while browser
+            .servers(&ServerFilter::default(), SortKey::Ping)
+            .len()
+            < 2
+        {
+            assert!(Instant::now() < deadline, "servers never answered");
+            serve(&mut master, &master_reply, b"c\n");
+            serve(&mut dm, &dm_reply, b"status\n");
+            serve(&mut ctf, &ctf_reply, b"status\n");
+            browser.poll().unwrap();
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert!(!browser.is_finished());
+
+        let all = browser.servers(&ServerFilter::default(), SortKey::Players);
+        assert_eq!(all[0].hostname(), "Deathmatch");
+        assert_eq!(all[0].players[0].name, "player one");
+        assert_eq!(all[0].players[1].frags, -1);
+        assert_eq!(all[0].players[1].ping, 120);
+        assert_eq!(all[1].gamedir(), "ctf");
+
+        let by_name = browser.servers(&ServerFilter::default(), SortKey::Name);
+        assert_eq!(by_name[1].hostname(), "Flags");
+        let ctf_only = ServerFilter {
+            gamedir: Some("CTF".to_string()),
+            ..ServerFilter::default()
+        };
+        assert_eq!(browser.servers(&ctf_only, SortKey::Ping).len(), 1);
+        let dm_maps = ServerFilter {
+            map: Some("dm".to_string()),
+            ..ServerFilter::default()
+        };
+        assert_eq!(browser.servers(&dm_maps, SortKey::Ping)[0].map(), "dm6");
+        let open_slots = ServerFilter {
+            hide_full: true,
+            ..ServerFilter::default()
+        };
+        assert_eq!(
+            browser.servers(&open_slots, SortKey::Ping)[0].hostname(),
+            "Flags"
+        );
+        let populated = ServerFilter {
+            min_players: 1,
+            ..ServerFilter::default()
+        };
+        assert_eq!(browser.servers(&populated, SortKey::Ping).len(), 1);
+    }
+
+    // Hands over the queued packets, and can't reach one address.
+    struct FakeTransport {
+        inbound: Vec<(Vec<u8>, SocketAddr)>,
+        unreachable: SocketAddr,
+        sent: Vec<SocketAddr>,
+    }
+
+    impl Transport for FakeTransport {
+        fn send_to(&mut self, _packet: Vec<u8>, addr: SocketAddr) -> io::Result<()> {
+            if addr == self.unreachable {
+                return Err(io::Error::new(
+                    io::ErrorKind::HostUnreachable,
+                    "no route to host",
+                ));
+            }
+            self.sent.push(addr);
+            Ok(())
+        }
+
+        fn recv_from<'a>(
+            &mut self,
+            _buf: &'a mut [u8],
+            _wait: Duration,
+        ) -> io::Result<Option<(Cow<'a, [u8]>, SocketAddr)>> {
+            Ok(self
+                .inbound
+                .pop()
+                .map(|(packet, from)| (Cow::Owned(packet), from)))
+        }
+
+        fn local_addr(&self) -> io::Result<SocketAddr> {
+            Ok(SocketAddr::from(([127, 0, 0, 1], 27001)))
+        }
+    }
+
+    #[test]
+    fn skips_servers_it_cannot_send_to() {
+        let master = SocketAddr::from(([10, 0, 0, 1], 27000));
+        let unreachable = SocketAddr::from(([10, 0, 0, 2], 27500));
+        let reachable = SocketAddr::from(([10, 0, 0, 3], 27500));
+        let transport = FakeTransport {
+            inbound: vec![(master_reply(&[unreachable, reachable]), master)],
+            unreachable,
+            sent: Vec::new(),
+        };
+        let mut browser = ServerBrowser::new(transport, vec![master]);
+        let skipped = browser.poll().unwrap();
+        assert_eq!(skipped, ["can't query 10.0.0.2:27500: no route to host"]);
+        assert_eq!(browser.transport.sent, [reachable]);
+        assert!(browser.pending.contains_key(&reachable));
+    }
+
+    #[test]
+    fn parses_master_replies_with_or_without_the_newline() {
+        let addrs = parse_master_reply(&[b'\n', 10, 0, 0, 1, 0x6b, 0x6c, 1, 2, 3, 4, 0, 0]);
+        assert_eq!(addrs, ["10.0.0.1:27500".parse::<SocketAddr>().unwrap()]);
+        let addrs = parse_master_reply(&[192, 168, 1, 2, 0x6b, 0x6d]);
+        assert_eq!(addrs, ["192.168.1.2:27501".parse::<SocketAddr>().unwrap()]);
+    }
+
+    #[test]
+    fn ignores_prints_from_servers_it_did_not_ask() {
+        let mut stranger = UdpTransport::bind("127.0.0.1:0").unwrap();
+        let mut browser = ServerBrowser::open(Vec::new()).unwrap();
+        let port = browser.transport.local_addr().unwrap().port();
+        let reply = status_reply("\\hostname\\Spoof", &[]);
+        stranger
+            .send_to(reply, SocketAddr::from(([127, 0, 0, 1], port)))
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        browser.poll().unwrap();
+        assert!(
+            browser
+                .servers(&ServerFilter::default(), SortKey::Ping)
+                .is_empty()
+        );
+    }
+}
@@ -1,20 +1,24 @@
 use std::fmt;
 
-use qw_common::NetAddr;
+use crate::session::Protocol;
+use qw_common::{NQ_DEFAULT_PORT, NetAddr, netsim_cvar, valid_netsim_value};
 
 pub const DEFAULT_SERVER_PORT: u16 = 27500;
 pub const DEFAULT_QPORT: u16 = 27001;
+pub const DEFAULT_MAP: &str = "start";
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum ClientMode {
     QuakeWorld,
     SinglePlayer,
+    Demo,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ClientArgs {
     pub mode: ClientMode,
     pub server: Option<NetAddr>,
+    pub protocol: Protocol,
     pub qport: u16,
     pub name: Option<String>,
     pub topcolor: Option<String>,
@@ -22,11 +26,18 @@ pub struct ClientArgs {
     pub rate: Option<String>,
     pub data_dir: Option<String>,
     pub download_dir: Option<String>,
+    pub map: String,
+    pub skill: Option<u8>,
+    pub demo: Option<String>,
+    pub timedemo: bool,
+    pub headless: bool,
+    // netsim_* cvars set from --netsim-* flags, in order.
+    pub netsim: Vec<(String, String)>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum CliAction {
-    Run(ClientArgs),
+    Run(Box<ClientArgs>),
     Help,
 }
 
@@ -53,7 +64,8 @@ pub fn usage() -> &'static str {
     "Usage: qw-client --connect <ip[:port]> [options]\n\
 Options:\n\
   --mode <qw|sp>             Client mode (default qw)\n\
-  -c, --connect <ip[:port]>  Server address (default port 27500)\n\
+  -c, --connect <ip[:port]>  Server address (default port 27500, 26000 for nq)\n\
+  --protocol <qw|nq|auto>    Network protocol (default auto)\n\
   --qport <port>             Client qport (default 27001)\n\
   --name <name>              Player name\n\
   --topcolor <0-13>          Top color\n\
@@ -61,6 +73,11 @@ Options:\n\
   --rate <value>             Rate (bytes/sec)\n\
   --data-dir <path>          Override data directory\n\
   --download-dir <path>      Override download directory\n\
+  --map <name>               Singleplayer start map (default start)\n\
+  --skill <0-3>              Singleplayer skill (default 1)\n\
+  --playdemo <file>          Play a .qwd demo at its recorded pace\n\
+  --timedemo <file>          Play a .qwd demo as fast as possible and report fps\n\
+  --headless                 Play the demo without opening a window\n\
   -h, --help                 Show this help\n"
 }
 
@@ -72,6 +89,7 @@ where
     let mut iter = args.into_iter().map(Into::into).peekable();
     let mut mode = ClientMode::QuakeWorld;
     let mut server_input: Option<String> = None;
+    let mut protocol = Protocol::Auto;
     let mut qport = DEFAULT_QPORT;
     let mut name = None;
     let mut topcolor = None;
@@ -79,8 +97,24 @@ where
     let mut rate = None;
     let mut data_dir = None;
     let mut download_dir = None;
+    let mut map = DEFAULT_MAP.to_string();
+    let mut skill = None;
+    let mut demo = None;
+    let mut timedemo = false;
+    let mut headless = false;
+    let mut netsim = Vec::new();
 
     while let Some(arg) = iter.next() {
+        if let Some(cvar) = netsim_cvar(&arg) {
+            let value = iter
+                .next()
+                .ok_or_else(|| CliError::MissingValue(arg.clone()))?;
+            if !valid_netsim_value(&value) {
+                return Err(CliError::InvalidValue(value));
+            }
+            netsim.push((cvar.to_string(), value));
+            continue;
+        }
         match arg.as_str() {
             "-h" | "--help" => return Ok(CliAction::Help),
             "--mode" => {
@@ -93,6 +127,17 @@ where
                     _ => return Err(CliError::InvalidValue(value)),
                 };
             }
+            "--protocol" => {
+                let value = iter
+                    .next()
+                    .ok_or_else(|| CliError::MissingValue(arg.clone()))?;
+                protocol = match value.to_ascii_lowercase().as_str() {
+                    "qw" | "quakeworld" => Protocol::QuakeWorld,
+                    "nq" | "netquake" => Protocol::NetQuake,
+                    "auto" => Protocol::Auto,
+                    _ => return Err(CliError::InvalidValue(value)),
+                };
+            }
             "-c" | "--connect" => {
                 let value = iter
                     .next()
@@ -143,6 +188,32 @@ where
                     .ok_or_else(|| CliError::MissingValue(arg.clone()))?;
                 download_dir = Some(value);
             }
+            "--map" => {
+                map = iter
+                    .next()
+                    .ok_or_else(|| CliError::MissingValue(arg.clone()))?;
+            }
+            "--skill" => {
+                let value = iter
+                    .next()
+                    .ok_or_else(|| CliError::MissingValue(arg.clone()))?;
+                skill = Some(
+                    value
+                        .parse::<u8>()
+                        .ok()
+                        .filter(|skill| *skill <= 3)
+                        .ok_or(CliError::InvalidValue(value))?,
+                );
+            }
+            "--playdemo" | "--timedemo" => {
+                let value = iter
+                    .next()
+                    .ok_or_else(|| CliError::MissingValue(arg.clone()))?;
+                mode = ClientMode::Demo;
+                demo = Some(value);
+                timedemo = arg == "--timedemo";
+            }
+            "--headless" => headless = true,
             _ if arg.starts_with('-') => return Err(CliError::InvalidFlag(arg)),
             _ => {
                 if server_input.is_none() {
@@ -154,20 +225,29 @@ where
         }
     }
 
+    let default_port = if protocol == Protocol::NetQuake {
+        NQ_DEFAULT_PORT
+    } else {
+        DEFAULT_SERVER_PORT
+    };
     let server = match server_input {
-        Some(value) => Some(
-            NetAddr::parse(&value, DEFAULT_SERVER_PORT)
-                .map_err(|_| CliError::InvalidValue(value))?,
-        ),
+        Some(value) => {
+            Some(NetAddr::parse(&value, default_port).map_err(|_| CliError::InvalidValue(value))?)
+        }
         None => None,
     };
     if mode == ClientMode::QuakeWorld && server.is_none() {
         return Err(CliError::MissingServer);
     }
+    // Only demo playback can run without a window.
+    if headless && mode != ClientMode::Demo {
+        return Err(CliError::InvalidFlag("--headless".to_string()));
+    }
 
-    Ok(CliAction::Run(ClientArgs {
+    Ok(CliAction::Run(Box::new(ClientArgs {
         mode,
         server,
+        protocol,
         qport,
         name,
         topcolor,
@@ -175,7 +255,13 @@ where
         rate,
         data_dir,
         download_dir,
-    }))
+        map,
+        skill,
+        demo,
+        timedemo,
+        headless,
+        netsim,
+    })))
 }
 
 #[cfg(test)]
@@ -212,6 +298,26 @@ mod tests {
         assert_eq!(parsed.name.as_deref(), Some("unit"));
     }
 
+    #[test]
+    fn parses_protocol_and_its_default_port() {
+        let action = parse_args(["--protocol", "nq", "10.0.0.5"]).unwrap();
+        let CliAction::Run(parsed) = action else {
+            panic!("expected run action");
+        };
+        assert_eq!(parsed.protocol, Protocol::NetQuake);
+        assert_eq!(parsed.server.unwrap().port, NQ_DEFAULT_PORT);
+
+        let action = parse_args(["10.0.0.5"]).unwrap();
+        let CliAction::Run(parsed) = action else {
+            panic!("expected run action");
+        };
+        assert_eq!(parsed.protocol, Protocol::Auto);
+        assert_eq!(
+            parse_args(["--protocol", "q3", "10.0.0.5"]).unwrap_err(),
+            CliError::InvalidValue("q3".to_string())
+        );
+    }
+
     #[test]
     fn rejects_missing_server() {
         let err = parse_args(Vec::<String>::new()).unwrap_err();
@@ -227,5 +333,71 @@ mod tests {
         };
         assert_eq!(parsed.mode, ClientMode::SinglePlayer);
         assert!(parsed.server.is_none());
+        assert_eq!(parsed.map, DEFAULT_MAP);
+        assert_eq!(parsed.skill, None);
+    }
+
+    #[test]
+    fn parses_singleplayer_map_and_skill() {
+        let args = ["--mode", "sp", "--map", "e1m1", "--skill", "2"];
+        let CliAction::Run(parsed) = parse_args(args).unwrap() else {
+            panic!("expected run action");
+        };
+        assert_eq!(parsed.map, "e1m1");
+        assert_eq!(parsed.skill, Some(2));
+
+        let err = parse_args(["--mode", "sp", "--skill", "4"]).unwrap_err();
+        assert_eq!(err, CliError::InvalidValue("4".to_string()));
+    }
+
+    #[test]
+    fn parses_demo_playback() {
+        let action = parse_args(["--timedemo", "demo1.qwd", "--headless"]).unwrap();
+        let CliAction::Run(parsed) = action else {
+            panic!("expected run action");
+        };
+        assert_eq!(parsed.mode, ClientMode::Demo);
+        assert_eq!(parsed.demo.as_deref(), Some("demo1.qwd"));
+        assert!(parsed.timedemo);
+        assert!(parsed.headless);
+        assert!(parsed.server.is_none());
+
+        let action = parse_args(["--playdemo", "demo1"]).unwrap();
+        let CliAction::Run(parsed) = action else {
+            panic!("expected run action");
+        };
+        assert!(!parsed.timedemo);
+        assert!(!parsed.headless);
+        assert_eq!(
+            parse_args(["--headless", "10.0.0.5"]).unwrap_err(),
+            CliError::InvalidFlag("--headless".to_string())
+        );
+    }
+
+    #[test]
+    fn collects_netsim_flags_as_cvars() {
+        let args = [
+            "-c",
+            "10.0.0.1",
+            "--netsim-latency",
+            "120",
+            "--netsim-loss",
+            "5",
+        ];
+        let CliAction::Run(parsed) = parse_args(args).unwrap() else {
+            panic!("expected run action");
+        };
+        assert_eq!(
+            parsed.netsim,
+            [
+                ("netsim_latency".to_string(), "120".to_string()),
+                ("netsim_loss".to_string(), "5".to_string()),
+            ]
+        );
+
+        let err = parse_args(["-c", "10.0.0.1", "--netsim-loss", "lots"]).unwrap_err();
+        assert_eq!(err, CliError::InvalidValue("lots".to_string()));
+        let err = parse_args(["-c", "10.0.0.1", "--netsim-bogus", "1"]).unwrap_err();
+        assert_eq!(err, CliError::InvalidFlag("--netsim-bogus".to_string()));
     }
 }
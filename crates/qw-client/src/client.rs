@@ -1,7 +1,8 @@
 use crate::handshake;
 use qw_common::{
-    Netchan, NetchanError, OobMessage, QuakeFs, SvcMessage, SvcParseError, build_out_of_band,
-    is_out_of_band, out_of_band_payload, parse_oob_message, parse_svc_stream,
+    Netchan, NetchanError, NqChannel, NqControl, NqError, NqMessage, NqParser, OobMessage, QuakeFs,
+    SvcMessage, SvcParseError, build_out_of_band, is_out_of_band, out_of_band_payload,
+    parse_oob_message, parse_svc_stream,
 };
 
 #[derive(Debug)]
@@ -9,6 +10,7 @@ use qw_common::{
 pub enum ClientPacket {
     OutOfBand(OobMessage),
     Messages(Vec<SvcMessage>),
+    Control(NqControl),
 }
 
 #[derive(Debug)]
@@ -16,6 +18,7 @@ pub enum ClientPacket {
 pub enum ClientError {
     Netchan(NetchanError),
     Parse(SvcParseError),
+    Nq(NqError),
 }
 
 impl From<NetchanError> for ClientError {
@@ -24,6 +27,12 @@ impl From<NetchanError> for ClientError {
     }
 }
 
+impl From<NqError> for ClientError {
+    fn from(err: NqError) -> Self {
+        ClientError::Nq(err)
+    }
+}
+
 impl From<SvcParseError> for ClientError {
     fn from(err: SvcParseError) -> Self {
         ClientError::Parse(err)
@@ -34,6 +43,10 @@ impl From<SvcParseError> for ClientError {
 pub struct Client {
     pub fs: QuakeFs,
     pub netchan: Netchan,
+    pub nq: NqChannel,
+    pub nq_parser: NqParser,
+    // NetQuake has no acked frames, so each unreliable datagram counts as one.
+    pub nq_sequence: u32,
 }
 
 #[allow(dead_code)]
@@ -42,6 +55,9 @@ impl Client {
         Self {
             fs: QuakeFs::new(),
             netchan: Netchan::new(qport),
+            nq: NqChannel::new(),
+            nq_parser: NqParser::new(),
+            nq_sequence: 0,
         }
     }
 
@@ -59,6 +75,22 @@ impl Client {
         Ok(ClientPacket::Messages(messages))
     }
 
+    // None when the packet only carried acks or part of a reliable message.
+    pub fn handle_nq_packet(&mut self, packet: &[u8]) -> Result<Option<ClientPacket>, ClientError> {
+        let Some(message) = self.nq.process_packet(packet)? else {
+            return Ok(None);
+        };
+        let data = match message {
+            NqMessage::Control(control) => return Ok(Some(ClientPacket::Control(control))),
+            NqMessage::Reliable(data) => data,
+            NqMessage::Unreliable(data) => {
+                self.nq_sequence = self.nq_sequence.wrapping_add(1);
+                data
+            }
+        };
+        Ok(Some(ClientPacket::Messages(self.nq_parser.parse(&data)?)))
+    }
+
     pub fn build_oob_message(text: &str) -> Vec<u8> {
         build_out_of_band(text.as_bytes())
     }
@@ -0,0 +1,895 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::browser::{ServerBrowser, ServerFilter, SortKey};
+use crate::cli::DEFAULT_SERVER_PORT;
+use crate::console::{Console, ConsoleHost};
+use crate::console_view::{CONSOLE_CVARS, ConsoleView};
+use crate::demo::DemoPlayer;
+use crate::hud::HUD_CVARS;
+use crate::input::{InputBindings, InputState, KeyDest, MOUSE_CVARS};
+use crate::menu::{Menu, MenuScreen, VIDEO_CVARS};
+use crate::runner::{ClientRunner, RunnerError, is_safe_download_path};
+use crate::session::SessionState;
+use crate::sound::SOUND_CVARS;
+use crate::state::PREDICTION_CVARS;
+use qw_common::{
+    Cvar, InfoString, NetAddr, QuakeFs, Transport, find_game_dir, find_id1_dir, locate_data_dir,
+};
+use qw_server::LocalServer;
+use qw_window_glfw::Key;
+
+// The client keeps its own scripts under this game directory, as QW does.
+const CONFIG_GAME_DIR: &str = "qw";
+
+// Userinfo keys that are also cvars, so config.cfg can set them.
+const USERINFO_CVARS: [&str; 7] = [
+    "name",
+    "team",
+    "skin",
+    "topcolor",
+    "bottomcolor",
+    "rate",
+    "msg",
+];
+
+const BUTTONS: [&str; 12] = [
+    "forward",
+    "back",
+    "moveleft",
+    "moveright",
+    "moveup",
+    "movedown",
+    "jump",
+    "attack",
+    "speed",
+    "showscores",
+    "mlook",
+    "strafe",
+];
+
+const HOST_COMMANDS: [&str; 5] = ["map", "load", "save", "skill", "coop"];
+
+const MENU_COMMANDS: [(&str, MenuScreen); 9] = [
+    ("menu_main", MenuScreen::Main),
+    ("menu_singleplayer", MenuScreen::SinglePlayer),
+    ("menu_multiplayer", MenuScreen::MultiPlayer),
+    ("menu_setup", MenuScreen::Setup),
+    ("menu_options", MenuScreen::Options),
+    ("menu_keys", MenuScreen::Keys),
+    ("menu_video", MenuScreen::Video),
+    ("help", MenuScreen::Help),
+    ("menu_quit", MenuScreen::Quit),
+];
+
+const DEFAULT_MASTERS: &str = "master.quakeservers.net:27000 qwmaster.fodquake.net:27000";
+
+// Everything console commands act on.
+pub struct ClientApp<T: Transport> {
+    pub runner: ClientRunner<T>,
+    pub userinfo: InfoString,
+    pub input: InputState,
+    pub bindings: InputBindings,
+    pub pending_server_cmds: VecDeque<String>,
+    pub local_server: Option<LocalServer>,
+    // Search path for scripts before a server has picked a game directory.
+    pub fs: QuakeFs,
+    pub browser: Option<ServerBrowser>,
+    pub server_filter: ServerFilter,
+    pub server_sort: SortKey,
+    // Whether slist is waiting to print the finished list.
+    slist_waiting: bool,
+    pub menu: Menu,
+    pub console_view: ConsoleView,
+    pub quit: bool,
+}
+
+impl<T: Transport> ClientApp<T> {
+    pub fn new(runner: ClientRunner<T>, userinfo: InfoString) -> Self {
+        let mut fs = QuakeFs::new();
+        if let Ok(base) = locate_data_dir() {
+            if let Some(id1) = find_id1_dir(&base) {
+                let _ = fs.add_game_dir(id1);
+            }
+            if let Some(dir) = find_game_dir(&base, CONFIG_GAME_DIR) {
+                let _ = fs.add_game_dir(dir);
+            }
+        }
+        if let Some(dir) = config_dir(&runner)
+            && dir.is_dir()
+        {
+            let _ = fs.add_game_dir(dir);
+        }
+        Self {
+            runner,
+            userinfo,
+            input: InputState::default(),
+            bindings: InputBindings::default(),
+            pending_server_cmds: VecDeque::new(),
+            local_server: None,
+            fs,
+            browser: None,
+            server_filter: ServerFilter::default(),
+            server_sort: SortKey::default(),
+            slist_waiting: false,
+            menu: Menu::default(),
+            console_view: ConsoleView::default(),
+            quit: false,
+        }
+    }
+
+    // The menu is over the console, which is over the game.
+    pub fn key_dest(&self) -> KeyDest {
+        if self.menu.is_open() {
+            KeyDest::Menu
+        } else if self.console_view.is_open() {
+            KeyDest::Console
+        } else {
+            KeyDest::Game
+        }
+    }
+
+    // Runs the server browser along; once a refresh slist started is done,
+    // returns the list for the console.
+    pub fn poll_browser(&mut self) -> Vec<String> {
+        let Some(browser) = &mut self.browser else {
+            return Vec::new();
+        };
+        let mut lines = match browser.poll() {
+            Ok(skipped) => skipped,
+            Err(err) => {
+                self.slist_waiting = false;
+                return vec![format!("server browser: {err}")];
+            }
+        };
+        if !self.slist_waiting || !browser.is_finished() {
+            return lines;
+        }
+        self.slist_waiting = false;
+        let servers = browser.servers(&self.server_filter, self.server_sort);
+        lines.push("ping players map      gamedir  hostname".to_string());
+        for server in &servers {
+            lines.push(format!(
+                "{:4} {:>3}/{:<3} {:8} {:8} {} ({})",
+                server.ping.as_millis(),
+                server.players.len(),
+                server.maxclients(),
+                server.map(),
+                server.gamedir(),
+                server.hostname(),
+                server.addr
+            ));
+        }
+        lines.push(format!("{} servers", servers.len()));
+        lines
+    }
+
+    // Host_WriteConfiguration: the key bindings and archived cvars, for the
+    // next startup.
+    pub fn write_config(&self, console: &Console<Self>) -> std::io::Result<()> {
+        let Some(dir) = config_dir(&self.runner) else {
+            return Ok(());
+        };
+        fs::create_dir_all(&dir)?;
+        let text = self.bindings.archived_config() + &console.archived_config();
+        fs::write(dir.join("config.cfg"), text)
+    }
+}
+
+impl<T: Transport> ConsoleHost for ClientApp<T> {
+    fn read_file(&self, name: &str) -> Option<Vec<u8>> {
+        let fs = &self.runner.client.fs;
+        if fs.contains(name) {
+            return fs.read(name).ok();
+        }
+        self.fs.read(name).ok()
+    }
+
+    fn forward(&mut self, line: &str) {
+        self.pending_server_cmds.push_back(line.to_string());
+    }
+
+    fn cvar_changed(&mut self, cvar: &Cvar) {
+        if cvar.info {
+            set_userinfo(self, &cvar.name, &cvar.value);
+        }
+        if cvar.name == "cl_timeout" && cvar.float_value > 0.0 {
+            self.runner.timeout = Duration::from_secs_f32(cvar.float_value);
+        }
+    }
+}
+
+fn config_dir<T: Transport>(runner: &ClientRunner<T>) -> Option<PathBuf> {
+    runner
+        .download_root()
+        .ok()
+        .map(|root| root.join(CONFIG_GAME_DIR))
+}
+
+pub fn register_client_commands<T: Transport>(
+    console: &mut Console<ClientApp<T>>,
+    userinfo: &InfoString,
+) {
+    for key in USERINFO_CVARS {
+        let value = qw_common::value_for_key(userinfo.as_str(), key).unwrap_or_default();
+        console
+            .cvars
+            .register(Cvar::new(key, value).with_flags(true, true));
+    }
+    console
+        .cvars
+        .register(Cvar::new("cl_masterservers", DEFAULT_MASTERS).with_flags(true, false));
+    console.cvars.register(Cvar::new("cl_timeout", "60"));
+    for (name, value, archive) in MOUSE_CVARS
+        .into_iter()
+        .chain(PREDICTION_CVARS)
+        .chain(HUD_CVARS)
+        .chain(SOUND_CVARS)
+        .chain(CONSOLE_CVARS)
+        .chain(VIDEO_CVARS)
+    {
+        console
+            .cvars
+            .register(Cvar::new(name, value).with_flags(archive, false));
+    }
+    for button in BUTTONS {
+        console.register(&format!("+{button}"), cmd_button);
+        console.register(&format!("-{button}"), cmd_button);
+    }
+    for name in HOST_COMMANDS {
+        console.register(name, cmd_host);
+    }
+    for (name, _) in MENU_COMMANDS {
+        console.register(name, cmd_menu);
+    }
+    console.register("togglemenu", cmd_togglemenu);
+    console.register("toggleconsole", cmd_toggleconsole);
+    console.register("condump", cmd_condump);
+    console.register("connect", cmd_connect);
+    console.register("impulse", cmd_button);
+    console.register("bind", cmd_bind);
+    console.register("unbind", cmd_unbind);
+    console.register("unbindall", cmd_unbindall);
+    console.register("bindlist", cmd_bindlist);
+    console.register("messagemode", cmd_messagemode);
+    console.register("setinfo", cmd_setinfo);
+    console.register("slist", cmd_slist);
+    console.register("reconnect", cmd_reconnect);
+    console.register("record", cmd_record);
+    console.register("stop", cmd_stop);
+    console.register("playdemo", cmd_playdemo);
+    console.register("timedemo", cmd_timedemo);
+    console.register("quit", cmd_quit);
+    console.register("exit", cmd_quit);
+}
+
+fn set_userinfo<T: Transport>(app: &mut ClientApp<T>, key: &str, value: &str) {
+    let result = if key.starts_with('*') {
+        app.userinfo.set_star(key, value)
+    } else {
+        app.userinfo.set(key, value)
+    };
+    if let Err(err) = result {
+        println!("[client] invalid userinfo: {err}");
+        return;
+    }
+    app.runner.session.userinfo = app.userinfo.as_str().to_string();
+    if app.runner.session.state == SessionState::Connected {
+        app.pending_server_cmds
+            .push_back(format!("setinfo {} {}", key, quote_if_needed(value)));
+    }
+}
+
+fn quote_if_needed(value: &str) -> String {
+    if value.contains(' ') {
+        format!("\"{}\"", value)
+    } else {
+        value.to_string()
+    }
+}
+
+fn cmd_button<T: Transport>(
+    _console: &mut Console<ClientApp<T>>,
+    app: &mut ClientApp<T>,
+    args: &[String],
+) {
+    app.input.apply_command(&args.join(" "));
+}
+
+fn cmd_bind<T: Transport>(
+    console: &mut Console<ClientApp<T>>,
+    app: &mut ClientApp<T>,
+    args: &[String],
+) {
+    let Some(name) = args.get(1) else {
+        console.print("bind <key> [command] : attach a command to a key");
+        return;
+    };
+    let Some(key) = Key::from_name(name) else {
+        console.print(format!("\"{name}\" isn't a valid key"));
+        return;
+    };
+    if args.len() == 2 {
+        match app.bindings.binding(key) {
+            Some(command) => console.print(format!("\"{name}\" = \"{command}\"")),
+            None => console.print(format!("\"{name}\" is not bound")),
+        }
+        return;
+    }
+    app.bindings.bind(key, args[2..].join(" "));
+}
+
+fn cmd_unbind<T: Transport>(
+    console: &mut Console<ClientApp<T>>,
+    app: &mut ClientApp<T>,
+    args: &[String],
+) {
+    let Some(name) = args.get(1) else {
+        console.print("unbind <key> : remove commands from a key");
+        return;
+    };
+    match Key::from_name(name) {
+        Some(key) => app.bindings.unbind(key),
+        None => console.print(format!("\"{name}\" isn't a valid key")),
+    }
+}
+
+fn cmd_unbindall<T: Transport>(
+    _console: &mut Console<ClientApp<T>>,
+    app: &mut ClientApp<T>,
+    _args: &[String],
+) {
+    app.bindings.unbind_all();
+}
+
+fn cmd_bindlist<T: Transport>(
+    console: &mut Console<ClientApp<T>>,
+    app: &mut ClientApp<T>,
+    _args: &[String],
+) {
+    for (key, command) in app.bindings.iter() {
+        if let Some(name) = key.name() {
+            console.print(format!("{name} \"{command}\""));
+        }
+    }
+}
+
+fn cmd_messagemode<T: Transport>(
+    _console: &mut Console<ClientApp<T>>,
+    _app: &mut ClientApp<T>,
+    _args: &[String],
+) {
+}
+
+fn cmd_setinfo<T: Transport>(
+    console: &mut Console<ClientApp<T>>,
+    app: &mut ClientApp<T>,
+    args: &[String],
+) {
+    match args {
+        [_] => console.print(app.userinfo.as_str().to_string()),
+        [_, key, value, ..] => set_userinfo(app, key, value),
+        _ => console.print("setinfo [ <key> <value> ]"),
+    }
+}
+
+// The listen server's own commands go to it; without one they're for the
+// remote server like anything else.
+fn cmd_host<T: Transport>(
+    console: &mut Console<ClientApp<T>>,
+    app: &mut ClientApp<T>,
+    args: &[String],
+) {
+    let line = args.join(" ");
+    match &app.local_server {
+        Some(server) => {
+            if let Err(err) = server.send_command(&line) {
+                console.print(format!("local server: {err}"));
+            }
+        }
+        None => app.forward(&line),
+    }
+}
+
+// slist [map <part>] [gamedir <dir>] [players <n>] [notfull] [sort <key>]
+fn cmd_slist<T: Transport>(
+    console: &mut Console<ClientApp<T>>,
+    app: &mut ClientApp<T>,
+    args: &[String],
+) {
+    let mut filter = ServerFilter::default();
+    let mut sort = SortKey::default();
+    let mut words = args[1..].iter();
+    while let Some(word) = words.next() {
+        let parsed = match word.as_str() {
+            "notfull" => {
+                filter.hide_full = true;
+                true
+            }
+            "map" => words
+                .next()
+                .map(|map| filter.map = Some(map.clone()))
+                .is_some(),
+            "gamedir" => words
+                .next()
+                .map(|dir| filter.gamedir = Some(dir.clone()))
+                .is_some(),
+            "players" => words
+                .next()
+                .and_then(|n| n.parse().ok())
+                .map(|n| filter.min_players = n)
+                .is_some(),
+            "sort" => words
+                .next()
+                .and_then(|key| SortKey::parse(key))
+                .map(|key| sort = key)
+                .is_some(),
+            _ => false,
+        };
+        if !parsed {
+            console.print(
+                "slist [map <name>] [gamedir <dir>] [players <n>] [notfull] [sort ping|players|map|name]",
+            );
+            return;
+        }
+    }
+
+    let masters = resolve_masters(&console.cvars.string("cl_masterservers"));
+    if masters.is_empty() {
+        console.print("no master servers could be resolved");
+        return;
+    }
+    let result = ServerBrowser::open(masters).and_then(|mut browser| {
+        browser.refresh()?;
+        Ok(browser)
+    });
+    match result {
+        Ok(browser) => {
+            console.print("querying master servers...");
+            app.browser = Some(browser);
+            app.server_filter = filter;
+            app.server_sort = sort;
+            app.slist_waiting = true;
+        }
+        Err(err) => console.print(format!("server browser: {err}")),
+    }
+}
+
+// The session keeps the userinfo setinfo has built up, so the server sees
+// the same player come back.
+fn cmd_reconnect<T: Transport>(
+    console: &mut Console<ClientApp<T>>,
+    app: &mut ClientApp<T>,
+    _args: &[String],
+) {
+    match app.runner.reconnect() {
+        Ok(true) => console.print("reconnecting..."),
+        Ok(false) => {}
+        Err(err) => console.print(format!("can't reconnect: {err:?}")),
+    }
+}
+
+fn cmd_connect<T: Transport>(
+    console: &mut Console<ClientApp<T>>,
+    app: &mut ClientApp<T>,
+    args: &[String],
+) {
+    let Some(address) = args.get(1) else {
+        console.print("connect <server>");
+        return;
+    };
+    if app.local_server.is_some() {
+        console.print("can't connect elsewhere from a local game");
+        return;
+    }
+    let Ok(addr) = NetAddr::parse(address, DEFAULT_SERVER_PORT) else {
+        console.print(format!("bad server address \"{address}\""));
+        return;
+    };
+    match app
+        .runner
+        .connect_to(SocketAddr::from(addr.to_socket_addr()))
+    {
+        Ok(true) => console.print(format!("connecting to {}...", addr.to_string())),
+        Ok(false) => console.print("can't connect while playing a demo"),
+        Err(err) => console.print(format!("can't connect: {err:?}")),
+    }
+}
+
+fn cmd_menu<T: Transport>(
+    console: &mut Console<ClientApp<T>>,
+    app: &mut ClientApp<T>,
+    args: &[String],
+) {
+    let name = args[0].to_ascii_lowercase();
+    if let Some((_, screen)) = MENU_COMMANDS.iter().find(|(command, _)| *command == name) {
+        app.console_view.close();
+        app.menu.open(*screen, &console.cvars);
+    }
+}
+
+// M_ToggleMenu_f: from a submenu back to the main one, from the main menu
+// out, and from the console or the game in.
+fn cmd_togglemenu<T: Transport>(
+    console: &mut Console<ClientApp<T>>,
+    app: &mut ClientApp<T>,
+    _args: &[String],
+) {
+    match app.key_dest() {
+        KeyDest::Menu if app.menu.screen() != Some(MenuScreen::Main) => {
+            app.menu.open(MenuScreen::Main, &console.cvars);
+        }
+        KeyDest::Menu => app.menu.close(),
+        KeyDest::Console => app.console_view.close(),
+        KeyDest::Game => app.menu.open(MenuScreen::Main, &console.cvars),
+    }
+}
+
+// Con_ToggleConsole_f: the console comes down over any menu.
+fn cmd_toggleconsole<T: Transport>(
+    _console: &mut Console<ClientApp<T>>,
+    app: &mut ClientApp<T>,
+    _args: &[String],
+) {
+    app.console_view.toggle();
+    app.menu.close();
+}
+
+// Con_Dump_f: the scrollback as text, next to config.cfg.
+fn cmd_condump<T: Transport>(
+    console: &mut Console<ClientApp<T>>,
+    app: &mut ClientApp<T>,
+    args: &[String],
+) {
+    let Some(name) = args.get(1) else {
+        console.print("condump <filename>");
+        return;
+    };
+    if !is_safe_download_path(name) {
+        console.print(format!("bad file name \"{name}\""));
+        return;
+    }
+    let Some(dir) = config_dir(&app.runner) else {
+        console.print("no directory to dump into");
+        return;
+    };
+    let mut path = dir.join(name);
+    if path.extension().is_none() {
+        path.set_extension("txt");
+    }
+    let written = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|()| fs::write(&path, app.console_view.text()));
+    match written {
+        Ok(()) => console.print(format!("Dumped console text to {}.", path.display())),
+        Err(err) => console.print(format!("couldn't write {}: {err}", path.display())),
+    }
+}
+
+fn resolve_masters(list: &str) -> Vec<SocketAddr> {
+    list.split_whitespace()
+        .filter_map(|name| name.to_socket_addrs().ok())
+        .flat_map(|addrs| addrs.filter(SocketAddr::is_ipv4).take(1))
+        .collect()
+}
+
+fn cmd_record<T: Transport>(
+    console: &mut Console<ClientApp<T>>,
+    app: &mut ClientApp<T>,
+    args: &[String],
+) {
+    let Some(name) = args.get(1) else {
+        console.print("record <demoname>");
+        return;
+    };
+    match app.runner.record(name) {
+        Ok(path) => console.print(format!("recording to {}", path.display())),
+        Err(err) => console.print(format!("can't record: {err:?}")),
+    }
+}
+
+// CL_Stop_f, and CL_StopPlayback for a demo being played back.
+fn cmd_stop<T: Transport>(
+    console: &mut Console<ClientApp<T>>,
+    app: &mut ClientApp<T>,
+    _args: &[String],
+) {
+    match app.runner.stop_recording() {
+        Ok(Some(path)) => console.print(format!("completed demo {}", path.display())),
+        Ok(None) if app.runner.is_playing_demo() => {
+            if let Err(err) = app.runner.disconnect() {
+                console.print(format!("can't stop demo: {err:?}"));
+            }
+        }
+        Ok(None) => console.print("not recording a demo"),
+        Err(err) => console.print(format!("can't finish demo: {err:?}")),
+    }
+}
+
+fn cmd_playdemo<T: Transport>(
+    console: &mut Console<ClientApp<T>>,
+    app: &mut ClientApp<T>,
+    args: &[String],
+) {
+    start_demo(console, app, args, false);
+}
+
+fn cmd_timedemo<T: Transport>(
+    console: &mut Console<ClientApp<T>>,
+    app: &mut ClientApp<T>,
+    args: &[String],
+) {
+    start_demo(console, app, args, true);
+}
+
+// CL_PlayDemo_f and CL_TimeDemo_f: the demo is looked for where record
+// leaves them, and takes the place of whatever server this was.
+fn start_demo<T: Transport>(
+    console: &mut Console<ClientApp<T>>,
+    app: &mut ClientApp<T>,
+    args: &[String],
+    timedemo: bool,
+) {
+    let Some(name) = args.get(1) else {
+        console.print(format!("{} <demoname>", args[0]));
+        return;
+    };
+    if app.local_server.is_some() {
+        console.print("can't play a demo from a local game");
+        return;
+    }
+    if !is_safe_download_path(name) {
+        console.print(format!("bad demo name \"{name}\""));
+        return;
+    }
+    let game_dir = app
+        .runner
+        .state
+        .serverdata
+        .as_ref()
+        .map_or(CONFIG_GAME_DIR, |data| data.game_dir.as_str());
+    let path = match app.runner.download_root() {
+        Ok(root) => root.join(game_dir).join(name),
+        Err(err) => {
+            console.print(format!("can't play {name}: {err:?}"));
+            return;
+        }
+    };
+    let played = DemoPlayer::open(&path, timedemo)
+        .map_err(RunnerError::from)
+        .and_then(|player| app.runner.play_demo(player));
+    match played {
+        Ok(()) => console.print(format!("Playing demo from {name}.")),
+        Err(err) => console.print(format!("can't play {name}: {err:?}")),
+    }
+}
+
+fn cmd_quit<T: Transport>(
+    _console: &mut Console<ClientApp<T>>,
+    app: &mut ClientApp<T>,
+    _args: &[String],
+) {
+    app.quit = true;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ClientConfig;
+    use crate::demo::DemoRecorder;
+    use crate::net::NetClient;
+    use crate::runner::tests::{ENV_LOCK, temp_dir};
+    use crate::session::Session;
+    use qw_common::LoopbackSocket;
+
+    fn test_app() -> (
+        Console<ClientApp<LoopbackSocket>>,
+        ClientApp<LoopbackSocket>,
+    ) {
+        let (client_end, _) = qw_common::loopback_pair();
+        let userinfo = ClientConfig::default().userinfo;
+        let session = Session::new(27001, userinfo.as_str());
+        let runner = ClientRunner::new(NetClient::loopback(client_end), session);
+        let mut console = Console::new();
+        register_client_commands(&mut console, &userinfo);
+        (console, ClientApp::new(runner, userinfo))
+    }
+
+    #[test]
+    fn userinfo_cvars_send_setinfo_once_connected() {
+        let (mut console, mut app) = test_app();
+        assert_eq!(console.cvars.string("name"), "unnamed");
+        console.append("name player");
+        console.execute(&mut app);
+        assert!(app.userinfo.as_str().contains("\\name\\player"));
+        assert!(app.runner.session.userinfo.contains("\\name\\player"));
+        assert!(app.pending_server_cmds.is_empty());
+
+        app.runner.session.state = SessionState::Connected;
+        console.append("name \"player one\"; setinfo *ver rq28; say hi");
+        console.execute(&mut app);
+        assert_eq!(
+            Vec::from(app.pending_server_cmds.clone()),
+            ["setinfo name \"player one\"", "setinfo *ver rq28", "say hi"]
+        );
+        assert!(app.userinfo.as_str().contains("\\*ver\\rq28"));
+    }
+
+    #[test]
+    fn buttons_and_quit_stay_local() {
+        let (mut console, mut app) = test_app();
+        console.append("+forward; +speed; impulse 7");
+        console.execute(&mut app);
+        let cmd = app.input.build_usercmd();
+        assert_eq!(cmd.forwardmove, 400);
+        assert_eq!(cmd.impulse, 7);
+        console.append("-forward; map e1m1; quit");
+        console.execute(&mut app);
+        assert_eq!(app.input.build_usercmd().forwardmove, 0);
+        assert!(app.quit);
+        assert_eq!(Vec::from(app.pending_server_cmds.clone()), ["map e1m1"]);
+    }
+
+    #[test]
+    fn slist_lists_servers_from_the_masters_it_is_given() {
+        let (mut console, mut app) = test_app();
+        let master = qw_common::UdpTransport::bind("127.0.0.1:0").unwrap();
+        let master_addr = master.local_addr().unwrap();
+        console.append(&format!("cl_masterservers {master_addr}; slist sort"));
+        console.execute(&mut app);
+        assert!(app.browser.is_none());
+        assert!(
+            console
+                .drain_output()
+                .any(|line| line.starts_with("slist [map"))
+        );
+
+        console.append("slist map dm players 1 notfull sort players");
+        console.execute(&mut app);
+        assert!(app.browser.is_some());
+        assert_eq!(app.server_filter.map.as_deref(), Some("dm"));
+        assert_eq!(app.server_filter.min_players, 1);
+        assert!(app.server_filter.hide_full);
+        assert_eq!(app.server_sort, SortKey::Players);
+        // Nothing to print until the masters have had their time.
+        assert!(app.poll_browser().is_empty());
+    }
+
+    #[test]
+    fn binds_keys_from_the_console() {
+        let (mut console, mut app) = test_app();
+        console.append("unbindall; bind w +forward; bind SEMICOLON \"impulse 7\"");
+        console.append("bind mwheelup impulse 2; unbind mwheelup; bind w; bind x; bind nokey a");
+        console.execute(&mut app);
+        assert_eq!(app.bindings.binding(Key::Up), None);
+        assert_eq!(app.bindings.binding(Key::Char(b';')), Some("impulse 7"));
+        assert_eq!(app.bindings.binding(Key::MWheelUp), None);
+        let output: Vec<String> = console.drain_output().collect();
+        assert_eq!(
+            output,
+            [
+                "\"w\" = \"+forward\"",
+                "\"x\" is not bound",
+                "\"nokey\" isn't a valid key"
+            ]
+        );
+
+        console.append("bindlist");
+        console.execute(&mut app);
+        let output: Vec<String> = console.drain_output().collect();
+        assert_eq!(output, ["SEMICOLON \"impulse 7\"", "w \"+forward\""]);
+
+        // What config.cfg saves execs back to the same bindings.
+        let saved = app.bindings.archived_config();
+        console.append("bind x +jump");
+        console.append(&saved);
+        console.execute(&mut app);
+        assert_eq!(app.bindings.binding(Key::Char(b'x')), None);
+        assert_eq!(app.bindings.binding(Key::Char(b'w')), Some("+forward"));
+    }
+
+    #[test]
+    fn menus_and_console_share_the_keys() {
+        let (mut console, mut app) = test_app();
+        assert_eq!(app.key_dest(), KeyDest::Game);
+        console.append("menu_options");
+        console.execute(&mut app);
+        assert_eq!(app.menu.screen(), Some(MenuScreen::Options));
+        console.append("togglemenu");
+        console.execute(&mut app);
+        assert_eq!(app.menu.screen(), Some(MenuScreen::Main));
+        console.append("togglemenu");
+        console.execute(&mut app);
+        assert_eq!(app.key_dest(), KeyDest::Game);
+
+        console.append("menu_main; toggleconsole");
+        console.execute(&mut app);
+        assert_eq!(app.key_dest(), KeyDest::Console);
+        assert!(!app.menu.is_open());
+        console.append("togglemenu");
+        console.execute(&mut app);
+        assert_eq!(app.key_dest(), KeyDest::Game);
+    }
+
+    #[test]
+    fn connect_points_the_runner_at_a_new_server() {
+        let (client_end, _server_end) = qw_common::loopback_pair();
+        let userinfo = ClientConfig::default().userinfo;
+        let session = Session::new(27001, userinfo.as_str());
+        let runner = ClientRunner::new(NetClient::loopback(client_end), session);
+        let mut console = Console::new();
+        register_client_commands(&mut console, &userinfo);
+        let mut app = ClientApp::new(runner, userinfo);
+        console.append("connect; connect bad:address:1; connect 10.0.0.2");
+        console.execute(&mut app);
+        let output: Vec<String> = console.drain_output().collect();
+        assert_eq!(
+            output,
+            [
+                "connect <server>",
+                "bad server address \"bad:address:1\"",
+                "connecting to 10.0.0.2:27500..."
+            ]
+        );
+        assert_eq!(app.runner.session.state, SessionState::ChallengeSent);
+    }
+
+    #[test]
+    fn plays_demos_from_where_record_leaves_them() {
+        let _guard = ENV_LOCK.lock().unwrap();
+        let download_dir = temp_dir();
+        let old_download = std::env::var("RUSTQUAKE_DOWNLOAD_DIR").ok();
+        // Safety: env var mutation is process-global; guard with ENV_LOCK.
+        unsafe {
+            std::env::set_var("RUSTQUAKE_DOWNLOAD_DIR", &download_dir);
+        }
+        DemoRecorder::create(&download_dir.join("qw").join("demo1.qwd"))
+            .unwrap()
+            .finish(0.0)
+            .unwrap();
+
+        let (mut console, mut app) = test_app();
+        console.append("playdemo; timedemo ../demo1; playdemo missing; stop");
+        console.execute(&mut app);
+        let output: Vec<String> = console.drain_output().collect();
+        assert_eq!(
+            output[..2],
+            ["playdemo <demoname>", "bad demo name \"../demo1\""]
+        );
+        assert!(output[2].starts_with("can't play missing"));
+        assert_eq!(output[3], "not recording a demo");
+        assert!(!app.runner.is_playing_demo());
+
+        console.append("playdemo demo1");
+        console.execute(&mut app);
+        assert!(app.runner.is_playing_demo());
+        assert!(!app.runner.is_timedemo());
+        assert_eq!(app.runner.session.state, SessionState::Connected);
+        console.append("stop");
+        console.execute(&mut app);
+        assert!(!app.runner.is_playing_demo());
+        assert_eq!(app.runner.session.state, SessionState::Disconnected);
+
+        console.append("timedemo demo1");
+        console.execute(&mut app);
+        assert!(app.runner.is_timedemo());
+        let output: Vec<String> = console.drain_output().collect();
+        assert_eq!(
+            output,
+            ["Playing demo from demo1.", "Playing demo from demo1."]
+        );
+
+        // Safety: env var mutation is process-global; guard with ENV_LOCK.
+        unsafe {
+            match old_download {
+                Some(value) => std::env::set_var("RUSTQUAKE_DOWNLOAD_DIR", value),
+                None => std::env::remove_var("RUSTQUAKE_DOWNLOAD_DIR"),
+            }
+        }
+        std::fs::remove_dir_all(download_dir).ok();
+    }
+}
@@ -0,0 +1,408 @@
+use std::collections::BTreeMap;
+
+use qw_common::{Cvar, CvarRegistry, com_tokenize};
+
+// Enough for any sane script; a runaway alias loop gets cut off here rather
+// than hanging the frame.
+const MAX_COMMANDS_PER_FRAME: usize = 4096;
+
+pub type CommandHandler<H> = fn(&mut Console<H>, &mut H, &[String]);
+
+// What the console needs from the client around it.
+pub trait ConsoleHost {
+    fn read_file(&self, name: &str) -> Option<Vec<u8>>;
+    // Lines no command, alias or cvar claims, as Cmd_ForwardToServer.
+    fn forward(&mut self, line: &str);
+    fn cvar_changed(&mut self, _cvar: &Cvar) {}
+}
+
+// The command buffer and everything it dispatches to, after cmd.c.
+pub struct Console<H> {
+    pub cvars: CvarRegistry,
+    commands: BTreeMap<String, CommandHandler<H>>,
+    aliases: BTreeMap<String, String>,
+    text: String,
+    waiting: bool,
+    output: Vec<String>,
+}
+
+impl<H: ConsoleHost> Console<H> {
+    pub fn new() -> Self {
+        let mut console = Self {
+            cvars: CvarRegistry::new(),
+            commands: BTreeMap::new(),
+            aliases: BTreeMap::new(),
+            text: String::new(),
+            waiting: false,
+            output: Vec::new(),
+        };
+        console.register("wait", cmd_wait);
+        console.register("alias", cmd_alias);
+        console.register("exec", cmd_exec);
+        console.register("echo", cmd_echo);
+        console.register("cmdlist", cmd_cmdlist);
+        console.register("cvarlist", cmd_cvarlist);
+        console.register("toggle", cmd_toggle);
+        console.register("set", cmd_set);
+        console.register("seta", cmd_set);
+        console
+    }
+
+    pub fn register(&mut self, name: &str, handler: CommandHandler<H>) {
+        self.commands.insert(name.to_ascii_lowercase(), handler);
+    }
+
+    // Cbuf_AddText: runs after whatever is already queued.
+    pub fn append(&mut self, text: &str) {
+        self.text.push_str(text);
+        if !text.ends_with('\n') {
+            self.text.push('\n');
+        }
+    }
+
+    // Cbuf_InsertText: runs before whatever is already queued.
+    pub fn insert(&mut self, text: &str) {
+        let mut inserted = text.to_string();
+        if !inserted.ends_with('\n') {
+            inserted.push('\n');
+        }
+        self.text.insert_str(0, &inserted);
+    }
+
+    // Cbuf_Execute: runs until the buffer is empty or a wait defers the rest
+    // to the next frame.
+    pub fn execute(&mut self, host: &mut H) {
+        self.waiting = false;
+        let mut executed = 0;
+        while !self.text.is_empty() {
+            if self.waiting {
+                return;
+            }
+            executed += 1;
+            if executed > MAX_COMMANDS_PER_FRAME {
+                self.print("too many commands in one frame, buffer cleared");
+                self.text.clear();
+                return;
+            }
+            let line = take_line(&mut self.text);
+            self.execute_line(host, &line);
+        }
+    }
+
+    // Cmd_ExecuteString for a single command.
+    pub fn execute_line(&mut self, host: &mut H, line: &str) {
+        let args = com_tokenize(line);
+        let Some(name) = args.first() else {
+            return;
+        };
+        let name = name.to_ascii_lowercase();
+        if let Some(handler) = self.commands.get(&name).copied() {
+            handler(self, host, &args);
+        } else if let Some(text) = self.aliases.get(&name).cloned() {
+            self.insert(&text);
+        } else if self.cvars.get(&name).is_some() {
+            match args.get(1) {
+                Some(value) => self.set_cvar(host, &name, value),
+                None => {
+                    let value = self.cvars.string(&name);
+                    self.print(format!("\"{name}\" is \"{value}\""));
+                }
+            }
+        } else {
+            host.forward(line.trim());
+        }
+    }
+
+    pub fn set_cvar(&mut self, host: &mut H, name: &str, value: &str) {
+        self.cvars.set(name, value);
+        if let Some(cvar) = self.cvars.get(name) {
+            host.cvar_changed(cvar);
+        }
+    }
+
+    pub fn print(&mut self, text: impl Into<String>) {
+        self.output.push(text.into());
+    }
+
+    pub fn drain_output(&mut self) -> impl Iterator<Item = String> + '_ {
+        self.output.drain(..)
+    }
+
+    // Every command, alias and cvar name starting with `partial`, for Tab.
+    pub fn complete(&self, partial: &str) -> Vec<String> {
+        let partial = partial.to_ascii_lowercase();
+        let mut names: Vec<String> = self
+            .commands
+            .keys()
+            .chain(self.aliases.keys())
+            .cloned()
+            .chain(self.cvars.iter().map(|var| var.name.clone()))
+            .filter(|name| name.to_ascii_lowercase().starts_with(&partial))
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    // The archived cvars in a form exec reads back, for config.cfg.
+    pub fn archived_config(&self) -> String {
+        let mut vars: Vec<&Cvar> = self.cvars.iter_archive().collect();
+        vars.sort_by(|a, b| a.name.cmp(&b.name));
+        let mut text = String::new();
+        for var in vars {
+            text.push_str(&format!("seta {} \"{}\"\n", var.name, var.value));
+        }
+        text
+    }
+}
+
+impl<H: ConsoleHost> Default for Console<H> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Splits off the first command: a newline ends one always, a ';' only when
+// it's outside quotes.
+fn take_line(text: &mut String) -> String {
+    let mut quoted = false;
+    let mut end = text.len();
+    for (i, c) in text.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => {
+                end = i;
+                break;
+            }
+            '\n' => {
+                end = i;
+                break;
+            }
+            _ => {}
+        }
+    }
+    let line = text[..end].to_string();
+    let rest = text.get(end + 1..).unwrap_or("").to_string();
+    *text = rest;
+    line
+}
+
+fn cmd_wait<H: ConsoleHost>(console: &mut Console<H>, _host: &mut H, _args: &[String]) {
+    console.waiting = true;
+}
+
+fn cmd_alias<H: ConsoleHost>(console: &mut Console<H>, _host: &mut H, args: &[String]) {
+    let Some(name) = args.get(1) else {
+        console.print("Current alias commands:");
+        let lines: Vec<String> = console
+            .aliases
+            .iter()
+            .map(|(name, text)| format!("{name} : {text}"))
+            .collect();
+        for line in lines {
+            console.print(line);
+        }
+        return;
+    };
+    let text = args[2..].join(" ");
+    console.aliases.insert(name.to_ascii_lowercase(), text);
+}
+
+fn cmd_exec<H: ConsoleHost>(console: &mut Console<H>, host: &mut H, args: &[String]) {
+    let Some(name) = args.get(1) else {
+        console.print("exec <filename> : execute a script file");
+        return;
+    };
+    match host.read_file(name) {
+        Some(data) => {
+            console.print(format!("execing {name}"));
+            console.insert(&String::from_utf8_lossy(&data));
+        }
+        None => console.print(format!("couldn't exec {name}")),
+    }
+}
+
+fn cmd_echo<H: ConsoleHost>(console: &mut Console<H>, _host: &mut H, args: &[String]) {
+    console.print(args[1..].join(" "));
+}
+
+fn cmd_cmdlist<H: ConsoleHost>(console: &mut Console<H>, _host: &mut H, _args: &[String]) {
+    let names: Vec<String> = console.commands.keys().cloned().collect();
+    let count = names.len();
+    for name in names {
+        console.print(name);
+    }
+    console.print(format!("{count} commands"));
+}
+
+fn cmd_cvarlist<H: ConsoleHost>(console: &mut Console<H>, _host: &mut H, _args: &[String]) {
+    let mut lines: Vec<(String, String)> = console
+        .cvars
+        .iter()
+        .map(|var| {
+            let archive = if var.archive { '*' } else { ' ' };
+            let info = if var.info { 'u' } else { ' ' };
+            (
+                var.name.clone(),
+                format!("{archive}{info} {} \"{}\"", var.name, var.value),
+            )
+        })
+        .collect();
+    lines.sort();
+    let count = lines.len();
+    for (_, line) in lines {
+        console.print(line);
+    }
+    console.print(format!("{count} cvars"));
+}
+
+fn cmd_toggle<H: ConsoleHost>(console: &mut Console<H>, host: &mut H, args: &[String]) {
+    let Some(name) = args.get(1) else {
+        console.print("toggle <cvar> : flip a cvar between 0 and 1");
+        return;
+    };
+    let value = if console.cvars.value(name) != 0.0 {
+        "0"
+    } else {
+        "1"
+    };
+    console.set_cvar(host, name, value);
+}
+
+fn cmd_set<H: ConsoleHost>(console: &mut Console<H>, host: &mut H, args: &[String]) {
+    let (Some(name), Some(value)) = (args.get(1), args.get(2)) else {
+        console.print(format!("{} <cvar> <value>", args[0]));
+        return;
+    };
+    console.set_cvar(host, name, value);
+    if args[0].eq_ignore_ascii_case("seta")
+        && let Some(var) = console.cvars.get_mut(name)
+    {
+        var.archive = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[derive(Default)]
+    struct TestHost {
+        files: HashMap<String, String>,
+        forwarded: Vec<String>,
+        changed: Vec<(String, String)>,
+        said: Vec<String>,
+    }
+
+    impl ConsoleHost for TestHost {
+        fn read_file(&self, name: &str) -> Option<Vec<u8>> {
+            self.files.get(name).map(|text| text.as_bytes().to_vec())
+        }
+
+        fn forward(&mut self, line: &str) {
+            self.forwarded.push(line.to_string());
+        }
+
+        fn cvar_changed(&mut self, cvar: &Cvar) {
+            self.changed.push((cvar.name.clone(), cvar.value.clone()));
+        }
+    }
+
+    fn cmd_say(_console: &mut Console<TestHost>, host: &mut TestHost, args: &[String]) {
+        host.said.push(args[1..].join(" "));
+    }
+
+    #[test]
+    fn splits_on_semicolons_outside_quotes() {
+        let mut console = Console::new();
+        let mut host = TestHost::default();
+        console.register("say", cmd_say);
+        console.append("say one; say \"two; three\"\nkill");
+        console.execute(&mut host);
+        assert_eq!(host.said, ["one", "two; three"]);
+        assert_eq!(host.forwarded, ["kill"]);
+    }
+
+    #[test]
+    fn wait_defers_the_rest_to_the_next_frame() {
+        let mut console = Console::new();
+        let mut host = TestHost::default();
+        console.register("say", cmd_say);
+        console.append("say a; wait; say b");
+        console.execute(&mut host);
+        assert_eq!(host.said, ["a"]);
+        console.execute(&mut host);
+        assert_eq!(host.said, ["a", "b"]);
+    }
+
+    #[test]
+    fn expands_aliases_and_stops_runaway_loops() {
+        let mut console = Console::new();
+        let mut host = TestHost::default();
+        console.register("say", cmd_say);
+        console.append("alias greet \"say hi; say there\"; greet; say done");
+        console.execute(&mut host);
+        assert_eq!(host.said, ["hi", "there", "done"]);
+
+        console.append("alias loop loop; loop");
+        console.execute(&mut host);
+        assert!(console.text.is_empty());
+        assert!(console.drain_output().any(|line| line.contains("too many")));
+    }
+
+    #[test]
+    fn execs_files_from_the_host() {
+        let mut console = Console::new();
+        let mut host = TestHost::default();
+        console.register("say", cmd_say);
+        host.files
+            .insert("autoexec.cfg".to_string(), "say from file\n".to_string());
+        console.append("exec autoexec.cfg; exec missing.cfg; say after");
+        console.execute(&mut host);
+        assert_eq!(host.said, ["from file", "after"]);
+        let output: Vec<String> = console.drain_output().collect();
+        assert_eq!(
+            output,
+            ["execing autoexec.cfg", "couldn't exec missing.cfg"]
+        );
+    }
+
+    #[test]
+    fn sets_toggles_and_archives_cvars() {
+        let mut console = Console::new();
+        let mut host = TestHost::default();
+        console
+            .cvars
+            .register(Cvar::new("name", "player").with_flags(true, true));
+        console.append("name \"new name\"; toggle cl_nolerp; toggle cl_nolerp; toggle cl_nolerp");
+        console.append("set temp 5; seta sensitivity 7; name; echo hello  world");
+        console.execute(&mut host);
+
+        assert_eq!(console.cvars.string("name"), "new name");
+        assert_eq!(console.cvars.value("cl_nolerp"), 1.0);
+        assert_eq!(
+            host.changed[0],
+            ("name".to_string(), "new name".to_string())
+        );
+        let output: Vec<String> = console.drain_output().collect();
+        assert_eq!(output, ["\"name\" is \"new name\"", "hello world"]);
+        assert_eq!(
+            console.archived_config(),
+            "seta name \"new name\"\nseta sensitivity \"7\"\n"
+        );
+        assert!(host.forwarded.is_empty());
+    }
+
+    #[test]
+    fn completes_commands_aliases_and_cvars() {
+        let mut console: Console<TestHost> = Console::new();
+        let mut host = TestHost::default();
+        console.cvars.register(Cvar::new("Tempo", "1"));
+        console.append("alias tele \"echo hi\"; set temp 5");
+        console.execute(&mut host);
+        assert_eq!(console.complete("TE"), ["Tempo", "tele", "temp"]);
+        assert_eq!(console.complete("tog"), ["toggle"]);
+        assert!(console.complete("nothing").is_empty());
+    }
+}
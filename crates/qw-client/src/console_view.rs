@@ -0,0 +1,450 @@
+use std::collections::VecDeque;
+
+use qw_renderer::UiImage;
+use qw_window_glfw::Key;
+
+use crate::console::{Console, ConsoleHost};
+use crate::hud::{Canvas, HudPics};
+
+pub const CONSOLE_PICS: [&str; 1] = ["gfx/conback.lmp"];
+
+pub const CONSOLE_CVARS: [(&str, &str, bool); 1] = [("con_notifytime", "3", true)];
+
+// console.c's CON_TEXTSIZE, counted in lines rather than bytes.
+const MAX_LINES: usize = 1024;
+// NUM_CON_TIMES.
+const NOTIFY_LINES: usize = 4;
+// keys.c's CMDLINES and MAXCMDLINE.
+const HISTORY_LINES: usize = 32;
+const MAX_INPUT: usize = 255;
+// How far PgUp and the mouse wheel move back through the text.
+const SCROLL_ROWS: usize = 2;
+const CURSOR_GLYPH: u8 = 11;
+
+// The drop-down console: console.c's scrollback and notify lines, with
+// keys.c's edit line and command history.
+#[derive(Debug, Default)]
+pub struct ConsoleView {
+    open: bool,
+    // Each printed line with the time it came in.
+    lines: VecDeque<(f64, String)>,
+    last_time: f64,
+    // Rows back from the newest that the bottom of the console shows.
+    backscroll: usize,
+    input: String,
+    // A byte offset into `input`, which only ever holds ASCII.
+    cursor: usize,
+    history: VecDeque<String>,
+    history_line: Option<usize>,
+}
+
+impl ConsoleView {
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    // Con_ToggleConsole_f also throws away a half-typed line.
+    pub fn toggle(&mut self) {
+        self.open = !self.open;
+        self.clear_input();
+    }
+
+    pub fn open(&mut self) {
+        self.open = true;
+    }
+
+    pub fn close(&mut self) {
+        self.open = false;
+    }
+
+    // Con_Print; scrolled back text stays where it is as more comes in.
+    // conchars only has the one byte per character, so anything past ASCII
+    // shows as '?'.
+    pub fn print(&mut self, text: &str, time: f64) {
+        self.last_time = time;
+        for line in text.trim_end_matches('\n').split('\n') {
+            if self.backscroll > 0 {
+                self.backscroll += 1;
+            }
+            let line = line
+                .chars()
+                .map(|ch| if ch.is_ascii() { ch } else { '?' })
+                .collect();
+            self.lines.push_back((time, line));
+            if self.lines.len() > MAX_LINES {
+                self.lines.pop_front();
+            }
+        }
+    }
+
+    // The scrollback as condump writes it.
+    pub fn text(&self) -> String {
+        self.lines
+            .iter()
+            .map(|(_, line)| format!("{line}\n"))
+            .collect()
+    }
+
+    // Key_Console: edits the line and returns it once Enter sends it off.
+    pub fn key<H: ConsoleHost>(&mut self, key: Key, console: &Console<H>) -> Option<String> {
+        match key {
+            Key::Enter | Key::KpEnter => return self.submit(),
+            Key::Tab => self.complete(console),
+            Key::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.input.remove(self.cursor);
+            }
+            Key::Del if self.cursor < self.input.len() => {
+                self.input.remove(self.cursor);
+            }
+            Key::Left => self.cursor = self.cursor.saturating_sub(1),
+            Key::Right => self.cursor = (self.cursor + 1).min(self.input.len()),
+            Key::Home => self.cursor = 0,
+            Key::End => self.cursor = self.input.len(),
+            Key::Up => {
+                let line = match self.history_line {
+                    Some(line) => line.saturating_sub(1),
+                    None => self.history.len().checked_sub(1)?,
+                };
+                self.recall(Some(line));
+            }
+            Key::Down => {
+                let line = self
+                    .history_line
+                    .map(|line| line + 1)
+                    .filter(|&line| line < self.history.len());
+                self.recall(line);
+            }
+            Key::PgUp | Key::MWheelUp => self.backscroll += SCROLL_ROWS,
+            Key::PgDn | Key::MWheelDown => {
+                self.backscroll = self.backscroll.saturating_sub(SCROLL_ROWS);
+            }
+            _ => {}
+        }
+        None
+    }
+
+    // Typed text; the console key's own characters never reach the line.
+    pub fn char(&mut self, ch: char) {
+        if !ch.is_ascii() || ch.is_ascii_control() || ch == '`' || ch == '~' {
+            return;
+        }
+        if self.input.len() < MAX_INPUT {
+            self.input.insert(self.cursor, ch);
+            self.cursor += 1;
+        }
+    }
+
+    fn submit(&mut self) -> Option<String> {
+        let line = std::mem::take(&mut self.input);
+        self.cursor = 0;
+        self.history_line = None;
+        self.backscroll = 0;
+        let time = self.last_time;
+        self.print(&format!("]{line}"), time);
+        let line = line.trim();
+        if line.is_empty() {
+            return None;
+        }
+        if self.history.back().map(String::as_str) != Some(line) {
+            self.history.push_back(line.to_string());
+            if self.history.len() > HISTORY_LINES {
+                self.history.pop_front();
+            }
+        }
+        // Quake takes commands with or without a leading slash.
+        Some(line.trim_start_matches(['/', '\\']).to_string())
+    }
+
+    fn recall(&mut self, line: Option<usize>) {
+        self.history_line = line;
+        self.input = line
+            .map(|line| self.history[line].clone())
+            .unwrap_or_default();
+        self.cursor = self.input.len();
+    }
+
+    fn clear_input(&mut self) {
+        self.input.clear();
+        self.cursor = 0;
+        self.history_line = None;
+    }
+
+    // Cmd_CompleteCommand and Cvar_CompleteVariable on the first word: one
+    // match fills it in, several are listed and the common start filled in.
+    fn complete<H: ConsoleHost>(&mut self, console: &Console<H>) {
+        let typed = self.input[..self.cursor].to_string();
+        let partial = typed.trim_start_matches(['/', '\\']);
+        if partial.is_empty() || partial.contains(char::is_whitespace) {
+            return;
+        }
+        let matches = console.complete(partial);
+        let completed = match matches.as_slice() {
+            [] => return,
+            [only] => format!("{only} "),
+            [first, rest @ ..] => {
+                let time = self.last_time;
+                for name in &matches {
+                    self.print(&format!("  {name}"), time);
+                }
+                let common = rest.iter().fold(first.len(), |len, name| {
+                    first
+                        .bytes()
+                        .zip(name.bytes())
+                        .take(len)
+                        .take_while(|(a, b)| a == b)
+                        .count()
+                });
+                first[..common].to_string()
+            }
+        };
+        let prefix = &typed[..typed.len() - partial.len()];
+        let rest = self.input[self.cursor..].to_string();
+        self.input = format!("{prefix}{completed}");
+        self.cursor = self.input.len();
+        self.input.push_str(&rest);
+        self.input.truncate(MAX_INPUT);
+    }
+
+    // Con_DrawConsole down to half the screen while it's open, and
+    // Con_DrawNotify's last few lines at the top while it isn't.
+    pub fn draw(
+        &mut self,
+        pics: &HudPics,
+        scale: f32,
+        screen: (u32, u32),
+        time: f64,
+        notify_time: f64,
+    ) -> Vec<UiImage> {
+        let mut canvas = Canvas::new(pics, scale, screen);
+        let columns = (canvas.width / 8 - 2).max(1) as usize;
+        if !self.open {
+            let recent: Vec<&str> = self
+                .lines
+                .iter()
+                .rev()
+                .take(NOTIFY_LINES)
+                .take_while(|(printed, _)| time - printed < notify_time)
+                .map(|(_, line)| line.as_str())
+                .collect();
+            let rows: Vec<&str> = recent
+                .into_iter()
+                .rev()
+                .flat_map(|line| wrap(line, columns))
+                .collect();
+            let rows = &rows[rows.len().saturating_sub(NOTIFY_LINES)..];
+            for (i, row) in rows.iter().enumerate() {
+                canvas.text(8, i as i32 * 8, row, false);
+            }
+            return canvas.images;
+        }
+
+        let lines = canvas.height / 2;
+        let (width, height) = (canvas.width, canvas.height);
+        canvas.stretch(0, 0, width, lines, "fade");
+        canvas.stretch(0, lines - height, width, height, "gfx/conback.lmp");
+
+        let rows: Vec<&str> = self
+            .lines
+            .iter()
+            .flat_map(|(_, line)| wrap(line, columns))
+            .collect();
+        self.backscroll = self.backscroll.min(rows.len().saturating_sub(1));
+        let mut y = lines - 24;
+        let shown = rows[..rows.len() - self.backscroll].iter().rev();
+        if self.backscroll > 0 {
+            // The bottom row marks that there's more below.
+            for x in (0..columns).step_by(4) {
+                canvas.character(8 + x as i32 * 8, y, b'^');
+            }
+            y -= 8;
+        }
+        for row in shown {
+            if y < 0 {
+                break;
+            }
+            canvas.text(8, y, row, false);
+            y -= 8;
+        }
+
+        // The edit line scrolls sideways to keep the cursor in view.
+        let line = format!("]{}", self.input);
+        let cursor = self.cursor + 1;
+        let start = (cursor + 1).saturating_sub(columns);
+        let visible = &line[start..line.len().min(start + columns)];
+        let y = lines - 16;
+        let blink = (time * 4.0) as i64 & 1 == 0;
+        for (i, byte) in visible.bytes().enumerate() {
+            if !(blink && start + i == cursor) {
+                canvas.character(8 + i as i32 * 8, y, byte);
+            }
+        }
+        if blink {
+            canvas.character(8 + (cursor - start) as i32 * 8, y, CURSOR_GLYPH);
+        }
+        canvas.images
+    }
+}
+
+// Con_Linefeed's wrapping: breaks at the last space that fits, or mid-word
+// when there isn't one.
+fn wrap(line: &str, columns: usize) -> Vec<&str> {
+    let mut rows = Vec::new();
+    let mut rest = line;
+    while rest.len() > columns {
+        let split = rest[..=columns]
+            .rfind(' ')
+            .filter(|&split| split > 0)
+            .unwrap_or(columns);
+        rows.push(&rest[..split]);
+        rest = rest[split..].strip_prefix(' ').unwrap_or(&rest[split..]);
+    }
+    rows.push(rest);
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use qw_common::Cvar;
+    use qw_renderer::UiPic;
+
+    struct NoHost;
+
+    impl ConsoleHost for NoHost {
+        fn read_file(&self, _name: &str) -> Option<Vec<u8>> {
+            None
+        }
+
+        fn forward(&mut self, _line: &str) {}
+    }
+
+    fn cmd_none(_console: &mut Console<NoHost>, _host: &mut NoHost, _args: &[String]) {}
+
+    fn console() -> Console<NoHost> {
+        let mut console = Console::new();
+        for name in ["map", "maps", "reconnect", "record"] {
+            console.register(name, cmd_none);
+        }
+        console.cvars.register(Cvar::new("rate", "2500"));
+        console
+    }
+
+    fn type_text(view: &mut ConsoleView, text: &str) {
+        text.chars().for_each(|ch| view.char(ch));
+    }
+
+    fn glyphs() -> HudPics {
+        let mut pics = HudPics::default();
+        let pic = |size| UiPic {
+            width: size,
+            height: size,
+            rgba: vec![255; (size * size * 4) as usize],
+        };
+        pics.set("fade", pic(1));
+        for glyph in 0..256 {
+            pics.set(&format!("char{glyph}"), pic(8));
+        }
+        pics
+    }
+
+    #[test]
+    fn edits_the_line_and_sends_it() {
+        let console = console();
+        let mut view = ConsoleView::default();
+        type_text(&mut view, "sya`~ hi");
+        assert_eq!(view.input, "sya hi");
+        for key in [Key::Home, Key::Right, Key::Right, Key::Backspace] {
+            view.key(key, &console);
+        }
+        view.key(Key::Right, &console);
+        type_text(&mut view, "y");
+        view.key(Key::End, &console);
+        view.key(Key::Left, &console);
+        view.key(Key::Del, &console);
+        assert_eq!(view.input, "say h");
+        assert_eq!(view.key(Key::Enter, &console).as_deref(), Some("say h"));
+        assert_eq!(view.input, "");
+        assert_eq!(view.key(Key::Enter, &console), None);
+        type_text(&mut view, "/kill");
+        assert_eq!(view.key(Key::KpEnter, &console).as_deref(), Some("kill"));
+        assert_eq!(view.text(), "]say h\n]\n]/kill\n");
+    }
+
+    #[test]
+    fn recalls_history() {
+        let console = console();
+        let mut view = ConsoleView::default();
+        for line in ["one", "two", "two", "three"] {
+            type_text(&mut view, line);
+            view.key(Key::Enter, &console);
+        }
+        view.key(Key::Up, &console);
+        assert_eq!(view.input, "three");
+        view.key(Key::Up, &console);
+        view.key(Key::Up, &console);
+        view.key(Key::Up, &console);
+        assert_eq!(view.input, "one");
+        view.key(Key::Down, &console);
+        assert_eq!(view.input, "two");
+        view.key(Key::Down, &console);
+        view.key(Key::Down, &console);
+        assert_eq!(view.input, "");
+    }
+
+    #[test]
+    fn completes_commands_and_cvars() {
+        let console = console();
+        let mut view = ConsoleView::default();
+        type_text(&mut view, "/ra");
+        view.key(Key::Tab, &console);
+        assert_eq!(view.input, "/rate ");
+
+        let mut view = ConsoleView::default();
+        type_text(&mut view, "re");
+        view.key(Key::Tab, &console);
+        assert_eq!(view.input, "reco");
+        assert_eq!(view.text(), "  reconnect\n  record\n");
+        type_text(&mut view, "r");
+        view.key(Key::Tab, &console);
+        assert_eq!(view.input, "record ");
+
+        // Only the command itself completes.
+        let mut view = ConsoleView::default();
+        type_text(&mut view, "map ma");
+        view.key(Key::Tab, &console);
+        assert_eq!(view.input, "map ma");
+    }
+
+    #[test]
+    fn notify_lines_fade_and_scrollback_stays_put() {
+        let pics = glyphs();
+        let mut view = ConsoleView::default();
+        view.print("a\nb\n", 0.0);
+        view.print("c", 2.0);
+        // Two characters on the row at the top, one for each line still up.
+        let images = view.draw(&pics, 1.0, (320, 200), 4.0, 3.0);
+        assert_eq!(images.len(), 1);
+        assert_eq!((images[0].x, images[0].y), (8, 0));
+        let images = view.draw(&pics, 1.0, (320, 200), 1.0, 3.0);
+        assert_eq!(images.len(), 3);
+
+        view.toggle();
+        assert!(view.draw(&pics, 1.0, (320, 200), 0.0, 3.0).len() > 3);
+        view.key(Key::PgUp, &console());
+        view.print("d", 5.0);
+        assert_eq!(view.backscroll, 3);
+        view.draw(&pics, 1.0, (320, 200), 0.0, 3.0);
+        assert_eq!(view.backscroll, 3);
+        view.key(Key::PgDn, &console());
+        view.key(Key::PgDn, &console());
+        assert_eq!(view.backscroll, 0);
+    }
+
+    #[test]
+    fn wraps_at_spaces() {
+        assert_eq!(wrap("abc def ghi", 7), ["abc def", "ghi"]);
+        assert_eq!(wrap("abcdefghij", 4), ["abcd", "efgh", "ij"]);
+        assert_eq!(wrap("", 4), [""]);
+    }
+}
@@ -0,0 +1,742 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::state::ClientState;
+use qw_common::{
+    MAX_MSGLEN, SizeBuf, SizeBufError, StringListChunk, SvcMessage, UserCmd, Vec3, value_for_key,
+    write_svc_message,
+};
+
+const DEM_CMD: u8 = 0;
+const DEM_READ: u8 = 1;
+const DEM_SET: u8 = 2;
+
+#[derive(Debug)]
+pub enum DemoError {
+    Io(io::Error),
+    Buffer(SizeBufError),
+    BadName(String),
+    BadRecord(u8),
+    BadLength(i32),
+    UnsupportedFormat(String),
+}
+
+impl fmt::Display for DemoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DemoError::Io(err) => write!(f, "demo io error: {}", err),
+            DemoError::Buffer(err) => write!(f, "demo buffer error: {:?}", err),
+            DemoError::BadName(name) => write!(f, "invalid demo name: {}", name),
+            DemoError::BadRecord(kind) => write!(f, "bad demo record type: {}", kind),
+            DemoError::BadLength(len) => write!(f, "bad demo message length: {}", len),
+            DemoError::UnsupportedFormat(ext) => write!(f, "unsupported demo format: {}", ext),
+        }
+    }
+}
+
+impl From<io::Error> for DemoError {
+    fn from(err: io::Error) -> Self {
+        DemoError::Io(err)
+    }
+}
+
+impl From<SizeBufError> for DemoError {
+    fn from(err: SizeBufError) -> Self {
+        DemoError::Buffer(err)
+    }
+}
+
+// Writes a .qwd stream: each record is a little-endian float time and a
+// command byte, laid out as cl_demo.c does so other clients can play it.
+pub struct DemoRecorder<W: Write = BufWriter<File>> {
+    out: W,
+}
+
+impl DemoRecorder {
+    pub fn create(path: &Path) -> Result<Self, DemoError> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write> DemoRecorder<W> {
+    pub fn new(out: W) -> Self {
+        Self { out }
+    }
+
+    // A received packet, netchan header included.
+    pub fn write_packet(&mut self, time: f32, packet: &[u8]) -> Result<(), DemoError> {
+        self.out.write_all(&time.to_le_bytes())?;
+        self.out.write_all(&[DEM_READ])?;
+        self.out.write_all(&(packet.len() as i32).to_le_bytes())?;
+        self.out.write_all(packet)?;
+        Ok(())
+    }
+
+    // The usercmd struct as the C client stores it in memory, padding and all.
+    pub fn write_cmd(
+        &mut self,
+        time: f32,
+        cmd: &UserCmd,
+        view_angles: Vec3,
+    ) -> Result<(), DemoError> {
+        self.out.write_all(&time.to_le_bytes())?;
+        self.out.write_all(&[DEM_CMD])?;
+        self.out.write_all(&[cmd.msec, 0, 0, 0])?;
+        write_vec3(&mut self.out, cmd.angles)?;
+        self.out.write_all(&cmd.forwardmove.to_le_bytes())?;
+        self.out.write_all(&cmd.sidemove.to_le_bytes())?;
+        self.out.write_all(&cmd.upmove.to_le_bytes())?;
+        self.out.write_all(&[cmd.buttons, cmd.impulse])?;
+        write_vec3(&mut self.out, view_angles)?;
+        Ok(())
+    }
+
+    pub fn write_set(&mut self, time: f32, outgoing: u32, incoming: u32) -> Result<(), DemoError> {
+        self.out.write_all(&time.to_le_bytes())?;
+        self.out.write_all(&[DEM_SET])?;
+        self.out.write_all(&outgoing.to_le_bytes())?;
+        self.out.write_all(&incoming.to_le_bytes())?;
+        Ok(())
+    }
+
+    // Recording started mid-game, so replay the signon the server sent us
+    // from the current state, in the order CL_Record_f writes it.
+    pub fn write_signon(&mut self, time: f32, state: &ClientState) -> Result<(), DemoError> {
+        let Some(data) = &state.serverdata else {
+            return Ok(());
+        };
+        let mut sequence = 1;
+
+        let serverinfo = format!("fullserverinfo \"{}\"\n", state.serverinfo.as_str());
+        self.write_signon_messages(
+            time,
+            &mut sequence,
+            &[
+                SvcMessage::ServerData(data.clone()),
+                SvcMessage::StuffText(serverinfo),
+            ],
+        )?;
+
+        for chunk in list_chunks(&state.sounds) {
+            self.write_signon_messages(time, &mut sequence, &[SvcMessage::SoundList(chunk)])?;
+        }
+        for chunk in list_chunks(&state.models) {
+            self.write_signon_messages(time, &mut sequence, &[SvcMessage::ModelList(chunk)])?;
+        }
+
+        let mut messages = Vec::new();
+        for entity in &state.static_entities {
+            messages.push(SvcMessage::SpawnStatic(*entity));
+        }
+        for sound in &state.static_sounds {
+            messages.push(SvcMessage::SpawnStaticSound {
+                origin: sound.origin,
+                sound: sound.sound,
+                volume: sound.volume,
+                attenuation: sound.attenuation,
+            });
+        }
+        for (entity, baseline) in state.baselines.iter().enumerate() {
+            if baseline.modelindex != 0 {
+                messages.push(SvcMessage::SpawnBaseline {
+                    entity: entity as u16,
+                    baseline: *baseline,
+                });
+            }
+        }
+        messages.push(SvcMessage::StuffText(format!(
+            "cmd spawn {} 0\n",
+            data.server_count
+        )));
+        self.write_signon_messages(time, &mut sequence, &messages)?;
+
+        let mut messages = Vec::new();
+        for (slot, player) in state.players.iter().enumerate() {
+            let name = value_for_key(player.userinfo.as_str(), "name");
+            if name.is_none_or(|name| name.is_empty()) {
+                continue;
+            }
+            let slot = slot as u8;
+            messages.push(SvcMessage::UpdateFrags {
+                slot,
+                frags: player.frags,
+            });
+            messages.push(SvcMessage::UpdatePing {
+                slot,
+                ping: player.ping,
+            });
+            messages.push(SvcMessage::UpdatePl {
+                slot,
+                packet_loss: player.packet_loss,
+            });
+            messages.push(SvcMessage::UpdateEnterTime {
+                slot,
+                seconds_ago: player.enter_time,
+            });
+            messages.push(SvcMessage::UpdateUserInfo {
+                slot,
+                user_id: player.user_id,
+                userinfo: player.userinfo.as_str().to_string(),
+            });
+        }
+        for (style, value) in state.lightstyles.iter().enumerate() {
+            messages.push(SvcMessage::LightStyle {
+                style: style as u8,
+                value: value.clone(),
+            });
+        }
+        for (index, value) in state.stats.iter().enumerate() {
+            messages.push(SvcMessage::UpdateStatLong {
+                index: index as u8,
+                value: *value,
+            });
+        }
+        messages.push(SvcMessage::StuffText("skins\n".to_string()));
+        self.write_signon_messages(time, &mut sequence, &messages)
+    }
+
+    // The same end marker the C client writes, so players stop cleanly.
+    pub fn finish(mut self, time: f32) -> Result<W, DemoError> {
+        let mut buf = SizeBuf::new(32);
+        buf.write_i32(-1)?;
+        write_svc_message(&mut buf, &SvcMessage::Disconnect)?;
+        buf.write_string(Some("EndOfDemo"))?;
+        self.write_packet(time, buf.as_slice())?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    // Packs messages into fake in-order packets, flushing at half a message
+    // the way CL_Record_f does so no packet overflows on playback.
+    fn write_signon_messages(
+        &mut self,
+        time: f32,
+        sequence: &mut u32,
+        messages: &[SvcMessage],
+    ) -> Result<(), DemoError> {
+        let mut buf = SizeBuf::new(MAX_MSGLEN + 8);
+        for message in messages {
+            if buf.len() == 0 {
+                buf.write_u32(*sequence)?;
+                buf.write_u32(*sequence)?;
+                *sequence += 1;
+            }
+            write_svc_message(&mut buf, message)?;
+            if buf.len() > MAX_MSGLEN / 2 {
+                self.write_packet(time, buf.as_slice())?;
+                buf.clear();
+            }
+        }
+        if buf.len() > 0 {
+            self.write_packet(time, buf.as_slice())?;
+        }
+        Ok(())
+    }
+}
+
+fn write_vec3(out: &mut impl Write, value: Vec3) -> io::Result<()> {
+    out.write_all(&value.x.to_le_bytes())?;
+    out.write_all(&value.y.to_le_bytes())?;
+    out.write_all(&value.z.to_le_bytes())
+}
+
+// Slot 0 is never sent; the list runs until the first empty name.
+fn list_chunks(names: &[String]) -> Vec<StringListChunk> {
+    let mut chunks = Vec::new();
+    let mut start = 1usize;
+    let mut items = Vec::new();
+    let mut size = 0;
+    for name in names.iter().skip(1).take_while(|name| !name.is_empty()) {
+        size += name.len() + 1;
+        items.push(name.clone());
+        if size > MAX_MSGLEN / 2 {
+            let next = start + items.len();
+            chunks.push(StringListChunk {
+                start: start as u8,
+                items: std::mem::take(&mut items),
+                next: next as u8,
+            });
+            start = next;
+            size = 0;
+        }
+    }
+    chunks.push(StringListChunk {
+        start: start as u8,
+        items,
+        next: 0,
+    });
+    chunks
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DemoRecord {
+    Packet(Vec<u8>),
+    Cmd { cmd: UserCmd, view_angles: Vec3 },
+    Set { outgoing: u32, incoming: u32 },
+}
+
+pub struct DemoReader<R: Read> {
+    input: R,
+}
+
+impl<R: Read> DemoReader<R> {
+    pub fn new(input: R) -> Self {
+        Self { input }
+    }
+
+    // None at the end of the file; a record cut short counts as the end too,
+    // since that's what a client that crashed mid-recording leaves behind.
+    pub fn next_record(&mut self) -> Result<Option<(f32, DemoRecord)>, DemoError> {
+        match self.read_record() {
+            Err(DemoError::Io(err)) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+            result => result.map(Some),
+        }
+    }
+
+    fn read_record(&mut self) -> Result<(f32, DemoRecord), DemoError> {
+        let time = f32::from_le_bytes(self.read_array()?);
+        let [kind] = self.read_array()?;
+        let record = match kind {
+            DEM_CMD => {
+                let [msec, _, _, _] = self.read_array()?;
+                let angles = self.read_vec3()?;
+                let forwardmove = i16::from_le_bytes(self.read_array()?);
+                let sidemove = i16::from_le_bytes(self.read_array()?);
+                let upmove = i16::from_le_bytes(self.read_array()?);
+                let [buttons, impulse] = self.read_array()?;
+                let view_angles = self.read_vec3()?;
+                DemoRecord::Cmd {
+                    cmd: UserCmd {
+                        msec,
+                        angles,
+                        forwardmove,
+                        sidemove,
+                        upmove,
+                        buttons,
+                        impulse,
+                    },
+                    view_angles,
+                }
+            }
+            DEM_READ => {
+                let len = i32::from_le_bytes(self.read_array()?);
+                if len < 0 || len as usize > MAX_MSGLEN + 8 {
+                    return Err(DemoError::BadLength(len));
+                }
+                let mut packet = vec![0; len as usize];
+                self.input.read_exact(&mut packet)?;
+                DemoRecord::Packet(packet)
+            }
+            DEM_SET => DemoRecord::Set {
+                outgoing: u32::from_le_bytes(self.read_array()?),
+                incoming: u32::from_le_bytes(self.read_array()?),
+            },
+            _ => return Err(DemoError::BadRecord(kind)),
+        };
+        Ok((time, record))
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], DemoError> {
+        let mut bytes = [0; N];
+        self.input.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn read_vec3(&mut self) -> Result<Vec3, DemoError> {
+        Ok(Vec3::new(
+            f32::from_le_bytes(self.read_array()?),
+            f32::from_le_bytes(self.read_array()?),
+            f32::from_le_bytes(self.read_array()?),
+        ))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TimeDemoReport {
+    pub frames: u32,
+    pub seconds: f64,
+}
+
+impl TimeDemoReport {
+    pub fn fps(&self) -> f64 {
+        if self.seconds > 0.0 {
+            f64::from(self.frames) / self.seconds
+        } else {
+            0.0
+        }
+    }
+}
+
+impl fmt::Display for TimeDemoReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} frames {:.1} seconds {:.1} fps",
+            self.frames,
+            self.seconds,
+            self.fps()
+        )
+    }
+}
+
+// Hands out demo records as they fall due. playdemo keeps the recorded
+// pace; timedemo gives one packet per frame as fast as frames come, like
+// CL_GetDemoMessage, and times the run.
+pub struct DemoPlayer<R: Read = BufReader<File>> {
+    reader: DemoReader<R>,
+    pending: Option<(f32, DemoRecord)>,
+    timedemo: bool,
+    time_offset: Option<f64>,
+    packet_due: bool,
+    frames: u32,
+    started: Option<f64>,
+    finished: Option<f64>,
+}
+
+impl DemoPlayer {
+    pub fn open(path: &Path, timedemo: bool) -> Result<Self, DemoError> {
+        let mut path = path.to_path_buf();
+        if !path.exists() && path.extension().is_none() {
+            path.set_extension("qwd");
+        }
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("qwd") => {}
+            ext => return Err(DemoError::UnsupportedFormat(ext.unwrap_or("").to_string())),
+        }
+        Ok(Self::new(BufReader::new(File::open(path)?), timedemo))
+    }
+}
+
+impl<R: Read> DemoPlayer<R> {
+    pub fn new(input: R, timedemo: bool) -> Self {
+        Self {
+            reader: DemoReader::new(input),
+            pending: None,
+            timedemo,
+            time_offset: None,
+            packet_due: true,
+            frames: 0,
+            started: None,
+            finished: None,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished.is_some()
+    }
+
+    pub fn is_timedemo(&self) -> bool {
+        self.timedemo
+    }
+
+    // Called once per client frame before pulling records.
+    pub fn start_frame(&mut self) {
+        self.packet_due = true;
+    }
+
+    // None when nothing is due yet or the demo has ended.
+    pub fn next_record(&mut self, now: f64) -> Result<Option<DemoRecord>, DemoError> {
+        if self.finished.is_some() {
+            return Ok(None);
+        }
+        if self.pending.is_none() {
+            self.pending = self.reader.next_record()?;
+        }
+        let Some((time, record)) = &self.pending else {
+            self.finish(now);
+            return Ok(None);
+        };
+        if let DemoRecord::Packet(packet) = record
+            && packet.starts_with(&[0xff; 4])
+        {
+            self.finish(now);
+            return Ok(None);
+        }
+        if !self.timedemo {
+            let offset = *self.time_offset.get_or_insert(f64::from(*time) - now);
+            if f64::from(*time) - offset > now {
+                return Ok(None);
+            }
+        } else if matches!(record, DemoRecord::Packet(_)) {
+            if !self.packet_due {
+                return Ok(None);
+            }
+            self.packet_due = false;
+            self.frames += 1;
+            self.started.get_or_insert(now);
+        }
+        Ok(self.pending.take().map(|(_, record)| record))
+    }
+
+    pub fn report(&self) -> Option<TimeDemoReport> {
+        let (started, finished) = (self.started?, self.finished?);
+        Some(TimeDemoReport {
+            frames: self.frames,
+            seconds: finished - started,
+        })
+    }
+
+    fn finish(&mut self, now: f64) {
+        self.pending = None;
+        self.finished = Some(now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use qw_common::{EntityState, MoveVars, MsgReader, ServerData, parse_svc_message};
+
+    fn read_records(data: &[u8]) -> Vec<(f32, u8, Vec<u8>)> {
+        let mut records = Vec::new();
+        let mut pos = 0;
+        while pos < data.len() {
+            let time = f32::from_le_bytes(data[pos..pos + 4].try_into().unwrap());
+            let kind = data[pos + 4];
+            pos += 5;
+            let len = match kind {
+                DEM_CMD => 36,
+                DEM_SET => 8,
+                _ => {
+                    let len = i32::from_le_bytes(data[pos..pos + 4].try_into().unwrap());
+                    pos += 4;
+                    len as usize
+                }
+            };
+            records.push((time, kind, data[pos..pos + len].to_vec()));
+            pos += len;
+        }
+        records
+    }
+
+    #[test]
+    fn writes_records_in_qwd_layout() {
+        let mut recorder = DemoRecorder::new(Vec::new());
+        recorder.write_packet(1.5, &[9, 8, 7]).unwrap();
+        let cmd = UserCmd {
+            msec: 13,
+            angles: Vec3::new(1.0, 2.0, 3.0),
+            forwardmove: 400,
+            sidemove: -350,
+            upmove: 0,
+            buttons: 1,
+            impulse: 7,
+        };
+        recorder
+            .write_cmd(2.0, &cmd, Vec3::new(4.0, 5.0, 6.0))
+            .unwrap();
+        recorder.write_set(2.5, 40, 39).unwrap();
+        let data = recorder.finish(3.0).unwrap();
+
+        let records = read_records(&data);
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[0], (1.5, DEM_READ, vec![9, 8, 7]));
+
+        let (time, kind, cmd) = &records[1];
+        assert_eq!((*time, *kind), (2.0, DEM_CMD));
+        assert_eq!(&cmd[..4], &[13, 0, 0, 0]);
+        assert_eq!(f32::from_le_bytes(cmd[8..12].try_into().unwrap()), 2.0);
+        assert_eq!(i16::from_le_bytes(cmd[18..20].try_into().unwrap()), -350);
+        assert_eq!(&cmd[22..24], &[1, 7]);
+        assert_eq!(f32::from_le_bytes(cmd[32..36].try_into().unwrap()), 6.0);
+
+        assert_eq!(records[2].1, DEM_SET);
+        assert_eq!(records[2].2, [40, 0, 0, 0, 39, 0, 0, 0]);
+
+        let end = &records[3].2;
+        assert_eq!(&end[..4], &[0xff; 4]);
+        let mut reader = MsgReader::new(&end[4..]);
+        assert_eq!(
+            parse_svc_message(&mut reader).unwrap(),
+            SvcMessage::Disconnect
+        );
+    }
+
+    #[test]
+    fn reads_back_what_it_records() {
+        let mut recorder = DemoRecorder::new(Vec::new());
+        let cmd = UserCmd {
+            msec: 20,
+            forwardmove: 200,
+            impulse: 3,
+            ..UserCmd::default()
+        };
+        recorder.write_set(0.0, 10, 9).unwrap();
+        recorder
+            .write_cmd(0.25, &cmd, Vec3::new(0.0, 90.0, 0.0))
+            .unwrap();
+        recorder.write_packet(0.5, &[1, 2, 3]).unwrap();
+        let data = recorder.finish(1.0).unwrap();
+
+        let mut reader = DemoReader::new(data.as_slice());
+        assert_eq!(
+            reader.next_record().unwrap(),
+            Some((
+                0.0,
+                DemoRecord::Set {
+                    outgoing: 10,
+                    incoming: 9
+                }
+            ))
+        );
+        assert_eq!(
+            reader.next_record().unwrap(),
+            Some((
+                0.25,
+                DemoRecord::Cmd {
+                    cmd,
+                    view_angles: Vec3::new(0.0, 90.0, 0.0)
+                }
+            ))
+        );
+        assert_eq!(
+            reader.next_record().unwrap(),
+            Some((0.5, DemoRecord::Packet(vec![1, 2, 3])))
+        );
+        assert!(reader.next_record().unwrap().is_some());
+        assert_eq!(reader.next_record().unwrap(), None);
+
+        let mut truncated = DemoReader::new(&data[..data.len() - 3]);
+        for _ in 0..3 {
+            truncated.next_record().unwrap();
+        }
+        assert_eq!(truncated.next_record().unwrap(), None);
+        let mut bad = DemoReader::new(&[0, 0, 0, 0, 9][..]);
+        assert!(matches!(bad.next_record(), Err(DemoError::BadRecord(9))));
+    }
+
+    #[test]
+    fn plays_at_the_recorded_pace_or_one_packet_per_frame() {
+        let mut recorder = DemoRecorder::new(Vec::new());
+        recorder.write_packet(10.0, &[1]).unwrap();
+        recorder.write_packet(10.5, &[2]).unwrap();
+        recorder.write_packet(11.0, &[3]).unwrap();
+        let data = recorder.finish(11.0).unwrap();
+
+        let mut player = DemoPlayer::new(data.as_slice(), false);
+        assert_eq!(
+            player.next_record(0.0).unwrap(),
+            Some(DemoRecord::Packet(vec![1]))
+        );
+        assert_eq!(player.next_record(0.25).unwrap(), None);
+        assert_eq!(
+            player.next_record(0.5).unwrap(),
+            Some(DemoRecord::Packet(vec![2]))
+        );
+        assert_eq!(
+            player.next_record(2.0).unwrap(),
+            Some(DemoRecord::Packet(vec![3]))
+        );
+        assert_eq!(player.next_record(2.0).unwrap(), None);
+        assert!(player.is_finished());
+        assert_eq!(player.report(), None);
+
+        let mut player = DemoPlayer::new(data.as_slice(), true);
+        let mut packets = 0;
+        for frame in 0..10 {
+            player.start_frame();
+            while player.next_record(f64::from(frame)).unwrap().is_some() {
+                packets += 1;
+            }
+            if frame < 3 {
+                assert_eq!(packets, frame + 1);
+            }
+        }
+        let report = player.report().unwrap();
+        assert_eq!(report.frames, 3);
+        assert_eq!(report.seconds, 2.0);
+        assert_eq!(report.to_string(), "3 frames 2.0 seconds 1.5 fps");
+    }
+
+    #[test]
+    fn signon_replays_current_state() {
+        let mut state = ClientState::new();
+        state.serverdata = Some(ServerData {
+            protocol: 28,
+            server_count: 3,
+            game_dir: "qw".to_string(),
+            player_num: 0,
+            spectator: false,
+            level_name: "start".to_string(),
+            movevars: MoveVars {
+                gravity: 800.0,
+                stopspeed: 100.0,
+                maxspeed: 320.0,
+                spectatormaxspeed: 500.0,
+                accelerate: 10.0,
+                airaccelerate: 10.0,
+                wateraccelerate: 10.0,
+                friction: 4.0,
+                waterfriction: 4.0,
+                entgravity: 1.0,
+            },
+        });
+        state.sounds = vec![String::new(), "misc/water1.wav".to_string()];
+        state.models = (0..200).map(|i| format!("progs/model{i}.mdl")).collect();
+        state.models[0].clear();
+        state.baselines[5] = EntityState {
+            modelindex: 2,
+            ..Default::default()
+        };
+        state.players[0].userinfo.set_raw("\\name\\player");
+        state.stats[1] = 100;
+
+        let mut recorder = DemoRecorder::new(Vec::new());
+        recorder.write_signon(0.5, &state).unwrap();
+        let data = recorder.out;
+
+        let mut messages = Vec::new();
+        let mut last_sequence = 0;
+        for (_, kind, packet) in read_records(&data) {
+            assert_eq!(kind, DEM_READ);
+            assert!(packet.len() <= MAX_MSGLEN);
+            let sequence = u32::from_le_bytes(packet[..4].try_into().unwrap());
+            assert_eq!(sequence, last_sequence + 1);
+            last_sequence = sequence;
+            let mut reader = MsgReader::new(&packet[8..]);
+            while reader.remaining() > 0 {
+                messages.push(parse_svc_message(&mut reader).unwrap());
+            }
+        }
+
+        assert!(matches!(messages[0], SvcMessage::ServerData(_)));
+        let SvcMessage::SoundList(sounds) = &messages[2] else {
+            panic!("expected a soundlist, got {:?}", messages[2]);
+        };
+        assert_eq!((sounds.start, sounds.next), (1, 0));
+        let models: Vec<&StringListChunk> = messages
+            .iter()
+            .filter_map(|message| match message {
+                SvcMessage::ModelList(chunk) => Some(chunk),
+                _ => None,
+            })
+            .collect();
+        assert!(models.len() > 1);
+        assert_eq!(models[1].start, models[0].next);
+        assert_eq!(models.last().unwrap().next, 0);
+        let total: usize = models.iter().map(|chunk| chunk.items.len()).sum();
+        assert_eq!(total, 199);
+        assert!(messages.contains(&SvcMessage::SpawnBaseline {
+            entity: 5,
+            baseline: state.baselines[5],
+        }));
+        assert!(messages.contains(&SvcMessage::UpdateStatLong {
+            index: 1,
+            value: 100,
+        }));
+        assert!(
+            messages
+                .iter()
+                .any(|message| matches!(message, SvcMessage::UpdateUserInfo { slot: 0, .. }))
+        );
+        assert_eq!(
+            messages.last(),
+            Some(&SvcMessage::StuffText("skins\n".to_string()))
+        );
+    }
+}
@@ -0,0 +1,756 @@
+// The status bar: Quake's Sbar drawn from gfx.wad pics, or a mini-HUD that
+// keeps to the corners of the screen.
+
+use std::collections::HashMap;
+
+use crate::console_view::CONSOLE_PICS;
+use crate::menu::MENU_PICS;
+use crate::state::ClientState;
+use qw_common::{
+    CvarRegistry, IT_ARMOR1, IT_ARMOR2, IT_ARMOR3, IT_CELLS, IT_INVISIBILITY, IT_INVULNERABILITY,
+    IT_NAILS, IT_QUAD, IT_ROCKETS, IT_SHELLS, IT_SHOTGUN, IT_SUIT, Palette, QuakeFs,
+    STAT_ACTIVEWEAPON, STAT_AMMO, STAT_ARMOR, STAT_HEALTH, STAT_ITEMS, STAT_SHELLS, Wad,
+};
+use qw_renderer::{UiImage, UiPic};
+
+// Name, default and whether config.cfg keeps it.
+pub const HUD_CVARS: [(&str, &str, bool); 2] =
+    [("scr_sbarscale", "2", true), ("scr_hudstyle", "0", true)];
+
+const SBAR_WIDTH: i32 = 320;
+const SBAR_HEIGHT: i32 = 24;
+const WEAPON_NAMES: [&str; 7] = [
+    "shotgun", "sshotgun", "nailgun", "snailgun", "rlaunch", "srlaunch", "lightng",
+];
+const AMMO_ITEMS: [u32; 4] = [IT_SHELLS, IT_NAILS, IT_ROCKETS, IT_CELLS];
+const AMMO_PICS: [&str; 4] = ["sb_shells", "sb_nails", "sb_rocket", "sb_cells"];
+// Keys, then the powerups, from bit 17 of the items.
+const ITEM_PICS: [&str; 6] = [
+    "sb_key1",
+    "sb_key2",
+    "sb_invis",
+    "sb_invuln",
+    "sb_suit",
+    "sb_quad",
+];
+const POWERUPS: [u32; 4] = [IT_INVISIBILITY, IT_INVULNERABILITY, IT_SUIT, IT_QUAD];
+const POWERUP_TIME: f64 = 30.0;
+// The yellow digits in conchars.
+const SMALL_DIGIT: u8 = 18;
+// Pics outside gfx.wad that the HUD uses.
+const LMP_PICS: [&str; 2] = ["gfx/ranking.lmp", "gfx/pause.lmp"];
+// Draw_FadeScreen's black, behind the menus.
+const FADE_ALPHA: u8 = 204;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HudStyle {
+    Classic,
+    Mini,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HudSettings {
+    pub scale: f32,
+    pub style: HudStyle,
+}
+
+impl HudSettings {
+    pub fn from_cvars(cvars: &CvarRegistry) -> Self {
+        Self {
+            scale: cvars.value("scr_sbarscale").max(1.0),
+            style: if cvars.value("scr_hudstyle") != 0.0 {
+                HudStyle::Mini
+            } else {
+                HudStyle::Classic
+            },
+        }
+    }
+}
+
+// Every qpic in gfx.wad, each conchars character, and the .lmp pics, by
+// name.
+#[derive(Debug, Default)]
+pub struct HudPics {
+    pics: Vec<UiPic>,
+    index: HashMap<String, usize>,
+    palette: Option<Palette>,
+}
+
+impl HudPics {
+    // A pic that's missing just isn't drawn.
+    pub fn load(fs: &QuakeFs, palette: &Palette) -> Self {
+        let mut pics = match fs.read("gfx.wad").ok().map(Wad::from_bytes) {
+            Some(Ok(wad)) => Self::from_wad(&wad, palette),
+            _ => Self {
+                palette: Some(palette.clone()),
+                ..Self::default()
+            },
+        };
+        for name in LMP_PICS.into_iter().chain(MENU_PICS).chain(CONSOLE_PICS) {
+            if let Ok(bytes) = fs.read(name)
+                && let Some(pic) = qpic(&bytes, palette)
+            {
+                pics.add(name, pic);
+            }
+        }
+        pics
+    }
+
+    pub fn from_wad(wad: &Wad, palette: &Palette) -> Self {
+        let mut pics = Self {
+            palette: Some(palette.clone()),
+            ..Self::default()
+        };
+        pics.add(
+            "fade",
+            UiPic {
+                width: 1,
+                height: 1,
+                rgba: vec![0, 0, 0, FADE_ALPHA],
+            },
+        );
+        for entry in wad.entries() {
+            let Ok(data) = wad.get_entry(entry) else {
+                continue;
+            };
+            if entry.name == "conchars" {
+                pics.add_conchars(data, palette);
+            } else if entry.typ == TYP_QPIC
+                && let Some(pic) = qpic(data, palette)
+            {
+                pics.add(&entry.name, pic);
+            }
+        }
+        pics
+    }
+
+    pub fn pics(&self) -> &[UiPic] {
+        &self.pics
+    }
+
+    pub fn palette(&self) -> Option<&Palette> {
+        self.palette.as_ref()
+    }
+
+    // Adds a pic, or swaps one already there in place.
+    pub fn set(&mut self, name: &str, pic: UiPic) {
+        match self.index.get(name) {
+            Some(&index) => self.pics[index] = pic,
+            None => self.add(name, pic),
+        }
+    }
+
+    fn add(&mut self, name: &str, pic: UiPic) {
+        self.index.insert(name.to_string(), self.pics.len());
+        self.pics.push(pic);
+    }
+
+    // conchars is a bare 128x128 sheet of 8x8 characters, with colour 0 see
+    // through.
+    fn add_conchars(&mut self, data: &[u8], palette: &Palette) {
+        if data.len() < 128 * 128 {
+            return;
+        }
+        for glyph in 0..256 {
+            let (col, row) = (glyph % 16, glyph / 16);
+            let mut indices = Vec::with_capacity(64);
+            for y in 0..8 {
+                let start = (row * 8 + y) * 128 + col * 8;
+                indices.extend_from_slice(&data[start..start + 8]);
+            }
+            let pic = UiPic {
+                width: 8,
+                height: 8,
+                rgba: palette.expand_indices(&indices, Some(0)),
+            };
+            self.add(&format!("char{glyph}"), pic);
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<(usize, &UiPic)> {
+        let index = *self.index.get(name)?;
+        Some((index, &self.pics[index]))
+    }
+}
+
+const TYP_QPIC: u8 = b'B';
+
+// A qpic: width and height, then a palette index per pixel, 255 clear.
+pub fn qpic_indices(data: &[u8]) -> Option<(u32, u32, &[u8])> {
+    let width = u32::from_le_bytes(data.get(0..4)?.try_into().ok()?);
+    let height = u32::from_le_bytes(data.get(4..8)?.try_into().ok()?);
+    let size = (width as usize).checked_mul(height as usize)?;
+    let pixels = data.get(8..8usize.checked_add(size)?)?;
+    Some((width, height, pixels))
+}
+
+fn qpic(data: &[u8], palette: &Palette) -> Option<UiPic> {
+    let (width, height, pixels) = qpic_indices(data)?;
+    Some(UiPic {
+        width,
+        height,
+        rgba: palette.expand_indices(pixels, Some(255)),
+    })
+}
+
+// Lays pics out in Quake's 320-wide coordinates and scales them onto the
+// screen.
+pub struct Canvas<'a> {
+    pics: &'a HudPics,
+    scale: f32,
+    pub origin: (i32, i32),
+    // The screen in those coordinates.
+    pub width: i32,
+    pub height: i32,
+    pub images: Vec<UiImage>,
+}
+
+impl<'a> Canvas<'a> {
+    // Never so big that 320 wide runs off the sides.
+    pub fn new(pics: &'a HudPics, scale: f32, screen: (u32, u32)) -> Self {
+        let scale = scale.min(screen.0 as f32 / SBAR_WIDTH as f32).max(1.0);
+        Self {
+            pics,
+            scale,
+            origin: (0, 0),
+            width: (screen.0 as f32 / scale) as i32,
+            height: (screen.1 as f32 / scale) as i32,
+            images: Vec::new(),
+        }
+    }
+
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    pub fn size_of(&self, name: &str) -> Option<(i32, i32)> {
+        let (_, info) = self.pics.get(name)?;
+        Some((info.width as i32, info.height as i32))
+    }
+
+    pub fn pic(&mut self, x: i32, y: i32, name: &str) {
+        if let Some((width, height)) = self.size_of(name) {
+            self.stretch(x, y, width, height, name);
+        }
+    }
+
+    pub fn stretch(&mut self, x: i32, y: i32, width: i32, height: i32, name: &str) {
+        let Some((pic, _)) = self.pics.get(name) else {
+            return;
+        };
+        let scale = self.scale;
+        self.images.push(UiImage {
+            pic,
+            x: ((self.origin.0 + x) as f32 * scale) as i32,
+            y: ((self.origin.1 + y) as f32 * scale) as i32,
+            width: (width as f32 * scale) as i32,
+            height: (height as f32 * scale) as i32,
+        });
+    }
+
+    // Draw_Character; spaces draw nothing.
+    pub fn character(&mut self, x: i32, y: i32, glyph: u8) {
+        if glyph & 127 != b' ' {
+            self.pic(x, y, &format!("char{glyph}"));
+        }
+    }
+
+    // Draw_String; `alt` sets the high bit for the brown letters the menus
+    // use.
+    pub fn text(&mut self, x: i32, y: i32, text: &str, alt: bool) {
+        let high = if alt { 128 } else { 0 };
+        for (i, byte) in text.bytes().enumerate() {
+            self.character(x + i as i32 * 8, y, byte | high);
+        }
+    }
+
+    // Sbar_DrawNum: right-aligned in `digits` places, keeping the low
+    // digits of anything wider.
+    fn num(&mut self, x: i32, y: i32, num: i32, digits: usize, red: bool) {
+        let text = num.to_string();
+        let text = &text[text.len().saturating_sub(digits)..];
+        let prefix = if red { "anum_" } else { "num_" };
+        let mut x = x + (digits - text.len()) as i32 * 24;
+        for ch in text.chars() {
+            let name = if ch == '-' {
+                format!("{prefix}minus")
+            } else {
+                format!("{prefix}{ch}")
+            };
+            self.pic(x, y, &name);
+            x += 24;
+        }
+    }
+
+    // Small yellow digits, as the inventory bar counts ammo.
+    fn small_num(&mut self, x: i32, y: i32, num: i32, digits: usize) {
+        let text = format!("{:>digits$}", num.clamp(0, 999));
+        for (i, ch) in text.bytes().enumerate() {
+            if ch.is_ascii_digit() {
+                let glyph = SMALL_DIGIT + (ch - b'0');
+                self.pic(x + i as i32 * 8, y, &format!("char{glyph}"));
+            }
+        }
+    }
+}
+
+// What the bar remembers between frames: when each item turned up, for
+// the pickup flash and powerup timers, and when the face last flinched.
+#[derive(Debug, Default)]
+pub struct Hud {
+    pics: Option<HudPics>,
+    pics_game_dir: Option<String>,
+    // Pics made on the fly, kept across a change of game directory.
+    generated: Vec<(String, UiPic)>,
+    item_gettime: [f64; 32],
+    last_items: u32,
+    last_health: i32,
+    face_anim_until: f64,
+}
+
+impl Hud {
+    // Loads the pics for a new game directory, returning them for the
+    // renderer when they change.
+    pub fn load_pics(
+        &mut self,
+        fs: &QuakeFs,
+        palette: Option<&Palette>,
+        game_dir: &str,
+    ) -> Option<Vec<UiPic>> {
+        let palette = palette?;
+        if fs.is_empty() || self.pics_game_dir.as_deref() == Some(game_dir) {
+            return None;
+        }
+        let mut pics = HudPics::load(fs, palette);
+        for (name, pic) in &self.generated {
+            pics.set(name, pic.clone());
+        }
+        let list = pics.pics().to_vec();
+        self.pics = Some(pics);
+        self.pics_game_dir = Some(game_dir.to_string());
+        Some(list)
+    }
+
+    fn update(&mut self, state: &ClientState, now: f64) {
+        let items = state.stats[STAT_ITEMS] as u32;
+        let gained = items & !self.last_items;
+        for (bit, time) in self.item_gettime.iter_mut().enumerate() {
+            if gained & (1 << bit) != 0 {
+                *time = now;
+            }
+        }
+        self.last_items = items;
+        let health = state.stats[STAT_HEALTH];
+        if health < self.last_health {
+            self.face_anim_until = now + 0.2;
+        }
+        self.last_health = health;
+    }
+
+    pub fn pics(&self) -> Option<&HudPics> {
+        self.pics.as_ref()
+    }
+
+    // Swaps in pics made on the fly, returning the whole set for the
+    // renderer.
+    pub fn set_pics(&mut self, pics: Vec<(String, UiPic)>) -> Option<Vec<UiPic>> {
+        let loaded = self.pics.as_mut()?;
+        for (name, pic) in pics {
+            loaded.set(&name, pic.clone());
+            match self.generated.iter_mut().find(|(kept, _)| *kept == name) {
+                Some(kept) => kept.1 = pic,
+                None => self.generated.push((name, pic)),
+            }
+        }
+        Some(loaded.pics().to_vec())
+    }
+
+    pub fn images(
+        &mut self,
+        state: &ClientState,
+        settings: &HudSettings,
+        screen: (u32, u32),
+        now: f64,
+        show_scores: bool,
+    ) -> Vec<UiImage> {
+        self.update(state, now);
+        let Some(pics) = &self.pics else {
+            return Vec::new();
+        };
+        let mut canvas = Canvas::new(pics, settings.scale, screen);
+        let (width, height) = (canvas.width, canvas.height);
+        if state.intermission.is_some() {
+            return canvas.images;
+        }
+        if show_scores {
+            canvas.pic((width - 160) / 2, 8, "gfx/ranking.lmp");
+        }
+        if state.paused {
+            canvas.pic((width - 128) / 2, (height - 24) / 2, "gfx/pause.lmp");
+        }
+        match settings.style {
+            HudStyle::Classic => {
+                canvas.origin = ((width - SBAR_WIDTH) / 2, height - SBAR_HEIGHT);
+                self.draw_inventory(&mut canvas, state, now);
+                if show_scores {
+                    canvas.pic(0, 0, "scorebar");
+                } else {
+                    self.draw_status(&mut canvas, state, now);
+                }
+            }
+            HudStyle::Mini => self.draw_mini(&mut canvas, state, now, width, height),
+        }
+        canvas.images
+    }
+
+    // Sbar_Draw's bottom row: armor, face, health and ammo.
+    fn draw_status(&self, canvas: &mut Canvas, state: &ClientState, now: f64) {
+        let items = state.stats[STAT_ITEMS] as u32;
+        let armor = state.stats[STAT_ARMOR];
+        let health = state.stats[STAT_HEALTH];
+        let ammo = state.stats[STAT_AMMO];
+        canvas.pic(0, 0, "sbar");
+        if items & IT_INVULNERABILITY != 0 {
+            canvas.num(24, 0, 666, 3, true);
+            canvas.pic(0, 0, "disc");
+        } else {
+            canvas.num(24, 0, armor, 3, armor <= 25);
+            if let Some(name) = armor_pic(items) {
+                canvas.pic(0, 0, name);
+            }
+        }
+        canvas.pic(112, 0, &self.face(state, now));
+        canvas.num(136, 0, health, 3, health <= 25);
+        if let Some(name) = ammo_pic(items) {
+            canvas.pic(224, 0, name);
+        }
+        canvas.num(248, 0, ammo, 3, ammo <= 10);
+    }
+
+    // Sbar_DrawInventory: owned weapons flash for a second after pickup,
+    // ammo counts, items, sigils, and how long each powerup has left.
+    fn draw_inventory(&self, canvas: &mut Canvas, state: &ClientState, now: f64) {
+        let items = state.stats[STAT_ITEMS] as u32;
+        canvas.pic(0, -24, "ibar");
+        for (i, weapon) in WEAPON_NAMES.iter().enumerate() {
+            if let Some(name) = self.weapon_pic(state, i, weapon, now) {
+                canvas.pic(i as i32 * 24, -16, &name);
+            }
+        }
+        for i in 0..4 {
+            let count = state.stats[STAT_SHELLS + i];
+            canvas.small_num((6 * i as i32 + 1) * 8 - 2 + 4, -24, count, 3);
+        }
+        for (i, name) in ITEM_PICS.iter().enumerate() {
+            let bit = 1 << (17 + i);
+            if items & bit != 0 {
+                canvas.pic(192 + i as i32 * 16, -16, name);
+                if let Some(left) = self.powerup_left(bit, now) {
+                    canvas.small_num(192 + i as i32 * 16, -24, left, 2);
+                }
+            }
+        }
+        for i in 0..4 {
+            if items & (1 << (28 + i)) != 0 {
+                canvas.pic(SBAR_WIDTH - 32 + i * 8, -16, &format!("sb_sigil{}", i + 1));
+            }
+        }
+    }
+
+    // Health and armor in the bottom left corner, ammo in the bottom right
+    // with the weapons above it, and items along the left over the armor.
+    fn draw_mini(
+        &self,
+        canvas: &mut Canvas,
+        state: &ClientState,
+        now: f64,
+        width: i32,
+        height: i32,
+    ) {
+        let items = state.stats[STAT_ITEMS] as u32;
+        let armor = state.stats[STAT_ARMOR];
+        let health = state.stats[STAT_HEALTH];
+        let ammo = state.stats[STAT_AMMO];
+        let bottom = height - SBAR_HEIGHT;
+        canvas.pic(0, bottom, &self.face(state, now));
+        canvas.num(24, bottom, health, 3, health <= 25);
+        if let Some(name) = armor_pic(items)
+            && armor > 0
+        {
+            canvas.pic(0, bottom - 24, name);
+            canvas.num(24, bottom - 24, armor, 3, armor <= 25);
+        }
+        if let Some(name) = ammo_pic(items) {
+            canvas.pic(width - 24, bottom, name);
+            canvas.num(width - 24 - 72, bottom, ammo, 3, ammo <= 10);
+        }
+        let mut y = bottom - 16;
+        for (i, weapon) in WEAPON_NAMES.iter().enumerate().rev() {
+            if let Some(name) = self.weapon_pic(state, i, weapon, now) {
+                canvas.pic(width - 24, y, &name);
+                y -= 16;
+            }
+        }
+        let mut x = 0;
+        for (i, name) in ITEM_PICS.iter().enumerate() {
+            let bit = 1 << (17 + i);
+            if items & bit != 0 {
+                canvas.pic(x, bottom - 64, name);
+                if let Some(left) = self.powerup_left(bit, now) {
+                    canvas.small_num(x, bottom - 72, left, 2);
+                }
+                x += 16;
+            }
+        }
+    }
+
+    fn weapon_pic(&self, state: &ClientState, i: usize, weapon: &str, now: f64) -> Option<String> {
+        let bit = IT_SHOTGUN << i;
+        if state.stats[STAT_ITEMS] as u32 & bit == 0 {
+            return None;
+        }
+        let flash = (((now - self.item_gettime[i]) * 10.0) as i32).max(0);
+        Some(if flash < 10 {
+            format!("inva{}_{}", flash % 5 + 1, weapon)
+        } else if state.stats[STAT_ACTIVEWEAPON] as u32 == bit {
+            format!("inv2_{}", weapon)
+        } else {
+            format!("inv_{}", weapon)
+        })
+    }
+
+    fn powerup_left(&self, bit: u32, now: f64) -> Option<i32> {
+        if !POWERUPS.contains(&bit) {
+            return None;
+        }
+        let left = POWERUP_TIME - (now - self.item_gettime[bit.trailing_zeros() as usize]);
+        (left > 0.0).then(|| left.ceil() as i32)
+    }
+
+    // Sbar_DrawFace: powerups first, then one of five faces by health,
+    // flinching for a moment after taking damage.
+    fn face(&self, state: &ClientState, now: f64) -> String {
+        let items = state.stats[STAT_ITEMS] as u32;
+        if items & (IT_INVISIBILITY | IT_INVULNERABILITY) == IT_INVISIBILITY | IT_INVULNERABILITY {
+            return "face_inv2".to_string();
+        }
+        if items & IT_QUAD != 0 {
+            return "face_quad".to_string();
+        }
+        if items & IT_INVISIBILITY != 0 {
+            return "face_invis".to_string();
+        }
+        if items & IT_INVULNERABILITY != 0 {
+            return "face_invul2".to_string();
+        }
+        let health = state.stats[STAT_HEALTH];
+        let f = if health >= 100 { 4 } else { health.max(0) / 20 };
+        if now <= self.face_anim_until {
+            format!("face_p{}", 5 - f)
+        } else {
+            format!("face{}", 5 - f)
+        }
+    }
+}
+
+fn armor_pic(items: u32) -> Option<&'static str> {
+    if items & IT_ARMOR3 != 0 {
+        Some("sb_armor3")
+    } else if items & IT_ARMOR2 != 0 {
+        Some("sb_armor2")
+    } else if items & IT_ARMOR1 != 0 {
+        Some("sb_armor1")
+    } else {
+        None
+    }
+}
+
+fn ammo_pic(items: u32) -> Option<&'static str> {
+    AMMO_ITEMS
+        .iter()
+        .position(|item| items & item != 0)
+        .map(|i| AMMO_PICS[i])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn palette() -> Palette {
+        let mut bytes = Vec::new();
+        for i in 0..=255u8 {
+            bytes.extend_from_slice(&[i, i, i]);
+        }
+        Palette::from_bytes(&bytes).unwrap()
+    }
+
+    fn qpic_bytes(width: u32, height: u32, index: u8) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&width.to_le_bytes());
+        data.extend_from_slice(&height.to_le_bytes());
+        data.extend(std::iter::repeat_n(index, (width * height) as usize));
+        data
+    }
+
+    fn build_wad(lumps: &[(&str, u8, Vec<u8>)]) -> Wad {
+        let mut data = b"WAD2".to_vec();
+        data.extend_from_slice(&(lumps.len() as u32).to_le_bytes());
+        data.extend_from_slice(&[0; 4]);
+        let mut directory = Vec::new();
+        for (name, typ, lump) in lumps {
+            let filepos = data.len() as u32;
+            data.extend_from_slice(lump);
+            directory.extend_from_slice(&filepos.to_le_bytes());
+            directory.extend_from_slice(&(lump.len() as u32).to_le_bytes());
+            directory.extend_from_slice(&(lump.len() as u32).to_le_bytes());
+            directory.extend_from_slice(&[*typ, 0, 0, 0]);
+            let mut padded = [0u8; 16];
+            padded[..name.len()].copy_from_slice(name.as_bytes());
+            directory.extend_from_slice(&padded);
+        }
+        let dir_offset = data.len() as u32;
+        data[8..12].copy_from_slice(&dir_offset.to_le_bytes());
+        data.extend_from_slice(&directory);
+        Wad::from_bytes(data).unwrap()
+    }
+
+    fn test_hud() -> Hud {
+        let mut lumps = vec![
+            ("sbar".to_string(), 320, 24),
+            ("ibar".to_string(), 320, 24),
+            ("scorebar".to_string(), 320, 24),
+            ("sb_armor2".to_string(), 24, 24),
+            ("sb_nails".to_string(), 24, 24),
+            ("sb_quad".to_string(), 16, 16),
+            ("inv_nailgun".to_string(), 24, 16),
+            ("inv2_nailgun".to_string(), 24, 16),
+            ("inva1_nailgun".to_string(), 24, 16),
+            ("face3".to_string(), 24, 24),
+            ("face_p3".to_string(), 24, 24),
+            ("face_quad".to_string(), 24, 24),
+        ];
+        for digit in 0..10 {
+            lumps.push((format!("num_{digit}"), 24, 24));
+            lumps.push((format!("anum_{digit}"), 24, 24));
+        }
+        let mut lumps: Vec<_> = lumps
+            .iter()
+            .map(|(name, width, height)| (name.as_str(), TYP_QPIC, qpic_bytes(*width, *height, 7)))
+            .collect();
+        lumps.push(("conchars", b'D', vec![0; 128 * 128]));
+        let wad = build_wad(&lumps);
+        Hud {
+            pics: Some(HudPics::from_wad(&wad, &palette())),
+            pics_game_dir: Some("id1".to_string()),
+            ..Hud::default()
+        }
+    }
+
+    fn named(hud: &Hud, images: &[UiImage]) -> Vec<(String, i32, i32)> {
+        let pics = hud.pics.as_ref().unwrap();
+        images
+            .iter()
+            .map(|image| {
+                let name = pics
+                    .index
+                    .iter()
+                    .find(|(_, index)| **index == image.pic)
+                    .map(|(name, _)| name.clone())
+                    .unwrap();
+                (name, image.x, image.y)
+            })
+            .collect()
+    }
+
+    const CLASSIC: HudSettings = HudSettings {
+        scale: 1.0,
+        style: HudStyle::Classic,
+    };
+
+    #[test]
+    fn loads_qpics_and_conchars_digits() {
+        let wad = build_wad(&[
+            ("face1", TYP_QPIC, qpic_bytes(2, 1, 255)),
+            ("conchars", b'D', vec![5; 128 * 128]),
+            ("palette", b'@', vec![0; 768]),
+        ]);
+        let pics = HudPics::from_wad(&wad, &palette());
+        let (_, face) = pics.get("face1").unwrap();
+        assert_eq!((face.width, face.height), (2, 1));
+        assert_eq!(face.rgba, vec![0; 8]);
+        let (_, digit) = pics.get("char18").unwrap();
+        assert_eq!(&digit.rgba[..4], &[5, 5, 5, 255]);
+        assert!(pics.get("palette").is_none());
+    }
+
+    #[test]
+    fn lays_out_the_classic_bar() {
+        let mut hud = test_hud();
+        let mut state = ClientState::new();
+        state.stats[STAT_HEALTH] = 57;
+        state.stats[STAT_ARMOR] = 20;
+        state.stats[STAT_AMMO] = 40;
+        state.stats[STAT_ITEMS] = (IT_ARMOR2 | IT_NAILS | (IT_SHOTGUN << 2)) as i32;
+        state.stats[STAT_ACTIVEWEAPON] = (IT_SHOTGUN << 2) as i32;
+
+        let images = hud.images(&state, &CLASSIC, (320, 200), 1.0, false);
+        let images = named(&hud, &images);
+        assert!(images.contains(&("ibar".to_string(), 0, 152)));
+        // A new weapon flashes, the bar sits at the bottom.
+        assert!(images.contains(&("inva1_nailgun".to_string(), 48, 160)));
+        assert!(images.contains(&("sbar".to_string(), 0, 176)));
+        assert!(images.contains(&("sb_armor2".to_string(), 0, 176)));
+        // Low armor is red, right-aligned in three places.
+        assert!(images.contains(&("anum_2".to_string(), 48, 176)));
+        assert!(images.contains(&("face3".to_string(), 112, 176)));
+        assert!(images.contains(&("num_5".to_string(), 160, 176)));
+        assert!(images.contains(&("sb_nails".to_string(), 224, 176)));
+
+        // Doubled, and flinching after losing health.
+        state.stats[STAT_HEALTH] = 50;
+        let doubled = HudSettings {
+            scale: 2.0,
+            ..CLASSIC
+        };
+        let images = hud.images(&state, &doubled, (640, 400), 3.0, false);
+        let images = named(&hud, &images);
+        assert!(images.contains(&("inv2_nailgun".to_string(), 96, 320)));
+        assert!(images.contains(&("face_p3".to_string(), 224, 352)));
+    }
+
+    #[test]
+    fn times_powerups() {
+        let mut hud = test_hud();
+        let mut state = ClientState::new();
+        state.stats[STAT_HEALTH] = 100;
+        state.stats[STAT_ITEMS] = IT_QUAD as i32;
+        hud.images(&state, &CLASSIC, (320, 200), 10.0, false);
+        assert_eq!(hud.powerup_left(IT_QUAD, 22.5), Some(18));
+        assert_eq!(hud.powerup_left(IT_QUAD, 41.0), None);
+        let images = hud.images(&state, &CLASSIC, (320, 200), 22.5, false);
+        let images = named(&hud, &images);
+        assert!(images.contains(&("face_quad".to_string(), 112, 176)));
+        assert!(images.contains(&("sb_quad".to_string(), 272, 160)));
+        assert!(images.contains(&("char19".to_string(), 272, 152)));
+        assert!(images.contains(&("char26".to_string(), 280, 152)));
+    }
+
+    #[test]
+    fn mini_hud_keeps_to_the_corners() {
+        let mut hud = test_hud();
+        let mut state = ClientState::new();
+        state.stats[STAT_HEALTH] = 57;
+        state.stats[STAT_AMMO] = 40;
+        state.stats[STAT_ITEMS] = IT_NAILS as i32;
+        let settings = HudSettings {
+            scale: 1.0,
+            style: HudStyle::Mini,
+        };
+        let images = hud.images(&state, &settings, (640, 480), 1.0, false);
+        let images = named(&hud, &images);
+        assert!(!images.iter().any(|(name, _, _)| name == "sbar"));
+        assert!(images.contains(&("face3".to_string(), 0, 456)));
+        assert!(images.contains(&("sb_nails".to_string(), 616, 456)));
+        assert!(images.contains(&("num_4".to_string(), 568, 456)));
+    }
+}
@@ -1,17 +1,19 @@
 use std::collections::HashMap;
 
-use qw_common::UserCmd;
+use qw_common::{CvarRegistry, UserCmd, Vec3};
 use qw_window_glfw::{Action, Key};
 
-#[derive(Debug, Clone)]
-pub struct InputBindings {
-    bindings: HashMap<Key, Bind>,
+// keys.c's key_dest: where key presses go.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyDest {
+    Game,
+    Console,
+    Menu,
 }
 
 #[derive(Debug, Clone)]
-enum Bind {
-    Toggle(String),
-    Command(String),
+pub struct InputBindings {
+    bindings: HashMap<Key, String>,
 }
 
 impl InputBindings {
@@ -21,38 +23,73 @@ impl InputBindings {
         }
     }
 
-    pub fn bind_toggle(&mut self, key: Key, base: impl Into<String>) {
-        self.bindings.insert(key, Bind::Toggle(base.into()));
+    pub fn bind(&mut self, key: Key, command: impl Into<String>) {
+        self.bindings.insert(key, command.into());
+    }
+
+    pub fn unbind(&mut self, key: Key) {
+        self.bindings.remove(&key);
+    }
+
+    pub fn unbind_all(&mut self) {
+        self.bindings.clear();
+    }
+
+    pub fn binding(&self, key: Key) -> Option<&str> {
+        self.bindings.get(&key).map(String::as_str)
     }
 
-    pub fn bind_command(&mut self, key: Key, command: impl Into<String>) {
-        self.bindings.insert(key, Bind::Command(command.into()));
+    // Bound keys in key number order, as Quake lists them.
+    pub fn iter(&self) -> impl Iterator<Item = (Key, &str)> {
+        let mut bound: Vec<(Key, &str)> = self
+            .bindings
+            .iter()
+            .map(|(key, command)| (*key, command.as_str()))
+            .collect();
+        bound.sort_by_key(|(key, _)| key.keynum());
+        bound.into_iter()
     }
 
+    // Key_Event: a press runs the binding, and a `+command` binding also
+    // gets its `-command` on release. Both carry the key number so the
+    // button knows which key let go. Autorepeat is ignored.
     pub fn command_for(&self, key: Key, action: Action) -> Option<String> {
         let binding = self.bindings.get(&key)?;
+        let keynum = key.keynum();
+        match action {
+            Action::Press if binding.starts_with('+') => Some(format!("{binding} {keynum}")),
+            Action::Press => Some(binding.clone()),
+            Action::Release => binding
+                .strip_prefix('+')
+                .map(|base| format!("-{base} {keynum}")),
+            Action::Repeat => None,
+        }
+    }
 
-        match (binding, action) {
-            (Bind::Toggle(base), Action::Press) => Some(format!("+{base}")),
-            (Bind::Toggle(base), Action::Release) => Some(format!("-{base}")),
-            (Bind::Command(command), Action::Press) => Some(command.clone()),
-            _ => None,
+    // Key_WriteBindings, after an unbindall so removed defaults stay gone.
+    pub fn archived_config(&self) -> String {
+        let mut text = String::from("unbindall\n");
+        for (key, command) in self.iter() {
+            if let Some(name) = key.name() {
+                text.push_str(&format!("bind {name} \"{command}\"\n"));
+            }
         }
+        text
     }
 }
 
 impl Default for InputBindings {
     fn default() -> Self {
         let mut bindings = Self::new();
-        bindings.bind_toggle(Key::Up, "forward");
-        bindings.bind_toggle(Key::Down, "back");
-        bindings.bind_toggle(Key::Left, "moveleft");
-        bindings.bind_toggle(Key::Right, "moveright");
-        bindings.bind_toggle(Key::Space, "jump");
-        bindings.bind_toggle(Key::Tab, "showscores");
-        bindings.bind_toggle(Key::Shift, "speed");
-        bindings.bind_toggle(Key::Mouse1, "attack");
-        bindings.bind_command(Key::Enter, "messagemode");
+        bindings.bind(Key::Up, "+forward");
+        bindings.bind(Key::Down, "+back");
+        bindings.bind(Key::Left, "+moveleft");
+        bindings.bind(Key::Right, "+moveright");
+        bindings.bind(Key::Space, "+jump");
+        bindings.bind(Key::Tab, "+showscores");
+        bindings.bind(Key::Shift, "+speed");
+        bindings.bind(Key::Mouse1, "+attack");
+        bindings.bind(Key::Enter, "messagemode");
         bindings
     }
 }
@@ -64,6 +101,48 @@ const UP_SPEED: i16 = 200;
 const SPEED_MULT: i32 = 2;
 const BUTTON_ATTACK: u8 = 1;
 const BUTTON_JUMP: u8 = 2;
+// v_centermove and v_centerspeed.
+const CENTER_MOVE: f32 = 0.15;
+const CENTER_SPEED: f32 = 500.0;
+
+// GLQuakeWorld's mouse cvars and defaults, and whether config.cfg keeps them.
+pub const MOUSE_CVARS: [(&str, &str, bool); 8] = [
+    ("sensitivity", "3", true),
+    ("m_pitch", "0.022", true),
+    ("m_yaw", "0.022", false),
+    ("m_forward", "1", false),
+    ("m_side", "0.8", false),
+    ("m_filter", "0", false),
+    ("lookspring", "0", true),
+    ("lookstrafe", "0", true),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MouseSettings {
+    pub sensitivity: f32,
+    pub pitch: f32,
+    pub yaw: f32,
+    pub forward: f32,
+    pub side: f32,
+    pub filter: bool,
+    pub lookspring: bool,
+    pub lookstrafe: bool,
+}
+
+impl MouseSettings {
+    pub fn from_cvars(cvars: &CvarRegistry) -> Self {
+        Self {
+            sensitivity: cvars.value("sensitivity"),
+            pitch: cvars.value("m_pitch"),
+            yaw: cvars.value("m_yaw"),
+            forward: cvars.value("m_forward"),
+            side: cvars.value("m_side"),
+            filter: cvars.value("m_filter") != 0.0,
+            lookspring: cvars.value("lookspring") != 0.0,
+            lookstrafe: cvars.value("lookstrafe") != 0.0,
+        }
+    }
+}
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CommandTarget {
@@ -71,44 +150,148 @@ pub enum CommandTarget {
     Server,
 }
 
+// kbutton_t: up to two keys can hold a button, and it stays down until
+// both let go. Key number -1 is the console, which no release matches.
+#[derive(Debug, Default, Clone, Copy)]
+struct KButton {
+    down: [i32; 2],
+    active: bool,
+}
+
+impl KButton {
+    fn key_down(&mut self, key: i32) {
+        if self.down.contains(&key) {
+            return;
+        }
+        if self.down[0] == 0 {
+            self.down[0] = key;
+        } else if self.down[1] == 0 {
+            self.down[1] = key;
+        } else {
+            println!("[client] three keys down for a button");
+            return;
+        }
+        self.active = true;
+    }
+
+    fn key_up(&mut self, key: Option<i32>) {
+        let Some(key) = key else {
+            // Typed at the console: unstick it whatever holds it.
+            *self = KButton::default();
+            return;
+        };
+        if self.down[0] == key {
+            self.down[0] = 0;
+        } else if self.down[1] == key {
+            self.down[1] = 0;
+        } else {
+            return;
+        }
+        if self.down == [0, 0] {
+            self.active = false;
+        }
+    }
+}
+
 #[derive(Debug, Default, Clone)]
 pub struct InputState {
-    forward: bool,
-    back: bool,
-    left: bool,
-    right: bool,
-    up: bool,
-    down: bool,
-    jump: bool,
-    attack: bool,
-    speed: bool,
+    forward: KButton,
+    back: KButton,
+    left: KButton,
+    right: KButton,
+    up: KButton,
+    down: KButton,
+    jump: KButton,
+    attack: KButton,
+    speed: KButton,
     impulse: Option<u8>,
-    showscores: bool,
+    showscores: KButton,
+    mlook: KButton,
+    strafe: KButton,
+    mouse: (f32, f32),
+    old_mouse: (f32, f32),
+    // Set by -mlook; lookspring decides whether it recenters.
+    mlook_released: bool,
+    drift: PitchDrift,
+}
+
+// View.c's pitch drift state. Drifting starts out allowed, so a view left
+// pitched with nothing holding it comes back level.
+#[derive(Debug, Default, Clone, Copy)]
+struct PitchDrift {
+    nodrift: bool,
+    pitchvel: f32,
+    driftmove: f32,
+}
+
+impl PitchDrift {
+    fn start(&mut self) {
+        if self.nodrift || self.pitchvel == 0.0 {
+            self.pitchvel = CENTER_SPEED;
+            self.nodrift = false;
+            self.driftmove = 0.0;
+        }
+    }
+
+    fn stop(&mut self) {
+        self.nodrift = true;
+        self.pitchvel = 0.0;
+    }
+
+    // V_DriftPitch, toward level since QuakeWorld has no ideal pitch.
+    fn update(&mut self, pitch: &mut f32, forwardmove: i16, lookspring: bool, frametime: f32) {
+        if self.nodrift {
+            if f32::from(forwardmove).abs() < f32::from(FORWARD_SPEED) {
+                self.driftmove = 0.0;
+            } else {
+                self.driftmove += frametime;
+            }
+            if self.driftmove > CENTER_MOVE && lookspring {
+                self.start();
+            }
+            return;
+        }
+        let delta = -*pitch;
+        if delta == 0.0 {
+            self.pitchvel = 0.0;
+            return;
+        }
+        let mut step = frametime * self.pitchvel;
+        self.pitchvel += frametime * CENTER_SPEED;
+        if step > delta.abs() {
+            self.pitchvel = 0.0;
+            step = delta.abs();
+        }
+        *pitch += step.copysign(delta);
+    }
 }
 
 impl InputState {
     pub fn apply_command(&mut self, command: &str) -> CommandTarget {
-        let trimmed = command.trim();
-        if trimmed.is_empty() {
+        let mut words = command.split_whitespace();
+        let Some(name) = words.next() else {
             return CommandTarget::Local;
-        }
+        };
+        let key = words.next().map(atoi);
 
-        if let Some(rest) = trimmed.strip_prefix('+') {
-            return self.handle_toggle(rest, true);
+        if let Some(rest) = name.strip_prefix('+') {
+            return self.handle_toggle(rest, |button| button.key_down(key.unwrap_or(-1)));
         }
-        if let Some(rest) = trimmed.strip_prefix('-') {
-            return self.handle_toggle(rest, false);
+        if let Some(rest) = name.strip_prefix('-') {
+            let target = self.handle_toggle(rest, |button| button.key_up(key));
+            if rest == "mlook" && !self.mlook.active {
+                self.mlook_released = true;
+            }
+            return target;
         }
 
-        if let Some(rest) = trimmed.strip_prefix("impulse") {
-            let value = rest.trim();
-            if let Ok(parsed) = value.parse::<u8>() {
-                self.impulse = Some(parsed);
-            }
+        // Like Q_atoi, junk is impulse 0 rather than an error.
+        if name == "impulse" {
+            self.impulse = Some(key.unwrap_or(0) as u8);
             return CommandTarget::Local;
         }
 
-        match trimmed {
+        match name {
             "messagemode" => CommandTarget::Local,
             _ => CommandTarget::Server,
         }
@@ -116,31 +299,31 @@ impl InputState {
 
     pub fn build_usercmd(&mut self) -> UserCmd {
         let mut cmd = UserCmd::default();
-        if self.forward {
+        if self.forward.active {
             cmd.forwardmove = cmd.forwardmove.saturating_add(FORWARD_SPEED);
         }
-        if self.back {
+        if self.back.active {
             cmd.forwardmove = cmd.forwardmove.saturating_sub(BACK_SPEED);
         }
-        if self.right {
+        if self.right.active {
             cmd.sidemove = cmd.sidemove.saturating_add(SIDE_SPEED);
         }
-        if self.left {
+        if self.left.active {
             cmd.sidemove = cmd.sidemove.saturating_sub(SIDE_SPEED);
         }
-        if self.up {
+        if self.up.active {
             cmd.upmove = cmd.upmove.saturating_add(UP_SPEED);
         }
-        if self.down {
+        if self.down.active {
             cmd.upmove = cmd.upmove.saturating_sub(UP_SPEED);
         }
-        if self.jump {
+        if self.jump.active {
             cmd.buttons |= BUTTON_JUMP;
         }
-        if self.attack {
+        if self.attack.active {
             cmd.buttons |= BUTTON_ATTACK;
         }
-        if self.speed {
+        if self.speed.active {
             scale_move(&mut cmd, SPEED_MULT);
         }
         if let Some(impulse) = self.impulse.take() {
@@ -150,27 +333,108 @@ impl InputState {
     }
 
     pub fn showscores(&self) -> bool {
-        self.showscores
-    }
-
-    fn handle_toggle(&mut self, base: &str, pressed: bool) -> CommandTarget {
-        match base {
-            "forward" => self.forward = pressed,
-            "back" => self.back = pressed,
-            "moveleft" => self.left = pressed,
-            "moveright" => self.right = pressed,
-            "moveup" => self.up = pressed,
-            "movedown" => self.down = pressed,
-            "jump" => self.jump = pressed,
-            "attack" => self.attack = pressed,
-            "speed" => self.speed = pressed,
-            "showscores" => self.showscores = pressed,
-            _ => return CommandTarget::Server,
+        self.showscores.active
+    }
+
+    pub fn add_mouse_motion(&mut self, dx: f32, dy: f32) {
+        self.mouse.0 += dx;
+        self.mouse.1 += dy;
+    }
+
+    // IN_MouseMove and the pitch handling around it: the motion since the
+    // last command turns the view, or with +strafe or without +mlook moves
+    // the player instead. `on_ground` gates lookspring's recentering.
+    pub fn apply_mouse(
+        &mut self,
+        cmd: &mut UserCmd,
+        view_angles: &mut Vec3,
+        settings: &MouseSettings,
+        on_ground: bool,
+        frametime: f32,
+    ) {
+        clamp_angles(view_angles);
+        let (mx, my) = std::mem::take(&mut self.mouse);
+        let (mut mouse_x, mut mouse_y) = if settings.filter {
+            ((mx + self.old_mouse.0) * 0.5, (my + self.old_mouse.1) * 0.5)
+        } else {
+            (mx, my)
+        };
+        self.old_mouse = (mx, my);
+        mouse_x *= settings.sensitivity;
+        mouse_y *= settings.sensitivity;
+
+        let mlook = self.mlook.active;
+        let strafe = self.strafe.active;
+        if strafe || (settings.lookstrafe && mlook) {
+            cmd.sidemove = (f32::from(cmd.sidemove) + settings.side * mouse_x) as i16;
+        } else {
+            view_angles.y -= settings.yaw * mouse_x;
+        }
+        if mlook {
+            self.drift.stop();
         }
+        if mlook && !strafe {
+            view_angles.x += settings.pitch * mouse_y;
+            clamp_angles(view_angles);
+        } else {
+            cmd.forwardmove = (f32::from(cmd.forwardmove) - settings.forward * mouse_y) as i16;
+        }
+
+        if std::mem::take(&mut self.mlook_released) && settings.lookspring {
+            self.drift.start();
+        }
+        if on_ground {
+            self.drift.update(
+                &mut view_angles.x,
+                cmd.forwardmove,
+                settings.lookspring,
+                frametime,
+            );
+        } else {
+            self.drift.driftmove = 0.0;
+            self.drift.pitchvel = 0.0;
+        }
+    }
+
+    fn handle_toggle(&mut self, base: &str, apply: impl FnOnce(&mut KButton)) -> CommandTarget {
+        let button = match base {
+            "forward" => &mut self.forward,
+            "back" => &mut self.back,
+            "moveleft" => &mut self.left,
+            "moveright" => &mut self.right,
+            "moveup" => &mut self.up,
+            "movedown" => &mut self.down,
+            "jump" => &mut self.jump,
+            "attack" => &mut self.attack,
+            "speed" => &mut self.speed,
+            "showscores" => &mut self.showscores,
+            "mlook" => &mut self.mlook,
+            "strafe" => &mut self.strafe,
+            _ => return CommandTarget::Server,
+        };
+        apply(button);
         CommandTarget::Local
     }
 }
 
+// CL_AdjustAngles' limits: 80 down, 70 up and 50 of roll either way.
+fn clamp_angles(angles: &mut Vec3) {
+    angles.x = angles.x.clamp(-70.0, 80.0);
+    angles.z = angles.z.clamp(-50.0, 50.0);
+}
+
+// Leading digits with an optional sign, anything else 0.
+fn atoi(text: &str) -> i32 {
+    let (sign, digits) = match text.strip_prefix('-') {
+        Some(rest) => (-1, rest),
+        None => (1, text),
+    };
+    let end = digits
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(digits.len());
+    digits[..end].parse::<i32>().map_or(0, |value| sign * value)
+}
+
 fn scale_move(cmd: &mut UserCmd, scale: i32) {
     cmd.forwardmove =
         ((cmd.forwardmove as i32) * scale).clamp(i16::MIN as i32, i16::MAX as i32) as i16;
@@ -186,14 +450,14 @@ mod tests {
     fn maps_press_to_plus_command() {
         let bindings = InputBindings::default();
         let cmd = bindings.command_for(Key::Up, Action::Press).unwrap();
-        assert_eq!(cmd, "+forward");
+        assert_eq!(cmd, "+forward 128");
     }
 
     #[test]
     fn maps_release_to_minus_command() {
         let bindings = InputBindings::default();
         let cmd = bindings.command_for(Key::Space, Action::Release).unwrap();
-        assert_eq!(cmd, "-jump");
+        assert_eq!(cmd, "-jump 32");
     }
 
     #[test]
@@ -205,7 +469,7 @@ mod tests {
     #[test]
     fn command_bind_only_fires_on_press() {
         let mut bindings = InputBindings::new();
-        bindings.bind_command(Key::Enter, "impulse 10");
+        bindings.bind(Key::Enter, "impulse 10");
         assert_eq!(
             bindings.command_for(Key::Enter, Action::Press),
             Some("impulse 10".to_string())
@@ -222,6 +486,51 @@ mod tests {
         assert_eq!(state.build_usercmd().forwardmove, 0);
     }
 
+    #[test]
+    fn button_stays_down_until_both_keys_release() {
+        let mut bindings = InputBindings::new();
+        bindings.bind(Key::Up, "+forward");
+        bindings.bind(Key::Char(b'w'), "+forward");
+        let mut state = InputState::default();
+        for (key, action) in [
+            (Key::Up, Action::Press),
+            (Key::Char(b'w'), Action::Press),
+            (Key::Up, Action::Release),
+        ] {
+            state.apply_command(&bindings.command_for(key, action).unwrap());
+        }
+        assert_eq!(state.build_usercmd().forwardmove, FORWARD_SPEED);
+        // A release from a key that never pressed it changes nothing.
+        state.apply_command("-forward 97");
+        assert_eq!(state.build_usercmd().forwardmove, FORWARD_SPEED);
+        state.apply_command(
+            &bindings
+                .command_for(Key::Char(b'w'), Action::Release)
+                .unwrap(),
+        );
+        assert_eq!(state.build_usercmd().forwardmove, 0);
+
+        // Typed at the console it sticks until an unkeyed release.
+        state.apply_command("+forward");
+        state.apply_command("-forward 128");
+        assert_eq!(state.build_usercmd().forwardmove, FORWARD_SPEED);
+        state.apply_command("-forward");
+        assert_eq!(state.build_usercmd().forwardmove, 0);
+    }
+
+    #[test]
+    fn writes_bindings_for_config() {
+        let mut bindings = InputBindings::new();
+        bindings.bind(Key::Char(b'w'), "+forward");
+        bindings.bind(Key::Char(b';'), "impulse 7");
+        bindings.bind(Key::Mouse1, "+attack");
+        bindings.bind(Key::Other(3), "impulse 1");
+        assert_eq!(
+            bindings.archived_config(),
+            "unbindall\nbind SEMICOLON \"impulse 7\"\nbind w \"+forward\"\nbind MOUSE1 \"+attack\"\n"
+        );
+    }
+
     #[test]
     fn jump_sets_button_bit() {
         let mut state = InputState::default();
@@ -244,6 +553,9 @@ mod tests {
         assert_eq!(state.apply_command("impulse 5"), CommandTarget::Local);
         let cmd = state.build_usercmd();
         assert_eq!(cmd.impulse, 5);
+        assert_eq!(state.build_usercmd().impulse, 0);
+        state.apply_command("impulse 12abc");
+        assert_eq!(state.build_usercmd().impulse, 12);
     }
 
     #[test]
@@ -256,6 +568,97 @@ mod tests {
         assert!(!state.showscores());
     }
 
+    fn mouse_settings() -> MouseSettings {
+        let mut cvars = CvarRegistry::new();
+        for (name, value, _) in MOUSE_CVARS {
+            cvars.register(qw_common::Cvar::new(name, value));
+        }
+        MouseSettings::from_cvars(&cvars)
+    }
+
+    #[test]
+    fn mouse_turns_and_moves_without_mlook() {
+        let settings = mouse_settings();
+        let mut state = InputState::default();
+        let mut angles = Vec3::default();
+        state.add_mouse_motion(50.0, 0.0);
+        state.add_mouse_motion(50.0, 10.0);
+        let mut cmd = state.build_usercmd();
+        state.apply_mouse(&mut cmd, &mut angles, &settings, true, 0.05);
+        assert!((angles.y + 100.0 * 3.0 * 0.022).abs() < 1e-4);
+        assert_eq!(angles.x, 0.0);
+        assert_eq!(cmd.forwardmove, -30);
+
+        state.apply_command("+strafe");
+        state.add_mouse_motion(10.0, 0.0);
+        let mut cmd = state.build_usercmd();
+        state.apply_mouse(&mut cmd, &mut angles, &settings, true, 0.05);
+        assert_eq!(cmd.sidemove, 24);
+    }
+
+    #[test]
+    fn mlook_pitches_and_clamps() {
+        let settings = mouse_settings();
+        let mut state = InputState::default();
+        let mut angles = Vec3::default();
+        state.apply_command("+mlook");
+        state.add_mouse_motion(0.0, 10000.0);
+        let mut cmd = state.build_usercmd();
+        state.apply_mouse(&mut cmd, &mut angles, &settings, true, 0.05);
+        assert_eq!(angles.x, 80.0);
+        assert_eq!(cmd.forwardmove, 0);
+        state.add_mouse_motion(0.0, -20000.0);
+        state.apply_mouse(&mut cmd, &mut angles, &settings, true, 0.05);
+        assert_eq!(angles.x, -70.0);
+
+        // Without lookspring the view stays where mlook left it.
+        state.apply_command("-mlook");
+        state.apply_mouse(&mut cmd, &mut angles, &settings, true, 0.05);
+        assert_eq!(angles.x, -70.0);
+    }
+
+    #[test]
+    fn lookspring_recenters_after_mlook() {
+        let settings = MouseSettings {
+            lookspring: true,
+            ..mouse_settings()
+        };
+        let mut state = InputState::default();
+        let mut angles = Vec3::new(30.0, 0.0, 0.0);
+        state.apply_command("+mlook");
+        let mut cmd = state.build_usercmd();
+        state.apply_mouse(&mut cmd, &mut angles, &settings, true, 0.05);
+        assert_eq!(angles.x, 30.0);
+        state.apply_command("-mlook");
+        // In the air nothing drifts.
+        state.apply_mouse(&mut cmd, &mut angles, &settings, false, 0.05);
+        assert_eq!(angles.x, 30.0);
+        state.apply_command("+mlook");
+        state.apply_command("-mlook");
+        for _ in 0..20 {
+            state.apply_mouse(&mut cmd, &mut angles, &settings, true, 0.05);
+        }
+        assert_eq!(angles.x, 0.0);
+    }
+
+    #[test]
+    fn filter_averages_with_the_last_motion() {
+        let settings = MouseSettings {
+            filter: true,
+            sensitivity: 1.0,
+            yaw: 1.0,
+            ..mouse_settings()
+        };
+        let mut state = InputState::default();
+        let mut angles = Vec3::default();
+        let mut cmd = UserCmd::default();
+        state.add_mouse_motion(10.0, 0.0);
+        state.apply_mouse(&mut cmd, &mut angles, &settings, true, 0.05);
+        assert_eq!(angles.y, -5.0);
+        state.apply_mouse(&mut cmd, &mut angles, &settings, true, 0.05);
+        assert_eq!(angles.y, -10.0);
+    }
+
     #[test]
     fn forwards_unknown_commands() {
         let mut state = InputState::default();
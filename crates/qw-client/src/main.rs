@@ -1,8 +1,15 @@
+mod browser;
 mod cli;
 mod client;
+mod commands;
 mod config;
+mod console;
+mod console_view;
+mod demo;
 mod handshake;
+mod hud;
 mod input;
+mod menu;
 mod model_cache;
 mod net;
 mod prediction;
@@ -12,30 +19,38 @@ mod sound;
 mod state;
 
 use std::collections::VecDeque;
+use std::path::Path;
 use std::sync::mpsc;
 use std::time::{Duration, Instant};
 
 use crate::cli::{CliAction, ClientMode, DEFAULT_QPORT};
+use crate::commands::{ClientApp, register_client_commands};
 use crate::config::ClientConfig;
-use crate::input::{CommandTarget, InputBindings, InputState};
+use crate::console::Console;
+use crate::demo::{DemoError, DemoPlayer};
+use crate::hud::{Hud, HudSettings};
+use crate::input::{KeyDest, MouseSettings};
+use crate::menu::MenuContext;
 use crate::model_cache::{ModelAsset, ModelKind};
 use crate::net::NetClient;
 use crate::runner::{ClientRunner, RunnerError};
-use crate::session::{Session, SessionState};
+use crate::session::{Protocol, Session, SessionState};
 use crate::sound::SoundManager;
-use crate::state::ClientState;
+use crate::state::{ClientState, PredictionSettings};
 use qw_audio::{AudioConfig, AudioSystem};
 use qw_common::{
-    InfoError, InfoString, STAT_AMMO, STAT_ARMOR, STAT_HEALTH, UPDATE_MASK, value_for_key,
+    CvarRegistry, InfoString, NetSimConfig, Palette, STAT_AMMO, STAT_ARMOR, STAT_HEALTH, Transport,
+    UPDATE_MASK, loopback_pair, value_for_key,
 };
 use qw_renderer::{
     RenderBeam, RenderDynamicLight, RenderEntity, RenderEntityKind, RenderModel, RenderModelKind,
-    RenderModelTexture, RenderParticle, RenderView, RenderWorld, Renderer, RendererConfig, UiLayer,
-    UiText,
+    RenderModelTexture, RenderParticle, RenderView, RenderWorld, Renderer, RendererConfig, UiImage,
+    UiLayer, UiText,
 };
 #[cfg(feature = "glow")]
 use qw_renderer_gl::GlDevice;
 use qw_renderer_gl::GlRenderer;
+use qw_server::GameSettings;
 use qw_window_glfw::{Action, GlfwWindow, Key, WindowConfig, WindowEvent};
 
 const MOVE_INTERVAL_MS: u64 = 50;
@@ -85,11 +100,85 @@ fn run() -> Result<(), AppError> {
         userinfo.set("rate", rate)?;
     }
 
-    if args.mode == ClientMode::SinglePlayer {
-        return Err(AppError::ModeNotImplemented("singleplayer"));
+    // Singleplayer runs a listen server in this process and talks to it over
+    // a loopback pipe instead of a socket.
+    let mut local_server = None;
+    let net = if args.mode == ClientMode::SinglePlayer {
+        let (client_end, server_end) = loopback_pair();
+        let mut settings = GameSettings::default();
+        if let Some(skill) = args.skill {
+            settings.skill = f32::from(skill);
+        }
+        local_server = Some(qw_server::spawn_local(&args.map, settings, server_end)?);
+        NetClient::loopback(client_end).boxed()
+    } else if args.mode == ClientMode::Demo {
+        // Playback reads the file instead; nothing goes over this pipe.
+        let (client_end, _) = loopback_pair();
+        NetClient::loopback(client_end).boxed()
+    } else {
+        let server = args.server.ok_or(cli::CliError::MissingServer)?;
+        NetClient::connect(std::net::SocketAddr::from(server.to_socket_addr()))?.boxed()
+    };
+    let mut console = Console::new();
+    register_client_commands(&mut console, &userinfo);
+    NetSimConfig::register_cvars(&mut console.cvars);
+    for (name, value) in &args.netsim {
+        console.cvars.set(name, value);
+    }
+    let netsim = NetSimConfig::from_cvars(&console.cvars);
+    let net = if netsim.is_active() {
+        println!("[client] simulating {netsim}");
+        net.simulated(netsim).boxed()
+    } else {
+        net
+    };
+
+    let qport = if args.qport == 0 {
+        DEFAULT_QPORT
+    } else {
+        args.qport
+    };
+    // The in-process server only speaks QuakeWorld.
+    let protocol = if args.mode == ClientMode::SinglePlayer {
+        Protocol::QuakeWorld
+    } else {
+        args.protocol
+    };
+    let session = Session::new(qport, userinfo.as_str().to_string()).with_protocol(protocol);
+    let mut app = ClientApp::new(ClientRunner::new(net, session), userinfo);
+    app.local_server = local_server;
+
+    // quake.rc's order: saved settings, then the user's own script. Flags
+    // from the command line win over both.
+    console.append("exec config.cfg\nexec autoexec.cfg");
+    console.execute(&mut app);
+    for (key, value) in [
+        ("name", &args.name),
+        ("topcolor", &args.topcolor),
+        ("bottomcolor", &args.bottomcolor),
+        ("rate", &args.rate),
+    ] {
+        if let Some(value) = value {
+            console.set_cvar(&mut app, key, value);
+        }
+    }
+    for line in console.drain_output() {
+        println!("{line}");
+        app.console_view.print(&line, 0.0);
+    }
+
+    if let Some(path) = args.demo.as_deref() {
+        app.runner
+            .play_demo(DemoPlayer::open(Path::new(path), args.timedemo)?)?;
+        if args.headless {
+            let settings = PredictionSettings::from_cvars(&console.cvars);
+            let hud_settings = HudSettings::from_cvars(&console.cvars);
+            return run_headless_demo(&mut app.runner, args.timedemo, &settings, &hud_settings);
+        }
+    } else {
+        app.runner.start_connect()?;
     }
 
-    let server = args.server.ok_or(cli::CliError::MissingServer)?;
     let mut window = GlfwWindow::new(WindowConfig::default());
     let (width, height) = window.size();
     let mut renderer = GlRenderer::new(RendererConfig {
@@ -105,26 +194,21 @@ fn run() -> Result<(), AppError> {
     let mut audio = AudioSystem::new(AudioConfig::default());
     let mut sound_manager = SoundManager::new();
 
-    let server_addr = std::net::SocketAddr::from(server.to_socket_addr());
-    let net = NetClient::connect(server_addr)?;
-    let qport = if args.qport == 0 {
-        DEFAULT_QPORT
-    } else {
-        args.qport
-    };
-    let session = Session::new(qport, userinfo.as_str().to_string());
-    let mut runner = ClientRunner::new(net, session);
-    runner.start_connect()?;
-
     let mut last_move = Instant::now();
     let mut last_title: Option<String> = None;
     let mut last_world: Option<String> = None;
     let mut last_model_count: usize = 0;
+    let mut hud = Hud::default();
+    // The menus work before any server has named a game directory.
+    if let Ok(bytes) = app.fs.read("gfx/palette.lmp")
+        && let Ok(palette) = Palette::from_bytes(&bytes)
+        && let Some(pics) = hud.load_pics(&app.fs, Some(&palette), "")
+    {
+        renderer.set_pics(pics);
+    }
     let mut buf = [0u8; 8192];
     let mut was_connected = false;
-    let mut pending_server_cmds: VecDeque<String> = VecDeque::new();
-    let input_bindings = InputBindings::default();
-    let mut input_state = InputState::default();
+    let mut bound_cmds: VecDeque<String> = VecDeque::new();
     let (tx, rx) = mpsc::channel::<String>();
     std::thread::spawn(move || {
         let stdin = std::io::stdin();
@@ -144,24 +228,76 @@ fn run() -> Result<(), AppError> {
         }
     });
     loop {
+        let mut closed = false;
         for event in window.poll_events() {
-            if handle_window_event(
+            closed |= handle_window_event(
                 &mut window,
                 &mut renderer,
-                &input_bindings,
-                &mut input_state,
+                &mut app,
+                &console,
                 event,
-                &mut pending_server_cmds,
-            ) {
-                return Ok(());
-            }
+                &mut bound_cmds,
+            );
+        }
+        if closed {
+            break;
+        }
+        for cmd in bound_cmds.drain(..) {
+            console.append(&cmd);
+        }
+        while let Ok(line) = rx.try_recv() {
+            console.append(&line);
+        }
+        console.execute(&mut app);
+        for line in app.poll_browser() {
+            console.print(line);
+        }
+        let now = app.runner.time_seconds();
+        for line in console.drain_output() {
+            println!("{line}");
+            app.console_view.print(&line, now);
         }
+        if app.quit {
+            break;
+        }
+        // The mouse looks around in game; a demo has nothing for it to do.
+        let captured = !app.runner.is_playing_demo() && app.key_dest() == KeyDest::Game;
+        if window.cursor_captured() != captured {
+            window.set_cursor_captured(captured);
+        }
+        apply_frame_cvars(&console.cvars, &mut window, &mut renderer, &mut audio);
 
-        if let Some(packet) = runner.poll_once(&mut buf)? {
+        let polled = match app.runner.poll_once(&mut buf) {
+            Err(RunnerError::ConnectTimeout) => {
+                drop_to_console(&mut app, "no answer from the server", now)?;
+                None
+            }
+            Err(RunnerError::ServerTimeout) => {
+                drop_to_console(&mut app, "server connection timed out", now)?;
+                was_connected = false;
+                None
+            }
+            Err(RunnerError::BadDownload(name)) => {
+                let reason = format!("{name} arrived damaged and was removed");
+                drop_to_console(&mut app, &reason, now)?;
+                was_connected = false;
+                None
+            }
+            Err(RunnerError::MapMismatch(name)) => {
+                let reason = format!("{name} doesn't match the server's map and was removed");
+                drop_to_console(&mut app, &reason, now)?;
+                was_connected = false;
+                None
+            }
+            polled => polled?,
+        };
+        let runner = &mut app.runner;
+        if let Some(packet) = polled {
             match packet {
                 crate::client::ClientPacket::Messages(_) => {
                     for (level, message) in runner.state.prints.drain(..) {
                         println!("[{level}] {message}");
+                        app.console_view.print(&message, now);
                     }
                     for message in runner.state.center_prints.drain(..) {
                         println!("[center] {message}");
@@ -171,6 +307,7 @@ fn run() -> Result<(), AppError> {
                     qw_common::OobMessage::Print(text) => {
                         if !text.is_empty() {
                             println!("[oob] {text}");
+                            app.console_view.print(&text, now);
                         }
                     }
                     qw_common::OobMessage::ClientCommand(text) => {
@@ -180,6 +317,11 @@ fn run() -> Result<(), AppError> {
                     }
                     _ => {}
                 },
+                crate::client::ClientPacket::Control(control) => {
+                    if let qw_common::NqControl::Reject(reason) = control {
+                        println!("[client] connection rejected: {reason}");
+                    }
+                }
             }
         }
         update_render_world(&mut renderer, &runner.state, &mut last_world);
@@ -198,86 +340,294 @@ fn run() -> Result<(), AppError> {
             [right.x, right.y, right.z],
         );
         sound_manager.handle_events(&mut audio, &mut runner.state, &runner.client.fs);
-        update_window_title(&mut window, &runner.state.serverinfo, &mut last_title);
+        update_window_title(
+            &mut window,
+            &runner.state.serverinfo,
+            runner.download_progress(),
+            &mut last_title,
+        );
 
         if runner.session.state == SessionState::Connected {
             was_connected = true;
-            while let Some(cmd) = pending_server_cmds.pop_front() {
+            while let Some(cmd) = app.pending_server_cmds.pop_front() {
                 runner.send_string_cmd(&cmd)?;
             }
             let elapsed = last_move.elapsed();
             if elapsed >= Duration::from_millis(MOVE_INTERVAL_MS) {
-                let mut cmd = input_state.build_usercmd();
+                let mut cmd = app.input.build_usercmd();
                 let msec = elapsed.as_millis().min(u128::from(u8::MAX)) as u8;
                 cmd.msec = msec;
+                let on_ground = runner
+                    .state
+                    .on_ground(runner.client.netchan.incoming_sequence());
+                app.input.apply_mouse(
+                    &mut cmd,
+                    &mut runner.state.view_angles,
+                    &MouseSettings::from_cvars(&console.cvars),
+                    on_ground,
+                    elapsed.as_secs_f32(),
+                );
                 cmd.angles = runner.state.view_angles;
                 runner.send_move(cmd)?;
                 last_move = Instant::now();
             }
         } else if runner.session.state == SessionState::Disconnected && was_connected {
-            break;
-        }
-
-        while let Ok(cmd) = rx.try_recv() {
-            if cmd.eq_ignore_ascii_case("quit") || cmd.eq_ignore_ascii_case("exit") {
-                return Ok(());
+            // A demo started from the command line ends the program with it.
+            if args.demo.is_some() {
+                break;
             }
-            match maybe_userinfo_command(&cmd, &mut userinfo) {
-                Ok(Some(mapped)) => {
-                    if runner.session.state == SessionState::Connected {
-                        runner.send_string_cmd(&mapped)?;
-                    } else {
-                        pending_server_cmds.push_back(mapped);
-                    }
-                }
-                Ok(None) => {
-                    if runner.session.state == SessionState::Connected {
-                        runner.send_string_cmd(&cmd)?;
-                    } else {
-                        pending_server_cmds.push_back(cmd);
-                    }
-                }
-                Err(err) => {
-                    println!("[client] invalid userinfo: {err}");
-                }
+            if let Some(report) = app.runner.timedemo_report() {
+                let report = report.to_string();
+                println!("[client] {report}");
+                app.console_view.print(&report, now);
             }
+            drop_to_console(&mut app, "disconnected", now)?;
+            was_connected = false;
         }
 
         if audio.is_running() {
-            renderer.set_view(build_render_view(&runner.state));
-            let incoming = runner.client.netchan.incoming_sequence();
-            let render_time = runner.time_seconds() - runner.state.latency;
-            renderer.set_entities(build_render_entities(&runner.state, incoming, render_time));
-            let entity_origins = build_entity_origin_map(&runner.state, incoming, render_time);
-            renderer.set_particles(build_render_particles(&runner.state));
-            renderer.set_beams(build_render_beams(&runner.state));
-            renderer.set_dynamic_lights(build_dynamic_lights(&runner.state, &entity_origins));
-            let fog = runner
-                .state
-                .client_data
-                .as_ref()
-                .and_then(|data| data.inwater.then_some([0.08, 0.12, 0.2, 0.04]));
-            renderer.set_fog(fog);
-            renderer.set_ui(build_ui_layer(&runner.state, input_state.showscores()));
-            renderer.update_lightmaps(&runner.state.lightstyles, runner.state.server_time);
-            renderer.begin_frame();
-            renderer.end_frame();
+            let settings = PredictionSettings::from_cvars(&console.cvars);
+            let hud_settings = HudSettings::from_cvars(&console.cvars);
+            let overlay = draw_overlay(&mut app, &console.cvars, &mut hud, &mut renderer);
+            draw_frame(
+                &mut renderer,
+                &app.runner,
+                &mut hud,
+                app.input.showscores(),
+                &settings,
+                &hud_settings,
+                overlay,
+            );
             window.swap_buffers();
         }
 
-        std::thread::sleep(Duration::from_millis(1));
+        // timedemo runs flat out.
+        if !app.runner.is_timedemo() {
+            std::thread::sleep(Duration::from_millis(1));
+        }
     }
 
+    if let Some(report) = app.runner.timedemo_report() {
+        println!("[client] {report}");
+    }
+    if let Err(err) = app.write_config(&console) {
+        println!("[client] couldn't write config.cfg: {err}");
+    }
     Ok(())
 }
 
+// Demo playback with no window or audio; the renderer still builds every
+// frame so timedemo measures the same work, minus presenting it.
+fn run_headless_demo<T: Transport>(
+    runner: &mut ClientRunner<T>,
+    timedemo: bool,
+    settings: &PredictionSettings,
+    hud_settings: &HudSettings,
+) -> Result<(), AppError> {
+    let mut renderer = GlRenderer::new(RendererConfig::default());
+    let mut hud = Hud::default();
+    let mut last_world: Option<String> = None;
+    let mut last_model_count: usize = 0;
+    let mut buf = [0u8; 8192];
+    while runner.session.state != SessionState::Disconnected {
+        if let Some(crate::client::ClientPacket::Messages(_)) = runner.poll_once(&mut buf)? {
+            for (level, message) in runner.state.prints.drain(..) {
+                println!("[{level}] {message}");
+            }
+            runner.state.center_prints.clear();
+        }
+        update_render_world(&mut renderer, &runner.state, &mut last_world);
+        let model_assets = runner.model_assets();
+        if model_assets.len() != last_model_count {
+            renderer.set_models(build_render_models(model_assets));
+            last_model_count = model_assets.len();
+        }
+        draw_frame(
+            &mut renderer,
+            runner,
+            &mut hud,
+            false,
+            settings,
+            hud_settings,
+            Vec::new(),
+        );
+        if !timedemo {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+    if let Some(report) = runner.timedemo_report() {
+        println!("[client] {report}");
+    }
+    Ok(())
+}
+
+fn draw_frame<T: Transport>(
+    renderer: &mut GlRenderer,
+    runner: &ClientRunner<T>,
+    hud: &mut Hud,
+    showscores: bool,
+    settings: &PredictionSettings,
+    hud_settings: &HudSettings,
+    overlay: Vec<UiImage>,
+) {
+    renderer.set_view(build_render_view(&runner.state));
+    let incoming = runner.incoming_sequence();
+    let render_time = runner.time_seconds() - runner.state.latency;
+    renderer.set_entities(build_render_entities(
+        &runner.state,
+        incoming,
+        render_time,
+        settings,
+    ));
+    let entity_origins = build_entity_origin_map(&runner.state, incoming, render_time, settings);
+    renderer.set_particles(build_render_particles(&runner.state));
+    renderer.set_beams(build_render_beams(&runner.state));
+    renderer.set_dynamic_lights(build_dynamic_lights(&runner.state, &entity_origins));
+    let fog = runner
+        .state
+        .client_data
+        .as_ref()
+        .and_then(|data| data.inwater.then_some([0.08, 0.12, 0.2, 0.04]));
+    renderer.set_fog(fog);
+    if let Some(data) = runner.state.serverdata.as_ref()
+        && let Some(pics) = hud.load_pics(
+            &runner.client.fs,
+            runner.state.palette.as_ref(),
+            &data.game_dir,
+        )
+    {
+        renderer.set_pics(pics);
+    }
+    let config = renderer.config();
+    let screen = (config.width, config.height);
+    let mut images = hud.images(
+        &runner.state,
+        hud_settings,
+        screen,
+        runner.time_seconds(),
+        showscores,
+    );
+    images.extend(overlay);
+    renderer.set_ui(build_ui_layer(&runner.state, showscores, images));
+    renderer.update_lightmaps(&runner.state.lightstyles, runner.state.server_time);
+    renderer.begin_frame();
+    renderer.end_frame();
+}
+
+// CL_Disconnect: says why, lets the server go and leaves the console up to
+// connect somewhere else.
+fn drop_to_console<T: Transport>(
+    app: &mut ClientApp<T>,
+    reason: &str,
+    time: f64,
+) -> Result<(), RunnerError> {
+    println!("[client] {reason}");
+    app.console_view.print(reason, time);
+    app.runner.disconnect()?;
+    app.console_view.open();
+    Ok(())
+}
+
+// The console or its notify lines, with any open menu over them. Player
+// setup's preview is brought up to date first.
+fn draw_overlay<T: Transport>(
+    app: &mut ClientApp<T>,
+    cvars: &CvarRegistry,
+    hud: &mut Hud,
+    renderer: &mut GlRenderer,
+) -> Vec<UiImage> {
+    if app.menu.is_open() {
+        let fs = if app.runner.client.fs.is_empty() {
+            &app.fs
+        } else {
+            &app.runner.client.fs
+        };
+        let preview = hud
+            .pics()
+            .and_then(|pics| pics.palette())
+            .and_then(|palette| app.menu.player_preview(fs, palette));
+        if let Some(preview) = preview
+            && let Some(pics) = hud.set_pics(preview)
+        {
+            renderer.set_pics(pics);
+        }
+    }
+    let Some(pics) = hud.pics() else {
+        return Vec::new();
+    };
+    let config = renderer.config();
+    let screen = (config.width, config.height);
+    let scale = HudSettings::from_cvars(cvars).scale;
+    let mut images = app.console_view.draw(
+        pics,
+        scale,
+        screen,
+        app.runner.time_seconds(),
+        f64::from(cvars.value("con_notifytime")),
+    );
+    if app.menu.is_open() {
+        let mut menu = std::mem::take(&mut app.menu);
+        images.extend(menu.draw(pics, scale, screen, &menu_context(app, cvars)));
+        app.menu = menu;
+    }
+    images
+}
+
+fn menu_context<'a, T: Transport>(
+    app: &'a ClientApp<T>,
+    cvars: &'a CvarRegistry,
+) -> MenuContext<'a> {
+    let servers = app
+        .browser
+        .as_ref()
+        .map(|browser| {
+            browser
+                .servers(&app.server_filter, app.server_sort)
+                .into_iter()
+                .map(|server| {
+                    let line = format!(
+                        "{:4} {:2} {:8.8} {}",
+                        server.ping.as_millis(),
+                        server.players.len(),
+                        server.map(),
+                        server.hostname()
+                    );
+                    (server.addr, line)
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    MenuContext {
+        cvars,
+        bindings: &app.bindings,
+        servers,
+        time: app.runner.time_seconds(),
+    }
+}
+
+// Quake's gamma darkens as it rises; the renderer's brightens.
+fn apply_frame_cvars(
+    cvars: &CvarRegistry,
+    window: &mut GlfwWindow,
+    renderer: &mut GlRenderer,
+    audio: &mut AudioSystem,
+) {
+    renderer.set_gamma(1.0 / cvars.value("gamma").clamp(0.5, 1.0));
+    audio.set_volume(cvars.value("volume"));
+    let vsync = cvars.value("vid_vsync") != 0.0;
+    if window.config().vsync != vsync {
+        window.set_vsync(vsync);
+    }
+}
+
 #[derive(Debug)]
 enum AppError {
     Cli(cli::CliError),
     Runner(RunnerError),
     Info(qw_common::InfoError),
     Io(std::io::Error),
-    ModeNotImplemented(&'static str),
+    Server(qw_server::ServerError),
+    Demo(DemoError),
 }
 
 impl std::fmt::Display for AppError {
@@ -287,7 +637,8 @@ impl std::fmt::Display for AppError {
             AppError::Runner(err) => write!(f, "{:?}", err),
             AppError::Info(err) => write!(f, "{}", err),
             AppError::Io(err) => write!(f, "{}", err),
-            AppError::ModeNotImplemented(mode) => write!(f, "{} mode not implemented", mode),
+            AppError::Server(err) => write!(f, "local server: {err}"),
+            AppError::Demo(err) => write!(f, "{err}"),
         }
     }
 }
@@ -310,59 +661,29 @@ impl From<qw_common::InfoError> for AppError {
     }
 }
 
-impl From<std::io::Error> for AppError {
-    fn from(err: std::io::Error) -> Self {
-        AppError::Io(err)
+impl From<DemoError> for AppError {
+    fn from(err: DemoError) -> Self {
+        AppError::Demo(err)
     }
 }
 
-fn maybe_userinfo_command(
-    input: &str,
-    userinfo: &mut InfoString,
-) -> Result<Option<String>, InfoError> {
-    let mut parts = input.splitn(2, ' ');
-    let command = parts.next().unwrap_or("").trim();
-    let rest = parts.next().unwrap_or("").trim();
-    if rest.is_empty() {
-        return Ok(None);
-    }
-
-    let (key, value) = match command {
-        "name" | "skin" | "team" | "topcolor" | "bottomcolor" | "rate" | "msg" => (command, rest),
-        "setinfo" => {
-            let mut inner = rest.splitn(2, ' ');
-            let key = inner.next().unwrap_or("").trim();
-            let value = inner.next().unwrap_or("").trim();
-            if key.is_empty() || value.is_empty() {
-                return Ok(None);
-            }
-            if key.starts_with('*') {
-                userinfo.set_star(key, value)?;
-            } else {
-                userinfo.set(key, value)?;
-            }
-            return Ok(Some(format!("setinfo {} {}", key, quote_if_needed(value))));
-        }
-        _ => return Ok(None),
-    };
-
-    userinfo.set(key, value)?;
-    Ok(Some(format!("setinfo {} {}", key, quote_if_needed(value))))
+impl From<qw_server::ServerError> for AppError {
+    fn from(err: qw_server::ServerError) -> Self {
+        AppError::Server(err)
+    }
 }
 
-fn quote_if_needed(value: &str) -> String {
-    if value.contains(' ') {
-        format!("\"{}\"", value)
-    } else {
-        value.to_string()
+impl From<std::io::Error> for AppError {
+    fn from(err: std::io::Error) -> Self {
+        AppError::Io(err)
     }
 }
 
-fn handle_window_event(
+fn handle_window_event<T: Transport>(
     window: &mut GlfwWindow,
     renderer: &mut GlRenderer,
-    input_bindings: &InputBindings,
-    input_state: &mut InputState,
+    app: &mut ClientApp<T>,
+    console: &Console<ClientApp<T>>,
     event: WindowEvent,
     pending_cmds: &mut VecDeque<String>,
 ) -> bool {
@@ -375,32 +696,89 @@ fn handle_window_event(
             renderer.resize(width, height);
             false
         }
+        WindowEvent::MouseMotion { dx, dy } => {
+            app.input.add_mouse_motion(dx, dy);
+            false
+        }
+        WindowEvent::CursorMoved { x, y } => {
+            if app.key_dest() == KeyDest::Menu {
+                let mut menu = std::mem::take(&mut app.menu);
+                let ctx = menu_context(app, &console.cvars);
+                menu.mouse_moved(x, y, &ctx);
+                app.menu = menu;
+            }
+            false
+        }
         WindowEvent::Key { key, action } => {
-            if let Some(cmd) = input_bindings.command_for(key, action)
-                && input_state.apply_command(&cmd) == CommandTarget::Server
-            {
-                pending_cmds.push_back(cmd);
+            handle_key(app, console, key, action, pending_cmds);
+            false
+        }
+        WindowEvent::Char(ch) => {
+            if app.key_dest() == KeyDest::Console {
+                app.console_view.char(ch);
             }
-            if key == Key::Escape && action == Action::Press {
-                window.close();
-                true
-            } else {
-                false
+            false
+        }
+    }
+}
+
+// Key_Event: Escape and the console key are wired in, everything else goes
+// where key_dest says. Releases always reach the bindings so nothing stays
+// held down behind a menu.
+fn handle_key<T: Transport>(
+    app: &mut ClientApp<T>,
+    console: &Console<ClientApp<T>>,
+    key: Key,
+    action: Action,
+    pending_cmds: &mut VecDeque<String>,
+) {
+    if action == Action::Release {
+        pending_cmds.extend(app.bindings.command_for(key, action));
+        return;
+    }
+    if key == Key::Char(b'`') && !app.menu.grabbing() {
+        if action == Action::Press {
+            pending_cmds.push_back("toggleconsole".to_string());
+        }
+        return;
+    }
+    match app.key_dest() {
+        KeyDest::Menu => {
+            let mut menu = std::mem::take(&mut app.menu);
+            let ctx = menu_context(app, &console.cvars);
+            let cmds = menu.key(key, &ctx);
+            app.menu = menu;
+            pending_cmds.extend(cmds);
+        }
+        _ if key == Key::Escape => {
+            if action == Action::Press {
+                pending_cmds.push_back("togglemenu".to_string());
             }
         }
+        KeyDest::Console => pending_cmds.extend(app.console_view.key(key, console)),
+        KeyDest::Game => pending_cmds.extend(app.bindings.command_for(key, action)),
     }
 }
 
+// The server's name, and while a download runs, how far along it is.
 fn update_window_title(
     window: &mut GlfwWindow,
     serverinfo: &InfoString,
+    download: Option<(&str, u8)>,
     last_title: &mut Option<String>,
 ) {
-    if let Some(hostname) = qw_common::value_for_key(serverinfo.as_str(), "hostname")
-        && last_title.as_deref() != Some(hostname.as_str())
-    {
-        window.set_title(format!("RustQuake - {hostname}"));
-        *last_title = Some(hostname);
+    let Some(hostname) = qw_common::value_for_key(serverinfo.as_str(), "hostname") else {
+        return;
+    };
+    let title = match download {
+        Some((name, percent)) => {
+            format!("RustQuake - {hostname} - downloading {name} ({percent}%)")
+        }
+        None => format!("RustQuake - {hostname}"),
+    };
+    if last_title.as_deref() != Some(title.as_str()) {
+        window.set_title(title.clone());
+        *last_title = Some(title);
     }
 }
 
@@ -449,12 +827,18 @@ fn build_render_entities(
     state: &ClientState,
     incoming_sequence: u32,
     render_time: f64,
+    settings: &PredictionSettings,
 ) -> Vec<RenderEntity> {
     let frame_index = (incoming_sequence as usize) & UPDATE_MASK;
     let prev_index = (incoming_sequence.wrapping_sub(1) as usize) & UPDATE_MASK;
     let frame = &state.frames[frame_index];
     let prev_frame = &state.frames[prev_index];
-    let frac = interpolation_fraction(render_time, prev_frame.receivedtime, frame.receivedtime);
+    let frac = interpolation_fraction(
+        render_time,
+        prev_frame.receivedtime,
+        frame.receivedtime,
+        settings,
+    );
     let mut entities = Vec::new();
 
     let mut prev_lookup = std::collections::HashMap::new();
@@ -469,6 +853,12 @@ fn build_render_entities(
         push_render_entity(&mut entities, &state.models, ent, prev, frac);
     }
 
+    for (_, player) in
+        state.predicted_players(incoming_sequence, player_time(state, render_time), settings)
+    {
+        push_player_entity(&mut entities, &state.models, &player);
+    }
+
     for ent in &state.static_entities {
         push_render_entity(&mut entities, &state.models, ent, None, 1.0);
     }
@@ -476,16 +866,28 @@ fn build_render_entities(
     entities
 }
 
+// CL_LinkPlayers draws other players a little ahead of the snapshots, but
+// never past the present.
+fn player_time(state: &ClientState, render_time: f64) -> f64 {
+    (render_time + 0.02).min(render_time + state.latency)
+}
+
 fn build_entity_origin_map(
     state: &ClientState,
     incoming_sequence: u32,
     render_time: f64,
+    settings: &PredictionSettings,
 ) -> std::collections::HashMap<u16, qw_common::Vec3> {
     let frame_index = (incoming_sequence as usize) & UPDATE_MASK;
     let prev_index = (incoming_sequence.wrapping_sub(1) as usize) & UPDATE_MASK;
     let frame = &state.frames[frame_index];
     let prev_frame = &state.frames[prev_index];
-    let frac = interpolation_fraction(render_time, prev_frame.receivedtime, frame.receivedtime);
+    let frac = interpolation_fraction(
+        render_time,
+        prev_frame.receivedtime,
+        frame.receivedtime,
+        settings,
+    );
     let mut origins = std::collections::HashMap::new();
 
     let mut prev_lookup = std::collections::HashMap::new();
@@ -506,6 +908,13 @@ fn build_entity_origin_map(
         }
     }
 
+    // Players are entities 1 to MAX_CLIENTS.
+    for (index, player) in
+        state.predicted_players(incoming_sequence, player_time(state, render_time), settings)
+    {
+        origins.insert(index as u16 + 1, player.origin);
+    }
+
     for ent in &state.static_entities {
         if ent.number >= 0 && ent.number <= u16::MAX as i32 {
             origins.entry(ent.number as u16).or_insert(ent.origin);
@@ -725,8 +1134,9 @@ fn push_render_entity(
         Some(previous) => lerp_vec3(previous.origin, ent.origin, frac),
         None => ent.origin,
     };
+    // Angles aren't carried on past the last snapshot.
     let angles = match prev {
-        Some(previous) => lerp_vec3(previous.angles, ent.angles, frac),
+        Some(previous) => lerp_vec3(previous.angles, ent.angles, frac.min(1.0)),
         None => ent.angles,
     };
 
@@ -741,6 +1151,27 @@ fn push_render_entity(
     });
 }
 
+// The model pitches a third as far as the view, as in CL_LinkPlayers.
+fn push_player_entity(
+    entities: &mut Vec<RenderEntity>,
+    models: &[String],
+    player: &qw_common::PlayerState,
+) {
+    let model_index = player.modelindex as usize;
+    if models.get(model_index).is_none() {
+        return;
+    }
+    entities.push(RenderEntity {
+        kind: RenderEntityKind::Alias,
+        model_index,
+        origin: player.origin,
+        angles: qw_common::Vec3::new(-player.viewangles.x / 3.0, player.viewangles.y, 0.0),
+        frame: player.frame.max(0) as u32,
+        skin: player.skinnum.max(0) as u32,
+        alpha: 1.0,
+    });
+}
+
 fn model_kind_from_name(name: &str) -> RenderEntityKind {
     if name.starts_with('*') {
         RenderEntityKind::Brush
@@ -789,7 +1220,9 @@ fn build_render_models(models: &[Option<ModelAsset>]) -> Vec<Option<RenderModel>
         .collect()
 }
 
-fn build_ui_layer(state: &ClientState, show_scores: bool) -> UiLayer {
+// The status bar is pics when gfx.wad loaded, and the old text lines when
+// it didn't.
+fn build_ui_layer(state: &ClientState, show_scores: bool, images: Vec<UiImage>) -> UiLayer {
     let mut texts = Vec::new();
     if state.render_world.is_none() {
         texts.push(UiText {
@@ -800,27 +1233,9 @@ fn build_ui_layer(state: &ClientState, show_scores: bool) -> UiLayer {
         });
     }
 
-    let health = state.stats[STAT_HEALTH];
-    let armor = state.stats[STAT_ARMOR];
-    let ammo = state.stats[STAT_AMMO];
-    texts.push(UiText {
-        text: format!("Health: {health}"),
-        x: 10,
-        y: 30,
-        color: [255, 80, 80, 255],
-    });
-    texts.push(UiText {
-        text: format!("Armor: {armor}"),
-        x: 10,
-        y: 44,
-        color: [80, 160, 255, 255],
-    });
-    texts.push(UiText {
-        text: format!("Ammo: {ammo}"),
-        x: 10,
-        y: 58,
-        color: [255, 255, 160, 255],
-    });
+    if images.is_empty() {
+        push_status_text(state, &mut texts);
+    }
 
     if show_scores {
         let mut entries = Vec::new();
@@ -845,14 +1260,48 @@ fn build_ui_layer(state: &ClientState, show_scores: bool) -> UiLayer {
         }
     }
 
-    UiLayer { texts }
+    UiLayer { images, texts }
 }
 
-fn interpolation_fraction(now: f64, previous: f64, current: f64) -> f32 {
-    if previous < 0.0 || current <= previous {
+fn push_status_text(state: &ClientState, texts: &mut Vec<UiText>) {
+    let health = state.stats[STAT_HEALTH];
+    let armor = state.stats[STAT_ARMOR];
+    let ammo = state.stats[STAT_AMMO];
+    texts.push(UiText {
+        text: format!("Health: {health}"),
+        x: 10,
+        y: 30,
+        color: [255, 80, 80, 255],
+    });
+    texts.push(UiText {
+        text: format!("Armor: {armor}"),
+        x: 10,
+        y: 44,
+        color: [80, 160, 255, 255],
+    });
+    texts.push(UiText {
+        text: format!("Ammo: {ammo}"),
+        x: 10,
+        y: 58,
+        color: [255, 255, 160, 255],
+    });
+}
+
+// With cl_nolerp everything sits where the last snapshot put it. Otherwise
+// entities move between the last two, and when the next is late carry on
+// for at most cl_lerp_time past the last.
+fn interpolation_fraction(
+    now: f64,
+    previous: f64,
+    current: f64,
+    settings: &PredictionSettings,
+) -> f32 {
+    if settings.nolerp || previous < 0.0 || current <= previous {
         return 1.0;
     }
-    let frac = ((now - previous) / (current - previous)).clamp(0.0, 1.0);
+    let interval = current - previous;
+    let limit = 1.0 + settings.lerp_time / interval;
+    let frac = ((now - previous) / interval).clamp(0.0, limit);
     frac as f32
 }
 
@@ -883,48 +1332,32 @@ fn angle_vectors(angles: qw_common::Vec3) -> (qw_common::Vec3, qw_common::Vec3,
 #[cfg(test)]
 mod tests {
     use super::*;
-
-    #[test]
-    fn maps_name_command_to_setinfo() {
-        let mut info = InfoString::new(128);
-        let cmd = maybe_userinfo_command("name player", &mut info)
-            .unwrap()
-            .expect("expected setinfo");
-        assert_eq!(cmd, "setinfo name player");
-        assert!(info.as_str().contains("\\name\\player"));
-    }
-
-    #[test]
-    fn quotes_values_with_spaces() {
-        let mut info = InfoString::new(128);
-        let cmd = maybe_userinfo_command("name player one", &mut info)
-            .unwrap()
-            .expect("expected setinfo");
-        assert_eq!(cmd, "setinfo name \"player one\"");
-    }
-
-    #[test]
-    fn preserves_star_keys_for_setinfo() {
-        let mut info = InfoString::new(128);
-        let cmd = maybe_userinfo_command("setinfo *ver rq28", &mut info)
-            .unwrap()
-            .expect("expected setinfo");
-        assert_eq!(cmd, "setinfo *ver rq28");
-        assert!(info.as_str().contains("\\*ver\\rq28"));
+    use qw_common::LoopbackSocket;
+
+    fn test_app() -> (
+        Console<ClientApp<LoopbackSocket>>,
+        ClientApp<LoopbackSocket>,
+    ) {
+        let (client_end, _) = loopback_pair();
+        let userinfo = ClientConfig::default().userinfo;
+        let session = Session::new(27001, userinfo.as_str());
+        let runner = ClientRunner::new(NetClient::loopback(client_end), session);
+        let mut console = Console::new();
+        register_client_commands(&mut console, &userinfo);
+        (console, ClientApp::new(runner, userinfo))
     }
 
     #[test]
     fn window_close_event_triggers_exit() {
         let mut window = GlfwWindow::new(WindowConfig::default());
         let mut renderer = GlRenderer::new(RendererConfig::default());
-        let bindings = InputBindings::default();
-        let mut input_state = InputState::default();
+        let (console, mut app) = test_app();
         let mut pending = VecDeque::new();
         let exit = handle_window_event(
             &mut window,
             &mut renderer,
-            &bindings,
-            &mut input_state,
+            &mut app,
+            &console,
             WindowEvent::CloseRequested,
             &mut pending,
         );
@@ -936,15 +1369,14 @@ mod tests {
     fn key_event_forwards_server_command() {
         let mut window = GlfwWindow::new(WindowConfig::default());
         let mut renderer = GlRenderer::new(RendererConfig::default());
-        let mut bindings = InputBindings::new();
-        bindings.bind_command(Key::Enter, "say hello");
-        let mut input_state = InputState::default();
+        let (console, mut app) = test_app();
+        app.bindings.bind(Key::Enter, "say hello");
         let mut pending = VecDeque::new();
         let exit = handle_window_event(
             &mut window,
             &mut renderer,
-            &bindings,
-            &mut input_state,
+            &mut app,
+            &console,
             WindowEvent::Key {
                 key: Key::Enter,
                 action: Action::Press,
@@ -955,15 +1387,145 @@ mod tests {
         assert_eq!(pending.pop_front(), Some("say hello".to_string()));
     }
 
+    #[test]
+    fn mouse_motion_accumulates_for_the_next_move() {
+        let mut window = GlfwWindow::new(WindowConfig::default());
+        let mut renderer = GlRenderer::new(RendererConfig::default());
+        let (console, mut app) = test_app();
+        let mut pending = VecDeque::new();
+        for _ in 0..2 {
+            handle_window_event(
+                &mut window,
+                &mut renderer,
+                &mut app,
+                &console,
+                WindowEvent::MouseMotion { dx: 5.0, dy: 0.0 },
+                &mut pending,
+            );
+        }
+        let settings = MouseSettings {
+            sensitivity: 1.0,
+            yaw: 1.0,
+            filter: false,
+            ..MouseSettings::from_cvars(&qw_common::CvarRegistry::new())
+        };
+        let mut angles = qw_common::Vec3::default();
+        let mut cmd = app.input.build_usercmd();
+        app.input
+            .apply_mouse(&mut cmd, &mut angles, &settings, false, 0.05);
+        assert_eq!(angles.y, -10.0);
+        assert!(pending.is_empty());
+    }
+
+    #[test]
+    fn escape_toggles_the_menu_and_it_takes_the_keys() {
+        let mut window = GlfwWindow::new(WindowConfig::default());
+        let mut renderer = GlRenderer::new(RendererConfig::default());
+        let (mut console, mut app) = test_app();
+        app.bindings.bind(Key::Enter, "say hello");
+        let mut pending = VecDeque::new();
+        let press = |key| WindowEvent::Key {
+            key,
+            action: Action::Press,
+        };
+        let exit = handle_window_event(
+            &mut window,
+            &mut renderer,
+            &mut app,
+            &console,
+            press(Key::Escape),
+            &mut pending,
+        );
+        assert!(!exit);
+        assert_eq!(pending.pop_front().as_deref(), Some("togglemenu"));
+        console.append("togglemenu");
+        console.execute(&mut app);
+        assert_eq!(app.key_dest(), KeyDest::Menu);
+
+        // Enter picks the menu item instead of running the binding.
+        handle_window_event(
+            &mut window,
+            &mut renderer,
+            &mut app,
+            &console,
+            press(Key::Enter),
+            &mut pending,
+        );
+        assert!(!pending.contains(&"say hello".to_string()));
+        assert_eq!(app.menu.screen(), Some(menu::MenuScreen::SinglePlayer));
+
+        handle_window_event(
+            &mut window,
+            &mut renderer,
+            &mut app,
+            &console,
+            press(Key::Char(b'`')),
+            &mut pending,
+        );
+        assert_eq!(pending.pop_back().as_deref(), Some("toggleconsole"));
+    }
+
+    #[test]
+    fn the_console_takes_typed_text_and_runs_it() {
+        let mut window = GlfwWindow::new(WindowConfig::default());
+        let mut renderer = GlRenderer::new(RendererConfig::default());
+        let (mut console, mut app) = test_app();
+        app.bindings.bind(Key::Char(b'q'), "+attack");
+        console.append("toggleconsole");
+        console.execute(&mut app);
+        assert_eq!(app.key_dest(), KeyDest::Console);
+        let mut pending = VecDeque::new();
+        let mut events: Vec<WindowEvent> = "echo q".chars().map(WindowEvent::Char).collect();
+        events.push(WindowEvent::Key {
+            key: Key::Char(b'q'),
+            action: Action::Press,
+        });
+        events.push(WindowEvent::Key {
+            key: Key::Enter,
+            action: Action::Press,
+        });
+        for event in events {
+            handle_window_event(
+                &mut window,
+                &mut renderer,
+                &mut app,
+                &console,
+                event,
+                &mut pending,
+            );
+        }
+        assert_eq!(pending, ["echo q"]);
+    }
+
+    #[test]
+    fn a_lost_server_leaves_the_console_up() {
+        let (_console, mut app) = test_app();
+        app.runner.session.state = SessionState::ChallengeSent;
+        drop_to_console(&mut app, "no answer from the server", 1.0).unwrap();
+        assert_eq!(app.runner.session.state, SessionState::Disconnected);
+        assert_eq!(app.key_dest(), KeyDest::Console);
+        assert_eq!(app.console_view.text(), "no answer from the server\n");
+    }
+
     #[test]
     fn updates_window_title_from_hostname() {
         let mut window = GlfwWindow::new(WindowConfig::default());
         let mut info = InfoString::new(128);
         info.set("hostname", "Unit").unwrap();
         let mut last_title = None;
-        update_window_title(&mut window, &info, &mut last_title);
+        update_window_title(&mut window, &info, None, &mut last_title);
         assert_eq!(window.config().title, "RustQuake - Unit");
-        assert_eq!(last_title.as_deref(), Some("Unit"));
+        assert_eq!(last_title.as_deref(), Some("RustQuake - Unit"));
+        update_window_title(
+            &mut window,
+            &info,
+            Some(("maps/dm7.bsp", 40)),
+            &mut last_title,
+        );
+        assert_eq!(
+            window.config().title,
+            "RustQuake - Unit - downloading maps/dm7.bsp (40%)"
+        );
     }
 
     #[test]
@@ -1031,6 +1593,23 @@ mod tests {
         assert!(renderer.world().is_some());
     }
 
+    #[test]
+    fn limits_entity_extrapolation() {
+        let mut settings = PredictionSettings {
+            predict_players: true,
+            nolerp: false,
+            lerp_time: 0.05,
+        };
+        assert_eq!(interpolation_fraction(1.05, 1.0, 1.1, &settings), 0.5);
+        // Half an interval late, only cl_lerp_time of it is carried on.
+        assert_eq!(interpolation_fraction(1.15, 1.0, 1.1, &settings), 1.5);
+        assert_eq!(interpolation_fraction(2.0, 1.0, 1.1, &settings), 1.5);
+        settings.lerp_time = 0.0;
+        assert_eq!(interpolation_fraction(2.0, 1.0, 1.1, &settings), 1.0);
+        settings.nolerp = true;
+        assert_eq!(interpolation_fraction(1.05, 1.0, 1.1, &settings), 1.0);
+    }
+
     #[test]
     fn builds_render_models_from_assets() {
         let sprite = qw_common::Sprite {
@@ -0,0 +1,1113 @@
+// The menus, after menu.c: one screen at a time drawn from the gfx/*.lmp
+// pics, driven by keys and the mouse, acting through console commands.
+
+use std::net::SocketAddr;
+
+use crate::hud::{Canvas, HudPics, qpic_indices};
+use crate::input::InputBindings;
+use qw_common::{CvarRegistry, Palette, PcxImage, QuakeFs};
+use qw_renderer::{UiImage, UiPic};
+use qw_window_glfw::Key;
+
+// The .lmp pics the menus draw, loaded along with the HUD's.
+pub const MENU_PICS: [&str; 35] = [
+    "gfx/qplaque.lmp",
+    "gfx/ttl_main.lmp",
+    "gfx/mainmenu.lmp",
+    "gfx/menudot1.lmp",
+    "gfx/menudot2.lmp",
+    "gfx/menudot3.lmp",
+    "gfx/menudot4.lmp",
+    "gfx/menudot5.lmp",
+    "gfx/menudot6.lmp",
+    "gfx/ttl_sgl.lmp",
+    "gfx/sp_menu.lmp",
+    "gfx/p_load.lmp",
+    "gfx/p_save.lmp",
+    "gfx/p_multi.lmp",
+    "gfx/p_option.lmp",
+    "gfx/ttl_cstm.lmp",
+    "gfx/vidmodes.lmp",
+    "gfx/bigbox.lmp",
+    "gfx/help0.lmp",
+    "gfx/help1.lmp",
+    "gfx/help2.lmp",
+    "gfx/help3.lmp",
+    "gfx/help4.lmp",
+    "gfx/help5.lmp",
+    "gfx/box_tl.lmp",
+    "gfx/box_ml.lmp",
+    "gfx/box_bl.lmp",
+    "gfx/box_tm.lmp",
+    "gfx/box_mm.lmp",
+    "gfx/box_mm2.lmp",
+    "gfx/box_bm.lmp",
+    "gfx/box_tr.lmp",
+    "gfx/box_mr.lmp",
+    "gfx/box_br.lmp",
+    "gfx/menuplyr.lmp",
+];
+
+// The pics player setup makes from the colors being picked.
+// What the video options menu adjusts; the main loop hands them on.
+pub const VIDEO_CVARS: [(&str, &str, bool); 2] = [("gamma", "1", true), ("vid_vsync", "1", true)];
+
+const PLAYER_PREVIEW: &str = "menu_player";
+const SKIN_PREVIEW: &str = "menu_skin";
+
+const MAIN_ITEMS: usize = 5;
+const SINGLEPLAYER_ITEMS: usize = 3;
+const SAVE_SLOTS: usize = 12;
+const MULTIPLAYER_ITEMS: [&str; 2] = ["Join a Game", "Player Setup"];
+const SERVER_ROWS: usize = 16;
+const HELP_PAGES: usize = 6;
+const SLIDER_RANGE: i32 = 10;
+const NAME_LENGTH: usize = 15;
+// The fourteen player colors.
+const MAX_COLOR: i32 = 13;
+// The shirt and pants rows of a player skin, from r_part.c's TOP_RANGE and
+// BOTTOM_RANGE.
+const TOP_RANGE: usize = 16;
+const BOTTOM_RANGE: usize = 96;
+
+// Setup's rows: name, team, skin, shirt, pants, accept.
+const SETUP_ROWS: [i32; 6] = [40, 64, 88, 112, 128, 152];
+
+const QUIT_MESSAGE: [&str; 4] = [
+    "  Are you sure you want ",
+    "   to leave the fight?  ",
+    "                        ",
+    "  Press Y to quit, N to ",
+];
+
+// menu.c's bindnames: what each key config row binds, and its label.
+const BINDNAMES: [(&str, &str); 13] = [
+    ("+attack", "attack"),
+    ("impulse 10", "change weapon"),
+    ("+jump", "jump / swim up"),
+    ("+forward", "walk forward"),
+    ("+back", "backpedal"),
+    ("+speed", "run"),
+    ("+moveleft", "step left"),
+    ("+moveright", "step right"),
+    ("+strafe", "sidestep"),
+    ("+mlook", "mouse look"),
+    ("+moveup", "swim up"),
+    ("+movedown", "swim down"),
+    ("+showscores", "show scores"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MenuItem {
+    Screen(&'static str, MenuScreen),
+    Command(&'static str, &'static str),
+    // A cvar between two ends, which may run backwards.
+    Slider {
+        label: &'static str,
+        cvar: &'static str,
+        min: f32,
+        max: f32,
+        step: f32,
+    },
+    Toggle(&'static str, &'static str),
+    // m_pitch flipped negative.
+    InvertMouse,
+}
+
+const OPTIONS: [MenuItem; 10] = [
+    MenuItem::Screen("Customize controls", MenuScreen::Keys),
+    MenuItem::Command("Go to console", "toggleconsole"),
+    MenuItem::Command("Reset to defaults", "exec default.cfg"),
+    MenuItem::Slider {
+        label: "Mouse Speed",
+        cvar: "sensitivity",
+        min: 1.0,
+        max: 11.0,
+        step: 0.5,
+    },
+    MenuItem::Slider {
+        label: "Sound Volume",
+        cvar: "volume",
+        min: 0.0,
+        max: 1.0,
+        step: 0.1,
+    },
+    // Lower gamma is brighter.
+    MenuItem::Slider {
+        label: "Brightness",
+        cvar: "gamma",
+        min: 1.0,
+        max: 0.5,
+        step: 0.05,
+    },
+    MenuItem::InvertMouse,
+    MenuItem::Toggle("Lookspring", "lookspring"),
+    MenuItem::Toggle("Lookstrafe", "lookstrafe"),
+    MenuItem::Screen("Video Options", MenuScreen::Video),
+];
+
+const VIDEO: [MenuItem; 3] = [
+    MenuItem::Toggle("Vertical sync", "vid_vsync"),
+    MenuItem::Slider {
+        label: "Status bar size",
+        cvar: "scr_sbarscale",
+        min: 1.0,
+        max: 4.0,
+        step: 0.5,
+    },
+    MenuItem::Toggle("Mini HUD", "scr_hudstyle"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MenuScreen {
+    Main,
+    SinglePlayer,
+    Load,
+    Save,
+    MultiPlayer,
+    Servers,
+    Setup,
+    Options,
+    Keys,
+    Video,
+    Help,
+    Quit,
+}
+
+impl MenuScreen {
+    // Where Escape goes back to.
+    fn parent(self) -> Option<MenuScreen> {
+        match self {
+            MenuScreen::Main => None,
+            MenuScreen::Load | MenuScreen::Save => Some(MenuScreen::SinglePlayer),
+            MenuScreen::Servers | MenuScreen::Setup => Some(MenuScreen::MultiPlayer),
+            MenuScreen::Keys | MenuScreen::Video => Some(MenuScreen::Options),
+            _ => Some(MenuScreen::Main),
+        }
+    }
+}
+
+// What the menus read from the rest of the client.
+pub struct MenuContext<'a> {
+    pub cvars: &'a CvarRegistry,
+    pub bindings: &'a InputBindings,
+    // Each server the browser has heard from, and its line in the list.
+    pub servers: Vec<(SocketAddr, String)>,
+    pub time: f64,
+}
+
+// Player setup's fields, applied only on Accept.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct SetupFields {
+    name: String,
+    team: String,
+    skin: String,
+    top: i32,
+    bottom: i32,
+}
+
+#[derive(Debug)]
+pub struct Menu {
+    screen: Option<MenuScreen>,
+    cursors: [usize; 12],
+    // The screen Quit returns to when it's turned down.
+    quit_return: Option<MenuScreen>,
+    help_page: usize,
+    setup: SetupFields,
+    // Key config waiting for the key to bind.
+    grabbing: bool,
+    // Where the last frame put the menu: its scale and origin.
+    layout: (f32, (i32, i32)),
+    preview_key: Option<(String, i32, i32)>,
+}
+
+impl Default for Menu {
+    fn default() -> Self {
+        Self {
+            screen: None,
+            cursors: [0; 12],
+            quit_return: None,
+            help_page: 0,
+            setup: SetupFields::default(),
+            grabbing: false,
+            layout: (1.0, (0, 0)),
+            preview_key: None,
+        }
+    }
+}
+
+impl Menu {
+    pub fn is_open(&self) -> bool {
+        self.screen.is_some()
+    }
+
+    pub fn screen(&self) -> Option<MenuScreen> {
+        self.screen
+    }
+
+    pub fn grabbing(&self) -> bool {
+        self.grabbing
+    }
+
+    pub fn open(&mut self, screen: MenuScreen, cvars: &CvarRegistry) {
+        match screen {
+            MenuScreen::Setup => {
+                self.setup = SetupFields {
+                    name: cvars.string("name"),
+                    team: cvars.string("team"),
+                    skin: cvars.string("skin"),
+                    top: (cvars.value("topcolor") as i32).clamp(0, MAX_COLOR),
+                    bottom: (cvars.value("bottomcolor") as i32).clamp(0, MAX_COLOR),
+                };
+            }
+            MenuScreen::Quit if self.screen != Some(MenuScreen::Quit) => {
+                self.quit_return = self.screen;
+            }
+            MenuScreen::Help => self.help_page = 0,
+            _ => {}
+        }
+        self.grabbing = false;
+        self.screen = Some(screen);
+    }
+
+    pub fn close(&mut self) {
+        self.screen = None;
+        self.grabbing = false;
+    }
+
+    fn cursor(&self) -> usize {
+        self.screen
+            .map_or(0, |screen| self.cursors[screen as usize])
+    }
+
+    fn set_cursor(&mut self, cursor: usize) {
+        if let Some(screen) = self.screen {
+            self.cursors[screen as usize] = cursor;
+        }
+    }
+
+    fn item_count(&self, ctx: &MenuContext) -> usize {
+        match self.screen {
+            Some(MenuScreen::Main) => MAIN_ITEMS,
+            Some(MenuScreen::SinglePlayer) => SINGLEPLAYER_ITEMS,
+            Some(MenuScreen::Load | MenuScreen::Save) => SAVE_SLOTS,
+            Some(MenuScreen::MultiPlayer) => MULTIPLAYER_ITEMS.len(),
+            Some(MenuScreen::Servers) => ctx.servers.len(),
+            Some(MenuScreen::Setup) => SETUP_ROWS.len(),
+            Some(MenuScreen::Options) => OPTIONS.len(),
+            Some(MenuScreen::Keys) => BINDNAMES.len(),
+            Some(MenuScreen::Video) => VIDEO.len(),
+            Some(MenuScreen::Help | MenuScreen::Quit) | None => 0,
+        }
+    }
+
+    // The top and height of each row, for the mouse.
+    fn row(&self, index: usize) -> (i32, i32) {
+        let i = index as i32;
+        match self.screen {
+            Some(MenuScreen::Main | MenuScreen::SinglePlayer) => (32 + i * 20, 20),
+            Some(MenuScreen::Servers) => {
+                let first = self.cursor().saturating_sub(SERVER_ROWS - 1) as i32;
+                (48 + (i - first) * 8, 8)
+            }
+            Some(MenuScreen::Setup) => (SETUP_ROWS[index] - 4, 16),
+            Some(MenuScreen::Keys) => (48 + i * 8, 8),
+            _ => (32 + i * 8, 8),
+        }
+    }
+
+    // M_Keydown, for presses and autorepeat. Returns the commands to run.
+    pub fn key(&mut self, key: Key, ctx: &MenuContext) -> Vec<String> {
+        let Some(screen) = self.screen else {
+            return Vec::new();
+        };
+        if self.grabbing {
+            return self.grab_key(key, ctx);
+        }
+        if screen == MenuScreen::Setup
+            && let Some(commands) = self.setup_key(key)
+        {
+            return commands;
+        }
+        match key {
+            Key::Escape | Key::Mouse2 => {
+                self.back();
+                Vec::new()
+            }
+            Key::Up | Key::KpUpArrow | Key::MWheelUp => {
+                self.step_cursor(-1, ctx);
+                Vec::new()
+            }
+            Key::Down | Key::KpDownArrow | Key::MWheelDown => {
+                self.step_cursor(1, ctx);
+                Vec::new()
+            }
+            Key::Left | Key::KpLeftArrow => self.adjust(-1, ctx),
+            Key::Right | Key::KpRightArrow => self.adjust(1, ctx),
+            Key::Enter | Key::KpEnter | Key::Mouse1 => self.activate(ctx),
+            Key::Backspace | Key::Del if screen == MenuScreen::Keys => {
+                unbind_command(BINDNAMES[self.cursor()].0, ctx.bindings)
+            }
+            Key::Char(b'y') if screen == MenuScreen::Quit => vec!["quit".to_string()],
+            Key::Char(b'n') if screen == MenuScreen::Quit => {
+                self.back();
+                Vec::new()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn back(&mut self) {
+        match self.screen {
+            Some(MenuScreen::Quit) => match self.quit_return {
+                Some(screen) => self.screen = Some(screen),
+                None => self.close(),
+            },
+            Some(screen) => match screen.parent() {
+                Some(parent) => self.screen = Some(parent),
+                None => self.close(),
+            },
+            None => {}
+        }
+    }
+
+    fn step_cursor(&mut self, step: i32, ctx: &MenuContext) {
+        if self.screen == Some(MenuScreen::Help) {
+            self.turn_page(step);
+            return;
+        }
+        let count = self.item_count(ctx) as i32;
+        if count > 0 {
+            self.set_cursor((self.cursor() as i32 + step).rem_euclid(count) as usize);
+        }
+    }
+
+    fn turn_page(&mut self, step: i32) {
+        self.help_page = (self.help_page as i32 + step).rem_euclid(HELP_PAGES as i32) as usize;
+    }
+
+    // Left and right: sliders, toggles, colors and help pages.
+    fn adjust(&mut self, dir: i32, ctx: &MenuContext) -> Vec<String> {
+        let cursor = self.cursor();
+        match self.screen {
+            Some(MenuScreen::Help) => {
+                self.turn_page(dir);
+                Vec::new()
+            }
+            Some(MenuScreen::Options) => adjust_item(OPTIONS[cursor], dir, ctx.cvars),
+            Some(MenuScreen::Video) => adjust_item(VIDEO[cursor], dir, ctx.cvars),
+            Some(MenuScreen::Setup) => {
+                let color = match cursor {
+                    3 => &mut self.setup.top,
+                    4 => &mut self.setup.bottom,
+                    _ => return Vec::new(),
+                };
+                *color = (*color + dir).rem_euclid(MAX_COLOR + 1);
+                Vec::new()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn activate(&mut self, ctx: &MenuContext) -> Vec<String> {
+        let cursor = self.cursor();
+        match self.screen {
+            Some(MenuScreen::Main) => {
+                let screen = [
+                    MenuScreen::SinglePlayer,
+                    MenuScreen::MultiPlayer,
+                    MenuScreen::Options,
+                    MenuScreen::Help,
+                    MenuScreen::Quit,
+                ][cursor];
+                self.open(screen, ctx.cvars);
+                Vec::new()
+            }
+            Some(MenuScreen::SinglePlayer) => match cursor {
+                0 => {
+                    self.close();
+                    vec!["map start".to_string()]
+                }
+                1 => {
+                    self.open(MenuScreen::Load, ctx.cvars);
+                    Vec::new()
+                }
+                _ => {
+                    self.open(MenuScreen::Save, ctx.cvars);
+                    Vec::new()
+                }
+            },
+            Some(screen @ (MenuScreen::Load | MenuScreen::Save)) => {
+                self.close();
+                let verb = if screen == MenuScreen::Load {
+                    "load"
+                } else {
+                    "save"
+                };
+                vec![format!("{verb} s{cursor}")]
+            }
+            Some(MenuScreen::MultiPlayer) => {
+                if cursor == 0 {
+                    self.open(MenuScreen::Servers, ctx.cvars);
+                    vec!["slist".to_string()]
+                } else {
+                    self.open(MenuScreen::Setup, ctx.cvars);
+                    Vec::new()
+                }
+            }
+            Some(MenuScreen::Servers) => match ctx.servers.get(cursor) {
+                Some((addr, _)) => {
+                    self.close();
+                    vec![format!("connect {addr}")]
+                }
+                None => Vec::new(),
+            },
+            Some(MenuScreen::Options) => self.activate_item(OPTIONS[cursor], ctx),
+            Some(MenuScreen::Video) => self.activate_item(VIDEO[cursor], ctx),
+            Some(MenuScreen::Keys) => {
+                self.grabbing = true;
+                Vec::new()
+            }
+            Some(MenuScreen::Setup) => self.adjust(1, ctx),
+            Some(MenuScreen::Help) => {
+                self.turn_page(1);
+                Vec::new()
+            }
+            Some(MenuScreen::Quit) | None => Vec::new(),
+        }
+    }
+
+    fn activate_item(&mut self, item: MenuItem, ctx: &MenuContext) -> Vec<String> {
+        match item {
+            MenuItem::Screen(_, screen) => {
+                self.open(screen, ctx.cvars);
+                Vec::new()
+            }
+            MenuItem::Command(_, command) => vec![command.to_string()],
+            _ => adjust_item(item, 1, ctx.cvars),
+        }
+    }
+
+    // The text fields take typing; Enter on Accept applies the lot.
+    fn setup_key(&mut self, key: Key) -> Option<Vec<String>> {
+        let cursor = self.cursor();
+        let field = match cursor {
+            0 => &mut self.setup.name,
+            1 => &mut self.setup.team,
+            2 => &mut self.setup.skin,
+            5 if matches!(key, Key::Enter | Key::KpEnter | Key::Mouse1) => {
+                let setup = &self.setup;
+                let commands = vec![
+                    format!("name \"{}\"", setup.name),
+                    format!("team \"{}\"", setup.team),
+                    format!("skin \"{}\"", setup.skin),
+                    format!("topcolor {}", setup.top),
+                    format!("bottomcolor {}", setup.bottom),
+                ];
+                self.screen = Some(MenuScreen::MultiPlayer);
+                return Some(commands);
+            }
+            _ => return None,
+        };
+        match key {
+            Key::Backspace => {
+                field.pop();
+            }
+            Key::Char(c) if c.is_ascii_graphic() && field.len() < NAME_LENGTH => {
+                field.push(char::from(c));
+            }
+            Key::Space if field.len() < NAME_LENGTH => field.push(' '),
+            _ => return None,
+        }
+        Some(Vec::new())
+    }
+
+    // M_Keys_Key while waiting: Escape gives up, anything else with a name
+    // is bound, first freeing a command that already has two keys.
+    fn grab_key(&mut self, key: Key, ctx: &MenuContext) -> Vec<String> {
+        self.grabbing = false;
+        if key == Key::Escape {
+            return Vec::new();
+        }
+        let Some(name) = key.name() else {
+            return Vec::new();
+        };
+        let command = BINDNAMES[self.cursor()].0;
+        let mut commands = Vec::new();
+        if keys_for(command, ctx.bindings).len() >= 2 {
+            commands = unbind_command(command, ctx.bindings);
+        }
+        commands.push(format!("bind \"{name}\" \"{command}\""));
+        commands
+    }
+
+    // The mouse picks whichever row it's over.
+    pub fn mouse_moved(&mut self, x: f32, y: f32, ctx: &MenuContext) {
+        let (scale, origin) = self.layout;
+        let mx = (x / scale) as i32 - origin.0;
+        let my = (y / scale) as i32 - origin.1;
+        if !(0..320).contains(&mx) || self.grabbing {
+            return;
+        }
+        for index in 0..self.item_count(ctx) {
+            let (top, height) = self.row(index);
+            if (top..top + height).contains(&my) {
+                self.set_cursor(index);
+                return;
+            }
+        }
+    }
+
+    // M_Draw: the screen faded, then the current menu centred on it.
+    pub fn draw(
+        &mut self,
+        pics: &HudPics,
+        scale: f32,
+        screen: (u32, u32),
+        ctx: &MenuContext,
+    ) -> Vec<UiImage> {
+        let Some(menu) = self.screen else {
+            return Vec::new();
+        };
+        let mut canvas = Canvas::new(pics, scale, screen);
+        canvas.stretch(0, 0, canvas.width, canvas.height, "fade");
+        canvas.origin = ((canvas.width - 320) / 2, (canvas.height - 200) / 2);
+        self.layout = (canvas.scale(), canvas.origin);
+        let blink = ((ctx.time * 4.0) as i32 & 1) as u8;
+        let cursor = self.cursor();
+        match menu {
+            MenuScreen::Main => {
+                header(&mut canvas, "gfx/ttl_main.lmp");
+                canvas.pic(72, 32, "gfx/mainmenu.lmp");
+                let dot = (ctx.time * 10.0) as i64 % 6 + 1;
+                canvas.pic(
+                    54,
+                    32 + cursor as i32 * 20,
+                    &format!("gfx/menudot{dot}.lmp"),
+                );
+            }
+            MenuScreen::SinglePlayer => {
+                header(&mut canvas, "gfx/ttl_sgl.lmp");
+                canvas.pic(72, 32, "gfx/sp_menu.lmp");
+                let dot = (ctx.time * 10.0) as i64 % 6 + 1;
+                canvas.pic(
+                    54,
+                    32 + cursor as i32 * 20,
+                    &format!("gfx/menudot{dot}.lmp"),
+                );
+            }
+            MenuScreen::Load | MenuScreen::Save => {
+                let title = if menu == MenuScreen::Load {
+                    "gfx/p_load.lmp"
+                } else {
+                    "gfx/p_save.lmp"
+                };
+                header(&mut canvas, title);
+                for slot in 0..SAVE_SLOTS {
+                    canvas.text(16, 32 + slot as i32 * 8, &format!("s{slot}"), true);
+                }
+                canvas.character(8, 32 + cursor as i32 * 8, 12 + blink);
+            }
+            MenuScreen::MultiPlayer => {
+                header(&mut canvas, "gfx/p_multi.lmp");
+                for (i, label) in MULTIPLAYER_ITEMS.iter().enumerate() {
+                    canvas.text(72, 32 + i as i32 * 8, label, true);
+                }
+                canvas.character(56, 32 + cursor as i32 * 8, 12 + blink);
+            }
+            MenuScreen::Servers => self.draw_servers(&mut canvas, ctx, blink),
+            MenuScreen::Setup => self.draw_setup(&mut canvas, blink),
+            MenuScreen::Options => {
+                header(&mut canvas, "gfx/p_option.lmp");
+                draw_items(&mut canvas, &OPTIONS, cursor, blink, ctx.cvars);
+            }
+            MenuScreen::Video => {
+                header(&mut canvas, "gfx/vidmodes.lmp");
+                draw_items(&mut canvas, &VIDEO, cursor, blink, ctx.cvars);
+            }
+            MenuScreen::Keys => self.draw_keys(&mut canvas, ctx, blink),
+            MenuScreen::Help => canvas.pic(0, 0, &format!("gfx/help{}.lmp", self.help_page)),
+            MenuScreen::Quit => {
+                text_box(&mut canvas, 56, 76, 24, 4);
+                for (i, line) in QUIT_MESSAGE.iter().enumerate() {
+                    canvas.text(64, 84 + i as i32 * 8, line, true);
+                }
+            }
+        }
+        canvas.images
+    }
+
+    fn draw_servers(&self, canvas: &mut Canvas, ctx: &MenuContext, blink: u8) {
+        header(canvas, "gfx/p_multi.lmp");
+        canvas.text(16, 32, "ping pl map      name", false);
+        if ctx.servers.is_empty() {
+            canvas.text(16, 48, "searching...", true);
+            return;
+        }
+        let cursor = self.cursor();
+        let first = cursor.saturating_sub(SERVER_ROWS - 1);
+        for (i, (_, line)) in ctx.servers.iter().enumerate().skip(first).take(SERVER_ROWS) {
+            let line: String = line.chars().take(36).collect();
+            canvas.text(16, self.row(i).0, &line, true);
+        }
+        canvas.character(8, self.row(cursor).0, 12 + blink);
+    }
+
+    // M_Setup_Draw, with QW's team and skin, and the player drawn in the
+    // colors being picked beside their skin.
+    fn draw_setup(&self, canvas: &mut Canvas, blink: u8) {
+        header(canvas, "gfx/p_multi.lmp");
+        let setup = &self.setup;
+        let fields = [
+            ("Your name", &setup.name),
+            ("Your team", &setup.team),
+            ("Your skin", &setup.skin),
+        ];
+        for (i, (label, value)) in fields.iter().enumerate() {
+            let y = SETUP_ROWS[i];
+            canvas.text(16, y, label, true);
+            text_box(canvas, 96, y - 8, 16, 1);
+            canvas.text(104, y, value, false);
+        }
+        canvas.text(16, SETUP_ROWS[3], "Shirt color", true);
+        canvas.text(112, SETUP_ROWS[3], &setup.top.to_string(), false);
+        canvas.text(16, SETUP_ROWS[4], "Pants color", true);
+        canvas.text(112, SETUP_ROWS[4], &setup.bottom.to_string(), false);
+        text_box(canvas, 16, SETUP_ROWS[5] - 8, 14, 1);
+        canvas.text(24, SETUP_ROWS[5], "Accept Changes", false);
+
+        canvas.pic(244, 40, "gfx/bigbox.lmp");
+        canvas.pic(256, 48, PLAYER_PREVIEW);
+        canvas.stretch(248, 120, 64, 42, SKIN_PREVIEW);
+
+        let cursor = self.cursor();
+        canvas.character(4, SETUP_ROWS[cursor], 12 + blink);
+        if let Some(field) = fields.get(cursor) {
+            let x = 104 + field.1.len() as i32 * 8;
+            canvas.character(x, SETUP_ROWS[cursor], 10 + blink);
+        }
+    }
+
+    fn draw_keys(&self, canvas: &mut Canvas, ctx: &MenuContext, blink: u8) {
+        header(canvas, "gfx/ttl_cstm.lmp");
+        if self.grabbing {
+            canvas.text(12, 32, "Press a key or button for this action", false);
+        } else {
+            canvas.text(18, 32, "Enter to change, backspace to clear", false);
+        }
+        for (i, (command, label)) in BINDNAMES.iter().enumerate() {
+            let y = self.row(i).0;
+            canvas.text(16, y, label, false);
+            let keys = keys_for(command, ctx.bindings);
+            let text = if keys.is_empty() {
+                "???".to_string()
+            } else {
+                keys.join(" or ")
+            };
+            canvas.text(140, y, &text, true);
+        }
+        let y = self.row(self.cursor()).0;
+        if self.grabbing {
+            canvas.character(130, y, b'=');
+        } else {
+            canvas.character(130, y, 12 + blink);
+        }
+    }
+
+    // While player setup is up, the player and skin pics in the colors
+    // being picked, when they've changed.
+    pub fn player_preview(
+        &mut self,
+        fs: &QuakeFs,
+        palette: &Palette,
+    ) -> Option<Vec<(String, UiPic)>> {
+        if self.screen != Some(MenuScreen::Setup) {
+            return None;
+        }
+        let setup = &self.setup;
+        let key = (setup.skin.clone(), setup.top, setup.bottom);
+        if self.preview_key.as_ref() == Some(&key) {
+            return None;
+        }
+        let translation = translation_table(setup.top, setup.bottom);
+        let translate = |pixels: &[u8]| -> Vec<u8> {
+            pixels.iter().map(|&p| translation[p as usize]).collect()
+        };
+        let player = fs
+            .read("gfx/menuplyr.lmp")
+            .ok()
+            .and_then(|data| {
+                let (width, height, pixels) = qpic_indices(&data)?;
+                Some(UiPic {
+                    width,
+                    height,
+                    rgba: palette.expand_indices(&translate(pixels), Some(255)),
+                })
+            })
+            .unwrap_or_else(blank_pic);
+        let skin_name = if setup.skin.is_empty() {
+            "base"
+        } else {
+            setup.skin.as_str()
+        };
+        let skin = fs
+            .read(&format!("skins/{skin_name}.pcx"))
+            .ok()
+            .and_then(|data| PcxImage::from_bytes(&data).ok())
+            .map(|pcx| UiPic {
+                width: pcx.width,
+                height: pcx.height,
+                rgba: palette.expand_indices(&translate(&pcx.indices), None),
+            })
+            .unwrap_or_else(blank_pic);
+        self.preview_key = Some(key);
+        Some(vec![
+            (PLAYER_PREVIEW.to_string(), player),
+            (SKIN_PREVIEW.to_string(), skin),
+        ])
+    }
+}
+
+// A clear pixel, so a skin that isn't there doesn't leave the last one up.
+fn blank_pic() -> UiPic {
+    UiPic {
+        width: 1,
+        height: 1,
+        rgba: vec![0; 4],
+    }
+}
+
+// M_BuildTranslationTable: the shirt and pants rows swapped for the
+// chosen colors' rows, the bright-to-dark ones turned around.
+fn translation_table(top: i32, bottom: i32) -> [u8; 256] {
+    let mut table = [0u8; 256];
+    for (i, entry) in table.iter_mut().enumerate() {
+        *entry = i as u8;
+    }
+    for (range, color) in [(TOP_RANGE, top), (BOTTOM_RANGE, bottom)] {
+        let base = (color.clamp(0, MAX_COLOR) * 16) as usize;
+        for j in 0..16 {
+            table[range + j] = if base < 128 {
+                (base + j) as u8
+            } else {
+                (base + 15 - j) as u8
+            };
+        }
+    }
+    table
+}
+
+// The plaque down the left and the screen's title across the top.
+fn header(canvas: &mut Canvas, title: &str) {
+    canvas.pic(16, 4, "gfx/qplaque.lmp");
+    if let Some((width, _)) = canvas.size_of(title) {
+        canvas.pic((320 - width) / 2, 4, title);
+    }
+}
+
+// M_DrawTextBox: `width` characters by `lines`, inside a border.
+fn text_box(canvas: &mut Canvas, x: i32, y: i32, width: i32, lines: i32) {
+    box_column(canvas, x, y, lines, ["tl", "ml", "ml", "bl"]);
+    let mut cx = x + 8;
+    for _ in 0..(width + 1) / 2 {
+        box_column(canvas, cx, y, lines, ["tm", "mm", "mm2", "bm"]);
+        cx += 16;
+    }
+    box_column(canvas, cx, y, lines, ["tr", "mr", "mr", "br"]);
+}
+
+// One column of the border: top, middle pieces with the second line's
+// own, and bottom.
+fn box_column(canvas: &mut Canvas, x: i32, y: i32, lines: i32, pieces: [&str; 4]) {
+    canvas.pic(x, y, &format!("gfx/box_{}.lmp", pieces[0]));
+    for line in 0..lines {
+        let piece = if line == 1 { pieces[2] } else { pieces[1] };
+        canvas.pic(x, y + 8 + line * 8, &format!("gfx/box_{piece}.lmp"));
+    }
+    canvas.pic(x, y + 8 + lines * 8, &format!("gfx/box_{}.lmp", pieces[3]));
+}
+
+// The options and video lists: labels right-aligned against the cursor,
+// values to its right.
+fn draw_items(
+    canvas: &mut Canvas,
+    items: &[MenuItem],
+    cursor: usize,
+    blink: u8,
+    cvars: &CvarRegistry,
+) {
+    for (i, item) in items.iter().enumerate() {
+        let y = 32 + i as i32 * 8;
+        let label = match *item {
+            MenuItem::Screen(label, _) | MenuItem::Command(label, _) => label,
+            MenuItem::Slider { label, .. } | MenuItem::Toggle(label, _) => label,
+            MenuItem::InvertMouse => "Invert Mouse",
+        };
+        canvas.text(192 - label.len() as i32 * 8, y, label, true);
+        match *item {
+            MenuItem::Slider { cvar, min, max, .. } => {
+                slider(canvas, 220, y, (cvars.value(cvar) - min) / (max - min))
+            }
+            MenuItem::Toggle(_, cvar) => checkbox(canvas, 220, y, cvars.value(cvar) != 0.0),
+            MenuItem::InvertMouse => checkbox(canvas, 220, y, cvars.value("m_pitch") < 0.0),
+            _ => {}
+        }
+    }
+    canvas.character(200, 32 + cursor as i32 * 8, 12 + blink);
+}
+
+// M_DrawSlider: a bar of conchars with the knob at `range` along it.
+fn slider(canvas: &mut Canvas, x: i32, y: i32, range: f32) {
+    let range = range.clamp(0.0, 1.0);
+    canvas.character(x - 8, y, 128);
+    for i in 0..SLIDER_RANGE {
+        canvas.character(x + i * 8, y, 129);
+    }
+    canvas.character(x + SLIDER_RANGE * 8, y, 130);
+    let knob = ((SLIDER_RANGE - 1) * 8) as f32 * range;
+    canvas.character(x + knob as i32, y, 131);
+}
+
+fn checkbox(canvas: &mut Canvas, x: i32, y: i32, on: bool) {
+    canvas.text(x, y, if on { "on" } else { "off" }, false);
+}
+
+// M_AdjustSliders: a step along a slider, or a toggle flipped, as the
+// command that sets it.
+fn adjust_item(item: MenuItem, dir: i32, cvars: &CvarRegistry) -> Vec<String> {
+    match item {
+        MenuItem::Slider {
+            cvar,
+            min,
+            max,
+            step,
+            ..
+        } => {
+            let span = max - min;
+            let along = (cvars.value(cvar) - min) / span;
+            let along = (along + dir as f32 * step / span.abs()).clamp(0.0, 1.0);
+            let value = ((min + along * span) * 100.0).round() / 100.0;
+            vec![format!("{cvar} {value}")]
+        }
+        MenuItem::Toggle(_, cvar) => {
+            let value = if cvars.value(cvar) != 0.0 { 0 } else { 1 };
+            vec![format!("{cvar} {value}")]
+        }
+        MenuItem::InvertMouse => vec![format!("m_pitch {}", -cvars.value("m_pitch"))],
+        MenuItem::Screen(..) | MenuItem::Command(..) => Vec::new(),
+    }
+}
+
+// M_FindKeysForCommand: the first two keys bound to exactly `command`.
+fn keys_for(command: &str, bindings: &InputBindings) -> Vec<String> {
+    bindings
+        .iter()
+        .filter(|(_, bound)| *bound == command)
+        .filter_map(|(key, _)| key.name())
+        .take(2)
+        .collect()
+}
+
+fn unbind_command(command: &str, bindings: &InputBindings) -> Vec<String> {
+    bindings
+        .iter()
+        .filter(|(_, bound)| *bound == command)
+        .filter_map(|(key, _)| key.name())
+        .map(|name| format!("unbind \"{name}\""))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use qw_common::Cvar;
+
+    fn cvars() -> CvarRegistry {
+        let mut cvars = CvarRegistry::new();
+        for (name, value) in [
+            ("sensitivity", "3"),
+            ("volume", "0.7"),
+            ("gamma", "1"),
+            ("m_pitch", "0.022"),
+            ("lookspring", "0"),
+            ("name", "player"),
+            ("team", ""),
+            ("skin", ""),
+            ("topcolor", "4"),
+            ("bottomcolor", "12"),
+        ] {
+            cvars.register(Cvar::new(name, value));
+        }
+        cvars
+    }
+
+    fn context<'a>(cvars: &'a CvarRegistry, bindings: &'a InputBindings) -> MenuContext<'a> {
+        MenuContext {
+            cvars,
+            bindings,
+            servers: Vec::new(),
+            time: 0.0,
+        }
+    }
+
+    fn press(menu: &mut Menu, keys: &[Key], ctx: &MenuContext) -> Vec<String> {
+        keys.iter().flat_map(|key| menu.key(*key, ctx)).collect()
+    }
+
+    #[test]
+    fn walks_between_screens_and_back_out() {
+        let (cvars, bindings) = (cvars(), InputBindings::default());
+        let ctx = context(&cvars, &bindings);
+        let mut menu = Menu::default();
+        menu.open(MenuScreen::Main, &cvars);
+        press(&mut menu, &[Key::Down, Key::Down, Key::Enter], &ctx);
+        assert_eq!(menu.screen(), Some(MenuScreen::Options));
+        press(&mut menu, &[Key::Up, Key::Enter], &ctx);
+        assert_eq!(menu.screen(), Some(MenuScreen::Video));
+        press(&mut menu, &[Key::Escape, Key::Escape], &ctx);
+        assert_eq!(menu.screen(), Some(MenuScreen::Main));
+        // The main menu remembers where it was; up from the top wraps.
+        press(
+            &mut menu,
+            &[Key::Up, Key::Up, Key::Up, Key::Up, Key::Enter],
+            &ctx,
+        );
+        assert_eq!(menu.screen(), Some(MenuScreen::Help));
+        press(&mut menu, &[Key::Escape, Key::Escape], &ctx);
+        assert!(!menu.is_open());
+    }
+
+    #[test]
+    fn sliders_and_toggles_set_cvars() {
+        let (cvars, bindings) = (cvars(), InputBindings::default());
+        let ctx = context(&cvars, &bindings);
+        let mut menu = Menu::default();
+        menu.open(MenuScreen::Options, &cvars);
+        let commands = press(
+            &mut menu,
+            &[Key::Down, Key::Down, Key::Down, Key::Right],
+            &ctx,
+        );
+        assert_eq!(commands, ["sensitivity 3.5"]);
+        assert_eq!(
+            press(&mut menu, &[Key::Down, Key::Left], &ctx),
+            ["volume 0.6"]
+        );
+        // Brightness runs backwards: right makes gamma smaller.
+        assert_eq!(
+            press(&mut menu, &[Key::Down, Key::Right], &ctx),
+            ["gamma 0.95"]
+        );
+        assert_eq!(
+            press(&mut menu, &[Key::Down, Key::Enter], &ctx),
+            ["m_pitch -0.022"]
+        );
+        assert_eq!(
+            press(&mut menu, &[Key::Down, Key::Left], &ctx),
+            ["lookspring 1"]
+        );
+        menu.set_cursor(1);
+        assert_eq!(press(&mut menu, &[Key::Enter], &ctx), ["toggleconsole"]);
+    }
+
+    #[test]
+    fn binds_the_next_key_pressed() {
+        let cvars = cvars();
+        let mut bindings = InputBindings::new();
+        bindings.bind(Key::Mouse1, "+attack");
+        bindings.bind(Key::Ctrl, "+attack");
+        let ctx = context(&cvars, &bindings);
+        let mut menu = Menu::default();
+        menu.open(MenuScreen::Keys, &cvars);
+        menu.key(Key::Enter, &ctx);
+        assert!(menu.grabbing());
+        let commands = menu.key(Key::Char(b'f'), &ctx);
+        assert_eq!(
+            commands,
+            [
+                "unbind \"CTRL\"",
+                "unbind \"MOUSE1\"",
+                "bind \"f\" \"+attack\""
+            ]
+        );
+        assert!(!menu.grabbing());
+        // Escape while waiting leaves the bindings alone and the menu up.
+        menu.key(Key::Enter, &ctx);
+        assert!(menu.key(Key::Escape, &ctx).is_empty());
+        assert_eq!(menu.screen(), Some(MenuScreen::Keys));
+        assert_eq!(menu.key(Key::Backspace, &ctx).len(), 2);
+    }
+
+    #[test]
+    fn player_setup_applies_on_accept() {
+        let (cvars, bindings) = (cvars(), InputBindings::default());
+        let ctx = context(&cvars, &bindings);
+        let mut menu = Menu::default();
+        menu.open(MenuScreen::Setup, &cvars);
+        press(&mut menu, &[Key::Backspace, Key::Char(b'!')], &ctx);
+        press(
+            &mut menu,
+            &[Key::Down, Key::Down, Key::Down, Key::Left],
+            &ctx,
+        );
+        press(&mut menu, &[Key::Down, Key::Right], &ctx);
+        let commands = press(&mut menu, &[Key::Down, Key::Enter], &ctx);
+        assert_eq!(
+            commands,
+            [
+                "name \"playe!\"",
+                "team \"\"",
+                "skin \"\"",
+                "topcolor 3",
+                "bottomcolor 13"
+            ]
+        );
+        assert_eq!(menu.screen(), Some(MenuScreen::MultiPlayer));
+    }
+
+    #[test]
+    fn joins_the_server_picked_from_the_list() {
+        let (cvars, bindings) = (cvars(), InputBindings::default());
+        let mut ctx = context(&cvars, &bindings);
+        let mut menu = Menu::default();
+        menu.open(MenuScreen::MultiPlayer, &cvars);
+        assert_eq!(menu.key(Key::Enter, &ctx), ["slist"]);
+        assert_eq!(menu.screen(), Some(MenuScreen::Servers));
+        ctx.servers = vec![
+            ("10.0.0.1:27500".parse().unwrap(), "a".to_string()),
+            ("10.0.0.2:27500".parse().unwrap(), "b".to_string()),
+        ];
+        // At 1x on a 320x200 screen the rows start 48 down.
+        menu.mouse_moved(40.0, 57.0, &ctx);
+        assert_eq!(menu.key(Key::Mouse1, &ctx), ["connect 10.0.0.2:27500"]);
+        assert!(!menu.is_open());
+    }
+
+    #[test]
+    fn quit_asks_first() {
+        let (cvars, bindings) = (cvars(), InputBindings::default());
+        let ctx = context(&cvars, &bindings);
+        let mut menu = Menu::default();
+        menu.open(MenuScreen::Quit, &cvars);
+        assert!(menu.key(Key::Char(b'n'), &ctx).is_empty());
+        assert!(!menu.is_open());
+        menu.open(MenuScreen::Options, &cvars);
+        menu.open(MenuScreen::Quit, &cvars);
+        menu.key(Key::Escape, &ctx);
+        assert_eq!(menu.screen(), Some(MenuScreen::Options));
+        menu.open(MenuScreen::Quit, &cvars);
+        assert_eq!(menu.key(Key::Char(b'y'), &ctx), ["quit"]);
+    }
+
+    #[test]
+    fn translates_shirt_and_pants() {
+        let table = translation_table(1, 9);
+        assert_eq!(table[0], 0);
+        assert_eq!(table[TOP_RANGE], 16);
+        assert_eq!(table[TOP_RANGE + 15], 31);
+        // Colors from 8 up run the other way.
+        assert_eq!(table[BOTTOM_RANGE], 159);
+        assert_eq!(table[BOTTOM_RANGE + 15], 144);
+        assert_eq!(table[255], 255);
+    }
+}
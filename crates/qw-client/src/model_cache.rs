@@ -91,7 +91,8 @@ impl ModelCache {
 
             let bytes = match fs.read(name) {
                 Ok(bytes) => bytes,
-                Err(FsError::NotFound) => continue,
+                // A name no download could fetch is as missing as any other.
+                Err(FsError::NotFound | FsError::InvalidPath) => continue,
                 Err(err) => return Err(err.into()),
             };
 
@@ -1,51 +1,105 @@
+use std::borrow::Cow;
 use std::io;
-use std::net::{SocketAddr, UdpSocket};
+use std::net::SocketAddr;
 use std::time::Duration;
 
-use qw_common::NetAddr;
+use qw_common::{
+    LOOPBACK_ADDR, LoopbackSocket, NetAddr, NetSim, NetSimConfig, Transport, UdpTransport,
+};
 
-pub struct NetClient {
-    socket: UdpSocket,
+pub struct NetClient<T: Transport = UdpTransport> {
+    transport: T,
     remote: SocketAddr,
+    // Where the connection started, for reconnecting after a NetQuake
+    // server has moved it.
+    server: SocketAddr,
 }
 
-impl NetClient {
+impl NetClient<UdpTransport> {
     pub fn connect(remote: SocketAddr) -> io::Result<Self> {
-        let socket = UdpSocket::bind("0.0.0.0:0")?;
-        socket.set_nonblocking(true)?;
-        Ok(Self { socket, remote })
+        Ok(Self::new(UdpTransport::bind("0.0.0.0:0")?, remote))
     }
 
     #[allow(dead_code)]
     pub fn connect_netaddr(addr: NetAddr) -> io::Result<Self> {
         Self::connect(SocketAddr::from(addr.to_socket_addr()))
     }
+}
+
+impl NetClient<LoopbackSocket> {
+    // Talks to a server running in this process.
+    pub fn loopback(socket: LoopbackSocket) -> Self {
+        Self::new(socket, LOOPBACK_ADDR)
+    }
+}
+
+impl<T: Transport> NetClient<T> {
+    pub fn new(transport: T, remote: SocketAddr) -> Self {
+        Self {
+            transport,
+            remote,
+            server: remote,
+        }
+    }
+
+    // Boxes the transport so connections of different kinds share a type.
+    pub fn boxed(self) -> NetClient<Box<dyn Transport>>
+    where
+        T: 'static,
+    {
+        NetClient::new(Box::new(self.transport), self.remote)
+    }
+
+    // Runs the connection over a simulated bad link.
+    pub fn simulated(self, config: NetSimConfig) -> NetClient<NetSim<T>> {
+        NetClient::new(NetSim::new(self.transport, config), self.remote)
+    }
 
     #[allow(dead_code)]
     pub fn local_addr(&self) -> io::Result<SocketAddr> {
-        self.socket.local_addr()
+        self.transport.local_addr()
     }
 
-    pub fn send(&self, data: &[u8]) -> io::Result<usize> {
-        self.socket.send_to(data, self.remote)
+    // NetQuake servers move each accepted client to a port of its own.
+    pub fn set_remote_port(&mut self, port: u16) {
+        self.remote.set_port(port);
     }
 
-    pub fn recv(&self, buf: &mut [u8]) -> io::Result<Option<(usize, SocketAddr)>> {
-        match self.socket.recv_from(buf) {
-            Ok((size, addr)) => Ok(Some((size, addr))),
-            Err(err) if err.kind() == io::ErrorKind::WouldBlock => Ok(None),
-            Err(err) => Err(err),
-        }
+    pub fn reset_remote(&mut self) {
+        self.remote = self.server;
     }
 
-    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
-        self.socket.set_read_timeout(timeout)
+    // Points the connection at another server.
+    pub fn set_server(&mut self, server: SocketAddr) {
+        self.server = server;
+        self.remote = server;
+    }
+
+    pub fn send(&mut self, packet: Vec<u8>) -> io::Result<()> {
+        self.transport.send_to(packet, self.remote)
+    }
+
+    // Never blocks.
+    pub fn recv<'a>(
+        &mut self,
+        buf: &'a mut [u8],
+    ) -> io::Result<Option<(Cow<'a, [u8]>, SocketAddr)>> {
+        self.recv_timeout(buf, Duration::ZERO)
+    }
+
+    pub fn recv_timeout<'a>(
+        &mut self,
+        buf: &'a mut [u8],
+        wait: Duration,
+    ) -> io::Result<Option<(Cow<'a, [u8]>, SocketAddr)>> {
+        self.transport.recv_from(buf, wait)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::net::UdpSocket;
 
     #[test]
     fn udp_round_trip() {
@@ -55,29 +109,33 @@ mod tests {
             .unwrap();
         let server_addr = server.local_addr().unwrap();
 
-        let client = NetClient::connect(server_addr).unwrap();
-        client.send(b"ping").unwrap();
+        let mut client = NetClient::connect(server_addr).unwrap();
+        client.send(b"ping".to_vec()).unwrap();
 
         let mut buf = [0u8; 64];
         let (size, client_addr) = server.recv_from(&mut buf).unwrap();
         assert_eq!(&buf[..size], b"ping");
 
         server.send_to(b"pong", client_addr).unwrap();
-        client
-            .set_read_timeout(Some(Duration::from_millis(200)))
-            .unwrap();
-
         let mut recv_buf = [0u8; 64];
-        let mut received = None;
-        for _ in 0..5 {
-            if let Some((len, _)) = client.recv(&mut recv_buf).unwrap() {
-                received = Some(len);
-                break;
-            }
-            std::thread::sleep(Duration::from_millis(10));
-        }
+        let (packet, _) = client
+            .recv_timeout(&mut recv_buf, Duration::from_millis(200))
+            .unwrap()
+            .expect("client received response");
+        assert_eq!(&*packet, b"pong");
+    }
+
+    #[test]
+    fn loopback_round_trip() {
+        let (client_end, server) = qw_common::loopback_pair();
+        let mut client = NetClient::loopback(client_end).boxed();
+        client.send(b"ping".to_vec()).unwrap();
+        assert_eq!(server.recv().unwrap(), Some(b"ping".to_vec()));
 
-        let len = received.expect("client received response");
-        assert_eq!(&recv_buf[..len], b"pong");
+        server.send(b"pong".to_vec()).unwrap();
+        let mut buf = [0u8; 4];
+        let (packet, addr) = client.recv(&mut buf).unwrap().unwrap();
+        assert_eq!(&*packet, b"pong");
+        assert_eq!(addr, LOOPBACK_ADDR);
     }
 }
@@ -1,18 +1,21 @@
 use std::collections::{HashSet, VecDeque};
-use std::fs::{self, File};
-use std::io::{self, Write};
-use std::time::Instant;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
 
-use crate::client::{Client, ClientPacket};
+use crate::client::{Client, ClientError, ClientPacket};
+use crate::demo::{DemoError, DemoPlayer, DemoRecord, DemoRecorder, TimeDemoReport};
 use crate::model_cache::{ModelAsset, ModelCache, ModelCacheError};
 use crate::net::NetClient;
-use crate::session::Session;
+use crate::session::{Protocol, Resend, Session, SessionState};
 use crate::state::ClientState;
 use qw_common::{
-    Bsp, BspCollision, BspError, BspRender, DataPathError, FsError, NetchanError, OobMessage,
-    Palette, PaletteError, QuakeFs, SizeBuf, SizeBufError, SvcMessage, UPDATE_BACKUP, UPDATE_MASK,
-    UserCmd, Wad, clc, find_game_dir, find_id1_dir, locate_data_dir, miptex_from_bytes,
-    parse_entities, worldspawn_wad_list,
+    Bsp, BspCollision, BspError, BspRender, DataPathError, FsError, NetchanError, NqControl,
+    NqError, OobMessage, Palette, PaletteError, QuakeFs, SERVERINFO_MAP_CHECKSUM, SizeBuf,
+    SizeBufError, SvcMessage, Transport, UPDATE_BACKUP, UPDATE_MASK, UdpTransport, UserCmd, Wad,
+    clc, find_game_dir, find_id1_dir, is_nq_control, locate_data_dir, miptex_from_bytes,
+    parse_entities, value_for_key, worldspawn_wad_list, write_nq_move,
 };
 use std::path::PathBuf;
 
@@ -22,13 +25,24 @@ pub enum RunnerError {
     Io(io::Error),
     Client(crate::client::ClientError),
     Netchan(NetchanError),
+    Nq(NqError),
     Buffer(SizeBufError),
     Fs(FsError),
     Bsp(BspError),
     Palette(PaletteError),
     Model(ModelCacheError),
     DataPath(DataPathError),
+    Demo(DemoError),
     MissingGameDir(String),
+    // A downloaded map that doesn't load; it's removed so the next
+    // connection fetches it again.
+    BadDownload(String),
+    // A downloaded world that loads but isn't the map the server runs.
+    MapMismatch(String),
+    // No answer to the handshake after every retry.
+    ConnectTimeout,
+    // The server went quiet for longer than cl_timeout.
+    ServerTimeout,
     NotConnected,
 }
 
@@ -50,12 +64,24 @@ impl From<NetchanError> for RunnerError {
     }
 }
 
+impl From<NqError> for RunnerError {
+    fn from(err: NqError) -> Self {
+        RunnerError::Nq(err)
+    }
+}
+
 impl From<SizeBufError> for RunnerError {
     fn from(err: SizeBufError) -> Self {
         RunnerError::Buffer(err)
     }
 }
 
+impl From<DemoError> for RunnerError {
+    fn from(err: DemoError) -> Self {
+        RunnerError::Demo(err)
+    }
+}
+
 impl From<FsError> for RunnerError {
     fn from(err: FsError) -> Self {
         RunnerError::Fs(err)
@@ -86,8 +112,8 @@ impl From<DataPathError> for RunnerError {
     }
 }
 
-pub struct ClientRunner {
-    pub net: NetClient,
+pub struct ClientRunner<T: Transport = UdpTransport> {
+    pub net: NetClient<T>,
     pub session: Session,
     pub client: Client,
     pub state: ClientState,
@@ -99,10 +125,15 @@ pub struct ClientRunner {
     download_seen: HashSet<String>,
     download: Option<DownloadState>,
     signon_phase: SignonPhase,
+    demo: Option<(DemoRecorder, PathBuf)>,
+    playback: Option<DemoPlayer>,
+    // cl_timeout, measured from the last packet in.
+    pub timeout: Duration,
+    last_received: Instant,
 }
 
-impl ClientRunner {
-    pub fn new(net: NetClient, session: Session) -> Self {
+impl<T: Transport> ClientRunner<T> {
+    pub fn new(net: NetClient<T>, session: Session) -> Self {
         let qport = session.qport;
         Self {
             net,
@@ -117,15 +148,171 @@ impl ClientRunner {
             download_seen: HashSet::new(),
             download: None,
             signon_phase: SignonPhase::Idle,
+            demo: None,
+            playback: None,
+            timeout: DEFAULT_TIMEOUT,
+            last_received: Instant::now(),
         }
     }
 
     pub fn start_connect(&mut self) -> Result<(), RunnerError> {
-        let packet = self.session.start();
-        self.net.send(&packet)?;
+        let packets = self.session.start();
+        self.send_handshake(packets)
+    }
+
+    fn send_handshake(&mut self, packets: Vec<Vec<u8>>) -> Result<(), RunnerError> {
+        for packet in packets {
+            self.net.send(packet)?;
+        }
+        Ok(())
+    }
+
+    // CL_Disconnect: the server is told, and a half-finished download, any
+    // recording and demo playback are closed off.
+    pub fn disconnect(&mut self) -> Result<(), RunnerError> {
+        if self.session.state == SessionState::Connected && self.playback.is_none() {
+            let _ = self.send_string_cmd("drop");
+        }
+        self.session.state = SessionState::Disconnected;
+        self.download = None;
+        self.download_queue.clear();
+        self.download_seen.clear();
+        self.signon_phase = SignonPhase::Idle;
+        self.playback = None;
+        self.stop_recording()?;
         Ok(())
     }
 
+    // CL_Reconnect_f: drops the connection and goes through the handshake
+    // again with the same userinfo. Not while a download is running, as
+    // the server is mid-transfer.
+    pub fn reconnect(&mut self) -> Result<bool, RunnerError> {
+        if self.download.is_some() || self.playback.is_some() {
+            return Ok(false);
+        }
+        self.disconnect()?;
+        self.restart_connection()?;
+        Ok(true)
+    }
+
+    // CL_Connect_f: drops whatever server this was, download and all, and
+    // starts the handshake with another.
+    pub fn connect_to(&mut self, server: SocketAddr) -> Result<bool, RunnerError> {
+        if self.playback.is_some() {
+            return Ok(false);
+        }
+        self.disconnect()?;
+        self.net.set_server(server);
+        self.restart_connection()?;
+        Ok(true)
+    }
+
+    fn restart_connection(&mut self) -> Result<(), RunnerError> {
+        let fs = std::mem::replace(&mut self.client.fs, QuakeFs::new());
+        self.client = Client::new(self.session.qport);
+        self.client.fs = fs;
+        self.state = ClientState::new();
+        self.net.reset_remote();
+        self.last_received = Instant::now();
+        self.start_connect()
+    }
+
+    // CL_CheckForResend while connecting; once connected, the server going
+    // quiet for longer than cl_timeout drops the connection.
+    fn check_connection(&mut self, now: Instant) -> Result<(), RunnerError> {
+        if self.session.state == SessionState::Connected {
+            if now.saturating_duration_since(self.last_received) > self.timeout {
+                self.disconnect()?;
+                return Err(RunnerError::ServerTimeout);
+            }
+            return Ok(());
+        }
+        match self.session.resend(now) {
+            Resend::Wait => Ok(()),
+            Resend::Send(packets) => self.send_handshake(packets),
+            Resend::TimedOut => Err(RunnerError::ConnectTimeout),
+        }
+    }
+
+    // The sequence the current entity frame is stored under.
+    pub fn incoming_sequence(&self) -> u32 {
+        match self.session.protocol {
+            Protocol::NetQuake => self.client.nq_sequence,
+            _ => self.client.netchan.incoming_sequence(),
+        }
+    }
+
+    // Starts a .qwd under the game directory, seeded with a signon built
+    // from the current state since the real one has already gone by.
+    pub fn record(&mut self, name: &str) -> Result<PathBuf, RunnerError> {
+        if self.session.protocol == Protocol::NetQuake || self.signon_phase != SignonPhase::Done {
+            return Err(RunnerError::NotConnected);
+        }
+        let Some(data) = &self.state.serverdata else {
+            return Err(RunnerError::NotConnected);
+        };
+        if !is_safe_download_path(name) {
+            return Err(DemoError::BadName(name.to_string()).into());
+        }
+        let mut path = self.download_root()?.join(&data.game_dir).join(name);
+        if path.extension().is_none() {
+            path.set_extension("qwd");
+        }
+        self.stop_recording()?;
+
+        let time = self.time_seconds() as f32;
+        let mut demo = DemoRecorder::create(&path)?;
+        demo.write_signon(time, &self.state)?;
+        demo.write_set(
+            time,
+            self.client.netchan.outgoing_sequence(),
+            self.client.netchan.incoming_sequence(),
+        )?;
+        self.demo = Some((demo, path.clone()));
+        Ok(path)
+    }
+
+    pub fn stop_recording(&mut self) -> Result<Option<PathBuf>, RunnerError> {
+        let Some((demo, path)) = self.demo.take() else {
+            return Ok(None);
+        };
+        demo.finish(self.time_seconds() as f32)?;
+        Ok(Some(path))
+    }
+
+    // Plays a demo in place of a server, dropping any server this was.
+    // Nothing is sent while it runs, and the session drops to disconnected
+    // when it ends.
+    pub fn play_demo(&mut self, player: DemoPlayer) -> Result<(), RunnerError> {
+        self.disconnect()?;
+        self.client = Client::new(self.session.qport);
+        self.state = ClientState::new();
+        self.signon_phase = SignonPhase::Idle;
+        self.session.protocol = Protocol::QuakeWorld;
+        self.session.state = crate::session::SessionState::Connected;
+        self.playback = Some(player);
+        Ok(())
+    }
+
+    pub fn is_playing_demo(&self) -> bool {
+        self.playback.is_some()
+    }
+
+    pub fn is_timedemo(&self) -> bool {
+        self.playback.as_ref().is_some_and(DemoPlayer::is_timedemo)
+    }
+
+    pub fn timedemo_report(&self) -> Option<TimeDemoReport> {
+        self.playback.as_ref().and_then(DemoPlayer::report)
+    }
+
+    // The file being downloaded and how far along it is, for the UI.
+    pub fn download_progress(&self) -> Option<(&str, u8)> {
+        self.download
+            .as_ref()
+            .map(|download| (download.name.as_str(), download.percent))
+    }
+
     pub fn model_assets(&self) -> &[Option<ModelAsset>] {
         self.model_cache.models()
     }
@@ -135,22 +322,85 @@ impl ClientRunner {
     }
 
     pub fn poll_once(&mut self, buf: &mut [u8]) -> Result<Option<ClientPacket>, RunnerError> {
-        let Some((size, _)) = self.net.recv(buf)? else {
+        if self.playback.is_some() {
+            return self.poll_demo();
+        }
+        if self.session.protocol == Protocol::NetQuake {
+            self.flush_nq()?;
+        }
+        let Some((packet, _)) = self.net.recv(buf)? else {
+            self.check_connection(Instant::now())?;
             return Ok(None);
         };
-        let packet = &buf[..size];
-        let parsed = self.client.handle_packet(packet)?;
+        self.last_received = Instant::now();
+        if self.session.protocol == Protocol::NetQuake
+            || (self.session.protocol == Protocol::Auto && is_nq_control(&packet))
+        {
+            return self.handle_nq_packet(&packet);
+        }
+        self.handle_qw_packet(&packet)
+    }
+
+    // Each poll is a frame: commands and sequence jumps are applied as they
+    // come, and the first due packet goes through the same path as one off
+    // the wire.
+    fn poll_demo(&mut self) -> Result<Option<ClientPacket>, RunnerError> {
+        let now = self.time_seconds();
+        if let Some(player) = &mut self.playback {
+            player.start_frame();
+        }
+        loop {
+            let Some(player) = &mut self.playback else {
+                return Ok(None);
+            };
+            let Some(record) = player.next_record(now)? else {
+                if player.is_finished() {
+                    self.session.state = crate::session::SessionState::Disconnected;
+                }
+                return Ok(None);
+            };
+            match record {
+                DemoRecord::Packet(packet) => return self.handle_qw_packet(&packet),
+                DemoRecord::Cmd { cmd, view_angles } => {
+                    let sequence = self.client.netchan.outgoing_sequence();
+                    self.state.store_outgoing_cmd(sequence, cmd);
+                    let index = (sequence as usize) & UPDATE_MASK;
+                    self.state.frames[index].senttime = now;
+                    self.state.view_angles = view_angles;
+                    let incoming = self.client.netchan.incoming_sequence();
+                    self.client
+                        .netchan
+                        .set_sequences(sequence.wrapping_add(1), incoming);
+                }
+                DemoRecord::Set { outgoing, incoming } => {
+                    self.client.netchan.set_sequences(outgoing, incoming);
+                }
+            }
+        }
+    }
+
+    fn handle_qw_packet(&mut self, packet: &[u8]) -> Result<Option<ClientPacket>, RunnerError> {
+        let parsed = match self.client.handle_packet(packet) {
+            // Stale and duplicated packets are dropped, as Netchan_Process does.
+            Err(ClientError::Netchan(NetchanError::OutOfOrder)) => return Ok(None),
+            parsed => parsed?,
+        };
+        if let ClientPacket::Messages(_) = &parsed
+            && let Some((demo, _)) = &mut self.demo
+        {
+            demo.write_packet(self.start_time.elapsed().as_secs_f32(), packet)?;
+        }
         match &parsed {
             ClientPacket::OutOfBand(msg) => {
                 if let Some(response) = self.session.handle_oob(msg) {
-                    self.net.send(&response)?;
+                    self.net.send(response)?;
                 }
                 if matches!(msg, OobMessage::Connection(_)) {
                     self.session.state = crate::session::SessionState::Connected;
                     self.send_string_cmd("new")?;
                 }
             }
-            ClientPacket::Messages(_) => {}
+            ClientPacket::Messages(_) | ClientPacket::Control(_) => {}
         }
 
         if let ClientPacket::Messages(messages) = &parsed {
@@ -177,7 +427,7 @@ impl ClientRunner {
                     self.state.mark_choked(*count, incoming_ack);
                 }
                 if matches!(message, SvcMessage::Disconnect) {
-                    self.session.state = crate::session::SessionState::Disconnected;
+                    self.disconnect()?;
                 }
                 self.state.apply_message(message, incoming_sequence);
                 self.handle_signon(message)?;
@@ -193,12 +443,26 @@ impl ClientRunner {
     }
 
     pub fn send_move(&mut self, cmd: UserCmd) -> Result<(), RunnerError> {
+        if self.playback.is_some() {
+            return Ok(());
+        }
         if self.session.state != crate::session::SessionState::Connected {
             return Err(RunnerError::NotConnected);
         }
+        if self.session.protocol == Protocol::NetQuake {
+            let mut buf = SizeBuf::new(32);
+            write_nq_move(&mut buf, self.state.server_time, &cmd)?;
+            let packet = self.client.nq.build_unreliable(buf.as_slice());
+            self.net.send(packet)?;
+            return Ok(());
+        }
 
         let sequence = self.client.netchan.outgoing_sequence();
         self.state.store_outgoing_cmd(sequence, cmd);
+        if let Some((demo, _)) = &mut self.demo {
+            let time = self.start_time.elapsed().as_secs_f32();
+            demo.write_cmd(time, &cmd, self.state.view_angles)?;
+        }
         let now = self.start_time.elapsed().as_secs_f64();
         let index = (sequence as usize) & UPDATE_MASK;
         self.state.frames[index].senttime = now;
@@ -209,7 +473,9 @@ impl ClientRunner {
             self.state.valid_sequence = 0;
         }
 
-        let delta_sequence = if self.state.valid_sequence != 0 {
+        // A demo can't replay deltas against frames from before it started,
+        // so ask for full updates while recording.
+        let delta_sequence = if self.state.valid_sequence != 0 && self.demo.is_none() {
             let delta = (self.state.valid_sequence & 0xff) as u8;
             self.state
                 .set_outgoing_delta_sequence(sequence, self.state.valid_sequence);
@@ -236,19 +502,120 @@ impl ClientRunner {
         clc::write_move_message(&mut buf, &message)?;
 
         let packet = self.client.netchan.build_packet(buf.as_slice(), true)?;
-        self.net.send(&packet)?;
+        self.net.send(packet)?;
         Ok(())
     }
 
     pub fn send_string_cmd(&mut self, text: &str) -> Result<(), RunnerError> {
+        if self.playback.is_some() {
+            return Ok(());
+        }
         if self.session.state != crate::session::SessionState::Connected {
             return Err(RunnerError::NotConnected);
         }
         let mut buf = SizeBuf::new(128);
         clc::write_string_cmd(&mut buf, text)?;
+        if self.session.protocol == Protocol::NetQuake {
+            self.client.nq.queue_reliable(buf.as_slice())?;
+            return self.flush_nq();
+        }
         self.client.netchan.queue_reliable(buf.as_slice())?;
         let packet = self.client.netchan.build_packet(&[], true)?;
-        self.net.send(&packet)?;
+        self.net.send(packet)?;
+        Ok(())
+    }
+
+    // Acks and reliable fragments the NetQuake channel has ready.
+    fn flush_nq(&mut self) -> Result<(), RunnerError> {
+        for packet in self.client.nq.transmit(Instant::now()) {
+            self.net.send(packet)?;
+        }
+        Ok(())
+    }
+
+    fn handle_nq_packet(&mut self, packet: &[u8]) -> Result<Option<ClientPacket>, RunnerError> {
+        let parsed = self.client.handle_nq_packet(packet)?;
+        match &parsed {
+            Some(ClientPacket::Control(control)) => {
+                if let Some(response) = self.session.handle_nq_control(control) {
+                    self.net.send(response)?;
+                }
+                if let NqControl::Accept { port } = control
+                    && *port != 0
+                {
+                    self.net.set_remote_port(*port);
+                }
+            }
+            Some(ClientPacket::Messages(messages)) => {
+                self.state.clear_frame_events();
+                let sequence = self.client.nq_sequence;
+                let now = self.start_time.elapsed().as_secs_f64();
+                let index = (sequence as usize) & UPDATE_MASK;
+                self.state.frames[index].receivedtime = now;
+                self.state.parsecount_time = Some(now);
+                for message in messages {
+                    if matches!(message, SvcMessage::Disconnect) {
+                        self.session.state = crate::session::SessionState::Disconnected;
+                    }
+                    self.state.apply_message(message, sequence);
+                    self.handle_nq_signon(message)?;
+                }
+                // There is no prediction; the view sits where the server put
+                // the player.
+                if let Some(data) = &self.state.serverdata
+                    && let Some(player) = self.state.frames[index]
+                        .playerstate
+                        .get(data.player_num as usize)
+                {
+                    self.state.sim_origin = player.origin;
+                    self.state.sim_velocity = player.velocity;
+                }
+                self.state.sim_angles = self.state.view_angles;
+            }
+            _ => {}
+        }
+        self.flush_nq()?;
+        Ok(parsed)
+    }
+
+    // NetQuake sends the whole precache list up front and then walks the
+    // client through signon stages 1-3 with svc_signonnum.
+    fn handle_nq_signon(&mut self, message: &SvcMessage) -> Result<(), RunnerError> {
+        match message {
+            SvcMessage::ServerData(_) => {
+                self.model_cache.clear();
+                self.signon_phase = SignonPhase::Idle;
+            }
+            SvcMessage::ModelList(_) => {
+                let Some(data) = self.state.serverdata.clone() else {
+                    return Ok(());
+                };
+                self.ensure_filesystem(&data)?;
+                self.load_models()?;
+                self.load_world(&data)?;
+            }
+            SvcMessage::SetView { entity } => {
+                if let Some(data) = self.state.serverdata.as_mut() {
+                    data.player_num = entity.saturating_sub(1) as u8;
+                }
+            }
+            SvcMessage::SignonNum(1) => self.send_string_cmd("prespawn")?,
+            SvcMessage::SignonNum(2) => {
+                let userinfo = &self.session.userinfo;
+                let name = value_for_key(userinfo, "name").unwrap_or_else(|| "player".into());
+                let top = value_for_key(userinfo, "topcolor").unwrap_or_else(|| "0".into());
+                let bottom = value_for_key(userinfo, "bottomcolor").unwrap_or_else(|| "0".into());
+                self.send_string_cmd(&format!("name \"{name}\""))?;
+                self.send_string_cmd(&format!("color {top} {bottom}"))?;
+                self.send_string_cmd("spawn")?;
+            }
+            SvcMessage::SignonNum(3) => {
+                self.signon_phase = SignonPhase::Done;
+                self.send_string_cmd("begin")?;
+            }
+            SvcMessage::StuffText(text) => self.handle_stufftext(text)?,
+            _ => {}
+        }
         Ok(())
     }
 
@@ -267,7 +634,7 @@ impl ClientRunner {
                     return Ok(());
                 };
                 if chunk.next != 0 {
-                    let cmd = format!("soundlist {} {}", data.server_count, chunk.next);
+                    let cmd = format!("soundlist {} {}", data.server_count, chunk.next - 1);
                     self.send_string_cmd(&cmd)?;
                 } else {
                     self.ensure_filesystem(&data)?;
@@ -288,7 +655,7 @@ impl ClientRunner {
                     return Ok(());
                 };
                 if chunk.next != 0 {
-                    let cmd = format!("modellist {} {}", data.server_count, chunk.next);
+                    let cmd = format!("modellist {} {}", data.server_count, chunk.next - 1);
                     self.send_string_cmd(&cmd)?;
                 } else {
                     self.ensure_filesystem(&data)?;
@@ -329,12 +696,17 @@ impl ClientRunner {
     }
 
     fn map_checksum2(&mut self, data: &qw_common::ServerData) -> Result<u32, RunnerError> {
+        let bsp = self.load_world(data)?;
+        let (_, checksum2) = bsp.map_checksums()?;
+        Ok(checksum2)
+    }
+
+    fn load_world(&mut self, data: &qw_common::ServerData) -> Result<Bsp, RunnerError> {
         self.ensure_filesystem(data)?;
         self.ensure_palette()?;
-        let map_name = map_path(&data.level_name);
+        let map_name = self.world_model_name(data);
         let bytes = self.client.fs.read(&map_name)?;
         let bsp = Bsp::from_bytes(bytes)?;
-        let (_, checksum2) = bsp.map_checksums()?;
         if self.state.render_world_map.as_deref() != Some(map_name.as_str()) {
             let mut render = BspRender::from_bsp(&bsp)?;
             self.load_wad_textures(&bsp, &mut render);
@@ -346,7 +718,16 @@ impl ClientRunner {
             self.state.collision = Some(collision);
             self.state.collision_map = Some(map_name.clone());
         }
-        Ok(checksum2)
+        Ok(bsp)
+    }
+
+    // The world is the first model; serverdata's level name is only a
+    // fallback, for servers that send the map name there.
+    fn world_model_name(&self, data: &qw_common::ServerData) -> String {
+        match self.state.models.get(1) {
+            Some(name) if name.ends_with(".bsp") => name.clone(),
+            _ => map_path(&data.level_name),
+        }
     }
 
     fn handle_stufftext(&mut self, text: &str) -> Result<(), RunnerError> {
@@ -362,8 +743,30 @@ impl ClientRunner {
                 }
                 continue;
             }
+            // The server is changing level; signon starts over with "new".
+            // A download in progress carries on, as CL_Changing_f has it.
+            if trimmed == "changing" {
+                if self.download.is_none() {
+                    self.signon_phase = SignonPhase::Idle;
+                    self.state.intermission = None;
+                }
+                continue;
+            }
+            // A NetQuake server sends the new serverinfo unasked.
+            if trimmed == "reconnect" {
+                if self.download.is_some() {
+                    continue;
+                }
+                if self.session.protocol == Protocol::NetQuake {
+                    self.signon_phase = SignonPhase::Idle;
+                } else {
+                    self.send_string_cmd("new")?;
+                }
+                continue;
+            }
             if let Some(rest) = trimmed.strip_prefix("fullserverinfo") {
-                let info = rest.trim_start();
+                // SV_New_f quotes it.
+                let info = rest.trim_start().trim_matches('"');
                 if !info.is_empty() {
                     self.state.serverinfo.set_raw(info);
                 }
@@ -554,6 +957,10 @@ impl ClientRunner {
         if self.download.is_some() {
             return Ok(true);
         }
+        // A demo can't fetch what it's missing; play on without it.
+        if self.playback.is_some() {
+            return Ok(false);
+        }
 
         let mut name = None;
         while let Some(candidate) = self.download_queue.pop_front() {
@@ -561,6 +968,9 @@ impl ClientRunner {
                 name = Some(candidate);
                 break;
             }
+            self.state
+                .prints
+                .push((2, format!("refusing to download {candidate}")));
         }
 
         let Some(name) = name else {
@@ -581,14 +991,26 @@ impl ClientRunner {
             .unwrap_or_else(|| "download.tmp".to_string());
         let temp_path = final_path.with_file_name(format!("{}.tmp", filename));
 
-        let file = File::create(&temp_path)?;
+        // What an earlier, interrupted attempt left is kept, and the server
+        // asked to carry on from its end.
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&temp_path)?;
+        let offset = file.metadata()?.len();
+        let cmd = if offset > 0 {
+            format!("download {} {}", name, offset)
+        } else {
+            format!("download {}", name)
+        };
         self.download = Some(DownloadState {
+            name,
             temp_path,
             final_path,
             file,
+            percent: 0,
+            resumed_from: offset,
         });
-
-        let cmd = format!("download {}", name);
         self.send_string_cmd(&cmd)?;
         Ok(true)
     }
@@ -610,8 +1032,15 @@ impl ClientRunner {
         }
 
         if size > 0 {
+            let resumed_from = std::mem::take(&mut state.resumed_from);
+            if resumed_from > 0 && restarted_from_zero(&state.temp_path, resumed_from, data)? {
+                // Stock servers ignore the offset and start over; appending
+                // their first chunk would corrupt the file.
+                state.file.set_len(0)?;
+            }
             state.file.write_all(data)?;
         }
+        state.percent = percent;
 
         if percent != 100 {
             self.send_string_cmd("nextdl")?;
@@ -619,10 +1048,14 @@ impl ClientRunner {
         }
 
         state.file.flush()?;
+        let name = state.name.clone();
         let temp_path = state.temp_path.clone();
         let final_path = state.final_path.clone();
         self.download = None;
         let _ = fs::rename(&temp_path, &final_path);
+        if name.ends_with(".bsp") {
+            self.verify_map(&name, &final_path)?;
+        }
 
         if self.start_next_download()? {
             return Ok(());
@@ -631,6 +1064,30 @@ impl ClientRunner {
         Ok(())
     }
 
+    // A map that arrived damaged would otherwise fail later at prespawn, when
+    // the server rejects its checksum, with no hint of the cause. The world is
+    // held to the checksum the server publishes in its serverinfo; stock
+    // servers don't, and then the map only has to load.
+    fn verify_map(&mut self, name: &str, path: &std::path::Path) -> Result<(), RunnerError> {
+        let checksum2 = fs::read(path)
+            .ok()
+            .and_then(|bytes| Bsp::from_bytes(bytes).ok())
+            .and_then(|bsp| bsp.map_checksums().ok())
+            .map(|(_, checksum2)| checksum2);
+        let Some(checksum2) = checksum2 else {
+            let _ = fs::remove_file(path);
+            return Err(RunnerError::BadDownload(name.to_string()));
+        };
+        let is_world = self.state.models.get(1).map(String::as_str) == Some(name);
+        let expected = value_for_key(self.state.serverinfo.as_str(), SERVERINFO_MAP_CHECKSUM)
+            .and_then(|value| value.parse::<u32>().ok());
+        if is_world && expected.is_some_and(|expected| expected != checksum2) {
+            let _ = fs::remove_file(path);
+            return Err(RunnerError::MapMismatch(name.to_string()));
+        }
+        Ok(())
+    }
+
     fn resume_signon(&mut self) -> Result<(), RunnerError> {
         let Some(data) = self.state.serverdata.clone() else {
             return Ok(());
@@ -665,7 +1122,7 @@ impl ClientRunner {
         Ok(())
     }
 
-    fn download_root(&self) -> Result<PathBuf, RunnerError> {
+    pub fn download_root(&self) -> Result<PathBuf, RunnerError> {
         if let Ok(value) = std::env::var("RUSTQUAKE_DOWNLOAD_DIR") {
             let trimmed = value.trim();
             if !trimmed.is_empty() {
@@ -679,7 +1136,7 @@ impl ClientRunner {
     }
 }
 
-fn is_safe_download_path(name: &str) -> bool {
+pub fn is_safe_download_path(name: &str) -> bool {
     if name.is_empty() || name.contains(':') || name.contains('\0') {
         return false;
     }
@@ -700,6 +1157,9 @@ fn is_safe_download_path(name: &str) -> bool {
     true
 }
 
+// cl_timeout's default.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 enum SignonPhase {
     Idle,
@@ -711,9 +1171,27 @@ enum SignonPhase {
 }
 
 struct DownloadState {
+    name: String,
     temp_path: PathBuf,
     final_path: PathBuf,
     file: File,
+    percent: u8,
+    // Bytes already on disk when the download was asked to resume; cleared
+    // once the first chunk shows whether the server honoured the offset.
+    resumed_from: u64,
+}
+
+// A server that ignored `download <name> <offset>` sends the file from its
+// start again, so its first chunk repeats what is already on disk.
+fn restarted_from_zero(
+    temp_path: &std::path::Path,
+    resumed_from: u64,
+    chunk: &[u8],
+) -> Result<bool, RunnerError> {
+    let len = chunk.len().min(resumed_from as usize);
+    let mut head = vec![0u8; len];
+    File::open(temp_path)?.read_exact(&mut head)?;
+    Ok(head == chunk[..len])
 }
 
 fn map_path(level_name: &str) -> String {
@@ -732,18 +1210,19 @@ fn map_path(level_name: &str) -> String {
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
     use crate::session::SessionState;
     use qw_common::{
-        Clc, MsgReader, Netchan, NetchanHeader, S2C_CHALLENGE, S2C_CONNECTION, ServerData, SizeBuf,
-        SvcMessage, UserCmd, Vec3, build_out_of_band, out_of_band_payload,
+        Clc, MsgReader, NetSimConfig, Netchan, NetchanHeader, S2C_CHALLENGE, S2C_CONNECTION,
+        ServerData, SizeBuf, SvcMessage, UserCmd, Vec3, build_out_of_band, out_of_band_payload,
     };
     use std::net::UdpSocket;
     use std::sync::Mutex;
     use std::time::Duration;
 
-    static ENV_LOCK: Mutex<()> = Mutex::new(());
+    // Shared with other modules' tests that point the download directory.
+    pub(crate) static ENV_LOCK: Mutex<()> = Mutex::new(());
 
     #[test]
     fn handshake_round_trip() {
@@ -1254,6 +1733,716 @@ mod tests {
         );
     }
 
+    #[test]
+    fn reconnect_stufftext_restarts_signon() {
+        let (client_end, server_end) = qw_common::loopback_pair();
+        let mut session = Session::new(27001, "\\name\\player");
+        session.state = SessionState::Connected;
+        let mut runner = ClientRunner::new(NetClient::loopback(client_end), session);
+
+        let mut buf = SizeBuf::new(128);
+        for text in ["changing\n", "reconnect\n"] {
+            qw_common::write_svc_message(&mut buf, &SvcMessage::StuffText(text.to_string()))
+                .unwrap();
+        }
+        let mut server_chan = Netchan::new(27001);
+        let packet = server_chan.build_packet(buf.as_slice(), false).unwrap();
+        server_end.send(packet).unwrap();
+
+        let mut client_buf = [0u8; 256];
+        assert!(runner.poll_once(&mut client_buf).unwrap().is_some());
+
+        let packet = server_end.recv().unwrap().expect("client replied");
+        let mut recv_chan = Netchan::new(27001);
+        let payload = recv_chan.process_packet(&packet, true).unwrap();
+        let mut reader = MsgReader::new(payload);
+        assert_eq!(reader.read_u8().unwrap(), Clc::StringCmd as u8);
+        assert_eq!(reader.read_string().unwrap(), "new");
+    }
+
+    #[test]
+    fn reconnect_keeps_userinfo() {
+        let (client_end, mut server_end) = qw_common::loopback_pair();
+        let mut session = Session::new(27001, "\\name\\player");
+        session.state = SessionState::Connected;
+        let mut runner = ClientRunner::new(NetClient::loopback(client_end), session);
+        let mut chan = Netchan::new(27001);
+        runner.session.userinfo = "\\name\\renamed".to_string();
+
+        assert!(runner.reconnect().unwrap());
+        assert_eq!(runner.session.state, SessionState::ChallengeSent);
+        let drop = server_end.recv().unwrap().expect("client dropped");
+        let payload = chan.process_packet(&drop, true).unwrap();
+        let mut reader = MsgReader::new(payload);
+        assert_eq!(reader.read_u8().unwrap(), Clc::StringCmd as u8);
+        assert_eq!(reader.read_string().unwrap(), "drop");
+        let packet = server_end.recv().unwrap().expect("client asked again");
+        assert_eq!(out_of_band_payload(&packet).unwrap(), b"getchallenge\n");
+
+        let challenge = build_out_of_band(&[S2C_CHALLENGE, b'7', 0]);
+        server_end
+            .send_to(challenge, qw_common::LOOPBACK_ADDR)
+            .unwrap();
+        let mut client_buf = [0u8; 256];
+        runner.poll_once(&mut client_buf).unwrap();
+        let packet = server_end.recv().unwrap().expect("client connected");
+        let text = String::from_utf8_lossy(out_of_band_payload(&packet).unwrap()).to_string();
+        assert!(text.starts_with("connect"));
+        assert!(text.contains("\\name\\renamed"));
+    }
+
+    #[test]
+    fn times_out_a_silent_server() {
+        let (client_end, server_end) = qw_common::loopback_pair();
+        let mut session = Session::new(27001, "\\name\\player");
+        session.state = SessionState::Connected;
+        let mut runner = ClientRunner::new(NetClient::loopback(client_end), session);
+        runner.timeout = Duration::ZERO;
+        std::thread::sleep(Duration::from_millis(5));
+
+        let mut client_buf = [0u8; 256];
+        assert!(matches!(
+            runner.poll_once(&mut client_buf),
+            Err(RunnerError::ServerTimeout)
+        ));
+        assert_eq!(runner.session.state, SessionState::Disconnected);
+        assert!(server_end.recv().unwrap().is_some());
+    }
+
+    // Drives the client until `done` holds, with the server on its own thread.
+    fn pump<T: Transport>(
+        runner: &mut ClientRunner<T>,
+        mut done: impl FnMut(&mut ClientRunner<T>) -> bool,
+    ) -> bool {
+        let mut buf = [0u8; 8192];
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while Instant::now() < deadline {
+            while runner.poll_once(&mut buf).unwrap().is_some() {}
+            if done(runner) {
+                return true;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        false
+    }
+
+    // A game directory holding just enough for the server to spawn a level:
+    // progs with the NetQuake system defs and an empty map.
+    fn write_test_game(data_dir: &std::path::Path) {
+        use qw_qc::{Definition, PROG_VERSION, ProgsDat, ProgsFlavor, QcType};
+        let size = |ty| if ty == QcType::Vector { 3 } else { 1 };
+        let flavor = ProgsFlavor::NetQuake;
+        let mut global_defs = Vec::new();
+        let mut globals = 28;
+        for (name, ty) in flavor.system_globals() {
+            global_defs.push(Definition {
+                ty,
+                offset: globals as i16,
+                name: name.to_string(),
+                save_global: false,
+            });
+            globals += size(ty);
+        }
+        let mut field_defs = Vec::new();
+        let mut fields = 0;
+        for (name, ty) in flavor.system_fields() {
+            field_defs.push(Definition {
+                ty,
+                offset: fields as i16,
+                name: name.to_string(),
+                save_global: false,
+            });
+            fields += size(ty);
+        }
+        let progs = ProgsDat {
+            version: PROG_VERSION,
+            crc: flavor.crc(),
+            statements: Vec::new(),
+            global_defs,
+            field_defs,
+            functions: Vec::new(),
+            strings: vec![0],
+            globals: vec![0; globals],
+            entity_fields: fields as i32,
+        };
+        let game_dir = data_dir.join("id1");
+        std::fs::create_dir_all(game_dir.join("maps")).unwrap();
+        std::fs::write(game_dir.join("progs.dat"), progs.to_bytes()).unwrap();
+        std::fs::write(game_dir.join("maps/test.bsp"), build_test_bsp()).unwrap();
+    }
+
+    #[test]
+    fn connects_and_plays_against_an_in_process_server() {
+        play_in_process(NetSimConfig::default());
+    }
+
+    #[test]
+    fn plays_over_a_lossy_link() {
+        play_in_process(NetSimConfig {
+            latency: Duration::from_millis(20),
+            jitter: Duration::from_millis(10),
+            loss: 10.0,
+            reorder: 5.0,
+            duplicate: 5.0,
+            seed: 3,
+            ..NetSimConfig::default()
+        });
+    }
+
+    fn play_in_process(netsim: NetSimConfig) {
+        let _guard = ENV_LOCK.lock().unwrap();
+        let data_dir = temp_dir();
+        write_test_game(&data_dir);
+        let old_data = std::env::var("RUSTQUAKE_DATA_DIR").ok();
+        // Safety: env var mutation is process-global; guard with ENV_LOCK.
+        unsafe {
+            std::env::set_var("RUSTQUAKE_DATA_DIR", &data_dir);
+        }
+
+        let (client_end, server_end) = qw_common::loopback_pair();
+        let server =
+            qw_server::spawn_local("test", qw_server::GameSettings::default(), server_end).unwrap();
+        let session = Session::new(27001, "\\name\\player");
+        let net = NetClient::loopback(client_end);
+        let net = if netsim.is_active() {
+            net.simulated(netsim).boxed()
+        } else {
+            net.boxed()
+        };
+        let mut runner = ClientRunner::new(net, session);
+        runner.start_connect().unwrap();
+
+        // Moves go out from the moment the connection is up, as in the main
+        // loop, and carry the acks for the server's reliable signon data.
+        let signed_on = pump(&mut runner, |runner| {
+            if runner.session.state == SessionState::Connected {
+                runner.send_move(UserCmd::default()).unwrap();
+            }
+            runner.signon_phase == SignonPhase::Done
+        });
+        assert!(signed_on, "signon did not finish");
+        assert_eq!(runner.session.state, SessionState::Connected);
+        let serverdata = runner.state.serverdata.clone().unwrap();
+        assert_eq!(serverdata.level_name, "test");
+        assert_eq!(
+            runner.state.models.get(1).map(String::as_str),
+            Some("maps/test.bsp")
+        );
+
+        // The server moves the player and reports it back in its frames.
+        let cmd = UserCmd {
+            msec: 50,
+            forwardmove: 200,
+            ..UserCmd::default()
+        };
+        let moved = pump(&mut runner, |runner| {
+            runner.send_move(cmd).unwrap();
+            runner
+                .state
+                .players
+                .first()
+                .is_some_and(|player| player.origin.x > 0.0)
+        });
+        assert!(moved, "player never moved");
+
+        drop(runner);
+        server.shutdown().unwrap();
+        unsafe {
+            match old_data {
+                Some(value) => std::env::set_var("RUSTQUAKE_DATA_DIR", value),
+                None => std::env::remove_var("RUSTQUAKE_DATA_DIR"),
+            }
+        }
+        std::fs::remove_dir_all(data_dir).ok();
+    }
+
+    #[test]
+    fn records_and_plays_back_a_demo() {
+        let _guard = ENV_LOCK.lock().unwrap();
+        let data_dir = temp_dir();
+        write_test_game(&data_dir);
+        let download_dir = temp_dir();
+        let old_data = std::env::var("RUSTQUAKE_DATA_DIR").ok();
+        let old_download = std::env::var("RUSTQUAKE_DOWNLOAD_DIR").ok();
+        // Safety: env var mutation is process-global; guard with ENV_LOCK.
+        unsafe {
+            std::env::set_var("RUSTQUAKE_DATA_DIR", &data_dir);
+            std::env::set_var("RUSTQUAKE_DOWNLOAD_DIR", &download_dir);
+        }
+
+        let (client_end, server_end) = qw_common::loopback_pair();
+        let server =
+            qw_server::spawn_local("test", qw_server::GameSettings::default(), server_end).unwrap();
+        let session = Session::new(27001, "\\name\\player");
+        let mut runner = ClientRunner::new(NetClient::loopback(client_end), session);
+        assert!(matches!(
+            runner.record("early"),
+            Err(RunnerError::NotConnected)
+        ));
+        runner.start_connect().unwrap();
+        let signed_on = pump(&mut runner, |runner| {
+            if runner.session.state == SessionState::Connected {
+                runner.send_move(UserCmd::default()).unwrap();
+            }
+            runner.signon_phase == SignonPhase::Done
+        });
+        assert!(signed_on, "signon did not finish");
+
+        let path = runner.record("test1").unwrap();
+        assert_eq!(path, download_dir.join("id1").join("test1.qwd"));
+        let incoming = runner.client.netchan.incoming_sequence();
+        let mut moves = 0;
+        pump(&mut runner, |runner| {
+            runner.send_move(UserCmd::default()).unwrap();
+            moves += 1;
+            moves > 10 && runner.client.netchan.incoming_sequence() > incoming + 2
+        });
+        assert_eq!(runner.stop_recording().unwrap(), Some(path.clone()));
+        assert_eq!(runner.stop_recording().unwrap(), None);
+
+        let data = std::fs::read(&path).unwrap();
+        let mut records = Vec::new();
+        let mut pos = 0;
+        while pos < data.len() {
+            let kind = data[pos + 4];
+            pos += 5;
+            let len = match kind {
+                0 => 36,
+                2 => 8,
+                _ => {
+                    let len = i32::from_le_bytes(data[pos..pos + 4].try_into().unwrap());
+                    pos += 4;
+                    len as usize
+                }
+            };
+            records.push((kind, data[pos..pos + len].to_vec()));
+            pos += len;
+        }
+        assert_eq!(pos, data.len());
+
+        // Signon first, then the sequences to continue from, then the game.
+        assert_eq!(records[0].0, 1);
+        assert_eq!(records[0].1[8], qw_common::Svc::ServerData as u8);
+        let set = records.iter().position(|(kind, _)| *kind == 2).unwrap();
+        let set_incoming = u32::from_le_bytes(records[set].1[4..8].try_into().unwrap());
+        assert_eq!(set_incoming, incoming);
+        let live: Vec<&Vec<u8>> = records[set + 1..]
+            .iter()
+            .filter(|(kind, _)| *kind == 1)
+            .map(|(_, packet)| packet)
+            .collect();
+        assert!(live.len() > 2);
+        for packet in &live[..live.len() - 1] {
+            let sequence = u32::from_le_bytes(packet[..4].try_into().unwrap()) & 0x7fff_ffff;
+            assert!(sequence > set_incoming);
+        }
+        assert_eq!(live.last().unwrap()[..4], [0xff; 4]);
+        assert!(records.iter().any(|(kind, _)| *kind == 0));
+        let origin = runner.state.players[0].origin;
+
+        // timedemo: every packet but the end marker is one frame.
+        let (client_end, _) = qw_common::loopback_pair();
+        let mut player = ClientRunner::new(NetClient::loopback(client_end), Session::new(0, ""));
+        player
+            .play_demo(DemoPlayer::open(&path.with_extension(""), true).unwrap())
+            .unwrap();
+        let mut buf = [0u8; 8192];
+        let mut frames = 0;
+        while player.session.state != SessionState::Disconnected {
+            player.poll_once(&mut buf).unwrap();
+            frames += 1;
+            assert!(frames < 10_000, "demo never ended");
+        }
+        assert_eq!(player.signon_phase, SignonPhase::Done);
+        assert_eq!(
+            player.state.models.get(1).map(String::as_str),
+            Some("maps/test.bsp")
+        );
+        assert_eq!(player.state.players[0].origin, origin);
+        let packets = records.iter().filter(|(kind, _)| *kind == 1).count();
+        assert_eq!(
+            player.timedemo_report().unwrap().frames as usize,
+            packets - 1
+        );
+
+        drop(runner);
+        server.shutdown().unwrap();
+        unsafe {
+            match old_data {
+                Some(value) => std::env::set_var("RUSTQUAKE_DATA_DIR", value),
+                None => std::env::remove_var("RUSTQUAKE_DATA_DIR"),
+            }
+            match old_download {
+                Some(value) => std::env::set_var("RUSTQUAKE_DOWNLOAD_DIR", value),
+                None => std::env::remove_var("RUSTQUAKE_DOWNLOAD_DIR"),
+            }
+        }
+        std::fs::remove_dir_all(data_dir).ok();
+        std::fs::remove_dir_all(download_dir).ok();
+    }
+
+    // Plays the server by hand: sends `message`, lets the client react and
+    // returns the commands it sent back.
+    fn exchange(
+        runner: &mut ClientRunner<qw_common::LoopbackSocket>,
+        server: &mut qw_common::LoopbackSocket,
+        chan: &mut Netchan,
+        message: &SvcMessage,
+    ) -> Result<Vec<String>, RunnerError> {
+        let mut buf = SizeBuf::new(qw_common::MAX_MSGLEN);
+        qw_common::write_svc_message(&mut buf, message).unwrap();
+        let packet = chan.build_packet(buf.as_slice(), false).unwrap();
+        server.send_to(packet, qw_common::LOOPBACK_ADDR).unwrap();
+        let mut client_buf = [0u8; 8192];
+        while runner.poll_once(&mut client_buf)?.is_some() {}
+
+        let mut cmds = Vec::new();
+        let mut recv_buf = [0u8; 8192];
+        while let Some((packet, _)) = server.recv_from(&mut recv_buf, Duration::ZERO).unwrap() {
+            let payload = chan.process_packet(&packet, true).unwrap().to_vec();
+            let mut reader = MsgReader::new(&payload);
+            while reader.read_u8().ok() == Some(Clc::StringCmd as u8) {
+                cmds.push(reader.read_string().unwrap());
+            }
+        }
+        Ok(cmds)
+    }
+
+    #[test]
+    fn downloads_a_missing_map_resuming_a_partial_file() {
+        let _guard = ENV_LOCK.lock().unwrap();
+        let data_dir = temp_dir();
+        write_test_game(&data_dir);
+        let download_dir = temp_dir();
+        let old_data = std::env::var("RUSTQUAKE_DATA_DIR").ok();
+        let old_download = std::env::var("RUSTQUAKE_DOWNLOAD_DIR").ok();
+        // Safety: env var mutation is process-global; guard with ENV_LOCK.
+        unsafe {
+            std::env::set_var("RUSTQUAKE_DATA_DIR", &data_dir);
+            std::env::set_var("RUSTQUAKE_DOWNLOAD_DIR", &download_dir);
+        }
+
+        let map = build_test_bsp();
+        let (_, checksum2) = qw_common::Bsp::from_bytes(map.clone())
+            .unwrap()
+            .map_checksums()
+            .unwrap();
+        let maps_dir = download_dir.join("id1").join("maps");
+        std::fs::create_dir_all(&maps_dir).unwrap();
+        std::fs::write(maps_dir.join("custom.bsp.tmp"), &map[..40]).unwrap();
+
+        let (client_end, mut server) = qw_common::loopback_pair();
+        let mut session = Session::new(27001, "\\name\\player");
+        session.state = SessionState::Connected;
+        let mut runner = ClientRunner::new(NetClient::loopback(client_end), session);
+        let mut chan = Netchan::new(27001);
+        let data = ServerData {
+            protocol: qw_common::PROTOCOL_VERSION,
+            server_count: 3,
+            game_dir: "id1".to_string(),
+            player_num: 0,
+            spectator: false,
+            level_name: "A Custom Level".to_string(),
+            movevars: qw_common::MoveVars {
+                gravity: 800.0,
+                stopspeed: 100.0,
+                maxspeed: 320.0,
+                spectatormaxspeed: 500.0,
+                accelerate: 10.0,
+                airaccelerate: 12.0,
+                wateraccelerate: 8.0,
+                friction: 4.0,
+                waterfriction: 2.0,
+                entgravity: 1.0,
+            },
+        };
+        let mut exchange = |runner: &mut ClientRunner<_>, message| {
+            exchange(runner, &mut server, &mut chan, &message)
+        };
+
+        let cmds = exchange(&mut runner, SvcMessage::ServerData(data)).unwrap();
+        assert_eq!(cmds, ["soundlist 3 0"]);
+        let info = format!("fullserverinfo \"\\*map_checksum2\\{checksum2}\"\n");
+        exchange(&mut runner, SvcMessage::StuffText(info)).unwrap();
+        assert_eq!(
+            value_for_key(runner.state.serverinfo.as_str(), "*map_checksum2"),
+            Some(checksum2.to_string())
+        );
+        let empty = qw_common::StringListChunk {
+            start: 0,
+            items: Vec::new(),
+            next: 0,
+        };
+        let cmds = exchange(&mut runner, SvcMessage::SoundList(empty)).unwrap();
+        assert_eq!(cmds, ["modellist 3 0"]);
+        let models = qw_common::StringListChunk {
+            start: 1,
+            items: vec!["maps/custom.bsp".to_string(), "../escape.mdl".to_string()],
+            next: 0,
+        };
+        let cmds = exchange(&mut runner, SvcMessage::ModelList(models)).unwrap();
+        assert_eq!(cmds, ["download maps/custom.bsp 40"]);
+        assert_eq!(runner.download_progress(), Some(("maps/custom.bsp", 0)));
+
+        let half = 40 + (map.len() - 40) / 2;
+        let first = SvcMessage::Download {
+            size: (half - 40) as i16,
+            percent: 50,
+            data: map[40..half].to_vec(),
+        };
+        assert_eq!(exchange(&mut runner, first).unwrap(), ["nextdl"]);
+        assert_eq!(runner.download_progress(), Some(("maps/custom.bsp", 50)));
+        let rest = SvcMessage::Download {
+            size: (map.len() - half) as i16,
+            percent: 100,
+            data: map[half..].to_vec(),
+        };
+        let cmds = exchange(&mut runner, rest).unwrap();
+        assert_eq!(cmds, [format!("prespawn 3 0 {checksum2}")]);
+        assert_eq!(runner.download_progress(), None);
+        assert_eq!(std::fs::read(maps_dir.join("custom.bsp")).unwrap(), map);
+        assert!(!maps_dir.join("custom.bsp.tmp").exists());
+        assert!(!download_dir.join("escape.mdl").exists());
+        assert!(
+            runner
+                .state
+                .prints
+                .iter()
+                .any(|(_, text)| text.contains("refusing to download ../escape.mdl"))
+        );
+
+        // A map that arrives damaged is thrown away rather than kept.
+        runner.signon_phase = SignonPhase::ModelList;
+        runner
+            .download_queue
+            .push_back("maps/broken.bsp".to_string());
+        assert!(runner.start_next_download().unwrap());
+        let broken = SvcMessage::Download {
+            size: 4,
+            percent: 100,
+            data: b"junk".to_vec(),
+        };
+        assert!(matches!(
+            exchange(&mut runner, broken),
+            Err(RunnerError::BadDownload(name)) if name == "maps/broken.bsp"
+        ));
+        assert!(!maps_dir.join("broken.bsp").exists());
+
+        // A server that ignores the offset starts over from byte zero, and
+        // the partial file is replaced rather than appended to.
+        std::fs::write(maps_dir.join("again.bsp.tmp"), &map[..40]).unwrap();
+        runner.signon_phase = SignonPhase::ModelList;
+        runner
+            .download_queue
+            .push_back("maps/again.bsp".to_string());
+        assert!(runner.start_next_download().unwrap());
+        let first = SvcMessage::Download {
+            size: half as i16,
+            percent: 50,
+            data: map[..half].to_vec(),
+        };
+        let cmds = exchange(&mut runner, first).unwrap();
+        assert!(cmds.iter().any(|cmd| cmd == "download maps/again.bsp 40"));
+        let rest = SvcMessage::Download {
+            size: (map.len() - half) as i16,
+            percent: 100,
+            data: map[half..].to_vec(),
+        };
+        exchange(&mut runner, rest).unwrap();
+        assert_eq!(std::fs::read(maps_dir.join("again.bsp")).unwrap(), map);
+
+        // A world that loads but isn't the server's map is thrown away too.
+        std::fs::remove_file(maps_dir.join("custom.bsp")).unwrap();
+        runner
+            .state
+            .serverinfo
+            .set_star("*map_checksum2", &checksum2.wrapping_add(1).to_string())
+            .unwrap();
+        runner.signon_phase = SignonPhase::ModelList;
+        runner
+            .download_queue
+            .push_back("maps/custom.bsp".to_string());
+        assert!(runner.start_next_download().unwrap());
+        let whole = SvcMessage::Download {
+            size: map.len() as i16,
+            percent: 100,
+            data: map.clone(),
+        };
+        assert!(matches!(
+            exchange(&mut runner, whole),
+            Err(RunnerError::MapMismatch(name)) if name == "maps/custom.bsp"
+        ));
+        assert!(!maps_dir.join("custom.bsp").exists());
+
+        // Safety: env var mutation is process-global; guard with ENV_LOCK.
+        unsafe {
+            match old_data {
+                Some(value) => std::env::set_var("RUSTQUAKE_DATA_DIR", value),
+                None => std::env::remove_var("RUSTQUAKE_DATA_DIR"),
+            }
+            match old_download {
+                Some(value) => std::env::set_var("RUSTQUAKE_DOWNLOAD_DIR", value),
+                None => std::env::remove_var("RUSTQUAKE_DOWNLOAD_DIR"),
+            }
+        }
+        std::fs::remove_dir_all(data_dir).ok();
+        std::fs::remove_dir_all(download_dir).ok();
+    }
+
+    fn nq_control(code: u8, body: &[u8]) -> Vec<u8> {
+        let len = (5 + body.len()) as u32;
+        let mut packet = (0x8000_0000 | len).to_be_bytes().to_vec();
+        packet.push(code);
+        packet.extend_from_slice(body);
+        packet
+    }
+
+    // A scripted NetQuake server: it answers the probe and connect, walks
+    // the client through signon, then streams fast updates.
+    #[test]
+    fn detects_and_plays_against_a_netquake_server() {
+        use qw_common::{NqChannel, NqMessage};
+        const SVC_SETVIEW: u8 = 5;
+        const SVC_SETANGLE: u8 = 10;
+        const SVC_SERVERINFO: u8 = 11;
+        const SVC_SPAWNBASELINE: u8 = 22;
+        const SVC_SIGNONNUM: u8 = 25;
+
+        let _guard = ENV_LOCK.lock().unwrap();
+        let data_dir = temp_dir();
+        write_test_game(&data_dir);
+        let old_data = std::env::var("RUSTQUAKE_DATA_DIR").ok();
+        // Safety: env var mutation is process-global; guard with ENV_LOCK.
+        unsafe {
+            std::env::set_var("RUSTQUAKE_DATA_DIR", &data_dir);
+        }
+
+        let (client_end, server) = qw_common::loopback_pair();
+        let session = Session::new(27001, "\\name\\nqplayer").with_protocol(Protocol::Auto);
+        let mut runner = ClientRunner::new(NetClient::loopback(client_end), session);
+        runner.start_connect().unwrap();
+
+        let mut chan = NqChannel::new();
+        let mut commands = Vec::new();
+        let mut moves = 0;
+        let mut begun = false;
+        let played = pump(&mut runner, |runner| {
+            while let Some(packet) = server.recv().unwrap() {
+                if qw_common::is_out_of_band(&packet) {
+                    continue;
+                }
+                let mut reply = SizeBuf::new(1024);
+                match chan.process_packet(&packet).unwrap() {
+                    Some(NqMessage::Control(qw_common::NqControl::Unknown(0x02))) => {
+                        let mut body = b"loop\0nqhost\0test\0".to_vec();
+                        body.extend_from_slice(&[0, 4, 3]);
+                        server.send(nq_control(0x83, &body)).unwrap();
+                    }
+                    Some(NqMessage::Control(qw_common::NqControl::Unknown(0x01))) => {
+                        server
+                            .send(nq_control(0x81, &26001i32.to_le_bytes()))
+                            .unwrap();
+                        reply.write_u8(SVC_SERVERINFO).unwrap();
+                        reply.write_i32(15).unwrap();
+                        reply.write_bytes(&[4, 0]).unwrap();
+                        for text in ["Test Map", "maps/test.bsp", "", ""] {
+                            reply.write_string(Some(text)).unwrap();
+                        }
+                        reply.write_u8(SVC_SIGNONNUM).unwrap();
+                        reply.write_u8(1).unwrap();
+                    }
+                    Some(NqMessage::Reliable(data)) => {
+                        let mut reader = MsgReader::new(&data);
+                        while reader.remaining() > 0 {
+                            assert_eq!(reader.read_u8().unwrap(), Clc::StringCmd as u8);
+                            let command = reader.read_string().unwrap();
+                            match command.as_str() {
+                                "prespawn" => {
+                                    reply.write_u8(SVC_SPAWNBASELINE).unwrap();
+                                    reply.write_u16(2).unwrap();
+                                    reply.write_bytes(&[1, 0, 0, 0]).unwrap();
+                                    for _ in 0..3 {
+                                        reply.write_coord(8.0).unwrap();
+                                        reply.write_angle(0.0).unwrap();
+                                    }
+                                    reply.write_u8(SVC_SIGNONNUM).unwrap();
+                                    reply.write_u8(2).unwrap();
+                                }
+                                "spawn" => {
+                                    reply.write_u8(SVC_SETVIEW).unwrap();
+                                    reply.write_u16(1).unwrap();
+                                    reply.write_u8(SVC_SETANGLE).unwrap();
+                                    for angle in [0.0, 90.0, 0.0] {
+                                        reply.write_angle(angle).unwrap();
+                                    }
+                                    reply.write_u8(SVC_SIGNONNUM).unwrap();
+                                    reply.write_u8(3).unwrap();
+                                }
+                                "begin" => begun = true,
+                                _ => {}
+                            }
+                            commands.push(command);
+                        }
+                    }
+                    Some(NqMessage::Unreliable(data)) => {
+                        assert_eq!(data[0], Clc::Move as u8);
+                        moves += 1;
+                    }
+                    _ => {}
+                }
+                if !reply.as_slice().is_empty() {
+                    chan.queue_reliable(reply.as_slice()).unwrap();
+                }
+            }
+            if begun {
+                // The player at x=64 and entity 2 moved up from its baseline.
+                let mut update = SizeBuf::new(64);
+                update.write_bytes(&[0x80 | 0x02, 1]).unwrap();
+                update.write_coord(64.0).unwrap();
+                update.write_bytes(&[0x80 | 0x08, 2]).unwrap();
+                update.write_coord(40.0).unwrap();
+                server
+                    .send(chan.build_unreliable(update.as_slice()))
+                    .unwrap();
+            }
+            for packet in chan.transmit(Instant::now()) {
+                server.send(packet).unwrap();
+            }
+            if runner.session.state == SessionState::Connected {
+                runner.send_move(UserCmd::default()).unwrap();
+            }
+            runner.signon_phase == SignonPhase::Done && runner.state.sim_origin.x == 64.0
+        });
+        assert!(played, "never saw the player's fast update");
+        assert_eq!(runner.session.protocol, Protocol::NetQuake);
+        assert_eq!(
+            commands[..5],
+            [
+                "prespawn",
+                "name \"nqplayer\"",
+                "color 0 0",
+                "spawn",
+                "begin"
+            ]
+        );
+        assert!(moves > 0);
+        assert_eq!(runner.state.view_angles, Vec3::new(0.0, 90.0, 0.0));
+        assert!(runner.state.render_world.is_some());
+        let frame = &runner.state.frames[(runner.incoming_sequence() as usize) & UPDATE_MASK];
+        let entity = &frame.packet_entities.entities[0];
+        assert_eq!(entity.number, 2);
+        assert_eq!(entity.origin, Vec3::new(8.0, 8.0, 40.0));
+
+        drop(runner);
+        unsafe {
+            match old_data {
+                Some(value) => std::env::set_var("RUSTQUAKE_DATA_DIR", value),
+                None => std::env::remove_var("RUSTQUAKE_DATA_DIR"),
+            }
+        }
+        std::fs::remove_dir_all(data_dir).ok();
+    }
+
     fn recv_payload(server: &UdpSocket, chan: &mut Netchan) -> Vec<u8> {
         let mut buf = [0u8; 512];
         for _ in 0..5 {
@@ -1276,7 +2465,7 @@ mod tests {
         panic!("no payload received");
     }
 
-    fn temp_dir() -> std::path::PathBuf {
+    pub(crate) fn temp_dir() -> std::path::PathBuf {
         let nanos = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
             .unwrap()
@@ -1,8 +1,25 @@
-use qw_common::OobMessage;
+use std::time::{Duration, Instant};
+
+use qw_common::{NqControl, OobMessage, build_nq_connect, build_nq_server_info_request};
 
 use crate::handshake::{ConnectRequest, build_connect, build_getchallenge, parse_challenge};
 use qw_common::InfoString;
 
+// CL_CheckForResend waits five seconds between tries; this starts quicker
+// and backs off to that.
+const RESEND_DELAY: Duration = Duration::from_secs(1);
+const MAX_RESEND_DELAY: Duration = Duration::from_secs(5);
+const CONNECT_ATTEMPTS: u32 = 5;
+
+// Auto asks both ways at once and settles on whichever kind of server
+// answers first.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Protocol {
+    QuakeWorld,
+    NetQuake,
+    Auto,
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum SessionState {
     Disconnected,
@@ -11,12 +28,22 @@ pub enum SessionState {
     Connected,
 }
 
+#[derive(Debug, PartialEq, Eq)]
+pub enum Resend {
+    Wait,
+    Send(Vec<Vec<u8>>),
+    TimedOut,
+}
+
 #[derive(Debug, Clone)]
 pub struct Session {
     pub state: SessionState,
     pub qport: u16,
     pub userinfo: String,
     pub challenge: Option<i32>,
+    pub protocol: Protocol,
+    attempts: u32,
+    next_resend: Option<Instant>,
 }
 
 impl Session {
@@ -26,6 +53,9 @@ impl Session {
             qport,
             userinfo: userinfo.into(),
             challenge: None,
+            protocol: Protocol::QuakeWorld,
+            attempts: 0,
+            next_resend: None,
         }
     }
 
@@ -34,22 +64,119 @@ impl Session {
         Self::new(qport, info.as_str().to_string())
     }
 
-    pub fn start(&mut self) -> Vec<u8> {
-        self.state = SessionState::ChallengeSent;
-        build_getchallenge()
+    pub fn with_protocol(mut self, protocol: Protocol) -> Self {
+        self.protocol = protocol;
+        self
+    }
+
+    pub fn start(&mut self) -> Vec<Vec<u8>> {
+        self.challenge = None;
+        if self.protocol == Protocol::NetQuake {
+            self.state = SessionState::ConnectSent;
+        } else {
+            self.state = SessionState::ChallengeSent;
+        }
+        self.restart_resend(Instant::now());
+        self.handshake_packets()
+    }
+
+    // What goes out for the step the handshake is waiting on: while Auto is
+    // still asking, that's both the QuakeWorld and the NetQuake probe.
+    fn handshake_packets(&self) -> Vec<Vec<u8>> {
+        let packet = self.handshake_packet();
+        if self.protocol == Protocol::Auto && self.state == SessionState::ChallengeSent {
+            vec![packet, build_nq_server_info_request()]
+        } else {
+            vec![packet]
+        }
+    }
+
+    fn handshake_packet(&self) -> Vec<u8> {
+        match (self.state, self.protocol, self.challenge) {
+            (SessionState::ConnectSent, Protocol::NetQuake, _) => build_nq_connect(),
+            (SessionState::ConnectSent, _, Some(challenge)) => {
+                let request = ConnectRequest::new(self.qport, challenge, self.userinfo.clone());
+                build_connect(&request)
+            }
+            _ => build_getchallenge(),
+        }
+    }
+
+    fn restart_resend(&mut self, now: Instant) {
+        self.attempts = 1;
+        self.next_resend = Some(now + RESEND_DELAY);
+    }
+
+    // Sends the last handshake packets again once their wait is up, doubling
+    // the wait each time, and gives up after CONNECT_ATTEMPTS tries.
+    pub fn resend(&mut self, now: Instant) -> Resend {
+        if !matches!(
+            self.state,
+            SessionState::ChallengeSent | SessionState::ConnectSent
+        ) {
+            return Resend::Wait;
+        }
+        let Some(due) = self.next_resend else {
+            return Resend::Wait;
+        };
+        if now < due {
+            return Resend::Wait;
+        }
+        if self.attempts >= CONNECT_ATTEMPTS {
+            self.state = SessionState::Disconnected;
+            self.next_resend = None;
+            return Resend::TimedOut;
+        }
+        let delay = (RESEND_DELAY * 2u32.pow(self.attempts)).min(MAX_RESEND_DELAY);
+        self.attempts += 1;
+        self.next_resend = Some(now + delay);
+        Resend::Send(self.handshake_packets())
+    }
+
+    pub fn handle_nq_control(&mut self, control: &NqControl) -> Option<Vec<u8>> {
+        match control {
+            NqControl::ServerInfo { .. }
+                if self.protocol == Protocol::Auto && self.state == SessionState::ChallengeSent =>
+            {
+                self.protocol = Protocol::NetQuake;
+                self.state = SessionState::ConnectSent;
+                self.restart_resend(Instant::now());
+                Some(build_nq_connect())
+            }
+            NqControl::Accept { .. }
+                if self.protocol == Protocol::NetQuake
+                    && self.state == SessionState::ConnectSent =>
+            {
+                self.state = SessionState::Connected;
+                self.next_resend = None;
+                None
+            }
+            NqControl::Reject(_) if self.protocol == Protocol::NetQuake => {
+                self.state = SessionState::Disconnected;
+                self.next_resend = None;
+                None
+            }
+            _ => None,
+        }
     }
 
     pub fn handle_oob(&mut self, msg: &OobMessage) -> Option<Vec<u8>> {
         match msg {
-            OobMessage::Challenge(_) => {
-                let challenge = parse_challenge(msg)?;
-                self.challenge = Some(challenge);
+            // Only while asking for one: a duplicated or late challenge
+            // would otherwise send a second connect.
+            OobMessage::Challenge(_)
+                if self.protocol != Protocol::NetQuake
+                    && self.state == SessionState::ChallengeSent =>
+            {
+                self.protocol = Protocol::QuakeWorld;
+                self.challenge = Some(parse_challenge(msg)?);
                 self.state = SessionState::ConnectSent;
-                let request = ConnectRequest::new(self.qport, challenge, self.userinfo.clone());
-                Some(build_connect(&request))
+                self.restart_resend(Instant::now());
+                Some(self.handshake_packet())
             }
             OobMessage::Connection(_) => {
                 self.state = SessionState::Connected;
+                self.next_resend = None;
                 None
             }
             _ => None,
@@ -67,9 +194,10 @@ mod tests {
     #[test]
     fn handshake_flow() {
         let mut session = Session::new(27001, "\\name\\player");
-        let packet = session.start();
+        let packets = session.start();
         assert_eq!(session.state, SessionState::ChallengeSent);
-        assert_eq!(out_of_band_payload(&packet).unwrap(), b"getchallenge\n");
+        assert_eq!(packets.len(), 1);
+        assert_eq!(out_of_band_payload(&packets[0]).unwrap(), b"getchallenge\n");
 
         let challenge_packet = build_out_of_band(&[S2C_CHALLENGE, b'1', b'2', b'3', 0]);
         let payload = out_of_band_payload(&challenge_packet).unwrap();
@@ -88,4 +216,118 @@ mod tests {
         session.handle_oob(&msg);
         assert_eq!(session.state, SessionState::Connected);
     }
+
+    #[test]
+    fn resends_with_backoff_until_it_gives_up() {
+        let mut session = Session::new(27001, "\\name\\player");
+        let first = session.start();
+        let start = Instant::now();
+        let mut sends = Vec::new();
+        let mut timed_out = None;
+        for tenth in 0..300 {
+            let now = start + Duration::from_millis(tenth * 100);
+            match session.resend(now) {
+                Resend::Wait => {}
+                Resend::Send(packets) => {
+                    assert_eq!(packets, first);
+                    sends.push(now);
+                }
+                Resend::TimedOut => {
+                    timed_out = Some(now);
+                    break;
+                }
+            }
+        }
+        assert_eq!(sends.len(), CONNECT_ATTEMPTS as usize - 1);
+        let gaps: Vec<_> = sends.windows(2).map(|pair| pair[1] - pair[0]).collect();
+        assert!(gaps.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(gaps.iter().all(|gap| *gap <= MAX_RESEND_DELAY));
+        assert!(timed_out.is_some());
+        assert_eq!(session.state, SessionState::Disconnected);
+    }
+
+    #[test]
+    fn resends_connect_once_challenged() {
+        let mut session = Session::new(27001, "\\name\\player");
+        session.start();
+        let challenge = build_out_of_band(&[S2C_CHALLENGE, b'9', 0]);
+        let msg = parse_oob_message(out_of_band_payload(&challenge).unwrap()).unwrap();
+        let connect = session.handle_oob(&msg).unwrap();
+        // A duplicated challenge doesn't send a second connect.
+        assert_eq!(session.handle_oob(&msg), None);
+
+        let later = Instant::now() + RESEND_DELAY;
+        assert_eq!(session.resend(later), Resend::Send(vec![connect]));
+    }
+
+    #[test]
+    fn auto_detects_a_netquake_server() {
+        let mut session = Session::new(27001, "\\name\\player").with_protocol(Protocol::Auto);
+        session.start();
+        let info = NqControl::ServerInfo {
+            address: "10.0.0.1:26000".to_string(),
+            host_name: "host".to_string(),
+            level_name: "e1m1".to_string(),
+            players: 0,
+            max_players: 8,
+            protocol: 3,
+        };
+        assert_eq!(session.handle_nq_control(&info), Some(build_nq_connect()));
+        assert_eq!(session.protocol, Protocol::NetQuake);
+        assert_eq!(session.state, SessionState::ConnectSent);
+
+        // A late QuakeWorld challenge doesn't switch it back.
+        let challenge = build_out_of_band(&[S2C_CHALLENGE, b'1', 0]);
+        let msg = parse_oob_message(out_of_band_payload(&challenge).unwrap()).unwrap();
+        assert_eq!(session.handle_oob(&msg), None);
+
+        session.handle_nq_control(&NqControl::Accept { port: 26001 });
+        assert_eq!(session.state, SessionState::Connected);
+    }
+
+    // Every retry asks both ways, so a NetQuake server whose first probe was
+    // lost still gets found.
+    #[test]
+    fn auto_resends_the_netquake_probe() {
+        let mut session = Session::new(27001, "\\name\\player").with_protocol(Protocol::Auto);
+        let first = session.start();
+        assert_eq!(
+            first,
+            [build_getchallenge(), build_nq_server_info_request()]
+        );
+
+        let start = Instant::now();
+        let mut retries = 0;
+        for tenth in 0..300 {
+            let now = start + Duration::from_millis(tenth * 100);
+            match session.resend(now) {
+                Resend::Wait => {}
+                Resend::Send(packets) => {
+                    assert_eq!(packets, first);
+                    retries += 1;
+                    if retries == 2 {
+                        break;
+                    }
+                }
+                Resend::TimedOut => panic!("timed out"),
+            }
+        }
+        assert_eq!(retries, 2);
+
+        let info = NqControl::ServerInfo {
+            address: "10.0.0.1:26000".to_string(),
+            host_name: "host".to_string(),
+            level_name: "e1m1".to_string(),
+            players: 0,
+            max_players: 8,
+            protocol: 3,
+        };
+        assert_eq!(session.handle_nq_control(&info), Some(build_nq_connect()));
+        // From here on only the NetQuake connect is retried.
+        let later = Instant::now() + RESEND_DELAY;
+        assert_eq!(
+            session.resend(later),
+            Resend::Send(vec![build_nq_connect()])
+        );
+    }
 }
@@ -6,6 +6,8 @@ use qw_audio::{AudioClip, AudioSystem, PlayParams, SoundId};
 use crate::state::ClientState;
 use qw_common::QuakeFs;
 
+pub const SOUND_CVARS: [(&str, &str, bool); 1] = [("volume", "0.7", true)];
+
 #[derive(Debug, Default)]
 pub struct SoundManager {
     active: HashMap<(u16, u8), SoundId>,
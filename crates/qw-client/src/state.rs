@@ -1,13 +1,44 @@
 use crate::prediction::{PhysEnt, PlayerMove};
 use qw_common::{
-    BspCollision, ClientDataMessage, EntityState, Frame, InfoString, MAX_CL_STATS, MAX_CLIENTS,
-    MAX_EDICTS, MAX_INFO_STRING, MAX_LIGHTSTYLES, MAX_PACKET_ENTITIES, MAX_SERVERINFO_STRING,
-    NailProjectile, PF_DEAD, PacketEntities, PacketEntitiesUpdate, Palette, PlayerState,
-    STAT_ACTIVEWEAPON, STAT_AMMO, STAT_ARMOR, STAT_CELLS, STAT_HEALTH, STAT_ITEMS, STAT_MONSTERS,
-    STAT_NAILS, STAT_ROCKETS, STAT_SECRETS, STAT_SHELLS, STAT_WEAPON, ServerData, StringListChunk,
-    SvcMessage, UPDATE_BACKUP, UPDATE_MASK, UserCmd, Vec3,
+    BspCollision, ClientDataMessage, CvarRegistry, EntityState, Frame, InfoString, MAX_CL_STATS,
+    MAX_CLIENTS, MAX_EDICTS, MAX_INFO_STRING, MAX_LIGHTSTYLES, MAX_PACKET_ENTITIES,
+    MAX_SERVERINFO_STRING, NailProjectile, PF_DEAD, PacketEntities, PacketEntitiesUpdate, Palette,
+    PlayerState, STAT_ACTIVEWEAPON, STAT_AMMO, STAT_ARMOR, STAT_CELLS, STAT_HEALTH, STAT_ITEMS,
+    STAT_MONSTERS, STAT_NAILS, STAT_ROCKETS, STAT_SECRETS, STAT_SHELLS, STAT_WEAPON, ServerData,
+    StringListChunk, SvcMessage, UPDATE_BACKUP, UPDATE_MASK, UserCmd, Vec3,
 };
 
+// Name, default and whether config.cfg keeps it.
+pub const PREDICTION_CVARS: [(&str, &str, bool); 3] = [
+    ("cl_predict_players", "1", false),
+    ("cl_nolerp", "0", false),
+    ("cl_lerp_time", "0.1", false),
+];
+
+// How long a correction from the server takes to ease out of the view.
+const PREDICTION_SMOOTH_TIME: f64 = 0.1;
+// A bigger correction than this is a teleport, and is taken at once.
+const MAX_PREDICTION_ERROR: f32 = 64.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PredictionSettings {
+    pub predict_players: bool,
+    pub nolerp: bool,
+    // How far past the last snapshot entities are carried along when the
+    // next one is late.
+    pub lerp_time: f64,
+}
+
+impl PredictionSettings {
+    pub fn from_cvars(cvars: &CvarRegistry) -> Self {
+        Self {
+            predict_players: cvars.value("cl_predict_players") != 0.0,
+            nolerp: cvars.value("cl_nolerp") != 0.0,
+            lerp_time: f64::from(cvars.value("cl_lerp_time").max(0.0)),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct PlayerInfo {
     pub user_id: i32,
@@ -99,6 +130,13 @@ pub struct ClientState {
     pub sim_origin: Vec3,
     pub sim_velocity: Vec3,
     pub sim_angles: Vec3,
+    // Our predicted origin for each outgoing sequence, to measure the
+    // server's correction against when its frame comes back.
+    predicted_origins: Vec<Option<(u32, Vec3)>>,
+    prediction_error: Vec3,
+    prediction_error_time: Option<f64>,
+    // The part of the error the view was last drawn with.
+    error_offset: Vec3,
     pub server_version: Option<i32>,
     pub parsecount_time: Option<f64>,
     pub latency: f64,
@@ -159,6 +197,10 @@ impl ClientState {
             sim_origin: Vec3::default(),
             sim_velocity: Vec3::default(),
             sim_angles: Vec3::default(),
+            predicted_origins: vec![None; UPDATE_BACKUP],
+            prediction_error: Vec3::default(),
+            prediction_error_time: None,
+            error_offset: Vec3::default(),
             server_version: None,
             parsecount_time: None,
             latency: 0.0,
@@ -331,6 +373,16 @@ impl ClientState {
                     };
 
                 let frame_index = (incoming_sequence as usize) & UPDATE_MASK;
+                let local = self
+                    .serverdata
+                    .as_ref()
+                    .is_some_and(|data| data.player_num == info.num);
+                if local
+                    && let Some((sequence, predicted)) = self.predicted_origins[frame_index]
+                    && sequence == incoming_sequence
+                {
+                    self.note_prediction_error(predicted, info.origin);
+                }
                 if let Some(state) = self.frames.get_mut(frame_index) {
                     let slot = info.num as usize;
                     if let Some(player_state) = state.playerstate.get_mut(slot) {
@@ -588,18 +640,72 @@ impl ClientState {
         Some(out)
     }
 
+    // Whether the last frame from the server had us standing on something,
+    // which V_DriftPitch waits for.
+    pub fn on_ground(&self, incoming_sequence: u32) -> bool {
+        let Some(data) = &self.serverdata else {
+            return false;
+        };
+        let frame = &self.frames[(incoming_sequence as usize) & UPDATE_MASK];
+        frame
+            .playerstate
+            .get(data.player_num as usize)
+            .is_some_and(|player| player.onground != -1)
+    }
+
     pub fn predict_move(&mut self, incoming_sequence: u32, outgoing_sequence: u32, now: f64) {
+        if self.predict_local(incoming_sequence, outgoing_sequence, now) {
+            self.smooth_prediction_error(now);
+        }
+    }
+
+    // CL_CheckPredictionError, after Quake 2: the view carries on from
+    // where the old prediction had it and eases onto the corrected one.
+    fn note_prediction_error(&mut self, predicted: Vec3, actual: Vec3) {
+        let delta = Vec3::new(
+            predicted.x - actual.x,
+            predicted.y - actual.y,
+            predicted.z - actual.z,
+        );
+        if delta.x.abs() > MAX_PREDICTION_ERROR
+            || delta.y.abs() > MAX_PREDICTION_ERROR
+            || delta.z.abs() > MAX_PREDICTION_ERROR
+        {
+            self.prediction_error = Vec3::default();
+        } else {
+            self.prediction_error = Vec3::new(
+                delta.x + self.error_offset.x,
+                delta.y + self.error_offset.y,
+                delta.z + self.error_offset.z,
+            );
+        }
+        self.prediction_error_time = None;
+    }
+
+    fn smooth_prediction_error(&mut self, now: f64) {
+        let start = *self.prediction_error_time.get_or_insert(now);
+        let remaining = (1.0 - (now - start) / PREDICTION_SMOOTH_TIME).clamp(0.0, 1.0);
+        self.error_offset = self.prediction_error.scale(remaining as f32);
+        self.sim_origin = Vec3::new(
+            self.sim_origin.x + self.error_offset.x,
+            self.sim_origin.y + self.error_offset.y,
+            self.sim_origin.z + self.error_offset.z,
+        );
+    }
+
+    // Whether sim_origin was worked out afresh.
+    fn predict_local(&mut self, incoming_sequence: u32, outgoing_sequence: u32, now: f64) -> bool {
         if self.paused || self.intermission.is_some() {
-            return;
+            return false;
         }
         let Some(data) = &self.serverdata else {
-            return;
+            return false;
         };
         if self.valid_sequence == 0 {
-            return;
+            return false;
         }
         if outgoing_sequence.wrapping_sub(incoming_sequence) >= (UPDATE_BACKUP as u32 - 1) {
-            return;
+            return false;
         }
 
         let player_num = data.player_num as usize;
@@ -629,6 +735,7 @@ impl ClientState {
                 break;
             };
             self.frames[index].playerstate[player_num] = predicted;
+            self.predicted_origins[index] = Some((seq, predicted.origin));
             to_state = Some(predicted);
             to_seq = Some(seq);
             if self.frames[index].senttime >= now {
@@ -642,7 +749,7 @@ impl ClientState {
             self.sim_origin = from_state.origin;
             self.sim_velocity = from_state.velocity;
             self.sim_angles = from_state.viewangles;
-            return;
+            return true;
         };
 
         let from_time = self.frames[(from_seq as usize) & UPDATE_MASK].senttime;
@@ -669,7 +776,7 @@ impl ClientState {
             self.sim_origin = to_state.origin;
             self.sim_velocity = to_state.velocity;
             self.sim_angles = to_state.viewangles;
-            return;
+            return true;
         }
 
         self.sim_origin = Vec3::new(
@@ -683,6 +790,56 @@ impl ClientState {
             from_state.velocity.z + frac * (to_state.velocity.z - from_state.velocity.z),
         );
         self.sim_angles = from_state.viewangles;
+        true
+    }
+
+    // CL_LinkPlayers: everyone else in the last frame, carried on to
+    // playertime by the command each was last seen running.
+    pub fn predicted_players(
+        &self,
+        incoming_sequence: u32,
+        playertime: f64,
+        settings: &PredictionSettings,
+    ) -> Vec<(usize, PlayerState)> {
+        let Some(data) = &self.serverdata else {
+            return Vec::new();
+        };
+        let frame = &self.frames[(incoming_sequence as usize) & UPDATE_MASK];
+        let physents = self.build_base_physents(frame);
+        let mut players = Vec::new();
+        for (index, state) in frame.playerstate.iter().enumerate() {
+            if index == data.player_num as usize
+                || state.messagenum != incoming_sequence as i32
+                || state.modelindex == 0
+            {
+                continue;
+            }
+            let mut state = *state;
+            if settings.predict_players {
+                state.origin = self.predict_player_origin(&state, playertime, &physents);
+            }
+            players.push((index, state));
+        }
+        players
+    }
+
+    // QuakeWorld runs the command for half the time since the update, which
+    // keeps a guess that turns out wrong from straying far.
+    fn predict_player_origin(
+        &self,
+        state: &PlayerState,
+        playertime: f64,
+        physents: &[PhysEnt],
+    ) -> Vec3 {
+        let msec = ((playertime - state.state_time) * 500.0).min(255.0) as i32;
+        if msec <= 0 {
+            return state.origin;
+        }
+        let mut cmd = state.command;
+        cmd.msec = msec as u8;
+        self.predict_usercmd_with_physents(state, cmd, false, Some(physents))
+            .map(|predicted| predicted.origin)
+            .unwrap_or(state.origin)
     }
 
     fn build_base_physents(&self, frame: &Frame) -> Vec<PhysEnt> {
@@ -761,24 +918,7 @@ impl ClientState {
                 continue;
             }
 
-            let delta = playertime - state.state_time;
-            let predicted_origin = if delta <= 0.0 {
-                state.origin
-            } else {
-                let mut msec = (delta * 500.0) as i32;
-                if msec > 255 {
-                    msec = 255;
-                }
-                if msec <= 0 {
-                    state.origin
-                } else {
-                    let mut cmd = state.command;
-                    cmd.msec = msec as u8;
-                    self.predict_usercmd_with_physents(state, cmd, false, Some(&base_physents))
-                        .map(|predicted| predicted.origin)
-                        .unwrap_or(state.origin)
-                }
-            };
+            let predicted_origin = self.predict_player_origin(state, playertime, &base_physents);
 
             physents.push(PhysEnt {
                 origin: predicted_origin,
@@ -1805,4 +1945,101 @@ mod tests {
         assert_eq!(predicted.oldbuttons, 7);
         assert_eq!(predicted.command, cmd);
     }
+
+    fn empty_level() -> ClientState {
+        let mut state = ClientState::new();
+        state.apply_message(
+            &SvcMessage::ServerData(qw_common::ServerData {
+                protocol: qw_common::PROTOCOL_VERSION,
+                server_count: 1,
+                game_dir: "id1".to_string(),
+                player_num: 0,
+                spectator: false,
+                level_name: "start".to_string(),
+                movevars: qw_common::MoveVars {
+                    gravity: 800.0,
+                    stopspeed: 100.0,
+                    maxspeed: 320.0,
+                    spectatormaxspeed: 500.0,
+                    accelerate: 10.0,
+                    airaccelerate: 12.0,
+                    wateraccelerate: 8.0,
+                    friction: 4.0,
+                    waterfriction: 2.0,
+                    entgravity: 1.0,
+                },
+            }),
+            0,
+        );
+        state.collision = Some(BspCollision {
+            planes: Vec::new(),
+            clipnodes: Vec::new(),
+            hull0_clipnodes: Vec::new(),
+            models: Vec::new(),
+        });
+        state
+    }
+
+    fn player_info(num: u8, origin: Vec3, velocity: [i16; 3]) -> SvcMessage {
+        SvcMessage::PlayerInfo(qw_common::PlayerInfoMessage {
+            num,
+            flags: qw_common::PF_MODEL as u16,
+            origin,
+            frame: 0,
+            msec: None,
+            command: None,
+            velocity,
+            model_index: Some(1),
+            skin_num: None,
+            effects: None,
+            weapon_frame: None,
+        })
+    }
+
+    #[test]
+    fn predicts_other_players_to_playertime() {
+        let mut state = empty_level();
+        state.parsecount_time = Some(1.0);
+        state.apply_message(&player_info(0, Vec3::default(), [0; 3]), 5);
+        state.apply_message(&player_info(1, Vec3::default(), [200, 0, 0]), 5);
+        let mut settings = PredictionSettings {
+            predict_players: true,
+            nolerp: false,
+            lerp_time: 0.1,
+        };
+
+        let players = state.predicted_players(5, 1.2, &settings);
+        assert_eq!(players.len(), 1);
+        let (index, player) = players[0];
+        assert_eq!(index, 1);
+        // Half of the 0.2 seconds since the update, at 200 units a second.
+        assert!((player.origin.x - 20.0).abs() < 0.5);
+
+        settings.predict_players = false;
+        let players = state.predicted_players(5, 1.2, &settings);
+        assert_eq!(players[0].1.origin, Vec3::default());
+        // Players missing from the latest frame aren't drawn.
+        assert!(state.predicted_players(6, 1.2, &settings).is_empty());
+    }
+
+    #[test]
+    fn eases_out_prediction_errors() {
+        let mut state = empty_level();
+        state.predicted_origins[5] = Some((5, Vec3::new(10.0, 0.0, 0.0)));
+        state.apply_message(&player_info(0, Vec3::new(6.0, 0.0, 0.0), [0; 3]), 5);
+
+        let view_x = |state: &mut ClientState, now| {
+            state.sim_origin = Vec3::new(6.0, 0.0, 0.0);
+            state.smooth_prediction_error(now);
+            state.sim_origin.x
+        };
+        assert_eq!(view_x(&mut state, 1.0), 10.0);
+        assert!((view_x(&mut state, 1.05) - 8.0).abs() < 1e-4);
+        assert_eq!(view_x(&mut state, 1.2), 6.0);
+
+        // A teleport is taken at once.
+        state.predicted_origins[6] = Some((6, Vec3::new(500.0, 0.0, 0.0)));
+        state.apply_message(&player_info(0, Vec3::new(6.0, 0.0, 0.0), [0; 3]), 6);
+        assert_eq!(view_x(&mut state, 2.0), 6.0);
+    }
 }
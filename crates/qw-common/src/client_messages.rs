@@ -111,8 +111,10 @@ pub fn write_serverdata(buf: &mut SizeBuf, data: &ServerData) -> Result<(), Size
     Ok(())
 }
 
+// On the wire both bytes count from one below the slot they name, since
+// slot 0 is never sent; a next of 0 ends the list.
 pub fn parse_string_list_chunk(reader: &mut MsgReader) -> Result<StringListChunk, MsgReadError> {
-    let start = reader.read_u8()?;
+    let start = reader.read_u8()?.saturating_add(1);
     let mut items = Vec::new();
     loop {
         let item = reader.read_string()?;
@@ -121,7 +123,10 @@ pub fn parse_string_list_chunk(reader: &mut MsgReader) -> Result<StringListChunk
         }
         items.push(item);
     }
-    let next = reader.read_u8()?;
+    let next = match reader.read_u8()? {
+        0 => 0,
+        next => next.saturating_add(1),
+    };
     Ok(StringListChunk { start, items, next })
 }
 
@@ -129,12 +134,12 @@ pub fn write_string_list_chunk(
     buf: &mut SizeBuf,
     chunk: &StringListChunk,
 ) -> Result<(), SizeBufError> {
-    buf.write_u8(chunk.start)?;
+    buf.write_u8(chunk.start.saturating_sub(1))?;
     for item in &chunk.items {
         buf.write_string(Some(item))?;
     }
     buf.write_string(Some(""))?;
-    buf.write_u8(chunk.next)?;
+    buf.write_u8(chunk.next.saturating_sub(1))?;
     Ok(())
 }
 
@@ -185,6 +190,8 @@ mod tests {
 
         let mut buf = SizeBuf::new(256);
         write_string_list_chunk(&mut buf, &chunk).unwrap();
+        assert_eq!(buf.as_slice()[0], 1);
+        assert_eq!(buf.as_slice()[buf.len() - 1], 4);
 
         let mut reader = MsgReader::new(buf.as_slice());
         let parsed = parse_string_list_chunk(&mut reader).unwrap();
@@ -74,6 +74,14 @@ impl CvarRegistry {
         self.vars.get(name)
     }
 
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Cvar> {
+        self.vars.get_mut(name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Cvar> {
+        self.vars.values()
+    }
+
     pub fn iter_archive(&self) -> impl Iterator<Item = &Cvar> {
         self.vars.values().filter(|v| v.archive)
     }
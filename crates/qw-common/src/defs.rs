@@ -15,6 +15,9 @@ pub const MAX_OSPATH: usize = 128;
 
 pub const MAX_INFO_STRING: usize = 196;
 pub const MAX_SERVERINFO_STRING: usize = 512;
+// Not a stock key: our server publishes the map checksum2 it will hold a
+// client's prespawn to, so a downloaded map can be checked on arrival.
+pub const SERVERINFO_MAP_CHECKSUM: &str = "*map_checksum2";
 pub const MAX_LOCALINFO_STRING: usize = 32768;
 
 pub const MAX_MSGLEN: usize = 1450;
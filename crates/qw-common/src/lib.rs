@@ -14,11 +14,15 @@ pub mod data_paths;
 pub mod defs;
 pub mod entities;
 pub mod info;
+pub mod loopback;
 pub mod md4;
 pub mod mdl;
 pub mod msg;
 pub mod netaddr;
 pub mod netchan;
+pub mod netsim;
+pub mod nq;
+pub mod nq_svc;
 pub mod oob;
 pub mod pak;
 pub mod palette;
@@ -26,6 +30,7 @@ pub mod pcx;
 pub mod protocol;
 pub mod spr;
 pub mod svc;
+pub mod transport;
 pub mod types;
 pub mod vfs;
 pub mod wad;
@@ -44,11 +49,15 @@ pub use data_paths::*;
 pub use defs::*;
 pub use entities::*;
 pub use info::*;
+pub use loopback::*;
 pub use md4::*;
 pub use mdl::*;
 pub use msg::*;
 pub use netaddr::*;
 pub use netchan::*;
+pub use netsim::*;
+pub use nq::*;
+pub use nq_svc::*;
 pub use oob::*;
 pub use pak::*;
 pub use palette::*;
@@ -56,6 +65,7 @@ pub use pcx::*;
 pub use protocol::*;
 pub use spr::*;
 pub use svc::*;
+pub use transport::*;
 pub use types::*;
 pub use vfs::*;
 pub use wad::*;
@@ -139,11 +139,21 @@ impl Netchan {
             return Err(NetchanError::OutOfOrder);
         }
 
+        // The ack bit echoes the reliable sequence the peer last received, so
+        // once it matches ours the reliable in flight has arrived and the
+        // next queued one can go out.
+        let reliable_ack = u32::from(header.ack_reliable);
+        if reliable_ack == self.reliable_sequence {
+            self.reliable_length = 0;
+        }
+
         self.received_any = true;
         self.incoming_sequence = header.sequence;
-        self.incoming_reliable_sequence = if header.reliable { 1 } else { 0 };
+        if header.reliable {
+            self.incoming_reliable_sequence ^= 1;
+        }
         self.incoming_acknowledged = header.ack;
-        self.incoming_reliable_acknowledged = if header.ack_reliable { 1 } else { 0 };
+        self.incoming_reliable_acknowledged = reliable_ack;
 
         let offset = packet.len() - reader.remaining();
         Ok(&packet[offset..])
@@ -160,6 +170,14 @@ impl Netchan {
     pub fn outgoing_sequence(&self) -> u32 {
         self.outgoing_sequence
     }
+
+    // Demo playback jumps the channel to the sequences the recording
+    // continued from, so later packets are in order against them.
+    pub fn set_sequences(&mut self, outgoing: u32, incoming: u32) {
+        self.outgoing_sequence = outgoing;
+        self.incoming_sequence = incoming;
+        self.received_any = true;
+    }
 }
 
 impl NetchanHeader {
@@ -253,4 +271,42 @@ mod tests {
         let payload = receiver.process_packet(&packet, true).unwrap();
         assert_eq!(payload, b"hello");
     }
+
+    #[test]
+    fn sends_queued_reliables_once_the_last_is_acked() {
+        let mut client = Netchan::new(27001);
+        let mut server = Netchan::new(27001);
+        client.queue_reliable(b"new").unwrap();
+        let packet = client.build_packet(&[], true).unwrap();
+        assert_eq!(server.process_packet(&packet, true).unwrap(), b"new");
+
+        // Held back while "new" is unacknowledged.
+        client.queue_reliable(b"soundlist").unwrap();
+        let packet = client.build_packet(b"move", true).unwrap();
+        assert_eq!(server.process_packet(&packet, true).unwrap(), b"move");
+
+        let reply = server.build_packet(&[], false).unwrap();
+        client.process_packet(&reply, false).unwrap();
+        let packet = client.build_packet(&[], true).unwrap();
+        assert_eq!(server.process_packet(&packet, true).unwrap(), b"soundlist");
+    }
+
+    #[test]
+    fn resends_a_lost_reliable() {
+        let mut client = Netchan::new(27001);
+        let mut server = Netchan::new(27001);
+        client.queue_reliable(b"new").unwrap();
+        let _lost = client.build_packet(&[], true).unwrap();
+
+        // The server acks packets sent after the reliable without its bit.
+        for _ in 0..2 {
+            let packet = client.build_packet(&[], true).unwrap();
+            assert_eq!(server.process_packet(&packet, true).unwrap(), b"");
+        }
+        let reply = server.build_packet(&[], false).unwrap();
+        client.process_packet(&reply, false).unwrap();
+
+        let packet = client.build_packet(&[], true).unwrap();
+        assert_eq!(server.process_packet(&packet, true).unwrap(), b"new");
+    }
 }
@@ -0,0 +1,443 @@
+// A transport wrapper that behaves like a bad link, for watching prediction and
+// the netchan cope without one. Packets are delayed, dropped, duplicated and
+// reordered in both directions, and each direction can be capped to a rate;
+// latency is one-way, so the round trip sees it twice. Every choice comes from
+// a seeded generator, so a given seed drops the same packets each run.
+
+use crate::{CvarRegistry, Transport};
+use std::borrow::Cow;
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+pub const NETSIM_CVARS: [&str; 7] = [
+    "netsim_latency",
+    "netsim_jitter",
+    "netsim_loss",
+    "netsim_reorder",
+    "netsim_dup",
+    "netsim_bandwidth",
+    "netsim_seed",
+];
+
+// A reordered packet is held back this much longer than the rest, enough to
+// land behind the next one at the 20Hz the client sends moves.
+const REORDER_DELAY: Duration = Duration::from_millis(60);
+// A capped link drops what would wait longer than this to go out.
+const MAX_BACKLOG: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct NetSimConfig {
+    pub latency: Duration,
+    pub jitter: Duration,
+    // Percentages of packets.
+    pub loss: f32,
+    pub reorder: f32,
+    pub duplicate: f32,
+    // Bytes per second each way; 0 leaves the link uncapped.
+    pub bandwidth: u32,
+    // 0 picks a seed from the clock.
+    pub seed: u64,
+}
+
+impl NetSimConfig {
+    pub fn register_cvars(cvars: &mut CvarRegistry) {
+        for name in NETSIM_CVARS {
+            cvars.register(crate::Cvar::new(name, "0"));
+        }
+    }
+
+    pub fn from_cvars(cvars: &CvarRegistry) -> Self {
+        // Delays are capped at a minute so they can't overflow a deadline.
+        let ms =
+            |name| Duration::from_micros((cvars.value(name).clamp(0.0, 60_000.0) * 1000.0) as u64);
+        let percent = |name| cvars.value(name).clamp(0.0, 100.0);
+        Self {
+            latency: ms("netsim_latency"),
+            jitter: ms("netsim_jitter"),
+            loss: percent("netsim_loss"),
+            reorder: percent("netsim_reorder"),
+            duplicate: percent("netsim_dup"),
+            bandwidth: cvars.value("netsim_bandwidth").max(0.0) as u32,
+            seed: cvars.string("netsim_seed").parse().unwrap_or(0),
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        *self
+            != Self {
+                seed: self.seed,
+                ..Self::default()
+            }
+    }
+}
+
+impl fmt::Display for NetSimConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "latency {}ms jitter {}ms loss {}% reorder {}% dup {}%",
+            self.latency.as_millis(),
+            self.jitter.as_millis(),
+            self.loss,
+            self.reorder,
+            self.duplicate
+        )?;
+        if self.bandwidth > 0 {
+            write!(f, " bandwidth {}B/s", self.bandwidth)?;
+        }
+        Ok(())
+    }
+}
+
+// Maps a command-line flag such as --netsim-loss to its cvar.
+pub fn netsim_cvar(flag: &str) -> Option<&'static str> {
+    let name = flag.strip_prefix("--netsim-")?;
+    NETSIM_CVARS
+        .into_iter()
+        .find(|cvar| cvar.strip_prefix("netsim_") == Some(name))
+}
+
+pub fn valid_netsim_value(value: &str) -> bool {
+    value
+        .parse::<f64>()
+        .is_ok_and(|value| value.is_finite() && value >= 0.0)
+}
+
+pub struct NetSim<T> {
+    inner: T,
+    config: NetSimConfig,
+    rng: SimRng,
+    outgoing: Link,
+    incoming: Link,
+}
+
+impl<T: Transport> NetSim<T> {
+    pub fn new(inner: T, config: NetSimConfig) -> Self {
+        let seed = match config.seed {
+            0 => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(1, |time| time.as_nanos() as u64),
+            seed => seed,
+        };
+        Self {
+            inner,
+            config,
+            rng: SimRng::new(seed),
+            outgoing: Link::default(),
+            incoming: Link::default(),
+        }
+    }
+
+    fn flush(&mut self, now: Instant) -> io::Result<()> {
+        while let Some(pending) = self.outgoing.pop_ready(now) {
+            self.inner.send_to(pending.packet, pending.addr)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: Transport> Transport for NetSim<T> {
+    fn send_to(&mut self, packet: Vec<u8>, addr: SocketAddr) -> io::Result<()> {
+        let now = Instant::now();
+        self.outgoing
+            .push(&self.config, &mut self.rng, packet, addr, now);
+        self.flush(now)
+    }
+
+    // Waits on the inner transport no longer than the next packet is due, so
+    // delayed packets go out and come in on time.
+    fn recv_from<'a>(
+        &mut self,
+        buf: &'a mut [u8],
+        wait: Duration,
+    ) -> io::Result<Option<(Cow<'a, [u8]>, SocketAddr)>> {
+        let deadline = Instant::now() + wait;
+        loop {
+            let now = Instant::now();
+            self.flush(now)?;
+            if let Some(pending) = self.incoming.pop_ready(now) {
+                return Ok(Some((Cow::Owned(pending.packet), pending.addr)));
+            }
+            let wait = [self.outgoing.next_due(), self.incoming.next_due()]
+                .into_iter()
+                .flatten()
+                .map(|due| due.saturating_duration_since(now))
+                .fold(deadline.saturating_duration_since(now), Duration::min);
+            match self.inner.recv_from(buf, wait)? {
+                Some((packet, addr)) => {
+                    let packet = packet.into_owned();
+                    self.incoming
+                        .push(&self.config, &mut self.rng, packet, addr, Instant::now());
+                }
+                None if Instant::now() >= deadline => return Ok(None),
+                None => {}
+            }
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+}
+
+struct Pending {
+    due: Instant,
+    // Breaks ties so packets due together leave in the order they were sent.
+    order: u64,
+    packet: Vec<u8>,
+    addr: SocketAddr,
+}
+
+impl PartialEq for Pending {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Pending {}
+
+impl PartialOrd for Pending {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Pending {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.due, self.order).cmp(&(other.due, other.order))
+    }
+}
+
+// One direction of the link.
+#[derive(Default)]
+struct Link {
+    queue: BinaryHeap<Reverse<Pending>>,
+    next_order: u64,
+    // When a capped link finishes putting out what it has queued.
+    busy_until: Option<Instant>,
+}
+
+impl Link {
+    fn push(
+        &mut self,
+        config: &NetSimConfig,
+        rng: &mut SimRng,
+        packet: Vec<u8>,
+        addr: SocketAddr,
+        now: Instant,
+    ) {
+        if rng.chance(config.loss) {
+            return;
+        }
+        let mut sent = now;
+        if config.bandwidth > 0 {
+            let start = self.busy_until.map_or(now, |busy| busy.max(now));
+            if start - now > MAX_BACKLOG {
+                return;
+            }
+            sent =
+                start + Duration::from_secs_f64(packet.len() as f64 / f64::from(config.bandwidth));
+            self.busy_until = Some(sent);
+        }
+        if rng.chance(config.duplicate) {
+            let due = sent + Self::delay(config, rng);
+            self.enqueue(due, packet.clone(), addr);
+        }
+        let due = sent + Self::delay(config, rng);
+        self.enqueue(due, packet, addr);
+    }
+
+    fn delay(config: &NetSimConfig, rng: &mut SimRng) -> Duration {
+        let mut delay = config.latency + rng.below(config.jitter);
+        if rng.chance(config.reorder) {
+            delay += REORDER_DELAY;
+        }
+        delay
+    }
+
+    fn enqueue(&mut self, due: Instant, packet: Vec<u8>, addr: SocketAddr) {
+        self.queue.push(Reverse(Pending {
+            due,
+            order: self.next_order,
+            packet,
+            addr,
+        }));
+        self.next_order += 1;
+    }
+
+    fn pop_ready(&mut self, now: Instant) -> Option<Pending> {
+        if self.next_due()? > now {
+            return None;
+        }
+        self.queue.pop().map(|Reverse(pending)| pending)
+    }
+
+    fn next_due(&self) -> Option<Instant> {
+        self.queue.peek().map(|Reverse(pending)| pending.due)
+    }
+}
+
+// SplitMix64: small, fast and good enough to pick which packets to drop.
+struct SimRng(u64);
+
+impl SimRng {
+    fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // A uniform value in 0..1.
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn chance(&mut self, percent: f32) -> bool {
+        percent > 0.0 && self.next_f64() * 100.0 < f64::from(percent)
+    }
+
+    fn below(&mut self, limit: Duration) -> Duration {
+        if limit.is_zero() {
+            return Duration::ZERO;
+        }
+        limit.mul_f64(self.next_f64())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{LOOPBACK_ADDR, loopback_pair};
+
+    fn send_all(link: &mut Link, config: &NetSimConfig, rng: &mut SimRng, now: Instant) {
+        for n in 0..100u8 {
+            link.push(config, rng, vec![n; 100], LOOPBACK_ADDR, now);
+        }
+    }
+
+    fn drain(link: &mut Link, now: Instant) -> Vec<u8> {
+        std::iter::from_fn(|| link.pop_ready(now))
+            .map(|pending| pending.packet[0])
+            .collect()
+    }
+
+    #[test]
+    fn delays_and_keeps_order_without_jitter() {
+        let config = NetSimConfig {
+            latency: Duration::from_millis(100),
+            ..NetSimConfig::default()
+        };
+        let (mut link, mut rng, now) = (Link::default(), SimRng::new(1), Instant::now());
+        send_all(&mut link, &config, &mut rng, now);
+        assert!(drain(&mut link, now + Duration::from_millis(99)).is_empty());
+        assert_eq!(
+            drain(&mut link, now + config.latency),
+            (0..100).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn drops_duplicates_and_reorders_the_same_way_for_a_seed() {
+        let config = NetSimConfig {
+            jitter: Duration::from_millis(10),
+            loss: 20.0,
+            reorder: 10.0,
+            duplicate: 10.0,
+            ..NetSimConfig::default()
+        };
+        let run = |seed| {
+            let (mut link, mut rng, now) = (Link::default(), SimRng::new(seed), Instant::now());
+            send_all(&mut link, &config, &mut rng, now);
+            drain(&mut link, now + Duration::from_secs(1))
+        };
+        let received = run(7);
+        assert_eq!(received, run(7));
+        assert_ne!(received, run(8));
+
+        let mut unique = received.clone();
+        unique.sort();
+        unique.dedup();
+        assert!(unique.len() < 100, "some packets were lost");
+        assert!(
+            received.len() > unique.len(),
+            "some packets were duplicated"
+        );
+        assert!(!received.is_sorted(), "some packets were reordered");
+    }
+
+    #[test]
+    fn caps_bandwidth_and_drops_the_backlog() {
+        // 100-byte packets at 1000 bytes/sec leave one every 100ms.
+        let config = NetSimConfig {
+            bandwidth: 1000,
+            ..NetSimConfig::default()
+        };
+        let (mut link, mut rng, now) = (Link::default(), SimRng::new(1), Instant::now());
+        send_all(&mut link, &config, &mut rng, now);
+        assert_eq!(
+            drain(&mut link, now + Duration::from_millis(350)),
+            [0, 1, 2]
+        );
+        assert_eq!(drain(&mut link, now + Duration::from_secs(60)).len(), 8);
+    }
+
+    #[test]
+    fn reads_settings_from_cvars_and_flags() {
+        let mut cvars = CvarRegistry::new();
+        NetSimConfig::register_cvars(&mut cvars);
+        assert!(!NetSimConfig::from_cvars(&cvars).is_active());
+
+        assert_eq!(netsim_cvar("--netsim-latency"), Some("netsim_latency"));
+        assert_eq!(netsim_cvar("--netsim-bogus"), None);
+        assert!(valid_netsim_value("2.5"));
+        assert!(!valid_netsim_value("-1"));
+        assert!(!valid_netsim_value("inf"));
+
+        cvars.set("netsim_latency", "120");
+        cvars.set("netsim_loss", "5");
+        cvars.set("netsim_seed", "42");
+        let config = NetSimConfig::from_cvars(&cvars);
+        assert!(config.is_active());
+        assert_eq!(config.latency, Duration::from_millis(120));
+        assert_eq!(config.loss, 5.0);
+        assert_eq!(config.seed, 42);
+    }
+
+    #[test]
+    fn wraps_a_transport() {
+        let (client, mut server) = loopback_pair();
+        let config = NetSimConfig {
+            latency: Duration::from_millis(20),
+            ..NetSimConfig::default()
+        };
+        let mut client = NetSim::new(client, config);
+        let started = Instant::now();
+        client.send_to(b"ping".to_vec(), LOOPBACK_ADDR).unwrap();
+        let mut buf = [0u8; 16];
+        assert!(
+            client
+                .recv_from(&mut buf, Duration::ZERO)
+                .unwrap()
+                .is_none()
+        );
+        // The packet goes out while the client waits for the reply.
+        server.send_to(b"early".to_vec(), LOOPBACK_ADDR).unwrap();
+        let (packet, _) = client
+            .recv_from(&mut buf, Duration::from_millis(500))
+            .unwrap()
+            .expect("reply arrived");
+        assert_eq!(&*packet, b"early");
+        assert!(started.elapsed() >= config.latency);
+        assert_eq!(server.recv().unwrap(), Some(b"ping".to_vec()));
+    }
+}
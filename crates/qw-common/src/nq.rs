@@ -0,0 +1,369 @@
+// NetQuake's datagram layer (net_dgrm.c). Every packet starts with a
+// big-endian length and flags word. Reliable messages go out in fragments
+// that are each acked before the next is sent, and unreliable ones carry
+// their own sequence so stale ones can be dropped. Connecting is done with
+// control packets rather than QuakeWorld's out-of-band text.
+
+use crate::msg::{MsgReader, SizeBuf};
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+pub const NQ_PROTOCOL_VERSION: i32 = 15;
+pub const NQ_NET_PROTOCOL_VERSION: u8 = 3;
+pub const NQ_DEFAULT_PORT: u16 = 26000;
+pub const NQ_MAX_DATAGRAM: usize = 1024;
+pub const NQ_MAX_MESSAGE: usize = 8192;
+
+const NETFLAG_LENGTH_MASK: u32 = 0x0000_ffff;
+const NETFLAG_DATA: u32 = 0x0001_0000;
+const NETFLAG_ACK: u32 = 0x0002_0000;
+const NETFLAG_EOM: u32 = 0x0008_0000;
+const NETFLAG_UNRELIABLE: u32 = 0x0010_0000;
+const NETFLAG_CTL: u32 = 0x8000_0000;
+const HEADER_SIZE: usize = 8;
+// How long a reliable fragment waits for its ack before going out again.
+const RESEND_TIME: Duration = Duration::from_secs(1);
+
+const CCREQ_CONNECT: u8 = 0x01;
+const CCREQ_SERVER_INFO: u8 = 0x02;
+const CCREP_ACCEPT: u8 = 0x81;
+const CCREP_REJECT: u8 = 0x82;
+const CCREP_SERVER_INFO: u8 = 0x83;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NqControl {
+    // The server moved the connection to this port; 0 keeps the current one.
+    Accept {
+        port: u16,
+    },
+    Reject(String),
+    ServerInfo {
+        address: String,
+        host_name: String,
+        level_name: String,
+        players: u8,
+        max_players: u8,
+        protocol: u8,
+    },
+    Unknown(u8),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NqMessage {
+    Reliable(Vec<u8>),
+    Unreliable(Vec<u8>),
+    Control(NqControl),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NqError {
+    BadHeader,
+    Overflow,
+}
+
+impl fmt::Display for NqError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NqError::BadHeader => write!(f, "bad netquake packet header"),
+            NqError::Overflow => write!(f, "netquake reliable message overflow"),
+        }
+    }
+}
+
+pub fn build_nq_connect() -> Vec<u8> {
+    build_control(CCREQ_CONNECT)
+}
+
+// Asks a server to describe itself without connecting, which is how an
+// unknown server is told apart from a QuakeWorld one.
+pub fn build_nq_server_info_request() -> Vec<u8> {
+    build_control(CCREQ_SERVER_INFO)
+}
+
+fn build_control(code: u8) -> Vec<u8> {
+    let mut body = SizeBuf::new(16);
+    let _ = body.write_u8(code);
+    let _ = body.write_string(Some("QUAKE"));
+    let _ = body.write_u8(NQ_NET_PROTOCOL_VERSION);
+    let mut packet = header(NETFLAG_CTL, 4 + body.len()).to_vec();
+    packet.extend_from_slice(body.as_slice());
+    packet
+}
+
+pub fn is_nq_control(packet: &[u8]) -> bool {
+    matches!(read_header(packet), Some((flags, _)) if flags & NETFLAG_CTL != 0)
+}
+
+pub fn parse_nq_control(packet: &[u8]) -> Option<NqControl> {
+    let (flags, _) = read_header(packet)?;
+    if flags & NETFLAG_CTL == 0 {
+        return None;
+    }
+    let mut reader = MsgReader::new(&packet[4..]);
+    let code = reader.read_u8().ok()?;
+    let control = match code {
+        CCREP_ACCEPT => NqControl::Accept {
+            port: reader.read_i32().ok()? as u16,
+        },
+        CCREP_REJECT => NqControl::Reject(reader.read_string().ok()?),
+        CCREP_SERVER_INFO => NqControl::ServerInfo {
+            address: reader.read_string().ok()?,
+            host_name: reader.read_string().ok()?,
+            level_name: reader.read_string().ok()?,
+            players: reader.read_u8().ok()?,
+            max_players: reader.read_u8().ok()?,
+            protocol: reader.read_u8().ok()?,
+        },
+        other => NqControl::Unknown(other),
+    };
+    Some(control)
+}
+
+fn header(flags: u32, length: usize) -> [u8; 4] {
+    (flags | length as u32).to_be_bytes()
+}
+
+// The flags and the length, which has to match the packet's.
+fn read_header(packet: &[u8]) -> Option<(u32, usize)> {
+    let word = u32::from_be_bytes(packet.get(..4)?.try_into().ok()?);
+    let length = (word & NETFLAG_LENGTH_MASK) as usize;
+    (length == packet.len()).then_some((word & !NETFLAG_LENGTH_MASK, length))
+}
+
+fn data_packet(flags: u32, sequence: u32, data: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(HEADER_SIZE + data.len());
+    packet.extend_from_slice(&header(flags, HEADER_SIZE + data.len()));
+    packet.extend_from_slice(&sequence.to_be_bytes());
+    packet.extend_from_slice(data);
+    packet
+}
+
+#[derive(Debug, Default)]
+pub struct NqChannel {
+    send_sequence: u32,
+    unreliable_send_sequence: u32,
+    receive_sequence: u32,
+    unreliable_receive_sequence: u32,
+    // Reliable data queued behind the message in flight.
+    pending: Vec<u8>,
+    // What is left of the message in flight, starting with the fragment
+    // waiting for its ack.
+    sending: Vec<u8>,
+    awaiting_ack: bool,
+    last_send: Option<Instant>,
+    receiving: Vec<u8>,
+    acks: VecDeque<u32>,
+}
+
+impl NqChannel {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn queue_reliable(&mut self, data: &[u8]) -> Result<(), NqError> {
+        if self.pending.len() + data.len() > NQ_MAX_MESSAGE {
+            return Err(NqError::Overflow);
+        }
+        self.pending.extend_from_slice(data);
+        Ok(())
+    }
+
+    pub fn build_unreliable(&mut self, data: &[u8]) -> Vec<u8> {
+        let sequence = self.unreliable_send_sequence;
+        self.unreliable_send_sequence = sequence.wrapping_add(1);
+        data_packet(NETFLAG_UNRELIABLE, sequence, data)
+    }
+
+    // Acks owed to the peer, then the next reliable fragment if one can go
+    // out or the last one has gone unacked for too long.
+    pub fn transmit(&mut self, now: Instant) -> Vec<Vec<u8>> {
+        let mut packets: Vec<_> = self
+            .acks
+            .drain(..)
+            .map(|sequence| data_packet(NETFLAG_ACK, sequence, &[]))
+            .collect();
+        if self.awaiting_ack {
+            let due = self
+                .last_send
+                .is_none_or(|sent| now.duration_since(sent) >= RESEND_TIME);
+            if due {
+                packets.push(self.fragment(self.send_sequence.wrapping_sub(1)));
+                self.last_send = Some(now);
+            }
+            return packets;
+        }
+        if self.sending.is_empty() {
+            self.sending = std::mem::take(&mut self.pending);
+        }
+        if !self.sending.is_empty() {
+            packets.push(self.fragment(self.send_sequence));
+            self.send_sequence = self.send_sequence.wrapping_add(1);
+            self.awaiting_ack = true;
+            self.last_send = Some(now);
+        }
+        packets
+    }
+
+    fn fragment(&self, sequence: u32) -> Vec<u8> {
+        let len = self.sending.len().min(NQ_MAX_DATAGRAM);
+        let eom = if len == self.sending.len() {
+            NETFLAG_EOM
+        } else {
+            0
+        };
+        data_packet(NETFLAG_DATA | eom, sequence, &self.sending[..len])
+    }
+
+    // Reliable data is acked even when it's a duplicate, since the ack for
+    // the first copy may have been lost.
+    pub fn process_packet(&mut self, packet: &[u8]) -> Result<Option<NqMessage>, NqError> {
+        let (flags, _) = read_header(packet).ok_or(NqError::BadHeader)?;
+        if flags & NETFLAG_CTL != 0 {
+            return Ok(parse_nq_control(packet).map(NqMessage::Control));
+        }
+        let sequence = packet
+            .get(4..HEADER_SIZE)
+            .map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            .ok_or(NqError::BadHeader)?;
+        let data = &packet[HEADER_SIZE..];
+
+        if flags & NETFLAG_UNRELIABLE != 0 {
+            if sequence < self.unreliable_receive_sequence {
+                return Ok(None);
+            }
+            self.unreliable_receive_sequence = sequence.wrapping_add(1);
+            return Ok(Some(NqMessage::Unreliable(data.to_vec())));
+        }
+
+        if flags & NETFLAG_ACK != 0 {
+            if self.awaiting_ack && sequence == self.send_sequence.wrapping_sub(1) {
+                let len = self.sending.len().min(NQ_MAX_DATAGRAM);
+                self.sending.drain(..len);
+                self.awaiting_ack = false;
+            }
+            return Ok(None);
+        }
+
+        if flags & NETFLAG_DATA != 0 {
+            self.acks.push_back(sequence);
+            if sequence != self.receive_sequence {
+                return Ok(None);
+            }
+            self.receive_sequence = sequence.wrapping_add(1);
+            if self.receiving.len() + data.len() > NQ_MAX_MESSAGE {
+                self.receiving.clear();
+                return Err(NqError::Overflow);
+            }
+            self.receiving.extend_from_slice(data);
+            if flags & NETFLAG_EOM != 0 {
+                return Ok(Some(NqMessage::Reliable(std::mem::take(
+                    &mut self.receiving,
+                ))));
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deliver(from: &mut NqChannel, to: &mut NqChannel, now: Instant) -> Vec<NqMessage> {
+        let mut messages = Vec::new();
+        for packet in from.transmit(now) {
+            messages.extend(to.process_packet(&packet).unwrap());
+        }
+        messages
+    }
+
+    #[test]
+    fn builds_and_parses_control_packets() {
+        let connect = build_nq_connect();
+        assert_eq!(&connect[..4], &[0x80, 0, 0, 12]);
+        assert_eq!(&connect[4..], b"\x01QUAKE\0\x03");
+        assert!(is_nq_control(&connect));
+        assert!(!is_nq_control(&[0xff, 0xff, 0xff, 0xff, b'c']));
+
+        let mut accept = header(NETFLAG_CTL, 9).to_vec();
+        accept.push(CCREP_ACCEPT);
+        accept.extend_from_slice(&26001i32.to_le_bytes());
+        assert_eq!(
+            parse_nq_control(&accept),
+            Some(NqControl::Accept { port: 26001 })
+        );
+
+        let mut info = header(NETFLAG_CTL, 0).to_vec();
+        info.push(CCREP_SERVER_INFO);
+        for text in ["10.0.0.1:26000", "host", "e1m1"] {
+            info.extend_from_slice(text.as_bytes());
+            info.push(0);
+        }
+        info.extend_from_slice(&[1, 8, NQ_NET_PROTOCOL_VERSION]);
+        let len = info.len();
+        info[..4].copy_from_slice(&header(NETFLAG_CTL, len));
+        assert!(matches!(
+            parse_nq_control(&info),
+            Some(NqControl::ServerInfo { max_players: 8, .. })
+        ));
+    }
+
+    #[test]
+    fn sends_reliable_messages_in_acked_fragments() {
+        let (mut client, mut server) = (NqChannel::new(), NqChannel::new());
+        let now = Instant::now();
+        let big: Vec<u8> = (0..2500u32).map(|n| n as u8).collect();
+        server.queue_reliable(&big).unwrap();
+
+        // One fragment at a time until the client's ack comes back, with
+        // anything queued meanwhile going out as the next message.
+        assert!(deliver(&mut server, &mut client, now).is_empty());
+        server.queue_reliable(b"next").unwrap();
+        assert!(server.transmit(now).is_empty());
+        assert!(deliver(&mut client, &mut server, now).is_empty());
+        assert!(deliver(&mut server, &mut client, now).is_empty());
+        deliver(&mut client, &mut server, now);
+        let messages = deliver(&mut server, &mut client, now);
+        assert_eq!(messages, [NqMessage::Reliable(big.clone())]);
+        deliver(&mut client, &mut server, now);
+        let messages = deliver(&mut server, &mut client, now);
+        assert_eq!(messages, [NqMessage::Reliable(b"next".to_vec())]);
+    }
+
+    #[test]
+    fn resends_unacked_fragments_and_drops_duplicates() {
+        let (mut client, mut server) = (NqChannel::new(), NqChannel::new());
+        let now = Instant::now();
+        server.queue_reliable(b"serverinfo").unwrap();
+        let lost = server.transmit(now);
+        assert_eq!(lost.len(), 1);
+        assert!(server.transmit(now + Duration::from_millis(500)).is_empty());
+
+        let resent = server.transmit(now + RESEND_TIME);
+        assert_eq!(resent, lost);
+        assert_eq!(
+            client.process_packet(&resent[0]).unwrap(),
+            Some(NqMessage::Reliable(b"serverinfo".to_vec()))
+        );
+        // The ack was lost too, so the server sends it a third time.
+        client.transmit(now);
+        let again = server.transmit(now + RESEND_TIME * 2);
+        assert_eq!(client.process_packet(&again[0]).unwrap(), None);
+        deliver(&mut client, &mut server, now);
+        assert!(server.transmit(now + RESEND_TIME * 3).is_empty());
+    }
+
+    #[test]
+    fn drops_stale_unreliable_datagrams() {
+        let (mut client, mut server) = (NqChannel::new(), NqChannel::new());
+        let first = server.build_unreliable(b"one");
+        let second = server.build_unreliable(b"two");
+        assert_eq!(
+            client.process_packet(&second).unwrap(),
+            Some(NqMessage::Unreliable(b"two".to_vec()))
+        );
+        assert_eq!(client.process_packet(&first).unwrap(), None);
+        assert_eq!(client.process_packet(&first[..6]), Err(NqError::BadHeader));
+    }
+}
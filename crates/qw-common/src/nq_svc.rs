@@ -0,0 +1,655 @@
+// NetQuake protocol 15 server messages, translated into the QuakeWorld
+// messages the client already understands. NetQuake sends each visible
+// entity as a "fast update" relative to its baseline rather than a delta
+// against an acked frame, so every datagram becomes one full
+// PacketEntities update. The player's own entity becomes a PlayerInfo.
+
+use crate::client_messages::{MoveVars, ServerData, StringListChunk};
+use crate::defs::PRINT_HIGH;
+use crate::msg::{MsgReadError, MsgReader, SizeBuf, SizeBufError};
+use crate::nq::NQ_PROTOCOL_VERSION;
+use crate::protocol::{
+    DEFAULT_SOUND_PACKET_ATTENUATION, DEFAULT_SOUND_PACKET_VOLUME, MAX_PACKET_ENTITIES, PF_EFFECTS,
+    PF_MODEL, PF_SKINNUM, PF_WEAPONFRAME, Svc, TE_EXPLOSION, TE_GUNSHOT, TE_KNIGHTSPIKE,
+    TE_LAVASPLASH, TE_LIGHTNING1, TE_LIGHTNING2, TE_LIGHTNING3, TE_SPIKE, TE_SUPERSPIKE,
+    TE_TAREXPLOSION, TE_TELEPORT, TE_WIZSPIKE,
+};
+use crate::svc::{
+    EntityDelta, PacketEntitiesUpdate, PlayerInfoMessage, SoundMessage, SvcMessage, SvcParseError,
+    TempEntityMessage, parse_clientdata, parse_particle,
+};
+use crate::types::{EntityState, UserCmd, Vec3};
+
+const SVC_NOP: u8 = 1;
+const SVC_DISCONNECT: u8 = 2;
+const SVC_UPDATESTAT: u8 = 3;
+const SVC_VERSION: u8 = 4;
+const SVC_SETVIEW: u8 = 5;
+const SVC_SOUND: u8 = 6;
+const SVC_TIME: u8 = 7;
+const SVC_PRINT: u8 = 8;
+const SVC_STUFFTEXT: u8 = 9;
+const SVC_SETANGLE: u8 = 10;
+const SVC_SERVERINFO: u8 = 11;
+const SVC_LIGHTSTYLE: u8 = 12;
+const SVC_UPDATENAME: u8 = 13;
+const SVC_UPDATEFRAGS: u8 = 14;
+const SVC_CLIENTDATA: u8 = 15;
+const SVC_STOPSOUND: u8 = 16;
+const SVC_UPDATECOLORS: u8 = 17;
+const SVC_PARTICLE: u8 = 18;
+const SVC_DAMAGE: u8 = 19;
+const SVC_SPAWNSTATIC: u8 = 20;
+const SVC_SPAWNBASELINE: u8 = 22;
+const SVC_TEMP_ENTITY: u8 = 23;
+const SVC_SETPAUSE: u8 = 24;
+const SVC_SIGNONNUM: u8 = 25;
+const SVC_CENTERPRINT: u8 = 26;
+const SVC_KILLEDMONSTER: u8 = 27;
+const SVC_FOUNDSECRET: u8 = 28;
+const SVC_SPAWNSTATICSOUND: u8 = 29;
+const SVC_INTERMISSION: u8 = 30;
+const SVC_FINALE: u8 = 31;
+const SVC_CDTRACK: u8 = 32;
+const SVC_SELLSCREEN: u8 = 33;
+const SVC_CUTSCENE: u8 = 34;
+
+const CLC_MOVE: u8 = 3;
+
+// Fast update bits; the command byte itself has the top bit set.
+const NQ_U_SIGNAL: u8 = 0x80;
+const NQ_U_MOREBITS: u16 = 1 << 0;
+const NQ_U_ORIGIN1: u16 = 1 << 1;
+const NQ_U_ORIGIN2: u16 = 1 << 2;
+const NQ_U_ORIGIN3: u16 = 1 << 3;
+const NQ_U_ANGLE2: u16 = 1 << 4;
+const NQ_U_FRAME: u16 = 1 << 6;
+const NQ_U_ANGLE1: u16 = 1 << 8;
+const NQ_U_ANGLE3: u16 = 1 << 9;
+const NQ_U_MODEL: u16 = 1 << 10;
+const NQ_U_COLORMAP: u16 = 1 << 11;
+const NQ_U_SKIN: u16 = 1 << 12;
+const NQ_U_EFFECTS: u16 = 1 << 13;
+const NQ_U_LONGENTITY: u16 = 1 << 14;
+
+const NQ_SND_VOLUME: u8 = 1 << 0;
+const NQ_SND_ATTENUATION: u8 = 1 << 1;
+
+const NQ_TE_EXPLOSION2: u8 = 12;
+const NQ_TE_BEAM: u8 = 13;
+
+// NetQuake never sends movement variables, so these are its sv_* defaults.
+fn nq_movevars() -> MoveVars {
+    MoveVars {
+        gravity: 800.0,
+        stopspeed: 100.0,
+        maxspeed: 320.0,
+        spectatormaxspeed: 320.0,
+        accelerate: 10.0,
+        airaccelerate: 10.0,
+        wateraccelerate: 10.0,
+        friction: 4.0,
+        waterfriction: 4.0,
+        entgravity: 1.0,
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct NqParser {
+    baselines: Vec<EntityState>,
+    server_count: i32,
+    view_entity: u16,
+    view: EntityState,
+    view_angles: Vec3,
+    velocity: Vec3,
+    weapon_frame: u8,
+}
+
+impl NqParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn parse(&mut self, data: &[u8]) -> Result<Vec<SvcMessage>, SvcParseError> {
+        let mut reader = MsgReader::new(data);
+        let mut messages = Vec::new();
+        let mut entities = Vec::new();
+        let mut view_updated = false;
+        while reader.remaining() > 0 {
+            let cmd = reader.read_u8()?;
+            if cmd & NQ_U_SIGNAL != 0 {
+                let state = self.parse_fast_update(&mut reader, cmd)?;
+                if state.number == self.view_entity as i32 && self.view_entity != 0 {
+                    self.view = state;
+                    view_updated = true;
+                } else if entities.len() < MAX_PACKET_ENTITIES {
+                    entities.push(full_delta(&state));
+                }
+                continue;
+            }
+            self.parse_message(&mut reader, cmd, &mut messages)?;
+        }
+        if view_updated {
+            messages.push(SvcMessage::PlayerInfo(self.view_player_info()));
+        }
+        if view_updated || !entities.is_empty() {
+            messages.push(SvcMessage::PacketEntities(PacketEntitiesUpdate {
+                delta_from: None,
+                entities,
+            }));
+        }
+        Ok(messages)
+    }
+
+    fn parse_message(
+        &mut self,
+        reader: &mut MsgReader,
+        cmd: u8,
+        messages: &mut Vec<SvcMessage>,
+    ) -> Result<(), SvcParseError> {
+        let message = match cmd {
+            SVC_NOP => SvcMessage::Nop,
+            SVC_DISCONNECT => SvcMessage::Disconnect,
+            SVC_UPDATESTAT => SvcMessage::UpdateStatLong {
+                index: reader.read_u8()?,
+                value: reader.read_i32()?,
+            },
+            SVC_VERSION => SvcMessage::Version(reader.read_i32()?),
+            SVC_SETVIEW => {
+                self.view_entity = reader.read_u16()?;
+                SvcMessage::SetView {
+                    entity: self.view_entity,
+                }
+            }
+            SVC_SOUND => SvcMessage::Sound(parse_nq_sound(reader)?),
+            SVC_TIME => SvcMessage::Time(reader.read_f32()?),
+            SVC_PRINT => SvcMessage::Print {
+                level: PRINT_HIGH,
+                message: reader.read_string()?,
+            },
+            SVC_STUFFTEXT => SvcMessage::StuffText(reader.read_string()?),
+            SVC_SETANGLE => {
+                self.view_angles = read_angles(reader)?;
+                SvcMessage::SetAngle(self.view_angles)
+            }
+            SVC_SERVERINFO => {
+                self.parse_serverinfo(reader, messages)?;
+                return Ok(());
+            }
+            SVC_LIGHTSTYLE => SvcMessage::LightStyle {
+                style: reader.read_u8()?,
+                value: reader.read_string()?,
+            },
+            SVC_UPDATENAME => SvcMessage::UpdateName {
+                slot: reader.read_u8()?,
+                name: reader.read_string()?,
+            },
+            SVC_UPDATEFRAGS => SvcMessage::UpdateFrags {
+                slot: reader.read_u8()?,
+                frags: reader.read_i16()?,
+            },
+            SVC_CLIENTDATA => {
+                let data = parse_clientdata(reader)?;
+                self.velocity = data.velocity;
+                self.weapon_frame = data.weapon_frame;
+                SvcMessage::ClientData(data)
+            }
+            SVC_STOPSOUND => {
+                let value = reader.read_u16()?;
+                SvcMessage::StopSound {
+                    entity: value >> 3,
+                    channel: (value & 7) as u8,
+                }
+            }
+            SVC_UPDATECOLORS => SvcMessage::UpdateColors {
+                slot: reader.read_u8()?,
+                colors: reader.read_u8()?,
+            },
+            SVC_PARTICLE => SvcMessage::Particle(parse_particle(reader)?),
+            SVC_DAMAGE => SvcMessage::Damage {
+                armor: reader.read_u8()?,
+                blood: reader.read_u8()?,
+                origin: read_coords(reader)?,
+            },
+            SVC_SPAWNSTATIC => SvcMessage::SpawnStatic(parse_nq_baseline(reader)?),
+            SVC_SPAWNBASELINE => {
+                let entity = reader.read_u16()?;
+                let mut baseline = parse_nq_baseline(reader)?;
+                baseline.number = entity as i32;
+                let index = entity as usize;
+                if self.baselines.len() <= index {
+                    self.baselines.resize(index + 1, EntityState::default());
+                }
+                self.baselines[index] = baseline;
+                SvcMessage::SpawnBaseline { entity, baseline }
+            }
+            SVC_TEMP_ENTITY => SvcMessage::TempEntity(parse_nq_temp_entity(reader)?),
+            SVC_SETPAUSE => SvcMessage::SetPause(reader.read_u8()? != 0),
+            SVC_SIGNONNUM => SvcMessage::SignonNum(reader.read_u8()?),
+            SVC_CENTERPRINT => SvcMessage::CenterPrint(reader.read_string()?),
+            SVC_KILLEDMONSTER => SvcMessage::KilledMonster,
+            SVC_FOUNDSECRET => SvcMessage::FoundSecret,
+            SVC_SPAWNSTATICSOUND => SvcMessage::SpawnStaticSound {
+                origin: read_coords(reader)?,
+                sound: reader.read_u8()?,
+                volume: reader.read_u8()?,
+                attenuation: reader.read_u8()?,
+            },
+            SVC_INTERMISSION => SvcMessage::Intermission {
+                origin: self.view.origin,
+                angles: self.view_angles,
+            },
+            SVC_FINALE | SVC_CUTSCENE => SvcMessage::Finale(reader.read_string()?),
+            SVC_CDTRACK => {
+                let track = reader.read_u8()?;
+                let _loop_track = reader.read_u8()?;
+                SvcMessage::CdTrack(track)
+            }
+            SVC_SELLSCREEN => SvcMessage::SellScreen,
+            other => return Err(SvcParseError::UnknownSvc(other)),
+        };
+        messages.push(message);
+        Ok(())
+    }
+
+    // serverinfo carries the whole precache list at once, so it becomes a
+    // ServerData followed by complete sound and model lists.
+    fn parse_serverinfo(
+        &mut self,
+        reader: &mut MsgReader,
+        messages: &mut Vec<SvcMessage>,
+    ) -> Result<(), SvcParseError> {
+        let protocol = reader.read_i32()?;
+        if protocol != NQ_PROTOCOL_VERSION {
+            return Err(SvcParseError::UnsupportedProtocol(protocol));
+        }
+        let _max_clients = reader.read_u8()?;
+        let _game_type = reader.read_u8()?;
+        let title = reader.read_string()?;
+        let models = read_precache_list(reader)?;
+        let sounds = read_precache_list(reader)?;
+
+        *self = Self {
+            server_count: self.server_count.wrapping_add(1),
+            ..Self::default()
+        };
+        let level_name = models
+            .first()
+            .map(|world| {
+                let name = world.strip_prefix("maps/").unwrap_or(world);
+                name.strip_suffix(".bsp").unwrap_or(name).to_string()
+            })
+            .unwrap_or_default();
+        messages.push(SvcMessage::ServerData(ServerData {
+            protocol,
+            server_count: self.server_count,
+            game_dir: "id1".to_string(),
+            player_num: 0,
+            spectator: false,
+            level_name,
+            movevars: nq_movevars(),
+        }));
+        messages.push(SvcMessage::Print {
+            level: PRINT_HIGH,
+            message: format!("{title}\n"),
+        });
+        messages.push(SvcMessage::SoundList(StringListChunk {
+            start: 1,
+            items: sounds,
+            next: 0,
+        }));
+        messages.push(SvcMessage::ModelList(StringListChunk {
+            start: 1,
+            items: models,
+            next: 0,
+        }));
+        Ok(())
+    }
+
+    fn parse_fast_update(
+        &self,
+        reader: &mut MsgReader,
+        cmd: u8,
+    ) -> Result<EntityState, MsgReadError> {
+        let mut bits = (cmd & !NQ_U_SIGNAL) as u16;
+        if bits & NQ_U_MOREBITS != 0 {
+            bits |= (reader.read_u8()? as u16) << 8;
+        }
+        let number = if bits & NQ_U_LONGENTITY != 0 {
+            reader.read_u16()?
+        } else {
+            reader.read_u8()? as u16
+        };
+        let mut state = self
+            .baselines
+            .get(number as usize)
+            .copied()
+            .unwrap_or_default();
+        state.number = number as i32;
+        if bits & NQ_U_MODEL != 0 {
+            state.modelindex = reader.read_u8()? as i32;
+        }
+        if bits & NQ_U_FRAME != 0 {
+            state.frame = reader.read_u8()? as i32;
+        }
+        if bits & NQ_U_COLORMAP != 0 {
+            state.colormap = reader.read_u8()? as i32;
+        }
+        if bits & NQ_U_SKIN != 0 {
+            state.skinnum = reader.read_u8()? as i32;
+        }
+        if bits & NQ_U_EFFECTS != 0 {
+            state.effects = reader.read_u8()? as i32;
+        }
+        if bits & NQ_U_ORIGIN1 != 0 {
+            state.origin.x = reader.read_coord()?;
+        }
+        if bits & NQ_U_ANGLE1 != 0 {
+            state.angles.x = reader.read_angle()?;
+        }
+        if bits & NQ_U_ORIGIN2 != 0 {
+            state.origin.y = reader.read_coord()?;
+        }
+        if bits & NQ_U_ANGLE2 != 0 {
+            state.angles.y = reader.read_angle()?;
+        }
+        if bits & NQ_U_ORIGIN3 != 0 {
+            state.origin.z = reader.read_coord()?;
+        }
+        if bits & NQ_U_ANGLE3 != 0 {
+            state.angles.z = reader.read_angle()?;
+        }
+        Ok(state)
+    }
+
+    fn view_player_info(&self) -> PlayerInfoMessage {
+        let velocity = self.velocity;
+        PlayerInfoMessage {
+            num: self.view_entity.saturating_sub(1) as u8,
+            flags: (PF_MODEL | PF_SKINNUM | PF_EFFECTS | PF_WEAPONFRAME) as u16,
+            origin: self.view.origin,
+            frame: self.view.frame as u8,
+            msec: None,
+            command: None,
+            velocity: [velocity.x as i16, velocity.y as i16, velocity.z as i16],
+            model_index: Some(self.view.modelindex as u8),
+            skin_num: Some(self.view.skinnum as u8),
+            effects: Some(self.view.effects as u8),
+            weapon_frame: Some(self.weapon_frame),
+        }
+    }
+}
+
+fn full_delta(state: &EntityState) -> EntityDelta {
+    EntityDelta {
+        number: state.number as u16,
+        remove: false,
+        flags: 0,
+        model_index: Some(state.modelindex as u8),
+        frame: Some(state.frame as u8),
+        colormap: Some(state.colormap as u8),
+        skin_num: Some(state.skinnum as u8),
+        effects: Some(state.effects as u8),
+        origin: [
+            Some(state.origin.x),
+            Some(state.origin.y),
+            Some(state.origin.z),
+        ],
+        angles: [
+            Some(state.angles.x),
+            Some(state.angles.y),
+            Some(state.angles.z),
+        ],
+        solid: false,
+    }
+}
+
+fn read_coords(reader: &mut MsgReader) -> Result<Vec3, MsgReadError> {
+    Ok(Vec3::new(
+        reader.read_coord()?,
+        reader.read_coord()?,
+        reader.read_coord()?,
+    ))
+}
+
+fn read_angles(reader: &mut MsgReader) -> Result<Vec3, MsgReadError> {
+    Ok(Vec3::new(
+        reader.read_angle()?,
+        reader.read_angle()?,
+        reader.read_angle()?,
+    ))
+}
+
+fn read_precache_list(reader: &mut MsgReader) -> Result<Vec<String>, MsgReadError> {
+    let mut items = Vec::new();
+    loop {
+        let item = reader.read_string()?;
+        if item.is_empty() {
+            return Ok(items);
+        }
+        items.push(item);
+    }
+}
+
+// Unlike QuakeWorld's, the origin and angle of each axis are interleaved.
+fn parse_nq_baseline(reader: &mut MsgReader) -> Result<EntityState, MsgReadError> {
+    let mut state = EntityState {
+        modelindex: reader.read_u8()? as i32,
+        frame: reader.read_u8()? as i32,
+        colormap: reader.read_u8()? as i32,
+        skinnum: reader.read_u8()? as i32,
+        ..EntityState::default()
+    };
+    state.origin.x = reader.read_coord()?;
+    state.angles.x = reader.read_angle()?;
+    state.origin.y = reader.read_coord()?;
+    state.angles.y = reader.read_angle()?;
+    state.origin.z = reader.read_coord()?;
+    state.angles.z = reader.read_angle()?;
+    Ok(state)
+}
+
+fn parse_nq_sound(reader: &mut MsgReader) -> Result<SoundMessage, MsgReadError> {
+    let field = reader.read_u8()?;
+    let volume = if field & NQ_SND_VOLUME != 0 {
+        reader.read_u8()?
+    } else {
+        DEFAULT_SOUND_PACKET_VOLUME
+    };
+    let attenuation = if field & NQ_SND_ATTENUATION != 0 {
+        reader.read_u8()? as f32 / 64.0
+    } else {
+        DEFAULT_SOUND_PACKET_ATTENUATION
+    };
+    let channel = reader.read_u16()?;
+    let sound_num = reader.read_u8()?;
+    let origin = read_coords(reader)?;
+    Ok(SoundMessage {
+        entity: channel >> 3,
+        channel: (channel & 7) as u8,
+        sound_num,
+        volume,
+        attenuation,
+        origin,
+    })
+}
+
+// QuakeWorld reuses codes 12 and 13 for blood, so NetQuake's colored
+// explosion and beam are shown as the closest QuakeWorld effects.
+fn parse_nq_temp_entity(reader: &mut MsgReader) -> Result<TempEntityMessage, SvcParseError> {
+    let kind = reader.read_u8()?;
+    let mut message = TempEntityMessage {
+        kind,
+        origin: None,
+        start: None,
+        end: None,
+        count: None,
+        entity: None,
+    };
+    match kind {
+        TE_LIGHTNING1 | TE_LIGHTNING2 | TE_LIGHTNING3 | NQ_TE_BEAM => {
+            if kind == NQ_TE_BEAM {
+                message.kind = TE_LIGHTNING1;
+            }
+            message.entity = Some(reader.read_u16()?);
+            message.start = Some(read_coords(reader)?);
+            message.end = Some(read_coords(reader)?);
+        }
+        NQ_TE_EXPLOSION2 => {
+            message.kind = TE_EXPLOSION;
+            message.origin = Some(read_coords(reader)?);
+            let _color_start = reader.read_u8()?;
+            let _color_length = reader.read_u8()?;
+        }
+        TE_GUNSHOT => {
+            message.origin = Some(read_coords(reader)?);
+            message.count = Some(1);
+        }
+        TE_SPIKE | TE_SUPERSPIKE | TE_EXPLOSION | TE_TAREXPLOSION | TE_WIZSPIKE
+        | TE_KNIGHTSPIKE | TE_LAVASPLASH | TE_TELEPORT => {
+            message.origin = Some(read_coords(reader)?);
+        }
+        _ => return Err(SvcParseError::UnsupportedSvc(Svc::TempEntity)),
+    }
+    Ok(message)
+}
+
+pub fn write_nq_move(buf: &mut SizeBuf, time: f32, cmd: &UserCmd) -> Result<(), SizeBufError> {
+    buf.write_u8(CLC_MOVE)?;
+    buf.write_f32(time)?;
+    buf.write_angle(cmd.angles.x)?;
+    buf.write_angle(cmd.angles.y)?;
+    buf.write_angle(cmd.angles.z)?;
+    buf.write_i16(cmd.forwardmove)?;
+    buf.write_i16(cmd.sidemove)?;
+    buf.write_i16(cmd.upmove)?;
+    buf.write_u8(cmd.buttons)?;
+    buf.write_u8(cmd.impulse)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn serverinfo(buf: &mut SizeBuf) {
+        buf.write_u8(SVC_SERVERINFO).unwrap();
+        buf.write_i32(NQ_PROTOCOL_VERSION).unwrap();
+        buf.write_u8(4).unwrap();
+        buf.write_u8(0).unwrap();
+        buf.write_string(Some("The Slipgate Complex")).unwrap();
+        for model in ["maps/e1m1.bsp", "progs/player.mdl", ""] {
+            buf.write_string(Some(model)).unwrap();
+        }
+        for sound in ["weapons/r_exp3.wav", ""] {
+            buf.write_string(Some(sound)).unwrap();
+        }
+    }
+
+    #[test]
+    fn translates_serverinfo_into_serverdata_and_lists() {
+        let mut buf = SizeBuf::new(256);
+        serverinfo(&mut buf);
+        buf.write_u8(SVC_UPDATESTAT).unwrap();
+        buf.write_u8(0).unwrap();
+        buf.write_i32(100).unwrap();
+        let messages = NqParser::new().parse(buf.as_slice()).unwrap();
+
+        let SvcMessage::ServerData(data) = &messages[0] else {
+            panic!("expected serverdata, got {:?}", messages[0]);
+        };
+        assert_eq!(data.level_name, "e1m1");
+        assert_eq!(data.protocol, NQ_PROTOCOL_VERSION);
+        assert!(matches!(
+            &messages[3],
+            SvcMessage::ModelList(list) if list.start == 1 && list.items[1] == "progs/player.mdl"
+        ));
+        assert!(matches!(
+            messages[4],
+            SvcMessage::UpdateStatLong {
+                index: 0,
+                value: 100
+            }
+        ));
+
+        let mut old = SizeBuf::new(16);
+        old.write_u8(SVC_SERVERINFO).unwrap();
+        old.write_i32(666).unwrap();
+        assert!(matches!(
+            NqParser::new().parse(old.as_slice()),
+            Err(SvcParseError::UnsupportedProtocol(666))
+        ));
+    }
+
+    #[test]
+    fn applies_fast_updates_to_baselines() {
+        let mut parser = NqParser::new();
+        let mut buf = SizeBuf::new(256);
+        serverinfo(&mut buf);
+        buf.write_u8(SVC_SETVIEW).unwrap();
+        buf.write_u16(1).unwrap();
+        for entity in [1u16, 300] {
+            buf.write_u8(SVC_SPAWNBASELINE).unwrap();
+            buf.write_u16(entity).unwrap();
+            buf.write_bytes(&[2, 0, 0, 0]).unwrap();
+            for axis in [10.0, 20.0, 30.0] {
+                buf.write_coord(axis).unwrap();
+                buf.write_angle(0.0).unwrap();
+            }
+        }
+        parser.parse(buf.as_slice()).unwrap();
+
+        let mut buf = SizeBuf::new(64);
+        // The player moves along x and the far entity changes frame.
+        buf.write_u8(NQ_U_SIGNAL | NQ_U_ORIGIN1 as u8).unwrap();
+        buf.write_u8(1).unwrap();
+        buf.write_coord(64.0).unwrap();
+        let bits = NQ_U_MOREBITS | NQ_U_FRAME | NQ_U_LONGENTITY;
+        buf.write_u8(NQ_U_SIGNAL | bits as u8).unwrap();
+        buf.write_u8((bits >> 8) as u8).unwrap();
+        buf.write_u16(300).unwrap();
+        buf.write_u8(5).unwrap();
+        let messages = parser.parse(buf.as_slice()).unwrap();
+
+        let SvcMessage::PlayerInfo(player) = &messages[0] else {
+            panic!("expected playerinfo, got {:?}", messages[0]);
+        };
+        assert_eq!(player.num, 0);
+        assert_eq!(player.origin, Vec3::new(64.0, 20.0, 30.0));
+        let SvcMessage::PacketEntities(update) = &messages[1] else {
+            panic!("expected packet entities, got {:?}", messages[1]);
+        };
+        assert_eq!(update.delta_from, None);
+        assert_eq!(update.entities.len(), 1);
+        let state = update.entities[0].apply_to(&EntityState::default());
+        assert_eq!(state.number, 300);
+        assert_eq!(state.frame, 5);
+        assert_eq!(state.modelindex, 2);
+        assert_eq!(state.origin, Vec3::new(10.0, 20.0, 30.0));
+    }
+
+    #[test]
+    fn writes_nq_move() {
+        let cmd = UserCmd {
+            angles: Vec3::new(0.0, 90.0, 0.0),
+            forwardmove: 200,
+            buttons: 1,
+            impulse: 7,
+            ..UserCmd::default()
+        };
+        let mut buf = SizeBuf::new(32);
+        write_nq_move(&mut buf, 1.5, &cmd).unwrap();
+        let mut reader = MsgReader::new(buf.as_slice());
+        assert_eq!(reader.read_u8().unwrap(), CLC_MOVE);
+        assert_eq!(reader.read_f32().unwrap(), 1.5);
+        assert_eq!(reader.read_angle().unwrap(), 0.0);
+        assert_eq!(reader.read_angle().unwrap(), 90.0);
+        reader.read_angle().unwrap();
+        assert_eq!(reader.read_i16().unwrap(), 200);
+        assert_eq!(reader.read_i16().unwrap(), 0);
+        assert_eq!(reader.read_i16().unwrap(), 0);
+        assert_eq!(reader.read_u8().unwrap(), 1);
+        assert_eq!(reader.read_u8().unwrap(), 7);
+        assert_eq!(reader.remaining(), 0);
+    }
+}
@@ -17,6 +17,8 @@ pub const A2C_PRINT: u8 = b'n';
 pub const S2M_HEARTBEAT: u8 = b'a';
 pub const A2C_CLIENT_COMMAND: u8 = b'B';
 pub const S2M_SHUTDOWN: u8 = b'C';
+pub const C2M_MASTER_SERVER_LIST: u8 = b'c';
+pub const M2C_MASTER_REPLY: u8 = b'd';
 
 #[repr(u8)]
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -333,6 +333,7 @@ pub enum SvcParseError {
     Read(MsgReadError),
     UnknownSvc(u8),
     UnsupportedSvc(Svc),
+    UnsupportedProtocol(i32),
 }
 
 impl From<MsgReadError> for SvcParseError {
@@ -346,16 +347,15 @@ fn parse_baseline(reader: &mut MsgReader) -> Result<EntityState, MsgReadError> {
     let frame = reader.read_u8()? as i32;
     let colormap = reader.read_u8()? as i32;
     let skinnum = reader.read_u8()? as i32;
-    let origin = Vec3::new(
-        reader.read_coord()?,
-        reader.read_coord()?,
-        reader.read_coord()?,
-    );
-    let angles = Vec3::new(
-        reader.read_angle()?,
-        reader.read_angle()?,
-        reader.read_angle()?,
-    );
+    // Each axis carries its coordinate and then its angle.
+    let mut origin = [0.0; 3];
+    let mut angles = [0.0; 3];
+    for axis in 0..3 {
+        origin[axis] = reader.read_coord()?;
+        angles[axis] = reader.read_angle()?;
+    }
+    let origin = Vec3::new(origin[0], origin[1], origin[2]);
+    let angles = Vec3::new(angles[0], angles[1], angles[2]);
 
     Ok(EntityState {
         number: 0,
@@ -402,7 +402,7 @@ fn parse_sound(reader: &mut MsgReader) -> Result<SoundMessage, MsgReadError> {
     })
 }
 
-fn parse_clientdata(reader: &mut MsgReader) -> Result<ClientDataMessage, MsgReadError> {
+pub(crate) fn parse_clientdata(reader: &mut MsgReader) -> Result<ClientDataMessage, MsgReadError> {
     let bits = reader.read_u16()?;
     let view_height = if bits & SU_VIEWHEIGHT != 0 {
         reader.read_i8()?
@@ -755,10 +755,10 @@ fn write_baseline(buf: &mut SizeBuf, baseline: &EntityState) -> Result<(), SizeB
     buf.write_u8(baseline.colormap as u8)?;
     buf.write_u8(baseline.skinnum as u8)?;
     buf.write_coord(baseline.origin.x)?;
-    buf.write_coord(baseline.origin.y)?;
-    buf.write_coord(baseline.origin.z)?;
     buf.write_angle(baseline.angles.x)?;
+    buf.write_coord(baseline.origin.y)?;
     buf.write_angle(baseline.angles.y)?;
+    buf.write_coord(baseline.origin.z)?;
     buf.write_angle(baseline.angles.z)?;
     Ok(())
 }
@@ -810,7 +810,7 @@ fn write_entity_delta(buf: &mut SizeBuf, delta: &EntityDelta) -> Result<(), Size
     Ok(())
 }
 
-fn parse_particle(reader: &mut MsgReader) -> Result<ParticleEffect, MsgReadError> {
+pub(crate) fn parse_particle(reader: &mut MsgReader) -> Result<ParticleEffect, MsgReadError> {
     let origin = Vec3::new(
         reader.read_coord()?,
         reader.read_coord()?,
@@ -0,0 +1,137 @@
+// What the client and server send packets through: a UDP socket normally, or an
+// in-process pipe for local play and tests. Packets go out as owned buffers so a
+// transport that queues them doesn't have to copy.
+
+use std::borrow::Cow;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+
+pub trait Transport {
+    fn send_to(&mut self, packet: Vec<u8>, addr: SocketAddr) -> io::Result<()>;
+
+    // Waits up to `wait` for a packet; a zero wait only polls. Packets are
+    // read into `buf` or handed over whole, whichever the transport can do.
+    fn recv_from<'a>(
+        &mut self,
+        buf: &'a mut [u8],
+        wait: Duration,
+    ) -> io::Result<Option<(Cow<'a, [u8]>, SocketAddr)>>;
+
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+}
+
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    fn send_to(&mut self, packet: Vec<u8>, addr: SocketAddr) -> io::Result<()> {
+        (**self).send_to(packet, addr)
+    }
+
+    fn recv_from<'a>(
+        &mut self,
+        buf: &'a mut [u8],
+        wait: Duration,
+    ) -> io::Result<Option<(Cow<'a, [u8]>, SocketAddr)>> {
+        (**self).recv_from(buf, wait)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        (**self).local_addr()
+    }
+}
+
+pub struct UdpTransport {
+    socket: UdpSocket,
+    // The read timeout the socket is set to; None while it is non-blocking.
+    wait: Option<Duration>,
+}
+
+impl UdpTransport {
+    pub fn bind(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self { socket, wait: None })
+    }
+
+    fn set_wait(&mut self, wait: Duration) -> io::Result<()> {
+        let wait = (!wait.is_zero()).then_some(wait);
+        if wait != self.wait {
+            self.socket.set_nonblocking(wait.is_none())?;
+            if wait.is_some() {
+                self.socket.set_read_timeout(wait)?;
+            }
+            self.wait = wait;
+        }
+        Ok(())
+    }
+}
+
+impl Transport for UdpTransport {
+    fn send_to(&mut self, packet: Vec<u8>, addr: SocketAddr) -> io::Result<()> {
+        self.socket.send_to(&packet, addr).map(|_| ())
+    }
+
+    fn recv_from<'a>(
+        &mut self,
+        buf: &'a mut [u8],
+        wait: Duration,
+    ) -> io::Result<Option<(Cow<'a, [u8]>, SocketAddr)>> {
+        self.set_wait(wait)?;
+        match self.socket.recv_from(buf) {
+            Ok((len, addr)) => Ok(Some((Cow::Borrowed(&buf[..len]), addr))),
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                Ok(None)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{LOOPBACK_ADDR, loopback_pair};
+
+    fn round_trip(a: &mut dyn Transport, b: &mut dyn Transport) {
+        let b_addr = b.local_addr().unwrap();
+        a.send_to(b"ping".to_vec(), b_addr).unwrap();
+        let mut buf = [0u8; 16];
+        let (packet, from) = b
+            .recv_from(&mut buf, Duration::from_millis(500))
+            .unwrap()
+            .expect("packet arrived");
+        assert_eq!(&*packet, b"ping");
+
+        b.send_to(b"pong".to_vec(), from).unwrap();
+        let (packet, _) = a
+            .recv_from(&mut buf, Duration::from_millis(500))
+            .unwrap()
+            .expect("reply arrived");
+        assert_eq!(&*packet, b"pong");
+        assert!(a.recv_from(&mut buf, Duration::ZERO).unwrap().is_none());
+    }
+
+    #[test]
+    fn udp_sends_and_waits_for_packets() {
+        let mut a = UdpTransport::bind("127.0.0.1:0").unwrap();
+        let mut b = UdpTransport::bind("127.0.0.1:0").unwrap();
+        round_trip(&mut a, &mut b);
+    }
+
+    #[test]
+    fn loopback_behaves_like_a_socket() {
+        let (a, b) = loopback_pair();
+        let mut a: Box<dyn Transport> = Box::new(a);
+        let mut b: Box<dyn Transport> = Box::new(b);
+        assert_eq!(a.local_addr().unwrap(), LOOPBACK_ADDR);
+        round_trip(&mut a, &mut b);
+    }
+}
@@ -0,0 +1,511 @@
+// Text listings of a progs.dat: defs, strings, functions and disassembly.
+
+use qw_qc::opcodes::{Operand, is_call, opcode_info};
+use qw_qc::{Definition, ProgsDat, QcType, Statement};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Write;
+use std::ops::Range;
+
+const OFS_RETURN: i16 = 1;
+const OFS_PARM0: i16 = 4;
+const PARM_COUNT: i16 = 8;
+
+pub struct ProgsDump<'a> {
+    progs: &'a ProgsDat,
+    globals_by_offset: HashMap<i16, &'a Definition>,
+    fields_by_offset: HashMap<i16, &'a Definition>,
+}
+
+impl<'a> ProgsDump<'a> {
+    pub fn new(progs: &'a ProgsDat) -> Self {
+        Self {
+            progs,
+            globals_by_offset: index_defs(&progs.global_defs),
+            fields_by_offset: index_defs(&progs.field_defs),
+        }
+    }
+
+    pub fn summary(&self) -> String {
+        let progs = self.progs;
+        let flavor = progs
+            .flavor()
+            .map(|flavor| flavor.name())
+            .unwrap_or("unknown");
+        let mut out = String::new();
+        let _ = writeln!(out, "version      {}", progs.version);
+        let _ = writeln!(out, "crc          {} ({flavor})", progs.crc);
+        let _ = writeln!(out, "statements   {}", progs.statements.len());
+        let _ = writeln!(out, "globaldefs   {}", progs.global_defs.len());
+        let _ = writeln!(out, "fielddefs    {}", progs.field_defs.len());
+        let _ = writeln!(out, "functions    {}", progs.functions.len());
+        let _ = writeln!(out, "strings      {} bytes", progs.strings.len());
+        let _ = writeln!(out, "globals      {}", progs.globals.len());
+        let _ = writeln!(out, "entityfields {}", progs.entity_fields);
+        if let Err(err) = progs.validate() {
+            let _ = writeln!(out, "validation   {err}");
+        }
+        out
+    }
+
+    pub fn functions(&self) -> String {
+        let mut out = String::new();
+        for (index, func) in self.progs.functions.iter().enumerate() {
+            let entry = if func.first_statement < 0 {
+                format!("builtin #{}", func.first_statement.unsigned_abs())
+            } else {
+                format!("statement {}", func.first_statement)
+            };
+            let param_count = func.num_params.clamp(0, 8) as usize;
+            let sizes = func.param_sizes[..param_count]
+                .iter()
+                .map(|size| size.to_string())
+                .collect::<Vec<_>>()
+                .join(",");
+            let _ = writeln!(
+                out,
+                "{index:5} {:<28} {entry:<16} parms {}({sizes}) locals {} parm_start {} {}",
+                func.name, func.num_params, func.locals, func.parm_start, func.file
+            );
+        }
+        out
+    }
+
+    pub fn globals(&self) -> String {
+        let mut out = String::new();
+        for def in &self.progs.global_defs {
+            let value = self.constant_value(def.offset, def.ty);
+            let save = if def.save_global { "*" } else { " " };
+            let _ = writeln!(
+                out,
+                "{:5} {save}{:<9} {:<28} = {value}",
+                def.offset,
+                def.ty.name(),
+                def.name
+            );
+        }
+        out
+    }
+
+    pub fn fields(&self) -> String {
+        let mut out = String::new();
+        for def in &self.progs.field_defs {
+            let _ = writeln!(out, "{:5} {:<9} {}", def.offset, def.ty.name(), def.name);
+        }
+        out
+    }
+
+    pub fn strings(&self) -> String {
+        let mut out = String::new();
+        let mut start = 0usize;
+        let bytes = &self.progs.strings;
+        while start < bytes.len() {
+            let end = bytes[start..]
+                .iter()
+                .position(|&b| b == 0)
+                .map(|idx| start + idx)
+                .unwrap_or(bytes.len());
+            if end > start {
+                let text = String::from_utf8_lossy(&bytes[start..end]);
+                let _ = writeln!(out, "{start:7} {}", quote(&text));
+            }
+            start = end + 1;
+        }
+        out
+    }
+
+    pub fn disassemble(&self, function: usize) -> String {
+        let mut out = String::new();
+        let Some(func) = self.progs.functions.get(function) else {
+            return out;
+        };
+        if func.first_statement < 0 {
+            let _ = writeln!(
+                out,
+                "{} = #{} ({})",
+                func.name,
+                func.first_statement.unsigned_abs(),
+                func.file
+            );
+            return out;
+        }
+        let range = self.function_range(function);
+        let _ = writeln!(
+            out,
+            "{} ({}) statements {}..{}",
+            func.name, func.file, range.start, range.end
+        );
+        for index in range {
+            let statement = self.progs.statements[index];
+            let _ = writeln!(out, "{}", self.statement_line(index, statement));
+        }
+        out
+    }
+
+    pub fn disassemble_all(&self) -> String {
+        let mut out = String::new();
+        for index in 0..self.progs.functions.len() {
+            if self.progs.functions[index].first_statement <= 0 {
+                continue;
+            }
+            out.push_str(&self.disassemble(index));
+            out.push('\n');
+        }
+        out
+    }
+
+    pub fn builtin_xref(&self) -> String {
+        let mut callers: BTreeMap<u32, BTreeSet<&str>> = BTreeMap::new();
+        let mut builtin_names: BTreeMap<u32, &str> = BTreeMap::new();
+        for func in &self.progs.functions {
+            if func.first_statement < 0 {
+                builtin_names
+                    .entry(func.first_statement.unsigned_abs())
+                    .or_insert(func.name.as_str());
+                callers
+                    .entry(func.first_statement.unsigned_abs())
+                    .or_default();
+            }
+        }
+
+        for (index, func) in self.progs.functions.iter().enumerate() {
+            if func.first_statement <= 0 {
+                continue;
+            }
+            for statement_index in self.function_range(index) {
+                let statement = self.progs.statements[statement_index];
+                let Some(target) = self.call_target(statement) else {
+                    continue;
+                };
+                let callee = &self.progs.functions[target];
+                if callee.first_statement < 0 {
+                    callers
+                        .entry(callee.first_statement.unsigned_abs())
+                        .or_default()
+                        .insert(func.name.as_str());
+                }
+            }
+        }
+
+        let mut out = String::new();
+        for (number, names) in callers {
+            let name = builtin_names.get(&number).copied().unwrap_or("?");
+            if names.is_empty() {
+                let _ = writeln!(out, "#{number:<4} {name}: never called");
+                continue;
+            }
+            let _ = writeln!(out, "#{number:<4} {name}: {} caller(s)", names.len());
+            for caller in names {
+                let _ = writeln!(out, "        {caller}");
+            }
+        }
+        out
+    }
+
+    pub fn function_range(&self, function: usize) -> Range<usize> {
+        let Some(func) = self.progs.functions.get(function) else {
+            return 0..0;
+        };
+        if func.first_statement < 0 {
+            return 0..0;
+        }
+        let start = (func.first_statement as usize).min(self.progs.statements.len());
+        let end = self
+            .progs
+            .functions
+            .iter()
+            .filter(|other| other.first_statement > func.first_statement)
+            .map(|other| other.first_statement as usize)
+            .min()
+            .unwrap_or(self.progs.statements.len())
+            .min(self.progs.statements.len());
+        start..end
+    }
+
+    fn statement_line(&self, index: usize, statement: Statement) -> String {
+        let Some(info) = opcode_info(statement.op) else {
+            return format!(
+                "{index:7}: <bad opcode {}> {} {} {}",
+                statement.op, statement.a, statement.b, statement.c
+            );
+        };
+        let operands = [
+            (info.a, statement.a),
+            (info.b, statement.b),
+            (info.c, statement.c),
+        ]
+        .into_iter()
+        .filter_map(|(kind, value)| self.operand(index, kind, value))
+        .collect::<Vec<_>>()
+        .join(", ");
+        let mut line = format!("{index:7}: {:<11} {operands}", info.name);
+        if let Some(target) = self.call_target(statement) {
+            let callee = &self.progs.functions[target];
+            if callee.first_statement < 0 {
+                let _ = write!(
+                    line,
+                    "  ; builtin #{}",
+                    callee.first_statement.unsigned_abs()
+                );
+            }
+        }
+        line.trim_end().to_string()
+    }
+
+    fn operand(&self, index: usize, kind: Operand, value: i16) -> Option<String> {
+        match kind {
+            Operand::Unused => None,
+            Operand::Jump => Some(format!("-> {}", index as i64 + i64::from(value))),
+            Operand::Global(ty) => Some(self.global_operand(value, ty)),
+        }
+    }
+
+    fn global_operand(&self, offset: i16, ty: QcType) -> String {
+        if let Some(def) = self.globals_by_offset.get(&offset) {
+            if def.name != "IMMEDIATE" && !def.name.is_empty() {
+                return def.name.clone();
+            }
+            let ty = if ty == QcType::Void { def.ty } else { ty };
+            return self.constant_value(offset, ty);
+        }
+        if (OFS_RETURN..OFS_PARM0).contains(&offset) {
+            return component_name("RETURN", offset - OFS_RETURN);
+        }
+        if (OFS_PARM0..OFS_PARM0 + PARM_COUNT * 3).contains(&offset) {
+            let parm = (offset - OFS_PARM0) / 3;
+            return component_name(&format!("PARM{parm}"), (offset - OFS_PARM0) % 3);
+        }
+        format!("#{offset}")
+    }
+
+    fn constant_value(&self, offset: i16, ty: QcType) -> String {
+        let raw = |delta: usize| {
+            usize::try_from(offset)
+                .ok()
+                .and_then(|index| self.progs.globals.get(index + delta))
+                .copied()
+                .unwrap_or(0)
+        };
+        match ty {
+            QcType::String => quote(&self.progs.string_at(raw(0) as i32).unwrap_or_default()),
+            QcType::Float => format_float(f32::from_bits(raw(0))),
+            QcType::Vector => format!(
+                "'{} {} {}'",
+                format_float(f32::from_bits(raw(0))),
+                format_float(f32::from_bits(raw(1))),
+                format_float(f32::from_bits(raw(2)))
+            ),
+            QcType::Function => match self.progs.functions.get(raw(0) as usize) {
+                Some(func) if raw(0) != 0 => format!("{}()", func.name),
+                _ => "null function".to_string(),
+            },
+            QcType::Field => match self.fields_by_offset.get(&(raw(0) as i16)) {
+                Some(def) => format!(".{}", def.name),
+                None => format!(".{}", raw(0)),
+            },
+            QcType::Entity => format!("entity {}", f32::from_bits(raw(0)) as i32),
+            QcType::Integer => (raw(0) as i32).to_string(),
+            QcType::Void | QcType::Pointer | QcType::Unknown(_) => format!("0x{:08x}", raw(0)),
+        }
+    }
+
+    fn call_target(&self, statement: Statement) -> Option<usize> {
+        if !is_call(statement.op) {
+            return None;
+        }
+        let def = self.globals_by_offset.get(&statement.a)?;
+        if def.ty != QcType::Function {
+            return None;
+        }
+        let target = *self.progs.globals.get(usize::try_from(statement.a).ok()?)? as usize;
+        (target != 0 && target < self.progs.functions.len()).then_some(target)
+    }
+}
+
+fn index_defs(defs: &[Definition]) -> HashMap<i16, &Definition> {
+    let mut map = HashMap::new();
+    for def in defs {
+        let entry = map.entry(def.offset).or_insert(def);
+        if entry.name == "IMMEDIATE" && def.name != "IMMEDIATE" {
+            *entry = def;
+        }
+    }
+    map
+}
+
+fn component_name(base: &str, component: i16) -> String {
+    match component {
+        0 => base.to_string(),
+        1 => format!("{base}_y"),
+        _ => format!("{base}_z"),
+    }
+}
+
+fn format_float(value: f32) -> String {
+    if value.fract() == 0.0 && value.abs() < 1e9 {
+        format!("{}", value as i64)
+    } else {
+        format!("{value}")
+    }
+}
+
+fn quote(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + 2);
+    out.push('"');
+    for ch in text.chars() {
+        match ch {
+            '\n' => out.push_str("\\n"),
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            _ => out.push(ch),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use qw_qc::opcodes::{OP_ADD_F, OP_CALL1, OP_DONE, OP_IFNOT, OP_STORE_F};
+    use qw_qc::{Function, PROG_VERSION};
+
+    fn def(ty: QcType, offset: i16, name: &str) -> Definition {
+        Definition {
+            ty,
+            offset,
+            name: name.to_string(),
+            save_global: false,
+        }
+    }
+
+    fn func(first_statement: i32, name: &str, num_params: i32) -> Function {
+        let mut param_sizes = [0u8; 8];
+        param_sizes[..num_params as usize].fill(1);
+        Function {
+            first_statement,
+            parm_start: 30,
+            locals: 1,
+            profile: 0,
+            name: name.to_string(),
+            file: "test.qc".to_string(),
+            num_params,
+            param_sizes,
+        }
+    }
+
+    fn test_progs() -> ProgsDat {
+        let mut globals = vec![0u32; 34];
+        globals[28] = 1;
+        globals[29] = 2;
+        globals[31] = 5.0f32.to_bits();
+        globals[32] = 1;
+        ProgsDat {
+            version: PROG_VERSION,
+            crc: 0,
+            statements: vec![
+                Statement {
+                    op: OP_DONE,
+                    a: 0,
+                    b: 0,
+                    c: 0,
+                },
+                Statement {
+                    op: OP_ADD_F,
+                    a: 30,
+                    b: 31,
+                    c: 33,
+                },
+                Statement {
+                    op: OP_IFNOT,
+                    a: 33,
+                    b: 2,
+                    c: 0,
+                },
+                Statement {
+                    op: OP_STORE_F,
+                    a: 30,
+                    b: 4,
+                    c: 0,
+                },
+                Statement {
+                    op: OP_CALL1,
+                    a: 28,
+                    b: 0,
+                    c: 0,
+                },
+                Statement {
+                    op: OP_DONE,
+                    a: 0,
+                    b: 0,
+                    c: 0,
+                },
+            ],
+            global_defs: vec![
+                def(QcType::Function, 28, "dprint"),
+                def(QcType::Function, 29, "think"),
+                def(QcType::Float, 30, "count"),
+                def(QcType::Float, 31, "IMMEDIATE"),
+                def(QcType::String, 32, "IMMEDIATE"),
+            ],
+            field_defs: Vec::new(),
+            functions: vec![
+                func(0, "", 0),
+                func(-1, "dprint", 1),
+                func(1, "think", 1),
+                func(-2, "spawn", 0),
+            ],
+            strings: b"\0hi\0".to_vec(),
+            globals,
+            entity_fields: 0,
+        }
+    }
+
+    #[test]
+    fn disassembles_with_resolved_operands() {
+        let progs = test_progs();
+        let dump = ProgsDump::new(&progs);
+        let text = dump.disassemble(2);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "think (test.qc) statements 1..6");
+        assert_eq!(lines[1], "      1: ADD_F       count, 5, #33");
+        assert_eq!(lines[2], "      2: IFNOT       #33, -> 4");
+        assert_eq!(lines[3], "      3: STORE_F     count, PARM0");
+        assert_eq!(lines[4], "      4: CALL1       dprint  ; builtin #1");
+    }
+
+    #[test]
+    fn cross_references_builtin_callers() {
+        let progs = test_progs();
+        let dump = ProgsDump::new(&progs);
+        assert_eq!(
+            dump.builtin_xref(),
+            "#1    dprint: 1 caller(s)\n        think\n#2    spawn: never called\n"
+        );
+    }
+
+    #[test]
+    fn lists_strings_and_immediates() {
+        let progs = test_progs();
+        let dump = ProgsDump::new(&progs);
+        assert_eq!(dump.strings(), "      1 \"hi\"\n");
+        assert!(
+            dump.globals()
+                .contains("IMMEDIATE                    = \"hi\"")
+        );
+        assert!(dump.functions().contains("builtin #1"));
+    }
+
+    #[test]
+    fn survives_the_most_negative_builtin_number() {
+        let mut progs = test_progs();
+        progs.functions[1].first_statement = i32::MIN;
+        let dump = ProgsDump::new(&progs);
+        assert!(dump.functions().contains("builtin #2147483648"));
+        assert!(
+            dump.builtin_xref()
+                .contains("#2147483648 dprint: 1 caller(s)")
+        );
+        assert!(dump.disassemble(1).starts_with("dprint = #2147483648"));
+        assert!(dump.disassemble(2).contains("builtin #2147483648"));
+    }
+}
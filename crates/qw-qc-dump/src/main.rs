@@ -0,0 +1,222 @@
+mod dump;
+
+use std::fmt;
+use std::io::Write;
+
+use dump::ProgsDump;
+use qw_qc::{ProgsDat, ProgsError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Section {
+    Summary,
+    Functions,
+    Globals,
+    Fields,
+    Strings,
+    Disasm,
+    Builtins,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct DumpArgs {
+    path: String,
+    sections: Vec<Section>,
+    function: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum CliAction {
+    Run(DumpArgs),
+    Help,
+}
+
+#[derive(Debug)]
+enum DumpError {
+    MissingPath,
+    MissingValue(String),
+    InvalidFlag(String),
+    Io(std::io::Error),
+    Progs(ProgsError),
+    UnknownFunction(String),
+}
+
+impl fmt::Display for DumpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DumpError::MissingPath => write!(f, "missing progs.dat path"),
+            DumpError::MissingValue(flag) => write!(f, "missing value for {}", flag),
+            DumpError::InvalidFlag(flag) => write!(f, "unknown flag: {}", flag),
+            DumpError::Io(err) => write!(f, "io error: {}", err),
+            DumpError::Progs(err) => write!(f, "progs error: {}", err),
+            DumpError::UnknownFunction(name) => write!(f, "no function named {}", name),
+        }
+    }
+}
+
+fn usage() -> &'static str {
+    "Usage: qw-qc-dump <progs.dat> [options]\n\
+Options:\n\
+  --summary             Header counts and CRC\n\
+  --functions           Function table\n\
+  --globals             Global defs with initial values\n\
+  --fields              Entity field defs\n\
+  --strings             String table\n\
+  --disasm              Disassemble every QuakeC function\n\
+  --function <name>     Disassemble a single function\n\
+  --builtins            Builtin numbers and the functions calling them\n\
+  -h, --help            Show this help\n\
+With no section flags every section is printed.\n"
+}
+
+fn parse_args<I, S>(args: I) -> Result<CliAction, DumpError>
+where
+    I: IntoIterator<Item = S>,
+    S: Into<String>,
+{
+    let mut iter = args.into_iter().map(Into::into);
+    let mut path = None;
+    let mut sections = Vec::new();
+    let mut function = None;
+
+    while let Some(arg) = iter.next() {
+        let section = match arg.as_str() {
+            "-h" | "--help" => return Ok(CliAction::Help),
+            "--summary" => Section::Summary,
+            "--functions" => Section::Functions,
+            "--globals" => Section::Globals,
+            "--fields" => Section::Fields,
+            "--strings" => Section::Strings,
+            "--disasm" => Section::Disasm,
+            "--builtins" => Section::Builtins,
+            "--function" => {
+                let value = iter.next().ok_or(DumpError::MissingValue(arg))?;
+                function = Some(value);
+                continue;
+            }
+            _ if arg.starts_with('-') => return Err(DumpError::InvalidFlag(arg)),
+            _ if path.is_none() => {
+                path = Some(arg);
+                continue;
+            }
+            _ => return Err(DumpError::InvalidFlag(arg)),
+        };
+        if !sections.contains(&section) {
+            sections.push(section);
+        }
+    }
+
+    let path = path.ok_or(DumpError::MissingPath)?;
+    if sections.is_empty() && function.is_none() {
+        sections = vec![
+            Section::Summary,
+            Section::Functions,
+            Section::Globals,
+            Section::Fields,
+            Section::Strings,
+            Section::Builtins,
+            Section::Disasm,
+        ];
+    }
+    Ok(CliAction::Run(DumpArgs {
+        path,
+        sections,
+        function,
+    }))
+}
+
+fn run(args: DumpArgs) -> Result<(), DumpError> {
+    let bytes = std::fs::read(&args.path).map_err(DumpError::Io)?;
+    let progs = ProgsDat::from_bytes(&bytes).map_err(DumpError::Progs)?;
+    let dump = ProgsDump::new(&progs);
+
+    let mut out = String::new();
+    for section in &args.sections {
+        let (title, body) = match section {
+            Section::Summary => ("summary", dump.summary()),
+            Section::Functions => ("functions", dump.functions()),
+            Section::Globals => ("globals", dump.globals()),
+            Section::Fields => ("fields", dump.fields()),
+            Section::Strings => ("strings", dump.strings()),
+            Section::Disasm => ("disassembly", dump.disassemble_all()),
+            Section::Builtins => ("builtins", dump.builtin_xref()),
+        };
+        if args.sections.len() > 1 {
+            out.push_str(&format!("== {title} ==\n"));
+        }
+        out.push_str(&body);
+        if args.sections.len() > 1 {
+            out.push('\n');
+        }
+    }
+    if let Some(name) = &args.function {
+        let index = progs
+            .functions
+            .iter()
+            .position(|func| func.name == *name)
+            .ok_or_else(|| DumpError::UnknownFunction(name.clone()))?;
+        out.push_str(&dump.disassemble(index));
+    }
+
+    // A closed pipe (e.g. `| head`) is not worth reporting.
+    let mut stdout = std::io::stdout().lock();
+    match stdout.write_all(out.as_bytes()) {
+        Err(err) if err.kind() != std::io::ErrorKind::BrokenPipe => Err(DumpError::Io(err)),
+        _ => Ok(()),
+    }
+}
+
+fn main() {
+    let action = match parse_args(std::env::args().skip(1)) {
+        Ok(action) => action,
+        Err(err) => {
+            eprintln!("{err}");
+            eprintln!("{}", usage());
+            std::process::exit(2);
+        }
+    };
+    let args = match action {
+        CliAction::Help => {
+            print!("{}", usage());
+            return;
+        }
+        CliAction::Run(args) => args,
+    };
+    if let Err(err) = run(args) {
+        eprintln!("{err}");
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_to_every_section() {
+        let CliAction::Run(args) = parse_args(["progs.dat"]).unwrap() else {
+            panic!("expected run");
+        };
+        assert_eq!(args.path, "progs.dat");
+        assert_eq!(args.sections.len(), 7);
+        assert_eq!(args.function, None);
+    }
+
+    #[test]
+    fn parses_sections_and_function() {
+        let CliAction::Run(args) =
+            parse_args(["--builtins", "qwprogs.dat", "--function", "main"]).unwrap()
+        else {
+            panic!("expected run");
+        };
+        assert_eq!(args.sections, vec![Section::Builtins]);
+        assert_eq!(args.function.as_deref(), Some("main"));
+        assert!(matches!(
+            parse_args(["--function"]),
+            Err(DumpError::MissingValue(_))
+        ));
+        assert!(matches!(
+            parse_args(["--disasm"]),
+            Err(DumpError::MissingPath)
+        ));
+    }
+}
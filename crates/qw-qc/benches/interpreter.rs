@@ -0,0 +1,142 @@
+// Times the decoded interpreter against the reference one.
+//
+// cargo bench -p qw-qc [-- path/to/progs.dat]
+//
+// Without a path the progs.dat (or qwprogs.dat) from the Quake data dir is used,
+// falling back to a synthetic loop when no game data is installed. Every QuakeC
+// function that takes no parameters is called in turn with builtins stubbed out,
+// so most of the progs gets exercised the same way under both interpreters.
+
+use qw_common::{QuakeFs, find_id1_dir, locate_data_dir};
+use qw_qc::opcodes::*;
+use qw_qc::{Function, Interpreter, PROG_VERSION, ProgsDat, Statement, Vm, VmError};
+use std::time::{Duration, Instant};
+
+const ROUNDS: usize = 20;
+const MAX_STEPS: usize = 100_000;
+const EDICTS: usize = 64;
+
+fn main() {
+    let (name, progs) = load_progs();
+    let functions: Vec<usize> = progs
+        .functions
+        .iter()
+        .enumerate()
+        .filter(|(_, func)| func.first_statement > 0 && func.num_params == 0)
+        .map(|(index, _)| index)
+        .collect();
+    println!(
+        "{name}: {} statements, {} functions called {ROUNDS} times",
+        progs.statements.len(),
+        functions.len()
+    );
+
+    let reference = workload(&progs, &functions, Interpreter::Reference);
+    let decoded = workload(&progs, &functions, Interpreter::Decoded);
+    println!("reference: {:>10.3} ms", reference.as_secs_f64() * 1000.0);
+    println!("decoded:   {:>10.3} ms", decoded.as_secs_f64() * 1000.0);
+    println!(
+        "speedup:   {:>10.2}x",
+        reference.as_secs_f64() / decoded.as_secs_f64().max(f64::EPSILON)
+    );
+}
+
+fn load_progs() -> (String, ProgsDat) {
+    let arg = std::env::args().skip(1).find(|arg| !arg.starts_with('-'));
+    if let Some(path) = arg {
+        let bytes = std::fs::read(&path).unwrap_or_else(|err| panic!("{path}: {err}"));
+        let progs = ProgsDat::from_bytes(&bytes).unwrap_or_else(|err| panic!("{path}: {err}"));
+        return (path, progs);
+    }
+
+    let game_dir = locate_data_dir().ok().and_then(|dir| find_id1_dir(&dir));
+    if let Some(game_dir) = game_dir {
+        let mut fs = QuakeFs::new();
+        if fs.add_game_dir(&game_dir).is_ok() {
+            for name in ["progs.dat", "qwprogs.dat"] {
+                if let Ok(progs) = fs.read(name).map(|bytes| ProgsDat::from_bytes(&bytes)) {
+                    match progs {
+                        Ok(progs) => return (name.to_string(), progs),
+                        Err(err) => panic!("{name}: {err}"),
+                    }
+                }
+            }
+        }
+    }
+    ("synthetic loop".to_string(), synthetic_progs())
+}
+
+fn workload(progs: &ProgsDat, functions: &[usize], interpreter: Interpreter) -> Duration {
+    let mut vm = Vm::new(progs.clone());
+    vm.set_interpreter(interpreter);
+    for func in &progs.functions {
+        if func.first_statement < 0 {
+            vm.register_builtin(func.first_statement.unsigned_abs() as usize, stub_builtin);
+        }
+    }
+    for _ in 0..EDICTS {
+        if vm.alloc_edict().is_err() {
+            break;
+        }
+    }
+    let self_global = progs.global_def("self").map(|def| def.offset);
+
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        for (n, &function) in functions.iter().enumerate() {
+            if let Some(offset) = self_global {
+                let entity = 1 + n % (vm.edict_count() - 1).max(1);
+                let _ = vm.write_global_f32(offset, entity as f32);
+            }
+            let _ = vm.call_function(function, MAX_STEPS);
+        }
+    }
+    start.elapsed()
+}
+
+fn stub_builtin(vm: &mut Vm) -> Result<(), VmError> {
+    vm.set_return_raw(0)
+}
+
+// Sums a float in a loop and runs some vector math on an edict field.
+fn synthetic_progs() -> ProgsDat {
+    let statement = |op, a, b, c| Statement { op, a, b, c };
+    let function = |first_statement| Function {
+        first_statement,
+        parm_start: 43,
+        locals: 0,
+        profile: 0,
+        name: String::new(),
+        file: String::new(),
+        num_params: 0,
+        param_sizes: [0; 8],
+    };
+    let mut globals = vec![0u32; 43];
+    globals[30] = 1.0f32.to_bits();
+    globals[31] = 10_000.0f32.to_bits();
+    globals[33] = 1.0f32.to_bits();
+    globals[39] = 1.0f32.to_bits();
+    ProgsDat {
+        version: PROG_VERSION,
+        crc: 0,
+        statements: vec![
+            statement(OP_DONE, 0, 0, 0),
+            statement(OP_STORE_F, 42, 28, 0),
+            statement(OP_ADD_F, 29, 28, 29),
+            statement(OP_MUL_VF, 33, 29, 36),
+            statement(OP_ADDRESS, 39, 40, 41),
+            statement(OP_STOREP_V, 36, 41, 0),
+            statement(OP_LOAD_V, 39, 40, 33),
+            statement(OP_ADD_F, 28, 30, 28),
+            statement(OP_LT, 28, 31, 32),
+            statement(OP_IF, 32, -7, 0),
+            statement(OP_DONE, 0, 0, 0),
+        ],
+        global_defs: Vec::new(),
+        field_defs: Vec::new(),
+        functions: vec![function(0), function(1)],
+        strings: vec![0],
+        globals,
+        entity_fields: 3,
+    }
+}
@@ -0,0 +1,34 @@
+#![no_main]
+
+// Runs every function of whatever progs the input decodes to, with all builtins
+// stubbed, under tight limits. Errors are fine; panics and hangs are not.
+
+use libfuzzer_sys::fuzz_target;
+use qw_qc::{ProgsDat, Vm, VmError, VmLimits};
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(progs) = ProgsDat::from_bytes(data) else {
+        return;
+    };
+    let mut vm = Vm::new(progs);
+    vm.set_limits(VmLimits {
+        max_edicts: 32,
+        max_string_bytes: 4096,
+        max_call_depth: 16,
+        max_frame_steps: 10_000,
+    });
+    for number in 1..64 {
+        vm.register_builtin(number, stub_builtin);
+    }
+    let _ = vm.alloc_edict();
+    for function in 0..vm.progs().functions.len() {
+        vm.start_frame();
+        let _ = vm.call_function(function, 1000);
+    }
+});
+
+fn stub_builtin(vm: &mut Vm) -> Result<(), VmError> {
+    let entity = vm.alloc_edict()?;
+    vm.alloc_string("fuzz")?;
+    vm.set_return_raw(entity as u32)
+}
@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use qw_qc::ProgsDat;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(progs) = ProgsDat::from_bytes(data) {
+        let bytes = progs.to_bytes();
+        assert!(ProgsDat::from_bytes(&bytes).is_ok());
+    }
+});
@@ -0,0 +1,497 @@
+// Statements decoded once at load time: operand offsets are checked against the
+// globals, jumps are turned into absolute statement numbers and the names OP_STATE
+// needs are looked up, so the dispatch loop only checks values it reads at runtime.
+
+use crate::opcodes::*;
+use crate::{OFS_RETURN, ProgsDat, QcType, Vm, VmError, VmResult};
+
+// Stand-ins for statements that would fail when run. ProgsDat::from_bytes rejects
+// these, but a hand-built ProgsDat only fails once the statement is reached.
+const OP_BAD_GLOBAL: u16 = u16::MAX;
+const OP_BAD_JUMP: u16 = u16::MAX - 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Instr {
+    op: u16,
+    a: u32,
+    b: u32,
+    c: u32,
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DecodedProgs {
+    code: Vec<Instr>,
+    state: StateOffsets,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct StateOffsets {
+    self_global: Option<usize>,
+    time_global: Option<usize>,
+    nextthink: Option<usize>,
+    frame: Option<usize>,
+    think: Option<usize>,
+}
+
+impl DecodedProgs {
+    pub(crate) fn new(progs: &ProgsDat) -> Self {
+        let globals = progs.globals.len();
+        let statements = progs.statements.len();
+        let code = progs
+            .statements
+            .iter()
+            .enumerate()
+            .map(|(index, statement)| {
+                let Some(info) = opcode_info(statement.op) else {
+                    return Instr {
+                        op: statement.op,
+                        a: 0,
+                        b: 0,
+                        c: 0,
+                    };
+                };
+                let mut instr = Instr {
+                    op: statement.op,
+                    a: 0,
+                    b: 0,
+                    c: 0,
+                };
+                for (operand, raw, slot) in [
+                    (info.a, statement.a, &mut instr.a),
+                    (info.b, statement.b, &mut instr.b),
+                    (info.c, statement.c, &mut instr.c),
+                ] {
+                    match operand {
+                        Operand::Unused => {}
+                        Operand::Global(ty) => {
+                            let size = if ty == QcType::Vector { 3 } else { 1 };
+                            if raw < 0 || raw as usize + size > globals {
+                                return Instr {
+                                    op: OP_BAD_GLOBAL,
+                                    a: raw as u32,
+                                    b: 0,
+                                    c: 0,
+                                };
+                            }
+                            *slot = raw as u32;
+                        }
+                        Operand::Jump => {
+                            let target = index as i64 + i64::from(raw);
+                            if target < 0 || target as usize >= statements {
+                                return Instr {
+                                    op: OP_BAD_JUMP,
+                                    a: target as u32,
+                                    b: 0,
+                                    c: 0,
+                                };
+                            }
+                            *slot = target as u32;
+                        }
+                    }
+                }
+                if statement.op == OP_RETURN {
+                    // A float return still copies three slots; don't run off the end.
+                    instr.b = (globals - instr.a as usize).min(3) as u32;
+                }
+                instr
+            })
+            .collect();
+
+        let global = |name| {
+            progs
+                .global_def(name)
+                .map(|def| def.offset)
+                .filter(|&offset| offset >= 0 && (offset as usize) < globals)
+                .map(|offset| offset as usize)
+        };
+        let field = |name| {
+            progs
+                .field_def(name)
+                .filter(|def| def.offset >= 0)
+                .map(|def| def.offset as usize)
+        };
+        let state = StateOffsets {
+            self_global: global("self"),
+            time_global: global("time"),
+            nextthink: field("nextthink"),
+            frame: field("frame"),
+            think: field("think"),
+        };
+        Self { code, state }
+    }
+}
+
+impl Vm {
+    pub(crate) fn execute_decoded(&mut self, max_steps: usize, steps: &mut usize) -> VmResult<()> {
+        let Some(frame) = self.call_stack.last() else {
+            return Ok(());
+        };
+        let mut pc = frame.statement_index;
+        let result = self.run_decoded(&mut pc, max_steps, steps);
+        if result.is_err()
+            && let Some(frame) = self.call_stack.last_mut()
+        {
+            frame.statement_index = pc;
+        }
+        result
+    }
+
+    fn run_decoded(&mut self, pc: &mut i32, max_steps: usize, steps: &mut usize) -> VmResult<()> {
+        loop {
+            if *steps >= max_steps {
+                let function = self
+                    .call_stack
+                    .last()
+                    .map(|frame| frame.function_index as i32)
+                    .unwrap_or(-1);
+                return Err(VmError::StepLimit {
+                    statement: *pc,
+                    function,
+                });
+            }
+            *steps += 1;
+
+            let Some(&instr) = usize::try_from(*pc)
+                .ok()
+                .and_then(|index| self.decoded.code.get(index))
+            else {
+                return Err(VmError::BadStatement(*pc));
+            };
+            let (a, b, c) = (instr.a as usize, instr.b as usize, instr.c as usize);
+            let mut next = *pc + 1;
+
+            match instr.op {
+                OP_DONE | OP_RETURN => {
+                    if instr.op == OP_RETURN {
+                        // A progs too small to hold the return slots fails as
+                        // copy_global does.
+                        if OFS_RETURN + b > self.globals.len() {
+                            return Err(VmError::BadGlobal(a as i16));
+                        }
+                        self.globals.copy_within(a..a + b, OFS_RETURN);
+                    }
+                    self.leave_function();
+                    match self.call_stack.last() {
+                        Some(frame) => next = frame.statement_index,
+                        None => return Ok(()),
+                    }
+                }
+                OP_GOTO => next = a as i32,
+                OP_IF => {
+                    if self.globals[a] != 0 {
+                        next = b as i32;
+                    }
+                }
+                OP_IFNOT => {
+                    if self.globals[a] == 0 {
+                        next = b as i32;
+                    }
+                }
+                OP_CALL0 | OP_CALL1 | OP_CALL2 | OP_CALL3 | OP_CALL4 | OP_CALL5 | OP_CALL6
+                | OP_CALL7 | OP_CALL8 => {
+                    if let Some(frame) = self.call_stack.last_mut() {
+                        frame.statement_index = *pc;
+                    }
+                    let depth = self.call_stack.len();
+                    self.call_function_index(self.globals[a] as i32, Some(next))?;
+                    if self.call_stack.len() != depth
+                        && let Some(frame) = self.call_stack.last()
+                    {
+                        next = frame.statement_index;
+                    }
+                }
+                OP_STORE_F | OP_STORE_ENT | OP_STORE_FLD | OP_STORE_S | OP_STORE_FNC => {
+                    self.globals[b] = self.globals[a];
+                }
+                OP_STORE_V => self.globals.copy_within(a..a + 3, b),
+                OP_STOREP_F | OP_STOREP_ENT | OP_STOREP_FLD | OP_STOREP_S | OP_STOREP_FNC => {
+                    self.store_pointer(self.globals[b], a as i16, 1)?;
+                }
+                OP_STOREP_V => self.store_pointer(self.globals[b], a as i16, 3)?,
+                OP_ADDRESS => {
+                    let entity = self.decoded_entity(a)? as u32;
+                    let field = self.globals[b] as i32;
+                    if !(0..=i32::from(u16::MAX)).contains(&field) {
+                        return Err(VmError::BadField(field));
+                    }
+                    self.globals[c] = (entity << 16) | field as u32;
+                }
+                OP_LOAD_F | OP_LOAD_ENT | OP_LOAD_FLD | OP_LOAD_S | OP_LOAD_FNC => {
+                    let value = self.decoded_field(a, b, 1)?[0];
+                    self.globals[c] = value;
+                }
+                OP_LOAD_V => {
+                    let values = self.decoded_field(a, b, 3)?;
+                    let values = [values[0], values[1], values[2]];
+                    self.globals[c..c + 3].copy_from_slice(&values);
+                }
+                OP_ADD_F => self.set_float(c, self.float(a) + self.float(b)),
+                OP_SUB_F => self.set_float(c, self.float(a) - self.float(b)),
+                OP_MUL_F => self.set_float(c, self.float(a) * self.float(b)),
+                OP_DIV_F => {
+                    let denom = self.float(b);
+                    let value = if denom == 0.0 {
+                        0.0
+                    } else {
+                        self.float(a) / denom
+                    };
+                    self.set_float(c, value);
+                }
+                OP_ADD_V => {
+                    for i in 0..3 {
+                        self.set_float(c + i, self.float(a + i) + self.float(b + i));
+                    }
+                }
+                OP_SUB_V => {
+                    for i in 0..3 {
+                        self.set_float(c + i, self.float(a + i) - self.float(b + i));
+                    }
+                }
+                OP_MUL_V => {
+                    let dot = (0..3).map(|i| self.float(a + i) * self.float(b + i)).sum();
+                    self.set_float(c, dot);
+                }
+                OP_MUL_FV => {
+                    let scalar = self.float(a);
+                    for i in 0..3 {
+                        self.set_float(c + i, self.float(b + i) * scalar);
+                    }
+                }
+                OP_MUL_VF => {
+                    let scalar = self.float(b);
+                    for i in 0..3 {
+                        self.set_float(c + i, self.float(a + i) * scalar);
+                    }
+                }
+                OP_EQ_F => self.set_bool(c, self.float(a) == self.float(b)),
+                OP_NE_F => self.set_bool(c, self.float(a) != self.float(b)),
+                OP_LT => self.set_bool(c, self.float(a) < self.float(b)),
+                OP_GT => self.set_bool(c, self.float(a) > self.float(b)),
+                OP_LE => self.set_bool(c, self.float(a) <= self.float(b)),
+                OP_GE => self.set_bool(c, self.float(a) >= self.float(b)),
+                OP_EQ_V | OP_NE_V => {
+                    let equal = (0..3).all(|i| self.float(a + i) == self.float(b + i));
+                    self.set_bool(c, equal == (instr.op == OP_EQ_V));
+                }
+                OP_EQ_S | OP_NE_S => {
+                    let equal = self.string_bytes(self.globals[a] as i32)
+                        == self.string_bytes(self.globals[b] as i32);
+                    self.set_bool(c, equal == (instr.op == OP_EQ_S));
+                }
+                OP_EQ_E | OP_EQ_FNC => self.set_bool(c, self.globals[a] == self.globals[b]),
+                OP_NE_E | OP_NE_FNC => self.set_bool(c, self.globals[a] != self.globals[b]),
+                OP_NOT_F | OP_NOT_FNC | OP_NOT_ENT => self.set_bool(c, self.globals[a] == 0),
+                OP_NOT_S => {
+                    let offset = self.globals[a] as i32;
+                    self.set_bool(c, offset == 0 || self.string_bytes(offset).is_empty());
+                }
+                OP_NOT_V => self.set_bool(c, (0..3).all(|i| self.float(a + i) == 0.0)),
+                OP_AND => self.set_bool(c, self.float(a) != 0.0 && self.float(b) != 0.0),
+                OP_OR => self.set_bool(c, self.float(a) != 0.0 || self.float(b) != 0.0),
+                OP_BITAND => {
+                    let value = self.float(a) as i32 & self.float(b) as i32;
+                    self.set_float(c, value as f32);
+                }
+                OP_BITOR => {
+                    let value = self.float(a) as i32 | self.float(b) as i32;
+                    self.set_float(c, value as f32);
+                }
+                OP_STATE => self.decoded_state(a, b)?,
+                OP_BAD_GLOBAL => return Err(VmError::BadGlobal(instr.a as i16)),
+                OP_BAD_JUMP => return Err(VmError::BadStatement(instr.a as i32)),
+                op => return Err(VmError::UnsupportedOpcode(op)),
+            }
+            *pc = next;
+        }
+    }
+
+    fn float(&self, index: usize) -> f32 {
+        f32::from_bits(self.globals[index])
+    }
+
+    fn set_float(&mut self, index: usize, value: f32) {
+        self.globals[index] = value.to_bits();
+    }
+
+    fn set_bool(&mut self, index: usize, value: bool) {
+        self.set_float(index, if value { 1.0 } else { 0.0 });
+    }
+
+    fn string_bytes(&self, offset: i32) -> &[u8] {
+        let Some(tail) = usize::try_from(offset)
+            .ok()
+            .and_then(|start| self.progs.strings.get(start..))
+        else {
+            return &[];
+        };
+        let end = tail.iter().position(|&b| b == 0).unwrap_or(tail.len());
+        &tail[..end]
+    }
+
+    fn decoded_entity(&self, index: usize) -> VmResult<usize> {
+        let entity = self.float(index) as i32;
+        if entity < 0 || entity as usize >= self.edicts.len() {
+            return Err(VmError::BadEdict(entity));
+        }
+        Ok(entity as usize)
+    }
+
+    fn decoded_field(&self, entity: usize, field: usize, size: usize) -> VmResult<&[u32]> {
+        let edict = &self.edicts[self.decoded_entity(entity)?];
+        let field = self.globals[field] as i32;
+        usize::try_from(field)
+            .ok()
+            .and_then(|start| edict.fields.get(start..start + size))
+            .ok_or(VmError::BadField(field))
+    }
+
+    fn decoded_state(&mut self, frame: usize, think: usize) -> VmResult<()> {
+        let state = self.decoded.state;
+        let (Some(self_global), Some(time_global)) = (state.self_global, state.time_global) else {
+            return Err(VmError::BadGlobal(-1));
+        };
+        let entity = self.decoded_entity(self_global)?;
+        let time = self.float(time_global);
+        let frame = self.float(frame);
+        let think = self.globals[think];
+        let fields = &mut self.edicts[entity].fields;
+        let mut set = |offset: Option<usize>, value: u32| match offset {
+            Some(offset) => match fields.get_mut(offset) {
+                Some(slot) => {
+                    *slot = value;
+                    Ok(())
+                }
+                None => Err(VmError::BadField(offset as i32)),
+            },
+            None => Ok(()),
+        };
+        set(state.nextthink, (time + 0.1).to_bits())?;
+        set(state.frame, frame.to_bits())?;
+        set(state.think, think)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Function, Interpreter, PROG_VERSION, Statement};
+
+    fn statement(op: u16, a: i16, b: i16, c: i16) -> Statement {
+        Statement { op, a, b, c }
+    }
+
+    fn function(first_statement: i32) -> Function {
+        Function {
+            first_statement,
+            parm_start: 43,
+            locals: 0,
+            profile: 0,
+            name: String::new(),
+            file: String::new(),
+            num_params: 0,
+            param_sizes: [0; 8],
+        }
+    }
+
+    // sum = 0 + 1 + ... + 9, then scale a vector by it, store it through a
+    // pointer into edict 1 and read it back for a dot product.
+    fn loop_progs() -> ProgsDat {
+        let mut globals = vec![0u32; 43];
+        globals[30] = 1.0f32.to_bits();
+        globals[31] = 10.0f32.to_bits();
+        globals[33] = 1.0f32.to_bits();
+        globals[34] = 2.0f32.to_bits();
+        globals[35] = 3.0f32.to_bits();
+        globals[39] = 1.0f32.to_bits();
+        ProgsDat {
+            version: PROG_VERSION,
+            crc: 0,
+            statements: vec![
+                statement(OP_DONE, 0, 0, 0),
+                statement(OP_ADD_F, 29, 28, 29),
+                statement(OP_ADD_F, 28, 30, 28),
+                statement(OP_LT, 28, 31, 32),
+                statement(OP_IF, 32, -3, 0),
+                statement(OP_MUL_VF, 33, 29, 36),
+                statement(OP_ADDRESS, 39, 40, 41),
+                statement(OP_STOREP_V, 36, 41, 0),
+                statement(OP_LOAD_V, 39, 40, 33),
+                statement(OP_MUL_V, 33, 33, 32),
+                statement(OP_RETURN, 29, 0, 0),
+                statement(OP_ADD_F, 5000, 28, 29),
+                statement(OP_DONE, 0, 0, 0),
+            ],
+            global_defs: Vec::new(),
+            field_defs: Vec::new(),
+            functions: vec![function(0), function(1), function(11)],
+            strings: vec![0],
+            globals,
+            entity_fields: 3,
+        }
+    }
+
+    fn run(interpreter: Interpreter, function: usize) -> (Vm, VmResult<()>) {
+        let mut vm = Vm::new(loop_progs());
+        vm.set_interpreter(interpreter);
+        vm.alloc_edict().unwrap();
+        let result = vm.call_function(function, 1000);
+        (vm, result)
+    }
+
+    #[test]
+    fn matches_reference_interpreter() {
+        let (decoded, result) = run(Interpreter::Decoded, 1);
+        result.unwrap();
+        let (reference, result) = run(Interpreter::Reference, 1);
+        result.unwrap();
+
+        assert_eq!(decoded.read_global_f32(29).unwrap(), 45.0);
+        assert_eq!(decoded.read_global_f32(32).unwrap(), 28350.0);
+        assert_eq!(decoded.read_global_f32(OFS_RETURN as i16).unwrap(), 45.0);
+        assert_eq!(decoded.globals, reference.globals);
+        assert_eq!(
+            decoded.read_edict_field_raw(1, 0, 3).unwrap(),
+            reference.read_edict_field_raw(1, 0, 3).unwrap()
+        );
+    }
+
+    #[test]
+    fn bad_operands_fail_only_when_reached() {
+        let progs = loop_progs();
+        let decoded = DecodedProgs::new(&progs);
+        assert_eq!(decoded.code[11].op, OP_BAD_GLOBAL);
+        assert_eq!(decoded.code[4].b, 1);
+
+        for interpreter in [Interpreter::Decoded, Interpreter::Reference] {
+            let (vm, result) = run(interpreter, 2);
+            assert!(matches!(result, Err(VmError::BadGlobal(5000))));
+            assert!(vm.call_stack.is_empty());
+        }
+        let (_, result) = run(Interpreter::Decoded, 1);
+        result.unwrap();
+    }
+
+    #[test]
+    fn returns_fail_cleanly_without_room_for_the_result() {
+        let mut progs = loop_progs();
+        progs.statements = vec![statement(OP_RETURN, 0, 0, 0)];
+        progs.functions = vec![Function {
+            parm_start: 0,
+            ..function(0)
+        }];
+        progs.globals = vec![0; 3];
+        progs.entity_fields = 0;
+        assert_eq!(progs.verify(), Ok(()));
+
+        for interpreter in [Interpreter::Decoded, Interpreter::Reference] {
+            let mut vm = Vm::new(progs.clone());
+            vm.set_interpreter(interpreter);
+            let result = vm.call_function(0, 10);
+            assert!(
+                matches!(result, Err(VmError::BadGlobal(0))),
+                "{interpreter:?}: {result:?}"
+            );
+            assert!(vm.call_stack.is_empty());
+        }
+    }
+}
@@ -1,21 +1,160 @@
 #![forbid(unsafe_code)]
 
-use qw_common::Vec3;
+use qw_common::{MAX_EDICTS, Vec3};
 use std::any::Any;
+use std::fmt;
+
+mod decode;
+pub mod opcodes;
+mod progdefs;
+mod reload;
+mod savegame;
+mod state;
+mod strings;
+mod verify;
+
+use decode::DecodedProgs;
+use opcodes::*;
+pub use progdefs::*;
+pub use reload::{ReloadError, ReloadReport};
+pub use savegame::*;
+pub use state::*;
+use strings::StringHeap;
+pub use strings::{StringKind, TEMP_STRING_SIZE, TEMP_STRINGS};
+pub use verify::{MAX_BUILTIN_NUMBER, MAX_ENTITY_FIELDS};
 
 pub const PROG_VERSION: i32 = 6;
 const DEF_SAVEGLOBAL: i16 = 1 << 15;
 const PARAM_SLOT_SIZE: usize = 3;
-const OFS_RETURN: usize = 0;
-const OFS_PARM0: usize = 4;
+// pr_comp.h: slot 0 is OFS_NULL, so the return value starts at 1.
+pub const OFS_RETURN: usize = 1;
+pub const OFS_PARM0: usize = 4;
+pub const MAX_PARMS: usize = 8;
+pub const RESERVED_OFS: usize = 28;
+const EDICT_REUSE_DELAY: f32 = 0.5;
+const EDICT_STARTUP_GRACE: f32 = 2.0;
+// pr_exec.c's MAX_STACK_DEPTH.
+pub const MAX_CALL_DEPTH: usize = 32;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ProgsError {
     BufferTooSmall,
     UnsupportedVersion(i32),
     InvalidLump(&'static str),
     InvalidStringOffset(i32),
     InvalidUtf8,
+    UnknownCrc(i32),
+    CrcMismatch {
+        expected: i32,
+        found: i32,
+    },
+    MissingGlobal(&'static str),
+    MissingField(&'static str),
+    WrongGlobalType {
+        name: &'static str,
+        expected: QcType,
+        found: QcType,
+    },
+    WrongFieldType {
+        name: &'static str,
+        expected: QcType,
+        found: QcType,
+    },
+    InvalidDefOffset {
+        name: String,
+        offset: i16,
+    },
+    BadEntityFields(i32),
+    BadOpcode {
+        statement: usize,
+        op: u16,
+    },
+    BadOperand {
+        statement: usize,
+        offset: i16,
+    },
+    BadJump {
+        statement: usize,
+        target: i64,
+    },
+    BadFunction {
+        name: String,
+        reason: &'static str,
+    },
+    BadGlobalValue {
+        name: String,
+        value: u32,
+    },
+}
+
+impl fmt::Display for ProgsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProgsError::BufferTooSmall => write!(f, "progs data is truncated"),
+            ProgsError::UnsupportedVersion(version) => write!(
+                f,
+                "progs has wrong version number ({version} should be {PROG_VERSION})"
+            ),
+            ProgsError::InvalidLump(name) => write!(f, "progs {name} lump is out of bounds"),
+            ProgsError::InvalidStringOffset(offset) => {
+                write!(f, "progs string offset {offset} is out of bounds")
+            }
+            ProgsError::InvalidUtf8 => write!(f, "progs string is not valid UTF-8"),
+            ProgsError::UnknownCrc(crc) => write!(
+                f,
+                "progs system vars have been modified (crc {crc}, expected {PROGHEADER_CRC_NQ} for NetQuake or {PROGHEADER_CRC_QW} for QuakeWorld)"
+            ),
+            ProgsError::CrcMismatch { expected, found } => write!(
+                f,
+                "progs crc {found} does not match the engine's progdefs crc {expected}"
+            ),
+            ProgsError::MissingGlobal(name) => write!(f, "progs is missing system global {name}"),
+            ProgsError::MissingField(name) => write!(f, "progs is missing system field {name}"),
+            ProgsError::WrongGlobalType {
+                name,
+                expected,
+                found,
+            } => write!(
+                f,
+                "global {name} should be {} but is {}",
+                expected.name(),
+                found.name()
+            ),
+            ProgsError::WrongFieldType {
+                name,
+                expected,
+                found,
+            } => write!(
+                f,
+                "field {name} should be {} but is {}",
+                expected.name(),
+                found.name()
+            ),
+            ProgsError::InvalidDefOffset { name, offset } => {
+                write!(f, "progs def {name} has out of range offset {offset}")
+            }
+            ProgsError::BadEntityFields(count) => {
+                write!(f, "progs has a bad entity field count ({count})")
+            }
+            ProgsError::BadOpcode { statement, op } => {
+                write!(f, "progs statement {statement} has unknown opcode {op}")
+            }
+            ProgsError::BadOperand { statement, offset } => write!(
+                f,
+                "progs statement {statement} uses out of range global {offset}"
+            ),
+            ProgsError::BadJump { statement, target } => write!(
+                f,
+                "progs statement {statement} jumps out of range to {target}"
+            ),
+            ProgsError::BadFunction { name, reason } => {
+                write!(f, "progs function {name}: {reason}")
+            }
+            ProgsError::BadGlobalValue { name, value } => {
+                write!(f, "progs global {name} has bad initial value {value}")
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -26,7 +165,7 @@ pub struct Statement {
     pub c: i16,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum QcType {
     Void,
     String,
@@ -40,6 +179,23 @@ pub enum QcType {
     Unknown(i16),
 }
 
+impl QcType {
+    pub fn name(self) -> &'static str {
+        match self {
+            QcType::Void => "void",
+            QcType::String => "string",
+            QcType::Float => "float",
+            QcType::Vector => "vector",
+            QcType::Entity => "entity",
+            QcType::Field => "field",
+            QcType::Function => "function",
+            QcType::Pointer => "pointer",
+            QcType::Integer => "integer",
+            QcType::Unknown(_) => "unknown",
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Definition {
     pub ty: QcType,
@@ -105,7 +261,7 @@ impl ProgsDat {
         let functions = read_functions(bytes, functions_lump, &strings)?;
         let globals = read_globals(bytes, globals_lump)?;
 
-        Ok(Self {
+        let progs = Self {
             version,
             crc,
             statements,
@@ -115,7 +271,67 @@ impl ProgsDat {
             strings,
             globals,
             entity_fields,
-        })
+        };
+        progs.verify()?;
+        Ok(progs)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut strings = StringTable::new(&self.strings);
+
+        let mut statements = Vec::with_capacity(self.statements.len() * 8);
+        for statement in &self.statements {
+            statements.extend_from_slice(&statement.op.to_le_bytes());
+            statements.extend_from_slice(&statement.a.to_le_bytes());
+            statements.extend_from_slice(&statement.b.to_le_bytes());
+            statements.extend_from_slice(&statement.c.to_le_bytes());
+        }
+        let global_defs = write_defs(&self.global_defs, &mut strings);
+        let field_defs = write_defs(&self.field_defs, &mut strings);
+        let mut functions = Vec::with_capacity(self.functions.len() * 36);
+        for func in &self.functions {
+            let name = strings.offset(&func.name);
+            let file = strings.offset(&func.file);
+            for value in [
+                func.first_statement,
+                func.parm_start,
+                func.locals,
+                func.profile,
+                name,
+                file,
+                func.num_params,
+            ] {
+                functions.extend_from_slice(&value.to_le_bytes());
+            }
+            functions.extend_from_slice(&func.param_sizes);
+        }
+        let mut globals = Vec::with_capacity(self.globals.len() * 4);
+        for value in &self.globals {
+            globals.extend_from_slice(&value.to_le_bytes());
+        }
+
+        let lumps = [
+            (statements, self.statements.len()),
+            (global_defs, self.global_defs.len()),
+            (field_defs, self.field_defs.len()),
+            (functions, self.functions.len()),
+            (strings.bytes, 0),
+            (globals, self.globals.len()),
+        ];
+        let mut header = Vec::with_capacity(60);
+        header.extend_from_slice(&self.version.to_le_bytes());
+        header.extend_from_slice(&self.crc.to_le_bytes());
+        let mut body = Vec::new();
+        for (index, (data, count)) in lumps.iter().enumerate() {
+            // The strings lump counts bytes rather than records.
+            let count = if index == 4 { data.len() } else { *count };
+            header.extend_from_slice(&((60 + body.len()) as i32).to_le_bytes());
+            header.extend_from_slice(&(count as i32).to_le_bytes());
+            body.extend_from_slice(data);
+        }
+        header.extend_from_slice(&self.entity_fields.to_le_bytes());
+        header.extend_from_slice(&body);
+        header
     }
 
     pub fn function_index(&self, name: &str) -> Option<usize> {
@@ -141,7 +357,7 @@ impl ProgsDat {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum VmError {
     BadGlobal(i16),
     BadStatement(i32),
@@ -153,6 +369,72 @@ pub enum VmError {
     BuiltinNotRegistered(i32),
     StepLimit { statement: i32, function: i32 },
     StringOverflow,
+    NoFreeEdicts,
+    StackOverflow(usize),
+    StringLimit(usize),
+    NotZoned(i32),
+    FrameStepLimit { statement: i32, function: i32 },
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VmError::BadGlobal(ofs) => write!(f, "bad global offset {ofs}"),
+            VmError::BadStatement(index) => write!(f, "bad statement {index}"),
+            VmError::BadFunction(index) => write!(f, "bad function {index}"),
+            VmError::BadEdict(index) => write!(f, "bad edict {index}"),
+            VmError::BadField(ofs) => write!(f, "bad field offset {ofs}"),
+            VmError::BadString(ofs) => write!(f, "bad string offset {ofs}"),
+            VmError::UnsupportedOpcode(op) => write!(f, "unsupported opcode {op}"),
+            VmError::BuiltinNotRegistered(number) => {
+                write!(f, "builtin #{} is not registered", number.unsigned_abs())
+            }
+            VmError::StepLimit {
+                statement,
+                function,
+            } => write!(
+                f,
+                "runaway loop at statement {statement} in function {function}"
+            ),
+            VmError::StringOverflow => write!(f, "string table overflow"),
+            VmError::NoFreeEdicts => write!(f, "no free edicts"),
+            VmError::StackOverflow(depth) => write!(f, "stack overflow (depth {depth})"),
+            VmError::StringLimit(limit) => {
+                write!(f, "string allocations exceeded {limit} bytes")
+            }
+            VmError::NotZoned(ofs) => write!(f, "string {ofs} was not made by strzone"),
+            VmError::FrameStepLimit {
+                statement,
+                function,
+            } => write!(
+                f,
+                "frame step budget used up at statement {statement} in function {function}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for VmError {}
+
+// Caps a server applies to progs it doesn't trust; the defaults are generous
+// enough for any normal mod.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VmLimits {
+    pub max_edicts: usize,
+    pub max_string_bytes: usize,
+    pub max_call_depth: usize,
+    pub max_frame_steps: usize,
+}
+
+impl Default for VmLimits {
+    fn default() -> Self {
+        Self {
+            max_edicts: MAX_EDICTS,
+            max_string_bytes: 16 << 20,
+            max_call_depth: MAX_CALL_DEPTH,
+            max_frame_steps: 10_000_000,
+        }
+    }
 }
 
 type VmResult<T> = Result<T, VmError>;
@@ -170,22 +452,47 @@ struct CallFrame {
 #[derive(Debug, Clone)]
 struct Edict {
     fields: Vec<u32>,
+    free: bool,
+    freetime: f32,
 }
 
 impl Edict {
     fn new(field_count: usize) -> Self {
         Self {
             fields: vec![0; field_count],
+            free: false,
+            freetime: 0.0,
         }
     }
+
+    fn clear(&mut self) {
+        self.fields.fill(0);
+        self.free = false;
+    }
+}
+
+// The reference interpreter decodes and checks each statement as it runs; it is
+// kept to compare against the decoded one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Interpreter {
+    #[default]
+    Decoded,
+    Reference,
 }
 
 pub struct Vm {
     progs: ProgsDat,
+    static_strings: usize,
+    string_heap: StringHeap,
+    decoded: DecodedProgs,
+    interpreter: Interpreter,
+    limits: VmLimits,
+    frame_steps: usize,
     globals: Vec<u32>,
     local_stack: Vec<u32>,
     call_stack: Vec<CallFrame>,
     edicts: Vec<Edict>,
+    reserved_edicts: usize,
     builtins: Vec<Option<BuiltinFn>>,
     context: Option<Box<dyn Any>>,
 }
@@ -196,11 +503,18 @@ impl Vm {
         let field_count = progs.entity_fields.max(0) as usize;
         let edicts = vec![Edict::new(field_count)];
         Self {
+            static_strings: progs.strings.len(),
+            string_heap: StringHeap::default(),
+            decoded: DecodedProgs::new(&progs),
+            interpreter: Interpreter::default(),
+            limits: VmLimits::default(),
+            frame_steps: 0,
             progs,
             globals,
             local_stack: Vec::new(),
             call_stack: Vec::new(),
             edicts,
+            reserved_edicts: 1,
             builtins: Vec::new(),
             context: None,
         }
@@ -228,6 +542,31 @@ impl Vm {
         &self.progs
     }
 
+    pub fn interpreter(&self) -> Interpreter {
+        self.interpreter
+    }
+
+    pub fn set_interpreter(&mut self, interpreter: Interpreter) {
+        self.interpreter = interpreter;
+    }
+
+    pub fn limits(&self) -> VmLimits {
+        self.limits
+    }
+
+    pub fn set_limits(&mut self, limits: VmLimits) {
+        self.limits = limits;
+    }
+
+    // Resets the step budget shared by every call made during one server frame.
+    pub fn start_frame(&mut self) {
+        self.frame_steps = 0;
+    }
+
+    pub fn frame_steps(&self) -> usize {
+        self.frame_steps
+    }
+
     pub fn register_builtin(&mut self, index: usize, func: BuiltinFn) {
         if self.builtins.len() <= index {
             self.builtins.resize_with(index + 1, || None);
@@ -251,11 +590,140 @@ impl Vm {
         self.edicts.len()
     }
 
-    pub fn alloc_edict(&mut self) -> usize {
+    pub fn active_edict_count(&self) -> usize {
+        self.edicts.iter().filter(|edict| !edict.free).count()
+    }
+
+    pub fn edict_is_free(&self, entity: usize) -> bool {
+        self.edicts.get(entity).is_some_and(|edict| edict.free)
+    }
+
+    pub fn edict_free_time(&self, entity: usize) -> Option<f32> {
+        self.edicts
+            .get(entity)
+            .filter(|edict| edict.free)
+            .map(|edict| edict.freetime)
+    }
+
+    // The most edicts this VM will hold, however they come to be made.
+    pub fn max_edicts(&self) -> usize {
+        self.limits.max_edicts.min(MAX_EDICTS)
+    }
+
+    pub fn reserve_edicts(&mut self, count: usize) {
+        let count = count.clamp(1, MAX_EDICTS);
+        let field_count = self.edict_field_count();
+        while self.edicts.len() < count {
+            self.edicts.push(Edict::new(field_count));
+        }
+        self.reserved_edicts = count;
+    }
+
+    pub fn alloc_edict(&mut self) -> VmResult<usize> {
+        // Wait before reusing a slot so clients don't lerp the new entity from the old one.
+        let time = self.current_time();
+        for index in self.reserved_edicts..self.edicts.len() {
+            let edict = &mut self.edicts[index];
+            if edict.free
+                && (edict.freetime < EDICT_STARTUP_GRACE
+                    || time - edict.freetime > EDICT_REUSE_DELAY)
+            {
+                edict.clear();
+                return Ok(index);
+            }
+        }
+
+        if self.edicts.len() >= self.max_edicts() {
+            return Err(VmError::NoFreeEdicts);
+        }
         let index = self.edicts.len();
         let field_count = self.edict_field_count();
         self.edicts.push(Edict::new(field_count));
-        index
+        Ok(index)
+    }
+
+    pub fn free_edict(&mut self, entity: usize) -> VmResult<()> {
+        if entity == 0 || entity >= self.edicts.len() {
+            return Err(VmError::BadEdict(entity as i32));
+        }
+
+        for (name, value) in FREED_EDICT_FIELDS {
+            let Some(def) = self.progs.field_def(name) else {
+                continue;
+            };
+            if def.offset < 0 {
+                continue;
+            }
+            let field = def.offset as usize;
+            let size = if def.ty == QcType::Vector { 3 } else { 1 };
+            let values = [value.to_bits(); 3];
+            self.write_edict_field_raw(entity, field, &values[..size])?;
+        }
+
+        let time = self.current_time();
+        let edict = &mut self.edicts[entity];
+        edict.free = true;
+        edict.freetime = time;
+        Ok(())
+    }
+
+    pub fn edict_field_strings(&self, entity: usize) -> VmResult<Vec<(String, String)>> {
+        let edict = self
+            .edicts
+            .get(entity)
+            .ok_or(VmError::BadEdict(entity as i32))?;
+        let mut pairs = Vec::new();
+        for def in &self.progs.field_defs {
+            if def.offset < 0 || is_vector_component(&def.name) {
+                continue;
+            }
+            let start = def.offset as usize;
+            let size = if def.ty == QcType::Vector { 3 } else { 1 };
+            let Some(values) = edict.fields.get(start..start + size) else {
+                continue;
+            };
+            if values.iter().all(|&value| value == 0) {
+                continue;
+            }
+            pairs.push((def.name.clone(), self.value_string(def.ty, values)));
+        }
+        Ok(pairs)
+    }
+
+    pub fn value_string(&self, ty: QcType, values: &[u32]) -> String {
+        let raw = values.first().copied().unwrap_or(0);
+        match ty {
+            QcType::String => self.progs.string_at(raw as i32).unwrap_or_default(),
+            QcType::Entity => format!("entity {}", f32::from_bits(raw) as i32),
+            QcType::Function => self
+                .progs
+                .functions
+                .get(raw as usize)
+                .map(|func| format!("{}()", func.name))
+                .unwrap_or_else(|| format!("bad function {raw}")),
+            QcType::Field => self
+                .progs
+                .field_defs
+                .iter()
+                .find(|def| def.offset as u32 == raw)
+                .map(|def| format!(".{}", def.name))
+                .unwrap_or_else(|| format!(".bad field {raw}")),
+            QcType::Void => "void".to_string(),
+            QcType::Float => format!("{:5.1}", f32::from_bits(raw)),
+            QcType::Vector => {
+                let component =
+                    |index: usize| f32::from_bits(values.get(index).copied().unwrap_or(0));
+                format!(
+                    "'{:5.1} {:5.1} {:5.1}'",
+                    component(0),
+                    component(1),
+                    component(2)
+                )
+            }
+            QcType::Pointer => "pointer".to_string(),
+            QcType::Integer => format!("{}", raw as i32),
+            QcType::Unknown(raw_type) => format!("bad type {raw_type}"),
+        }
     }
 
     pub fn call_by_name(&mut self, name: &str, max_steps: usize) -> VmResult<()> {
@@ -268,7 +736,14 @@ impl Vm {
 
     pub fn call_function(&mut self, index: usize, max_steps: usize) -> VmResult<()> {
         self.enter_function(index, None)?;
-        self.execute(max_steps)
+        let result = self.execute(max_steps);
+        if result.is_err() {
+            // Like PR_RunError, dump the stack so the next call starts clean.
+            while !self.call_stack.is_empty() {
+                self.leave_function();
+            }
+        }
+        result
     }
 
     pub fn read_param_raw(&self, param: usize) -> VmResult<u32> {
@@ -306,18 +781,10 @@ impl Vm {
     }
 
     pub fn set_return_string(&mut self, value: &str) -> VmResult<()> {
-        let offset = self.alloc_string(value)?;
+        let offset = self.alloc_temp_string(value)?;
         self.write_raw(OFS_RETURN as i16, offset as u32)
     }
 
-    pub fn alloc_string(&mut self, value: &str) -> VmResult<i32> {
-        let offset =
-            i32::try_from(self.progs.strings.len()).map_err(|_| VmError::StringOverflow)?;
-        self.progs.strings.extend_from_slice(value.as_bytes());
-        self.progs.strings.push(0);
-        Ok(offset)
-    }
-
     pub fn read_global_raw(&self, ofs: i16) -> VmResult<u32> {
         self.read_raw(ofs)
     }
@@ -410,10 +877,37 @@ impl Vm {
         self.write_edict_field_raw(entity, field, &values)
     }
 
+    fn current_time(&self) -> f32 {
+        self.progs
+            .global_def("time")
+            .and_then(|def| self.read_f32(def.offset).ok())
+            .unwrap_or(0.0)
+    }
+
     fn execute(&mut self, max_steps: usize) -> VmResult<()> {
-        let mut steps = 0usize;
+        let frame_budget = self.limits.max_frame_steps.saturating_sub(self.frame_steps);
+        let budget = max_steps.min(frame_budget);
+        let mut steps = 0;
+        let result = match self.interpreter {
+            Interpreter::Decoded => self.execute_decoded(budget, &mut steps),
+            Interpreter::Reference => self.execute_reference(budget, &mut steps),
+        };
+        self.frame_steps += steps;
+        match result {
+            Err(VmError::StepLimit {
+                statement,
+                function,
+            }) if budget < max_steps => Err(VmError::FrameStepLimit {
+                statement,
+                function,
+            }),
+            other => other,
+        }
+    }
+
+    fn execute_reference(&mut self, max_steps: usize, steps: &mut usize) -> VmResult<()> {
         while !self.call_stack.is_empty() {
-            if steps >= max_steps {
+            if *steps >= max_steps {
                 let (statement, function) = self
                     .call_stack
                     .last()
@@ -424,7 +918,7 @@ impl Vm {
                     function,
                 });
             }
-            steps += 1;
+            *steps += 1;
 
             let frame_index = self.call_stack.len() - 1;
             let statement_index = self.call_stack[frame_index].statement_index;
@@ -443,8 +937,10 @@ impl Vm {
                     update_statement = false;
                 }
                 OP_RETURN => {
-                    let a = statement.a;
-                    self.copy_global(a, OFS_RETURN, 3)?;
+                    // A float return still copies three slots; don't run off the end.
+                    let a = self.global_index(statement.a)?;
+                    let count = (self.globals.len() - a).min(3);
+                    self.copy_global(statement.a, OFS_RETURN, count)?;
                     self.leave_function();
                     update_statement = false;
                 }
@@ -516,9 +1012,11 @@ impl Vm {
                 OP_LOAD_V => {
                     let (entity, field) = self.read_entity_field(statement)?;
                     let values = self.edict_field(entity, field, 3)?;
-                    self.write_raw(statement.c, values[0])?;
-                    self.write_raw(statement.c + 1, values[1])?;
-                    self.write_raw(statement.c + 2, values[2])?;
+                    let c = self.global_index(statement.c)?;
+                    self.globals
+                        .get_mut(c..c + 3)
+                        .ok_or(VmError::BadGlobal(statement.c))?
+                        .copy_from_slice(&values);
                 }
                 OP_ADD_F => {
                     let value = self.read_f32(statement.a)? + self.read_f32(statement.b)?;
@@ -554,7 +1052,7 @@ impl Vm {
                 OP_MUL_V => {
                     let a = self.read_vec(statement.a)?;
                     let b = self.read_vec(statement.b)?;
-                    self.write_vec(statement.c, Vec3::new(a.x * b.x, a.y * b.y, a.z * b.z))?;
+                    self.write_f32(statement.c, a.x * b.x + a.y * b.y + a.z * b.z)?;
                 }
                 OP_MUL_FV => {
                     let scalar = self.read_f32(statement.a)?;
@@ -717,7 +1215,7 @@ impl Vm {
         let first_statement = func.first_statement;
         let parm_start = func.parm_start.max(0) as usize;
         let locals = func.locals.max(0) as usize;
-        let num_params = func.num_params.max(0) as usize;
+        let num_params = func.num_params.clamp(0, MAX_PARMS as i32) as usize;
         let param_sizes = func.param_sizes;
 
         if first_statement < 0 {
@@ -726,6 +1224,9 @@ impl Vm {
         if parm_start + locals > self.globals.len() {
             return Err(VmError::BadGlobal(func.parm_start as i16));
         }
+        if self.call_stack.len() >= self.limits.max_call_depth {
+            return Err(VmError::StackOverflow(self.call_stack.len()));
+        }
 
         let local_base = self.local_stack.len();
         self.local_stack
@@ -751,7 +1252,7 @@ impl Vm {
     }
 
     fn call_builtin(&mut self, first_statement: i32) -> VmResult<()> {
-        let builtin_index = (-first_statement) as usize;
+        let builtin_index = first_statement.unsigned_abs() as usize;
         let builtin = self
             .builtins
             .get(builtin_index)
@@ -895,85 +1396,100 @@ impl Vm {
     }
 
     fn read_vec(&self, ofs: i16) -> VmResult<Vec3> {
-        let x = self.read_f32(ofs)?;
-        let y = self.read_f32(ofs + 1)?;
-        let z = self.read_f32(ofs + 2)?;
-        Ok(Vec3::new(x, y, z))
+        let index = self.global_index(ofs)?;
+        let values = self
+            .globals
+            .get(index..index + 3)
+            .ok_or(VmError::BadGlobal(ofs))?;
+        Ok(Vec3::new(
+            f32::from_bits(values[0]),
+            f32::from_bits(values[1]),
+            f32::from_bits(values[2]),
+        ))
     }
 
     fn write_vec(&mut self, ofs: i16, value: Vec3) -> VmResult<()> {
-        self.write_f32(ofs, value.x)?;
-        self.write_f32(ofs + 1, value.y)?;
-        self.write_f32(ofs + 2, value.z)
+        let index = self.global_index(ofs)?;
+        self.globals
+            .get_mut(index..index + 3)
+            .ok_or(VmError::BadGlobal(ofs))?
+            .copy_from_slice(&[value.x.to_bits(), value.y.to_bits(), value.z.to_bits()]);
+        Ok(())
+    }
+}
+
+// Fields reset by ED_Free so a removed entity stops being drawn or thinking.
+const FREED_EDICT_FIELDS: [(&str, f32); 10] = [
+    ("model", 0.0),
+    ("takedamage", 0.0),
+    ("modelindex", 0.0),
+    ("colormap", 0.0),
+    ("skin", 0.0),
+    ("frame", 0.0),
+    ("origin", 0.0),
+    ("angles", 0.0),
+    ("nextthink", -1.0),
+    ("solid", 0.0),
+];
+
+struct StringTable {
+    bytes: Vec<u8>,
+    offsets: std::collections::HashMap<String, i32>,
+}
+
+impl StringTable {
+    fn new(existing: &[u8]) -> Self {
+        let mut bytes = existing.to_vec();
+        if bytes.is_empty() {
+            bytes.push(0);
+        }
+        let mut offsets = std::collections::HashMap::new();
+        let mut start = 0usize;
+        while start < bytes.len() {
+            let end = bytes[start..]
+                .iter()
+                .position(|&b| b == 0)
+                .map(|idx| start + idx)
+                .unwrap_or(bytes.len());
+            if let Ok(value) = std::str::from_utf8(&bytes[start..end]) {
+                offsets.entry(value.to_string()).or_insert(start as i32);
+            }
+            start = end + 1;
+        }
+        Self { bytes, offsets }
+    }
+
+    fn offset(&mut self, value: &str) -> i32 {
+        if value.is_empty() {
+            return 0;
+        }
+        if let Some(&offset) = self.offsets.get(value) {
+            return offset;
+        }
+        if self.bytes.last() != Some(&0) {
+            self.bytes.push(0);
+        }
+        let offset = self.bytes.len() as i32;
+        self.bytes.extend_from_slice(value.as_bytes());
+        self.bytes.push(0);
+        self.offsets.insert(value.to_string(), offset);
+        offset
     }
 }
 
-const OP_DONE: u16 = 0;
-const OP_MUL_F: u16 = 1;
-const OP_MUL_V: u16 = 2;
-const OP_MUL_FV: u16 = 3;
-const OP_MUL_VF: u16 = 4;
-const OP_DIV_F: u16 = 5;
-const OP_ADD_F: u16 = 6;
-const OP_ADD_V: u16 = 7;
-const OP_SUB_F: u16 = 8;
-const OP_SUB_V: u16 = 9;
-const OP_EQ_F: u16 = 10;
-const OP_EQ_V: u16 = 11;
-const OP_EQ_S: u16 = 12;
-const OP_EQ_E: u16 = 13;
-const OP_EQ_FNC: u16 = 14;
-const OP_NE_F: u16 = 15;
-const OP_NE_V: u16 = 16;
-const OP_NE_S: u16 = 17;
-const OP_NE_E: u16 = 18;
-const OP_NE_FNC: u16 = 19;
-const OP_LE: u16 = 20;
-const OP_GE: u16 = 21;
-const OP_LT: u16 = 22;
-const OP_GT: u16 = 23;
-const OP_LOAD_F: u16 = 24;
-const OP_LOAD_V: u16 = 25;
-const OP_LOAD_S: u16 = 26;
-const OP_LOAD_ENT: u16 = 27;
-const OP_LOAD_FLD: u16 = 28;
-const OP_LOAD_FNC: u16 = 29;
-const OP_ADDRESS: u16 = 30;
-const OP_STORE_F: u16 = 31;
-const OP_STORE_V: u16 = 32;
-const OP_STORE_S: u16 = 33;
-const OP_STORE_ENT: u16 = 34;
-const OP_STORE_FLD: u16 = 35;
-const OP_STORE_FNC: u16 = 36;
-const OP_STOREP_F: u16 = 37;
-const OP_STOREP_V: u16 = 38;
-const OP_STOREP_S: u16 = 39;
-const OP_STOREP_ENT: u16 = 40;
-const OP_STOREP_FLD: u16 = 41;
-const OP_STOREP_FNC: u16 = 42;
-const OP_RETURN: u16 = 43;
-const OP_NOT_F: u16 = 44;
-const OP_NOT_V: u16 = 45;
-const OP_NOT_S: u16 = 46;
-const OP_NOT_ENT: u16 = 47;
-const OP_NOT_FNC: u16 = 48;
-const OP_IF: u16 = 49;
-const OP_IFNOT: u16 = 50;
-const OP_CALL0: u16 = 51;
-const OP_CALL1: u16 = 52;
-const OP_CALL2: u16 = 53;
-const OP_CALL3: u16 = 54;
-const OP_CALL4: u16 = 55;
-const OP_CALL5: u16 = 56;
-const OP_CALL6: u16 = 57;
-const OP_CALL7: u16 = 58;
-const OP_CALL8: u16 = 59;
-const OP_STATE: u16 = 60;
-const OP_GOTO: u16 = 61;
-const OP_AND: u16 = 62;
-const OP_OR: u16 = 63;
-const OP_BITAND: u16 = 64;
-const OP_BITOR: u16 = 65;
+fn write_defs(defs: &[Definition], strings: &mut StringTable) -> Vec<u8> {
+    let mut out = Vec::with_capacity(defs.len() * 8);
+    for def in defs {
+        let mut raw_type = qc_type_raw(def.ty);
+        if def.save_global {
+            raw_type |= DEF_SAVEGLOBAL;
+        }
+        out.extend_from_slice(&raw_type.to_le_bytes());
+        out.extend_from_slice(&def.offset.to_le_bytes());
+        out.extend_from_slice(&strings.offset(&def.name).to_le_bytes());
+    }
+    out
+}
 
 fn read_defs(bytes: &[u8], lump: Lump, strings: &[u8]) -> Result<Vec<Definition>, ProgsError> {
     let size = lump
@@ -1126,6 +1642,11 @@ fn read_cstring(bytes: &[u8], offset: i32) -> Result<String, ProgsError> {
         .map_err(|_| ProgsError::InvalidUtf8)
 }
 
+fn is_vector_component(name: &str) -> bool {
+    let bytes = name.as_bytes();
+    bytes.len() > 2 && bytes[bytes.len() - 2] == b'_'
+}
+
 fn qc_type(raw: i16) -> QcType {
     match raw {
         0 => QcType::Void,
@@ -1141,6 +1662,21 @@ fn qc_type(raw: i16) -> QcType {
     }
 }
 
+fn qc_type_raw(ty: QcType) -> i16 {
+    match ty {
+        QcType::Void => 0,
+        QcType::String => 1,
+        QcType::Float => 2,
+        QcType::Vector => 3,
+        QcType::Entity => 4,
+        QcType::Field => 5,
+        QcType::Function => 6,
+        QcType::Pointer => 7,
+        QcType::Integer => 8,
+        QcType::Unknown(raw) => raw,
+    }
+}
+
 fn read_i32(bytes: &[u8], offset: usize) -> Result<i32, ProgsError> {
     let slice = bytes
         .get(offset..offset + 4)
@@ -1255,6 +1791,37 @@ mod tests {
         assert_eq!(progs.functions[0].name, "main");
     }
 
+    #[test]
+    fn to_bytes_round_trips() {
+        let mut progs = edict_test_progs();
+        progs.statements.push(Statement {
+            op: OP_ADD_F,
+            a: 28,
+            b: 29,
+            c: 30,
+        });
+        progs.functions.push(Function {
+            first_statement: 0,
+            parm_start: 29,
+            locals: 2,
+            profile: 0,
+            name: "added_name".to_string(),
+            file: "test.qc".to_string(),
+            num_params: 1,
+            param_sizes: [1, 0, 0, 0, 0, 0, 0, 0],
+        });
+        progs.global_defs[0].save_global = true;
+
+        let parsed = ProgsDat::from_bytes(&progs.to_bytes()).unwrap();
+        assert_eq!(parsed.statements, progs.statements);
+        assert_eq!(parsed.global_defs, progs.global_defs);
+        assert_eq!(parsed.field_defs, progs.field_defs);
+        assert_eq!(parsed.functions, progs.functions);
+        assert_eq!(parsed.globals, progs.globals);
+        assert_eq!(parsed.entity_fields, progs.entity_fields);
+        assert!(parsed.strings.starts_with(&progs.strings));
+    }
+
     #[test]
     fn reads_strings() {
         let bytes = b"\0hello\0world\0";
@@ -1279,7 +1846,7 @@ mod tests {
 
         let mut vm = Vm::new(progs);
         assert_eq!(vm.edict_count(), 1);
-        let entity = vm.alloc_edict();
+        let entity = vm.alloc_edict().unwrap();
         assert_eq!(entity, 1);
         assert_eq!(vm.edict_count(), 2);
 
@@ -1296,4 +1863,333 @@ mod tests {
         vm.write_edict_field_vec(entity, 1, vec).unwrap();
         assert_eq!(vm.read_edict_field_vec(entity, 1).unwrap(), vec);
     }
+
+    fn edict_test_progs() -> ProgsDat {
+        let strings = b"\0time\0nextthink\0origin\0origin_x\0".to_vec();
+        ProgsDat {
+            version: PROG_VERSION,
+            crc: 0,
+            statements: Vec::new(),
+            global_defs: vec![Definition {
+                ty: QcType::Float,
+                offset: 28,
+                name: "time".to_string(),
+                save_global: false,
+            }],
+            field_defs: vec![
+                Definition {
+                    ty: QcType::Float,
+                    offset: 0,
+                    name: "nextthink".to_string(),
+                    save_global: false,
+                },
+                Definition {
+                    ty: QcType::Vector,
+                    offset: 1,
+                    name: "origin".to_string(),
+                    save_global: false,
+                },
+                Definition {
+                    ty: QcType::Float,
+                    offset: 1,
+                    name: "origin_x".to_string(),
+                    save_global: false,
+                },
+            ],
+            functions: Vec::new(),
+            strings,
+            globals: vec![0; 32],
+            entity_fields: 4,
+        }
+    }
+
+    #[test]
+    fn freed_edicts_wait_before_reuse() {
+        let mut vm = Vm::new(edict_test_progs());
+        vm.write_global_f32(28, 10.0).unwrap();
+        let first = vm.alloc_edict().unwrap();
+        vm.write_edict_field_f32(first, 1, 5.0).unwrap();
+        vm.free_edict(first).unwrap();
+        assert!(vm.edict_is_free(first));
+        assert_eq!(vm.edict_free_time(first), Some(10.0));
+        assert_eq!(vm.read_edict_field_f32(first, 0).unwrap(), -1.0);
+        assert_eq!(vm.read_edict_field_f32(first, 1).unwrap(), 0.0);
+
+        vm.write_global_f32(28, 10.2).unwrap();
+        let second = vm.alloc_edict().unwrap();
+        assert_ne!(second, first);
+
+        vm.write_global_f32(28, 10.6).unwrap();
+        let reused = vm.alloc_edict().unwrap();
+        assert_eq!(reused, first);
+        assert!(!vm.edict_is_free(reused));
+        assert_eq!(vm.read_edict_field_f32(reused, 0).unwrap(), 0.0);
+    }
+
+    #[test]
+    fn edicts_freed_at_startup_are_reused_immediately() {
+        let mut vm = Vm::new(edict_test_progs());
+        let first = vm.alloc_edict().unwrap();
+        vm.free_edict(first).unwrap();
+        assert_eq!(vm.alloc_edict().unwrap(), first);
+    }
+
+    #[test]
+    fn reserved_edicts_are_never_allocated() {
+        let mut vm = Vm::new(edict_test_progs());
+        vm.reserve_edicts(5);
+        assert_eq!(vm.edict_count(), 5);
+        assert_eq!(vm.alloc_edict().unwrap(), 5);
+        assert!(vm.free_edict(0).is_err());
+    }
+
+    #[test]
+    fn alloc_edict_stops_at_max_edicts() {
+        let mut vm = Vm::new(edict_test_progs());
+        for _ in 1..MAX_EDICTS {
+            vm.alloc_edict().unwrap();
+        }
+        assert!(matches!(vm.alloc_edict(), Err(VmError::NoFreeEdicts)));
+        vm.free_edict(MAX_EDICTS - 1).unwrap();
+        assert_eq!(vm.alloc_edict().unwrap(), MAX_EDICTS - 1);
+    }
+
+    #[test]
+    fn limits_cap_edicts_strings_depth_and_frame_steps() {
+        let mut progs = edict_test_progs();
+        // 0: calls itself forever; 1: spins forever.
+        progs.statements = vec![
+            Statement {
+                op: OP_CALL0,
+                a: 30,
+                b: 0,
+                c: 0,
+            },
+            Statement {
+                op: OP_GOTO,
+                a: 0,
+                b: 0,
+                c: 0,
+            },
+        ];
+        let function = |first_statement| Function {
+            first_statement,
+            parm_start: 32,
+            locals: 0,
+            profile: 0,
+            name: String::new(),
+            file: String::new(),
+            num_params: 0,
+            param_sizes: [0; 8],
+        };
+        progs.functions = vec![function(0), function(0), function(1)];
+        progs.globals[30] = 1;
+        progs.globals.push(0);
+        let mut vm = Vm::new(progs);
+        vm.set_limits(VmLimits {
+            max_edicts: 4,
+            max_string_bytes: 32,
+            max_call_depth: 8,
+            max_frame_steps: 100,
+        });
+
+        for _ in 1..4 {
+            vm.alloc_edict().unwrap();
+        }
+        assert_eq!(vm.alloc_edict(), Err(VmError::NoFreeEdicts));
+        vm.alloc_string("1234").unwrap();
+        assert_eq!(
+            vm.alloc_string(&"x".repeat(16)),
+            Err(VmError::StringLimit(32))
+        );
+
+        assert_eq!(vm.call_function(1, 1000), Err(VmError::StackOverflow(8)));
+        assert!(vm.call_stack.is_empty());
+
+        vm.start_frame();
+        assert_eq!(
+            vm.call_function(2, 60),
+            Err(VmError::StepLimit {
+                statement: 1,
+                function: 2
+            })
+        );
+        assert!(matches!(
+            vm.call_function(2, 60),
+            Err(VmError::FrameStepLimit { function: 2, .. })
+        ));
+        assert_eq!(vm.frame_steps(), 100);
+        vm.start_frame();
+        assert_eq!(vm.frame_steps(), 0);
+    }
+
+    #[test]
+    fn returns_into_the_slot_compiled_code_reads() {
+        let mut progs = edict_test_progs();
+        // 0: return time; 1: call 0 and store what it gave back in 31.
+        progs.statements = vec![
+            Statement {
+                op: OP_RETURN,
+                a: 28,
+                b: 0,
+                c: 0,
+            },
+            Statement {
+                op: OP_CALL0,
+                a: 30,
+                b: 0,
+                c: 0,
+            },
+            Statement {
+                op: OP_STORE_F,
+                a: 1,
+                b: 31,
+                c: 0,
+            },
+            Statement {
+                op: OP_DONE,
+                a: 0,
+                b: 0,
+                c: 0,
+            },
+        ];
+        let function = |first_statement| Function {
+            first_statement,
+            parm_start: 32,
+            locals: 0,
+            profile: 0,
+            name: String::new(),
+            file: String::new(),
+            num_params: 0,
+            param_sizes: [0; 8],
+        };
+        progs.functions = vec![function(0), function(0), function(1)];
+        progs.globals[30] = 1;
+        let mut vm = Vm::new(progs);
+        vm.write_global_f32(28, 7.0).unwrap();
+
+        vm.call_function(2, 100).unwrap();
+        assert_eq!(vm.read_global_f32(31).unwrap(), 7.0);
+        // Slot 0 is the null slot every unset reference reads; a return
+        // there would make them all see the last value returned.
+        assert_eq!(vm.read_global_f32(0).unwrap(), 0.0);
+    }
+
+    #[test]
+    fn mul_v_is_a_dot_product() {
+        let mut progs = edict_test_progs();
+        progs.statements = vec![
+            Statement {
+                op: OP_MUL_V,
+                a: 32,
+                b: 35,
+                c: 38,
+            },
+            Statement {
+                op: OP_DONE,
+                a: 0,
+                b: 0,
+                c: 0,
+            },
+        ];
+        progs.functions = vec![
+            Function {
+                first_statement: 0,
+                parm_start: 0,
+                locals: 0,
+                profile: 0,
+                name: String::new(),
+                file: String::new(),
+                num_params: 0,
+                param_sizes: [0; 8],
+            };
+            2
+        ];
+        progs.globals.resize(48, 0);
+        for interpreter in [Interpreter::Reference, Interpreter::Decoded] {
+            let mut vm = Vm::new(progs.clone());
+            vm.set_interpreter(interpreter);
+            vm.write_global_vec(32, Vec3::new(1.0, 2.0, 3.0)).unwrap();
+            vm.write_global_vec(35, Vec3::new(4.0, 5.0, 6.0)).unwrap();
+            vm.call_function(1, 10).unwrap();
+            // pr_exec.c: a float result, with the slots after it untouched.
+            assert_eq!(vm.read_global_f32(38).unwrap(), 32.0, "{interpreter:?}");
+            assert_eq!(vm.read_global_f32(39).unwrap(), 0.0, "{interpreter:?}");
+            assert_eq!(vm.read_global_f32(40).unwrap(), 0.0, "{interpreter:?}");
+        }
+    }
+
+    #[test]
+    fn a_failed_call_unwinds_every_frame() {
+        let mut progs = edict_test_progs();
+        // 0: set a local and call 2; 3: spin until the step limit.
+        progs.statements = vec![
+            Statement {
+                op: OP_STORE_F,
+                a: 40,
+                b: 44,
+                c: 0,
+            },
+            Statement {
+                op: OP_CALL0,
+                a: 41,
+                b: 0,
+                c: 0,
+            },
+            Statement {
+                op: OP_DONE,
+                a: 0,
+                b: 0,
+                c: 0,
+            },
+            Statement {
+                op: OP_GOTO,
+                a: 0,
+                b: 0,
+                c: 0,
+            },
+        ];
+        let function = |first_statement, locals| Function {
+            first_statement,
+            parm_start: 44,
+            locals,
+            profile: 0,
+            name: String::new(),
+            file: String::new(),
+            num_params: 0,
+            param_sizes: [0; 8],
+        };
+        progs.functions = vec![function(0, 0), function(0, 1), function(3, 0)];
+        progs.globals.resize(48, 0);
+        progs.globals[40] = 9.0f32.to_bits();
+        progs.globals[41] = 2;
+        for interpreter in [Interpreter::Reference, Interpreter::Decoded] {
+            let mut vm = Vm::new(progs.clone());
+            vm.set_interpreter(interpreter);
+            vm.write_global_f32(44, 5.0).unwrap();
+            for _ in 0..2 {
+                assert!(matches!(
+                    vm.call_function(1, 50),
+                    Err(VmError::StepLimit { function: 2, .. })
+                ));
+                assert!(vm.call_stack.is_empty(), "{interpreter:?}");
+                assert!(vm.local_stack.is_empty(), "{interpreter:?}");
+                // The caller's local is put back as it would be on a return.
+                assert_eq!(vm.read_global_f32(44).unwrap(), 5.0, "{interpreter:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn edict_field_strings_skip_zero_and_components() {
+        let mut vm = Vm::new(edict_test_progs());
+        let entity = vm.alloc_edict().unwrap();
+        vm.write_edict_field_vec(entity, 1, Vec3::new(1.0, 2.0, 3.0))
+            .unwrap();
+        let pairs = vm.edict_field_strings(entity).unwrap();
+        assert_eq!(
+            pairs,
+            vec![("origin".to_string(), "'  1.0   2.0   3.0'".to_string())]
+        );
+    }
 }
@@ -0,0 +1,188 @@
+// QuakeC opcodes and their operand types, as in pr_comp.h.
+
+use crate::QcType;
+
+pub const OP_DONE: u16 = 0;
+pub const OP_MUL_F: u16 = 1;
+pub const OP_MUL_V: u16 = 2;
+pub const OP_MUL_FV: u16 = 3;
+pub const OP_MUL_VF: u16 = 4;
+pub const OP_DIV_F: u16 = 5;
+pub const OP_ADD_F: u16 = 6;
+pub const OP_ADD_V: u16 = 7;
+pub const OP_SUB_F: u16 = 8;
+pub const OP_SUB_V: u16 = 9;
+pub const OP_EQ_F: u16 = 10;
+pub const OP_EQ_V: u16 = 11;
+pub const OP_EQ_S: u16 = 12;
+pub const OP_EQ_E: u16 = 13;
+pub const OP_EQ_FNC: u16 = 14;
+pub const OP_NE_F: u16 = 15;
+pub const OP_NE_V: u16 = 16;
+pub const OP_NE_S: u16 = 17;
+pub const OP_NE_E: u16 = 18;
+pub const OP_NE_FNC: u16 = 19;
+pub const OP_LE: u16 = 20;
+pub const OP_GE: u16 = 21;
+pub const OP_LT: u16 = 22;
+pub const OP_GT: u16 = 23;
+pub const OP_LOAD_F: u16 = 24;
+pub const OP_LOAD_V: u16 = 25;
+pub const OP_LOAD_S: u16 = 26;
+pub const OP_LOAD_ENT: u16 = 27;
+pub const OP_LOAD_FLD: u16 = 28;
+pub const OP_LOAD_FNC: u16 = 29;
+pub const OP_ADDRESS: u16 = 30;
+pub const OP_STORE_F: u16 = 31;
+pub const OP_STORE_V: u16 = 32;
+pub const OP_STORE_S: u16 = 33;
+pub const OP_STORE_ENT: u16 = 34;
+pub const OP_STORE_FLD: u16 = 35;
+pub const OP_STORE_FNC: u16 = 36;
+pub const OP_STOREP_F: u16 = 37;
+pub const OP_STOREP_V: u16 = 38;
+pub const OP_STOREP_S: u16 = 39;
+pub const OP_STOREP_ENT: u16 = 40;
+pub const OP_STOREP_FLD: u16 = 41;
+pub const OP_STOREP_FNC: u16 = 42;
+pub const OP_RETURN: u16 = 43;
+pub const OP_NOT_F: u16 = 44;
+pub const OP_NOT_V: u16 = 45;
+pub const OP_NOT_S: u16 = 46;
+pub const OP_NOT_ENT: u16 = 47;
+pub const OP_NOT_FNC: u16 = 48;
+pub const OP_IF: u16 = 49;
+pub const OP_IFNOT: u16 = 50;
+pub const OP_CALL0: u16 = 51;
+pub const OP_CALL1: u16 = 52;
+pub const OP_CALL2: u16 = 53;
+pub const OP_CALL3: u16 = 54;
+pub const OP_CALL4: u16 = 55;
+pub const OP_CALL5: u16 = 56;
+pub const OP_CALL6: u16 = 57;
+pub const OP_CALL7: u16 = 58;
+pub const OP_CALL8: u16 = 59;
+pub const OP_STATE: u16 = 60;
+pub const OP_GOTO: u16 = 61;
+pub const OP_AND: u16 = 62;
+pub const OP_OR: u16 = 63;
+pub const OP_BITAND: u16 = 64;
+pub const OP_BITOR: u16 = 65;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Unused,
+    Global(QcType),
+    Jump,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpcodeInfo {
+    pub name: &'static str,
+    pub a: Operand,
+    pub b: Operand,
+    pub c: Operand,
+}
+
+pub fn opcode_info(op: u16) -> Option<OpcodeInfo> {
+    use Operand::{Global, Jump, Unused};
+    use QcType::{Entity, Field, Float, Function, Pointer, String, Vector, Void};
+
+    let (name, a, b, c) = match op {
+        OP_DONE => ("DONE", Unused, Unused, Unused),
+        OP_MUL_F => ("MUL_F", Global(Float), Global(Float), Global(Float)),
+        OP_MUL_V => ("MUL_V", Global(Vector), Global(Vector), Global(Float)),
+        OP_MUL_FV => ("MUL_FV", Global(Float), Global(Vector), Global(Vector)),
+        OP_MUL_VF => ("MUL_VF", Global(Vector), Global(Float), Global(Vector)),
+        OP_DIV_F => ("DIV_F", Global(Float), Global(Float), Global(Float)),
+        OP_ADD_F => ("ADD_F", Global(Float), Global(Float), Global(Float)),
+        OP_ADD_V => ("ADD_V", Global(Vector), Global(Vector), Global(Vector)),
+        OP_SUB_F => ("SUB_F", Global(Float), Global(Float), Global(Float)),
+        OP_SUB_V => ("SUB_V", Global(Vector), Global(Vector), Global(Vector)),
+        OP_EQ_F => ("EQ_F", Global(Float), Global(Float), Global(Float)),
+        OP_EQ_V => ("EQ_V", Global(Vector), Global(Vector), Global(Float)),
+        OP_EQ_S => ("EQ_S", Global(String), Global(String), Global(Float)),
+        OP_EQ_E => ("EQ_E", Global(Entity), Global(Entity), Global(Float)),
+        OP_EQ_FNC => ("EQ_FNC", Global(Function), Global(Function), Global(Float)),
+        OP_NE_F => ("NE_F", Global(Float), Global(Float), Global(Float)),
+        OP_NE_V => ("NE_V", Global(Vector), Global(Vector), Global(Float)),
+        OP_NE_S => ("NE_S", Global(String), Global(String), Global(Float)),
+        OP_NE_E => ("NE_E", Global(Entity), Global(Entity), Global(Float)),
+        OP_NE_FNC => ("NE_FNC", Global(Function), Global(Function), Global(Float)),
+        OP_LE => ("LE", Global(Float), Global(Float), Global(Float)),
+        OP_GE => ("GE", Global(Float), Global(Float), Global(Float)),
+        OP_LT => ("LT", Global(Float), Global(Float), Global(Float)),
+        OP_GT => ("GT", Global(Float), Global(Float), Global(Float)),
+        OP_LOAD_F => ("LOAD_F", Global(Entity), Global(Field), Global(Float)),
+        OP_LOAD_V => ("LOAD_V", Global(Entity), Global(Field), Global(Vector)),
+        OP_LOAD_S => ("LOAD_S", Global(Entity), Global(Field), Global(String)),
+        OP_LOAD_ENT => ("LOAD_ENT", Global(Entity), Global(Field), Global(Entity)),
+        OP_LOAD_FLD => ("LOAD_FLD", Global(Entity), Global(Field), Global(Field)),
+        OP_LOAD_FNC => ("LOAD_FNC", Global(Entity), Global(Field), Global(Function)),
+        OP_ADDRESS => ("ADDRESS", Global(Entity), Global(Field), Global(Pointer)),
+        OP_STORE_F => ("STORE_F", Global(Float), Global(Float), Unused),
+        OP_STORE_V => ("STORE_V", Global(Vector), Global(Vector), Unused),
+        OP_STORE_S => ("STORE_S", Global(String), Global(String), Unused),
+        OP_STORE_ENT => ("STORE_ENT", Global(Entity), Global(Entity), Unused),
+        OP_STORE_FLD => ("STORE_FLD", Global(Field), Global(Field), Unused),
+        OP_STORE_FNC => ("STORE_FNC", Global(Function), Global(Function), Unused),
+        OP_STOREP_F => ("STOREP_F", Global(Float), Global(Pointer), Unused),
+        OP_STOREP_V => ("STOREP_V", Global(Vector), Global(Pointer), Unused),
+        OP_STOREP_S => ("STOREP_S", Global(String), Global(Pointer), Unused),
+        OP_STOREP_ENT => ("STOREP_ENT", Global(Entity), Global(Pointer), Unused),
+        OP_STOREP_FLD => ("STOREP_FLD", Global(Field), Global(Pointer), Unused),
+        OP_STOREP_FNC => ("STOREP_FNC", Global(Function), Global(Pointer), Unused),
+        OP_RETURN => ("RETURN", Global(Void), Unused, Unused),
+        OP_NOT_F => ("NOT_F", Global(Float), Unused, Global(Float)),
+        OP_NOT_V => ("NOT_V", Global(Vector), Unused, Global(Float)),
+        OP_NOT_S => ("NOT_S", Global(String), Unused, Global(Float)),
+        OP_NOT_ENT => ("NOT_ENT", Global(Entity), Unused, Global(Float)),
+        OP_NOT_FNC => ("NOT_FNC", Global(Function), Unused, Global(Float)),
+        OP_IF => ("IF", Global(Float), Jump, Unused),
+        OP_IFNOT => ("IFNOT", Global(Float), Jump, Unused),
+        OP_CALL0 => ("CALL0", Global(Function), Unused, Unused),
+        OP_CALL1 => ("CALL1", Global(Function), Unused, Unused),
+        OP_CALL2 => ("CALL2", Global(Function), Unused, Unused),
+        OP_CALL3 => ("CALL3", Global(Function), Unused, Unused),
+        OP_CALL4 => ("CALL4", Global(Function), Unused, Unused),
+        OP_CALL5 => ("CALL5", Global(Function), Unused, Unused),
+        OP_CALL6 => ("CALL6", Global(Function), Unused, Unused),
+        OP_CALL7 => ("CALL7", Global(Function), Unused, Unused),
+        OP_CALL8 => ("CALL8", Global(Function), Unused, Unused),
+        OP_STATE => ("STATE", Global(Float), Global(Function), Unused),
+        OP_GOTO => ("GOTO", Jump, Unused, Unused),
+        OP_AND => ("AND", Global(Float), Global(Float), Global(Float)),
+        OP_OR => ("OR", Global(Float), Global(Float), Global(Float)),
+        OP_BITAND => ("BITAND", Global(Float), Global(Float), Global(Float)),
+        OP_BITOR => ("BITOR", Global(Float), Global(Float), Global(Float)),
+        _ => return None,
+    };
+    Some(OpcodeInfo { name, a, b, c })
+}
+
+pub fn is_call(op: u16) -> bool {
+    (OP_CALL0..=OP_CALL8).contains(&op)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_opcode_has_info() {
+        for op in OP_DONE..=OP_BITOR {
+            assert!(opcode_info(op).is_some(), "missing info for opcode {op}");
+        }
+        assert!(opcode_info(OP_BITOR + 1).is_none());
+    }
+
+    #[test]
+    fn describes_branch_operands() {
+        let info = opcode_info(OP_IFNOT).unwrap();
+        assert_eq!(info.name, "IFNOT");
+        assert_eq!(info.a, Operand::Global(QcType::Float));
+        assert_eq!(info.b, Operand::Jump);
+        assert!(is_call(OP_CALL3));
+        assert!(!is_call(OP_STATE));
+    }
+}
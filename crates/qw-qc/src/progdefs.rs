@@ -0,0 +1,362 @@
+// System globals and fields the engine expects, mirroring progdefs.h.
+
+use crate::{Definition, ProgsDat, ProgsError, QcType};
+
+pub const PROGHEADER_CRC_NQ: i32 = 5927;
+pub const PROGHEADER_CRC_QW: i32 = 54730;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgsFlavor {
+    NetQuake,
+    QuakeWorld,
+}
+
+impl ProgsFlavor {
+    pub fn from_crc(crc: i32) -> Option<Self> {
+        match crc {
+            PROGHEADER_CRC_NQ => Some(Self::NetQuake),
+            PROGHEADER_CRC_QW => Some(Self::QuakeWorld),
+            _ => None,
+        }
+    }
+
+    pub fn crc(self) -> i32 {
+        match self {
+            Self::NetQuake => PROGHEADER_CRC_NQ,
+            Self::QuakeWorld => PROGHEADER_CRC_QW,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::NetQuake => "NetQuake",
+            Self::QuakeWorld => "QuakeWorld",
+        }
+    }
+
+    pub fn system_globals(self) -> Vec<(&'static str, QcType)> {
+        let mut globals = vec![
+            ("self", QcType::Entity),
+            ("other", QcType::Entity),
+            ("world", QcType::Entity),
+            ("time", QcType::Float),
+            ("frametime", QcType::Float),
+        ];
+        if self == Self::QuakeWorld {
+            globals.push(("newmis", QcType::Entity));
+        }
+        globals.extend([
+            ("force_retouch", QcType::Float),
+            ("mapname", QcType::String),
+        ]);
+        if self == Self::NetQuake {
+            globals.extend([
+                ("deathmatch", QcType::Float),
+                ("coop", QcType::Float),
+                ("teamplay", QcType::Float),
+            ]);
+        }
+        globals.extend(COMMON_GLOBALS);
+        globals
+    }
+
+    pub fn system_fields(self) -> Vec<(&'static str, QcType)> {
+        let mut fields = vec![
+            ("modelindex", QcType::Float),
+            ("absmin", QcType::Vector),
+            ("absmax", QcType::Vector),
+            ("ltime", QcType::Float),
+        ];
+        if self == Self::QuakeWorld {
+            fields.push(("lastruntime", QcType::Float));
+        }
+        fields.extend([
+            ("movetype", QcType::Float),
+            ("solid", QcType::Float),
+            ("origin", QcType::Vector),
+            ("oldorigin", QcType::Vector),
+            ("velocity", QcType::Vector),
+            ("angles", QcType::Vector),
+            ("avelocity", QcType::Vector),
+        ]);
+        if self == Self::NetQuake {
+            fields.push(("punchangle", QcType::Vector));
+        }
+        fields.extend(COMMON_FIELDS_BEFORE_IDEALPITCH);
+        if self == Self::NetQuake {
+            fields.push(("idealpitch", QcType::Float));
+        }
+        fields.extend(COMMON_FIELDS_AFTER_IDEALPITCH);
+        fields
+    }
+}
+
+const COMMON_GLOBALS: [(&str, QcType); 44] = [
+    ("serverflags", QcType::Float),
+    ("total_secrets", QcType::Float),
+    ("total_monsters", QcType::Float),
+    ("found_secrets", QcType::Float),
+    ("killed_monsters", QcType::Float),
+    ("parm1", QcType::Float),
+    ("parm2", QcType::Float),
+    ("parm3", QcType::Float),
+    ("parm4", QcType::Float),
+    ("parm5", QcType::Float),
+    ("parm6", QcType::Float),
+    ("parm7", QcType::Float),
+    ("parm8", QcType::Float),
+    ("parm9", QcType::Float),
+    ("parm10", QcType::Float),
+    ("parm11", QcType::Float),
+    ("parm12", QcType::Float),
+    ("parm13", QcType::Float),
+    ("parm14", QcType::Float),
+    ("parm15", QcType::Float),
+    ("parm16", QcType::Float),
+    ("v_forward", QcType::Vector),
+    ("v_up", QcType::Vector),
+    ("v_right", QcType::Vector),
+    ("trace_allsolid", QcType::Float),
+    ("trace_startsolid", QcType::Float),
+    ("trace_fraction", QcType::Float),
+    ("trace_endpos", QcType::Vector),
+    ("trace_plane_normal", QcType::Vector),
+    ("trace_plane_dist", QcType::Float),
+    ("trace_ent", QcType::Entity),
+    ("trace_inopen", QcType::Float),
+    ("trace_inwater", QcType::Float),
+    ("msg_entity", QcType::Entity),
+    ("main", QcType::Function),
+    ("StartFrame", QcType::Function),
+    ("PlayerPreThink", QcType::Function),
+    ("PlayerPostThink", QcType::Function),
+    ("ClientKill", QcType::Function),
+    ("ClientConnect", QcType::Function),
+    ("PutClientInServer", QcType::Function),
+    ("ClientDisconnect", QcType::Function),
+    ("SetNewParms", QcType::Function),
+    ("SetChangeParms", QcType::Function),
+];
+
+const COMMON_FIELDS_BEFORE_IDEALPITCH: [(&str, QcType); 35] = [
+    ("classname", QcType::String),
+    ("model", QcType::String),
+    ("frame", QcType::Float),
+    ("skin", QcType::Float),
+    ("effects", QcType::Float),
+    ("mins", QcType::Vector),
+    ("maxs", QcType::Vector),
+    ("size", QcType::Vector),
+    ("touch", QcType::Function),
+    ("use", QcType::Function),
+    ("think", QcType::Function),
+    ("blocked", QcType::Function),
+    ("nextthink", QcType::Float),
+    ("groundentity", QcType::Entity),
+    ("health", QcType::Float),
+    ("frags", QcType::Float),
+    ("weapon", QcType::Float),
+    ("weaponmodel", QcType::String),
+    ("weaponframe", QcType::Float),
+    ("currentammo", QcType::Float),
+    ("ammo_shells", QcType::Float),
+    ("ammo_nails", QcType::Float),
+    ("ammo_rockets", QcType::Float),
+    ("ammo_cells", QcType::Float),
+    ("items", QcType::Float),
+    ("takedamage", QcType::Float),
+    ("chain", QcType::Entity),
+    ("deadflag", QcType::Float),
+    ("view_ofs", QcType::Vector),
+    ("button0", QcType::Float),
+    ("button1", QcType::Float),
+    ("button2", QcType::Float),
+    ("impulse", QcType::Float),
+    ("fixangle", QcType::Float),
+    ("v_angle", QcType::Vector),
+];
+
+const COMMON_FIELDS_AFTER_IDEALPITCH: [(&str, QcType); 29] = [
+    ("netname", QcType::String),
+    ("enemy", QcType::Entity),
+    ("flags", QcType::Float),
+    ("colormap", QcType::Float),
+    ("team", QcType::Float),
+    ("max_health", QcType::Float),
+    ("teleport_time", QcType::Float),
+    ("armortype", QcType::Float),
+    ("armorvalue", QcType::Float),
+    ("waterlevel", QcType::Float),
+    ("watertype", QcType::Float),
+    ("ideal_yaw", QcType::Float),
+    ("yaw_speed", QcType::Float),
+    ("aiment", QcType::Entity),
+    ("goalentity", QcType::Entity),
+    ("spawnflags", QcType::Float),
+    ("target", QcType::String),
+    ("targetname", QcType::String),
+    ("dmg_take", QcType::Float),
+    ("dmg_save", QcType::Float),
+    ("dmg_inflictor", QcType::Entity),
+    ("owner", QcType::Entity),
+    ("movedir", QcType::Vector),
+    ("message", QcType::String),
+    ("sounds", QcType::Float),
+    ("noise", QcType::String),
+    ("noise1", QcType::String),
+    ("noise2", QcType::String),
+    ("noise3", QcType::String),
+];
+
+impl ProgsDat {
+    pub fn flavor(&self) -> Option<ProgsFlavor> {
+        ProgsFlavor::from_crc(self.crc)
+    }
+
+    pub fn validate(&self) -> Result<ProgsFlavor, ProgsError> {
+        let flavor = self.flavor().ok_or(ProgsError::UnknownCrc(self.crc))?;
+        self.validate_as(flavor)?;
+        Ok(flavor)
+    }
+
+    pub fn validate_as(&self, flavor: ProgsFlavor) -> Result<(), ProgsError> {
+        if self.crc != flavor.crc() {
+            return Err(ProgsError::CrcMismatch {
+                expected: flavor.crc(),
+                found: self.crc,
+            });
+        }
+
+        for (name, ty) in flavor.system_globals() {
+            let def = self
+                .global_def(name)
+                .ok_or(ProgsError::MissingGlobal(name))?;
+            if def.ty != ty {
+                return Err(ProgsError::WrongGlobalType {
+                    name,
+                    expected: ty,
+                    found: def.ty,
+                });
+            }
+            check_def_offset(def, self.globals.len())?;
+        }
+        for (name, ty) in flavor.system_fields() {
+            let def = self.field_def(name).ok_or(ProgsError::MissingField(name))?;
+            if def.ty != ty {
+                return Err(ProgsError::WrongFieldType {
+                    name,
+                    expected: ty,
+                    found: def.ty,
+                });
+            }
+            check_def_offset(def, self.entity_fields.max(0) as usize)?;
+        }
+        Ok(())
+    }
+}
+
+fn check_def_offset(def: &Definition, limit: usize) -> Result<(), ProgsError> {
+    let size = if def.ty == QcType::Vector { 3 } else { 1 };
+    if def.offset < 0 || def.offset as usize + size > limit {
+        return Err(ProgsError::InvalidDefOffset {
+            name: def.name.clone(),
+            offset: def.offset,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PROG_VERSION;
+
+    fn progs_for(flavor: ProgsFlavor) -> ProgsDat {
+        let mut global_defs = Vec::new();
+        let mut offset = 28i16;
+        for (name, ty) in flavor.system_globals() {
+            global_defs.push(Definition {
+                ty,
+                offset,
+                name: name.to_string(),
+                save_global: false,
+            });
+            offset += if ty == QcType::Vector { 3 } else { 1 };
+        }
+        let mut field_defs = Vec::new();
+        let mut field_offset = 0i16;
+        for (name, ty) in flavor.system_fields() {
+            field_defs.push(Definition {
+                ty,
+                offset: field_offset,
+                name: name.to_string(),
+                save_global: false,
+            });
+            field_offset += if ty == QcType::Vector { 3 } else { 1 };
+        }
+        ProgsDat {
+            version: PROG_VERSION,
+            crc: flavor.crc(),
+            statements: Vec::new(),
+            global_defs,
+            field_defs,
+            functions: Vec::new(),
+            strings: vec![0],
+            globals: vec![0; offset as usize],
+            entity_fields: i32::from(field_offset),
+        }
+    }
+
+    #[test]
+    fn accepts_both_known_layouts() {
+        assert_eq!(
+            progs_for(ProgsFlavor::NetQuake).validate().unwrap(),
+            ProgsFlavor::NetQuake
+        );
+        assert_eq!(
+            progs_for(ProgsFlavor::QuakeWorld).validate().unwrap(),
+            ProgsFlavor::QuakeWorld
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_and_mismatched_crc() {
+        let mut progs = progs_for(ProgsFlavor::NetQuake);
+        progs.crc = 1234;
+        assert!(matches!(
+            progs.validate(),
+            Err(ProgsError::UnknownCrc(1234))
+        ));
+
+        let progs = progs_for(ProgsFlavor::NetQuake);
+        assert!(matches!(
+            progs.validate_as(ProgsFlavor::QuakeWorld),
+            Err(ProgsError::CrcMismatch {
+                expected: PROGHEADER_CRC_QW,
+                found: PROGHEADER_CRC_NQ,
+            })
+        ));
+    }
+
+    #[test]
+    fn reports_missing_and_mistyped_defs() {
+        let mut progs = progs_for(ProgsFlavor::QuakeWorld);
+        progs.global_defs.retain(|def| def.name != "other");
+        assert!(matches!(
+            progs.validate(),
+            Err(ProgsError::MissingGlobal("other"))
+        ));
+
+        let mut progs = progs_for(ProgsFlavor::QuakeWorld);
+        for def in &mut progs.field_defs {
+            if def.name == "origin" {
+                def.ty = QcType::Float;
+            }
+        }
+        let err = progs.validate().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "field origin should be vector but is float"
+        );
+    }
+}
@@ -0,0 +1,434 @@
+// Swapping a running VM over to a rebuilt progs.dat, carrying edicts and saved
+// globals across by name.
+
+use crate::strings::{StringHeap, StringKind};
+use crate::{DecodedProgs, Definition, Edict, ProgsDat, QcType, Vm, is_vector_component};
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug)]
+pub enum ReloadError {
+    Busy,
+    CrcChanged {
+        current: i32,
+        found: i32,
+    },
+    GlobalTypeChanged {
+        name: String,
+        current: QcType,
+        found: QcType,
+    },
+    FieldTypeChanged {
+        name: String,
+        current: QcType,
+        found: QcType,
+    },
+}
+
+impl fmt::Display for ReloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReloadError::Busy => write!(f, "can't reload progs while QuakeC is running"),
+            ReloadError::CrcChanged { current, found } => write!(
+                f,
+                "new progs has crc {found} but the running progs has crc {current}"
+            ),
+            ReloadError::GlobalTypeChanged {
+                name,
+                current,
+                found,
+            } => write!(
+                f,
+                "global {name} changed from {} to {}",
+                current.name(),
+                found.name()
+            ),
+            ReloadError::FieldTypeChanged {
+                name,
+                current,
+                found,
+            } => write!(
+                f,
+                "field {name} changed from {} to {}",
+                current.name(),
+                found.name()
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReloadReport {
+    pub globals_kept: usize,
+    pub fields_kept: usize,
+    pub added_fields: Vec<String>,
+    pub dropped_fields: Vec<String>,
+    pub dropped_globals: Vec<String>,
+    pub missing_functions: Vec<String>,
+}
+
+impl Vm {
+    // Edict fields and saved globals that exist under the same name in both
+    // progs keep their values; strings, functions and field references are
+    // translated to the new tables. Anything with a changed type refuses the
+    // reload and leaves the VM untouched. Builtins must be registered again.
+    pub fn reload_progs(&mut self, mut progs: ProgsDat) -> Result<ReloadReport, ReloadError> {
+        if !self.call_stack.is_empty() {
+            return Err(ReloadError::Busy);
+        }
+        if progs.crc != self.progs.crc {
+            return Err(ReloadError::CrcChanged {
+                current: self.progs.crc,
+                found: progs.crc,
+            });
+        }
+
+        let globals = matching_defs(
+            saved_globals(&self.progs.global_defs),
+            saved_globals(&progs.global_defs),
+        );
+        for (old, new) in &globals {
+            if old.ty != new.ty {
+                return Err(ReloadError::GlobalTypeChanged {
+                    name: new.name.clone(),
+                    current: old.ty,
+                    found: new.ty,
+                });
+            }
+        }
+        let fields = matching_defs(named(&self.progs.field_defs), named(&progs.field_defs));
+        for (old, new) in &fields {
+            if old.ty != new.ty {
+                return Err(ReloadError::FieldTypeChanged {
+                    name: new.name.clone(),
+                    current: old.ty,
+                    found: new.ty,
+                });
+            }
+        }
+
+        let mut report = ReloadReport::default();
+        for def in named(&progs.field_defs) {
+            if !is_vector_component(&def.name) && self.progs.field_def(&def.name).is_none() {
+                report.added_fields.push(def.name.clone());
+            }
+        }
+        for def in named(&self.progs.field_defs) {
+            if !is_vector_component(&def.name) && progs.field_def(&def.name).is_none() {
+                report.dropped_fields.push(def.name.clone());
+            }
+        }
+        for def in saved_globals(&self.progs.global_defs) {
+            if !is_vector_component(&def.name)
+                && !globals.iter().any(|(old, _)| old.name == def.name)
+            {
+                report.dropped_globals.push(def.name.clone());
+            }
+        }
+        report.globals_kept = globals
+            .iter()
+            .filter(|(old, _)| !is_vector_component(&old.name))
+            .count();
+        report.fields_kept = fields
+            .iter()
+            .filter(|(old, _)| !is_vector_component(&old.name))
+            .count();
+
+        let field_offsets: HashMap<u32, u32> = fields
+            .iter()
+            .map(|(old, new)| (old.offset as u32, new.offset as u32))
+            .collect();
+        let static_strings = progs.strings.len();
+        let mut remap = ValueRemap {
+            old: &self.progs,
+            old_heap: &self.string_heap,
+            new: &mut progs,
+            heap: StringHeap::default(),
+            field_offsets,
+            strings: HashMap::new(),
+            missing_functions: &mut report.missing_functions,
+        };
+
+        let mut new_globals = remap.new.globals.clone();
+        for (old, new) in &globals {
+            let from = old.offset as usize;
+            let to = new.offset as usize;
+            for slot in 0..value_size(old.ty) {
+                if let (Some(&value), Some(target)) = (
+                    self.globals.get(from + slot),
+                    new_globals.get_mut(to + slot),
+                ) {
+                    *target = remap.value(old.ty, value);
+                }
+            }
+        }
+
+        let field_count = remap.new.entity_fields.max(0) as usize;
+        let mut new_edicts = Vec::with_capacity(self.edicts.len());
+        for edict in &self.edicts {
+            let mut moved = Edict::new(field_count);
+            moved.free = edict.free;
+            moved.freetime = edict.freetime;
+            if !edict.free {
+                for (old, new) in &fields {
+                    let from = old.offset as usize;
+                    let to = new.offset as usize;
+                    for slot in 0..value_size(old.ty) {
+                        if let (Some(&value), Some(target)) = (
+                            edict.fields.get(from + slot),
+                            moved.fields.get_mut(to + slot),
+                        ) {
+                            *target = remap.value(old.ty, value);
+                        }
+                    }
+                }
+            }
+            new_edicts.push(moved);
+        }
+
+        let string_heap = remap.heap;
+        report.missing_functions.sort();
+        report.missing_functions.dedup();
+        self.decoded = DecodedProgs::new(&progs);
+        self.static_strings = static_strings;
+        self.string_heap = string_heap;
+        self.progs = progs;
+        self.globals = new_globals;
+        self.edicts = new_edicts;
+        self.local_stack.clear();
+        self.builtins.clear();
+        Ok(report)
+    }
+}
+
+struct ValueRemap<'a> {
+    old: &'a ProgsDat,
+    old_heap: &'a StringHeap,
+    new: &'a mut ProgsDat,
+    heap: StringHeap,
+    field_offsets: HashMap<u32, u32>,
+    strings: HashMap<u32, u32>,
+    missing_functions: &'a mut Vec<String>,
+}
+
+impl ValueRemap<'_> {
+    fn value(&mut self, ty: QcType, value: u32) -> u32 {
+        if value == 0 {
+            return 0;
+        }
+        match ty {
+            QcType::String => self.string(value),
+            QcType::Function => {
+                let Some(function) = self.old.functions.get(value as usize) else {
+                    return 0;
+                };
+                match self.new.function_index(&function.name) {
+                    Some(index) => index as u32,
+                    None => {
+                        self.missing_functions.push(function.name.clone());
+                        0
+                    }
+                }
+            }
+            QcType::Field => self.field_offsets.get(&value).copied().unwrap_or(0),
+            _ => value,
+        }
+    }
+
+    // Strings are offsets into the old string table, so the text is copied
+    // over to the end of the new one. Zoned strings stay zoned; everything else,
+    // temp strings included, is kept for good.
+    fn string(&mut self, value: u32) -> u32 {
+        if let Some(&offset) = self.strings.get(&value) {
+            return offset;
+        }
+        let Ok(text) = self.old.string_at(value as i32) else {
+            return 0;
+        };
+        let kind = match self.old_heap.kind_at(value as usize) {
+            Some(StringKind::Zone) => StringKind::Zone,
+            _ => StringKind::Engine,
+        };
+        let Some(offset) =
+            self.heap
+                .alloc(&mut self.new.strings, text.as_bytes(), kind, usize::MAX)
+        else {
+            return 0;
+        };
+        let offset = offset as u32;
+        self.strings.insert(value, offset);
+        offset
+    }
+}
+
+fn named(defs: &[Definition]) -> impl Iterator<Item = &Definition> + Clone {
+    defs.iter().filter(|def| !def.name.is_empty())
+}
+
+fn saved_globals(defs: &[Definition]) -> impl Iterator<Item = &Definition> + Clone {
+    named(defs).filter(|def| def.save_global)
+}
+
+fn matching_defs<'a>(
+    old: impl Iterator<Item = &'a Definition>,
+    new: impl Iterator<Item = &'a Definition> + Clone,
+) -> Vec<(Definition, Definition)> {
+    old.filter_map(|old_def| {
+        new.clone()
+            .find(|new_def| new_def.name == old_def.name)
+            .map(|new_def| (old_def.clone(), new_def.clone()))
+    })
+    .collect()
+}
+
+fn value_size(ty: QcType) -> usize {
+    if ty == QcType::Vector { 3 } else { 1 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Function, PROG_VERSION};
+    use qw_common::Vec3;
+
+    fn def(ty: QcType, offset: i16, name: &str, save_global: bool) -> Definition {
+        Definition {
+            ty,
+            offset,
+            name: name.to_string(),
+            save_global,
+        }
+    }
+
+    fn function(name: &str) -> Function {
+        Function {
+            first_statement: -1,
+            parm_start: 0,
+            locals: 0,
+            profile: 0,
+            name: name.to_string(),
+            file: String::new(),
+            num_params: 0,
+            param_sizes: [0; 8],
+        }
+    }
+
+    fn progs(field_defs: Vec<Definition>, functions: &[&str], entity_fields: i32) -> ProgsDat {
+        ProgsDat {
+            version: PROG_VERSION,
+            crc: 0,
+            statements: Vec::new(),
+            global_defs: vec![
+                def(QcType::Float, 28, "time", false),
+                def(QcType::Float, 29, "level", true),
+                def(QcType::String, 30, "motd", true),
+            ],
+            field_defs,
+            functions: functions.iter().map(|name| function(name)).collect(),
+            strings: b"\0".to_vec(),
+            globals: vec![0; 32],
+            entity_fields,
+        }
+    }
+
+    fn old_progs() -> ProgsDat {
+        progs(
+            vec![
+                def(QcType::Float, 0, "health", false),
+                def(QcType::Function, 1, "think", false),
+                def(QcType::String, 2, "classname", false),
+                def(QcType::Vector, 3, "origin", false),
+                def(QcType::Float, 3, "origin_x", false),
+                def(QcType::Float, 6, "oldstuff", false),
+            ],
+            &["", "monster_think", "removed_think"],
+            7,
+        )
+    }
+
+    fn new_progs() -> ProgsDat {
+        progs(
+            vec![
+                def(QcType::Float, 0, "armor", false),
+                def(QcType::Vector, 1, "origin", false),
+                def(QcType::Float, 1, "origin_x", false),
+                def(QcType::String, 4, "classname", false),
+                def(QcType::Float, 5, "health", false),
+                def(QcType::Function, 6, "think", false),
+            ],
+            &["", "helper", "monster_think"],
+            7,
+        )
+    }
+
+    #[test]
+    fn remaps_edicts_and_saved_globals_by_name() {
+        let mut vm = Vm::new(old_progs());
+        vm.write_global_f32(28, 12.5).unwrap();
+        vm.write_global_f32(29, 3.0).unwrap();
+        let motd = vm.alloc_string("welcome").unwrap();
+        vm.write_global_raw(30, motd as u32).unwrap();
+        let monster = vm.alloc_edict().unwrap();
+        let classname = vm.alloc_string("monster_ogre").unwrap();
+        vm.write_edict_field_f32(monster, 0, 80.0).unwrap();
+        vm.write_edict_field_raw(monster, 1, &[1]).unwrap();
+        vm.write_edict_field_raw(monster, 2, &[classname as u32])
+            .unwrap();
+        vm.write_edict_field_vec(monster, 3, Vec3::new(1.0, 2.0, 3.0))
+            .unwrap();
+        vm.write_edict_field_f32(monster, 6, 9.0).unwrap();
+        let other = vm.alloc_edict().unwrap();
+        vm.write_edict_field_raw(other, 1, &[2]).unwrap();
+
+        let report = vm.reload_progs(new_progs()).unwrap();
+        assert_eq!(report.fields_kept, 4);
+        assert_eq!(report.globals_kept, 2);
+        assert_eq!(report.added_fields, vec!["armor".to_string()]);
+        assert_eq!(report.dropped_fields, vec!["oldstuff".to_string()]);
+        assert_eq!(report.missing_functions, vec!["removed_think".to_string()]);
+
+        assert_eq!(vm.edict_field_count(), 7);
+        assert_eq!(vm.read_global_f32(28).unwrap(), 0.0);
+        assert_eq!(vm.read_global_f32(29).unwrap(), 3.0);
+        let motd = vm.read_global_raw(30).unwrap();
+        assert_eq!(vm.progs().string_at(motd as i32).unwrap(), "welcome");
+        assert_eq!(vm.read_edict_field_f32(monster, 0).unwrap(), 0.0);
+        assert_eq!(
+            vm.read_edict_field_vec(monster, 1).unwrap(),
+            Vec3::new(1.0, 2.0, 3.0)
+        );
+        let classname = vm.read_edict_field_raw(monster, 4, 1).unwrap()[0];
+        assert_eq!(
+            vm.progs().string_at(classname as i32).unwrap(),
+            "monster_ogre"
+        );
+        assert_eq!(vm.read_edict_field_f32(monster, 5).unwrap(), 80.0);
+        assert_eq!(vm.read_edict_field_raw(monster, 6, 1).unwrap()[0], 2);
+        assert_eq!(vm.read_edict_field_raw(other, 6, 1).unwrap()[0], 0);
+    }
+
+    #[test]
+    fn refuses_changed_types_without_touching_the_vm() {
+        let mut vm = Vm::new(old_progs());
+        let monster = vm.alloc_edict().unwrap();
+        vm.write_edict_field_f32(monster, 0, 80.0).unwrap();
+
+        let mut changed = new_progs();
+        changed.field_defs[4].ty = QcType::String;
+        let err = vm.reload_progs(changed).unwrap_err();
+        assert_eq!(err.to_string(), "field health changed from float to string");
+        assert_eq!(vm.read_edict_field_f32(monster, 0).unwrap(), 80.0);
+
+        let mut changed = new_progs();
+        changed.global_defs[1].ty = QcType::Entity;
+        let err = vm.reload_progs(changed).unwrap_err();
+        assert_eq!(err.to_string(), "global level changed from float to entity");
+
+        let mut changed = new_progs();
+        changed.crc = 5927;
+        assert!(matches!(
+            vm.reload_progs(changed),
+            Err(ReloadError::CrcChanged { .. })
+        ));
+    }
+}
@@ -0,0 +1,538 @@
+// NetQuake .sav files as written by Host_Savegame_f: a header, the saved globals
+// as one { } block and then one block per edict, free edicts left empty.
+
+use crate::{Edict, QcType, Vm, is_vector_component};
+use qw_common::{MAX_LIGHTSTYLES, com_parse};
+use std::fmt;
+use std::fmt::Write as _;
+
+pub const SAVEGAME_VERSION: i32 = 5;
+pub const NUM_SPAWN_PARMS: usize = 16;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SaveGame {
+    pub comment: String,
+    pub spawn_parms: [f32; NUM_SPAWN_PARMS],
+    pub skill: i32,
+    pub mapname: String,
+    pub time: f32,
+    pub lightstyles: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SaveGameError {
+    Busy,
+    UnsupportedVersion(String),
+    Truncated,
+    UnexpectedToken(String),
+    BadValue { key: String, value: String },
+    TooManyEdicts,
+}
+
+impl fmt::Display for SaveGameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveGameError::Busy => write!(f, "can't load a game while QuakeC is running"),
+            SaveGameError::UnsupportedVersion(version) => {
+                write!(f, "savegame is version {version}, not {SAVEGAME_VERSION}")
+            }
+            SaveGameError::Truncated => write!(f, "savegame is truncated"),
+            SaveGameError::UnexpectedToken(token) => {
+                write!(f, "savegame parse error at \"{token}\"")
+            }
+            SaveGameError::BadValue { key, value } => {
+                write!(f, "savegame has bad value \"{value}\" for {key}")
+            }
+            SaveGameError::TooManyEdicts => write!(f, "savegame has too many edicts"),
+        }
+    }
+}
+
+impl SaveGame {
+    // Just the header, to find out which map to spawn before loading the rest.
+    pub fn parse_header(text: &str) -> Result<Self, SaveGameError> {
+        read_header(text).map(|(save, _)| save)
+    }
+}
+
+impl Vm {
+    pub fn write_savegame(&self, save: &SaveGame) -> String {
+        let mut out = String::new();
+        let comment: String = save
+            .comment
+            .chars()
+            .map(|c| if c.is_whitespace() { '_' } else { c })
+            .collect();
+        let _ = writeln!(out, "{SAVEGAME_VERSION}");
+        let _ = writeln!(out, "{}", if comment.is_empty() { "_" } else { &comment });
+        for parm in save.spawn_parms {
+            let _ = writeln!(out, "{parm:.6}");
+        }
+        let _ = writeln!(out, "{}", save.skill);
+        let _ = writeln!(out, "{}", save.mapname);
+        let _ = writeln!(out, "{:.6}", save.time);
+        for index in 0..MAX_LIGHTSTYLES {
+            let style = save.lightstyles.get(index).map(String::as_str);
+            let _ = writeln!(out, "{}", style.filter(|s| !s.is_empty()).unwrap_or("m"));
+        }
+
+        // ED_WriteGlobals only keeps the plain value types.
+        out.push_str("{\n");
+        for def in &self.progs.global_defs {
+            if !def.save_global
+                || !matches!(def.ty, QcType::String | QcType::Float | QcType::Entity)
+            {
+                continue;
+            }
+            let Some(&value) = self.globals.get(def.offset.max(0) as usize) else {
+                continue;
+            };
+            let _ = writeln!(
+                out,
+                "\"{}\" \"{}\"",
+                def.name,
+                self.ugly_value_string(def.ty, &[value])
+            );
+        }
+        out.push_str("}\n");
+
+        for edict in &self.edicts {
+            out.push_str("{\n");
+            if !edict.free {
+                for def in &self.progs.field_defs {
+                    if def.name.is_empty() || is_vector_component(&def.name) {
+                        continue;
+                    }
+                    let start = def.offset.max(0) as usize;
+                    let size = if def.ty == QcType::Vector { 3 } else { 1 };
+                    let Some(values) = edict.fields.get(start..start + size) else {
+                        continue;
+                    };
+                    if values.iter().all(|&value| value == 0) {
+                        continue;
+                    }
+                    let _ = writeln!(
+                        out,
+                        "\"{}\" \"{}\"",
+                        def.name,
+                        self.ugly_value_string(def.ty, values)
+                    );
+                }
+            }
+            out.push_str("}\n");
+        }
+        out
+    }
+
+    // Like Host_Loadgame_f after the map has been spawned: globals start from
+    // the progs defaults, and the edict list is replaced by the saved one.
+    pub fn read_savegame(&mut self, text: &str) -> Result<SaveGame, SaveGameError> {
+        if !self.call_stack.is_empty() {
+            return Err(SaveGameError::Busy);
+        }
+        let (save, mut rest) = read_header(text)?;
+
+        let mut blocks = Vec::new();
+        while let Some((token, after)) = com_parse(rest) {
+            if token != "{" {
+                return Err(SaveGameError::UnexpectedToken(token));
+            }
+            // The globals come first, then one block per edict.
+            if blocks.len() > self.max_edicts() {
+                return Err(SaveGameError::TooManyEdicts);
+            }
+            let mut pairs = Vec::new();
+            rest = after;
+            loop {
+                let (key, after) = com_parse(rest).ok_or(SaveGameError::Truncated)?;
+                rest = after;
+                if key == "}" {
+                    break;
+                }
+                let (value, after) = com_parse(rest).ok_or(SaveGameError::Truncated)?;
+                rest = after;
+                if value == "}" {
+                    return Err(SaveGameError::UnexpectedToken(value));
+                }
+                pairs.push((key, value));
+            }
+            blocks.push(pairs);
+        }
+        let mut blocks = blocks.into_iter();
+        let globals = blocks.next().ok_or(SaveGameError::Truncated)?;
+
+        // Parse into scratch state so a bad value leaves the VM as it was.
+        let static_strings = self.static_strings;
+        let strings = std::mem::take(&mut self.progs.strings);
+        let string_heap = std::mem::take(&mut self.string_heap);
+        let old_globals = std::mem::replace(&mut self.globals, self.progs.globals.clone());
+        let old_edicts = std::mem::take(&mut self.edicts);
+        self.progs.strings = strings[..static_strings].to_vec();
+        let result = self.parse_saved_blocks(&globals, blocks);
+        if result.is_err() {
+            self.progs.strings = strings;
+            self.string_heap = string_heap;
+            self.globals = old_globals;
+            self.edicts = old_edicts;
+        }
+        result?;
+        Ok(save)
+    }
+
+    fn parse_saved_blocks(
+        &mut self,
+        globals: &[(String, String)],
+        edicts: impl Iterator<Item = Vec<(String, String)>>,
+    ) -> Result<(), SaveGameError> {
+        for (key, value) in globals {
+            let Some(def) = self.progs.global_def(key).cloned() else {
+                continue;
+            };
+            let values = self.parse_saved_value(def.ty, key, value)?;
+            let start = def.offset.max(0) as usize;
+            if let Some(slots) = self.globals.get_mut(start..start + values.len()) {
+                slots.copy_from_slice(&values);
+            }
+        }
+
+        let field_count = self.edict_field_count();
+        for pairs in edicts {
+            let mut edict = Edict::new(field_count);
+            edict.free = pairs.is_empty();
+            for (key, value) in &pairs {
+                if key.starts_with('_') {
+                    continue;
+                }
+                let Some(def) = self.progs.field_def(key).cloned() else {
+                    continue;
+                };
+                let values = self.parse_saved_value(def.ty, key, value)?;
+                let start = def.offset.max(0) as usize;
+                if let Some(slots) = edict.fields.get_mut(start..start + values.len()) {
+                    slots.copy_from_slice(&values);
+                }
+            }
+            self.edicts.push(edict);
+        }
+        while self.edicts.len() < self.reserved_edicts {
+            self.edicts.push(Edict::new(field_count));
+        }
+        Ok(())
+    }
+
+    // ED_ParseEpair
+    fn parse_saved_value(
+        &mut self,
+        ty: QcType,
+        key: &str,
+        value: &str,
+    ) -> Result<Vec<u32>, SaveGameError> {
+        let bad_value = || SaveGameError::BadValue {
+            key: key.to_string(),
+            value: value.to_string(),
+        };
+        let raw = match ty {
+            QcType::String => {
+                let offset = self
+                    .alloc_string(&unescape(value))
+                    .map_err(|_| bad_value())?;
+                offset as u32
+            }
+            QcType::Float => atof(value).to_bits(),
+            QcType::Vector => {
+                let mut parts = value.split_whitespace().map(atof);
+                return Ok((0..3)
+                    .map(|_| parts.next().unwrap_or(0.0).to_bits())
+                    .collect());
+            }
+            QcType::Entity => (atof(value) as i32 as f32).to_bits(),
+            QcType::Field => {
+                let def = self.progs.field_def(value).ok_or_else(bad_value)?;
+                def.offset as u32
+            }
+            QcType::Function => self.progs.function_index(value).ok_or_else(bad_value)? as u32,
+            _ => return Ok(Vec::new()),
+        };
+        Ok(vec![raw])
+    }
+
+    // PR_UglyValueString
+    fn ugly_value_string(&self, ty: QcType, values: &[u32]) -> String {
+        let raw = values.first().copied().unwrap_or(0);
+        match ty {
+            QcType::String => self.progs.string_at(raw as i32).unwrap_or_default(),
+            QcType::Entity => format!("{}", f32::from_bits(raw) as i32),
+            QcType::Function => self
+                .progs
+                .functions
+                .get(raw as usize)
+                .map(|func| func.name.clone())
+                .unwrap_or_default(),
+            QcType::Field => self
+                .progs
+                .field_defs
+                .iter()
+                .find(|def| def.offset as u32 == raw)
+                .map(|def| def.name.clone())
+                .unwrap_or_default(),
+            QcType::Void => "void".to_string(),
+            QcType::Float => format!("{:.6}", f32::from_bits(raw)),
+            QcType::Vector => {
+                let component =
+                    |index: usize| f32::from_bits(values.get(index).copied().unwrap_or(0));
+                format!(
+                    "{:.6} {:.6} {:.6}",
+                    component(0),
+                    component(1),
+                    component(2)
+                )
+            }
+            QcType::Pointer => "pointer".to_string(),
+            QcType::Integer => format!("{}", raw as i32),
+            QcType::Unknown(raw_type) => format!("bad type {raw_type}"),
+        }
+    }
+}
+
+fn read_header(text: &str) -> Result<(SaveGame, &str), SaveGameError> {
+    let mut rest = text;
+    let version = next_word(&mut rest)?;
+    if version.parse::<i32>().ok() != Some(SAVEGAME_VERSION) {
+        return Err(SaveGameError::UnsupportedVersion(version.to_string()));
+    }
+    let comment = next_word(&mut rest)?.to_string();
+    let mut spawn_parms = [0.0; NUM_SPAWN_PARMS];
+    for parm in &mut spawn_parms {
+        *parm = atof(next_word(&mut rest)?);
+    }
+    let skill = (atof(next_word(&mut rest)?) + 0.1) as i32;
+    let mapname = next_word(&mut rest)?.to_string();
+    let time = atof(next_word(&mut rest)?);
+    let mut lightstyles = Vec::with_capacity(MAX_LIGHTSTYLES);
+    for _ in 0..MAX_LIGHTSTYLES {
+        lightstyles.push(next_word(&mut rest)?.to_string());
+    }
+    let save = SaveGame {
+        comment,
+        spawn_parms,
+        skill,
+        mapname,
+        time,
+        lightstyles,
+    };
+    Ok((save, rest))
+}
+
+fn next_word<'a>(text: &mut &'a str) -> Result<&'a str, SaveGameError> {
+    let trimmed = text.trim_start();
+    let end = trimmed.find(char::is_whitespace).unwrap_or(trimmed.len());
+    if end == 0 {
+        return Err(SaveGameError::Truncated);
+    }
+    *text = &trimmed[end..];
+    Ok(&trimmed[..end])
+}
+
+fn atof(text: &str) -> f32 {
+    text.trim().parse().unwrap_or(0.0)
+}
+
+// ED_NewString turns \n back into a newline; any other escape becomes a backslash.
+fn unescape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\\' && chars.peek().is_some() {
+            out.push(if chars.next() == Some('n') {
+                '\n'
+            } else {
+                '\\'
+            });
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Definition, Function, PROG_VERSION, ProgsDat};
+
+    fn def(ty: QcType, offset: i16, name: &str, save_global: bool) -> Definition {
+        Definition {
+            ty,
+            offset,
+            name: name.to_string(),
+            save_global,
+        }
+    }
+
+    fn progs() -> ProgsDat {
+        ProgsDat {
+            version: PROG_VERSION,
+            crc: 5927,
+            statements: Vec::new(),
+            global_defs: vec![
+                def(QcType::Entity, 28, "self", true),
+                def(QcType::Float, 29, "total_monsters", true),
+                def(QcType::String, 30, "mapname", true),
+                def(QcType::Vector, 31, "v_forward", true),
+            ],
+            field_defs: vec![
+                def(QcType::String, 0, "classname", false),
+                def(QcType::Vector, 1, "origin", false),
+                def(QcType::Float, 1, "origin_x", false),
+                def(QcType::Function, 4, "think", false),
+                def(QcType::Entity, 5, "enemy", false),
+            ],
+            functions: vec![
+                Function {
+                    first_statement: 0,
+                    parm_start: 0,
+                    locals: 0,
+                    profile: 0,
+                    name: String::new(),
+                    file: String::new(),
+                    num_params: 0,
+                    param_sizes: [0; 8],
+                },
+                Function {
+                    first_statement: 0,
+                    parm_start: 0,
+                    locals: 0,
+                    profile: 0,
+                    name: "monster_think".to_string(),
+                    file: String::new(),
+                    num_params: 0,
+                    param_sizes: [0; 8],
+                },
+            ],
+            strings: vec![0],
+            globals: vec![0; 34],
+            entity_fields: 6,
+        }
+    }
+
+    fn header() -> SaveGame {
+        let mut spawn_parms = [0.0; NUM_SPAWN_PARMS];
+        spawn_parms[0] = 15.0;
+        SaveGame {
+            comment: "The Slipgate Complex".to_string(),
+            spawn_parms,
+            skill: 2,
+            mapname: "e1m1".to_string(),
+            time: 12.5,
+            lightstyles: vec!["m".to_string(), "mmnmmommommnonmmonqnmmo".to_string()],
+        }
+    }
+
+    #[test]
+    fn writes_netquake_blocks() {
+        let mut vm = Vm::new(progs());
+        vm.write_global_f32(29, 12.0).unwrap();
+        let mapname = vm.alloc_string("e1m1").unwrap();
+        vm.write_global_raw(30, mapname as u32).unwrap();
+        let ent = vm.alloc_edict().unwrap();
+        let classname = vm.alloc_string("monster_army").unwrap();
+        vm.write_edict_field_raw(ent, 0, &[classname as u32])
+            .unwrap();
+        vm.write_edict_field_raw(ent, 1, &[1.0f32.to_bits(), 0, 0])
+            .unwrap();
+        vm.write_edict_field_raw(ent, 4, &[1]).unwrap();
+        let freed = vm.alloc_edict().unwrap();
+        vm.free_edict(freed).unwrap();
+
+        let text = vm.write_savegame(&header());
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "5");
+        assert_eq!(lines[1], "The_Slipgate_Complex");
+        assert_eq!(lines[2], "15.000000");
+        assert_eq!(lines[18], "2");
+        assert_eq!(lines[19], "e1m1");
+        assert_eq!(lines[20], "12.500000");
+        assert_eq!(lines[22], "mmnmmommommnonmmonqnmmo");
+        assert_eq!(lines[23], "m");
+        assert_eq!(
+            &lines[85..],
+            &[
+                "{",
+                "\"self\" \"0\"",
+                "\"total_monsters\" \"12.000000\"",
+                "\"mapname\" \"e1m1\"",
+                "}",
+                "{",
+                "}",
+                "{",
+                "\"classname\" \"monster_army\"",
+                "\"origin\" \"1.000000 0.000000 0.000000\"",
+                "\"think\" \"monster_think\"",
+                "}",
+                "{",
+                "}",
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_back_what_it_writes() {
+        let mut vm = Vm::new(progs());
+        vm.write_global_f32(29, 12.0).unwrap();
+        let ent = vm.alloc_edict().unwrap();
+        let classname = vm.alloc_string("monster_army").unwrap();
+        vm.write_edict_field_raw(ent, 0, &[classname as u32])
+            .unwrap();
+        vm.write_edict_field_vec(ent, 1, qw_common::Vec3::new(1.0, -2.5, 3.0))
+            .unwrap();
+        vm.write_edict_field_raw(ent, 4, &[1]).unwrap();
+        vm.write_edict_field_f32(ent, 5, ent as f32).unwrap();
+        let freed = vm.alloc_edict().unwrap();
+        vm.free_edict(freed).unwrap();
+        let text = vm.write_savegame(&header());
+
+        let mut loaded = Vm::new(progs());
+        let save = loaded.read_savegame(&text).unwrap();
+        assert_eq!(save.comment, "The_Slipgate_Complex");
+        assert_eq!(save.spawn_parms, header().spawn_parms);
+        assert_eq!(save.skill, 2);
+        assert_eq!(save.mapname, "e1m1");
+        assert_eq!(save.time, 12.5);
+        assert_eq!(save.lightstyles.len(), MAX_LIGHTSTYLES);
+        assert_eq!(loaded.edict_count(), vm.edict_count());
+        assert!(loaded.edict_is_free(freed));
+        assert_eq!(loaded.read_global_f32(29).unwrap(), 12.0);
+        assert_eq!(
+            loaded.edict_field_strings(ent).unwrap(),
+            vm.edict_field_strings(ent).unwrap()
+        );
+        assert_eq!(loaded.write_savegame(&save), text);
+    }
+
+    #[test]
+    fn rejects_bad_saves_without_touching_the_vm() {
+        let mut vm = Vm::new(progs());
+        vm.write_global_f32(29, 3.0).unwrap();
+        let text = vm.write_savegame(&header());
+
+        assert_eq!(
+            vm.read_savegame("6\nnope\n"),
+            Err(SaveGameError::UnsupportedVersion("6".to_string()))
+        );
+        let broken = text.replace("{\n}\n", "{\n\"think\" \"missing_function\"\n}\n");
+        assert_eq!(
+            vm.read_savegame(&broken),
+            Err(SaveGameError::BadValue {
+                key: "think".to_string(),
+                value: "missing_function".to_string()
+            })
+        );
+        let crowded = text.clone() + &"{\n}\n".repeat(qw_common::MAX_EDICTS);
+        assert_eq!(
+            vm.read_savegame(&crowded),
+            Err(SaveGameError::TooManyEdicts)
+        );
+        assert_eq!(vm.read_global_f32(29).unwrap(), 3.0);
+        assert_eq!(unescape("a\\nb\\tc"), "a\nb\\c");
+    }
+}
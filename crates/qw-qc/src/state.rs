@@ -0,0 +1,283 @@
+// Binary snapshots of the mutable VM state: globals, every edict and the strings
+// allocated at runtime. A snapshot only restores into a VM running the same progs.
+
+use crate::strings::{StringBlock, StringHeap, StringKind};
+use crate::{Edict, Vm};
+use std::fmt;
+
+const STATE_MAGIC: &[u8; 4] = b"QVMS";
+pub const STATE_VERSION: u32 = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateError {
+    Busy,
+    Truncated,
+    BadMagic,
+    UnsupportedVersion(u32),
+    ProgsMismatch(&'static str),
+    TrailingData(usize),
+    BadStringHeap,
+    TooManyEdicts(usize),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::Busy => write!(f, "can't restore state while QuakeC is running"),
+            StateError::Truncated => write!(f, "vm state is truncated"),
+            StateError::BadMagic => write!(f, "not a vm state snapshot"),
+            StateError::UnsupportedVersion(version) => write!(
+                f,
+                "vm state has version {version}, expected {STATE_VERSION}"
+            ),
+            StateError::ProgsMismatch(what) => {
+                write!(
+                    f,
+                    "vm state was saved with different progs ({what} differs)"
+                )
+            }
+            StateError::TrailingData(len) => {
+                write!(f, "vm state has {len} unexpected trailing bytes")
+            }
+            StateError::BadStringHeap => write!(f, "vm state has a corrupt string heap"),
+            StateError::TooManyEdicts(count) => {
+                write!(f, "vm state has {count} edicts, more than the limit")
+            }
+        }
+    }
+}
+
+impl Vm {
+    // Layout, all little-endian: magic, version, progs crc, static string bytes,
+    // global count, entity field count, edict count, then the runtime strings
+    // (length prefixed), the string heap blocks (count, then offset, capacity
+    // and kind each) and the next temp slot, the globals, and per edict a free
+    // flag, its free time and its fields.
+    pub fn save_state(&self) -> Vec<u8> {
+        let dynamic = &self.progs.strings[self.static_strings..];
+        let field_count = self.edict_field_count();
+        let mut out = Vec::with_capacity(
+            32 + dynamic.len() + 4 * (self.globals.len() + self.edicts.len() * (field_count + 2)),
+        );
+        out.extend_from_slice(STATE_MAGIC);
+        for value in [
+            STATE_VERSION,
+            self.progs.crc as u32,
+            self.static_strings as u32,
+            self.globals.len() as u32,
+            field_count as u32,
+            self.edicts.len() as u32,
+            dynamic.len() as u32,
+        ] {
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+        out.extend_from_slice(dynamic);
+        let blocks: Vec<_> = self.string_heap.blocks().collect();
+        out.extend_from_slice(&(blocks.len() as u32).to_le_bytes());
+        for (offset, block) in blocks {
+            out.extend_from_slice(&(offset as u32).to_le_bytes());
+            out.extend_from_slice(&(block.capacity as u32).to_le_bytes());
+            out.push(block.kind as u8);
+        }
+        out.extend_from_slice(&(self.string_heap.next_temp() as u32).to_le_bytes());
+        for value in &self.globals {
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+        for edict in &self.edicts {
+            out.push(u8::from(edict.free));
+            out.extend_from_slice(&edict.freetime.to_le_bytes());
+            for value in &edict.fields {
+                out.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        out
+    }
+
+    // Nothing is changed unless the whole snapshot reads back cleanly.
+    pub fn restore_state(&mut self, bytes: &[u8]) -> Result<(), StateError> {
+        if !self.call_stack.is_empty() {
+            return Err(StateError::Busy);
+        }
+        let mut reader = Reader { bytes, pos: 0 };
+        if reader.take(4)? != STATE_MAGIC {
+            return Err(StateError::BadMagic);
+        }
+        let version = reader.u32()?;
+        if version != STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        let field_count = self.edict_field_count();
+        for (what, expected) in [
+            ("crc", self.progs.crc as u32),
+            ("string table", self.static_strings as u32),
+            ("global count", self.globals.len() as u32),
+            ("entity field count", field_count as u32),
+        ] {
+            if reader.u32()? != expected {
+                return Err(StateError::ProgsMismatch(what));
+            }
+        }
+        let edict_count = reader.u32()? as usize;
+        if edict_count > self.max_edicts() {
+            return Err(StateError::TooManyEdicts(edict_count));
+        }
+        let string_len = reader.u32()? as usize;
+        let dynamic = reader.take(string_len)?;
+        let string_heap = self.read_string_heap(&mut reader, string_len)?;
+
+        let mut globals = Vec::with_capacity(self.globals.len());
+        for _ in 0..self.globals.len() {
+            globals.push(reader.u32()?);
+        }
+        let mut edicts = Vec::with_capacity(edict_count);
+        for _ in 0..edict_count {
+            let mut edict = Edict::new(field_count);
+            edict.free = reader.take(1)?[0] != 0;
+            edict.freetime = f32::from_bits(reader.u32()?);
+            for value in &mut edict.fields {
+                *value = reader.u32()?;
+            }
+            edicts.push(edict);
+        }
+        let trailing = bytes.len() - reader.pos;
+        if trailing != 0 {
+            return Err(StateError::TrailingData(trailing));
+        }
+
+        self.progs.strings.truncate(self.static_strings);
+        self.progs.strings.extend_from_slice(dynamic);
+        self.string_heap = string_heap;
+        self.globals = globals;
+        self.edicts = edicts;
+        Ok(())
+    }
+
+    // Blocks have to be in order, inside the runtime strings and not overlap.
+    fn read_string_heap(
+        &self,
+        reader: &mut Reader,
+        string_len: usize,
+    ) -> Result<StringHeap, StateError> {
+        let count = reader.u32()? as usize;
+        let mut blocks = Vec::with_capacity(count.min(string_len));
+        let mut end = self.static_strings;
+        for _ in 0..count {
+            let offset = reader.u32()? as usize;
+            let capacity = reader.u32()? as usize;
+            let kind = match reader.take(1)?[0] {
+                0 => StringKind::Free,
+                1 => StringKind::Temp,
+                2 => StringKind::Engine,
+                3 => StringKind::Zone,
+                _ => return Err(StateError::BadStringHeap),
+            };
+            if offset < end
+                || !capacity.is_power_of_two()
+                || offset + capacity > self.static_strings + string_len
+            {
+                return Err(StateError::BadStringHeap);
+            }
+            end = offset + capacity;
+            blocks.push((offset, StringBlock { capacity, kind }));
+        }
+        let next_temp = reader.u32()? as usize;
+        Ok(StringHeap::from_blocks(blocks, next_temp))
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        let end = self.pos.checked_add(len).ok_or(StateError::Truncated)?;
+        let slice = self.bytes.get(self.pos..end).ok_or(StateError::Truncated)?;
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn u32(&mut self) -> Result<u32, StateError> {
+        let slice = self.take(4)?;
+        Ok(u32::from_le_bytes([slice[0], slice[1], slice[2], slice[3]]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Definition, PROG_VERSION, ProgsDat, QcType};
+
+    fn progs() -> ProgsDat {
+        ProgsDat {
+            version: PROG_VERSION,
+            crc: 5927,
+            statements: Vec::new(),
+            global_defs: vec![Definition {
+                ty: QcType::Float,
+                offset: 28,
+                name: "time".to_string(),
+                save_global: true,
+            }],
+            field_defs: Vec::new(),
+            functions: Vec::new(),
+            strings: b"\0time\0".to_vec(),
+            globals: vec![0; 30],
+            entity_fields: 2,
+        }
+    }
+
+    #[test]
+    fn snapshot_round_trips_globals_edicts_and_strings() {
+        let mut vm = Vm::new(progs());
+        vm.write_global_f32(28, 4.0).unwrap();
+        let name = vm.zone_string("player").unwrap();
+        vm.write_global_raw(29, name as u32).unwrap();
+        vm.alloc_temp_string("0").unwrap();
+        let ent = vm.alloc_edict().unwrap();
+        vm.write_edict_field_f32(ent, 1, 7.0).unwrap();
+        let freed = vm.alloc_edict().unwrap();
+        vm.free_edict(freed).unwrap();
+        let saved = vm.save_state();
+
+        let mut restored = Vm::new(progs());
+        restored.alloc_string("scratch").unwrap();
+        restored.restore_state(&saved).unwrap();
+        assert_eq!(restored.save_state(), saved);
+        assert_eq!(restored.read_global_f32(28).unwrap(), 4.0);
+        let name = restored.read_global_raw(29).unwrap() as i32;
+        assert_eq!(restored.progs().string_at(name).unwrap(), "player");
+        restored.unzone_string(name).unwrap();
+        assert_eq!(restored.read_edict_field_f32(ent, 1).unwrap(), 7.0);
+        assert!(restored.edict_is_free(freed));
+        assert_eq!(restored.edict_free_time(freed), Some(4.0));
+    }
+
+    #[test]
+    fn rejects_snapshots_from_other_progs() {
+        let saved = Vm::new(progs()).save_state();
+        let mut other = progs();
+        other.entity_fields = 3;
+        let mut vm = Vm::new(other);
+        assert_eq!(
+            vm.restore_state(&saved),
+            Err(StateError::ProgsMismatch("entity field count"))
+        );
+        assert_eq!(vm.restore_state(&saved[..10]), Err(StateError::Truncated));
+        assert_eq!(vm.restore_state(b"nope"), Err(StateError::BadMagic));
+    }
+
+    #[test]
+    fn rejects_more_edicts_than_the_limit() {
+        let mut saved = Vm::new(progs()).save_state();
+        // The edict count follows the magic and five header words.
+        saved[24..28].copy_from_slice(&u32::MAX.to_le_bytes());
+        let mut vm = Vm::new(progs());
+        assert_eq!(
+            vm.restore_state(&saved),
+            Err(StateError::TooManyEdicts(u32::MAX as usize))
+        );
+        assert_eq!(vm.edict_count(), 1);
+    }
+}
@@ -0,0 +1,268 @@
+// Strings made at runtime live after the progs' own string table. Temporary
+// strings (what ftos and vtos return) take the next slot of a fixed ring, so a
+// builtin call recycles the slot of the oldest one and QuakeC has to strzone
+// anything it keeps. Engine and zoned strings get power-of-two blocks that go
+// back on a free list once released, so the table stops growing once a server
+// settles down.
+
+use crate::{Vm, VmError, VmResult};
+use std::collections::BTreeMap;
+
+pub const TEMP_STRINGS: usize = 16;
+pub const TEMP_STRING_SIZE: usize = 256;
+const MIN_BLOCK: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StringKind {
+    Free,
+    Temp,
+    Engine,
+    Zone,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct StringBlock {
+    pub(crate) capacity: usize,
+    pub(crate) kind: StringKind,
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct StringHeap {
+    blocks: BTreeMap<usize, StringBlock>,
+    // Offsets of free blocks, indexed by log2 of their capacity.
+    free: Vec<Vec<usize>>,
+    temp_slots: Vec<usize>,
+    next_temp: usize,
+}
+
+impl StringHeap {
+    pub(crate) fn blocks(&self) -> impl Iterator<Item = (usize, StringBlock)> + '_ {
+        self.blocks.iter().map(|(&offset, &block)| (offset, block))
+    }
+
+    pub(crate) fn next_temp(&self) -> usize {
+        self.next_temp
+    }
+
+    // Rebuilds a heap from the blocks a snapshot listed, in offset order.
+    pub(crate) fn from_blocks(
+        blocks: impl IntoIterator<Item = (usize, StringBlock)>,
+        next_temp: usize,
+    ) -> Self {
+        let mut heap = Self::default();
+        for (offset, block) in blocks {
+            heap.blocks.insert(offset, block);
+            match block.kind {
+                StringKind::Free => heap.free_list(block.capacity).push(offset),
+                StringKind::Temp => heap.temp_slots.push(offset),
+                StringKind::Engine | StringKind::Zone => {}
+            }
+        }
+        heap.next_temp = next_temp % heap.temp_slots.len().max(1);
+        heap
+    }
+
+    pub(crate) fn kind_at(&self, offset: usize) -> Option<StringKind> {
+        self.blocks.get(&offset).map(|block| block.kind)
+    }
+
+    // `max_len` caps how far the table may grow when no free block fits.
+    pub(crate) fn alloc(
+        &mut self,
+        table: &mut Vec<u8>,
+        value: &[u8],
+        kind: StringKind,
+        max_len: usize,
+    ) -> Option<usize> {
+        let capacity = (value.len() + 1).max(MIN_BLOCK).next_power_of_two();
+        let offset = match self.free_list(capacity).pop() {
+            Some(offset) => offset,
+            None => {
+                if table.len() + capacity > max_len {
+                    return None;
+                }
+                let offset = table.len();
+                table.resize(offset + capacity, 0);
+                offset
+            }
+        };
+        table[offset..offset + value.len()].copy_from_slice(value);
+        table[offset + value.len()..offset + capacity].fill(0);
+        self.blocks.insert(offset, StringBlock { capacity, kind });
+        Some(offset)
+    }
+
+    fn release(&mut self, table: &mut [u8], offset: usize) {
+        let Some(block) = self.blocks.get_mut(&offset) else {
+            return;
+        };
+        block.kind = StringKind::Free;
+        let capacity = block.capacity;
+        // Stale references read back as an empty string.
+        table[offset] = 0;
+        self.free_list(capacity).push(offset);
+    }
+
+    fn free_list(&mut self, capacity: usize) -> &mut Vec<usize> {
+        let class = capacity.trailing_zeros() as usize;
+        if self.free.len() <= class {
+            self.free.resize_with(class + 1, Vec::new);
+        }
+        &mut self.free[class]
+    }
+}
+
+impl Vm {
+    // A string the engine keeps for good, such as a spawn value.
+    pub fn alloc_string(&mut self, value: &str) -> VmResult<i32> {
+        self.alloc_block(value.as_bytes(), StringKind::Engine)
+    }
+
+    // PF_strzone: a copy QuakeC owns until it calls strunzone.
+    pub fn zone_string(&mut self, value: &str) -> VmResult<i32> {
+        self.alloc_block(value.as_bytes(), StringKind::Zone)
+    }
+
+    // PF_strunzone: only strings from strzone may be released, and only once.
+    pub fn unzone_string(&mut self, offset: i32) -> VmResult<()> {
+        let start = usize::try_from(offset).map_err(|_| VmError::NotZoned(offset))?;
+        if self.string_heap.kind_at(start) != Some(StringKind::Zone) {
+            return Err(VmError::NotZoned(offset));
+        }
+        self.string_heap.release(&mut self.progs.strings, start);
+        Ok(())
+    }
+
+    // Longer values are cut to fit the slot, like the fixed buffers of the
+    // original engine.
+    pub fn alloc_temp_string(&mut self, value: &str) -> VmResult<i32> {
+        if self.string_heap.temp_slots.is_empty() {
+            for _ in 0..TEMP_STRINGS {
+                let offset = self.alloc_block(&[0; TEMP_STRING_SIZE - 1], StringKind::Temp)?;
+                self.string_heap.temp_slots.push(offset as usize);
+            }
+        }
+        let mut len = value.len().min(TEMP_STRING_SIZE - 1);
+        while !value.is_char_boundary(len) {
+            len -= 1;
+        }
+        let heap = &mut self.string_heap;
+        let offset = heap.temp_slots[heap.next_temp];
+        heap.next_temp = (heap.next_temp + 1) % heap.temp_slots.len();
+        let slot = &mut self.progs.strings[offset..offset + TEMP_STRING_SIZE];
+        slot[..len].copy_from_slice(&value.as_bytes()[..len]);
+        slot[len..].fill(0);
+        Ok(offset as i32)
+    }
+
+    pub fn string_kind(&self, offset: i32) -> Option<StringKind> {
+        let offset = usize::try_from(offset).ok()?;
+        self.string_heap.kind_at(offset)
+    }
+
+    // Bytes the string table has grown by at runtime, free blocks included.
+    pub fn dynamic_string_bytes(&self) -> usize {
+        self.progs.strings.len() - self.static_strings
+    }
+
+    fn alloc_block(&mut self, value: &[u8], kind: StringKind) -> VmResult<i32> {
+        let max_len = self
+            .static_strings
+            .saturating_add(self.limits.max_string_bytes)
+            .min(i32::MAX as usize);
+        let offset = self
+            .string_heap
+            .alloc(&mut self.progs.strings, value, kind, max_len)
+            .ok_or(VmError::StringLimit(self.limits.max_string_bytes))?;
+        Ok(offset as i32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PROG_VERSION, ProgsDat};
+
+    fn progs() -> ProgsDat {
+        ProgsDat {
+            version: PROG_VERSION,
+            crc: 0,
+            statements: Vec::new(),
+            global_defs: Vec::new(),
+            field_defs: Vec::new(),
+            functions: Vec::new(),
+            strings: b"\0hello\0".to_vec(),
+            globals: vec![0; 28],
+            entity_fields: 1,
+        }
+    }
+
+    fn text(vm: &Vm, offset: i32) -> String {
+        vm.progs().string_at(offset).unwrap()
+    }
+
+    #[test]
+    fn temp_strings_recycle_a_fixed_ring() {
+        let mut vm = Vm::new(progs());
+        let first = vm.alloc_temp_string("1").unwrap();
+        let size = vm.dynamic_string_bytes();
+        assert_eq!(size, TEMP_STRINGS * TEMP_STRING_SIZE);
+
+        let second = vm.alloc_temp_string("2").unwrap();
+        assert_eq!(text(&vm, first), "1");
+        assert_eq!(text(&vm, second), "2");
+        for n in 0..TEMP_STRINGS - 1 {
+            vm.alloc_temp_string(&n.to_string()).unwrap();
+        }
+        assert_eq!(text(&vm, first), (TEMP_STRINGS - 2).to_string());
+        assert_eq!(vm.string_kind(first), Some(StringKind::Temp));
+
+        let long = "é".repeat(TEMP_STRING_SIZE);
+        let cut = vm.alloc_temp_string(&long).unwrap();
+        assert_eq!(text(&vm, cut).len(), TEMP_STRING_SIZE - 2);
+        assert_eq!(vm.dynamic_string_bytes(), size);
+    }
+
+    #[test]
+    fn zoned_strings_are_validated_and_reused() {
+        let mut vm = Vm::new(progs());
+        let zoned = vm.zone_string("netname").unwrap();
+        let engine = vm.alloc_string("e1m1").unwrap();
+        let temp = vm.alloc_temp_string("3").unwrap();
+        assert_eq!(text(&vm, zoned), "netname");
+
+        assert_eq!(vm.unzone_string(1), Err(VmError::NotZoned(1)));
+        assert_eq!(vm.unzone_string(engine), Err(VmError::NotZoned(engine)));
+        assert_eq!(vm.unzone_string(temp), Err(VmError::NotZoned(temp)));
+        assert_eq!(
+            vm.unzone_string(zoned + 1),
+            Err(VmError::NotZoned(zoned + 1))
+        );
+        vm.unzone_string(zoned).unwrap();
+        assert_eq!(text(&vm, zoned), "");
+        assert_eq!(vm.unzone_string(zoned), Err(VmError::NotZoned(zoned)));
+
+        assert_eq!(vm.zone_string("other").unwrap(), zoned);
+        assert_eq!(text(&vm, engine), "e1m1");
+    }
+
+    // A day of a 20 fps server that formats a score every frame and swaps a
+    // zoned message now and then.
+    #[test]
+    fn memory_stays_bounded_over_a_long_run() {
+        let mut vm = Vm::new(progs());
+        let mut motd = vm.zone_string("welcome").unwrap();
+        let mut settled = 0;
+        for frame in 0..24 * 60 * 60 * 20 {
+            vm.alloc_temp_string(&format!("{frame}")).unwrap();
+            if frame % 100 == 0 {
+                vm.unzone_string(motd).unwrap();
+                motd = vm.zone_string(&format!("frame {frame}")).unwrap();
+            }
+            if frame == 1000 {
+                settled = vm.dynamic_string_bytes();
+            }
+        }
+        assert_eq!(vm.dynamic_string_bytes(), settled);
+    }
+}
@@ -0,0 +1,342 @@
+// Structural checks run on every loaded progs so the VM never has to trust an
+// offset, jump or function header coming from a downloaded file.
+
+use crate::opcodes::{Operand, opcode_info};
+use crate::{Definition, MAX_PARMS, ProgsDat, ProgsError, QcType};
+
+// Fields are addressed through 16 bits of an OP_ADDRESS pointer.
+pub const MAX_ENTITY_FIELDS: i32 = 0x10000;
+// Keeps the builtin table a VM allocates for a progs to a sane size.
+pub const MAX_BUILTIN_NUMBER: u32 = 0xFFFF;
+
+impl ProgsDat {
+    pub fn verify(&self) -> Result<(), ProgsError> {
+        if !(0..=MAX_ENTITY_FIELDS).contains(&self.entity_fields) {
+            return Err(ProgsError::BadEntityFields(self.entity_fields));
+        }
+        let globals = self.globals.len();
+        let fields = self.entity_fields as usize;
+        for def in &self.global_defs {
+            check_offset(def, globals)?;
+        }
+        for def in &self.field_defs {
+            check_offset(def, fields)?;
+        }
+
+        for (index, statement) in self.statements.iter().enumerate() {
+            let info = opcode_info(statement.op).ok_or(ProgsError::BadOpcode {
+                statement: index,
+                op: statement.op,
+            })?;
+            for (operand, raw) in [
+                (info.a, statement.a),
+                (info.b, statement.b),
+                (info.c, statement.c),
+            ] {
+                match operand {
+                    Operand::Unused => {}
+                    Operand::Global(ty) => {
+                        if raw < 0 || raw as usize + value_size(ty) > globals {
+                            return Err(ProgsError::BadOperand {
+                                statement: index,
+                                offset: raw,
+                            });
+                        }
+                    }
+                    Operand::Jump => {
+                        let target = index as i64 + i64::from(raw);
+                        if target < 0 || target as usize >= self.statements.len() {
+                            return Err(ProgsError::BadJump {
+                                statement: index,
+                                target,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        for func in &self.functions {
+            let bad = |reason| ProgsError::BadFunction {
+                name: func.name.clone(),
+                reason,
+            };
+            if func.first_statement >= 0 {
+                if func.first_statement as usize >= self.statements.len() {
+                    return Err(bad("first statement is out of range"));
+                }
+            } else if func.first_statement.unsigned_abs() > MAX_BUILTIN_NUMBER {
+                return Err(bad("builtin number is out of range"));
+            }
+            if func.parm_start < 0
+                || func.locals < 0
+                || func.parm_start as usize + func.locals as usize > globals
+            {
+                return Err(bad("locals are out of range"));
+            }
+            // qcc stores -1 - count for functions taking `...`.
+            let max_params = MAX_PARMS as i32;
+            if !(-1 - max_params..=max_params).contains(&func.num_params) {
+                return Err(bad("too many parameters"));
+            }
+            if func.param_sizes.iter().any(|&size| size > 3) {
+                return Err(bad("bad parameter size"));
+            }
+            let param_size: usize = func.param_sizes[..func.num_params.max(0) as usize]
+                .iter()
+                .map(|&size| size as usize)
+                .sum();
+            if func.first_statement >= 0 && param_size > func.locals as usize {
+                return Err(bad("parameters don't fit in its locals"));
+            }
+        }
+
+        // Initialized globals holding references must point at something real.
+        for def in &self.global_defs {
+            // A void def can sit just past the end, so look it up rather than
+            // trust check_offset.
+            let Some(&value) = self.globals.get(def.offset as usize) else {
+                return Err(ProgsError::InvalidDefOffset {
+                    name: def.name.clone(),
+                    offset: def.offset,
+                });
+            };
+            let valid = match def.ty {
+                QcType::String => (value as usize) < self.strings.len().max(1),
+                QcType::Function => (value as usize) < self.functions.len().max(1),
+                QcType::Field => (value as usize) < fields.max(1),
+                _ => true,
+            };
+            if !valid {
+                return Err(ProgsError::BadGlobalValue {
+                    name: def.name.clone(),
+                    value,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+fn value_size(ty: QcType) -> usize {
+    if ty == QcType::Vector { 3 } else { 1 }
+}
+
+fn check_offset(def: &Definition, limit: usize) -> Result<(), ProgsError> {
+    // qcc starts both def tables with a void placeholder at offset 0.
+    let size = if def.ty == QcType::Void {
+        0
+    } else {
+        value_size(def.ty)
+    };
+    if def.offset < 0 || def.offset as usize + size > limit {
+        return Err(ProgsError::InvalidDefOffset {
+            name: def.name.clone(),
+            offset: def.offset,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opcodes::*;
+    use crate::{Function, PROG_VERSION, Statement, Vm, VmError, VmLimits};
+
+    fn progs() -> ProgsDat {
+        let statement = |op, a, b, c| Statement { op, a, b, c };
+        let function = |first_statement, name: &str, num_params| Function {
+            first_statement,
+            parm_start: 40,
+            locals: 4,
+            profile: 0,
+            name: name.to_string(),
+            file: String::new(),
+            num_params,
+            param_sizes: [3, 1, 0, 0, 0, 0, 0, 0],
+        };
+        let mut globals = vec![0u32; 44];
+        globals[31] = 1;
+        globals[32] = 2;
+        globals[33] = 1;
+        ProgsDat {
+            version: PROG_VERSION,
+            crc: 0,
+            statements: vec![
+                statement(OP_DONE, 0, 0, 0),
+                statement(OP_STORE_F, 41, 43, 0),
+                statement(OP_ADD_V, 40, 40, 34),
+                statement(OP_ADDRESS, 28, 33, 37),
+                statement(OP_STOREP_V, 34, 37, 0),
+                statement(OP_LOAD_V, 28, 33, 34),
+                statement(OP_CALL1, 32, 0, 0),
+                statement(OP_IF, 43, -6, 0),
+                statement(OP_RETURN, 34, 0, 0),
+            ],
+            global_defs: vec![
+                Definition {
+                    ty: QcType::Entity,
+                    offset: 28,
+                    name: "self".to_string(),
+                    save_global: false,
+                },
+                Definition {
+                    ty: QcType::Function,
+                    offset: 32,
+                    name: "spawn".to_string(),
+                    save_global: false,
+                },
+                Definition {
+                    ty: QcType::Field,
+                    offset: 33,
+                    name: "origin".to_string(),
+                    save_global: false,
+                },
+            ],
+            field_defs: vec![Definition {
+                ty: QcType::Vector,
+                offset: 1,
+                name: "origin".to_string(),
+                save_global: false,
+            }],
+            functions: vec![
+                function(0, "", 0),
+                function(-1, "spawn", 0),
+                function(2, "think", 2),
+            ],
+            strings: b"\0self\0spawn\0origin\0think\0".to_vec(),
+            globals,
+            entity_fields: 4,
+        }
+    }
+
+    #[test]
+    fn rejects_out_of_range_references() {
+        assert_eq!(progs().verify(), Ok(()));
+
+        let mut bad = progs();
+        bad.statements[2].c = 42;
+        assert_eq!(
+            bad.verify(),
+            Err(ProgsError::BadOperand {
+                statement: 2,
+                offset: 42
+            })
+        );
+
+        let mut bad = progs();
+        bad.statements[7].b = -8;
+        assert_eq!(
+            bad.verify(),
+            Err(ProgsError::BadJump {
+                statement: 7,
+                target: -1
+            })
+        );
+
+        let mut bad = progs();
+        bad.statements[0].op = 500;
+        assert_eq!(
+            bad.verify(),
+            Err(ProgsError::BadOpcode {
+                statement: 0,
+                op: 500
+            })
+        );
+
+        let mut bad = progs();
+        bad.functions[2].first_statement = 9;
+        assert!(matches!(bad.verify(), Err(ProgsError::BadFunction { .. })));
+
+        let mut bad = progs();
+        bad.globals[32] = 3;
+        assert!(matches!(
+            bad.verify(),
+            Err(ProgsError::BadGlobalValue { value: 3, .. })
+        ));
+
+        let mut bad = progs();
+        bad.field_defs[0].offset = 2;
+        assert!(matches!(
+            bad.verify(),
+            Err(ProgsError::InvalidDefOffset { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_a_void_def_past_the_last_global() {
+        let progs = ProgsDat {
+            version: PROG_VERSION,
+            crc: 0,
+            statements: Vec::new(),
+            global_defs: vec![Definition {
+                ty: QcType::Void,
+                offset: 1,
+                name: "end".to_string(),
+                save_global: false,
+            }],
+            field_defs: Vec::new(),
+            functions: Vec::new(),
+            strings: b"\0end\0".to_vec(),
+            globals: vec![0],
+            entity_fields: 0,
+        };
+        assert!(matches!(
+            ProgsDat::from_bytes(&progs.to_bytes()),
+            Err(ProgsError::InvalidDefOffset { offset: 1, .. })
+        ));
+    }
+
+    fn spawn(vm: &mut Vm) -> Result<(), VmError> {
+        let entity = vm.alloc_edict()?;
+        vm.alloc_string("spawned")?;
+        vm.set_return_raw(entity as u32)
+    }
+
+    // A poor man's fuzzer for the targets under fuzz/: mutated progs must either
+    // fail to load or run to an error without panicking.
+    #[test]
+    fn mutated_progs_never_panic() {
+        let bytes = progs().to_bytes();
+        let mut seed = 0x2545_f491_u32;
+        let mut next = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed
+        };
+        let mut loaded = 0;
+        for _ in 0..5000 {
+            let mut mutated = bytes.clone();
+            for _ in 0..1 + next() % 4 {
+                let index = next() as usize % mutated.len();
+                mutated[index] = match next() % 3 {
+                    0 => next() as u8,
+                    1 => mutated[index] ^ (1 << (next() % 8)),
+                    _ => 0xff,
+                };
+            }
+            if next() % 8 == 0 {
+                mutated.truncate(next() as usize % mutated.len());
+            }
+            let Ok(progs) = ProgsDat::from_bytes(&mutated) else {
+                continue;
+            };
+            loaded += 1;
+            let mut vm = Vm::new(progs);
+            vm.set_limits(VmLimits {
+                max_edicts: 16,
+                max_string_bytes: 256,
+                max_call_depth: 8,
+                max_frame_steps: 2000,
+            });
+            vm.register_builtin(1, spawn);
+            for function in 0..vm.progs().functions.len() {
+                vm.start_frame();
+                let _ = vm.call_function(function, 500);
+            }
+        }
+        assert!(loaded > 0);
+    }
+}
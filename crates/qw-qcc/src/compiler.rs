@@ -0,0 +1,1462 @@
+// Single pass QuakeC parser and code generator, following qcc's pr_comp.c.
+
+use crate::CompileError;
+use crate::lexer::{Lexer, Token};
+use crate::progdefs::progdefs_crc;
+use qw_qc::opcodes::*;
+use qw_qc::{
+    Definition, Function, MAX_PARMS, OFS_PARM0, OFS_RETURN, PROG_VERSION, ProgsDat, QcType,
+    RESERVED_OFS, Statement,
+};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+const TOP_PRIORITY: u8 = 6;
+const NOT_PRIORITY: u8 = 4;
+const ASSIGN_PRIORITY: u8 = 5;
+const MAX_GLOBALS: usize = i16::MAX as usize;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Void,
+    String,
+    Float,
+    Vector,
+    Entity,
+    Field(Box<Type>),
+    Function(Rc<FunctionType>),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct FunctionType {
+    ret: Type,
+    params: Vec<Type>,
+    varargs: bool,
+}
+
+impl Type {
+    fn basic(&self) -> QcType {
+        match self {
+            Type::Void => QcType::Void,
+            Type::String => QcType::String,
+            Type::Float => QcType::Float,
+            Type::Vector => QcType::Vector,
+            Type::Entity => QcType::Entity,
+            Type::Field(_) => QcType::Field,
+            Type::Function(_) => QcType::Function,
+        }
+    }
+
+    fn size(&self) -> usize {
+        if *self == Type::Vector { 3 } else { 1 }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Type::Field(inner) => format!(".{}", inner.describe()),
+            Type::Function(func) => {
+                let mut params: Vec<String> = func.params.iter().map(Type::describe).collect();
+                if func.varargs {
+                    params.push("...".to_string());
+                }
+                format!("{}({})", func.ret.describe(), params.join(", "))
+            }
+            other => other.basic().name().to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Def {
+    pub name: String,
+    pub ty: Type,
+    offset: i16,
+    local: bool,
+    initialized: bool,
+    file: Rc<str>,
+    line: usize,
+}
+
+pub struct CompiledProgs {
+    pub progs: ProgsDat,
+    pub line_numbers: Vec<i32>,
+}
+
+impl CompiledProgs {
+    // fteqcc's .lno layout: "LNOF", version 1, the def/global/statement counts
+    // it was built against, then one source line per statement.
+    pub fn lno_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(24 + self.line_numbers.len() * 4);
+        out.extend_from_slice(b"LNOF");
+        for value in [
+            1,
+            self.progs.global_defs.len() as i32,
+            self.progs.globals.len() as i32,
+            self.progs.field_defs.len() as i32,
+            self.progs.statements.len() as i32,
+        ] {
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+        for line in &self.line_numbers {
+            out.extend_from_slice(&line.to_le_bytes());
+        }
+        out
+    }
+}
+
+pub struct Compiler {
+    defs: Vec<Def>,
+    global_names: HashMap<String, usize>,
+    globals: Vec<u32>,
+    statements: Vec<Statement>,
+    line_numbers: Vec<i32>,
+    functions: Vec<Function>,
+    strings: Vec<u8>,
+    string_offsets: HashMap<String, i32>,
+    immediates: HashMap<(QcType, [u32; 3]), i16>,
+    field_size: usize,
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Compiler {
+    pub fn new() -> Self {
+        Self {
+            defs: Vec::new(),
+            global_names: HashMap::new(),
+            globals: vec![0; RESERVED_OFS],
+            statements: vec![Statement {
+                op: OP_DONE,
+                a: 0,
+                b: 0,
+                c: 0,
+            }],
+            line_numbers: vec![0],
+            functions: vec![Function {
+                first_statement: 0,
+                parm_start: 0,
+                locals: 0,
+                profile: 0,
+                name: String::new(),
+                file: String::new(),
+                num_params: 0,
+                param_sizes: [0; 8],
+            }],
+            strings: vec![0],
+            string_offsets: HashMap::new(),
+            immediates: HashMap::new(),
+            field_size: 0,
+        }
+    }
+
+    pub fn compile_file(&mut self, file: &str, source: &str) -> Result<(), CompileError> {
+        let mut parser = Parser::new(self, file, source)?;
+        while parser.token != Token::Eof {
+            parser.global_defs()?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<CompiledProgs, CompileError> {
+        for def in &self.defs {
+            if !def.local && matches!(def.ty, Type::Function(_)) && !def.initialized {
+                return Err(CompileError::new(
+                    &def.file,
+                    def.line,
+                    format!("function {} was not defined", def.name),
+                ));
+            }
+        }
+
+        let crc = progdefs_crc(&self.defs);
+        let mut global_defs = vec![empty_definition()];
+        let mut field_defs = vec![empty_definition()];
+        for def in &self.defs {
+            let save_global = !def.local
+                && !def.initialized
+                && !matches!(def.ty, Type::Function(_) | Type::Field(_));
+            global_defs.push(Definition {
+                ty: def.ty.basic(),
+                offset: def.offset,
+                name: def.name.clone(),
+                save_global,
+            });
+            if let (Type::Field(inner), false) = (&def.ty, def.local) {
+                field_defs.push(Definition {
+                    ty: inner.basic(),
+                    offset: self.globals[def.offset as usize] as i16,
+                    name: def.name.clone(),
+                    save_global: false,
+                });
+            }
+        }
+
+        let progs = ProgsDat {
+            version: PROG_VERSION,
+            crc: i32::from(crc),
+            statements: self.statements,
+            global_defs,
+            field_defs,
+            functions: self.functions,
+            strings: self.strings,
+            globals: self.globals,
+            entity_fields: self.field_size as i32,
+        };
+        Ok(CompiledProgs {
+            progs,
+            line_numbers: self.line_numbers,
+        })
+    }
+
+    fn string_offset(&mut self, value: &str) -> i32 {
+        if value.is_empty() {
+            return 0;
+        }
+        if let Some(&offset) = self.string_offsets.get(value) {
+            return offset;
+        }
+        let offset = self.strings.len() as i32;
+        self.strings.extend_from_slice(value.as_bytes());
+        self.strings.push(0);
+        self.string_offsets.insert(value.to_string(), offset);
+        offset
+    }
+}
+
+fn empty_definition() -> Definition {
+    Definition {
+        ty: QcType::Void,
+        offset: 0,
+        name: String::new(),
+        save_global: false,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ValueKind {
+    Variable,
+    Constant,
+    Temp,
+}
+
+#[derive(Debug, Clone)]
+struct Value {
+    offset: i16,
+    ty: Type,
+    kind: ValueKind,
+}
+
+enum Expr {
+    Value(Value),
+    Field { entity: i16, field: i16, ty: Type },
+}
+
+struct FunctionScope {
+    locals: HashMap<String, usize>,
+    live_temps: Vec<(i16, usize)>,
+    free_temps: Vec<(i16, usize)>,
+}
+
+struct BinaryOp {
+    token: &'static str,
+    priority: u8,
+    op: u16,
+    a: QcType,
+    b: QcType,
+    c: QcType,
+}
+
+const fn binary(token: &'static str, priority: u8, op: u16, types: [QcType; 3]) -> BinaryOp {
+    BinaryOp {
+        token,
+        priority,
+        op,
+        a: types[0],
+        b: types[1],
+        c: types[2],
+    }
+}
+
+const F: QcType = QcType::Float;
+const V: QcType = QcType::Vector;
+const S: QcType = QcType::String;
+const E: QcType = QcType::Entity;
+const FNC: QcType = QcType::Function;
+
+const BINARY_OPS: [BinaryOp; 29] = [
+    binary("*", 2, OP_MUL_F, [F, F, F]),
+    binary("*", 2, OP_MUL_V, [V, V, F]),
+    binary("*", 2, OP_MUL_FV, [F, V, V]),
+    binary("*", 2, OP_MUL_VF, [V, F, V]),
+    binary("/", 2, OP_DIV_F, [F, F, F]),
+    binary("&", 2, OP_BITAND, [F, F, F]),
+    binary("|", 2, OP_BITOR, [F, F, F]),
+    binary("+", 3, OP_ADD_F, [F, F, F]),
+    binary("+", 3, OP_ADD_V, [V, V, V]),
+    binary("-", 3, OP_SUB_F, [F, F, F]),
+    binary("-", 3, OP_SUB_V, [V, V, V]),
+    binary("==", 4, OP_EQ_F, [F, F, F]),
+    binary("==", 4, OP_EQ_V, [V, V, F]),
+    binary("==", 4, OP_EQ_S, [S, S, F]),
+    binary("==", 4, OP_EQ_E, [E, E, F]),
+    binary("==", 4, OP_EQ_FNC, [FNC, FNC, F]),
+    binary("!=", 4, OP_NE_F, [F, F, F]),
+    binary("!=", 4, OP_NE_V, [V, V, F]),
+    binary("!=", 4, OP_NE_S, [S, S, F]),
+    binary("!=", 4, OP_NE_E, [E, E, F]),
+    binary("!=", 4, OP_NE_FNC, [FNC, FNC, F]),
+    binary("<=", 4, OP_LE, [F, F, F]),
+    binary(">=", 4, OP_GE, [F, F, F]),
+    binary("<", 4, OP_LT, [F, F, F]),
+    binary(">", 4, OP_GT, [F, F, F]),
+    binary("&&", 6, OP_AND, [F, F, F]),
+    binary("||", 6, OP_OR, [F, F, F]),
+    // Truth tests on entities, strings and functions read the raw slot.
+    binary("&&", 6, OP_AND, [QcType::Void, QcType::Void, F]),
+    binary("||", 6, OP_OR, [QcType::Void, QcType::Void, F]),
+];
+
+struct Parser<'a> {
+    compiler: &'a mut Compiler,
+    lexer: Lexer<'a>,
+    file: Rc<str>,
+    token: Token,
+    // The line of the last token consumed, which errors and statements
+    // report, and the line of the lookahead in `token`.
+    line: usize,
+    token_line: usize,
+    param_names: Vec<String>,
+    scope: Option<FunctionScope>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(
+        compiler: &'a mut Compiler,
+        file: &'a str,
+        source: &'a str,
+    ) -> Result<Self, CompileError> {
+        let mut lexer = Lexer::new(file, source);
+        let token = lexer.next_token()?;
+        let line = lexer.line();
+        Ok(Self {
+            compiler,
+            lexer,
+            file: Rc::from(file),
+            token,
+            line,
+            token_line: line,
+            param_names: Vec::new(),
+            scope: None,
+        })
+    }
+
+    fn error(&self, message: impl Into<String>) -> CompileError {
+        CompileError::new(&self.file, self.line, message.into())
+    }
+
+    fn advance(&mut self) -> Result<Token, CompileError> {
+        let next = self.lexer.next_token()?;
+        self.line = std::mem::replace(&mut self.token_line, self.lexer.line());
+        Ok(std::mem::replace(&mut self.token, next))
+    }
+
+    fn check(&mut self, punct: &str) -> Result<bool, CompileError> {
+        if matches!(self.token, Token::Punct(p) if p == punct) {
+            self.advance()?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    fn check_name(&mut self, keyword: &str) -> Result<bool, CompileError> {
+        if matches!(&self.token, Token::Name(name) if name == keyword) {
+            self.advance()?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<(), CompileError> {
+        if !self.check(punct)? {
+            return Err(self.error(format!(
+                "expected \"{punct}\", found {}",
+                self.token.describe()
+            )));
+        }
+        Ok(())
+    }
+
+    fn expect_name(&mut self) -> Result<String, CompileError> {
+        match self.advance()? {
+            Token::Name(name) => Ok(name),
+            other => Err(self.error(format!("expected a name, found {}", other.describe()))),
+        }
+    }
+
+    fn at_type(&self) -> bool {
+        match &self.token {
+            Token::Punct(".") => true,
+            Token::Name(name) => {
+                matches!(
+                    name.as_str(),
+                    "void" | "float" | "vector" | "string" | "entity"
+                )
+            }
+            _ => false,
+        }
+    }
+
+    fn parse_type(&mut self) -> Result<Type, CompileError> {
+        if self.check(".")? {
+            let inner = self.parse_type()?;
+            return Ok(Type::Field(Box::new(inner)));
+        }
+        let name = self.expect_name()?;
+        let basic = match name.as_str() {
+            "void" => Type::Void,
+            "float" => Type::Float,
+            "vector" => Type::Vector,
+            "string" => Type::String,
+            "entity" => Type::Entity,
+            _ => return Err(self.error(format!("\"{name}\" is not a type"))),
+        };
+        if !self.check("(")? {
+            return Ok(basic);
+        }
+
+        let mut params = Vec::new();
+        let mut names = Vec::new();
+        let mut varargs = false;
+        if !self.check(")")? {
+            loop {
+                if self.check("...")? {
+                    varargs = true;
+                    self.expect(")")?;
+                    break;
+                }
+                let ty = self.parse_type()?;
+                let name = match &self.token {
+                    Token::Name(_) => self.expect_name()?,
+                    _ => String::new(),
+                };
+                if params.len() == MAX_PARMS {
+                    return Err(self.error("too many parameters"));
+                }
+                params.push(ty);
+                names.push(name);
+                if self.check(")")? {
+                    break;
+                }
+                self.expect(",")?;
+            }
+        }
+        self.param_names = names;
+        Ok(Type::Function(Rc::new(FunctionType {
+            ret: basic,
+            params,
+            varargs,
+        })))
+    }
+
+    fn global_defs(&mut self) -> Result<(), CompileError> {
+        let ty = self.parse_type()?;
+        let param_names = self.param_names.clone();
+        loop {
+            let name = self.expect_name()?;
+            let def = self.get_global(&name, &ty)?;
+            let mut body = false;
+            if self.check("=")? {
+                if self.compiler.defs[def].initialized {
+                    return Err(self.error(format!("{name} redeclared")));
+                }
+                if let Type::Function(func) = &ty {
+                    body = self.function_initializer(def, func, &param_names)?;
+                } else {
+                    let value = self.immediate_value(&ty)?;
+                    let offset = self.compiler.defs[def].offset as usize;
+                    let size = ty.size();
+                    self.compiler.globals[offset..offset + size].copy_from_slice(&value[..size]);
+                }
+                self.compiler.defs[def].initialized = true;
+            }
+            if self.check(",")? {
+                continue;
+            }
+            if body && !matches!(self.token, Token::Punct(";")) {
+                return Ok(());
+            }
+            return self.expect(";");
+        }
+    }
+
+    fn immediate_value(&mut self, ty: &Type) -> Result<[u32; 3], CompileError> {
+        let negate = self.check("-")?;
+        let (found, mut value) = match self.advance()? {
+            Token::Float(value) => (Type::Float, [value.to_bits(), 0, 0]),
+            Token::Vector(v) => (Type::Vector, v.map(f32::to_bits)),
+            Token::String(value) if !negate => (
+                Type::String,
+                [self.compiler.string_offset(&value) as u32, 0, 0],
+            ),
+            other => {
+                return Err(self.error(format!("expected a constant, found {}", other.describe())));
+            }
+        };
+        if found.basic() != ty.basic() {
+            return Err(self.error(format!(
+                "type mismatch: {} initialized with {}",
+                ty.describe(),
+                found.describe()
+            )));
+        }
+        if negate {
+            for bits in &mut value[..found.size()] {
+                *bits = (-f32::from_bits(*bits)).to_bits();
+            }
+        }
+        Ok(value)
+    }
+
+    fn function_initializer(
+        &mut self,
+        def: usize,
+        func: &Rc<FunctionType>,
+        param_names: &[String],
+    ) -> Result<bool, CompileError> {
+        let name = self.compiler.defs[def].name.clone();
+        let index = self.compiler.functions.len();
+        let mut param_sizes = [0u8; 8];
+        for (size, ty) in param_sizes.iter_mut().zip(&func.params) {
+            *size = ty.size() as u8;
+        }
+        self.compiler.functions.push(Function {
+            first_statement: 0,
+            parm_start: 0,
+            locals: 0,
+            profile: 0,
+            name,
+            file: self.file.to_string(),
+            num_params: func.params.len() as i32,
+            param_sizes,
+        });
+        let offset = self.compiler.defs[def].offset as usize;
+        self.compiler.globals[offset] = index as u32;
+
+        if self.check("#")? {
+            let number = match self.advance()? {
+                Token::Float(value) if value >= 1.0 && value.fract() == 0.0 => value as i32,
+                other => {
+                    return Err(self.error(format!("bad builtin number {}", other.describe())));
+                }
+            };
+            self.compiler.functions[index].first_statement = -number;
+            return Ok(false);
+        }
+
+        let state = if self.check("[")? {
+            let frame = self.expression(TOP_PRIORITY)?;
+            let frame = self.rvalue(frame)?;
+            if frame.ty != Type::Float {
+                return Err(self.error("state frame must be a float"));
+            }
+            self.expect(",")?;
+            let think = self.expect_name()?;
+            let think_type = Type::Function(Rc::new(FunctionType {
+                ret: Type::Void,
+                params: Vec::new(),
+                varargs: false,
+            }));
+            // qcc declares an unknown think function as void() on the spot.
+            let think = match self.lookup(&think) {
+                Some(def) => def,
+                None => self.get_global(&think, &think_type)?,
+            };
+            if !matches!(self.compiler.defs[think].ty, Type::Function(_)) {
+                return Err(self.error("state think must be a function"));
+            }
+            self.expect("]")?;
+            Some((frame.offset, self.compiler.defs[think].offset))
+        } else {
+            None
+        };
+
+        let parm_start = self.compiler.globals.len();
+        self.scope = Some(FunctionScope {
+            locals: HashMap::new(),
+            live_temps: Vec::new(),
+            free_temps: Vec::new(),
+        });
+        for (position, ty) in func.params.iter().enumerate() {
+            let name = param_names.get(position).cloned().unwrap_or_default();
+            if name.is_empty() {
+                return Err(self.error(format!("parameter {} has no name", position + 1)));
+            }
+            self.new_def(&name, ty, true)?;
+        }
+
+        self.compiler.functions[index].first_statement = self.compiler.statements.len() as i32;
+        if let Some((frame, think)) = state {
+            self.emit(OP_STATE, frame, think, 0);
+        }
+        self.expect("{")?;
+        while !self.check("}")? {
+            if self.token == Token::Eof {
+                return Err(self.error("unexpected end of file in function body"));
+            }
+            self.statement(&func.ret)?;
+        }
+        self.emit(OP_DONE, 0, 0, 0);
+
+        self.scope = None;
+        let function = &mut self.compiler.functions[index];
+        function.parm_start = parm_start as i32;
+        function.locals = (self.compiler.globals.len() - parm_start) as i32;
+        Ok(true)
+    }
+
+    fn statement(&mut self, return_type: &Type) -> Result<(), CompileError> {
+        if self.check("{")? {
+            while !self.check("}")? {
+                if self.token == Token::Eof {
+                    return Err(self.error("unexpected end of file in block"));
+                }
+                self.statement(return_type)?;
+            }
+            return Ok(());
+        }
+
+        if self.check_name("return")? {
+            if self.check(";")? {
+                self.emit(OP_RETURN, 0, 0, 0);
+                return Ok(());
+            }
+            let expr = self.expression(TOP_PRIORITY)?;
+            let value = self.rvalue(expr)?;
+            if value.ty.basic() != return_type.basic() {
+                return Err(self.error(format!(
+                    "type mismatch for return: {} returns {}",
+                    return_type.describe(),
+                    value.ty.describe()
+                )));
+            }
+            self.emit(OP_RETURN, value.offset, 0, 0);
+            self.release_temps();
+            return self.expect(";");
+        }
+
+        if self.check_name("while")? {
+            self.expect("(")?;
+            let start = self.compiler.statements.len();
+            let cond = self.condition()?;
+            self.expect(")")?;
+            let exit = self.emit_branch(cond, false);
+            self.release_temps();
+            self.statement(return_type)?;
+            let back = start as i32 - self.compiler.statements.len() as i32;
+            self.emit(OP_GOTO, back as i16, 0, 0);
+            self.patch_branch(exit);
+            return Ok(());
+        }
+
+        if self.check_name("do")? {
+            let start = self.compiler.statements.len();
+            self.statement(return_type)?;
+            if !self.check_name("while")? {
+                return Err(self.error("expected \"while\" after do body"));
+            }
+            self.expect("(")?;
+            let cond = self.condition()?;
+            self.expect(")")?;
+            let branch = self.emit_branch(cond, true);
+            let statement = &mut self.compiler.statements[branch];
+            statement.b = (start as i32 - branch as i32) as i16;
+            self.release_temps();
+            return self.expect(";");
+        }
+
+        if self.check_name("if")? {
+            self.expect("(")?;
+            let cond = self.condition()?;
+            self.expect(")")?;
+            let skip = self.emit_branch(cond, false);
+            self.release_temps();
+            self.statement(return_type)?;
+            if self.check_name("else")? {
+                let end = self.emit(OP_GOTO, 0, 0, 0);
+                self.patch_branch(skip);
+                self.statement(return_type)?;
+                self.patch_branch(end);
+            } else {
+                self.patch_branch(skip);
+            }
+            return Ok(());
+        }
+
+        if self.check(";")? {
+            return Ok(());
+        }
+
+        if self.check_name("local")? || self.at_type() {
+            return self.local_defs();
+        }
+
+        self.expression(TOP_PRIORITY)?;
+        self.release_temps();
+        self.expect(";")
+    }
+
+    fn local_defs(&mut self) -> Result<(), CompileError> {
+        let ty = self.parse_type()?;
+        loop {
+            let name = self.expect_name()?;
+            let def = self.new_def(&name, &ty, true)?;
+            if self.check("=")? {
+                let expr = self.expression(TOP_PRIORITY)?;
+                let value = self.rvalue(expr)?;
+                let target = self.def_value(def);
+                self.store(&target, &value)?;
+                self.release_temps();
+            }
+            if !self.check(",")? {
+                return self.expect(";");
+            }
+        }
+    }
+
+    fn condition(&mut self) -> Result<Value, CompileError> {
+        let expr = self.expression(TOP_PRIORITY)?;
+        let value = self.rvalue(expr)?;
+        // IF/IFNOT only test the first slot; vectors and strings need a real truth test.
+        let not = match value.ty {
+            Type::Vector => OP_NOT_V,
+            Type::String => OP_NOT_S,
+            Type::Void => return Err(self.error("void value used as a condition")),
+            _ => return Ok(value),
+        };
+        let negated = self.emit_temp(not, value.offset, 0, Type::Float);
+        let truth = self.emit_temp(OP_NOT_F, negated.offset, 0, Type::Float);
+        Ok(truth)
+    }
+
+    fn emit_branch(&mut self, cond: Value, when_true: bool) -> usize {
+        let op = if when_true { OP_IF } else { OP_IFNOT };
+        self.emit(op, cond.offset, 0, 0)
+    }
+
+    fn patch_branch(&mut self, statement: usize) {
+        let distance = (self.compiler.statements.len() - statement) as i16;
+        let patched = &mut self.compiler.statements[statement];
+        if patched.op == OP_GOTO {
+            patched.a = distance;
+        } else {
+            patched.b = distance;
+        }
+    }
+
+    fn expression(&mut self, priority: u8) -> Result<Expr, CompileError> {
+        if priority == 0 {
+            return self.term();
+        }
+        let mut expr = self.expression(priority - 1)?;
+        loop {
+            if priority == 1 {
+                if self.check("(")? {
+                    return self.call(expr);
+                }
+                if self.check(".")? {
+                    expr = self.field_access(expr)?;
+                    continue;
+                }
+            }
+            if priority == ASSIGN_PRIORITY && self.check("=")? {
+                expr = self.assign(expr)?;
+                continue;
+            }
+            let Token::Punct(token) = self.token else {
+                return Ok(expr);
+            };
+            let is_operator = BINARY_OPS
+                .iter()
+                .any(|op| op.priority == priority && op.token == token);
+            if !is_operator {
+                return Ok(expr);
+            }
+            self.advance()?;
+            let lhs = self.rvalue(expr)?;
+            let rhs = self.expression(priority - 1)?;
+            let rhs = self.rvalue(rhs)?;
+            expr = Expr::Value(self.binary(token, lhs, rhs)?);
+        }
+    }
+
+    fn binary(&mut self, token: &str, lhs: Value, rhs: Value) -> Result<Value, CompileError> {
+        let (a, b) = (lhs.ty.basic(), rhs.ty.basic());
+        let truth_operand = |ty: QcType| !matches!(ty, QcType::Vector | QcType::Void);
+        let op = BINARY_OPS.iter().find(|op| {
+            op.token == token
+                && if op.a == QcType::Void {
+                    truth_operand(a) && truth_operand(b)
+                } else {
+                    op.a == a && op.b == b
+                }
+        });
+        let Some(op) = op else {
+            return Err(self.error(format!(
+                "type mismatch for {token}: {} and {}",
+                lhs.ty.describe(),
+                rhs.ty.describe()
+            )));
+        };
+        let result_type = if op.c == QcType::Vector {
+            Type::Vector
+        } else {
+            Type::Float
+        };
+        Ok(self.emit_temp(op.op, lhs.offset, rhs.offset, result_type))
+    }
+
+    fn term(&mut self) -> Result<Expr, CompileError> {
+        if self.check("!")? {
+            let expr = self.expression(NOT_PRIORITY)?;
+            let value = self.rvalue(expr)?;
+            let op = match value.ty {
+                Type::Float => OP_NOT_F,
+                Type::Vector => OP_NOT_V,
+                Type::String => OP_NOT_S,
+                Type::Entity => OP_NOT_ENT,
+                Type::Function(_) => OP_NOT_FNC,
+                _ => {
+                    return Err(self.error(format!("type mismatch for !: {}", value.ty.describe())));
+                }
+            };
+            return Ok(Expr::Value(self.emit_temp(
+                op,
+                value.offset,
+                0,
+                Type::Float,
+            )));
+        }
+        if self.check("(")? {
+            let expr = self.expression(TOP_PRIORITY)?;
+            self.expect(")")?;
+            return Ok(expr);
+        }
+        if self.check("-")? {
+            match self.token {
+                Token::Float(value) => {
+                    self.advance()?;
+                    return Ok(Expr::Value(
+                        self.immediate(Type::Float, [(-value).to_bits(), 0, 0]),
+                    ));
+                }
+                Token::Vector(v) => {
+                    self.advance()?;
+                    return Ok(Expr::Value(
+                        self.immediate(Type::Vector, v.map(|c| (-c).to_bits())),
+                    ));
+                }
+                _ => {}
+            }
+            let expr = self.expression(1)?;
+            let value = self.rvalue(expr)?;
+            let zero = self.immediate(value.ty.clone(), [0; 3]);
+            let op = match value.ty {
+                Type::Float => OP_SUB_F,
+                Type::Vector => OP_SUB_V,
+                _ => {
+                    return Err(self.error(format!(
+                        "type mismatch for unary -: {}",
+                        value.ty.describe()
+                    )));
+                }
+            };
+            return Ok(Expr::Value(self.emit_temp(
+                op,
+                zero.offset,
+                value.offset,
+                value.ty,
+            )));
+        }
+
+        match self.advance()? {
+            Token::Float(value) => Ok(Expr::Value(
+                self.immediate(Type::Float, [value.to_bits(), 0, 0]),
+            )),
+            Token::Vector(v) => Ok(Expr::Value(
+                self.immediate(Type::Vector, v.map(f32::to_bits)),
+            )),
+            Token::String(value) => {
+                let offset = self.compiler.string_offset(&value);
+                Ok(Expr::Value(
+                    self.immediate(Type::String, [offset as u32, 0, 0]),
+                ))
+            }
+            Token::Name(name) => match self.lookup(&name) {
+                Some(def) => Ok(Expr::Value(self.def_value(def))),
+                None => Err(self.error(format!("unknown value \"{name}\""))),
+            },
+            other => Err(self.error(format!("expected a value, found {}", other.describe()))),
+        }
+    }
+
+    fn field_access(&mut self, expr: Expr) -> Result<Expr, CompileError> {
+        let entity = self.rvalue(expr)?;
+        if entity.ty != Type::Entity {
+            return Err(self.error(format!(
+                "type mismatch for .: {} is not an entity",
+                entity.ty.describe()
+            )));
+        }
+        let field = self.term()?;
+        let field = self.rvalue(field)?;
+        let Type::Field(inner) = field.ty else {
+            return Err(self.error(format!(
+                "type mismatch for .: {} is not a field",
+                field.ty.describe()
+            )));
+        };
+        Ok(Expr::Field {
+            entity: entity.offset,
+            field: field.offset,
+            ty: *inner,
+        })
+    }
+
+    fn call(&mut self, expr: Expr) -> Result<Expr, CompileError> {
+        let func = self.rvalue(expr)?;
+        let Type::Function(func_type) = func.ty.clone() else {
+            return Err(self.error(format!("{} is not a function", func.ty.describe())));
+        };
+
+        let mut args = Vec::new();
+        if !self.check(")")? {
+            loop {
+                if args.len() == MAX_PARMS {
+                    return Err(self.error("more than eight parameters"));
+                }
+                let expr = self.expression(TOP_PRIORITY)?;
+                let arg = self.rvalue(expr)?;
+                match func_type.params.get(args.len()) {
+                    Some(param) if param.basic() != arg.ty.basic() => {
+                        return Err(self.error(format!(
+                            "type mismatch on parm {}: expected {}, found {}",
+                            args.len() + 1,
+                            param.describe(),
+                            arg.ty.describe()
+                        )));
+                    }
+                    None if !func_type.varargs => {
+                        return Err(self.error("too many parameters"));
+                    }
+                    _ => {}
+                }
+                args.push(arg);
+                if self.check(")")? {
+                    break;
+                }
+                self.expect(",")?;
+            }
+        }
+        if args.len() < func_type.params.len() {
+            return Err(self.error("too few parameters"));
+        }
+
+        // Arguments are all evaluated before any parm is written so a nested
+        // call can't overwrite the parms of this one.
+        for (position, arg) in args.iter().enumerate() {
+            let parm = (OFS_PARM0 + position * 3) as i16;
+            let op = store_op(&arg.ty);
+            self.emit(op, arg.offset, parm, 0);
+        }
+        self.emit(OP_CALL0 + args.len() as u16, func.offset, 0, 0);
+
+        if func_type.ret == Type::Void {
+            return Ok(Expr::Value(Value {
+                offset: OFS_RETURN as i16,
+                ty: Type::Void,
+                kind: ValueKind::Temp,
+            }));
+        }
+        let result = self.alloc_temp(&func_type.ret);
+        let op = store_op(&func_type.ret);
+        self.emit(op, OFS_RETURN as i16, result.offset, 0);
+        Ok(Expr::Value(result))
+    }
+
+    fn assign(&mut self, target: Expr) -> Result<Expr, CompileError> {
+        let expr = self.expression(ASSIGN_PRIORITY)?;
+        let value = self.rvalue(expr)?;
+        match target {
+            Expr::Field { entity, field, ty } => {
+                if ty.basic() != value.ty.basic() {
+                    return Err(self.error(format!(
+                        "type mismatch for =: {} and {}",
+                        ty.describe(),
+                        value.ty.describe()
+                    )));
+                }
+                let pointer = self.emit_temp(OP_ADDRESS, entity, field, Type::Float);
+                let op = match ty.basic() {
+                    QcType::Vector => OP_STOREP_V,
+                    QcType::String => OP_STOREP_S,
+                    QcType::Entity => OP_STOREP_ENT,
+                    QcType::Field => OP_STOREP_FLD,
+                    QcType::Function => OP_STOREP_FNC,
+                    _ => OP_STOREP_F,
+                };
+                self.emit(op, value.offset, pointer.offset, 0);
+                Ok(Expr::Value(value))
+            }
+            Expr::Value(target) => {
+                if target.kind != ValueKind::Variable {
+                    return Err(self.error("assignment to a constant or temporary"));
+                }
+                self.store(&target, &value)?;
+                Ok(Expr::Value(target))
+            }
+        }
+    }
+
+    fn store(&mut self, target: &Value, value: &Value) -> Result<(), CompileError> {
+        if target.ty.basic() != value.ty.basic() {
+            return Err(self.error(format!(
+                "type mismatch for =: {} and {}",
+                target.ty.describe(),
+                value.ty.describe()
+            )));
+        }
+        let op = store_op(&target.ty);
+        self.emit(op, value.offset, target.offset, 0);
+        Ok(())
+    }
+
+    fn rvalue(&mut self, expr: Expr) -> Result<Value, CompileError> {
+        match expr {
+            Expr::Value(value) => Ok(value),
+            Expr::Field { entity, field, ty } => {
+                let op = match ty.basic() {
+                    QcType::Vector => OP_LOAD_V,
+                    QcType::String => OP_LOAD_S,
+                    QcType::Entity => OP_LOAD_ENT,
+                    QcType::Field => OP_LOAD_FLD,
+                    QcType::Function => OP_LOAD_FNC,
+                    _ => OP_LOAD_F,
+                };
+                Ok(self.emit_temp(op, entity, field, ty))
+            }
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<usize> {
+        self.scope
+            .as_ref()
+            .and_then(|scope| scope.locals.get(name))
+            .or_else(|| self.compiler.global_names.get(name))
+            .copied()
+    }
+
+    fn def_value(&self, def: usize) -> Value {
+        let def = &self.compiler.defs[def];
+        let kind = if def.name == "IMMEDIATE" {
+            ValueKind::Constant
+        } else {
+            ValueKind::Variable
+        };
+        Value {
+            offset: def.offset,
+            ty: def.ty.clone(),
+            kind,
+        }
+    }
+
+    fn get_global(&mut self, name: &str, ty: &Type) -> Result<usize, CompileError> {
+        if let Some(&def) = self.compiler.global_names.get(name) {
+            let existing = &self.compiler.defs[def].ty;
+            if existing != ty {
+                return Err(self.error(format!(
+                    "type mismatch on redeclaration of {name}: {} and {}",
+                    existing.describe(),
+                    ty.describe()
+                )));
+            }
+            return Ok(def);
+        }
+        self.new_def(name, ty, false)
+    }
+
+    fn new_def(&mut self, name: &str, ty: &Type, local: bool) -> Result<usize, CompileError> {
+        if local
+            && self
+                .scope
+                .as_ref()
+                .is_some_and(|scope| scope.locals.contains_key(name))
+        {
+            return Err(self.error(format!("{name} redeclared")));
+        }
+
+        let offset = self.alloc_global(ty.size())?;
+        let def = self.push_def(name, ty.clone(), offset, local);
+        match ty {
+            Type::Vector => {
+                for (component, suffix) in ["_x", "_y", "_z"].iter().enumerate() {
+                    self.push_def(
+                        &format!("{name}{suffix}"),
+                        Type::Float,
+                        offset + component as i16,
+                        local,
+                    );
+                }
+            }
+            Type::Field(inner) if !local => {
+                let field = self.compiler.field_size;
+                self.compiler.globals[offset as usize] = field as u32;
+                if **inner == Type::Vector {
+                    for (component, suffix) in ["_x", "_y", "_z"].iter().enumerate() {
+                        let component_offset = self.alloc_global(1)?;
+                        self.compiler.globals[component_offset as usize] =
+                            (field + component) as u32;
+                        self.push_def(
+                            &format!("{name}{suffix}"),
+                            Type::Field(Box::new(Type::Float)),
+                            component_offset,
+                            false,
+                        );
+                    }
+                }
+                self.compiler.field_size += inner.size();
+            }
+            _ => {}
+        }
+        Ok(def)
+    }
+
+    fn push_def(&mut self, name: &str, ty: Type, offset: i16, local: bool) -> usize {
+        let index = self.compiler.defs.len();
+        self.compiler.defs.push(Def {
+            name: name.to_string(),
+            ty,
+            offset,
+            local,
+            initialized: false,
+            file: self.file.clone(),
+            line: self.line,
+        });
+        if name != "IMMEDIATE" {
+            match (&mut self.scope, local) {
+                (Some(scope), true) => {
+                    scope.locals.insert(name.to_string(), index);
+                }
+                _ => {
+                    self.compiler.global_names.insert(name.to_string(), index);
+                }
+            }
+        }
+        index
+    }
+
+    fn alloc_global(&mut self, size: usize) -> Result<i16, CompileError> {
+        let offset = self.compiler.globals.len();
+        if offset + size > MAX_GLOBALS {
+            return Err(self.error("too many globals"));
+        }
+        self.compiler.globals.resize(offset + size, 0);
+        Ok(offset as i16)
+    }
+
+    fn immediate(&mut self, ty: Type, value: [u32; 3]) -> Value {
+        let key = (ty.basic(), value);
+        let offset = match self.compiler.immediates.get(&key) {
+            Some(&offset) => offset,
+            None => {
+                // Inside a function body this lands in its locals range, as with
+                // qcc; the value never changes so saving it across calls is harmless.
+                let offset = self.compiler.globals.len() as i16;
+                let size = ty.size();
+                self.compiler.globals.extend_from_slice(&value[..size]);
+                let def = self.push_def("IMMEDIATE", ty.clone(), offset, false);
+                self.compiler.defs[def].initialized = true;
+                self.compiler.immediates.insert(key, offset);
+                offset
+            }
+        };
+        Value {
+            offset,
+            ty,
+            kind: ValueKind::Constant,
+        }
+    }
+
+    fn alloc_temp(&mut self, ty: &Type) -> Value {
+        let size = ty.size();
+        let reused = self.scope.as_mut().and_then(|scope| {
+            let position = scope.free_temps.iter().position(|&(_, s)| s == size)?;
+            Some(scope.free_temps.swap_remove(position).0)
+        });
+        let offset = reused.unwrap_or_else(|| {
+            let offset = self.compiler.globals.len() as i16;
+            self.compiler
+                .globals
+                .resize(self.compiler.globals.len() + size, 0);
+            offset
+        });
+        if let Some(scope) = &mut self.scope {
+            scope.live_temps.push((offset, size));
+        }
+        Value {
+            offset,
+            ty: ty.clone(),
+            kind: ValueKind::Temp,
+        }
+    }
+
+    fn release_temps(&mut self) {
+        if let Some(scope) = &mut self.scope {
+            let live = std::mem::take(&mut scope.live_temps);
+            scope.free_temps.extend(live);
+        }
+    }
+
+    fn emit_temp(&mut self, op: u16, a: i16, b: i16, ty: Type) -> Value {
+        let result = self.alloc_temp(&ty);
+        self.emit(op, a, b, result.offset);
+        result
+    }
+
+    fn emit(&mut self, op: u16, a: i16, b: i16, c: i16) -> usize {
+        let index = self.compiler.statements.len();
+        self.compiler.statements.push(Statement { op, a, b, c });
+        self.compiler.line_numbers.push(self.line as i32);
+        index
+    }
+}
+
+fn store_op(ty: &Type) -> u16 {
+    match ty.basic() {
+        QcType::Vector => OP_STORE_V,
+        QcType::String => OP_STORE_S,
+        QcType::Entity => OP_STORE_ENT,
+        QcType::Field => OP_STORE_FLD,
+        QcType::Function => OP_STORE_FNC,
+        _ => OP_STORE_F,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use qw_qc::Vm;
+
+    fn compile(source: &str) -> CompiledProgs {
+        let mut compiler = Compiler::new();
+        compiler.compile_file("test.qc", source).unwrap();
+        compiler.finish().unwrap()
+    }
+
+    fn compile_error(source: &str) -> String {
+        let mut compiler = Compiler::new();
+        match compiler.compile_file("test.qc", source) {
+            Ok(()) => compiler.finish().err().unwrap().to_string(),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    fn global_f32(vm: &Vm, name: &str) -> f32 {
+        let offset = vm.global_def(name).unwrap().offset;
+        vm.read_global_f32(offset).unwrap()
+    }
+
+    fn field(vm: &Vm, name: &str) -> usize {
+        vm.field_def(name).unwrap().offset as usize
+    }
+
+    fn builtin_spawn(vm: &mut Vm) -> Result<(), qw_qc::VmError> {
+        let ent = vm.alloc_edict()?;
+        vm.set_return_f32(ent as f32)
+    }
+
+    fn builtin_report(vm: &mut Vm) -> Result<(), qw_qc::VmError> {
+        let value = vm.read_param_f32(0)?;
+        vm.context_mut::<Vec<f32>>().unwrap().push(value);
+        Ok(())
+    }
+
+    #[test]
+    fn runs_recursive_functions() {
+        let compiled = compile(
+            "float result;\n\
+             float(float n) fact =\n\
+             {\n\
+             \tif (n <= 1)\n\
+             \t\treturn 1;\n\
+             \treturn n * fact(n - 1);\n\
+             };\n\
+             void() main = { result = fact(5) + fact(3); };\n",
+        );
+        let mut vm = Vm::new(compiled.progs);
+        vm.call_by_name("main", 10_000).unwrap();
+        assert_eq!(global_f32(&vm, "result"), 126.0);
+    }
+
+    #[test]
+    fn runs_fields_vectors_loops_and_builtins() {
+        let compiled = compile(
+            "entity world;\n\
+             .vector origin;\n\
+             .float health;\n\
+             .void() think;\n\
+             vector v;\n\
+             entity() spawn = #14;\n\
+             void(float f, ...) report = #1;\n\
+             void() th = {};\n\
+             void() main =\n\
+             {\n\
+             \tlocal entity e;\n\
+             \tlocal float i = 0;\n\
+             \te = spawn();\n\
+             \te.origin = '1 2 3';\n\
+             \te.health = 10;\n\
+             \twhile (i < 3)\n\
+             \t{\n\
+             \t\te.health = e.health + e.origin_z;\n\
+             \t\ti = i + 1;\n\
+             \t}\n\
+             \tv = e.origin * 2;\n\
+             \tv_x = v_x + -1;\n\
+             \treport(e.health, v * '1 1 1');\n\
+             \tif (!e.think)\n\
+             \t\te.think = th;\n\
+             \tif (e != world && \"a\" != \"b\")\n\
+             \t\treport(-v_y);\n\
+             };\n",
+        );
+        let mut vm = Vm::with_context(compiled.progs, Vec::<f32>::new());
+        vm.register_builtin(1, builtin_report);
+        vm.register_builtin(14, builtin_spawn);
+        vm.call_by_name("main", 10_000).unwrap();
+
+        assert_eq!(vm.context_ref::<Vec<f32>>().unwrap(), &vec![19.0, -4.0]);
+        let v = vm
+            .read_global_vec(vm.global_def("v").unwrap().offset)
+            .unwrap();
+        assert_eq!((v.x, v.y, v.z), (1.0, 4.0, 6.0));
+        let think = vm.read_edict_field_raw(1, field(&vm, "think"), 1).unwrap()[0];
+        assert_eq!(vm.progs().functions[think as usize].name, "th");
+        assert_eq!(field(&vm, "origin_z"), field(&vm, "origin") + 2);
+    }
+
+    #[test]
+    fn state_functions_set_frame_and_think() {
+        let compiled = compile(
+            "entity self;\n\
+             float time;\n\
+             .float frame;\n\
+             .float nextthink;\n\
+             .void() think;\n\
+             $frame stand1 stand2\n\
+             $frame walk1\n\
+             void() walk2 = [$walk1, walk3] {};\n\
+             void() walk3 = {};\n",
+        );
+        let statement =
+            compiled.progs.statements[compiled.progs.functions[1].first_statement as usize];
+        assert_eq!(statement.op, OP_STATE);
+
+        let mut vm = Vm::new(compiled.progs);
+        let ent = vm.alloc_edict().unwrap();
+        vm.write_global_f32(vm.global_def("self").unwrap().offset, ent as f32)
+            .unwrap();
+        vm.write_global_f32(vm.global_def("time").unwrap().offset, 5.0)
+            .unwrap();
+        vm.call_by_name("walk2", 100).unwrap();
+        assert_eq!(
+            vm.read_edict_field_f32(ent, field(&vm, "frame")).unwrap(),
+            2.0
+        );
+        assert_eq!(
+            vm.read_edict_field_f32(ent, field(&vm, "nextthink"))
+                .unwrap(),
+            5.1
+        );
+        let think = vm
+            .read_edict_field_raw(ent, field(&vm, "think"), 1)
+            .unwrap()[0];
+        assert_eq!(think, vm.progs().function_index("walk3").unwrap() as u32);
+    }
+
+    #[test]
+    fn not_binds_looser_than_comparisons_like_qcc() {
+        let compiled = compile(
+            "float a;\n\
+             float b;\n\
+             void() main = { a = !2 == 3; b = !(2 == 2) + 4; };\n",
+        );
+        let mut vm = Vm::new(compiled.progs);
+        vm.call_by_name("main", 100).unwrap();
+        assert_eq!(global_f32(&vm, "a"), 1.0);
+        // The operand of ! extends over the +, so this is !((2 == 2) + 4).
+        assert_eq!(global_f32(&vm, "b"), 0.0);
+    }
+
+    #[test]
+    fn writes_defs_and_line_numbers() {
+        let compiled = compile("float x = 3;\nfloat y;\nvoid() main =\n{\n\ty = x;\n};\n");
+        let progs = &compiled.progs;
+        let x = progs.global_def("x").unwrap();
+        assert!(!x.save_global);
+        assert_eq!(f32::from_bits(progs.globals[x.offset as usize]), 3.0);
+        assert!(progs.global_def("y").unwrap().save_global);
+        assert_eq!(compiled.line_numbers.len(), progs.statements.len());
+        let store = progs.functions[1].first_statement as usize;
+        assert_eq!(progs.statements[store].op, OP_STORE_F);
+        assert_eq!(compiled.line_numbers[store], 5);
+
+        let lno = compiled.lno_bytes();
+        assert_eq!(&lno[..4], b"LNOF");
+        assert_eq!(lno.len(), 24 + progs.statements.len() * 4);
+
+        let parsed = ProgsDat::from_bytes(&progs.to_bytes()).unwrap();
+        assert_eq!(parsed.functions, progs.functions);
+        assert_eq!(parsed.global_defs, progs.global_defs);
+    }
+
+    #[test]
+    fn multi_line_statements_report_the_line_they_end_on() {
+        assert_eq!(
+            compile_error("float x;\nvoid() main =\n{\n\tx = 1 +\n\t\t\"text\"\n\t;\n};\n"),
+            "test.qc:5: type mismatch for +: float and string"
+        );
+
+        let compiled =
+            compile("float x;\nvoid() main =\n{\n\tif (x ==\n\t\t2)\n\t{\n\t\tx = 3;\n\t}\n};\n");
+        let progs = &compiled.progs;
+        let first = progs.functions[1].first_statement as usize;
+        let branch = (first..progs.statements.len())
+            .find(|&index| progs.statements[index].op == OP_IFNOT)
+            .unwrap();
+        assert_eq!(compiled.line_numbers[branch], 5);
+    }
+
+    #[test]
+    fn reports_errors_with_file_and_line() {
+        assert_eq!(
+            compile_error("float x;\nvoid() main =\n{\n\tx = \"text\";\n};\n"),
+            "test.qc:4: type mismatch for =: float and string"
+        );
+        assert_eq!(
+            compile_error("void() missing;\n"),
+            "test.qc:1: function missing was not defined"
+        );
+        assert_eq!(
+            compile_error("float x;\nvector x;\n"),
+            "test.qc:2: type mismatch on redeclaration of x: float and vector"
+        );
+        assert_eq!(
+            compile_error("void(float a) f = {};\nvoid() main = { f(); };\n"),
+            "test.qc:2: too few parameters"
+        );
+    }
+}
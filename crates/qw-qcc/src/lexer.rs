@@ -0,0 +1,320 @@
+// QuakeC tokenizer, including the `$frame` grab macros from qcc's pr_lex.c.
+
+use crate::CompileError;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Name(String),
+    Punct(&'static str),
+    Float(f32),
+    String(String),
+    Vector([f32; 3]),
+    Eof,
+}
+
+impl Token {
+    pub fn describe(&self) -> String {
+        match self {
+            Token::Name(name) => format!("\"{name}\""),
+            Token::Punct(punct) => format!("\"{punct}\""),
+            Token::Float(value) => format!("{value}"),
+            Token::String(value) => format!("\"{value}\""),
+            Token::Vector(v) => format!("'{} {} {}'", v[0], v[1], v[2]),
+            Token::Eof => "end of file".to_string(),
+        }
+    }
+}
+
+const PUNCTUATION: [&str; 27] = [
+    "&&", "||", "<=", ">=", "==", "!=", "...", ";", ",", "!", "*", "/", "(", ")", "-", "+", "=",
+    "[", "]", "{", "}", ".", "<", ">", "#", "&", "|",
+];
+
+// Model grabbing directives qcc skips to the end of the line.
+const IGNORED_GRABS: [&str; 6] = ["cd", "origin", "base", "flags", "scale", "skin"];
+
+pub struct Lexer<'a> {
+    file: &'a str,
+    src: &'a [u8],
+    pos: usize,
+    line: usize,
+    frames: HashMap<String, f32>,
+    next_frame: f32,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(file: &'a str, source: &'a str) -> Self {
+        Self {
+            file,
+            src: source.as_bytes(),
+            pos: 0,
+            line: 1,
+            frames: HashMap::new(),
+            next_frame: 0.0,
+        }
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn next_token(&mut self) -> Result<Token, CompileError> {
+        loop {
+            self.skip_whitespace_and_comments()?;
+            let Some(&c) = self.src.get(self.pos) else {
+                return Ok(Token::Eof);
+            };
+
+            if c == b'$' {
+                self.pos += 1;
+                if let Some(token) = self.grab()? {
+                    return Ok(token);
+                }
+                continue;
+            }
+            if c == b'"' {
+                return self.string();
+            }
+            if c == b'\'' {
+                return self.vector();
+            }
+            if c.is_ascii_digit()
+                || (c == b'.' && self.src.get(self.pos + 1).is_some_and(u8::is_ascii_digit))
+            {
+                return self.number().map(Token::Float);
+            }
+            if c.is_ascii_alphabetic() || c == b'_' {
+                let name = self.name();
+                return Ok(Token::Name(name));
+            }
+            for punct in PUNCTUATION {
+                if self.src[self.pos..].starts_with(punct.as_bytes()) {
+                    self.pos += punct.len();
+                    return Ok(Token::Punct(punct));
+                }
+            }
+            return Err(self.error(format!("unknown character '{}'", c as char)));
+        }
+    }
+
+    fn error(&self, message: String) -> CompileError {
+        CompileError::new(self.file, self.line, message)
+    }
+
+    fn skip_whitespace_and_comments(&mut self) -> Result<(), CompileError> {
+        loop {
+            match self.src.get(self.pos) {
+                Some(b'\n') => {
+                    self.line += 1;
+                    self.pos += 1;
+                }
+                Some(c) if c.is_ascii_whitespace() => self.pos += 1,
+                Some(b'/') if self.src.get(self.pos + 1) == Some(&b'/') => {
+                    while self.src.get(self.pos).is_some_and(|&c| c != b'\n') {
+                        self.pos += 1;
+                    }
+                }
+                Some(b'/') if self.src.get(self.pos + 1) == Some(&b'*') => {
+                    self.pos += 2;
+                    loop {
+                        match self.src.get(self.pos) {
+                            None => return Err(self.error("unterminated comment".to_string())),
+                            Some(b'*') if self.src.get(self.pos + 1) == Some(&b'/') => {
+                                self.pos += 2;
+                                break;
+                            }
+                            Some(b'\n') => self.line += 1,
+                            Some(_) => {}
+                        }
+                        self.pos += 1;
+                    }
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn name(&mut self) -> String {
+        let start = self.pos;
+        while self
+            .src
+            .get(self.pos)
+            .is_some_and(|&c| c.is_ascii_alphanumeric() || c == b'_')
+        {
+            self.pos += 1;
+        }
+        String::from_utf8_lossy(&self.src[start..self.pos]).into_owned()
+    }
+
+    fn number(&mut self) -> Result<f32, CompileError> {
+        let start = self.pos;
+        if self.src.get(self.pos) == Some(&b'-') {
+            self.pos += 1;
+        }
+        while self
+            .src
+            .get(self.pos)
+            .is_some_and(|&c| c.is_ascii_digit() || c == b'.')
+        {
+            self.pos += 1;
+        }
+        let text = String::from_utf8_lossy(&self.src[start..self.pos]);
+        text.parse::<f32>()
+            .map_err(|_| self.error(format!("bad number {text}")))
+    }
+
+    fn string(&mut self) -> Result<Token, CompileError> {
+        self.pos += 1;
+        let mut bytes = Vec::new();
+        loop {
+            let Some(&c) = self.src.get(self.pos) else {
+                return Err(self.error("unterminated string".to_string()));
+            };
+            self.pos += 1;
+            match c {
+                b'"' => break,
+                b'\\' => {
+                    let Some(&escaped) = self.src.get(self.pos) else {
+                        return Err(self.error("unterminated string".to_string()));
+                    };
+                    self.pos += 1;
+                    bytes.push(match escaped {
+                        b'n' => b'\n',
+                        b't' => b'\t',
+                        other => other,
+                    });
+                }
+                b'\n' => {
+                    self.line += 1;
+                    bytes.push(c);
+                }
+                _ => bytes.push(c),
+            }
+        }
+        Ok(Token::String(String::from_utf8_lossy(&bytes).into_owned()))
+    }
+
+    fn vector(&mut self) -> Result<Token, CompileError> {
+        self.pos += 1;
+        let mut values = [0.0f32; 3];
+        for value in &mut values {
+            while self
+                .src
+                .get(self.pos)
+                .is_some_and(|&c| c == b' ' || c == b'\t')
+            {
+                self.pos += 1;
+            }
+            let starts_number = self
+                .src
+                .get(self.pos)
+                .is_some_and(|&c| c.is_ascii_digit() || c == b'-' || c == b'.');
+            if !starts_number {
+                return Err(self.error("bad vector constant".to_string()));
+            }
+            *value = self.number()?;
+        }
+        while self
+            .src
+            .get(self.pos)
+            .is_some_and(|&c| c == b' ' || c == b'\t')
+        {
+            self.pos += 1;
+        }
+        if self.src.get(self.pos) != Some(&b'\'') {
+            return Err(self.error("bad vector constant".to_string()));
+        }
+        self.pos += 1;
+        Ok(Token::Vector(values))
+    }
+
+    fn grab(&mut self) -> Result<Option<Token>, CompileError> {
+        if !self
+            .src
+            .get(self.pos)
+            .is_some_and(|&c| c.is_ascii_alphanumeric() || c == b'_')
+        {
+            return Err(self.error("hanging $".to_string()));
+        }
+        let name = self.name();
+        if name == "frame" {
+            while let Some(frame) = self.line_word() {
+                self.frames.insert(frame, self.next_frame);
+                self.next_frame += 1.0;
+            }
+            return Ok(None);
+        }
+        if IGNORED_GRABS.contains(&name.as_str()) {
+            while self.line_word().is_some() {}
+            return Ok(None);
+        }
+        match self.frames.get(&name) {
+            Some(&value) => Ok(Some(Token::Float(value))),
+            None => Err(self.error(format!("unknown frame macro ${name}"))),
+        }
+    }
+
+    fn line_word(&mut self) -> Option<String> {
+        while self
+            .src
+            .get(self.pos)
+            .is_some_and(|&c| c != b'\n' && c.is_ascii_whitespace())
+        {
+            self.pos += 1;
+        }
+        let start = self.pos;
+        while self
+            .src
+            .get(self.pos)
+            .is_some_and(|&c| !c.is_ascii_whitespace())
+        {
+            self.pos += 1;
+        }
+        (self.pos > start).then(|| String::from_utf8_lossy(&self.src[start..self.pos]).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(source: &str) -> Vec<Token> {
+        let mut lexer = Lexer::new("test.qc", source);
+        let mut out = Vec::new();
+        loop {
+            let token = lexer.next_token().unwrap();
+            if token == Token::Eof {
+                return out;
+            }
+            out.push(token);
+        }
+    }
+
+    #[test]
+    fn lexes_literals_and_punctuation() {
+        assert_eq!(
+            tokens("a<=1.5 \"hi\\n\" '1 -2 .5' ... // comment\n/* x */ ;"),
+            vec![
+                Token::Name("a".to_string()),
+                Token::Punct("<="),
+                Token::Float(1.5),
+                Token::String("hi\n".to_string()),
+                Token::Vector([1.0, -2.0, 0.5]),
+                Token::Punct("..."),
+                Token::Punct(";"),
+            ]
+        );
+    }
+
+    #[test]
+    fn expands_frame_macros() {
+        assert_eq!(
+            tokens("$cd /raid/quake\n$frame stand1 stand2\n$frame walk1\n$walk1 $stand2"),
+            vec![Token::Float(2.0), Token::Float(1.0)]
+        );
+        let mut lexer = Lexer::new("test.qc", "\n$nope");
+        let err = lexer.next_token().unwrap_err();
+        assert_eq!(err.to_string(), "test.qc:2: unknown frame macro $nope");
+    }
+}
@@ -0,0 +1,138 @@
+#![forbid(unsafe_code)]
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+mod compiler;
+mod lexer;
+mod progdefs;
+
+pub use compiler::{CompiledProgs, Compiler};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompileError {
+    pub file: String,
+    pub line: usize,
+    pub message: String,
+}
+
+impl CompileError {
+    pub fn new(file: &str, line: usize, message: String) -> Self {
+        Self {
+            file: file.to_string(),
+            line,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}: {}", self.file, self.message)
+        } else {
+            write!(f, "{}:{}: {}", self.file, self.line, self.message)
+        }
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProgsSrc {
+    pub output: PathBuf,
+    pub files: Vec<PathBuf>,
+}
+
+impl ProgsSrc {
+    // progs.src names the output file first, then every .qc file in order;
+    // paths are relative to the directory holding progs.src.
+    pub fn parse(path: &Path, text: &str) -> Result<Self, CompileError> {
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        let mut words = text
+            .lines()
+            .map(|line| line.split("//").next().unwrap_or_default())
+            .flat_map(str::split_whitespace);
+        let output = words.next().ok_or_else(|| {
+            CompileError::new(
+                &path.display().to_string(),
+                0,
+                "no output file named".to_string(),
+            )
+        })?;
+        Ok(Self {
+            output: dir.join(output),
+            files: words.map(|word| dir.join(word)).collect(),
+        })
+    }
+}
+
+pub fn compile_progs_src(path: &Path) -> Result<(ProgsSrc, CompiledProgs), CompileError> {
+    let text = read_source(path)?;
+    let src = ProgsSrc::parse(path, &text)?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut compiler = Compiler::new();
+    for file in &src.files {
+        let source = read_source(file)?;
+        let name = file.strip_prefix(dir).unwrap_or(file).display().to_string();
+        compiler.compile_file(&name, &source)?;
+    }
+    let compiled = compiler.finish()?;
+    Ok((src, compiled))
+}
+
+fn read_source(path: &Path) -> Result<String, CompileError> {
+    let bytes = std::fs::read(path).map_err(|err| {
+        CompileError::new(
+            &path.display().to_string(),
+            0,
+            format!("couldn't read file: {err}"),
+        )
+    })?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_progs_src_relative_to_its_directory() {
+        let src = ProgsSrc::parse(
+            Path::new("qc/progs.src"),
+            "../qwprogs.dat // output\n\ndefs.qc\nsubs.qc  world.qc\n",
+        )
+        .unwrap();
+        assert_eq!(src.output, Path::new("qc/../qwprogs.dat"));
+        assert_eq!(
+            src.files,
+            vec![
+                PathBuf::from("qc/defs.qc"),
+                PathBuf::from("qc/subs.qc"),
+                PathBuf::from("qc/world.qc"),
+            ]
+        );
+    }
+
+    #[test]
+    fn compiles_files_listed_in_progs_src() {
+        let dir = std::env::temp_dir().join(format!("qw-qcc-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("progs.src"), "out.dat\na.qc\nb.qc\n").unwrap();
+        std::fs::write(dir.join("a.qc"), "float count;\nvoid() bump;\n").unwrap();
+        std::fs::write(dir.join("b.qc"), "void() bump =\n{\n\tcount = oops;\n};\n").unwrap();
+
+        let err = compile_progs_src(&dir.join("progs.src")).err().unwrap();
+        assert_eq!(err.to_string(), "b.qc:3: unknown value \"oops\"");
+
+        std::fs::write(
+            dir.join("b.qc"),
+            "void() bump =\n{\n\tcount = count + 1;\n};\n",
+        )
+        .unwrap();
+        let (src, compiled) = compile_progs_src(&dir.join("progs.src")).unwrap();
+        assert_eq!(src.output, dir.join("out.dat"));
+        assert_eq!(compiled.progs.functions[1].file, "b.qc");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,140 @@
+use std::fmt;
+use std::path::PathBuf;
+
+use qw_qcc::{CompileError, compile_progs_src};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct QccArgs {
+    src: PathBuf,
+    write_lno: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum CliAction {
+    Run(QccArgs),
+    Help,
+}
+
+#[derive(Debug)]
+enum QccError {
+    MissingValue(String),
+    InvalidFlag(String),
+    Compile(CompileError),
+    Io(PathBuf, std::io::Error),
+}
+
+impl fmt::Display for QccError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QccError::MissingValue(flag) => write!(f, "missing value for {}", flag),
+            QccError::InvalidFlag(flag) => write!(f, "unknown flag: {}", flag),
+            QccError::Compile(err) => write!(f, "{}", err),
+            QccError::Io(path, err) => write!(f, "couldn't write {}: {}", path.display(), err),
+        }
+    }
+}
+
+fn usage() -> &'static str {
+    "Usage: qw-qcc [options] [progs.src]\n\
+Options:\n\
+  -src <dir>            Directory holding progs.src\n\
+  --no-lno              Don't write the .lno line number file\n\
+  -h, --help            Show this help\n"
+}
+
+fn parse_args<I, S>(args: I) -> Result<CliAction, QccError>
+where
+    I: IntoIterator<Item = S>,
+    S: Into<String>,
+{
+    let mut iter = args.into_iter().map(Into::into);
+    let mut dir = None;
+    let mut file = None;
+    let mut write_lno = true;
+
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(CliAction::Help),
+            "-src" | "--src" => {
+                let value = iter.next().ok_or(QccError::MissingValue(arg))?;
+                dir = Some(PathBuf::from(value));
+            }
+            "--no-lno" => write_lno = false,
+            _ if arg.starts_with('-') || file.is_some() => {
+                return Err(QccError::InvalidFlag(arg));
+            }
+            _ => file = Some(PathBuf::from(arg)),
+        }
+    }
+
+    let file = file.unwrap_or_else(|| PathBuf::from("progs.src"));
+    let src = match dir {
+        Some(dir) => dir.join(file),
+        None => file,
+    };
+    Ok(CliAction::Run(QccArgs { src, write_lno }))
+}
+
+fn run(args: QccArgs) -> Result<(), QccError> {
+    let (src, compiled) = compile_progs_src(&args.src).map_err(QccError::Compile)?;
+    let progs = &compiled.progs;
+    std::fs::write(&src.output, progs.to_bytes())
+        .map_err(|err| QccError::Io(src.output.clone(), err))?;
+    if args.write_lno {
+        let lno = src.output.with_extension("lno");
+        std::fs::write(&lno, compiled.lno_bytes()).map_err(|err| QccError::Io(lno, err))?;
+    }
+    println!(
+        "{}: {} statements, {} functions, {} globals, {} entity fields, crc {}",
+        src.output.display(),
+        progs.statements.len(),
+        progs.functions.len(),
+        progs.globals.len(),
+        progs.entity_fields,
+        progs.crc
+    );
+    Ok(())
+}
+
+fn main() {
+    let action = match parse_args(std::env::args().skip(1)) {
+        Ok(action) => action,
+        Err(err) => {
+            eprintln!("{err}");
+            eprintln!("{}", usage());
+            std::process::exit(2);
+        }
+    };
+    let args = match action {
+        CliAction::Help => {
+            print!("{}", usage());
+            return;
+        }
+        CliAction::Run(args) => args,
+    };
+    if let Err(err) = run(args) {
+        eprintln!("{err}");
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_progs_src_in_source_dir() {
+        let CliAction::Run(args) = parse_args(["-src", "qc"]).unwrap() else {
+            panic!("expected run");
+        };
+        assert_eq!(args.src, PathBuf::from("qc/progs.src"));
+        assert!(args.write_lno);
+
+        let CliAction::Run(args) = parse_args(["mod.src", "--no-lno"]).unwrap() else {
+            panic!("expected run");
+        };
+        assert_eq!(args.src, PathBuf::from("mod.src"));
+        assert!(!args.write_lno);
+        assert!(matches!(parse_args(["-x"]), Err(QccError::InvalidFlag(_))));
+    }
+}
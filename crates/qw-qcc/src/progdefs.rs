@@ -0,0 +1,95 @@
+// The progdefs.h text qcc writes for the system defs; its CRC becomes the progs crc.
+
+use crate::compiler::{Def, Type};
+use qw_common::crc::crc_block;
+
+pub fn progdefs_text(defs: &[Def]) -> String {
+    let mut out = String::from("\n/* file generated by qcc, do not modify */\n\ntypedef struct\n{");
+    out.push_str(&format!("\tint\tpad[{}];\n", qw_qc::RESERVED_OFS));
+    let mut index = 0;
+    while let Some(def) = defs.get(index) {
+        if def.name == "end_sys_globals" {
+            break;
+        }
+        out.push_str(&format!("\t{}\t{};\n", c_type(&def.ty), def.name));
+        // Vectors are followed by their _x, _y and _z component defs.
+        index += if def.ty == Type::Vector { 4 } else { 1 };
+    }
+    out.push_str("} globalvars_t;\n\n");
+
+    out.push_str("typedef struct\n{\n");
+    let mut index = 0;
+    while let Some(def) = defs.get(index) {
+        if def.name == "end_sys_fields" {
+            break;
+        }
+        index += 1;
+        let Type::Field(inner) = &def.ty else {
+            continue;
+        };
+        out.push_str(&format!("\t{}\t{};\n", c_type(inner), def.name));
+        if **inner == Type::Vector {
+            index += 3;
+        }
+    }
+    out.push_str("} entvars_t;\n\n");
+    out
+}
+
+pub fn progdefs_crc(defs: &[Def]) -> u16 {
+    crc_block(progdefs_text(defs).as_bytes())
+}
+
+fn c_type(ty: &Type) -> &'static str {
+    match ty {
+        Type::Float => "float",
+        Type::Vector => "vec3_t",
+        Type::String => "string_t",
+        Type::Function(_) => "func_t",
+        _ => "int",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Compiler;
+    use qw_qc::{ProgsFlavor, QcType};
+
+    fn type_name(ty: QcType) -> &'static str {
+        match ty {
+            QcType::Function => "void()",
+            other => other.name(),
+        }
+    }
+
+    fn system_defs(flavor: ProgsFlavor) -> String {
+        let mut source = String::new();
+        for (name, ty) in flavor.system_globals() {
+            source.push_str(&format!("{} {name};\n", type_name(ty)));
+        }
+        source.push_str("void end_sys_globals;\n");
+        for (name, ty) in flavor.system_fields() {
+            source.push_str(&format!(".{} {name};\n", type_name(ty)));
+        }
+        source.push_str("void end_sys_fields;\n");
+        for (name, ty) in flavor.system_globals() {
+            if ty == QcType::Function {
+                source.push_str(&format!("void() {name} = {{}};\n"));
+            }
+        }
+        source
+    }
+
+    #[test]
+    fn matches_engine_progheader_crcs() {
+        for flavor in [ProgsFlavor::NetQuake, ProgsFlavor::QuakeWorld] {
+            let mut compiler = Compiler::new();
+            compiler
+                .compile_file("defs.qc", &system_defs(flavor))
+                .unwrap();
+            let compiled = compiler.finish().unwrap();
+            assert_eq!(compiled.progs.crc, flavor.crc());
+            assert_eq!(compiled.progs.validate().unwrap(), flavor);
+        }
+    }
+}
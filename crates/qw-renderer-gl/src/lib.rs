@@ -7,11 +7,11 @@ use qw_renderer::RenderSurface;
 use qw_renderer::{
     RenderBeam, RenderDrawList, RenderDynamicLight, RenderEntity, RenderModel, RenderParticle,
     RenderVertex, RenderView, RenderWorld, Renderer, RendererConfig, ResolvedEntity, UiLayer,
-    build_draw_list,
+    UiPic, build_draw_list,
 };
 #[cfg(any(feature = "glow", test))]
 use qw_renderer::{
-    RenderEntityKind, RenderModelFrame, RenderModelKind, RenderModelTexture, UiText,
+    RenderEntityKind, RenderModelFrame, RenderModelKind, RenderModelTexture, UiImage, UiText,
 };
 #[cfg(feature = "glow")]
 use qw_renderer::{RenderLiquidKind, RenderSurfaceKind};
@@ -95,6 +95,12 @@ impl GlTexture {
         Self { id, width, height }
     }
 
+    // The texture lives in the context until it's deleted; dropping the
+    // handle doesn't free it.
+    unsafe fn release(self, device: &GlDevice) {
+        device.gl.delete_texture(self.id);
+    }
+
     unsafe fn from_rgba_mips(device: &GlDevice, texture: &GpuTexture) -> Self {
         Self::from_rgba_mips_data(device, texture.width, texture.height, &texture.mips)
     }
@@ -184,6 +190,46 @@ impl GlTexture {
         Self { id, width, height }
     }
 
+    // UI pics keep their hard pixel edges when the status bar is scaled up.
+    unsafe fn from_rgba_nearest(device: &GlDevice, width: u32, height: u32, data: &[u8]) -> Self {
+        let gl = &device.gl;
+        let id = gl.create_texture().expect("gl texture");
+        gl.bind_texture(glow::TEXTURE_2D, Some(id));
+        gl.tex_parameter_i32(
+            glow::TEXTURE_2D,
+            glow::TEXTURE_MIN_FILTER,
+            glow::NEAREST as i32,
+        );
+        gl.tex_parameter_i32(
+            glow::TEXTURE_2D,
+            glow::TEXTURE_MAG_FILTER,
+            glow::NEAREST as i32,
+        );
+        gl.tex_parameter_i32(
+            glow::TEXTURE_2D,
+            glow::TEXTURE_WRAP_S,
+            glow::CLAMP_TO_EDGE as i32,
+        );
+        gl.tex_parameter_i32(
+            glow::TEXTURE_2D,
+            glow::TEXTURE_WRAP_T,
+            glow::CLAMP_TO_EDGE as i32,
+        );
+        gl.tex_image_2d(
+            glow::TEXTURE_2D,
+            0,
+            glow::RGBA8 as i32,
+            width as i32,
+            height as i32,
+            0,
+            glow::RGBA,
+            glow::UNSIGNED_BYTE,
+            Some(data),
+        );
+
+        Self { id, width, height }
+    }
+
     unsafe fn from_r8_nearest(device: &GlDevice, width: u32, height: u32, data: &[u8]) -> Self {
         let gl = &device.gl;
         let id = gl.create_texture().expect("gl texture");
@@ -696,6 +742,7 @@ struct GlUiProgram {
     projection: Option<glow::UniformLocation>,
     color: Option<glow::UniformLocation>,
     font_sampler: Option<glow::UniformLocation>,
+    rgba: Option<glow::UniformLocation>,
 }
 
 #[cfg(feature = "glow")]
@@ -720,12 +767,17 @@ in vec2 v_uv;
 
 uniform sampler2D u_font;
 uniform vec4 u_color;
+uniform int u_rgba;
 
 out vec4 frag_color;
 
 void main() {
-    float alpha = texture(u_font, v_uv).r;
-    frag_color = vec4(u_color.rgb, u_color.a * alpha);
+    vec4 texel = texture(u_font, v_uv);
+    if (u_rgba != 0) {
+        frag_color = texel * u_color;
+    } else {
+        frag_color = vec4(u_color.rgb, u_color.a * texel.r);
+    }
 }
 "#;
 
@@ -753,6 +805,7 @@ void main() {
         if let Some(location) = font_sampler.as_ref() {
             gl.uniform_1_i32(Some(location), 0);
         }
+        let rgba = gl.get_uniform_location(program, "u_rgba");
         gl.use_program(None);
 
         Self {
@@ -760,6 +813,7 @@ void main() {
             projection,
             color,
             font_sampler,
+            rgba,
         }
     }
 }
@@ -1419,6 +1473,9 @@ pub struct GlRenderer {
     gl_models: Vec<Option<GlModel>>,
     gpu_world: Option<GpuWorld>,
     ui: UiLayer,
+    pics: Vec<UiPic>,
+    #[cfg(feature = "glow")]
+    gl_pics: Vec<GlTexture>,
     debug_wireframe: bool,
     debug_lightmap: bool,
     gamma: f32,
@@ -1458,6 +1515,9 @@ impl GlRenderer {
             gl_models: Vec::new(),
             gpu_world: None,
             ui: UiLayer::default(),
+            pics: Vec::new(),
+            #[cfg(feature = "glow")]
+            gl_pics: Vec::new(),
             debug_wireframe: false,
             debug_lightmap: false,
             gamma: 1.0,
@@ -1542,6 +1602,7 @@ impl GlRenderer {
             }
         }
         self.rebuild_gl_models();
+        self.rebuild_gl_pics();
     }
 
     #[cfg(feature = "glow")]
@@ -1595,6 +1656,36 @@ impl GlRenderer {
         &self.ui
     }
 
+    pub fn set_pics(&mut self, pics: Vec<UiPic>) {
+        self.pics = pics;
+        #[cfg(feature = "glow")]
+        if self.gl_device.is_some() {
+            self.rebuild_gl_pics();
+        }
+    }
+
+    pub fn pics(&self) -> &[UiPic] {
+        &self.pics
+    }
+
+    #[cfg(feature = "glow")]
+    fn rebuild_gl_pics(&mut self) {
+        let Some(device) = self.gl_device.as_ref() else {
+            self.gl_pics.clear();
+            return;
+        };
+        for texture in self.gl_pics.drain(..) {
+            unsafe { texture.release(device) };
+        }
+        self.gl_pics = self
+            .pics
+            .iter()
+            .map(|pic| unsafe {
+                GlTexture::from_rgba_nearest(device, pic.width, pic.height, &pic.rgba)
+            })
+            .collect();
+    }
+
     pub fn set_wireframe(&mut self, enabled: bool) {
         self.debug_wireframe = enabled;
     }
@@ -2290,7 +2381,7 @@ impl GlRenderer {
 
     #[cfg(feature = "glow")]
     fn draw_ui(&self, device: &GlDevice, gl_state: &mut GlState) {
-        if self.ui.texts.is_empty() {
+        if self.ui.texts.is_empty() && self.ui.images.is_empty() {
             return;
         }
         let gl = &device.gl;
@@ -2308,11 +2399,35 @@ impl GlRenderer {
                 gl.uniform_matrix_4_f32_slice(Some(location), false, &projection.data);
             }
             gl.active_texture(glow::TEXTURE0);
-            gl.bind_texture(glow::TEXTURE_2D, Some(gl_state.ui_font.id));
             gl.enable(glow::BLEND);
             gl.blend_func(glow::SRC_ALPHA, glow::ONE_MINUS_SRC_ALPHA);
             gl.disable(glow::DEPTH_TEST);
             gl.bind_vertex_array(Some(gl_state.ui_mesh.vao));
+            if let Some(location) = gl_state.ui_program.rgba.as_ref() {
+                gl.uniform_1_i32(Some(location), 1);
+            }
+            if let Some(location) = gl_state.ui_program.color.as_ref() {
+                gl.uniform_4_f32(Some(location), 1.0, 1.0, 1.0, 1.0);
+            }
+        }
+
+        for image in &self.ui.images {
+            let Some(texture) = self.gl_pics.get(image.pic) else {
+                continue;
+            };
+            let vertices = build_ui_image_vertices(image);
+            unsafe {
+                gl.bind_texture(glow::TEXTURE_2D, Some(texture.id));
+                gl_state.ui_mesh.upload(device, &vertices);
+                gl.draw_arrays(glow::TRIANGLES, 0, (vertices.len() / 4) as i32);
+            }
+        }
+
+        unsafe {
+            gl.bind_texture(glow::TEXTURE_2D, Some(gl_state.ui_font.id));
+            if let Some(location) = gl_state.ui_program.rgba.as_ref() {
+                gl.uniform_1_i32(Some(location), 0);
+            }
         }
 
         for text in &self.ui.texts {
@@ -2623,6 +2738,18 @@ fn build_beam_vertices(beams: &[RenderBeam]) -> Vec<f32> {
     vertices
 }
 
+#[cfg(feature = "glow")]
+fn build_ui_image_vertices(image: &UiImage) -> Vec<f32> {
+    let x0 = image.x as f32;
+    let y0 = image.y as f32;
+    let x1 = (image.x + image.width) as f32;
+    let y1 = (image.y + image.height) as f32;
+    vec![
+        x0, y0, 0.0, 0.0, x1, y0, 1.0, 0.0, x1, y1, 1.0, 1.0, x0, y0, 0.0, 0.0, x1, y1, 1.0, 1.0,
+        x0, y1, 0.0, 1.0,
+    ]
+}
+
 #[cfg(feature = "glow")]
 fn build_ui_vertices(text: &UiText) -> Vec<f32> {
     let cell = FONT_CELL as f32;
@@ -2902,6 +3029,13 @@ mod tests {
     fn stores_ui_state() {
         let mut renderer = GlRenderer::new(RendererConfig::default());
         let ui = UiLayer {
+            images: vec![UiImage {
+                pic: 0,
+                x: 0,
+                y: 152,
+                width: 320,
+                height: 24,
+            }],
             texts: vec![UiText {
                 text: "Loading...".to_string(),
                 x: 10,
@@ -2911,6 +3045,14 @@ mod tests {
         };
         renderer.set_ui(ui.clone());
         assert_eq!(renderer.ui(), &ui);
+
+        let pic = UiPic {
+            width: 1,
+            height: 1,
+            rgba: vec![255, 0, 0, 255],
+        };
+        renderer.set_pics(vec![pic.clone()]);
+        assert_eq!(renderer.pics(), &[pic]);
     }
 
     #[test]
@@ -263,8 +263,29 @@ pub struct UiText {
     pub color: [u8; 4],
 }
 
+// A 2D picture, such as a status bar piece, handed to the renderer once
+// with set_pics and drawn by index.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UiPic {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+}
+
+// A pic stretched over a screen rectangle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UiImage {
+    pub pic: usize,
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+// Images are drawn first, then text over them.
 #[derive(Debug, Clone, PartialEq, Default)]
 pub struct UiLayer {
+    pub images: Vec<UiImage>,
     pub texts: Vec<UiText>,
 }
 
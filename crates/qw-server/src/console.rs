@@ -0,0 +1,225 @@
+// Server console commands read from stdin.
+
+use crate::qc;
+use qw_qc::{ProgsDat, ReloadReport, Vm};
+use std::sync::mpsc;
+
+pub fn spawn_stdin_reader() -> mpsc::Receiver<String> {
+    let (tx, rx) = mpsc::channel::<String>();
+    std::thread::spawn(move || {
+        let stdin = std::io::stdin();
+        let mut line = String::new();
+        loop {
+            line.clear();
+            match stdin.read_line(&mut line) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {}
+            }
+            let trimmed = line.trim();
+            if trimmed.is_empty() {
+                continue;
+            }
+            if tx.send(trimmed.to_string()).is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+pub fn execute(
+    vm: &mut Vm,
+    line: &str,
+    load_progs: &dyn Fn() -> Result<ProgsDat, String>,
+) -> Vec<String> {
+    let mut parts = line.split_whitespace();
+    let Some(cmd) = parts.next() else {
+        return Vec::new();
+    };
+
+    match cmd.to_ascii_lowercase().as_str() {
+        "edicts" => print_edicts(vm),
+        "edict" => {
+            let Some(arg) = parts.next() else {
+                return vec!["usage: edict <number>".to_string()];
+            };
+            match arg.parse::<usize>() {
+                Ok(ent) if ent < vm.edict_count() => print_edict(vm, ent),
+                _ => vec![format!("bad edict number {arg}")],
+            }
+        }
+        "edictcount" => print_edict_count(vm),
+        "progs_reload" => match load_progs() {
+            Ok(progs) => match qc::reload_progs(vm, progs) {
+                Ok(report) => print_reload_report(&report),
+                Err(err) => vec![format!("progs_reload refused: {err}")],
+            },
+            Err(err) => vec![format!("progs_reload failed: {err}")],
+        },
+        _ => vec![format!("unknown command \"{cmd}\"")],
+    }
+}
+
+fn print_edicts(vm: &Vm) -> Vec<String> {
+    let mut lines = vec![format!("{} entities", vm.edict_count())];
+    for ent in 0..vm.edict_count() {
+        lines.extend(print_edict(vm, ent));
+    }
+    lines
+}
+
+fn print_edict(vm: &Vm, ent: usize) -> Vec<String> {
+    let mut lines = vec![format!("EDICT {ent}:")];
+    if vm.edict_is_free(ent) {
+        let freetime = vm.edict_free_time(ent).unwrap_or(0.0);
+        lines.push(format!("FREE (since {freetime:.1})"));
+        return lines;
+    }
+    if let Ok(pairs) = vm.edict_field_strings(ent) {
+        for (name, value) in pairs {
+            lines.push(format!("{name:<15}{value}"));
+        }
+    }
+    lines
+}
+
+fn print_edict_count(vm: &Vm) -> Vec<String> {
+    let counts = qc::edict_counts(vm);
+    vec![
+        format!("num_edicts:{:3}", counts.total),
+        format!("active    :{:3}", counts.active),
+        format!("view      :{:3}", counts.view),
+        format!("touch     :{:3}", counts.touch),
+        format!("step      :{:3}", counts.step),
+    ]
+}
+
+fn print_reload_report(report: &ReloadReport) -> Vec<String> {
+    let mut lines = vec![format!(
+        "progs reloaded: kept {} fields and {} globals",
+        report.fields_kept, report.globals_kept
+    )];
+    let lists = [
+        ("new fields", &report.added_fields),
+        ("dropped fields", &report.dropped_fields),
+        ("dropped globals", &report.dropped_globals),
+        ("cleared references to", &report.missing_functions),
+    ];
+    for (label, names) in lists {
+        if !names.is_empty() {
+            lines.push(format!("{label}: {}", names.join(", ")));
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use qw_qc::{Definition, PROG_VERSION, ProgsDat, QcType};
+
+    fn no_progs() -> Result<ProgsDat, String> {
+        Err("no progs".to_string())
+    }
+
+    fn test_progs() -> ProgsDat {
+        ProgsDat {
+            version: PROG_VERSION,
+            crc: 0,
+            statements: Vec::new(),
+            global_defs: Vec::new(),
+            field_defs: vec![Definition {
+                ty: QcType::Float,
+                offset: 0,
+                name: "solid".to_string(),
+                save_global: false,
+            }],
+            functions: Vec::new(),
+            strings: vec![0],
+            globals: vec![0; 32],
+            entity_fields: 1,
+        }
+    }
+
+    fn test_vm() -> Vm {
+        let mut vm = Vm::with_context(test_progs(), qc::ServerQcContext::default());
+        qc::configure_vm(&mut vm, "start").unwrap();
+        vm
+    }
+
+    #[test]
+    fn edictcount_reports_active_and_touch() {
+        let mut vm = test_vm();
+        let ent = vm.alloc_edict().unwrap();
+        vm.write_edict_field_f32(ent, 0, 2.0).unwrap();
+        let freed = vm.alloc_edict().unwrap();
+        vm.free_edict(freed).unwrap();
+
+        let lines = execute(&mut vm, "edictcount", &no_progs);
+        assert_eq!(lines[0], format!("num_edicts:{:3}", vm.edict_count()));
+        assert_eq!(lines[1], format!("active    :{:3}", vm.edict_count() - 1));
+        assert_eq!(lines[3], "touch     :  1");
+    }
+
+    #[test]
+    fn edict_prints_fields_or_free_marker() {
+        let mut vm = test_vm();
+        let ent = vm.alloc_edict().unwrap();
+        vm.write_edict_field_f32(ent, 0, 3.0).unwrap();
+        assert_eq!(
+            execute(&mut vm, &format!("edict {ent}"), &no_progs),
+            vec![format!("EDICT {ent}:"), "solid            3.0".to_string()]
+        );
+
+        vm.free_edict(ent).unwrap();
+        assert_eq!(
+            execute(&mut vm, &format!("edict {ent}"), &no_progs)[1],
+            "FREE (since 0.0)"
+        );
+        assert_eq!(
+            execute(&mut vm, "edict 9999", &no_progs),
+            vec!["bad edict number 9999"]
+        );
+    }
+
+    #[test]
+    fn progs_reload_keeps_edicts_or_explains_refusal() {
+        let mut vm = test_vm();
+        let ent = vm.alloc_edict().unwrap();
+        vm.write_edict_field_f32(ent, 0, 3.0).unwrap();
+
+        let mut rebuilt = test_progs();
+        rebuilt.field_defs.insert(
+            0,
+            Definition {
+                ty: QcType::Float,
+                offset: 0,
+                name: "health".to_string(),
+                save_global: false,
+            },
+        );
+        rebuilt.field_defs[1].offset = 1;
+        rebuilt.entity_fields = 2;
+        let load = move || Ok(rebuilt.clone());
+        assert_eq!(
+            execute(&mut vm, "progs_reload", &load),
+            vec![
+                "progs reloaded: kept 1 fields and 0 globals",
+                "new fields: health"
+            ]
+        );
+        assert_eq!(vm.read_edict_field_f32(ent, 1).unwrap(), 3.0);
+
+        let mut changed = test_progs();
+        changed.field_defs[0].ty = QcType::String;
+        let load = move || Ok(changed.clone());
+        assert_eq!(
+            execute(&mut vm, "progs_reload", &load),
+            vec!["progs_reload refused: field solid changed from float to string"]
+        );
+        assert_eq!(
+            execute(&mut vm, "progs_reload", &no_progs),
+            vec!["progs_reload failed: no progs"]
+        );
+    }
+}
@@ -0,0 +1,132 @@
+// Game commands a listen server takes from its console: changing level,
+// saving and loading, and the skill and coop settings used by the next map.
+
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GameSettings {
+    pub skill: f32,
+    pub coop: f32,
+}
+
+impl Default for GameSettings {
+    fn default() -> Self {
+        Self {
+            skill: 1.0,
+            coop: 0.0,
+        }
+    }
+}
+
+impl GameSettings {
+    // Host_Map rounds skill to a whole level in 0..=3.
+    pub fn current_skill(&self) -> i32 {
+        ((self.skill + 0.5) as i32).clamp(0, 3)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HostCommand {
+    Map(String),
+    Load(String),
+    Save(String),
+    Skill(Option<f32>),
+    Coop(Option<f32>),
+}
+
+pub fn is_host_command(line: &str) -> bool {
+    parse(line).is_some()
+}
+
+// None when the line isn't a host command at all, so it can go on to the
+// server console.
+pub fn parse(line: &str) -> Option<Result<HostCommand, &'static str>> {
+    let mut parts = line.split_whitespace();
+    let cmd = parts.next()?.to_ascii_lowercase();
+    let arg = parts.next();
+    let command = match cmd.as_str() {
+        "map" => arg
+            .map(|name| HostCommand::Map(name.to_string()))
+            .ok_or("map <levelname> : start a new level"),
+        "load" => arg
+            .map(|name| HostCommand::Load(name.to_string()))
+            .ok_or("load <savename> : load a game"),
+        "save" => arg
+            .map(|name| HostCommand::Save(name.to_string()))
+            .ok_or("save <savename> : save a game"),
+        "skill" => parse_value(arg)
+            .map(HostCommand::Skill)
+            .ok_or("skill [0-3] : set the skill for the next map"),
+        "coop" => parse_value(arg)
+            .map(HostCommand::Coop)
+            .ok_or("coop [0|1] : set cooperative play for the next map"),
+        _ => return None,
+    };
+    Some(command)
+}
+
+fn parse_value(arg: Option<&str>) -> Option<Option<f32>> {
+    match arg {
+        None => Some(None),
+        Some(value) => value.parse::<f32>().ok().map(Some),
+    }
+}
+
+// Saves live in the game directory; a name can't climb out of it.
+pub fn savegame_path(game_dir: &Path, name: &str) -> Option<PathBuf> {
+    if name.is_empty() || name.contains("..") || name.contains(['/', '\\', ':']) {
+        return None;
+    }
+    let mut path = game_dir.join(name);
+    if path.extension().is_none() {
+        path.set_extension("sav");
+    }
+    Some(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_host_commands() {
+        assert_eq!(
+            parse("map e1m1"),
+            Some(Ok(HostCommand::Map("e1m1".to_string())))
+        );
+        assert_eq!(
+            parse("SAVE quick"),
+            Some(Ok(HostCommand::Save("quick".to_string())))
+        );
+        assert_eq!(parse("skill 3"), Some(Ok(HostCommand::Skill(Some(3.0)))));
+        assert_eq!(parse("coop"), Some(Ok(HostCommand::Coop(None))));
+        assert!(matches!(parse("load"), Some(Err(_))));
+        assert!(matches!(parse("skill hard"), Some(Err(_))));
+        assert_eq!(parse("edicts"), None);
+        assert!(!is_host_command(""));
+    }
+
+    #[test]
+    fn rounds_and_clamps_skill() {
+        let skill = |skill| GameSettings { skill, coop: 0.0 }.current_skill();
+        assert_eq!(skill(1.4), 1);
+        assert_eq!(skill(1.6), 2);
+        assert_eq!(skill(7.0), 3);
+        assert_eq!(skill(-1.0), 0);
+    }
+
+    #[test]
+    fn keeps_saves_inside_the_game_dir() {
+        let dir = Path::new("/quake/id1");
+        assert_eq!(
+            savegame_path(dir, "s0"),
+            Some(PathBuf::from("/quake/id1/s0.sav"))
+        );
+        assert_eq!(
+            savegame_path(dir, "quick.sav"),
+            Some(PathBuf::from("/quake/id1/quick.sav"))
+        );
+        assert_eq!(savegame_path(dir, "../autoexec"), None);
+        assert_eq!(savegame_path(dir, "/etc/passwd"), None);
+    }
+}
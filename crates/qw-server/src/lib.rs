@@ -0,0 +1,2781 @@
+use qw_common::{
+    A2A_ACK, A2A_ECHO, Bsp, BspCollision, BspError, CONTENTS_LAVA, CONTENTS_SLIME, CONTENTS_WATER,
+    Clc, ClientDataMessage, CvarRegistry, DataPathError, Entity, EntityDelta, EntityError,
+    EntityState, FsError, HULL1_MAXS, HULL1_MINS, Hull, InfoString, MAX_CLIENTS,
+    MAX_SERVERINFO_STRING, MoveVars, MsgReadError, MsgReader, NetSim, NetSimConfig, Netchan,
+    NetchanError, OobMessage, PF_COMMAND, PF_MSEC, PF_VELOCITY1, PF_VELOCITY2, PF_VELOCITY3,
+    PORT_SERVER, PROTOCOL_VERSION, PacketEntitiesUpdate, PlayerInfoMessage, QuakeFs, S2C_CHALLENGE,
+    S2C_CONNECTION, SERVERINFO_MAP_CHECKSUM, SU_VELOCITY1, SU_VELOCITY2, SU_VELOCITY3,
+    SU_VIEWHEIGHT, ServerData, SizeBuf, StringListChunk, SvcMessage, Transport, UPDATE_MASK,
+    UdpTransport, UserCmd, Vec3, build_out_of_band, find_game_dir, find_id1_dir,
+    hull_point_contents, locate_data_dir, netsim_cvar, out_of_band_payload, parse_entities,
+    parse_oob_message, valid_netsim_value, write_svc_message,
+};
+use qw_qc::{NUM_SPAWN_PARMS, ProgsDat, ProgsError, ProgsFlavor, SaveGame, Vm, VmError};
+use std::collections::HashMap;
+use std::env;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+mod console;
+mod host;
+mod monster;
+mod phys;
+mod qc;
+mod world;
+
+use host::HostCommand;
+pub use host::{GameSettings, is_host_command};
+use world::{MoveKind, MoveTrace};
+
+const MAX_QC_STEPS: usize = 200_000;
+// How long the server loop waits for a packet before looking at its console.
+const NET_WAIT: Duration = Duration::from_millis(50);
+// The shortest frame the world runs, and the longest one a stall may cost.
+const MIN_FRAMETIME: Duration = Duration::from_millis(30);
+const MAX_FRAMETIME: f64 = 0.1;
+
+#[derive(Clone)]
+struct ServerInfo {
+    server_count: i32,
+    game_dir: String,
+    level_name: String,
+    // What a client's prespawn checksum has to match.
+    map_checksum2: Option<u32>,
+    movevars: MoveVars,
+    sound_list: Vec<String>,
+    model_list: Vec<String>,
+    lightstyles: Vec<Option<String>>,
+}
+
+#[derive(Clone)]
+struct ServerWorld {
+    spawn_point: SpawnPoint,
+    static_entities: Vec<EntityState>,
+    static_sounds: Vec<StaticSoundInfo>,
+    player_baseline: EntityState,
+}
+
+#[derive(Clone)]
+struct StaticSoundInfo {
+    origin: Vec3,
+    sound: u8,
+    volume: u8,
+    attenuation: u8,
+}
+
+struct ClientState {
+    netchan: Netchan,
+    signon: u8,
+    last_heard: Instant,
+    userinfo: String,
+    last_frame: Instant,
+    player_origin: Vec3,
+    player_angles: Vec3,
+    player_velocity: Vec3,
+    last_cmd: UserCmd,
+    ground_z: f32,
+    last_sent_state: EntityState,
+    last_packet_sequence: Option<u32>,
+    player_hull: usize,
+    on_ground: bool,
+    // The edict the player stands on while on_ground.
+    ground_entity: usize,
+    in_water: bool,
+    water_level: u8,
+    // The player's edict, 1..=MAX_CLIENTS.
+    edict: usize,
+    // client_t.spawn_parms: none until SetNewParms has run for a new player.
+    spawn_parms: Option<[f32; NUM_SPAWN_PARMS]>,
+    // Whether PutClientInServer has run on this level.
+    spawned: bool,
+}
+
+impl ClientState {
+    fn new(qport: u16, userinfo: String, edict: usize) -> Self {
+        Self {
+            netchan: Netchan::new(qport),
+            signon: 0,
+            last_heard: Instant::now(),
+            userinfo,
+            last_frame: Instant::now(),
+            player_origin: Vec3::default(),
+            player_angles: Vec3::default(),
+            player_velocity: Vec3::default(),
+            last_cmd: UserCmd::default(),
+            ground_z: 0.0,
+            last_sent_state: EntityState::default(),
+            last_packet_sequence: None,
+            player_hull: 1,
+            on_ground: false,
+            ground_entity: 0,
+            in_water: false,
+            water_level: 0,
+            edict,
+            spawn_parms: None,
+            spawned: false,
+        }
+    }
+}
+
+struct ServerContext {
+    info: ServerInfo,
+    world: ServerWorld,
+    vm: Vm,
+    start: Instant,
+    // sv.time, which QuakeC's time follows.
+    time: f64,
+    last_physics: Instant,
+    // The players' edicts came from a savegame, so begin leaves them be.
+    loadgame: bool,
+}
+
+// Where the running progs came from, so progs_reload can read it again.
+struct ProgsSource {
+    fs: QuakeFs,
+    name: &'static str,
+    flavor: ProgsFlavor,
+}
+
+impl ProgsSource {
+    fn load(&self) -> Result<ProgsDat, ServerError> {
+        let bytes = self.fs.read(self.name).map_err(ServerError::Fs)?;
+        let progs = ProgsDat::from_bytes(&bytes).map_err(ServerError::Progs)?;
+        progs.validate_as(self.flavor).map_err(ServerError::Progs)?;
+        Ok(progs)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct MapData {
+    entities: Vec<Entity>,
+    collision: BspCollision,
+    checksum2: u32,
+}
+
+#[derive(Clone, Copy, Default)]
+struct SpawnPoint {
+    origin: Vec3,
+    angles: Vec3,
+}
+
+pub fn run() -> Result<(), ServerError> {
+    let netsim = netsim_from_args(env::args().skip(1))?;
+    let game = find_game()?;
+    let map_name = env::var("RUSTQUAKE_MAP").unwrap_or_else(|_| "start".to_string());
+    let settings = GameSettings::default();
+    let context = spawn_level(&game, &map_name, &settings, 1, 0.0)?;
+
+    let bind_addr = format!("0.0.0.0:{PORT_SERVER}");
+    let socket = UdpTransport::bind(&bind_addr).map_err(ServerError::Net)?;
+    println!("[server] listening on {bind_addr}");
+    let mut socket: Box<dyn Transport> = if netsim.is_active() {
+        println!("[server] simulating {netsim}");
+        Box::new(NetSim::new(socket, netsim))
+    } else {
+        Box::new(socket)
+    };
+    run_network(
+        &mut socket,
+        &game,
+        context,
+        settings,
+        console::spawn_stdin_reader(),
+        false,
+    )
+}
+
+// The dedicated server takes the client's --netsim-* flags to put a simulated
+// bad link in front of its socket.
+fn netsim_from_args(args: impl IntoIterator<Item = String>) -> Result<NetSimConfig, ServerError> {
+    let mut cvars = CvarRegistry::new();
+    NetSimConfig::register_cvars(&mut cvars);
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let Some(cvar) = netsim_cvar(&arg) else {
+            return Err(ServerError::BadArgument(arg));
+        };
+        match args.next() {
+            Some(value) if valid_netsim_value(&value) => cvars.set(cvar, &value),
+            _ => return Err(ServerError::BadArgument(arg)),
+        }
+    }
+    Ok(NetSimConfig::from_cvars(&cvars))
+}
+
+// A listen server for singleplayer: the level is spawned before this returns
+// so a bad map or progs is reported to the caller, then the server runs on its
+// own thread until its client hangs up or it is shut down.
+pub fn spawn_local<T>(
+    map_name: &str,
+    settings: GameSettings,
+    mut transport: T,
+) -> Result<LocalServer, ServerError>
+where
+    T: Transport + Send + 'static,
+{
+    let (commands, console_rx) = mpsc::channel();
+    let (ready_tx, ready_rx) = mpsc::sync_channel(1);
+    let map_name = map_name.to_string();
+    let thread = thread::spawn(move || {
+        let started = find_game().and_then(|game| {
+            if !game.has_map(&map_name) {
+                return Err(ServerError::MapMissing(map_name.clone()));
+            }
+            let context = spawn_level(&game, &map_name, &settings, 1, 0.0)?;
+            Ok((game, context))
+        });
+        let (game, context) = match started {
+            Ok(started) => {
+                let _ = ready_tx.send(Ok(()));
+                started
+            }
+            Err(err) => {
+                let _ = ready_tx.send(Err(err));
+                return Ok(());
+            }
+        };
+        run_network(&mut transport, &game, context, settings, console_rx, true)
+    });
+    match ready_rx.recv() {
+        Ok(Ok(())) => Ok(LocalServer {
+            commands,
+            thread: Some(thread),
+        }),
+        Ok(Err(err)) => Err(err),
+        Err(_) => Err(ServerError::Stopped),
+    }
+}
+
+pub struct LocalServer {
+    commands: mpsc::Sender<String>,
+    thread: Option<thread::JoinHandle<Result<(), ServerError>>>,
+}
+
+impl LocalServer {
+    // Runs a line on the server console, as typed at a dedicated server.
+    pub fn send_command(&self, line: &str) -> Result<(), ServerError> {
+        self.commands
+            .send(line.to_string())
+            .map_err(|_| ServerError::Stopped)
+    }
+
+    pub fn shutdown(mut self) -> Result<(), ServerError> {
+        self.stop()
+    }
+
+    fn stop(&mut self) -> Result<(), ServerError> {
+        let (commands, _) = mpsc::channel();
+        drop(std::mem::replace(&mut self.commands, commands));
+        match self.thread.take().map(thread::JoinHandle::join) {
+            Some(Ok(result)) => result,
+            Some(Err(_)) => Err(ServerError::Stopped),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for LocalServer {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
+
+struct GameData {
+    name: String,
+    dir: PathBuf,
+    progs: ProgsSource,
+}
+
+impl GameData {
+    fn has_map(&self, map_name: &str) -> bool {
+        self.progs.fs.contains(&format!("maps/{map_name}.bsp"))
+    }
+}
+
+fn find_game() -> Result<GameData, ServerError> {
+    let data_dir = locate_data_dir().map_err(ServerError::DataPath)?;
+    let game_name = env::var("RUSTQUAKE_GAME").unwrap_or_else(|_| "id1".to_string());
+    let game_dir = find_game_dir(&data_dir, &game_name)
+        .or_else(|| find_id1_dir(&data_dir))
+        .ok_or(ServerError::GameDirMissing)?;
+
+    let mut fs = QuakeFs::new();
+    fs.add_game_dir(&game_dir).map_err(ServerError::Fs)?;
+
+    let progs_name = if fs.contains("progs.dat") {
+        "progs.dat"
+    } else if fs.contains("qwprogs.dat") {
+        "qwprogs.dat"
+    } else {
+        return Err(ServerError::ProgsMissing);
+    };
+
+    let bytes = fs.read(progs_name).map_err(ServerError::Fs)?;
+    let progs = ProgsDat::from_bytes(&bytes).map_err(ServerError::Progs)?;
+    let flavor = progs.validate().map_err(ServerError::Progs)?;
+    Ok(GameData {
+        name: game_name,
+        dir: game_dir,
+        progs: ProgsSource {
+            fs,
+            name: progs_name,
+            flavor,
+        },
+    })
+}
+
+// Every level starts from a fresh VM, as SV_SpawnServer reloads the progs.
+fn spawn_level(
+    game: &GameData,
+    map_name: &str,
+    settings: &GameSettings,
+    server_count: i32,
+    serverflags: f32,
+) -> Result<ServerContext, ServerError> {
+    let progs = game.progs.load()?;
+    let mut vm = Vm::with_context(progs, qc::ServerQcContext::default());
+    qc::configure_vm(&mut vm, map_name).map_err(ServerError::Vm)?;
+    qc::set_game_mode(
+        &mut vm,
+        settings.current_skill(),
+        settings.coop,
+        serverflags,
+    )
+    .map_err(ServerError::Vm)?;
+    let mut time = 1.0;
+    qc::set_time(&mut vm, time as f32).map_err(ServerError::Vm)?;
+
+    let func_count = vm.progs().functions.len();
+    let global_count = vm.progs().globals.len();
+    println!(
+        "[server] loaded {} ({} progs) with {func_count} functions and {global_count} globals",
+        game.progs.name,
+        game.progs.flavor.name()
+    );
+    if let Err(err) = vm.call_by_name("main", MAX_QC_STEPS) {
+        println!(
+            "[server] qc main not executed: {}",
+            describe_vm_error(&vm, &err)
+        );
+    }
+
+    let map_data = load_map_data(&game.progs.fs, map_name).ok();
+    qc::set_collision(
+        &mut vm,
+        map_data.as_ref().map(|data| data.collision.clone()),
+    );
+    if let Some(data) = map_data.as_ref() {
+        let entities = &data.entities;
+        if let Err(err) = qc::apply_worldspawn(&mut vm, entities) {
+            println!("[server] qc worldspawn not applied: {err:?}");
+        }
+        if let Err(err) = vm.call_by_name("worldspawn", MAX_QC_STEPS) {
+            println!(
+                "[server] qc worldspawn not executed: {}",
+                describe_vm_error(&vm, &err)
+            );
+        }
+        if let Err(err) = qc::spawn_entities(&mut vm, entities, MAX_QC_STEPS) {
+            println!(
+                "[server] qc entity spawn failed: {}",
+                describe_vm_error(&vm, &err)
+            );
+        }
+    }
+
+    // Two frames to let everything settle, as SV_SpawnServer runs.
+    let gravity = default_movevars().gravity;
+    for _ in 0..2 {
+        let frame = phys::Frame {
+            gravity,
+            time: time as f32,
+            frametime: MAX_FRAMETIME as f32,
+        };
+        if let Err(err) = phys::run_frame(&mut vm, &frame) {
+            println!(
+                "[server] qc physics failed: {}",
+                describe_vm_error(&vm, &err)
+            );
+        }
+        time += MAX_FRAMETIME;
+    }
+
+    let qc_snapshot = qc::snapshot(&vm);
+    let mut server_info =
+        build_server_info(&game.name, map_name, server_count, qc_snapshot.clone());
+    server_info.map_checksum2 = map_data.as_ref().map(|data| data.checksum2);
+    let spawn_point = map_data
+        .as_ref()
+        .map(|data| find_spawn_point(&data.entities))
+        .unwrap_or_default();
+    let server_world = build_world_snapshot(&vm, &server_info, &qc_snapshot, spawn_point);
+    Ok(ServerContext {
+        info: server_info,
+        world: server_world,
+        vm,
+        start: Instant::now(),
+        time,
+        last_physics: Instant::now(),
+        loadgame: false,
+    })
+}
+
+#[derive(Debug)]
+pub enum ServerError {
+    DataPath(DataPathError),
+    Fs(FsError),
+    Progs(ProgsError),
+    Vm(VmError),
+    Bsp(BspError),
+    Entities(EntityError),
+    Net(std::io::Error),
+    GameDirMissing,
+    ProgsMissing,
+    MapMissing(String),
+    BadArgument(String),
+    Stopped,
+}
+
+impl std::fmt::Display for ServerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ServerError::DataPath(err) => write!(f, "data path error: {:?}", err),
+            ServerError::Fs(err) => write!(f, "fs error: {:?}", err),
+            ServerError::Progs(err) => write!(f, "progs error: {err}"),
+            ServerError::Vm(err) => write!(f, "vm error: {err}"),
+            ServerError::Bsp(err) => write!(f, "bsp error: {}", err),
+            ServerError::Entities(err) => write!(f, "entity parse error: {:?}", err),
+            ServerError::Net(err) => write!(f, "network error: {err}"),
+            ServerError::GameDirMissing => write!(f, "game directory not found"),
+            ServerError::ProgsMissing => write!(f, "progs.dat or qwprogs.dat not found"),
+            ServerError::MapMissing(name) => write!(f, "couldn't spawn server maps/{name}.bsp"),
+            ServerError::BadArgument(arg) => write!(f, "bad argument: {arg}"),
+            ServerError::Stopped => write!(f, "local server stopped"),
+        }
+    }
+}
+
+impl std::error::Error for ServerError {}
+
+fn load_map_data(fs: &QuakeFs, map_name: &str) -> Result<MapData, ServerError> {
+    let map_path = format!("maps/{map_name}.bsp");
+    let bytes = fs.read(&map_path).map_err(ServerError::Fs)?;
+    let bsp = Bsp::from_bytes(bytes).map_err(ServerError::Bsp)?;
+    let text = bsp.entities_text().map_err(ServerError::Bsp)?;
+    let entities = parse_entities(&text).map_err(ServerError::Entities)?;
+    let collision = BspCollision::from_bsp(&bsp).map_err(ServerError::Bsp)?;
+    let (_, checksum2) = bsp.map_checksums().map_err(ServerError::Bsp)?;
+    Ok(MapData {
+        entities,
+        collision,
+        checksum2,
+    })
+}
+
+fn build_server_info(
+    game_name: &str,
+    map_name: &str,
+    server_count: i32,
+    snapshot: qc::ServerQcSnapshot,
+) -> ServerInfo {
+    let mut sound_list = Vec::new();
+    sound_list.push(String::new());
+    sound_list.extend(snapshot.precache_sounds);
+    let sound_list = dedupe_case(sound_list);
+
+    let mut model_list = Vec::new();
+    model_list.push(String::new());
+    model_list.push(format!("maps/{map_name}.bsp"));
+    model_list.extend(snapshot.precache_models);
+    let model_list = dedupe_case(model_list);
+
+    ServerInfo {
+        server_count,
+        game_dir: game_name.to_string(),
+        level_name: map_name.to_string(),
+        map_checksum2: None,
+        movevars: default_movevars(),
+        sound_list,
+        model_list,
+        lightstyles: snapshot.lightstyles,
+    }
+}
+
+fn default_movevars() -> MoveVars {
+    MoveVars {
+        gravity: 800.0,
+        stopspeed: 100.0,
+        maxspeed: 320.0,
+        spectatormaxspeed: 500.0,
+        accelerate: 10.0,
+        airaccelerate: 0.0,
+        wateraccelerate: 10.0,
+        friction: 6.0,
+        waterfriction: 1.0,
+        entgravity: 1.0,
+    }
+}
+
+fn dedupe_case(list: Vec<String>) -> Vec<String> {
+    let mut seen = HashMap::new();
+    let mut out = Vec::new();
+    for item in list {
+        let key = item.to_ascii_lowercase();
+        if seen.insert(key, ()).is_none() {
+            out.push(item);
+        }
+    }
+    out
+}
+
+fn build_world_snapshot(
+    vm: &Vm,
+    server_info: &ServerInfo,
+    snapshot: &qc::ServerQcSnapshot,
+    spawn: SpawnPoint,
+) -> ServerWorld {
+    let mut static_entities = Vec::new();
+    for ent in &snapshot.static_entities {
+        if let Some(state) = qc::entity_state(vm, *ent, &server_info.model_list) {
+            static_entities.push(state);
+        }
+    }
+
+    let sound_index = build_index_map(&server_info.sound_list);
+    let mut static_sounds = Vec::new();
+    for sound in &snapshot.ambient_sounds {
+        let key = sound.sample.to_ascii_lowercase();
+        let index = sound_index.get(&key).or_else(|| {
+            key.strip_prefix("sound/")
+                .and_then(|name| sound_index.get(name))
+        });
+        let Some(index) = index else {
+            continue;
+        };
+        static_sounds.push(StaticSoundInfo {
+            origin: sound.origin,
+            sound: *index,
+            volume: clamp_u8(sound.volume * 255.0),
+            attenuation: clamp_u8(sound.attenuation * 64.0),
+        });
+    }
+
+    ServerWorld {
+        spawn_point: spawn,
+        static_entities,
+        static_sounds,
+        player_baseline: build_player_baseline(spawn, server_info),
+    }
+}
+
+fn build_index_map(list: &[String]) -> HashMap<String, u8> {
+    let mut map = HashMap::new();
+    for (index, item) in list.iter().enumerate() {
+        if index > u8::MAX as usize {
+            break;
+        }
+        map.insert(item.to_ascii_lowercase(), index as u8);
+    }
+    map
+}
+
+fn clamp_u8(value: f32) -> u8 {
+    value.round().clamp(0.0, 255.0) as u8
+}
+
+fn build_player_baseline(spawn: SpawnPoint, server_info: &ServerInfo) -> EntityState {
+    let model_index = model_index_for("progs/player.mdl", &server_info.model_list);
+    EntityState {
+        number: 1,
+        flags: 0,
+        origin: spawn.origin,
+        angles: spawn.angles,
+        modelindex: model_index as i32,
+        frame: 0,
+        colormap: 0,
+        skinnum: 0,
+        effects: 0,
+    }
+}
+
+fn model_index_for(name: &str, model_list: &[String]) -> u8 {
+    for (index, entry) in model_list.iter().enumerate() {
+        if index > u8::MAX as usize {
+            break;
+        }
+        if entry.eq_ignore_ascii_case(name) {
+            return index as u8;
+        }
+    }
+    0
+}
+
+// A listen server stops once its client or its console goes away; a dedicated
+// server keeps running when stdin closes.
+fn run_network(
+    socket: &mut dyn Transport,
+    game: &GameData,
+    mut context: ServerContext,
+    mut settings: GameSettings,
+    console_rx: mpsc::Receiver<String>,
+    listen: bool,
+) -> Result<(), ServerError> {
+    let mut rng_state = 0x1234_5678u32;
+    let mut challenges: HashMap<SocketAddr, i32> = HashMap::new();
+    let mut clients: HashMap<SocketAddr, ClientState> = HashMap::new();
+    let run_once = env::var("RUSTQUAKE_RUN_ONCE").is_ok();
+    let mut buf = [0u8; 1400];
+
+    loop {
+        match socket.recv_from(&mut buf, NET_WAIT) {
+            Ok(Some((packet, addr))) => {
+                handle_packet(
+                    socket,
+                    addr,
+                    &packet,
+                    &mut context,
+                    &mut clients,
+                    &mut challenges,
+                    &mut rng_state,
+                )
+                .map_err(ServerError::Net)?;
+            }
+            Ok(None) => {}
+            Err(err) if listen && err.kind() == std::io::ErrorKind::NotConnected => {
+                return Ok(());
+            }
+            Err(err) => return Err(ServerError::Net(err)),
+        }
+
+        run_physics(&mut context, &mut clients);
+        check_changelevel(socket, game, &mut context, &settings, &mut clients)
+            .map_err(ServerError::Net)?;
+
+        loop {
+            let line = match console_rx.try_recv() {
+                Ok(line) => line,
+                Err(mpsc::TryRecvError::Empty) => break,
+                Err(mpsc::TryRecvError::Disconnected) if listen => return Ok(()),
+                Err(mpsc::TryRecvError::Disconnected) => break,
+            };
+            let output = match host::parse(&line) {
+                Some(Ok(command)) => execute_host_command(
+                    socket,
+                    game,
+                    &mut context,
+                    &mut settings,
+                    &mut clients,
+                    command,
+                )
+                .map_err(ServerError::Net)?,
+                Some(Err(usage)) => vec![usage.to_string()],
+                None => {
+                    let load_progs = || game.progs.load().map_err(|err| err.to_string());
+                    console::execute(&mut context.vm, &line, &load_progs)
+                }
+            };
+            for line in output {
+                println!("{line}");
+            }
+        }
+
+        if run_once && context.start.elapsed() > Duration::from_millis(200) {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+// Host_ServerFrame: the world runs at most every MIN_FRAMETIME, and never
+// more than MAX_FRAMETIME at once.
+fn run_physics(context: &mut ServerContext, clients: &mut HashMap<SocketAddr, ClientState>) {
+    let elapsed = context.last_physics.elapsed();
+    if elapsed < MIN_FRAMETIME {
+        return;
+    }
+    context.last_physics = Instant::now();
+    physics_frame(context, clients, elapsed.as_secs_f64().min(MAX_FRAMETIME));
+}
+
+fn physics_frame(
+    context: &mut ServerContext,
+    clients: &mut HashMap<SocketAddr, ClientState>,
+    frametime: f64,
+) {
+    let frame = phys::Frame {
+        gravity: context.info.movevars.gravity,
+        time: context.time as f32,
+        frametime: frametime as f32,
+    };
+    if let Err(err) = phys::run_frame(&mut context.vm, &frame) {
+        println!(
+            "[server] qc physics failed: {}",
+            describe_vm_error(&context.vm, &err)
+        );
+    }
+    // Pushers carry players too.
+    for client in clients.values_mut().filter(|client| client.spawned) {
+        phys::load_player(&context.vm, client);
+    }
+    context.time += frametime;
+}
+
+// The progs called changelevel: SV_SaveSpawnparms keeps what each player
+// carries, then the next level spawns and everyone reconnects to it.
+fn check_changelevel(
+    socket: &mut dyn Transport,
+    game: &GameData,
+    context: &mut ServerContext,
+    settings: &GameSettings,
+    clients: &mut HashMap<SocketAddr, ClientState>,
+) -> Result<(), std::io::Error> {
+    let Some(map_name) = qc::take_changelevel(&mut context.vm) else {
+        return Ok(());
+    };
+    if !game.has_map(&map_name) {
+        println!("[server] {}", ServerError::MapMissing(map_name));
+        return Ok(());
+    }
+
+    for client in clients.values_mut().filter(|client| client.spawned) {
+        let vm = &mut context.vm;
+        let result = qc::set_time(vm, context.time as f32)
+            .and_then(|()| qc::call_entry(vm, "SetChangeParms", client.edict, MAX_QC_STEPS));
+        if let Err(err) = result {
+            println!(
+                "[server] qc SetChangeParms failed: {}",
+                describe_vm_error(vm, &err)
+            );
+        }
+        client.spawn_parms = Some(qc::spawn_parms(&context.vm));
+    }
+    let serverflags = qc::serverflags(&context.vm);
+    match spawn_level(
+        game,
+        &map_name,
+        settings,
+        context.info.server_count + 1,
+        serverflags,
+    ) {
+        Ok(next) => {
+            *context = next;
+            change_level(socket, clients)
+        }
+        Err(err) => {
+            println!("[server] changelevel {map_name} failed: {err}");
+            Ok(())
+        }
+    }
+}
+
+fn execute_host_command(
+    socket: &mut dyn Transport,
+    game: &GameData,
+    context: &mut ServerContext,
+    settings: &mut GameSettings,
+    clients: &mut HashMap<SocketAddr, ClientState>,
+    command: HostCommand,
+) -> Result<Vec<String>, std::io::Error> {
+    let output = match command {
+        HostCommand::Skill(None) => vec![format!("\"skill\" is \"{}\"", settings.skill)],
+        HostCommand::Skill(Some(value)) => {
+            settings.skill = value;
+            vec!["skill will be changed for the next map".to_string()]
+        }
+        HostCommand::Coop(None) => vec![format!("\"coop\" is \"{}\"", settings.coop)],
+        HostCommand::Coop(Some(value)) => {
+            settings.coop = value;
+            vec!["coop will be changed for the next map".to_string()]
+        }
+        HostCommand::Map(map_name) => {
+            if !game.has_map(&map_name) {
+                return Ok(vec![ServerError::MapMissing(map_name).to_string()]);
+            }
+            match spawn_level(
+                game,
+                &map_name,
+                settings,
+                context.info.server_count + 1,
+                0.0,
+            ) {
+                Ok(next) => {
+                    *context = next;
+                    // A new game: everyone starts over from SetNewParms.
+                    for client in clients.values_mut() {
+                        client.spawn_parms = None;
+                    }
+                    change_level(socket, clients)?;
+                    vec![format!("spawned {map_name}")]
+                }
+                Err(err) => vec![format!("map {map_name} failed: {err}")],
+            }
+        }
+        HostCommand::Save(name) => save_game(game, context, settings, clients, &name),
+        HostCommand::Load(name) => {
+            let (output, loaded) = load_game(game, settings, context.info.server_count, &name);
+            if let Some((next, spawn_parms)) = loaded {
+                *context = next;
+                for client in clients.values_mut() {
+                    client.spawn_parms = Some(spawn_parms);
+                }
+                change_level(socket, clients)?;
+            }
+            output
+        }
+    };
+    Ok(output)
+}
+
+// Sends every client back through signon for the level now running, as
+// SV_Map does with "changing" and "reconnect".
+fn change_level(
+    socket: &mut dyn Transport,
+    clients: &mut HashMap<SocketAddr, ClientState>,
+) -> Result<(), std::io::Error> {
+    for (addr, client) in clients.iter_mut() {
+        client.signon = 0;
+        client.spawned = false;
+        let messages = [
+            SvcMessage::StuffText("changing\n".to_string()),
+            SvcMessage::StuffText("reconnect\n".to_string()),
+        ];
+        send_svc_messages(socket, *addr, client, &messages)?;
+    }
+    Ok(())
+}
+
+fn save_game(
+    game: &GameData,
+    context: &mut ServerContext,
+    settings: &GameSettings,
+    clients: &HashMap<SocketAddr, ClientState>,
+    name: &str,
+) -> Vec<String> {
+    if clients.len() > 1 {
+        return vec!["Can't save multiplayer games.".to_string()];
+    }
+    let Some(path) = host::savegame_path(&game.dir, name) else {
+        return vec!["Relative pathnames are not allowed.".to_string()];
+    };
+    // The player's edict is where the movement code would keep them.
+    let client = clients.values().next();
+    if let Some(client) = client
+        && client.signon >= 3
+    {
+        let _ = qc::set_entity_origin(
+            &mut context.vm,
+            client.edict,
+            client.player_origin,
+            client.player_angles,
+        );
+    }
+    let save = SaveGame {
+        comment: context.info.level_name.clone(),
+        // Host_Savegame_f keeps the parms the player entered the level with.
+        spawn_parms: client
+            .and_then(|client| client.spawn_parms)
+            .unwrap_or([0.0; NUM_SPAWN_PARMS]),
+        skill: settings.current_skill(),
+        mapname: context.info.level_name.clone(),
+        time: context.time as f32,
+        lightstyles: context
+            .info
+            .lightstyles
+            .iter()
+            .map(|style| style.clone().unwrap_or_default())
+            .collect(),
+    };
+    let text = context.vm.write_savegame(&save);
+    match std::fs::write(&path, text) {
+        Ok(()) => vec![format!("Saving game to {}...", path.display())],
+        Err(err) => vec![format!("ERROR: couldn't write {}: {err}", path.display())],
+    }
+}
+
+fn load_game(
+    game: &GameData,
+    settings: &mut GameSettings,
+    server_count: i32,
+    name: &str,
+) -> (Vec<String>, Option<(ServerContext, [f32; NUM_SPAWN_PARMS])>) {
+    let Some(path) = host::savegame_path(&game.dir, name) else {
+        return (
+            vec!["Relative pathnames are not allowed.".to_string()],
+            None,
+        );
+    };
+    let mut output = vec![format!("Loading game from {}...", path.display())];
+    let text = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(err) => {
+            output.push(format!("ERROR: couldn't open: {err}"));
+            return (output, None);
+        }
+    };
+    let header = match SaveGame::parse_header(&text) {
+        Ok(header) => header,
+        Err(err) => {
+            output.push(format!("ERROR: {err}"));
+            return (output, None);
+        }
+    };
+    if !game.has_map(&header.mapname) {
+        output.push(ServerError::MapMissing(header.mapname).to_string());
+        return (output, None);
+    }
+    settings.skill = header.skill as f32;
+    let mut context = match spawn_level(game, &header.mapname, settings, server_count + 1, 0.0) {
+        Ok(context) => context,
+        Err(err) => {
+            output.push(format!("ERROR: {err}"));
+            return (output, None);
+        }
+    };
+    if let Err(err) = context.vm.read_savegame(&text) {
+        output.push(format!("ERROR: {err}"));
+        return (output, None);
+    }
+
+    for (slot, style) in context.info.lightstyles.iter_mut().enumerate() {
+        if let Some(value) = header.lightstyles.get(slot) {
+            *style = (!value.is_empty()).then(|| value.clone());
+        }
+    }
+    if let Some(player) = qc::entity_state(&context.vm, 1, &context.info.model_list) {
+        context.world.spawn_point = SpawnPoint {
+            origin: player.origin,
+            angles: player.angles,
+        };
+        context.world.player_baseline =
+            build_player_baseline(context.world.spawn_point, &context.info);
+    }
+    context.time = header.time as f64;
+    context.loadgame = true;
+    (output, Some((context, header.spawn_parms)))
+}
+
+fn handle_packet(
+    socket: &mut dyn Transport,
+    addr: SocketAddr,
+    packet: &[u8],
+    context: &mut ServerContext,
+    clients: &mut HashMap<SocketAddr, ClientState>,
+    challenges: &mut HashMap<SocketAddr, i32>,
+    rng_state: &mut u32,
+) -> Result<(), std::io::Error> {
+    if let Some(payload) = out_of_band_payload(packet) {
+        return handle_oob(socket, addr, payload, clients, challenges, rng_state);
+    }
+
+    handle_inband(socket, addr, packet, context, clients)
+}
+
+fn handle_oob(
+    socket: &mut dyn Transport,
+    addr: SocketAddr,
+    payload: &[u8],
+    clients: &mut HashMap<SocketAddr, ClientState>,
+    challenges: &mut HashMap<SocketAddr, i32>,
+    rng_state: &mut u32,
+) -> Result<(), std::io::Error> {
+    let text = String::from_utf8_lossy(payload);
+    let trimmed = text.trim_matches(|ch| ch == '\0' || ch == '\n' || ch == '\r');
+
+    if trimmed.starts_with("getchallenge") {
+        let challenge = next_challenge(rng_state);
+        challenges.insert(addr, challenge);
+        let mut reply = Vec::new();
+        reply.push(S2C_CHALLENGE);
+        reply.extend_from_slice(challenge.to_string().as_bytes());
+        reply.push(0);
+        let packet = build_out_of_band(&reply);
+        socket.send_to(packet, addr)?;
+        return Ok(());
+    }
+
+    if trimmed.starts_with("connect") {
+        if let Some(connect) = parse_connect(trimmed) {
+            if connect.protocol == PROTOCOL_VERSION {
+                let matches = challenges.get(&addr).copied() == Some(connect.challenge);
+                // A client reconnecting from the same address gives up its old slot.
+                let edict = (1..=MAX_CLIENTS).find(|&edict| {
+                    !clients
+                        .iter()
+                        .any(|(other, client)| *other != addr && client.edict == edict)
+                });
+                if matches && let Some(edict) = edict {
+                    let client = ClientState::new(connect.qport, connect.userinfo, edict);
+                    clients.insert(addr, client);
+                    let packet = build_out_of_band(&[S2C_CONNECTION, 0]);
+                    socket.send_to(packet, addr)?;
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    if let Some(msg) = parse_oob_message(payload) {
+        match msg {
+            OobMessage::Ping => {
+                let packet = build_out_of_band(&[A2A_ACK, b'\n']);
+                socket.send_to(packet, addr)?;
+            }
+            OobMessage::Echo(value) => {
+                let mut reply = Vec::new();
+                reply.push(A2A_ECHO);
+                reply.extend_from_slice(value.as_bytes());
+                reply.push(0);
+                let packet = build_out_of_band(&reply);
+                socket.send_to(packet, addr)?;
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+fn handle_inband(
+    socket: &mut dyn Transport,
+    addr: SocketAddr,
+    packet: &[u8],
+    context: &mut ServerContext,
+    clients: &mut HashMap<SocketAddr, ClientState>,
+) -> Result<(), std::io::Error> {
+    let Some(client) = clients.get_mut(&addr) else {
+        return Ok(());
+    };
+    client.last_heard = Instant::now();
+    // Stale and duplicated packets are dropped, as Netchan_Process does.
+    let payload = match client.netchan.process_packet(packet, true) {
+        Ok(payload) => payload,
+        Err(NetchanError::OutOfOrder) => return Ok(()),
+        Err(err) => return Err(netchan_to_io(err)),
+    };
+    let mut reader = MsgReader::new(payload);
+    let mut pending = None;
+    let mut saw_move = false;
+
+    while reader.remaining() > 0 {
+        let cmd = match pending.take() {
+            Some(value) => value,
+            None => reader.read_u8().map_err(msg_to_io)?,
+        };
+        let Ok(clc) = Clc::try_from(cmd) else {
+            break;
+        };
+
+        match clc {
+            Clc::Nop => {}
+            Clc::StringCmd => {
+                let text = reader.read_string().map_err(msg_to_io)?;
+                handle_string_cmd(socket, addr, client, context, &text)?;
+            }
+            Clc::Move => {
+                let parsed = parse_move(&mut reader).map_err(msg_to_io)?;
+                run_client_move(context, client, parsed.cmd);
+                client.last_cmd = parsed.cmd;
+                client.player_angles = parsed.cmd.angles;
+                pending = parsed.next;
+                saw_move = true;
+            }
+            Clc::Delta => {
+                let _ = reader.read_u8().map_err(msg_to_io)?;
+            }
+            Clc::TMove => {
+                if reader.remaining() >= 6 {
+                    let _ = reader.read_i16().map_err(msg_to_io)?;
+                    let _ = reader.read_i16().map_err(msg_to_io)?;
+                    let _ = reader.read_i16().map_err(msg_to_io)?;
+                } else {
+                    break;
+                }
+            }
+            _ => break,
+        }
+    }
+
+    if saw_move {
+        maybe_send_frame(socket, addr, client, context)?;
+    }
+
+    Ok(())
+}
+
+fn handle_string_cmd(
+    socket: &mut dyn Transport,
+    addr: SocketAddr,
+    client: &mut ClientState,
+    context: &mut ServerContext,
+    text: &str,
+) -> Result<(), std::io::Error> {
+    let server_info = &context.info;
+    let server_world = &context.world;
+    let mut parts = text.split_whitespace();
+    let Some(cmd) = parts.next() else {
+        return Ok(());
+    };
+
+    match cmd {
+        "new" => {
+            send_serverdata(socket, addr, client, server_info)?;
+        }
+        "soundlist" => {
+            let _ = parts.next();
+            // The client names the slot before the first one it wants.
+            let start = parts
+                .next()
+                .and_then(|value| value.parse::<u8>().ok())
+                .unwrap_or(0)
+                .saturating_add(1);
+            send_soundlist(socket, addr, client, server_info, start)?;
+        }
+        "modellist" => {
+            let _ = parts.next();
+            // The client names the slot before the first one it wants.
+            let start = parts
+                .next()
+                .and_then(|value| value.parse::<u8>().ok())
+                .unwrap_or(0)
+                .saturating_add(1);
+            send_modellist(socket, addr, client, server_info, start)?;
+        }
+        "prespawn" => {
+            send_prespawn(socket, addr, client, server_world)?;
+        }
+        "spawn" => {
+            send_spawn(socket, addr, client, server_info, server_world)?;
+        }
+        "begin" => {
+            send_begin(context, client);
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+fn send_serverdata(
+    socket: &mut dyn Transport,
+    addr: SocketAddr,
+    client: &mut ClientState,
+    server_info: &ServerInfo,
+) -> Result<(), std::io::Error> {
+    let data = ServerData {
+        protocol: PROTOCOL_VERSION,
+        server_count: server_info.server_count,
+        game_dir: server_info.game_dir.clone(),
+        player_num: 0,
+        spectator: false,
+        level_name: server_info.level_name.clone(),
+        movevars: server_info.movevars,
+    };
+    let mut messages = Vec::new();
+    messages.push(SvcMessage::ServerData(data));
+    // SV_New_f hands over the whole serverinfo before any lists.
+    let info = server_info_string(server_info);
+    messages.push(SvcMessage::StuffText(format!(
+        "fullserverinfo \"{}\"\n",
+        info.as_str()
+    )));
+    messages.push(SvcMessage::SignonNum(1));
+    for (index, style) in server_info.lightstyles.iter().enumerate() {
+        if let Some(value) = style {
+            messages.push(SvcMessage::LightStyle {
+                style: index as u8,
+                value: value.clone(),
+            });
+        }
+    }
+    client.signon = 1;
+    send_svc_messages(socket, addr, client, &messages)
+}
+
+fn send_soundlist(
+    socket: &mut dyn Transport,
+    addr: SocketAddr,
+    client: &mut ClientState,
+    server_info: &ServerInfo,
+    start: u8,
+) -> Result<(), std::io::Error> {
+    let chunk = build_list_chunk(&server_info.sound_list, start);
+    send_svc_messages(socket, addr, client, &[SvcMessage::SoundList(chunk)])
+}
+
+fn send_modellist(
+    socket: &mut dyn Transport,
+    addr: SocketAddr,
+    client: &mut ClientState,
+    server_info: &ServerInfo,
+    start: u8,
+) -> Result<(), std::io::Error> {
+    let chunk = build_list_chunk(&server_info.model_list, start);
+    send_svc_messages(socket, addr, client, &[SvcMessage::ModelList(chunk)])
+}
+
+fn send_prespawn(
+    socket: &mut dyn Transport,
+    addr: SocketAddr,
+    client: &mut ClientState,
+    server_world: &ServerWorld,
+) -> Result<(), std::io::Error> {
+    let mut messages = Vec::new();
+    messages.push(SvcMessage::SignonNum(2));
+    for entity in &server_world.static_entities {
+        messages.push(SvcMessage::SpawnStatic(*entity));
+    }
+    for sound in &server_world.static_sounds {
+        messages.push(SvcMessage::SpawnStaticSound {
+            origin: sound.origin,
+            sound: sound.sound,
+            volume: sound.volume,
+            attenuation: sound.attenuation,
+        });
+    }
+    messages.push(SvcMessage::SpawnBaseline {
+        entity: server_world.player_baseline.number as u16,
+        baseline: server_world.player_baseline,
+    });
+    messages.push(SvcMessage::StuffText("cmd spawn 0 0\n".to_string()));
+    client.signon = 2;
+    send_svc_messages(socket, addr, client, &messages)
+}
+
+fn send_spawn(
+    socket: &mut dyn Transport,
+    addr: SocketAddr,
+    client: &mut ClientState,
+    server_info: &ServerInfo,
+    server_world: &ServerWorld,
+) -> Result<(), std::io::Error> {
+    client.player_origin = server_world.spawn_point.origin;
+    client.player_angles = server_world.spawn_point.angles;
+    client.player_velocity = Vec3::default();
+    client.last_cmd = UserCmd {
+        angles: server_world.spawn_point.angles,
+        ..UserCmd::default()
+    };
+    client.ground_z = server_world.spawn_point.origin.z;
+    client.last_sent_state = server_world.player_baseline;
+    client.last_packet_sequence = None;
+    client.player_hull = 1;
+    client.on_ground = true;
+    client.in_water = false;
+    client.water_level = 0;
+
+    let mut messages = Vec::new();
+    messages.push(SvcMessage::SignonNum(3));
+    messages.extend(server_info_messages(server_info));
+    messages.push(SvcMessage::UpdateUserInfo {
+        slot: 0,
+        user_id: 1,
+        userinfo: client.userinfo.clone(),
+    });
+    messages.push(SvcMessage::SetView {
+        entity: client.edict as u16,
+    });
+    messages.push(SvcMessage::ClientData(default_client_data()));
+    messages.push(SvcMessage::StuffText("cmd begin\n".to_string()));
+    client.signon = 3;
+    send_svc_messages(socket, addr, client, &messages)
+}
+
+// SV_Begin_f: a player new to the level is put in it by the progs, with the
+// spawn parms they arrived with.
+fn send_begin(context: &mut ServerContext, client: &mut ClientState) {
+    client.signon = 3;
+    if client.spawned {
+        return;
+    }
+    client.spawned = true;
+    if let Err(err) = begin_client(context, client) {
+        println!(
+            "[server] qc client spawn failed: {}",
+            describe_vm_error(&context.vm, &err)
+        );
+    }
+}
+
+fn begin_client(context: &mut ServerContext, client: &mut ClientState) -> Result<(), VmError> {
+    let vm = &mut context.vm;
+    qc::set_time(vm, context.time as f32)?;
+    let parms = match client.spawn_parms {
+        Some(parms) => parms,
+        None => {
+            qc::call_entry(vm, "SetNewParms", 0, MAX_QC_STEPS)?;
+            qc::spawn_parms(vm)
+        }
+    };
+    client.spawn_parms = Some(parms);
+    qc::set_client_parms(vm, client.edict, parms);
+    if context.loadgame {
+        // The savegame already put the player where they were.
+        phys::load_player(vm, client);
+        return Ok(());
+    }
+
+    phys::store_player(vm, client)?;
+    qc::set_spawn_parms(vm, &parms)?;
+    qc::call_entry(vm, "ClientConnect", client.edict, MAX_QC_STEPS)?;
+    qc::call_entry(vm, "PutClientInServer", client.edict, MAX_QC_STEPS)?;
+    phys::load_player(vm, client);
+    let fields = qc::fields_from_context(vm);
+    client.player_angles = qc::read_field_vec(vm, client.edict, fields.angles);
+    Ok(())
+}
+
+// SV_RunCmd: the progs see the command either side of the move.
+fn run_client_move(context: &mut ServerContext, client: &mut ClientState, cmd: UserCmd) {
+    let frame = phys::Frame {
+        gravity: context.info.movevars.gravity,
+        time: context.time as f32,
+        frametime: cmd.msec as f32 * 0.001,
+    };
+    let vm = &mut context.vm;
+    if client.spawned
+        && let Err(err) = phys::client_pre_think(vm, client, &cmd, &frame)
+    {
+        println!(
+            "[server] qc PlayerPreThink failed: {}",
+            describe_vm_error(vm, &err)
+        );
+    }
+    // The player moves through the world and everything solid in it but
+    // themselves.
+    let edict = client.edict;
+    let trace = |start, end| {
+        world::move_box(
+            vm,
+            start,
+            HULL1_MINS,
+            HULL1_MAXS,
+            end,
+            MoveKind::Normal,
+            edict,
+        )
+    };
+    apply_move(
+        &context.info.movevars,
+        qc::collision(vm),
+        trace,
+        client,
+        cmd,
+    );
+    if client.spawned
+        && let Err(err) = phys::client_post_think(vm, client, &frame)
+    {
+        println!(
+            "[server] qc PlayerPostThink failed: {}",
+            describe_vm_error(vm, &err)
+        );
+    }
+}
+
+fn send_svc_messages(
+    socket: &mut dyn Transport,
+    addr: SocketAddr,
+    client: &mut ClientState,
+    messages: &[SvcMessage],
+) -> Result<(), std::io::Error> {
+    let mut buf = SizeBuf::new(2048);
+    for message in messages {
+        write_svc_message(&mut buf, message).map_err(sizebuf_to_io)?;
+    }
+    client
+        .netchan
+        .queue_reliable(buf.as_slice())
+        .map_err(netchan_to_io)?;
+    let packet = client
+        .netchan
+        .build_packet(&[], false)
+        .map_err(netchan_to_io)?;
+    socket.send_to(packet, addr)?;
+    Ok(())
+}
+
+fn send_unreliable_messages(
+    socket: &mut dyn Transport,
+    addr: SocketAddr,
+    client: &mut ClientState,
+    messages: &[SvcMessage],
+) -> Result<(), std::io::Error> {
+    let mut buf = SizeBuf::new(2048);
+    for message in messages {
+        write_svc_message(&mut buf, message).map_err(sizebuf_to_io)?;
+    }
+    let packet = client
+        .netchan
+        .build_packet(buf.as_slice(), false)
+        .map_err(netchan_to_io)?;
+    socket.send_to(packet, addr)?;
+    Ok(())
+}
+
+fn build_list_chunk(list: &[String], start: u8) -> StringListChunk {
+    // Slot 0 is the empty placeholder, which would read as the end of the list.
+    let start_index = (start as usize).max(1);
+    let start = start_index.min(u8::MAX as usize) as u8;
+    if start_index >= list.len() {
+        return StringListChunk {
+            start,
+            items: Vec::new(),
+            next: 0,
+        };
+    }
+
+    let max_items = 64usize;
+    let items: Vec<String> = list
+        .iter()
+        .skip(start_index)
+        .take(max_items)
+        .cloned()
+        .collect();
+    let next_index = start_index + items.len();
+    let next = if next_index < list.len() && next_index <= u8::MAX as usize {
+        next_index as u8
+    } else {
+        0
+    };
+
+    StringListChunk { start, items, next }
+}
+
+fn find_spawn_point(entities: &[Entity]) -> SpawnPoint {
+    let candidates = ["info_player_start", "info_player_deathmatch"];
+    for name in candidates {
+        if let Some(entity) = entities.iter().find(|entity| {
+            entity
+                .get("classname")
+                .map(|value| value.eq_ignore_ascii_case(name))
+                .unwrap_or(false)
+        }) {
+            return spawn_from_entity(entity);
+        }
+    }
+    SpawnPoint::default()
+}
+
+fn spawn_from_entity(entity: &Entity) -> SpawnPoint {
+    let origin = entity
+        .get("origin")
+        .and_then(parse_vec3)
+        .unwrap_or_default();
+    let angles = entity
+        .get("angles")
+        .and_then(parse_vec3)
+        .or_else(|| {
+            entity
+                .get("angle")
+                .and_then(|value| value.trim().parse::<f32>().ok())
+                .map(|yaw| Vec3::new(0.0, yaw, 0.0))
+        })
+        .unwrap_or_default();
+    SpawnPoint { origin, angles }
+}
+
+fn parse_vec3(value: &str) -> Option<Vec3> {
+    let mut iter = value
+        .split(|ch: char| ch == ' ' || ch == '\t')
+        .filter(|part| !part.is_empty());
+    let x = iter.next()?.parse::<f32>().ok()?;
+    let y = iter.next()?.parse::<f32>().ok()?;
+    let z = iter.next()?.parse::<f32>().ok()?;
+    Some(Vec3::new(x, y, z))
+}
+
+fn server_info_pairs(server_info: &ServerInfo) -> Vec<(&'static str, String)> {
+    let mut pairs = vec![
+        ("hostname", "RustQuake".to_string()),
+        ("map", server_info.level_name.clone()),
+        ("maxclients", "1".to_string()),
+    ];
+    if let Some(checksum2) = server_info.map_checksum2 {
+        pairs.push((SERVERINFO_MAP_CHECKSUM, checksum2.to_string()));
+    }
+    pairs
+}
+
+fn server_info_messages(server_info: &ServerInfo) -> Vec<SvcMessage> {
+    server_info_pairs(server_info)
+        .into_iter()
+        .map(|(key, value)| SvcMessage::ServerInfo {
+            key: key.to_string(),
+            value,
+        })
+        .collect()
+}
+
+fn server_info_string(server_info: &ServerInfo) -> InfoString {
+    let mut info = InfoString::new(MAX_SERVERINFO_STRING);
+    for (key, value) in server_info_pairs(server_info) {
+        let _ = if key.starts_with('*') {
+            info.set_star(key, &value)
+        } else {
+            info.set(key, &value)
+        };
+    }
+    info
+}
+
+fn default_client_data() -> ClientDataMessage {
+    ClientDataMessage {
+        bits: 0,
+        view_height: 22,
+        ideal_pitch: 0,
+        punch_angle: Vec3::default(),
+        velocity: Vec3::default(),
+        items: 0,
+        onground: false,
+        inwater: false,
+        weapon_frame: 0,
+        armor: 0,
+        weapon: 0,
+        health: 100,
+        ammo: 0,
+        ammo_counts: [0; 4],
+        active_weapon: 0,
+    }
+}
+
+fn build_client_data(client: &ClientState) -> ClientDataMessage {
+    let mut data = default_client_data();
+    data.bits = SU_VIEWHEIGHT | SU_VELOCITY1 | SU_VELOCITY2 | SU_VELOCITY3;
+    data.velocity = client.player_velocity;
+    data.onground = client.on_ground;
+    data.inwater = client.in_water;
+    data
+}
+
+fn delta_from_sequence(seq: u32) -> u8 {
+    (seq & UPDATE_MASK as u32) as u8
+}
+
+fn is_water_contents(contents: i32) -> bool {
+    matches!(contents, CONTENTS_WATER | CONTENTS_SLIME | CONTENTS_LAVA)
+}
+
+fn water_level_for(origin: Vec3, hull: &Hull<'_>) -> u8 {
+    let foot = Vec3::new(origin.x, origin.y, origin.z + hull.clip_mins.z + 1.0);
+    let mid = Vec3::new(
+        origin.x,
+        origin.y,
+        origin.z + (hull.clip_mins.z + hull.clip_maxs.z) * 0.5,
+    );
+    let head = Vec3::new(origin.x, origin.y, origin.z + hull.clip_maxs.z - 1.0);
+    water_level_for_points(foot, mid, head, |pos| {
+        hull_point_contents(hull, hull.firstclipnode, pos)
+    })
+}
+
+fn water_level_for_points<F>(foot: Vec3, mid: Vec3, head: Vec3, contents: F) -> u8
+where
+    F: Fn(Vec3) -> i32,
+{
+    let mut level = 0;
+    if is_water_contents(contents(foot)) {
+        level = 1;
+        if is_water_contents(contents(mid)) {
+            level = 2;
+            if is_water_contents(contents(head)) {
+                level = 3;
+            }
+        }
+    }
+    level
+}
+
+fn maybe_send_frame(
+    socket: &mut dyn Transport,
+    addr: SocketAddr,
+    client: &mut ClientState,
+    context: &ServerContext,
+) -> Result<(), std::io::Error> {
+    if client.last_frame.elapsed() < Duration::from_millis(50) {
+        return Ok(());
+    }
+    // A client still signing on gets a bare packet instead, as in
+    // SV_SendClientMessages, so acks keep flowing and a lost reliable goes out
+    // again.
+    if client.signon < 3 {
+        send_unreliable_messages(socket, addr, client, &[])?;
+        client.last_frame = Instant::now();
+        return Ok(());
+    }
+
+    let server_time = context.time as f32;
+    let player_state = player_state_for_client(&context.world, client);
+    let delta = entity_delta_between(&client.last_sent_state, &player_state);
+    let delta_from = client.last_packet_sequence.map(delta_from_sequence);
+    let outgoing_seq = client.netchan.outgoing_sequence();
+    let update = PacketEntitiesUpdate {
+        delta_from,
+        entities: delta.into_iter().collect(),
+    };
+    let client_data = build_client_data(client);
+    let info = build_player_info(0, client);
+    let messages = [
+        SvcMessage::Time(server_time),
+        SvcMessage::SetAngle(client.player_angles),
+        SvcMessage::ClientData(client_data),
+        SvcMessage::PlayerInfo(info),
+        SvcMessage::PacketEntities(update),
+    ];
+    send_unreliable_messages(socket, addr, client, &messages)?;
+    client.last_sent_state = player_state;
+    client.last_packet_sequence = Some(outgoing_seq);
+    client.last_frame = Instant::now();
+    Ok(())
+}
+
+fn player_state_for_client(server_world: &ServerWorld, client: &ClientState) -> EntityState {
+    let mut state = server_world.player_baseline;
+    state.origin = client.player_origin;
+    state.angles = client.player_angles;
+    state
+}
+
+fn build_player_info(num: u8, client: &ClientState) -> PlayerInfoMessage {
+    let cmd = client.last_cmd;
+    let flags = (PF_COMMAND | PF_MSEC | PF_VELOCITY1 | PF_VELOCITY2 | PF_VELOCITY3) as u16;
+    PlayerInfoMessage {
+        num,
+        flags,
+        origin: client.player_origin,
+        frame: 0,
+        msec: Some(cmd.msec),
+        command: Some(cmd),
+        velocity: [
+            clamp_i16(client.player_velocity.x),
+            clamp_i16(client.player_velocity.y),
+            clamp_i16(client.player_velocity.z),
+        ],
+        model_index: None,
+        skin_num: None,
+        effects: None,
+        weapon_frame: None,
+    }
+}
+
+fn entity_delta_between(from: &EntityState, to: &EntityState) -> Option<EntityDelta> {
+    let mut origin = [None; 3];
+    let mut angles = [None; 3];
+
+    if from.origin.x != to.origin.x {
+        origin[0] = Some(to.origin.x);
+    }
+    if from.origin.y != to.origin.y {
+        origin[1] = Some(to.origin.y);
+    }
+    if from.origin.z != to.origin.z {
+        origin[2] = Some(to.origin.z);
+    }
+    if from.angles.x != to.angles.x {
+        angles[0] = Some(to.angles.x);
+    }
+    if from.angles.y != to.angles.y {
+        angles[1] = Some(to.angles.y);
+    }
+    if from.angles.z != to.angles.z {
+        angles[2] = Some(to.angles.z);
+    }
+
+    let model_index = (from.modelindex != to.modelindex).then(|| clamp_entity_u8(to.modelindex));
+    let frame = (from.frame != to.frame).then(|| clamp_entity_u8(to.frame));
+    let colormap = (from.colormap != to.colormap).then(|| clamp_entity_u8(to.colormap));
+    let skin_num = (from.skinnum != to.skinnum).then(|| clamp_entity_u8(to.skinnum));
+    let effects = (from.effects != to.effects).then(|| clamp_entity_u8(to.effects));
+
+    if origin.iter().all(Option::is_none)
+        && angles.iter().all(Option::is_none)
+        && model_index.is_none()
+        && frame.is_none()
+        && colormap.is_none()
+        && skin_num.is_none()
+        && effects.is_none()
+    {
+        return None;
+    }
+
+    Some(EntityDelta {
+        number: to.number.max(0).min(u16::MAX as i32) as u16,
+        remove: false,
+        flags: 0,
+        model_index: model_index.flatten(),
+        frame: frame.flatten(),
+        colormap: colormap.flatten(),
+        skin_num: skin_num.flatten(),
+        effects: effects.flatten(),
+        origin,
+        angles,
+        solid: false,
+    })
+}
+
+fn clamp_entity_u8(value: i32) -> Option<u8> {
+    if value < 0 {
+        return Some(0);
+    }
+    if value > u8::MAX as i32 {
+        return Some(u8::MAX);
+    }
+    Some(value as u8)
+}
+
+fn clamp_i16(value: f32) -> i16 {
+    value.round().clamp(i16::MIN as f32, i16::MAX as f32) as i16
+}
+
+fn apply_move(
+    movevars: &MoveVars,
+    collision: Option<&BspCollision>,
+    trace: impl Fn(Vec3, Vec3) -> MoveTrace,
+    client: &mut ClientState,
+    cmd: UserCmd,
+) {
+    if cmd.msec == 0 {
+        client.player_velocity = Vec3::default();
+        return;
+    }
+
+    let dt = cmd.msec as f32 / 1000.0;
+    let (forward, right, up) = angles_to_vectors(cmd.angles);
+    let wish = Vec3::new(
+        forward.x * cmd.forwardmove as f32
+            + right.x * cmd.sidemove as f32
+            + up.x * cmd.upmove as f32,
+        forward.y * cmd.forwardmove as f32
+            + right.y * cmd.sidemove as f32
+            + up.y * cmd.upmove as f32,
+        forward.z * cmd.forwardmove as f32
+            + right.z * cmd.sidemove as f32
+            + up.z * cmd.upmove as f32,
+    );
+
+    let mut velocity = client.player_velocity;
+    velocity = apply_friction(velocity, movevars.friction, dt);
+    velocity = apply_accel(velocity, wish, movevars.maxspeed, movevars.accelerate, dt);
+    velocity.z -= movevars.gravity * movevars.entgravity * dt;
+
+    let start = client.player_origin;
+    let end = Vec3::new(
+        client.player_origin.x + velocity.x * dt,
+        client.player_origin.y + velocity.y * dt,
+        client.player_origin.z + velocity.z * dt,
+    );
+
+    let mut on_ground = false;
+    let mut ground_entity = 0;
+    let first = trace(start, end);
+    let (endpos, end_velocity) = if first.trace.fraction < 1.0 {
+        let normal = first.trace.plane.normal;
+        if normal.z > 0.7 {
+            on_ground = true;
+            ground_entity = first.ent.unwrap_or(0);
+        }
+        let slide_vel = slide_velocity(velocity, normal);
+        let remaining = (1.0 - first.trace.fraction) * dt;
+        let slide_end = Vec3::new(
+            first.trace.endpos.x + slide_vel.x * remaining,
+            first.trace.endpos.y + slide_vel.y * remaining,
+            first.trace.endpos.z + slide_vel.z * remaining,
+        );
+        let second = trace(first.trace.endpos, slide_end);
+        if second.trace.fraction < 1.0 && second.trace.plane.normal.z > 0.7 {
+            on_ground = true;
+            ground_entity = second.ent.unwrap_or(0);
+        }
+        (second.trace.endpos, slide_vel)
+    } else {
+        (first.trace.endpos, velocity)
+    };
+
+    client.player_velocity = end_velocity;
+    client.player_origin = endpos;
+    if client.player_origin.z <= client.ground_z {
+        client.player_origin.z = client.ground_z;
+        on_ground = true;
+    }
+    if on_ground && client.player_velocity.z < 0.0 {
+        client.player_velocity.z = 0.0;
+    }
+    client.on_ground = on_ground;
+    client.ground_entity = ground_entity;
+    if let Some(hull) = collision.and_then(|world| world.hull(0, client.player_hull)) {
+        let level = water_level_for(client.player_origin, &hull);
+        client.water_level = level;
+        client.in_water = level > 0;
+    } else {
+        client.water_level = 0;
+        client.in_water = false;
+    }
+}
+
+fn vec_length(vec: Vec3) -> f32 {
+    vec.dot(vec).sqrt()
+}
+
+fn apply_friction(mut velocity: Vec3, friction: f32, dt: f32) -> Vec3 {
+    let horizontal = Vec3::new(velocity.x, velocity.y, 0.0);
+    let speed = vec_length(horizontal);
+    if speed < 1.0 {
+        velocity.x = 0.0;
+        velocity.y = 0.0;
+        return velocity;
+    }
+    let drop = speed * friction * dt;
+    let new_speed = (speed - drop).max(0.0);
+    if new_speed > 0.0 {
+        let scale = new_speed / speed;
+        velocity.x *= scale;
+        velocity.y *= scale;
+    } else {
+        velocity.x = 0.0;
+        velocity.y = 0.0;
+    }
+    velocity
+}
+
+fn apply_accel(velocity: Vec3, wish: Vec3, maxspeed: f32, accel: f32, dt: f32) -> Vec3 {
+    let wish_speed = vec_length(wish);
+    if wish_speed == 0.0 {
+        return velocity;
+    }
+    let wish_dir = wish.scale(1.0 / wish_speed);
+    let capped = wish_speed.min(maxspeed);
+    let current = velocity.dot(wish_dir);
+    let add_speed = capped - current;
+    if add_speed <= 0.0 {
+        return velocity;
+    }
+    let accel_speed = (accel * dt * capped).min(add_speed);
+    Vec3::new(
+        velocity.x + wish_dir.x * accel_speed,
+        velocity.y + wish_dir.y * accel_speed,
+        velocity.z + wish_dir.z * accel_speed,
+    )
+}
+
+fn slide_velocity(velocity: Vec3, normal: Vec3) -> Vec3 {
+    let backoff = velocity.dot(normal);
+    Vec3::new(
+        velocity.x - normal.x * backoff,
+        velocity.y - normal.y * backoff,
+        velocity.z - normal.z * backoff,
+    )
+}
+
+fn angles_to_vectors(angles: Vec3) -> (Vec3, Vec3, Vec3) {
+    let pitch = angles.x.to_radians();
+    let yaw = angles.y.to_radians();
+    let cp = pitch.cos();
+    let sp = pitch.sin();
+    let cy = yaw.cos();
+    let sy = yaw.sin();
+
+    let forward = Vec3::new(cp * cy, cp * sy, -sp);
+    let right = Vec3::new(-sy, cy, 0.0);
+    let up = Vec3::new(0.0, 0.0, 1.0);
+    (forward, right, up)
+}
+
+struct MoveParseResult {
+    cmd: UserCmd,
+    next: Option<u8>,
+}
+
+fn parse_move(reader: &mut MsgReader) -> Result<MoveParseResult, MsgReadError> {
+    let _checksum = reader.read_u8()?;
+    let _lost = reader.read_u8()?;
+    let base = UserCmd::default();
+    let cmd0 = reader.read_delta_usercmd(&base)?;
+    let cmd1 = reader.read_delta_usercmd(&cmd0)?;
+    let cmd2 = reader.read_delta_usercmd(&cmd1)?;
+
+    let next = if reader.remaining() > 0 {
+        let next = reader.read_u8()?;
+        if next == Clc::Delta as u8 {
+            let _ = reader.read_u8()?;
+            None
+        } else {
+            Some(next)
+        }
+    } else {
+        None
+    };
+    Ok(MoveParseResult { cmd: cmd2, next })
+}
+
+fn netchan_to_io(err: NetchanError) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::Other, format!("netchan: {err:?}"))
+}
+
+fn msg_to_io(err: MsgReadError) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::Other, format!("message: {err:?}"))
+}
+
+fn sizebuf_to_io(err: qw_common::SizeBufError) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::Other, format!("sizebuf: {err:?}"))
+}
+
+fn next_challenge(state: &mut u32) -> i32 {
+    *state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+    (*state & 0x7FFF_FFFF) as i32
+}
+
+#[derive(Debug, Clone)]
+struct ConnectInfo {
+    protocol: i32,
+    qport: u16,
+    challenge: i32,
+    userinfo: String,
+}
+
+fn parse_connect(text: &str) -> Option<ConnectInfo> {
+    let quote_start = text.find('"')?;
+    let quote_end = text.rfind('"')?;
+    if quote_end <= quote_start {
+        return None;
+    }
+    let userinfo = text[quote_start + 1..quote_end].to_string();
+    let head = &text[..quote_start];
+    let mut parts = head.split_whitespace();
+    let cmd = parts.next()?;
+    if cmd != "connect" {
+        return None;
+    }
+    let protocol = parts.next()?.parse::<i32>().ok()?;
+    let qport = parts.next()?.parse::<u16>().ok()?;
+    let challenge = parts.next()?.parse::<i32>().ok()?;
+    Some(ConnectInfo {
+        protocol,
+        qport,
+        challenge,
+        userinfo,
+    })
+}
+
+fn describe_vm_error(vm: &Vm, err: &VmError) -> String {
+    match err {
+        VmError::StepLimit {
+            statement,
+            function,
+        } => {
+            let name = vm
+                .progs()
+                .functions
+                .get(*function as usize)
+                .map(|func| func.name.as_str())
+                .unwrap_or("unknown");
+            let stmt = vm.progs().statements.get(*statement as usize).copied();
+            let op = stmt.map(|value| value.op).unwrap_or(0);
+            let (a, b, c) = stmt
+                .map(|value| (value.a, value.b, value.c))
+                .unwrap_or((0, 0, 0));
+            format!(
+                "step limit at {name} (fn {function}, statement {statement}, op {op}, a {a}, b {b}, c {c})"
+            )
+        }
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use qw_common::{
+        BSP_VERSION, CONTENTS_EMPTY, CONTENTS_SOLID, HEADER_LUMPS, HULL1_MAXS, HULL1_MINS,
+        LUMP_CLIPNODES, LUMP_ENTITIES, LUMP_LEAFS, LUMP_MODELS, LUMP_NODES, LUMP_PLANES,
+        MAX_MAP_HULLS, Trace, trace_hull,
+    };
+
+    fn assert_close(actual: f32, expected: f32) {
+        let eps = 0.01;
+        assert!(
+            (actual - expected).abs() < eps,
+            "expected {expected}, got {actual}"
+        );
+    }
+
+    #[test]
+    fn finds_spawn_point_from_entities() {
+        let text = r#"
+{
+"classname" "info_player_deathmatch"
+"origin" "10 20 30"
+"angles" "0 180 0"
+}
+{
+"classname" "info_player_start"
+"origin" "1 2 3"
+"angle" "90"
+}
+"#;
+        let entities = parse_entities(text).unwrap();
+        let spawn = find_spawn_point(&entities);
+        assert_eq!(spawn.origin, Vec3::new(1.0, 2.0, 3.0));
+        assert_eq!(spawn.angles, Vec3::new(0.0, 90.0, 0.0));
+    }
+
+    #[test]
+    fn parses_move_last_command_and_next() {
+        let base = UserCmd::default();
+        let cmd0 = UserCmd {
+            msec: 1,
+            angles: Vec3::new(10.0, 20.0, 30.0),
+            forwardmove: 100,
+            sidemove: -50,
+            upmove: 0,
+            buttons: 1,
+            impulse: 0,
+        };
+        let cmd1 = UserCmd {
+            msec: 2,
+            angles: Vec3::new(15.0, 25.0, 35.0),
+            forwardmove: 110,
+            sidemove: -40,
+            upmove: 5,
+            buttons: 3,
+            impulse: 1,
+        };
+        let cmd2 = UserCmd {
+            msec: 3,
+            angles: Vec3::new(20.0, 30.0, 40.0),
+            forwardmove: 120,
+            sidemove: -30,
+            upmove: 10,
+            buttons: 2,
+            impulse: 0,
+        };
+
+        let mut buf = SizeBuf::new(128);
+        buf.write_u8(0).unwrap();
+        buf.write_u8(0).unwrap();
+        buf.write_delta_usercmd(&base, &cmd0).unwrap();
+        buf.write_delta_usercmd(&cmd0, &cmd1).unwrap();
+        buf.write_delta_usercmd(&cmd1, &cmd2).unwrap();
+        buf.write_u8(Clc::StringCmd as u8).unwrap();
+
+        let mut reader = MsgReader::new(buf.as_slice());
+        let parsed = parse_move(&mut reader).unwrap();
+        assert_eq!(parsed.cmd.msec, cmd2.msec);
+        assert_eq!(parsed.cmd.forwardmove, cmd2.forwardmove);
+        assert_eq!(parsed.cmd.sidemove, cmd2.sidemove);
+        assert_eq!(parsed.cmd.upmove, cmd2.upmove);
+        assert_eq!(parsed.cmd.buttons, cmd2.buttons);
+        assert_eq!(parsed.cmd.impulse, cmd2.impulse);
+        let angle_eps = 0.01;
+        assert!((parsed.cmd.angles.x - cmd2.angles.x).abs() < angle_eps);
+        assert!((parsed.cmd.angles.y - cmd2.angles.y).abs() < angle_eps);
+        assert!((parsed.cmd.angles.z - cmd2.angles.z).abs() < angle_eps);
+        assert_eq!(parsed.next, Some(Clc::StringCmd as u8));
+    }
+
+    #[test]
+    fn angles_to_vectors_basic_axes() {
+        let (forward, right, up) = angles_to_vectors(Vec3::new(0.0, 0.0, 0.0));
+        assert_close(forward.x, 1.0);
+        assert_close(forward.y, 0.0);
+        assert_close(forward.z, 0.0);
+        assert_close(right.x, 0.0);
+        assert_close(right.y, 1.0);
+        assert_close(right.z, 0.0);
+        assert_close(up.x, 0.0);
+        assert_close(up.y, 0.0);
+        assert_close(up.z, 1.0);
+    }
+
+    #[test]
+    fn apply_move_advances_origin_and_velocity() {
+        let movevars = MoveVars {
+            gravity: 800.0,
+            stopspeed: 100.0,
+            maxspeed: 320.0,
+            spectatormaxspeed: 500.0,
+            accelerate: 10.0,
+            airaccelerate: 0.0,
+            wateraccelerate: 10.0,
+            friction: 6.0,
+            waterfriction: 1.0,
+            entgravity: 1.0,
+        };
+        let mut client = ClientState::new(0, "\\name\\tester".to_string(), 1);
+        client.player_origin = Vec3::default();
+        let cmd = UserCmd {
+            msec: 100,
+            angles: Vec3::new(0.0, 0.0, 0.0),
+            forwardmove: 100,
+            sidemove: 0,
+            upmove: 0,
+            buttons: 0,
+            impulse: 0,
+        };
+        let open = |_, end| MoveTrace {
+            trace: Trace {
+                endpos: end,
+                ..Trace::default()
+            },
+            ent: None,
+        };
+        apply_move(&movevars, None, open, &mut client, cmd);
+        assert_close(client.player_velocity.x, 100.0);
+        assert_close(client.player_origin.x, 10.0);
+    }
+
+    #[test]
+    fn slide_velocity_removes_normal_component() {
+        let velocity = Vec3::new(10.0, 5.0, 0.0);
+        let normal = Vec3::new(1.0, 0.0, 0.0);
+        let slid = slide_velocity(velocity, normal);
+        assert_close(slid.x, 0.0);
+        assert_close(slid.y, 5.0);
+        assert_close(slid.z, 0.0);
+    }
+
+    #[test]
+    fn water_level_counts_layers() {
+        let foot = Vec3::new(0.0, 0.0, 0.0);
+        let mid = Vec3::new(0.0, 0.0, 1.0);
+        let head = Vec3::new(0.0, 0.0, 2.0);
+        let contents = |pos: Vec3| {
+            if pos.z < 2.0 {
+                CONTENTS_WATER
+            } else {
+                CONTENTS_EMPTY
+            }
+        };
+        assert_eq!(water_level_for_points(foot, mid, head, contents), 2);
+    }
+
+    #[test]
+    fn trace_hull_blocks_against_world_plane() {
+        let collision = build_test_collision();
+        let hull = collision.hull(0, 1).unwrap();
+        let trace = trace_hull(&hull, Vec3::new(128.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0));
+        assert!(trace.fraction < 1.0);
+    }
+
+    #[test]
+    fn level_change_sends_clients_back_through_signon() {
+        let (mut socket, client_end) = qw_common::loopback_pair();
+        let mut clients = HashMap::new();
+        let mut client = ClientState::new(27001, "\\name\\tester".to_string(), 1);
+        client.signon = 3;
+        clients.insert(qw_common::LOOPBACK_ADDR, client);
+
+        change_level(&mut socket, &mut clients).unwrap();
+        assert_eq!(clients[&qw_common::LOOPBACK_ADDR].signon, 0);
+
+        let packet = client_end.recv().unwrap().expect("reliable sent");
+        let mut chan = Netchan::new(27001);
+        let payload = chan.process_packet(&packet, false).unwrap();
+        let mut reader = MsgReader::new(payload);
+        let messages = qw_common::parse_svc_stream(&mut reader).unwrap();
+        assert_eq!(
+            messages,
+            [
+                SvcMessage::StuffText("changing\n".to_string()),
+                SvcMessage::StuffText("reconnect\n".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn serverdata_publishes_the_map_checksum() {
+        let (mut socket, client_end) = qw_common::loopback_pair();
+        let mut client = ClientState::new(27001, "\\name\\tester".to_string(), 1);
+        let mut info = build_server_info("id1", "start", 1, qc::ServerQcSnapshot::default());
+        info.map_checksum2 = Some(1234);
+        send_serverdata(&mut socket, qw_common::LOOPBACK_ADDR, &mut client, &info).unwrap();
+
+        let packet = client_end.recv().unwrap().expect("reliable sent");
+        let mut chan = Netchan::new(27001);
+        let payload = chan.process_packet(&packet, false).unwrap();
+        let mut reader = MsgReader::new(payload);
+        let messages = qw_common::parse_svc_stream(&mut reader).unwrap();
+        assert_eq!(
+            messages[1],
+            SvcMessage::StuffText(
+                "fullserverinfo \"\\hostname\\RustQuake\\map\\start\\maxclients\\1\\*map_checksum2\\1234\"\n"
+                    .to_string()
+            )
+        );
+    }
+
+    // Just enough QuakeC for a player to walk into a trigger_changelevel.
+    const TRIGGER_QC: &str = r#"
+float SOLID_TRIGGER = 1;
+float SOLID_SLIDEBOX = 3;
+.string map;
+float frames, thinks, prethinks, postthinks, spawned_with;
+
+void(entity e, vector o) setorigin = #2;
+void(entity e, vector min, vector max) setsize = #4;
+entity() spawn = #14;
+entity(entity start, .string fld, string match) find = #18;
+void(string level) changelevel = #70;
+
+void() main = {};
+void() StartFrame = { frames = frames + 1; };
+void() PlayerPreThink = { prethinks = prethinks + 1; };
+void() PlayerPostThink = { postthinks = postthinks + 1; };
+void() ClientKill = {};
+void() ClientConnect = {};
+void() ClientDisconnect = {};
+void() SetNewParms = { parm1 = 7; };
+void() SetChangeParms = { parm1 = 42; };
+void() PutClientInServer = {
+    local entity spot;
+    spawned_with = parm1;
+    self.classname = "player";
+    self.solid = SOLID_SLIDEBOX;
+    setsize(self, '-16 -16 -24', '16 16 32');
+    spot = find(world, classname, "info_player_start");
+    setorigin(self, spot.origin);
+};
+
+void() timer_think = {
+    thinks = thinks + 1;
+    self.nextthink = time + 0.5;
+};
+void() worldspawn = {
+    local entity timer;
+    timer = spawn();
+    timer.think = timer_think;
+    timer.nextthink = time + 0.1;
+};
+void() info_player_start = {};
+void() changelevel_touch = {
+    if (other.classname != "player")
+        return;
+    changelevel(self.map);
+};
+void() trigger_changelevel = {
+    self.solid = SOLID_TRIGGER;
+    setsize(self, '0 -32 -32', '32 32 32');
+    self.touch = changelevel_touch;
+};
+"#;
+
+    fn write_trigger_game(dir: &std::path::Path) -> GameData {
+        let start = concat!(
+            "{\n\"classname\" \"worldspawn\"\n}\n",
+            "{\n\"classname\" \"info_player_start\"\n\"origin\" \"-200 0 24\"\n}\n",
+            "{\n\"classname\" \"trigger_changelevel\"\n\"map\" \"next\"\n}\n",
+        );
+        let next = concat!(
+            "{\n\"classname\" \"worldspawn\"\n}\n",
+            "{\n\"classname\" \"info_player_start\"\n\"origin\" \"-100 0 24\"\n}\n",
+        );
+        write_game(
+            dir,
+            TRIGGER_QC,
+            &[
+                ("start", build_test_map(start)),
+                ("next", build_test_map(next)),
+            ],
+        )
+    }
+
+    // The progs compiled from qc over NetQuake's system defs, and the maps.
+    fn write_game(dir: &std::path::Path, qc: &str, maps: &[(&str, Vec<u8>)]) -> GameData {
+        let type_name = |ty| match ty {
+            qw_qc::QcType::Function => "void()",
+            other => other.name(),
+        };
+        let mut defs = String::new();
+        for (name, ty) in ProgsFlavor::NetQuake.system_globals() {
+            defs.push_str(&format!("{} {name};\n", type_name(ty)));
+        }
+        defs.push_str("void end_sys_globals;\n");
+        for (name, ty) in ProgsFlavor::NetQuake.system_fields() {
+            defs.push_str(&format!(".{} {name};\n", type_name(ty)));
+        }
+        defs.push_str("void end_sys_fields;\n");
+        let mut compiler = qw_qcc::Compiler::new();
+        compiler.compile_file("defs.qc", &defs).unwrap();
+        compiler.compile_file("game.qc", qc).unwrap();
+        let progs = compiler.finish().unwrap().progs;
+
+        let game_dir = dir.join("id1");
+        std::fs::create_dir_all(game_dir.join("maps")).unwrap();
+        std::fs::write(game_dir.join("progs.dat"), progs.to_bytes()).unwrap();
+        for (name, bsp) in maps {
+            std::fs::write(game_dir.join(format!("maps/{name}.bsp")), bsp).unwrap();
+        }
+
+        let mut fs = QuakeFs::new();
+        fs.add_game_dir(&game_dir).unwrap();
+        GameData {
+            name: "id1".to_string(),
+            dir: game_dir,
+            progs: ProgsSource {
+                fs,
+                name: "progs.dat",
+                flavor: ProgsFlavor::NetQuake,
+            },
+        }
+    }
+
+    const MOVE_QC: &str = r#"
+float SOLID_BBOX = 2;
+float SOLID_SLIDEBOX = 3;
+float SOLID_BSP = 4;
+float MOVETYPE_STEP = 4;
+float MOVETYPE_PUSH = 7;
+float FL_ONGROUND = 512;
+entity monster, crate, plat, rider;
+float floor_fraction, floor_z, crate_hit, floor_contents, air_contents;
+float dropped, bottom, walked, blocked_walk, found, blocks;
+
+void(entity e, vector o) setorigin = #2;
+void(entity e, string m) setmodel = #3;
+void(entity e, vector min, vector max) setsize = #4;
+void(vector v1, vector v2, float nomonsters, entity forent) traceline = #16;
+entity(vector org, float rad) findradius = #22;
+float(float yaw, float dist) walkmove = #32;
+float() droptofloor = #34;
+float(entity e) checkbottom = #40;
+float(vector v) pointcontents = #41;
+
+void() main = {};
+void() StartFrame = {};
+void() PlayerPreThink = {};
+void() PlayerPostThink = {};
+void() ClientKill = {};
+void() ClientConnect = {};
+void() ClientDisconnect = {};
+void() SetNewParms = {};
+void() SetChangeParms = {};
+void() PutClientInServer = {};
+void() worldspawn = {};
+void() monster_test = {
+    self.solid = SOLID_SLIDEBOX;
+    self.movetype = MOVETYPE_STEP;
+    setsize(self, '-16 -16 -24', '16 16 40');
+    monster = self;
+};
+void() crate_test = {
+    self.solid = SOLID_BBOX;
+    setsize(self, '-16 -16 0', '16 16 32');
+    crate = self;
+};
+void() ceiling_test = {
+    self.solid = SOLID_BBOX;
+    setsize(self, '-16 -16 0', '16 16 8');
+};
+void() rider_test = {
+    self.solid = SOLID_BBOX;
+    self.movetype = MOVETYPE_STEP;
+    self.flags = FL_ONGROUND;
+    setsize(self, '-16 -16 0', '16 16 32');
+    rider = self;
+};
+void() plat_blocked = { blocks = blocks + 1; };
+void() func_plat = {
+    self.solid = SOLID_BSP;
+    self.movetype = MOVETYPE_PUSH;
+    setmodel(self, self.model);
+    self.blocked = plat_blocked;
+    plat = self;
+};
+
+void() probe = {
+    traceline('0 0 100', '0 0 -100', 0, monster);
+    floor_fraction = trace_fraction;
+    floor_z = trace_endpos * '0 0 1';
+    traceline('0 0 16', '200 0 16', 0, monster);
+    crate_hit = trace_ent == crate;
+    floor_contents = pointcontents('0 0 -8');
+    air_contents = pointcontents('0 0 8');
+    found = findradius('100 0 16', 20) == crate;
+
+    self = monster;
+    dropped = droptofloor();
+    bottom = checkbottom(monster);
+    walked = walkmove(0, 10);
+    blocked_walk = walkmove(0, 60);
+};
+void() start_plat = {
+    rider.groundentity = plat;
+    plat.velocity = '0 0 100';
+    plat.nextthink = plat.ltime + 100;
+};
+"#;
+
+    const MOVE_ENTITIES: &str = concat!(
+        "{\n\"classname\" \"worldspawn\"\n}\n",
+        "{\n\"classname\" \"monster_test\"\n\"origin\" \"0 0 100\"\n}\n",
+        "{\n\"classname\" \"crate_test\"\n\"origin\" \"100 0 0\"\n}\n",
+        "{\n\"classname\" \"func_plat\"\n\"model\" \"*1\"\n\"origin\" \"300 0 16\"\n}\n",
+        "{\n\"classname\" \"rider_test\"\n\"origin\" \"300 0 16\"\n}\n",
+        "{\n\"classname\" \"ceiling_test\"\n\"origin\" \"300 0 60\"\n}\n",
+    );
+
+    #[test]
+    fn movement_builtins_trace_against_the_world_and_entities() {
+        let dir = temp_dir();
+        let game = write_game(&dir, MOVE_QC, &[("start", build_floor_map(MOVE_ENTITIES))]);
+        let mut context = spawn_level(&game, "start", &GameSettings::default(), 1, 0.0).unwrap();
+        context.vm.call_by_name("probe", MAX_QC_STEPS).unwrap();
+        let vm = &context.vm;
+        assert_close(qc_global(vm, "floor_fraction"), 0.5);
+        // Traces stop just short of what they hit.
+        assert!((0.0..1.0).contains(&qc_global(vm, "floor_z")));
+        assert_eq!(qc_global(vm, "crate_hit"), 1.0);
+        assert_eq!(qc_global(vm, "floor_contents"), CONTENTS_SOLID as f32);
+        assert_eq!(qc_global(vm, "air_contents"), CONTENTS_EMPTY as f32);
+        assert_eq!(qc_global(vm, "found"), 1.0);
+
+        // The monster lands on the floor, takes a step, and can't walk into
+        // the crate.
+        assert_eq!(qc_global(vm, "dropped"), 1.0);
+        assert_eq!(qc_global(vm, "bottom"), 1.0);
+        assert_eq!(qc_global(vm, "walked"), 1.0);
+        assert_eq!(qc_global(vm, "blocked_walk"), 0.0);
+        let fields = qc::fields_from_context(vm);
+        let monster = qc_global(vm, "monster") as usize;
+        let origin = qc::read_field_vec(vm, monster, fields.origin);
+        assert_close(origin.x, 10.0);
+        assert!((24.0..25.0).contains(&origin.z));
+
+        // Players are stopped by it too.
+        let mut client = ClientState::new(27001, "\\name\\tester".to_string(), 1);
+        client.player_origin = Vec3::new(50.0, 0.0, 24.0);
+        let cmd = UserCmd {
+            msec: 100,
+            forwardmove: 400,
+            ..UserCmd::default()
+        };
+        let trace = |start, end| {
+            world::move_box(vm, start, HULL1_MINS, HULL1_MAXS, end, MoveKind::Normal, 1)
+        };
+        for _ in 0..10 {
+            apply_move(
+                &default_movevars(),
+                qc::collision(vm),
+                trace,
+                &mut client,
+                cmd,
+            );
+        }
+        assert!(client.player_origin.x > 60.0 && client.player_origin.x <= 68.0);
+        assert!(client.on_ground);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn pushers_carry_riders_and_stop_when_blocked() {
+        let dir = temp_dir();
+        let game = write_game(&dir, MOVE_QC, &[("start", build_floor_map(MOVE_ENTITIES))]);
+        let mut context = spawn_level(&game, "start", &GameSettings::default(), 1, 0.0).unwrap();
+        context.vm.call_by_name("start_plat", MAX_QC_STEPS).unwrap();
+        let height = |context: &ServerContext, name| {
+            let vm = &context.vm;
+            let ent = qc_global(vm, name) as usize;
+            qc::read_field_vec(vm, ent, qc::fields_from_context(vm).origin).z
+        };
+
+        // The plat lifts what stands on it.
+        physics_frame(&mut context, &mut HashMap::new(), 0.1);
+        assert_close(height(&context, "plat"), 26.0);
+        assert_close(height(&context, "rider"), 26.0);
+        assert_eq!(qc_global(&context.vm, "blocks"), 0.0);
+
+        // Until the rider would be crushed against the crate above.
+        physics_frame(&mut context, &mut HashMap::new(), 0.1);
+        assert_close(height(&context, "plat"), 26.0);
+        assert_close(height(&context, "rider"), 26.0);
+        assert_eq!(qc_global(&context.vm, "blocks"), 1.0);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    fn temp_dir() -> PathBuf {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        env::temp_dir().join(format!("rustquake-test-{}-{nanos}", std::process::id()))
+    }
+
+    fn qc_global(vm: &Vm, name: &str) -> f32 {
+        vm.read_global_f32(qc::global_offset(vm, name).unwrap())
+            .unwrap()
+    }
+
+    #[test]
+    fn walking_into_a_trigger_changes_level() {
+        let dir = temp_dir();
+        let game = write_trigger_game(&dir);
+        let settings = GameSettings::default();
+        let mut context = spawn_level(&game, "start", &settings, 1, 0.0).unwrap();
+        // The settle frames ran StartFrame and the timer's think.
+        assert_eq!(qc_global(&context.vm, "frames"), 2.0);
+        assert!(qc_global(&context.vm, "thinks") > 0.0);
+
+        let (mut socket, _client_end) = qw_common::loopback_pair();
+        let addr = qw_common::LOOPBACK_ADDR;
+        let mut clients = HashMap::new();
+        clients.insert(
+            addr,
+            ClientState::new(27001, "\\name\\tester".to_string(), 1),
+        );
+        for cmd in ["spawn", "begin"] {
+            let client = clients.get_mut(&addr).unwrap();
+            handle_string_cmd(&mut socket, addr, client, &mut context, cmd).unwrap();
+        }
+        assert_eq!(qc_global(&context.vm, "spawned_with"), 7.0);
+        assert_eq!(clients[&addr].player_origin, Vec3::new(-200.0, 0.0, 24.0));
+
+        let cmd = UserCmd {
+            msec: 100,
+            forwardmove: 400,
+            ..UserCmd::default()
+        };
+        for _ in 0..50 {
+            if context.info.level_name == "next" {
+                break;
+            }
+            let client = clients.get_mut(&addr).unwrap();
+            run_client_move(&mut context, client, cmd);
+            physics_frame(&mut context, &mut clients, 0.1);
+            check_changelevel(&mut socket, &game, &mut context, &settings, &mut clients).unwrap();
+        }
+        assert_eq!(context.info.level_name, "next");
+        assert_eq!(context.info.server_count, 2);
+        let client = clients.get_mut(&addr).unwrap();
+        assert_eq!(client.signon, 0);
+        assert_eq!(client.spawn_parms.map(|parms| parms[0]), Some(42.0));
+
+        // The parms SetChangeParms left follow the player onto the next level.
+        for cmd in ["spawn", "begin"] {
+            handle_string_cmd(&mut socket, addr, client, &mut context, cmd).unwrap();
+        }
+        assert_eq!(qc_global(&context.vm, "spawned_with"), 42.0);
+        assert_eq!(client.player_origin, Vec3::new(-100.0, 0.0, 24.0));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn savegames_keep_the_players_spawn_parms() {
+        let dir = temp_dir();
+        let game = write_trigger_game(&dir);
+        let mut settings = GameSettings::default();
+        let mut context = spawn_level(&game, "start", &settings, 1, 0.0).unwrap();
+        let (mut socket, _client_end) = qw_common::loopback_pair();
+        let addr = qw_common::LOOPBACK_ADDR;
+        let mut client = ClientState::new(27001, "\\name\\tester".to_string(), 1);
+        client.spawn_parms = Some([3.0; NUM_SPAWN_PARMS]);
+        for cmd in ["spawn", "begin"] {
+            handle_string_cmd(&mut socket, addr, &mut client, &mut context, cmd).unwrap();
+        }
+        let clients = HashMap::from([(addr, client)]);
+
+        let output = save_game(&game, &mut context, &settings, &clients, "s0");
+        assert!(output[0].starts_with("Saving game"), "{output:?}");
+        let text = std::fs::read_to_string(game.dir.join("s0.sav")).unwrap();
+        assert_eq!(
+            SaveGame::parse_header(&text).unwrap().spawn_parms,
+            [3.0; NUM_SPAWN_PARMS]
+        );
+        let (_, loaded) = load_game(&game, &mut settings, 1, "s0");
+        let (context, spawn_parms) = loaded.unwrap();
+        assert!(context.loadgame);
+        assert_eq!(spawn_parms, [3.0; NUM_SPAWN_PARMS]);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn reads_netsim_flags() {
+        let args = ["--netsim-latency", "120", "--netsim-loss", "5"].map(String::from);
+        let config = netsim_from_args(args).unwrap();
+        assert_eq!(config.latency, Duration::from_millis(120));
+        assert_eq!(config.loss, 5.0);
+        assert_eq!(netsim_from_args([]).unwrap(), NetSimConfig::default());
+        for args in [
+            &["--netsim-loss"][..],
+            &["--netsim-loss", "-5"],
+            &["--port"],
+        ] {
+            let args = args.iter().map(|arg| arg.to_string());
+            assert!(matches!(
+                netsim_from_args(args),
+                Err(ServerError::BadArgument(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn list_chunks_skip_the_empty_slot() {
+        let list = vec![String::new(), "maps/e1m1.bsp".to_string()];
+        let chunk = build_list_chunk(&list, 1);
+        assert_eq!(chunk.start, 1);
+        assert_eq!(chunk.items, ["maps/e1m1.bsp"]);
+        assert_eq!(chunk.next, 0);
+        assert!(build_list_chunk(&list[..1], 1).items.is_empty());
+    }
+
+    #[test]
+    fn delta_from_sequence_wraps_update_mask() {
+        assert_eq!(delta_from_sequence(0), 0);
+        let wrap = UPDATE_MASK as u32 + 1;
+        assert_eq!(delta_from_sequence(wrap), 0);
+        assert_eq!(delta_from_sequence(wrap + 1), 1);
+    }
+
+    fn build_test_collision() -> BspCollision {
+        let bsp = Bsp::from_bytes(build_test_map("")).unwrap();
+        let collision = BspCollision::from_bsp(&bsp).unwrap();
+        let hull1 = collision.hull(0, 1).unwrap();
+        assert_eq!(hull1.clip_mins, HULL1_MINS);
+        assert_eq!(hull1.clip_maxs, HULL1_MAXS);
+        collision
+    }
+
+    // A map that is solid beyond x = 64, with the given entity lump.
+    fn build_test_map(entities: &str) -> Vec<u8> {
+        build_plane_map(entities, 0, 64.0, 64.0, &[])
+    }
+
+    // A floor at z = 0, and a plat as submodel 1.
+    fn build_floor_map(entities: &str) -> Vec<u8> {
+        let plat = (Vec3::new(-32.0, -32.0, -16.0), Vec3::new(32.0, 32.0, 0.0));
+        build_plane_map(entities, 2, 0.0, 24.0, &[plat])
+    }
+
+    // A map split by one axial plane at dist: solid beyond it along x, and
+    // below it along y or z. The clipping hulls split at clip_dist instead.
+    // Submodels share the world's hulls, wherever their entity puts them.
+    fn build_plane_map(
+        entities: &str,
+        axis: usize,
+        dist: f32,
+        clip_dist: f32,
+        submodels: &[(Vec3, Vec3)],
+    ) -> Vec<u8> {
+        let mut lumps = vec![Vec::new(); HEADER_LUMPS];
+        lumps[LUMP_ENTITIES] = format!("{entities}\0").into_bytes();
+
+        let mut planes = Vec::new();
+        for plane_dist in [dist, clip_dist] {
+            for component in 0..3 {
+                push_f32(&mut planes, if component == axis { 1.0 } else { 0.0 });
+            }
+            push_f32(&mut planes, plane_dist);
+            push_i32(&mut planes, axis as i32);
+        }
+        lumps[LUMP_PLANES] = planes;
+
+        let (front, back) = if axis == 0 {
+            (CONTENTS_SOLID, CONTENTS_EMPTY)
+        } else {
+            (CONTENTS_EMPTY, CONTENTS_SOLID)
+        };
+        let mut clipnodes = Vec::new();
+        push_i32(&mut clipnodes, 1);
+        push_i16(&mut clipnodes, front as i16);
+        push_i16(&mut clipnodes, back as i16);
+        lumps[LUMP_CLIPNODES] = clipnodes;
+
+        // Leaf 0 is solid and leaf 1 empty.
+        let leaf = |contents| if contents == CONTENTS_SOLID { -1 } else { -2 };
+        let mut nodes = Vec::new();
+        push_i32(&mut nodes, 0);
+        push_i16(&mut nodes, leaf(front));
+        push_i16(&mut nodes, leaf(back));
+        for _ in 0..6 {
+            push_i16(&mut nodes, 0);
+        }
+        push_u16(&mut nodes, 0);
+        push_u16(&mut nodes, 0);
+        lumps[LUMP_NODES] = nodes;
+
+        let mut leafs = Vec::new();
+        push_i32(&mut leafs, CONTENTS_SOLID);
+        push_i32(&mut leafs, 0);
+        for _ in 0..6 {
+            push_i16(&mut leafs, 0);
+        }
+        push_u16(&mut leafs, 0);
+        push_u16(&mut leafs, 0);
+        for _ in 0..4 {
+            push_u8(&mut leafs, 0);
+        }
+
+        push_i32(&mut leafs, CONTENTS_EMPTY);
+        push_i32(&mut leafs, 0);
+        for _ in 0..6 {
+            push_i16(&mut leafs, 0);
+        }
+        push_u16(&mut leafs, 0);
+        push_u16(&mut leafs, 0);
+        for _ in 0..4 {
+            push_u8(&mut leafs, 0);
+        }
+        lumps[LUMP_LEAFS] = leafs;
+
+        let mut models = Vec::new();
+        let world = (Vec3::default(), Vec3::default());
+        for (mins, maxs) in std::iter::once(&world).chain(submodels) {
+            for value in [mins.x, mins.y, mins.z, maxs.x, maxs.y, maxs.z] {
+                push_f32(&mut models, value);
+            }
+            for _ in 0..3 {
+                push_f32(&mut models, 0.0);
+            }
+            for _ in 0..MAX_MAP_HULLS {
+                push_i32(&mut models, 0);
+            }
+            push_i32(&mut models, 0);
+            push_i32(&mut models, 0);
+            push_i32(&mut models, 0);
+        }
+        lumps[LUMP_MODELS] = models;
+
+        build_bsp(lumps)
+    }
+
+    fn build_bsp(lumps: Vec<Vec<u8>>) -> Vec<u8> {
+        let header_size = 4 + HEADER_LUMPS * 8;
+        let mut data = Vec::new();
+        data.extend_from_slice(&BSP_VERSION.to_le_bytes());
+
+        let mut offset = header_size as u32;
+        for i in 0..HEADER_LUMPS {
+            let length = lumps[i].len() as u32;
+            data.extend_from_slice(&offset.to_le_bytes());
+            data.extend_from_slice(&length.to_le_bytes());
+            offset += length;
+        }
+
+        for payload in lumps {
+            data.extend_from_slice(&payload);
+        }
+
+        data
+    }
+
+    fn push_f32(buf: &mut Vec<u8>, value: f32) {
+        buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn push_i32(buf: &mut Vec<u8>, value: i32) {
+        buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn push_i16(buf: &mut Vec<u8>, value: i16) {
+        buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn push_u16(buf: &mut Vec<u8>, value: u16) {
+        buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn push_u8(buf: &mut Vec<u8>, value: u8) {
+        buf.push(value);
+    }
+}
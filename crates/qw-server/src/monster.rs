@@ -0,0 +1,328 @@
+// sv_move.c: monster movement. Monsters step along the ground a bit at a time
+// through walkmove and movetogoal rather than by velocity.
+//
+// A builtin can't run QuakeC, so where Quake relinks with SV_LinkEdict(ent,
+// true) the box is updated here and the trigger touches are left to
+// phys::call once the builtin's caller returns.
+
+use crate::phys::{self, FL_FLY, FL_ONGROUND, FL_PARTIALGROUND, FL_SWIM};
+use crate::qc::{self, vec_add};
+use crate::world::{self, MoveKind};
+use qw_common::{CONTENTS_EMPTY, CONTENTS_SOLID, Vec3};
+use qw_qc::{Vm, VmError};
+
+const STEPSIZE: f32 = 18.0;
+
+// SV_CheckBottom: whether the entity's box has ground under all its corners,
+// or close enough to the middle to stand on.
+pub fn check_bottom(vm: &Vm, ent: usize) -> bool {
+    let fields = qc::fields_from_context(vm);
+    let origin = qc::read_field_vec(vm, ent, fields.origin);
+    let mins = vec_add(origin, qc::read_field_vec(vm, ent, fields.mins));
+    let maxs = vec_add(origin, qc::read_field_vec(vm, ent, fields.maxs));
+    let corners = [
+        (mins.x, mins.y),
+        (mins.x, maxs.y),
+        (maxs.x, mins.y),
+        (maxs.x, maxs.y),
+    ];
+
+    // Solid world under every corner gets out easy.
+    if corners
+        .iter()
+        .all(|&(x, y)| world::point_contents(vm, Vec3::new(x, y, mins.z - 1.0)) == CONTENTS_SOLID)
+    {
+        return true;
+    }
+
+    // The midpoint must be within a step of the bottom, and the corners
+    // within a step of the midpoint.
+    let down = |x: f32, y: f32| {
+        let start = Vec3::new(x, y, mins.z);
+        let stop = Vec3::new(x, y, mins.z - 2.0 * STEPSIZE);
+        let zero = Vec3::default();
+        world::move_box(vm, start, zero, zero, stop, MoveKind::NoMonsters, ent).trace
+    };
+    let trace = down((mins.x + maxs.x) * 0.5, (mins.y + maxs.y) * 0.5);
+    if trace.fraction == 1.0 {
+        return false;
+    }
+    let mid = trace.endpos.z;
+    corners.iter().all(|&(x, y)| {
+        let trace = down(x, y);
+        trace.fraction != 1.0 && mid - trace.endpos.z <= STEPSIZE
+    })
+}
+
+// SV_movestep: tries to move the entity, stepping up and down stairs and not
+// off ledges. Flying and swimming monsters move freely instead.
+pub fn movestep(vm: &mut Vm, ent: usize, movement: Vec3, relink: bool) -> Result<bool, VmError> {
+    let fields = qc::fields_from_context(vm);
+    let old_origin = qc::read_field_vec(vm, ent, fields.origin);
+    let mins = qc::read_field_vec(vm, ent, fields.mins);
+    let maxs = qc::read_field_vec(vm, ent, fields.maxs);
+    let flags = qc::read_field_f32(vm, ent, fields.flags) as i32;
+
+    if flags & (FL_SWIM | FL_FLY) != 0 {
+        // One try closing on the enemy's height, then one straight.
+        let enemy = qc::read_field_entity(vm, ent, fields.enemy);
+        for attempt in 0..2 {
+            let mut new_origin = vec_add(old_origin, movement);
+            if attempt == 0 && enemy != 0 {
+                let dz = old_origin.z - qc::read_field_vec(vm, enemy, fields.origin).z;
+                if dz > 40.0 {
+                    new_origin.z -= 8.0;
+                }
+                if dz < 30.0 {
+                    new_origin.z += 8.0;
+                }
+            }
+            let trace = world::move_box(
+                vm,
+                old_origin,
+                mins,
+                maxs,
+                new_origin,
+                MoveKind::Normal,
+                ent,
+            )
+            .trace;
+            if trace.fraction == 1.0 {
+                // Swimmers don't leave the water.
+                if flags & FL_SWIM != 0 && world::point_contents(vm, trace.endpos) == CONTENTS_EMPTY
+                {
+                    return Ok(false);
+                }
+                phys::set_vec(vm, ent, fields.origin, trace.endpos)?;
+                if relink {
+                    link(vm, ent)?;
+                }
+                return Ok(true);
+            }
+            if enemy == 0 {
+                break;
+            }
+        }
+        return Ok(false);
+    }
+
+    // Push down from a step above the wished position.
+    let mut new_origin = vec_add(old_origin, movement);
+    new_origin.z += STEPSIZE;
+    let end = Vec3::new(new_origin.x, new_origin.y, new_origin.z - STEPSIZE * 2.0);
+    let mut trace = world::move_box(vm, new_origin, mins, maxs, end, MoveKind::Normal, ent);
+    if trace.trace.allsolid {
+        return Ok(false);
+    }
+    if trace.trace.startsolid {
+        new_origin.z -= STEPSIZE;
+        trace = world::move_box(vm, new_origin, mins, maxs, end, MoveKind::Normal, ent);
+        if trace.trace.allsolid || trace.trace.startsolid {
+            return Ok(false);
+        }
+    }
+    if trace.trace.fraction == 1.0 {
+        // A monster that had the ground pulled out from under it falls.
+        if flags & FL_PARTIALGROUND != 0 {
+            phys::set_vec(vm, ent, fields.origin, vec_add(old_origin, movement))?;
+            if relink {
+                link(vm, ent)?;
+            }
+            phys::set_f32(vm, ent, fields.flags, (flags & !FL_ONGROUND) as f32)?;
+            return Ok(true);
+        }
+        // Walked off an edge.
+        return Ok(false);
+    }
+
+    // Check point traces down for dangling corners.
+    phys::set_vec(vm, ent, fields.origin, trace.trace.endpos)?;
+    if !check_bottom(vm, ent) {
+        if flags & FL_PARTIALGROUND != 0 {
+            // Still trying to get back onto solid ground.
+            if relink {
+                link(vm, ent)?;
+            }
+            return Ok(true);
+        }
+        phys::set_vec(vm, ent, fields.origin, old_origin)?;
+        return Ok(false);
+    }
+    phys::set_f32(vm, ent, fields.flags, (flags & !FL_PARTIALGROUND) as f32)?;
+    let ground = trace.ent.unwrap_or(0);
+    phys::set_f32(vm, ent, fields.groundentity, ground as f32)?;
+    if relink {
+        link(vm, ent)?;
+    }
+    Ok(true)
+}
+
+// SV_MoveToGoal: movetogoal's step towards self.goalentity, picking a new
+// direction when the way ahead is blocked.
+pub fn move_to_goal(vm: &mut Vm, ent: usize, dist: f32) -> Result<(), VmError> {
+    let fields = qc::fields_from_context(vm);
+    let flags = qc::read_field_f32(vm, ent, fields.flags) as i32;
+    if flags & (FL_ONGROUND | FL_FLY | FL_SWIM) == 0 {
+        return Ok(());
+    }
+    let goal = qc::read_field_entity(vm, ent, fields.goalentity);
+    // The next step would hit the enemy.
+    if qc::read_field_entity(vm, ent, fields.enemy) != 0 && close_enough(vm, ent, goal, dist) {
+        return Ok(());
+    }
+    let ideal_yaw = qc::read_field_f32(vm, ent, fields.ideal_yaw);
+    if qc::rand(vm) & 3 == 1 || !step_direction(vm, ent, ideal_yaw, dist)? {
+        new_chase_dir(vm, ent, goal, dist)?;
+    }
+    Ok(())
+}
+
+// PF_changeyaw: turns towards ideal_yaw, at most yaw_speed degrees.
+pub fn change_yaw(vm: &mut Vm, ent: usize) -> Result<(), VmError> {
+    let fields = qc::fields_from_context(vm);
+    let mut angles = qc::read_field_vec(vm, ent, fields.angles);
+    let current = anglemod(angles.y);
+    let ideal = qc::read_field_f32(vm, ent, fields.ideal_yaw);
+    let speed = qc::read_field_f32(vm, ent, fields.yaw_speed);
+    if current == ideal {
+        return Ok(());
+    }
+    let mut turn = ideal - current;
+    if ideal > current {
+        if turn >= 180.0 {
+            turn -= 360.0;
+        }
+    } else if turn <= -180.0 {
+        turn += 360.0;
+    }
+    let turn = if turn > 0.0 {
+        turn.min(speed)
+    } else {
+        turn.max(-speed)
+    };
+    angles.y = anglemod(current + turn);
+    phys::set_vec(vm, ent, fields.angles, angles)
+}
+
+// SV_StepDirection: turns to face yaw and steps along it once turned most of
+// the way.
+fn step_direction(vm: &mut Vm, ent: usize, yaw: f32, dist: f32) -> Result<bool, VmError> {
+    let fields = qc::fields_from_context(vm);
+    phys::set_f32(vm, ent, fields.ideal_yaw, yaw)?;
+    change_yaw(vm, ent)?;
+
+    let radians = yaw.to_radians();
+    let movement = Vec3::new(radians.cos() * dist, radians.sin() * dist, 0.0);
+    let old_origin = qc::read_field_vec(vm, ent, fields.origin);
+    let moved = movestep(vm, ent, movement, false)?;
+    if moved {
+        let delta = qc::read_field_vec(vm, ent, fields.angles).y - yaw;
+        if delta > 45.0 && delta < 315.0 {
+            // Not turned far enough, so don't take the step.
+            phys::set_vec(vm, ent, fields.origin, old_origin)?;
+        }
+    }
+    link(vm, ent)?;
+    Ok(moved)
+}
+
+// SV_NewChaseDir
+fn new_chase_dir(vm: &mut Vm, ent: usize, enemy: usize, dist: f32) -> Result<(), VmError> {
+    let fields = qc::fields_from_context(vm);
+    let old_dir = anglemod((qc::read_field_f32(vm, ent, fields.ideal_yaw) / 45.0).trunc() * 45.0);
+    let turnaround = anglemod(old_dir - 180.0);
+    let origin = qc::read_field_vec(vm, ent, fields.origin);
+    let enemy_origin = qc::read_field_vec(vm, enemy, fields.origin);
+    let delta_x = enemy_origin.x - origin.x;
+    let delta_y = enemy_origin.y - origin.y;
+    let mut dir_x = if delta_x > 10.0 {
+        Some(0.0)
+    } else if delta_x < -10.0 {
+        Some(180.0)
+    } else {
+        None
+    };
+    let mut dir_y = if delta_y < -10.0 {
+        Some(270.0)
+    } else if delta_y > 10.0 {
+        Some(90.0)
+    } else {
+        None
+    };
+
+    // Try the direct route.
+    if let (Some(x), Some(y)) = (dir_x, dir_y) {
+        let diagonal = match (x == 0.0, y == 90.0) {
+            (true, true) => 45.0,
+            (true, false) => 315.0,
+            (false, true) => 135.0,
+            (false, false) => 215.0,
+        };
+        if diagonal != turnaround && step_direction(vm, ent, diagonal, dist)? {
+            return Ok(());
+        }
+    }
+
+    // Then the other directions, the longer way first.
+    if qc::rand(vm) & 1 != 0 || delta_y.abs() > delta_x.abs() {
+        std::mem::swap(&mut dir_x, &mut dir_y);
+    }
+    for dir in [dir_x, dir_y].into_iter().flatten() {
+        if dir != turnaround && step_direction(vm, ent, dir, dist)? {
+            return Ok(());
+        }
+    }
+
+    // There's no direct path to the enemy, so pick another direction.
+    if step_direction(vm, ent, old_dir, dist)? {
+        return Ok(());
+    }
+    let mut dirs: Vec<f32> = (0..8).map(|step| step as f32 * 45.0).collect();
+    if qc::rand(vm) & 1 == 0 {
+        dirs.reverse();
+    }
+    for dir in dirs {
+        if dir != turnaround && step_direction(vm, ent, dir, dist)? {
+            return Ok(());
+        }
+    }
+    if step_direction(vm, ent, turnaround, dist)? {
+        return Ok(());
+    }
+
+    // Can't move. If a bridge was pulled out from under the monster it may
+    // not have anywhere to stand at all.
+    phys::set_f32(vm, ent, fields.ideal_yaw, old_dir)?;
+    if !check_bottom(vm, ent) {
+        let flags = qc::read_field_f32(vm, ent, fields.flags) as i32;
+        phys::set_f32(vm, ent, fields.flags, (flags | FL_PARTIALGROUND) as f32)?;
+    }
+    Ok(())
+}
+
+// SV_CloseEnough
+fn close_enough(vm: &Vm, ent: usize, goal: usize, dist: f32) -> bool {
+    let fields = qc::fields_from_context(vm);
+    let absmin = qc::read_field_vec(vm, ent, fields.absmin);
+    let absmax = qc::read_field_vec(vm, ent, fields.absmax);
+    let goal_min = qc::read_field_vec(vm, goal, fields.absmin);
+    let goal_max = qc::read_field_vec(vm, goal, fields.absmax);
+    goal_min.x <= absmax.x + dist
+        && goal_min.y <= absmax.y + dist
+        && goal_min.z <= absmax.z + dist
+        && goal_max.x >= absmin.x - dist
+        && goal_max.y >= absmin.y - dist
+        && goal_max.z >= absmin.z - dist
+}
+
+// SV_LinkEdict(ent, true), with the touches deferred.
+fn link(vm: &mut Vm, ent: usize) -> Result<(), VmError> {
+    let fields = qc::fields_from_context(vm);
+    qc::update_abs_bounds(vm, ent, fields)?;
+    qc::queue_touch(vm, ent);
+    Ok(())
+}
+
+fn anglemod(angle: f32) -> f32 {
+    (360.0 / 65536.0) * (((angle * (65536.0 / 360.0)) as i32) & 65535) as f32
+}
@@ -0,0 +1,625 @@
+// sv_phys.c: what happens between client packets. Each frame runs StartFrame,
+// then lets every entity that isn't a client think and move by its movetype.
+// Clients move when their packets arrive, between PlayerPreThink and
+// PlayerPostThink as SV_RunCmd does.
+
+use crate::qc::{self, vec_add, vec_scale, vec_sub};
+use crate::world::{self, MoveKind, SOLID_NOT, SOLID_TRIGGER};
+use crate::{ClientState, MAX_QC_STEPS};
+use qw_common::{MAX_CLIENTS, Trace, UserCmd, Vec3};
+use qw_qc::{Vm, VmError};
+
+const MOVETYPE_NONE: f32 = 0.0;
+pub const MOVETYPE_STEP: f32 = 4.0;
+const MOVETYPE_FLY: f32 = 5.0;
+const MOVETYPE_TOSS: f32 = 6.0;
+const MOVETYPE_PUSH: f32 = 7.0;
+const MOVETYPE_NOCLIP: f32 = 8.0;
+const MOVETYPE_FLYMISSILE: f32 = 9.0;
+const MOVETYPE_BOUNCE: f32 = 10.0;
+
+pub const FL_FLY: i32 = 1;
+pub const FL_SWIM: i32 = 2;
+pub const FL_ONGROUND: i32 = 512;
+pub const FL_PARTIALGROUND: i32 = 1024;
+
+// sv_maxvelocity's default.
+const MAX_VELOCITY: f32 = 2000.0;
+
+pub struct Frame {
+    pub gravity: f32,
+    pub time: f32,
+    pub frametime: f32,
+}
+
+// SV_Physics
+pub fn run_frame(vm: &mut Vm, frame: &Frame) -> Result<(), VmError> {
+    vm.start_frame();
+    let globals = qc::globals_from_context(vm);
+    if let Some(ofs) = globals.frametime_ofs {
+        vm.write_global_f32(ofs, frame.frametime)?;
+    }
+    qc::set_time(vm, frame.time)?;
+    qc::call_entry(vm, "StartFrame", 0, MAX_QC_STEPS)?;
+
+    let retouch = force_retouch(vm);
+    // Entities spawned during the frame get to run in it too.
+    let mut ent = 0;
+    while ent < vm.edict_count() {
+        if !vm.edict_is_free(ent) {
+            if retouch > 0.0 {
+                link_edict(vm, ent, true, frame.time)?;
+            }
+            // Clients are run from their packets.
+            if ent == 0 || ent > MAX_CLIENTS {
+                run_entity(vm, ent, frame)?;
+            }
+        }
+        ent += 1;
+    }
+    let retouch = force_retouch(vm);
+    if retouch > 0.0
+        && let Some(ofs) = globals.force_retouch_ofs
+    {
+        vm.write_global_f32(ofs, retouch - 1.0)?;
+    }
+    Ok(())
+}
+
+// SV_RunCmd up to the move: the command lands in the player's edict, then
+// PlayerPreThink and the player's own think run.
+pub fn client_pre_think(
+    vm: &mut Vm,
+    client: &mut ClientState,
+    cmd: &UserCmd,
+    frame: &Frame,
+) -> Result<(), VmError> {
+    let ent = client.edict;
+    let fields = qc::fields_from_context(vm);
+    if qc::read_field_f32(vm, ent, fields.fixangle) == 0.0 {
+        set_vec(vm, ent, fields.v_angle, cmd.angles)?;
+        // The model shows a third of the pitch the player looks at.
+        if qc::read_field_f32(vm, ent, fields.health) > 0.0 {
+            let angles = qc::read_field_vec(vm, ent, fields.angles);
+            let angles = Vec3::new(-cmd.angles.x / 3.0, cmd.angles.y, angles.z);
+            set_vec(vm, ent, fields.angles, angles)?;
+        }
+    }
+    set_f32(vm, ent, fields.button0, (cmd.buttons & 1) as f32)?;
+    set_f32(vm, ent, fields.button2, ((cmd.buttons & 2) >> 1) as f32)?;
+    if cmd.impulse != 0 {
+        set_f32(vm, ent, fields.impulse, cmd.impulse as f32)?;
+    }
+    store_player(vm, client)?;
+
+    let globals = qc::globals_from_context(vm);
+    if let Some(ofs) = globals.frametime_ofs {
+        vm.write_global_f32(ofs, (cmd.msec as f32 * 0.001).min(0.1))?;
+    }
+    qc::set_time(vm, frame.time)?;
+    qc::call_entry(vm, "PlayerPreThink", ent, MAX_QC_STEPS)?;
+    run_think(vm, ent, frame)?;
+    load_player(vm, client);
+    Ok(())
+}
+
+// SV_RunCmd after the move, then SV_PostRunCmd: the player touches the
+// triggers where they ended up and PlayerPostThink runs.
+pub fn client_post_think(
+    vm: &mut Vm,
+    client: &mut ClientState,
+    frame: &Frame,
+) -> Result<(), VmError> {
+    let ent = client.edict;
+    store_player(vm, client)?;
+    link_edict(vm, ent, true, frame.time)?;
+    qc::set_time(vm, frame.time)?;
+    qc::call_entry(vm, "PlayerPostThink", ent, MAX_QC_STEPS)?;
+    load_player(vm, client);
+    Ok(())
+}
+
+// The player's edict follows the server's movement, not the other way round,
+// except where QuakeC moves them itself, e.g. through a teleporter.
+pub fn store_player(vm: &mut Vm, client: &ClientState) -> Result<(), VmError> {
+    let ent = client.edict;
+    let fields = qc::fields_from_context(vm);
+    set_vec(vm, ent, fields.origin, client.player_origin)?;
+    set_vec(vm, ent, fields.velocity, client.player_velocity)?;
+    let flags = qc::read_field_f32(vm, ent, fields.flags) as i32;
+    let flags = if client.on_ground {
+        flags | FL_ONGROUND
+    } else {
+        flags & !FL_ONGROUND
+    };
+    set_f32(vm, ent, fields.flags, flags as f32)?;
+    if client.on_ground {
+        set_f32(vm, ent, fields.groundentity, client.ground_entity as f32)?;
+    }
+    qc::update_abs_bounds(vm, ent, fields)
+}
+
+pub fn load_player(vm: &Vm, client: &mut ClientState) {
+    let fields = qc::fields_from_context(vm);
+    let origin = qc::read_field_vec(vm, client.edict, fields.origin);
+    if origin != client.player_origin {
+        client.player_origin = origin;
+        client.ground_z = client.ground_z.min(origin.z);
+    }
+    client.player_velocity = qc::read_field_vec(vm, client.edict, fields.velocity);
+}
+
+// SV_RunEntity
+fn run_entity(vm: &mut Vm, ent: usize, frame: &Frame) -> Result<(), VmError> {
+    let fields = qc::fields_from_context(vm);
+    let movetype = qc::read_field_f32(vm, ent, fields.movetype);
+    if movetype == MOVETYPE_PUSH {
+        physics_pusher(vm, ent, frame)
+    } else if movetype == MOVETYPE_NOCLIP {
+        physics_noclip(vm, ent, frame)
+    } else if movetype == MOVETYPE_STEP {
+        physics_step(vm, ent, frame)
+    } else if movetype == MOVETYPE_TOSS
+        || movetype == MOVETYPE_BOUNCE
+        || movetype == MOVETYPE_FLY
+        || movetype == MOVETYPE_FLYMISSILE
+    {
+        physics_toss(vm, ent, frame)
+    } else {
+        run_think(vm, ent, frame).map(|_| ())
+    }
+}
+
+// SV_RunThink: false once the entity has removed itself.
+fn run_think(vm: &mut Vm, ent: usize, frame: &Frame) -> Result<bool, VmError> {
+    let fields = qc::fields_from_context(vm);
+    loop {
+        let think_time = qc::read_field_f32(vm, ent, fields.nextthink);
+        if think_time <= 0.0 || think_time > frame.time + frame.frametime {
+            return Ok(true);
+        }
+        // Don't let things stay in the past.
+        let think_time = think_time.max(frame.time);
+        set_f32(vm, ent, fields.nextthink, 0.0)?;
+        qc::set_time(vm, think_time)?;
+        let think = qc::read_field_raw(vm, ent, fields.think);
+        call(vm, think, ent, 0)?;
+        if vm.edict_is_free(ent) {
+            return Ok(false);
+        }
+    }
+}
+
+// SV_Physics_Pusher: a pusher thinks by its own clock, ltime, which only runs
+// while it moves.
+fn physics_pusher(vm: &mut Vm, ent: usize, frame: &Frame) -> Result<(), VmError> {
+    let fields = qc::fields_from_context(vm);
+    let old_ltime = qc::read_field_f32(vm, ent, fields.ltime);
+    let think_time = qc::read_field_f32(vm, ent, fields.nextthink);
+    let movetime = if think_time < old_ltime + frame.frametime {
+        (think_time - old_ltime).max(0.0)
+    } else {
+        frame.frametime
+    };
+    if movetime > 0.0 {
+        push_move(vm, ent, movetime, frame)?;
+    }
+
+    let ltime = qc::read_field_f32(vm, ent, fields.ltime);
+    if think_time > old_ltime && think_time <= ltime {
+        let old_origin = qc::read_field_vec(vm, ent, fields.origin);
+        set_f32(vm, ent, fields.nextthink, 0.0)?;
+        qc::set_time(vm, frame.time)?;
+        let think = qc::read_field_raw(vm, ent, fields.think);
+        call(vm, think, ent, 0)?;
+        if vm.edict_is_free(ent) {
+            return Ok(());
+        }
+        // A think that moved the pusher itself still pushes.
+        let origin = qc::read_field_vec(vm, ent, fields.origin);
+        if origin != old_origin {
+            set_vec(vm, ent, fields.origin, old_origin)?;
+            push(vm, ent, vec_sub(origin, old_origin), frame)?;
+        }
+    }
+    Ok(())
+}
+
+// SV_PushMove: the pusher's clock only runs when it isn't blocked.
+fn push_move(vm: &mut Vm, ent: usize, movetime: f32, frame: &Frame) -> Result<(), VmError> {
+    let fields = qc::fields_from_context(vm);
+    let velocity = qc::read_field_vec(vm, ent, fields.velocity);
+    if velocity != Vec3::default() && !push(vm, ent, vec_scale(velocity, movetime), frame)? {
+        return Ok(());
+    }
+    let ltime = qc::read_field_f32(vm, ent, fields.ltime);
+    set_f32(vm, ent, fields.ltime, ltime + movetime)
+}
+
+// SV_Push: moves the pusher, carrying what stands on it and shoving what it
+// runs into. Something that can't be moved out of the way blocks it: the
+// pusher's blocked function runs and everything goes back where it was.
+fn push(vm: &mut Vm, pusher: usize, movement: Vec3, frame: &Frame) -> Result<bool, VmError> {
+    let fields = qc::fields_from_context(vm);
+    let mins = vec_add(qc::read_field_vec(vm, pusher, fields.absmin), movement);
+    let maxs = vec_add(qc::read_field_vec(vm, pusher, fields.absmax), movement);
+    let pusher_origin = qc::read_field_vec(vm, pusher, fields.origin);
+    let pusher_solid = qc::read_field_f32(vm, pusher, fields.solid);
+    set_vec(vm, pusher, fields.origin, vec_add(pusher_origin, movement))?;
+    link_edict(vm, pusher, false, frame.time)?;
+
+    let mut moved = Vec::new();
+    for check in 1..vm.edict_count() {
+        if vm.edict_is_free(check) {
+            continue;
+        }
+        let movetype = qc::read_field_f32(vm, check, fields.movetype);
+        if movetype == MOVETYPE_PUSH || movetype == MOVETYPE_NONE || movetype == MOVETYPE_NOCLIP {
+            continue;
+        }
+
+        // Already stuck in something other than the pusher: leave it be.
+        set_f32(vm, pusher, fields.solid, SOLID_NOT)?;
+        let stuck = world::test_entity_position(vm, check);
+        set_f32(vm, pusher, fields.solid, pusher_solid)?;
+        if stuck {
+            continue;
+        }
+
+        // Anything not riding the pusher has to be in its way to be moved.
+        let flags = qc::read_field_f32(vm, check, fields.flags) as i32;
+        let riding = flags & FL_ONGROUND != 0
+            && qc::read_field_entity(vm, check, fields.groundentity) == pusher;
+        if !riding {
+            let absmin = qc::read_field_vec(vm, check, fields.absmin);
+            let absmax = qc::read_field_vec(vm, check, fields.absmax);
+            if absmin.x >= maxs.x
+                || absmin.y >= maxs.y
+                || absmin.z >= maxs.z
+                || absmax.x <= mins.x
+                || absmax.y <= mins.y
+                || absmax.z <= mins.z
+            {
+                continue;
+            }
+            if !world::test_entity_position(vm, check) {
+                continue;
+            }
+        }
+
+        let origin = qc::read_field_vec(vm, check, fields.origin);
+        moved.push((check, origin));
+        set_vec(vm, check, fields.origin, vec_add(origin, movement))?;
+        if !world::test_entity_position(vm, check) {
+            link_edict(vm, check, false, frame.time)?;
+            continue;
+        }
+
+        // Where it was may be fine after all, if the pusher moved away.
+        set_vec(vm, check, fields.origin, origin)?;
+        if !world::test_entity_position(vm, check) {
+            moved.pop();
+            continue;
+        }
+
+        // Point entities never block, and corpses are squashed flat.
+        let check_mins = qc::read_field_vec(vm, check, fields.mins);
+        let check_maxs = qc::read_field_vec(vm, check, fields.maxs);
+        if check_mins.x == check_maxs.x {
+            link_edict(vm, check, false, frame.time)?;
+            continue;
+        }
+        let solid = qc::read_field_f32(vm, check, fields.solid);
+        if solid == SOLID_NOT || solid == SOLID_TRIGGER {
+            let flat = Vec3::new(0.0, 0.0, check_mins.z);
+            set_vec(vm, check, fields.mins, flat)?;
+            set_vec(vm, check, fields.maxs, flat)?;
+            link_edict(vm, check, false, frame.time)?;
+            continue;
+        }
+
+        set_vec(vm, pusher, fields.origin, pusher_origin)?;
+        link_edict(vm, pusher, false, frame.time)?;
+        let blocked = qc::read_field_raw(vm, pusher, fields.blocked);
+        if blocked != 0 {
+            let globals = qc::globals_from_context(vm);
+            let saved = save_self_other(vm, &globals)?;
+            qc::set_time(vm, frame.time)?;
+            call(vm, blocked, pusher, check)?;
+            restore_self_other(vm, &globals, saved)?;
+        }
+        for (ent, origin) in moved {
+            if !vm.edict_is_free(ent) {
+                set_vec(vm, ent, fields.origin, origin)?;
+                link_edict(vm, ent, false, frame.time)?;
+            }
+        }
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+// SV_Physics_Noclip
+fn physics_noclip(vm: &mut Vm, ent: usize, frame: &Frame) -> Result<(), VmError> {
+    if !run_think(vm, ent, frame)? {
+        return Ok(());
+    }
+    let fields = qc::fields_from_context(vm);
+    let angles = qc::read_field_vec(vm, ent, fields.angles);
+    let avelocity = qc::read_field_vec(vm, ent, fields.avelocity);
+    set_vec(
+        vm,
+        ent,
+        fields.angles,
+        vec_add(angles, vec_scale(avelocity, frame.frametime)),
+    )?;
+    let origin = qc::read_field_vec(vm, ent, fields.origin);
+    let velocity = qc::read_field_vec(vm, ent, fields.velocity);
+    set_vec(
+        vm,
+        ent,
+        fields.origin,
+        vec_add(origin, vec_scale(velocity, frame.frametime)),
+    )?;
+    link_edict(vm, ent, false, frame.time)
+}
+
+// SV_Physics_Step: monsters walk with walkmove, and only fall here.
+fn physics_step(vm: &mut Vm, ent: usize, frame: &Frame) -> Result<(), VmError> {
+    let fields = qc::fields_from_context(vm);
+    let flags = qc::read_field_f32(vm, ent, fields.flags) as i32;
+    if flags & (FL_ONGROUND | FL_FLY | FL_SWIM) == 0 {
+        let mut velocity = qc::read_field_vec(vm, ent, fields.velocity);
+        velocity.z -= frame.gravity * frame.frametime;
+        set_vec(vm, ent, fields.velocity, clamp_velocity(velocity))?;
+        fall(vm, ent, frame, 1.0)?;
+    }
+    if !vm.edict_is_free(ent) {
+        run_think(vm, ent, frame)?;
+    }
+    Ok(())
+}
+
+// SV_Physics_Toss: thrown, bouncing and flying things.
+fn physics_toss(vm: &mut Vm, ent: usize, frame: &Frame) -> Result<(), VmError> {
+    if !run_think(vm, ent, frame)? {
+        return Ok(());
+    }
+    let fields = qc::fields_from_context(vm);
+    let mut velocity = qc::read_field_vec(vm, ent, fields.velocity);
+    let mut flags = qc::read_field_f32(vm, ent, fields.flags) as i32;
+    if velocity.z > 0.0 {
+        flags &= !FL_ONGROUND;
+        set_f32(vm, ent, fields.flags, flags as f32)?;
+    }
+    if flags & FL_ONGROUND != 0 {
+        return Ok(());
+    }
+
+    let movetype = qc::read_field_f32(vm, ent, fields.movetype);
+    if movetype != MOVETYPE_FLY && movetype != MOVETYPE_FLYMISSILE {
+        velocity.z -= frame.gravity * frame.frametime;
+    }
+    set_vec(vm, ent, fields.velocity, clamp_velocity(velocity))?;
+    let angles = qc::read_field_vec(vm, ent, fields.angles);
+    let avelocity = qc::read_field_vec(vm, ent, fields.avelocity);
+    set_vec(
+        vm,
+        ent,
+        fields.angles,
+        vec_add(angles, vec_scale(avelocity, frame.frametime)),
+    )?;
+    let backoff = if movetype == MOVETYPE_BOUNCE {
+        1.5
+    } else {
+        1.0
+    };
+    fall(vm, ent, frame, backoff)
+}
+
+// The move shared by toss and step: push along the velocity, glance off what
+// was hit and come to rest on a floor.
+fn fall(vm: &mut Vm, ent: usize, frame: &Frame, backoff: f32) -> Result<(), VmError> {
+    let fields = qc::fields_from_context(vm);
+    let velocity = qc::read_field_vec(vm, ent, fields.velocity);
+    let (trace, ground) = push_entity(vm, ent, vec_scale(velocity, frame.frametime), frame)?;
+    if trace.fraction == 1.0 || vm.edict_is_free(ent) {
+        return Ok(());
+    }
+
+    let velocity = qc::read_field_vec(vm, ent, fields.velocity);
+    let normal = trace.plane.normal;
+    let velocity = clip_velocity(velocity, normal, backoff);
+    set_vec(vm, ent, fields.velocity, velocity)?;
+    let movetype = qc::read_field_f32(vm, ent, fields.movetype);
+    if normal.z > 0.7 && (velocity.z < 60.0 || movetype != MOVETYPE_BOUNCE) {
+        let flags = qc::read_field_f32(vm, ent, fields.flags) as i32;
+        set_f32(vm, ent, fields.flags, (flags | FL_ONGROUND) as f32)?;
+        set_f32(vm, ent, fields.groundentity, ground as f32)?;
+        set_vec(vm, ent, fields.velocity, Vec3::default())?;
+        set_vec(vm, ent, fields.avelocity, Vec3::default())?;
+    }
+    Ok(())
+}
+
+// SV_PushEntity: moves as far as the world and the solid entities in it
+// allow, touching triggers on the way and whatever it ran into. Also gives
+// the edict it stopped on, the world when it stopped on nothing.
+fn push_entity(
+    vm: &mut Vm,
+    ent: usize,
+    push: Vec3,
+    frame: &Frame,
+) -> Result<(Trace, usize), VmError> {
+    let fields = qc::fields_from_context(vm);
+    let origin = qc::read_field_vec(vm, ent, fields.origin);
+    let mins = qc::read_field_vec(vm, ent, fields.mins);
+    let maxs = qc::read_field_vec(vm, ent, fields.maxs);
+    let movetype = qc::read_field_f32(vm, ent, fields.movetype);
+    let solid = qc::read_field_f32(vm, ent, fields.solid);
+    let kind = if movetype == MOVETYPE_FLYMISSILE {
+        MoveKind::Missile
+    } else if solid == SOLID_TRIGGER || solid == SOLID_NOT {
+        // Only clip against brush models.
+        MoveKind::NoMonsters
+    } else {
+        MoveKind::Normal
+    };
+    let result = world::move_box(vm, origin, mins, maxs, vec_add(origin, push), kind, ent);
+    let hit = result.ent.unwrap_or(0);
+    set_vec(vm, ent, fields.origin, result.trace.endpos)?;
+    link_edict(vm, ent, true, frame.time)?;
+    if result.ent.is_some() {
+        impact(vm, ent, hit, frame.time)?;
+    }
+    Ok((result.trace, hit))
+}
+
+// SV_Impact
+fn impact(vm: &mut Vm, e1: usize, e2: usize, time: f32) -> Result<(), VmError> {
+    let fields = qc::fields_from_context(vm);
+    let globals = qc::globals_from_context(vm);
+    let saved = save_self_other(vm, &globals)?;
+    qc::set_time(vm, time)?;
+    for (ent, other) in [(e1, e2), (e2, e1)] {
+        let touch = qc::read_field_raw(vm, ent, fields.touch);
+        if touch != 0 && qc::read_field_f32(vm, ent, fields.solid) != SOLID_NOT {
+            call(vm, touch, ent, other)?;
+        }
+    }
+    restore_self_other(vm, &globals, saved)
+}
+
+// SV_LinkEdict: updates the entity's box, and with touch_triggers runs the
+// touch function of every trigger it now overlaps (SV_TouchLinks).
+pub fn link_edict(vm: &mut Vm, ent: usize, touch_triggers: bool, time: f32) -> Result<(), VmError> {
+    if ent == 0 || vm.edict_is_free(ent) {
+        return Ok(());
+    }
+    let fields = qc::fields_from_context(vm);
+    qc::update_abs_bounds(vm, ent, fields)?;
+    if !touch_triggers || qc::read_field_f32(vm, ent, fields.solid) == SOLID_NOT {
+        return Ok(());
+    }
+
+    let globals = qc::globals_from_context(vm);
+    let saved = save_self_other(vm, &globals)?;
+    for trigger in 1..vm.edict_count() {
+        if trigger == ent || vm.edict_is_free(trigger) || vm.edict_is_free(ent) {
+            continue;
+        }
+        if qc::read_field_f32(vm, trigger, fields.solid) != SOLID_TRIGGER {
+            continue;
+        }
+        let touch = qc::read_field_raw(vm, trigger, fields.touch);
+        if touch == 0 {
+            continue;
+        }
+        let absmin = qc::read_field_vec(vm, ent, fields.absmin);
+        let absmax = qc::read_field_vec(vm, ent, fields.absmax);
+        let trigger_min = qc::read_field_vec(vm, trigger, fields.absmin);
+        let trigger_max = qc::read_field_vec(vm, trigger, fields.absmax);
+        if absmin.x > trigger_max.x
+            || absmin.y > trigger_max.y
+            || absmin.z > trigger_max.z
+            || absmax.x < trigger_min.x
+            || absmax.y < trigger_min.y
+            || absmax.z < trigger_min.z
+        {
+            continue;
+        }
+        qc::set_time(vm, time)?;
+        call(vm, touch, trigger, ent)?;
+    }
+    restore_self_other(vm, &globals, saved)
+}
+
+fn call(vm: &mut Vm, func: u32, self_ent: usize, other: usize) -> Result<(), VmError> {
+    if func == 0 {
+        return Ok(());
+    }
+    let globals = qc::globals_from_context(vm);
+    if let Some(ofs) = globals.self_ofs {
+        vm.write_global_f32(ofs, self_ent as f32)?;
+    }
+    if let Some(ofs) = globals.other_ofs {
+        vm.write_global_f32(ofs, other as f32)?;
+    }
+    vm.call_function(func as usize, MAX_QC_STEPS)?;
+    touch_moved(vm)
+}
+
+// The SV_LinkEdict(ent, true) walkmove and movetogoal couldn't make from
+// inside the progs, now that they've returned.
+fn touch_moved(vm: &mut Vm) -> Result<(), VmError> {
+    let time = qc::globals_from_context(vm)
+        .time_ofs
+        .and_then(|ofs| vm.read_global_f32(ofs).ok())
+        .unwrap_or(0.0);
+    for ent in qc::take_touched(vm) {
+        link_edict(vm, ent, true, time)?;
+    }
+    Ok(())
+}
+
+fn save_self_other(vm: &Vm, globals: &qc::QcGlobals) -> Result<[u32; 2], VmError> {
+    let read = |ofs: Option<i16>| ofs.map_or(Ok(0), |ofs| vm.read_global_raw(ofs));
+    Ok([read(globals.self_ofs)?, read(globals.other_ofs)?])
+}
+
+fn restore_self_other(
+    vm: &mut Vm,
+    globals: &qc::QcGlobals,
+    saved: [u32; 2],
+) -> Result<(), VmError> {
+    for (ofs, value) in [globals.self_ofs, globals.other_ofs].into_iter().zip(saved) {
+        if let Some(ofs) = ofs {
+            vm.write_global_raw(ofs, value)?;
+        }
+    }
+    Ok(())
+}
+
+fn force_retouch(vm: &Vm) -> f32 {
+    qc::globals_from_context(vm)
+        .force_retouch_ofs
+        .and_then(|ofs| vm.read_global_f32(ofs).ok())
+        .unwrap_or(0.0)
+}
+
+// SV_CheckVelocity
+fn clamp_velocity(velocity: Vec3) -> Vec3 {
+    Vec3::new(
+        velocity.x.clamp(-MAX_VELOCITY, MAX_VELOCITY),
+        velocity.y.clamp(-MAX_VELOCITY, MAX_VELOCITY),
+        velocity.z.clamp(-MAX_VELOCITY, MAX_VELOCITY),
+    )
+}
+
+// ClipVelocity
+fn clip_velocity(velocity: Vec3, normal: Vec3, overbounce: f32) -> Vec3 {
+    let backoff = velocity.dot(normal) * overbounce;
+    let clip = |value: f32, normal: f32| {
+        let value = value - normal * backoff;
+        if value.abs() < 0.1 { 0.0 } else { value }
+    };
+    Vec3::new(
+        clip(velocity.x, normal.x),
+        clip(velocity.y, normal.y),
+        clip(velocity.z, normal.z),
+    )
+}
+
+pub fn set_f32(vm: &mut Vm, ent: usize, field: Option<usize>, value: f32) -> Result<(), VmError> {
+    match field {
+        Some(field) => vm.write_edict_field_f32(ent, field, value),
+        None => Ok(()),
+    }
+}
+
+pub fn set_vec(vm: &mut Vm, ent: usize, field: Option<usize>, value: Vec3) -> Result<(), VmError> {
+    match field {
+        Some(field) => vm.write_edict_field_vec(ent, field, value),
+        None => Ok(()),
+    }
+}
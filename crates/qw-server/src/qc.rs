@@ -1,5 +1,8 @@
-use qw_common::{Entity, EntityState, Vec3};
-use qw_qc::{QcType, Vm, VmError};
+use crate::monster;
+use crate::phys::{FL_FLY, FL_ONGROUND, FL_SWIM, MOVETYPE_STEP};
+use crate::world::{self, MoveKind, SOLID_NOT};
+use qw_common::{BspCollision, Entity, EntityState, MAX_CLIENTS, Vec3};
+use qw_qc::{NUM_SPAWN_PARMS, ProgsDat, QcType, ReloadError, ReloadReport, Vm, VmError};
 use std::collections::HashMap;
 
 pub struct ServerQcContext {
@@ -12,9 +15,20 @@ pub struct ServerQcContext {
     ambient_sounds: Vec<AmbientSound>,
     sounds: Vec<QueuedSound>,
     static_entities: Vec<usize>,
+    changelevel: Option<String>,
+    changelevel_issued: bool,
     rng_state: u32,
     globals: QcGlobals,
     fields: QcFields,
+    // sv.worldmodel's hulls, for the builtins that trace.
+    collision: Option<BspCollision>,
+    // Each spawned client's client_t.spawn_parms, for setspawnparms.
+    client_parms: HashMap<usize, [f32; NUM_SPAWN_PARMS]>,
+    // sv.lastcheck and sv.lastchecktime, for checkclient.
+    last_check: usize,
+    last_check_time: f32,
+    // Entities builtins moved that still have to touch triggers.
+    touched: Vec<usize>,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -65,52 +79,100 @@ impl Default for ServerQcContext {
             ambient_sounds: Vec::new(),
             sounds: Vec::new(),
             static_entities: Vec::new(),
+            changelevel: None,
+            changelevel_issued: false,
             rng_state: 0,
             globals: QcGlobals::default(),
             fields: QcFields::default(),
+            collision: None,
+            client_parms: HashMap::new(),
+            last_check: 0,
+            last_check_time: 0.0,
+            touched: Vec::new(),
         }
     }
 }
 
 #[derive(Default, Clone, Copy)]
-struct QcGlobals {
-    self_ofs: Option<i16>,
-    other_ofs: Option<i16>,
-    world_ofs: Option<i16>,
-    time_ofs: Option<i16>,
-    frametime_ofs: Option<i16>,
-    mapname_ofs: Option<i16>,
-    v_forward_ofs: Option<i16>,
-    v_right_ofs: Option<i16>,
-    v_up_ofs: Option<i16>,
-    trace_allsolid_ofs: Option<i16>,
-    trace_startsolid_ofs: Option<i16>,
-    trace_fraction_ofs: Option<i16>,
-    trace_endpos_ofs: Option<i16>,
-    trace_plane_normal_ofs: Option<i16>,
-    trace_plane_dist_ofs: Option<i16>,
-    trace_ent_ofs: Option<i16>,
-    trace_inopen_ofs: Option<i16>,
-    trace_inwater_ofs: Option<i16>,
+pub struct QcGlobals {
+    pub self_ofs: Option<i16>,
+    pub other_ofs: Option<i16>,
+    pub world_ofs: Option<i16>,
+    pub time_ofs: Option<i16>,
+    pub frametime_ofs: Option<i16>,
+    pub force_retouch_ofs: Option<i16>,
+    pub mapname_ofs: Option<i16>,
+    pub v_forward_ofs: Option<i16>,
+    pub v_right_ofs: Option<i16>,
+    pub v_up_ofs: Option<i16>,
+    pub trace_allsolid_ofs: Option<i16>,
+    pub trace_startsolid_ofs: Option<i16>,
+    pub trace_fraction_ofs: Option<i16>,
+    pub trace_endpos_ofs: Option<i16>,
+    pub trace_plane_normal_ofs: Option<i16>,
+    pub trace_plane_dist_ofs: Option<i16>,
+    pub trace_ent_ofs: Option<i16>,
+    pub trace_inopen_ofs: Option<i16>,
+    pub trace_inwater_ofs: Option<i16>,
 }
 
 #[derive(Default, Clone, Copy)]
-struct QcFields {
-    origin: Option<usize>,
-    mins: Option<usize>,
-    maxs: Option<usize>,
-    size: Option<usize>,
-    absmin: Option<usize>,
-    absmax: Option<usize>,
-    model: Option<usize>,
-    classname: Option<usize>,
-    angles: Option<usize>,
-    frame: Option<usize>,
-    skin: Option<usize>,
-    effects: Option<usize>,
-    colormap: Option<usize>,
-    modelindex: Option<usize>,
-}
+pub struct QcFields {
+    pub origin: Option<usize>,
+    pub mins: Option<usize>,
+    pub maxs: Option<usize>,
+    pub size: Option<usize>,
+    pub absmin: Option<usize>,
+    pub absmax: Option<usize>,
+    pub model: Option<usize>,
+    pub classname: Option<usize>,
+    pub angles: Option<usize>,
+    pub frame: Option<usize>,
+    pub skin: Option<usize>,
+    pub effects: Option<usize>,
+    pub colormap: Option<usize>,
+    pub modelindex: Option<usize>,
+    pub solid: Option<usize>,
+    pub movetype: Option<usize>,
+    pub velocity: Option<usize>,
+    pub avelocity: Option<usize>,
+    pub ltime: Option<usize>,
+    pub nextthink: Option<usize>,
+    pub think: Option<usize>,
+    pub touch: Option<usize>,
+    pub flags: Option<usize>,
+    pub groundentity: Option<usize>,
+    pub health: Option<usize>,
+    pub fixangle: Option<usize>,
+    pub v_angle: Option<usize>,
+    pub button0: Option<usize>,
+    pub button2: Option<usize>,
+    pub impulse: Option<usize>,
+    pub owner: Option<usize>,
+    pub blocked: Option<usize>,
+    pub chain: Option<usize>,
+    pub enemy: Option<usize>,
+    pub goalentity: Option<usize>,
+    pub ideal_yaw: Option<usize>,
+    pub yaw_speed: Option<usize>,
+    pub takedamage: Option<usize>,
+    pub team: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EdictCounts {
+    pub total: usize,
+    pub active: usize,
+    pub view: usize,
+    pub touch: usize,
+    pub step: usize,
+}
+
+// defs.qc's FL_ITEM, which SV_LinkEdict gives a wider box to touch.
+const FL_ITEM: f32 = 256.0;
+const FL_NOTARGET: i32 = 128;
+// defs.qc's DAMAGE_AIM: what aim turns shots towards.
+const DAMAGE_AIM: f32 = 2.0;
 
 pub fn configure_vm(vm: &mut Vm, mapname: &str) -> Result<(), VmError> {
     if vm.context_ref::<ServerQcContext>().is_none() {
@@ -125,11 +187,160 @@ pub fn configure_vm(vm: &mut Vm, mapname: &str) -> Result<(), VmError> {
         ctx.fields = fields;
     }
 
+    vm.reserve_edicts(MAX_CLIENTS + 1);
     init_globals(vm, mapname)?;
     register_builtins(vm);
     Ok(())
 }
 
+// Swaps in a rebuilt progs on a running server; edicts keep their slots so
+// connected clients are unaffected.
+pub fn reload_progs(vm: &mut Vm, progs: ProgsDat) -> Result<ReloadReport, ReloadError> {
+    let report = vm.reload_progs(progs)?;
+    let globals = resolve_globals(vm);
+    let fields = resolve_fields(vm);
+    if let Some(ctx) = vm.context_mut::<ServerQcContext>() {
+        ctx.globals = globals;
+        ctx.fields = fields;
+    }
+    register_builtins(vm);
+    Ok(report)
+}
+
+// SV_SpawnServer copies skill and coop into the cvars QuakeC reads and sets
+// the coop, deathmatch and serverflags globals before any spawn function runs.
+pub fn set_game_mode(vm: &mut Vm, skill: i32, coop: f32, serverflags: f32) -> Result<(), VmError> {
+    if let Some(ctx) = vm.context_mut::<ServerQcContext>() {
+        ctx.cvars.insert("skill".to_string(), skill.to_string());
+        ctx.cvars.insert("coop".to_string(), coop.to_string());
+        ctx.cvars.insert("deathmatch".to_string(), "0".to_string());
+    }
+    if let Some(ofs) = global_offset(vm, "coop") {
+        vm.write_global_f32(ofs, coop)?;
+    }
+    if let Some(ofs) = global_offset(vm, "deathmatch") {
+        vm.write_global_f32(ofs, 0.0)?;
+    }
+    if let Some(ofs) = global_offset(vm, "serverflags") {
+        vm.write_global_f32(ofs, serverflags)?;
+    }
+    Ok(())
+}
+
+// svs.serverflags outlives the level, so a changelevel carries it over.
+pub fn serverflags(vm: &Vm) -> f32 {
+    global_offset(vm, "serverflags")
+        .and_then(|ofs| vm.read_global_f32(ofs).ok())
+        .unwrap_or(0.0)
+}
+
+pub fn set_time(vm: &mut Vm, time: f32) -> Result<(), VmError> {
+    if let Some(ofs) = globals_from_context(vm).time_ofs {
+        vm.write_global_f32(ofs, time)?;
+    }
+    Ok(())
+}
+
+// Runs one of the progs' entry points (StartFrame, PlayerPreThink, ...) with
+// self set as the engine sets it; progs that leave one out are skipped.
+pub fn call_entry(
+    vm: &mut Vm,
+    name: &str,
+    self_ent: usize,
+    max_steps: usize,
+) -> Result<(), VmError> {
+    let Some(func) = vm.progs().function_index(name) else {
+        return Ok(());
+    };
+    let globals = globals_from_context(vm);
+    if let Some(ofs) = globals.self_ofs {
+        vm.write_global_f32(ofs, self_ent as f32)?;
+    }
+    if let Some(ofs) = globals.other_ofs {
+        vm.write_global_f32(ofs, 0.0)?;
+    }
+    vm.call_function(func, max_steps)
+}
+
+// The parm1..parm16 globals a client's spawn parms pass through.
+pub fn spawn_parms(vm: &Vm) -> [f32; NUM_SPAWN_PARMS] {
+    let mut parms = [0.0; NUM_SPAWN_PARMS];
+    for (index, parm) in parms.iter_mut().enumerate() {
+        if let Some(ofs) = global_offset(vm, &format!("parm{}", index + 1)) {
+            *parm = vm.read_global_f32(ofs).unwrap_or(0.0);
+        }
+    }
+    parms
+}
+
+pub fn set_spawn_parms(vm: &mut Vm, parms: &[f32; NUM_SPAWN_PARMS]) -> Result<(), VmError> {
+    for (index, parm) in parms.iter().enumerate() {
+        if let Some(ofs) = global_offset(vm, &format!("parm{}", index + 1)) {
+            vm.write_global_f32(ofs, *parm)?;
+        }
+    }
+    Ok(())
+}
+
+// The level the progs asked for with changelevel, once per level as
+// svs.changelevel_issued allows.
+pub fn take_changelevel(vm: &mut Vm) -> Option<String> {
+    vm.context_mut::<ServerQcContext>()?.changelevel.take()
+}
+
+// The world the level's builtins trace against.
+pub fn set_collision(vm: &mut Vm, collision: Option<BspCollision>) {
+    if let Some(ctx) = vm.context_mut::<ServerQcContext>() {
+        ctx.collision = collision;
+    }
+}
+
+pub fn collision(vm: &Vm) -> Option<&BspCollision> {
+    vm.context_ref::<ServerQcContext>()?.collision.as_ref()
+}
+
+// What a client spawned with, which setspawnparms hands back to the progs.
+pub fn set_client_parms(vm: &mut Vm, ent: usize, parms: [f32; NUM_SPAWN_PARMS]) {
+    if let Some(ctx) = vm.context_mut::<ServerQcContext>() {
+        ctx.client_parms.insert(ent, parms);
+    }
+}
+
+pub fn queue_touch(vm: &mut Vm, ent: usize) {
+    if let Some(ctx) = vm.context_mut::<ServerQcContext>() {
+        ctx.touched.push(ent);
+    }
+}
+
+pub fn take_touched(vm: &mut Vm) -> Vec<usize> {
+    vm.context_mut::<ServerQcContext>()
+        .map(|ctx| std::mem::take(&mut ctx.touched))
+        .unwrap_or_default()
+}
+
+// rand(): the top bits of the generator random uses, as the low ones cycle.
+pub fn rand(vm: &mut Vm) -> u32 {
+    vm.context_mut::<ServerQcContext>()
+        .map(|ctx| next_random(ctx) >> 16)
+        .unwrap_or(0)
+}
+
+pub fn set_entity_origin(
+    vm: &mut Vm,
+    ent: usize,
+    origin: Vec3,
+    angles: Vec3,
+) -> Result<(), VmError> {
+    let fields = fields_from_context(vm);
+    if let Some(field) = fields.origin {
+        vm.write_edict_field_vec(ent, field, origin)?;
+    }
+    if let Some(field) = fields.angles {
+        vm.write_edict_field_vec(ent, field, angles)?;
+    }
+    Ok(())
+}
+
 pub fn snapshot(vm: &Vm) -> ServerQcSnapshot {
     let Some(ctx) = vm.context_ref::<ServerQcContext>() else {
         return ServerQcSnapshot::default();
@@ -153,7 +364,7 @@ pub fn snapshot(vm: &Vm) -> ServerQcSnapshot {
 }
 
 pub fn entity_state(vm: &Vm, ent: usize, model_list: &[String]) -> Option<EntityState> {
-    if ent >= vm.edict_count() {
+    if ent >= vm.edict_count() || vm.edict_is_free(ent) {
         return None;
     }
     let fields = fields_from_context(vm);
@@ -216,7 +427,7 @@ pub fn spawn_entities(vm: &mut Vm, entities: &[Entity], max_steps: usize) -> Res
             continue;
         }
 
-        let ent = vm.alloc_edict();
+        let ent = vm.alloc_edict()?;
         apply_entity_pairs(vm, ent, entity)?;
         vm.write_global_f32(self_ofs, ent as f32)?;
 
@@ -224,12 +435,37 @@ pub fn spawn_entities(vm: &mut Vm, entities: &[Entity], max_steps: usize) -> Res
             vm.call_function(func, max_steps)?;
         } else {
             println!("[server] missing spawn function for {classname}");
+            vm.free_edict(ent)?;
         }
     }
 
     Ok(())
 }
 
+pub fn edict_counts(vm: &Vm) -> EdictCounts {
+    let fields = fields_from_context(vm);
+    let mut counts = EdictCounts {
+        total: vm.edict_count(),
+        ..EdictCounts::default()
+    };
+    for ent in 0..vm.edict_count() {
+        if vm.edict_is_free(ent) {
+            continue;
+        }
+        counts.active += 1;
+        if read_field_raw(vm, ent, fields.model) != 0 {
+            counts.view += 1;
+        }
+        if read_field_f32(vm, ent, fields.solid) != 0.0 {
+            counts.touch += 1;
+        }
+        if read_field_f32(vm, ent, fields.movetype) == MOVETYPE_STEP {
+            counts.step += 1;
+        }
+    }
+    counts
+}
+
 fn resolve_globals(vm: &Vm) -> QcGlobals {
     QcGlobals {
         self_ofs: global_offset(vm, "self"),
@@ -237,6 +473,7 @@ fn resolve_globals(vm: &Vm) -> QcGlobals {
         world_ofs: global_offset(vm, "world"),
         time_ofs: global_offset(vm, "time"),
         frametime_ofs: global_offset(vm, "frametime"),
+        force_retouch_ofs: global_offset(vm, "force_retouch"),
         mapname_ofs: global_offset(vm, "mapname"),
         v_forward_ofs: global_offset(vm, "v_forward"),
         v_right_ofs: global_offset(vm, "v_right"),
@@ -269,6 +506,31 @@ fn resolve_fields(vm: &Vm) -> QcFields {
         effects: field_offset(vm, "effects"),
         colormap: field_offset(vm, "colormap"),
         modelindex: field_offset(vm, "modelindex"),
+        solid: field_offset(vm, "solid"),
+        movetype: field_offset(vm, "movetype"),
+        velocity: field_offset(vm, "velocity"),
+        avelocity: field_offset(vm, "avelocity"),
+        ltime: field_offset(vm, "ltime"),
+        nextthink: field_offset(vm, "nextthink"),
+        think: field_offset(vm, "think"),
+        touch: field_offset(vm, "touch"),
+        flags: field_offset(vm, "flags"),
+        groundentity: field_offset(vm, "groundentity"),
+        health: field_offset(vm, "health"),
+        fixangle: field_offset(vm, "fixangle"),
+        v_angle: field_offset(vm, "v_angle"),
+        button0: field_offset(vm, "button0"),
+        button2: field_offset(vm, "button2"),
+        impulse: field_offset(vm, "impulse"),
+        owner: field_offset(vm, "owner"),
+        blocked: field_offset(vm, "blocked"),
+        chain: field_offset(vm, "chain"),
+        enemy: field_offset(vm, "enemy"),
+        goalentity: field_offset(vm, "goalentity"),
+        ideal_yaw: field_offset(vm, "ideal_yaw"),
+        yaw_speed: field_offset(vm, "yaw_speed"),
+        takedamage: field_offset(vm, "takedamage"),
+        team: field_offset(vm, "team"),
     }
 }
 
@@ -304,7 +566,7 @@ fn register_builtins(vm: &mut Vm) {
     let mut builtin_map = HashMap::new();
     for func in &vm.progs().functions {
         if func.first_statement < 0 {
-            let index = (-func.first_statement) as usize;
+            let index = func.first_statement.unsigned_abs() as usize;
             builtin_map
                 .entry(index)
                 .or_insert_with(|| func.name.clone());
@@ -325,6 +587,8 @@ fn register_builtins(vm: &mut Vm) {
             "ftos" => builtin_ftos,
             "vtos" => builtin_vtos,
             "stof" => builtin_stof,
+            "strzone" => builtin_strzone,
+            "strunzone" => builtin_strunzone,
             "cvar" => builtin_cvar,
             "cvar_set" => builtin_cvar_set,
             "makevectors" => builtin_makevectors,
@@ -336,10 +600,15 @@ fn register_builtins(vm: &mut Vm) {
             "find" => builtin_find,
             "nextent" => builtin_nextent,
             "traceline" => builtin_traceline,
-            "droptofloor" => builtin_true,
-            "checkbottom" => builtin_true,
-            "walkmove" => builtin_true,
+            "droptofloor" => builtin_droptofloor,
+            "checkbottom" => builtin_checkbottom,
+            "walkmove" => builtin_walkmove,
+            "movetogoal" => builtin_movetogoal,
+            "changeyaw" => builtin_changeyaw,
             "pointcontents" => builtin_pointcontents,
+            "findradius" => builtin_findradius,
+            "checkclient" => builtin_checkclient,
+            "aim" => builtin_aim,
             "lightstyle" => builtin_lightstyle,
             "ambientsound" => builtin_ambientsound,
             "sound" => builtin_sound,
@@ -352,7 +621,8 @@ fn register_builtins(vm: &mut Vm) {
             "rint" => builtin_rint,
             "floor" => builtin_floor,
             "ceil" => builtin_ceil,
-            "setspawnparms" => builtin_noop,
+            "changelevel" => builtin_changelevel,
+            "setspawnparms" => builtin_setspawnparms,
             _ => builtin_noop,
         };
 
@@ -364,10 +634,6 @@ fn builtin_noop(vm: &mut Vm) -> Result<(), VmError> {
     vm.set_return_f32(0.0)
 }
 
-fn builtin_true(vm: &mut Vm) -> Result<(), VmError> {
-    vm.set_return_f32(1.0)
-}
-
 fn builtin_dprint(vm: &mut Vm) -> Result<(), VmError> {
     let message = read_param_string(vm, 0);
     if let Some(ctx) = vm.context_mut::<ServerQcContext>() {
@@ -418,16 +684,31 @@ fn builtin_precache_sound(vm: &mut Vm) -> Result<(), VmError> {
     Ok(())
 }
 
+fn builtin_changelevel(vm: &mut Vm) -> Result<(), VmError> {
+    let map = read_param_string(vm, 0);
+    if let Some(ctx) = vm.context_mut::<ServerQcContext>()
+        && !ctx.changelevel_issued
+    {
+        ctx.changelevel_issued = true;
+        ctx.changelevel = Some(map);
+    }
+    Ok(())
+}
+
 fn builtin_random(vm: &mut Vm) -> Result<(), VmError> {
     let value = if let Some(ctx) = vm.context_mut::<ServerQcContext>() {
-        ctx.rng_state = ctx.rng_state.wrapping_mul(1664525).wrapping_add(1013904223);
-        ctx.rng_state as f32 / u32::MAX as f32
+        next_random(ctx) as f32 / u32::MAX as f32
     } else {
         0.0
     };
     vm.set_return_f32(value)
 }
 
+fn next_random(ctx: &mut ServerQcContext) -> u32 {
+    ctx.rng_state = ctx.rng_state.wrapping_mul(1664525).wrapping_add(1013904223);
+    ctx.rng_state
+}
+
 fn builtin_ftos(vm: &mut Vm) -> Result<(), VmError> {
     let value = vm.read_param_f32(0)?;
     vm.set_return_string(&format!("{value}"))
@@ -444,16 +725,30 @@ fn builtin_stof(vm: &mut Vm) -> Result<(), VmError> {
     vm.set_return_f32(parsed)
 }
 
+fn builtin_strzone(vm: &mut Vm) -> Result<(), VmError> {
+    let value = read_param_string(vm, 0);
+    let offset = vm.zone_string(&value)?;
+    vm.set_return_raw(offset as u32)
+}
+
+fn builtin_strunzone(vm: &mut Vm) -> Result<(), VmError> {
+    let offset = vm.read_param_raw(0)? as i32;
+    vm.unzone_string(offset)
+}
+
 fn builtin_cvar(vm: &mut Vm) -> Result<(), VmError> {
     let name = read_param_string(vm, 0);
-    let value = vm
-        .context_ref::<ServerQcContext>()
-        .and_then(|ctx| ctx.cvars.get(&name))
-        .and_then(|value| value.parse::<f32>().ok())
-        .unwrap_or(0.0);
+    let value = cvar_value(vm, &name, 0.0);
     vm.set_return_f32(value)
 }
 
+fn cvar_value(vm: &Vm, name: &str, default: f32) -> f32 {
+    vm.context_ref::<ServerQcContext>()
+        .and_then(|ctx| ctx.cvars.get(name))
+        .and_then(|value| value.parse::<f32>().ok())
+        .unwrap_or(default)
+}
+
 fn builtin_cvar_set(vm: &mut Vm) -> Result<(), VmError> {
     let name = read_param_string(vm, 0);
     let value = read_param_string(vm, 1);
@@ -523,19 +818,37 @@ fn builtin_setmodel(vm: &mut Vm) -> Result<(), VmError> {
     if let Some(ofs) = fields.model {
         vm.write_edict_field_raw(ent, ofs, &[model])?;
     }
+
+    // Brush models are sized by their submodel, so they can be hit and touched.
+    let name = read_param_string(vm, 1);
+    let bounds = name
+        .strip_prefix('*')
+        .and_then(|number| number.parse::<usize>().ok())
+        .and_then(|number| collision(vm)?.models.get(number))
+        .map(|model| (model.mins, model.maxs));
+    if let Some((mins, maxs)) = bounds {
+        if let Some(ofs) = fields.mins {
+            vm.write_edict_field_vec(ent, ofs, mins)?;
+        }
+        if let Some(ofs) = fields.maxs {
+            vm.write_edict_field_vec(ent, ofs, maxs)?;
+        }
+        if let Some(ofs) = fields.size {
+            vm.write_edict_field_vec(ent, ofs, vec_sub(maxs, mins))?;
+        }
+        update_abs_bounds(vm, ent, fields)?;
+    }
     Ok(())
 }
 
 fn builtin_spawn(vm: &mut Vm) -> Result<(), VmError> {
-    let ent = vm.alloc_edict();
+    let ent = vm.alloc_edict()?;
     vm.set_return_f32(ent as f32)
 }
 
 fn builtin_remove(vm: &mut Vm) -> Result<(), VmError> {
     let ent = read_param_entity(vm, 0)?;
-    let field_count = vm.edict_field_count();
-    let zeros = vec![0u32; field_count];
-    vm.write_edict_field_raw(ent, 0, &zeros)
+    vm.free_edict(ent)
 }
 
 fn builtin_find(vm: &mut Vm) -> Result<(), VmError> {
@@ -545,6 +858,10 @@ fn builtin_find(vm: &mut Vm) -> Result<(), VmError> {
 
     let mut index = if start < 0 { 0 } else { start + 1 } as usize;
     while index < vm.edict_count() {
+        if vm.edict_is_free(index) {
+            index += 1;
+            continue;
+        }
         if let Some(value) = read_edict_string(vm, index, field) {
             if value.eq_ignore_ascii_case(&target) {
                 return vm.set_return_f32(index as f32);
@@ -562,6 +879,10 @@ fn builtin_nextent(vm: &mut Vm) -> Result<(), VmError> {
     let fields = fields_from_context(vm);
 
     while index < vm.edict_count() {
+        if vm.edict_is_free(index) {
+            index += 1;
+            continue;
+        }
         if let Some(classname_ofs) = fields.classname {
             if let Some(value) = read_edict_string(vm, index, classname_ofs) {
                 if !value.is_empty() {
@@ -577,46 +898,279 @@ fn builtin_nextent(vm: &mut Vm) -> Result<(), VmError> {
     vm.set_return_f32(0.0)
 }
 
+// PF_traceline
 fn builtin_traceline(vm: &mut Vm) -> Result<(), VmError> {
+    let start = vm.read_param_vec(0)?;
     let end = vm.read_param_vec(1)?;
-    let globals = vm
-        .context_ref::<ServerQcContext>()
-        .map(|ctx| ctx.globals)
-        .unwrap_or_default();
+    let kind = MoveKind::from_f32(vm.read_param_f32(2)?);
+    let pass = read_param_entity(vm, 3)?;
+    let zero = Vec3::default();
+    let result = world::move_box(vm, start, zero, zero, end, kind, pass);
+    let trace = result.trace;
+    let flag = |value: bool| if value { 1.0 } else { 0.0 };
+    let globals = globals_from_context(vm);
 
     if let Some(ofs) = globals.trace_allsolid_ofs {
-        vm.write_global_f32(ofs, 0.0)?;
+        vm.write_global_f32(ofs, flag(trace.allsolid))?;
     }
     if let Some(ofs) = globals.trace_startsolid_ofs {
-        vm.write_global_f32(ofs, 0.0)?;
+        vm.write_global_f32(ofs, flag(trace.startsolid))?;
     }
     if let Some(ofs) = globals.trace_fraction_ofs {
-        vm.write_global_f32(ofs, 1.0)?;
+        vm.write_global_f32(ofs, trace.fraction)?;
     }
     if let Some(ofs) = globals.trace_endpos_ofs {
-        vm.write_global_vec(ofs, end)?;
+        vm.write_global_vec(ofs, trace.endpos)?;
     }
     if let Some(ofs) = globals.trace_plane_normal_ofs {
-        vm.write_global_vec(ofs, Vec3::default())?;
+        vm.write_global_vec(ofs, trace.plane.normal)?;
     }
     if let Some(ofs) = globals.trace_plane_dist_ofs {
-        vm.write_global_f32(ofs, 0.0)?;
+        vm.write_global_f32(ofs, trace.plane.dist)?;
     }
     if let Some(ofs) = globals.trace_ent_ofs {
-        vm.write_global_f32(ofs, 0.0)?;
+        vm.write_global_f32(ofs, result.ent.unwrap_or(0) as f32)?;
     }
     if let Some(ofs) = globals.trace_inopen_ofs {
-        vm.write_global_f32(ofs, 0.0)?;
+        vm.write_global_f32(ofs, flag(trace.inopen))?;
     }
     if let Some(ofs) = globals.trace_inwater_ofs {
-        vm.write_global_f32(ofs, 0.0)?;
+        vm.write_global_f32(ofs, flag(trace.inwater))?;
     }
+    Ok(())
+}
+
+fn builtin_pointcontents(vm: &mut Vm) -> Result<(), VmError> {
+    let point = vm.read_param_vec(0)?;
+    let contents = world::point_contents(vm, point);
+    vm.set_return_f32(contents as f32)
+}
 
+// PF_droptofloor: self falls up to 256 units onto whatever is below.
+fn builtin_droptofloor(vm: &mut Vm) -> Result<(), VmError> {
+    let ent = self_entity(vm);
+    let fields = fields_from_context(vm);
+    let origin = read_field_vec(vm, ent, fields.origin);
+    let mins = read_field_vec(vm, ent, fields.mins);
+    let maxs = read_field_vec(vm, ent, fields.maxs);
+    let end = Vec3::new(origin.x, origin.y, origin.z - 256.0);
+    let result = world::move_box(vm, origin, mins, maxs, end, MoveKind::Normal, ent);
+    if result.trace.fraction == 1.0 || result.trace.allsolid {
+        return vm.set_return_f32(0.0);
+    }
+
+    if let Some(ofs) = fields.origin {
+        vm.write_edict_field_vec(ent, ofs, result.trace.endpos)?;
+    }
+    update_abs_bounds(vm, ent, fields)?;
+    let flags = read_field_f32(vm, ent, fields.flags) as i32;
+    if let Some(ofs) = fields.flags {
+        vm.write_edict_field_f32(ent, ofs, (flags | FL_ONGROUND) as f32)?;
+    }
+    if let Some(ofs) = fields.groundentity {
+        vm.write_edict_field_f32(ent, ofs, result.ent.unwrap_or(0) as f32)?;
+    }
     vm.set_return_f32(1.0)
 }
 
-fn builtin_pointcontents(vm: &mut Vm) -> Result<(), VmError> {
-    vm.set_return_f32(0.0)
+fn builtin_checkbottom(vm: &mut Vm) -> Result<(), VmError> {
+    let ent = read_param_entity(vm, 0)?;
+    let on_ground = monster::check_bottom(vm, ent);
+    vm.set_return_f32(if on_ground { 1.0 } else { 0.0 })
+}
+
+// PF_walkmove: self steps dist along yaw, if it has something to walk on.
+fn builtin_walkmove(vm: &mut Vm) -> Result<(), VmError> {
+    let ent = self_entity(vm);
+    let yaw = vm.read_param_f32(0)?.to_radians();
+    let dist = vm.read_param_f32(1)?;
+    let fields = fields_from_context(vm);
+    let flags = read_field_f32(vm, ent, fields.flags) as i32;
+    if flags & (FL_ONGROUND | FL_FLY | FL_SWIM) == 0 {
+        return vm.set_return_f32(0.0);
+    }
+    let movement = Vec3::new(yaw.cos() * dist, yaw.sin() * dist, 0.0);
+    let moved = monster::movestep(vm, ent, movement, true)?;
+    vm.set_return_f32(if moved { 1.0 } else { 0.0 })
+}
+
+fn builtin_movetogoal(vm: &mut Vm) -> Result<(), VmError> {
+    let ent = self_entity(vm);
+    let dist = vm.read_param_f32(0)?;
+    monster::move_to_goal(vm, ent, dist)
+}
+
+fn builtin_changeyaw(vm: &mut Vm) -> Result<(), VmError> {
+    let ent = self_entity(vm);
+    monster::change_yaw(vm, ent)
+}
+
+// PF_findradius: every solid entity whose middle is within rad, linked
+// through .chain.
+fn builtin_findradius(vm: &mut Vm) -> Result<(), VmError> {
+    let origin = vm.read_param_vec(0)?;
+    let radius = vm.read_param_f32(1)?;
+    let fields = fields_from_context(vm);
+    let mut chain = 0;
+    for ent in 1..vm.edict_count() {
+        if vm.edict_is_free(ent) || read_field_f32(vm, ent, fields.solid) == SOLID_NOT {
+            continue;
+        }
+        let middle = vec_add(
+            read_field_vec(vm, ent, fields.origin),
+            vec_scale(
+                vec_add(
+                    read_field_vec(vm, ent, fields.mins),
+                    read_field_vec(vm, ent, fields.maxs),
+                ),
+                0.5,
+            ),
+        );
+        let delta = vec_sub(origin, middle);
+        if delta.dot(delta).sqrt() > radius {
+            continue;
+        }
+        if let Some(ofs) = fields.chain {
+            vm.write_edict_field_f32(ent, ofs, chain as f32)?;
+        }
+        chain = ent;
+    }
+    vm.set_return_f32(chain as f32)
+}
+
+// PF_checkclient: the client monsters look for, a different one every tenth
+// of a second. No PVS is loaded, so unlike Quake it isn't limited to clients
+// self could see; the progs' visible() traceline decides that.
+fn builtin_checkclient(vm: &mut Vm) -> Result<(), VmError> {
+    let time = globals_from_context(vm)
+        .time_ofs
+        .and_then(|ofs| vm.read_global_f32(ofs).ok())
+        .unwrap_or(0.0);
+    let Some((last_check, last_check_time)) = vm
+        .context_ref::<ServerQcContext>()
+        .map(|ctx| (ctx.last_check, ctx.last_check_time))
+    else {
+        return vm.set_return_f32(0.0);
+    };
+    let check = if time - last_check_time >= 0.1 {
+        let check = new_check_client(vm, last_check);
+        if let Some(ctx) = vm.context_mut::<ServerQcContext>() {
+            ctx.last_check = check;
+            ctx.last_check_time = time;
+        }
+        check
+    } else {
+        last_check
+    };
+
+    let fields = fields_from_context(vm);
+    if check == 0 || vm.edict_is_free(check) || read_field_f32(vm, check, fields.health) <= 0.0 {
+        return vm.set_return_f32(0.0);
+    }
+    vm.set_return_f32(check as f32)
+}
+
+// PF_newcheckclient: the next live, targetable client after check, or check
+// again when there's no other.
+fn new_check_client(vm: &Vm, check: usize) -> usize {
+    let fields = fields_from_context(vm);
+    let check = check.clamp(1, MAX_CLIENTS);
+    let mut ent = check;
+    loop {
+        ent = if ent == MAX_CLIENTS { 1 } else { ent + 1 };
+        if ent == check {
+            return ent;
+        }
+        if vm.edict_is_free(ent)
+            || read_field_f32(vm, ent, fields.health) <= 0.0
+            || read_field_f32(vm, ent, fields.flags) as i32 & FL_NOTARGET != 0
+        {
+            continue;
+        }
+        return ent;
+    }
+}
+
+// PF_aim: v_forward, bent towards the best target within sv_aim of it that
+// a shot from ent would reach.
+fn builtin_aim(vm: &mut Vm) -> Result<(), VmError> {
+    let ent = read_param_entity(vm, 0)?;
+    let fields = fields_from_context(vm);
+    let forward = globals_from_context(vm)
+        .v_forward_ofs
+        .and_then(|ofs| vm.read_global_vec(ofs).ok())
+        .unwrap_or_default();
+    let origin = read_field_vec(vm, ent, fields.origin);
+    let start = Vec3::new(origin.x, origin.y, origin.z + 20.0);
+    let zero = Vec3::default();
+    let team = read_field_f32(vm, ent, fields.team);
+    let teamplay = cvar_value(vm, "teamplay", 0.0) != 0.0;
+    let target = |other: usize| {
+        read_field_f32(vm, other, fields.takedamage) == DAMAGE_AIM
+            && (!teamplay || team <= 0.0 || read_field_f32(vm, other, fields.team) != team)
+    };
+
+    // Straight ahead first.
+    let end = vec_add(start, vec_scale(forward, 2048.0));
+    let ahead = world::move_box(vm, start, zero, zero, end, MoveKind::Normal, ent);
+    if ahead.ent.is_some_and(target) {
+        return vm.set_return_vec(forward);
+    }
+
+    // NetQuake's default; QuakeWorld servers turn autoaim off with 2.
+    let mut best_dist = cvar_value(vm, "sv_aim", 0.93);
+    let mut best = None;
+    for check in 1..vm.edict_count() {
+        if check == ent || vm.edict_is_free(check) || !target(check) {
+            continue;
+        }
+        let middle = vec_add(
+            read_field_vec(vm, check, fields.origin),
+            vec_scale(
+                vec_add(
+                    read_field_vec(vm, check, fields.mins),
+                    read_field_vec(vm, check, fields.maxs),
+                ),
+                0.5,
+            ),
+        );
+        let dist = vec_normalize(vec_sub(middle, start)).dot(forward);
+        // Too far to turn.
+        if dist < best_dist {
+            continue;
+        }
+        let trace = world::move_box(vm, start, zero, zero, middle, MoveKind::Normal, ent);
+        if trace.ent == Some(check) {
+            best_dist = dist;
+            best = Some(check);
+        }
+    }
+
+    let dir = match best {
+        Some(best) => {
+            let dir = vec_sub(read_field_vec(vm, best, fields.origin), origin);
+            let mut end = vec_scale(forward, dir.dot(forward));
+            end.z = dir.z;
+            vec_normalize(end)
+        }
+        None => forward,
+    };
+    vm.set_return_vec(dir)
+}
+
+// PF_setspawnparms: a client's spawn parms, back in parm1..parm16.
+fn builtin_setspawnparms(vm: &mut Vm) -> Result<(), VmError> {
+    let ent = read_param_entity(vm, 0)?;
+    if !(1..=MAX_CLIENTS).contains(&ent) {
+        return Err(VmError::BadEdict(ent as i32));
+    }
+    let parms = vm
+        .context_ref::<ServerQcContext>()
+        .and_then(|ctx| ctx.client_parms.get(&ent).copied());
+    if let Some(parms) = parms {
+        set_spawn_parms(vm, &parms)?;
+    }
+    Ok(())
 }
 
 fn builtin_lightstyle(vm: &mut Vm) -> Result<(), VmError> {
@@ -684,13 +1238,7 @@ fn builtin_vlen(vm: &mut Vm) -> Result<(), VmError> {
 
 fn builtin_normalize(vm: &mut Vm) -> Result<(), VmError> {
     let value = vm.read_param_vec(0)?;
-    let len = (value.x * value.x + value.y * value.y + value.z * value.z).sqrt();
-    let normalized = if len == 0.0 {
-        Vec3::default()
-    } else {
-        vec_scale(value, 1.0 / len)
-    };
-    vm.set_return_vec(normalized)
+    vm.set_return_vec(vec_normalize(value))
 }
 
 fn builtin_vectoyaw(vm: &mut Vm) -> Result<(), VmError> {
@@ -743,7 +1291,14 @@ fn read_param_string(vm: &mut Vm, param: usize) -> String {
     vm.read_param_string(param).unwrap_or_default()
 }
 
-fn global_offset(vm: &Vm, name: &str) -> Option<i16> {
+fn self_entity(vm: &Vm) -> usize {
+    globals_from_context(vm)
+        .self_ofs
+        .and_then(|ofs| vm.read_global_f32(ofs).ok())
+        .map_or(0, |value| value.max(0.0) as usize)
+}
+
+pub fn global_offset(vm: &Vm, name: &str) -> Option<i16> {
     vm.global_def(name).map(|def| def.offset)
 }
 
@@ -757,12 +1312,18 @@ fn field_offset(vm: &Vm, name: &str) -> Option<usize> {
     })
 }
 
-fn fields_from_context(vm: &Vm) -> QcFields {
+pub fn fields_from_context(vm: &Vm) -> QcFields {
     vm.context_ref::<ServerQcContext>()
         .map(|ctx| ctx.fields)
         .unwrap_or_default()
 }
 
+pub fn globals_from_context(vm: &Vm) -> QcGlobals {
+    vm.context_ref::<ServerQcContext>()
+        .map(|ctx| ctx.globals)
+        .unwrap_or_default()
+}
+
 fn read_edict_string(vm: &Vm, ent: usize, field: usize) -> Option<String> {
     let value = vm
         .read_edict_field_raw(ent, field, 1)
@@ -773,20 +1334,44 @@ fn read_edict_string(vm: &Vm, ent: usize, field: usize) -> Option<String> {
     vm.progs().string_at(value as i32).ok()
 }
 
-fn read_field_f32(vm: &Vm, ent: usize, field: Option<usize>) -> f32 {
+pub fn read_field_raw(vm: &Vm, ent: usize, field: Option<usize>) -> u32 {
+    let Some(field) = field else {
+        return 0;
+    };
+    vm.read_edict_field_raw(ent, field, 1)
+        .ok()
+        .and_then(|values| values.first().copied())
+        .unwrap_or(0)
+}
+
+pub fn read_field_f32(vm: &Vm, ent: usize, field: Option<usize>) -> f32 {
     let Some(field) = field else {
         return 0.0;
     };
     vm.read_edict_field_f32(ent, field).unwrap_or(0.0)
 }
 
-fn read_field_vec(vm: &Vm, ent: usize, field: Option<usize>) -> Vec3 {
+pub fn read_field_vec(vm: &Vm, ent: usize, field: Option<usize>) -> Vec3 {
     let Some(field) = field else {
         return Vec3::default();
     };
     vm.read_edict_field_vec(ent, field).unwrap_or_default()
 }
 
+// An entity field, with anything out of range read as the world.
+pub fn read_field_entity(vm: &Vm, ent: usize, field: Option<usize>) -> usize {
+    let value = read_field_f32(vm, ent, field);
+    if value > 0.0 && (value as usize) < vm.edict_count() {
+        value as usize
+    } else {
+        0
+    }
+}
+
+pub fn read_field_string(vm: &Vm, ent: usize, field: Option<usize>) -> Option<String> {
+    read_edict_string(vm, ent, field?)
+}
+
 fn model_index_for_name(name: &str, model_list: &[String]) -> i32 {
     if let Some(rest) = name.strip_prefix('*') {
         if let Ok(number) = rest.trim().parse::<i32>() {
@@ -801,7 +1386,7 @@ fn model_index_for_name(name: &str, model_list: &[String]) -> i32 {
     0
 }
 
-fn update_abs_bounds(vm: &mut Vm, ent: usize, fields: QcFields) -> Result<(), VmError> {
+pub fn update_abs_bounds(vm: &mut Vm, ent: usize, fields: QcFields) -> Result<(), VmError> {
     let (Some(origin_ofs), Some(mins_ofs), Some(maxs_ofs), Some(absmin_ofs), Some(absmax_ofs)) = (
         fields.origin,
         fields.mins,
@@ -815,8 +1400,16 @@ fn update_abs_bounds(vm: &mut Vm, ent: usize, fields: QcFields) -> Result<(), Vm
     let origin = vm.read_edict_field_vec(ent, origin_ofs)?;
     let mins = vm.read_edict_field_vec(ent, mins_ofs)?;
     let maxs = vm.read_edict_field_vec(ent, maxs_ofs)?;
-    vm.write_edict_field_vec(ent, absmin_ofs, vec_add(origin, mins))?;
-    vm.write_edict_field_vec(ent, absmax_ofs, vec_add(origin, maxs))?;
+    // SV_LinkEdict: movement stops an epsilon short of an edge, so boxes are
+    // grown to still touch, and items more so to be easy to pick up.
+    let flags = read_field_f32(vm, ent, fields.flags) as i32;
+    let grow = if flags & FL_ITEM as i32 != 0 {
+        Vec3::new(15.0, 15.0, 0.0)
+    } else {
+        Vec3::new(1.0, 1.0, 1.0)
+    };
+    vm.write_edict_field_vec(ent, absmin_ofs, vec_sub(vec_add(origin, mins), grow))?;
+    vm.write_edict_field_vec(ent, absmax_ofs, vec_add(vec_add(origin, maxs), grow))?;
     Ok(())
 }
 
@@ -836,18 +1429,27 @@ fn angle_vectors(angles: Vec3) -> (Vec3, Vec3, Vec3) {
     (forward, right, up)
 }
 
-fn vec_add(a: Vec3, b: Vec3) -> Vec3 {
+pub fn vec_add(a: Vec3, b: Vec3) -> Vec3 {
     Vec3::new(a.x + b.x, a.y + b.y, a.z + b.z)
 }
 
-fn vec_sub(a: Vec3, b: Vec3) -> Vec3 {
+pub fn vec_sub(a: Vec3, b: Vec3) -> Vec3 {
     Vec3::new(a.x - b.x, a.y - b.y, a.z - b.z)
 }
 
-fn vec_scale(a: Vec3, scale: f32) -> Vec3 {
+pub fn vec_scale(a: Vec3, scale: f32) -> Vec3 {
     Vec3::new(a.x * scale, a.y * scale, a.z * scale)
 }
 
+fn vec_normalize(a: Vec3) -> Vec3 {
+    let len = a.dot(a).sqrt();
+    if len == 0.0 {
+        Vec3::default()
+    } else {
+        vec_scale(a, 1.0 / len)
+    }
+}
+
 fn apply_entity_pairs(vm: &mut Vm, ent: usize, entity: &Entity) -> Result<(), VmError> {
     for (key, value) in entity.pairs() {
         if key.starts_with('_') {
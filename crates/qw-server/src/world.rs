@@ -0,0 +1,207 @@
+// world.c: boxes moving through the world and the solid entities in it. There
+// are no areanodes; a move looks at every edict whose box it could reach.
+
+use crate::qc::{self, QcFields, vec_add, vec_sub};
+use qw_common::{BoxHull, CONTENTS_EMPTY, Hull, Trace, Vec3, hull_point_contents, trace_hull};
+use qw_qc::Vm;
+
+pub const SOLID_NOT: f32 = 0.0;
+pub const SOLID_TRIGGER: f32 = 1.0;
+pub const SOLID_BSP: f32 = 4.0;
+
+// defs.qc's FL_MONSTER: missiles see monsters with a bigger box.
+const FL_MONSTER: i32 = 32;
+
+// SV_Move's type argument, as traceline's nomonsters passes it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveKind {
+    Normal,
+    NoMonsters,
+    Missile,
+}
+
+impl MoveKind {
+    pub fn from_f32(value: f32) -> Self {
+        match value as i32 {
+            1 => MoveKind::NoMonsters,
+            2 => MoveKind::Missile,
+            _ => MoveKind::Normal,
+        }
+    }
+}
+
+// A trace and the edict it stopped on, the world being 0.
+#[derive(Debug, Clone, Copy)]
+pub struct MoveTrace {
+    pub trace: Trace,
+    pub ent: Option<usize>,
+}
+
+// SV_Move: the world first, then every solid entity the move could touch.
+pub fn move_box(
+    vm: &Vm,
+    start: Vec3,
+    mins: Vec3,
+    maxs: Vec3,
+    end: Vec3,
+    kind: MoveKind,
+    pass: usize,
+) -> MoveTrace {
+    let fields = qc::fields_from_context(vm);
+    let mut clip = clip_to_entity(vm, &fields, 0, start, mins, maxs, end);
+    let (mins2, maxs2) = if kind == MoveKind::Missile {
+        (Vec3::new(-15.0, -15.0, -15.0), Vec3::new(15.0, 15.0, 15.0))
+    } else {
+        (mins, maxs)
+    };
+
+    // SV_MoveBounds
+    let box_mins = Vec3::new(
+        start.x.min(end.x) + mins2.x - 1.0,
+        start.y.min(end.y) + mins2.y - 1.0,
+        start.z.min(end.z) + mins2.z - 1.0,
+    );
+    let box_maxs = Vec3::new(
+        start.x.max(end.x) + maxs2.x + 1.0,
+        start.y.max(end.y) + maxs2.y + 1.0,
+        start.z.max(end.z) + maxs2.z + 1.0,
+    );
+
+    // SV_ClipToLinks
+    let pass_has_size = qc::read_field_vec(vm, pass, fields.size).x != 0.0;
+    let pass_owner = qc::read_field_entity(vm, pass, fields.owner);
+    for touch in 1..vm.edict_count() {
+        if touch == pass || vm.edict_is_free(touch) {
+            continue;
+        }
+        let solid = qc::read_field_f32(vm, touch, fields.solid);
+        if solid == SOLID_NOT || solid == SOLID_TRIGGER {
+            continue;
+        }
+        if kind == MoveKind::NoMonsters && solid != SOLID_BSP {
+            continue;
+        }
+        let absmin = qc::read_field_vec(vm, touch, fields.absmin);
+        let absmax = qc::read_field_vec(vm, touch, fields.absmax);
+        if !boxes_overlap(box_mins, box_maxs, absmin, absmax) {
+            continue;
+        }
+        // Point-sized things are only hit by points.
+        if pass_has_size && qc::read_field_vec(vm, touch, fields.size).x == 0.0 {
+            continue;
+        }
+        if clip.trace.allsolid {
+            break;
+        }
+        // Nothing clips against its own missiles, nor they against it.
+        if qc::read_field_entity(vm, touch, fields.owner) == pass || pass_owner == touch {
+            continue;
+        }
+
+        let flags = qc::read_field_f32(vm, touch, fields.flags) as i32;
+        let trace = if flags & FL_MONSTER != 0 {
+            clip_to_entity(vm, &fields, touch, start, mins2, maxs2, end)
+        } else {
+            clip_to_entity(vm, &fields, touch, start, mins, maxs, end)
+        };
+        if trace.trace.allsolid
+            || trace.trace.startsolid
+            || trace.trace.fraction < clip.trace.fraction
+        {
+            let startsolid = clip.trace.startsolid;
+            clip = trace;
+            clip.trace.startsolid |= startsolid;
+        }
+    }
+    clip
+}
+
+// SV_PointContents
+pub fn point_contents(vm: &Vm, point: Vec3) -> i32 {
+    match qc::collision(vm).and_then(|world| world.hull(0, 0)) {
+        Some(hull) => hull_point_contents(&hull, hull.firstclipnode, point),
+        None => CONTENTS_EMPTY,
+    }
+}
+
+// SV_TestEntityPosition: whether the entity is stuck where it stands.
+pub fn test_entity_position(vm: &Vm, ent: usize) -> bool {
+    let fields = qc::fields_from_context(vm);
+    let origin = qc::read_field_vec(vm, ent, fields.origin);
+    let mins = qc::read_field_vec(vm, ent, fields.mins);
+    let maxs = qc::read_field_vec(vm, ent, fields.maxs);
+    move_box(vm, origin, mins, maxs, origin, MoveKind::Normal, ent)
+        .trace
+        .startsolid
+}
+
+// SV_ClipMoveToEntity: the move in the entity's own frame, through the hull
+// SV_HullForEntity gives it.
+fn clip_to_entity(
+    vm: &Vm,
+    fields: &QcFields,
+    ent: usize,
+    start: Vec3,
+    mins: Vec3,
+    maxs: Vec3,
+    end: Vec3,
+) -> MoveTrace {
+    let origin = qc::read_field_vec(vm, ent, fields.origin);
+    let trace = if ent == 0 || qc::read_field_f32(vm, ent, fields.solid) == SOLID_BSP {
+        // Brush models carry hulls for the three sizes that fit through them.
+        let size = maxs.x - mins.x;
+        let hull_index = if size < 3.0 {
+            0
+        } else if size <= 32.0 {
+            1
+        } else {
+            2
+        };
+        let model = brush_model(vm, fields, ent);
+        match model.and_then(|model| qc::collision(vm)?.hull(model, hull_index)) {
+            Some(hull) => {
+                let offset = vec_add(vec_sub(hull.clip_mins, mins), origin);
+                trace_offset(&hull, start, end, offset)
+            }
+            None => Trace {
+                endpos: end,
+                ..Trace::default()
+            },
+        }
+    } else {
+        // Anything else is its box grown by the mover's.
+        let ent_mins = qc::read_field_vec(vm, ent, fields.mins);
+        let ent_maxs = qc::read_field_vec(vm, ent, fields.maxs);
+        let hull = BoxHull::new(vec_sub(ent_mins, maxs), vec_sub(ent_maxs, mins));
+        trace_offset(&hull.hull(), start, end, origin)
+    };
+    let hit = trace.fraction < 1.0 || trace.startsolid;
+    MoveTrace {
+        trace,
+        ent: hit.then_some(ent),
+    }
+}
+
+fn trace_offset(hull: &Hull<'_>, start: Vec3, end: Vec3, offset: Vec3) -> Trace {
+    let mut trace = trace_hull(hull, vec_sub(start, offset), vec_sub(end, offset));
+    trace.endpos = vec_add(trace.endpos, offset);
+    trace
+}
+
+// The world is model 0; a brush entity names its submodel "*N".
+fn brush_model(vm: &Vm, fields: &QcFields, ent: usize) -> Option<usize> {
+    if ent == 0 {
+        return Some(0);
+    }
+    let name = qc::read_field_string(vm, ent, fields.model)?;
+    name.strip_prefix('*')?.parse().ok()
+}
+
+fn boxes_overlap(mins: Vec3, maxs: Vec3, other_mins: Vec3, other_maxs: Vec3) -> bool {
+    mins.x <= other_maxs.x
+        && mins.y <= other_maxs.y
+        && mins.z <= other_maxs.z
+        && maxs.x >= other_mins.x
+        && maxs.y >= other_mins.y
+        && maxs.z >= other_mins.z
+}
@@ -7,6 +7,8 @@ pub struct GlfwWindow {
     glfw: glfw::Glfw,
     window: glfw::Window,
     events: Receiver<(f64, glfw::WindowEvent)>,
+    cursor_captured: bool,
+    last_cursor: Option<(f64, f64)>,
     pending_events: Vec<WindowEvent>,
 }
 
@@ -29,16 +31,22 @@ impl GlfwWindow {
             )
             .expect("failed to create GLFW window");
         window.set_key_polling(true);
+        window.set_char_polling(true);
         window.set_mouse_button_polling(true);
+        window.set_scroll_polling(true);
+        window.set_cursor_pos_polling(true);
         window.set_framebuffer_size_polling(true);
         window.set_close_polling(true);
         window.make_current();
+        glfw.set_swap_interval(swap_interval(config.vsync));
 
         Self {
             config,
             glfw,
             window,
             events,
+            cursor_captured: false,
+            last_cursor: None,
             pending_events: Vec::new(),
         }
     }
@@ -46,6 +54,40 @@ impl GlfwWindow {
     pub fn poll_events(&mut self) -> Vec<WindowEvent> {
         self.glfw.poll_events();
         for (_, event) in glfw::flush_messages(&self.events) {
+            // A wheel notch is a press and release at once, as Quake's
+            // MWHEELUP/MWHEELDOWN keys are.
+            if let glfw::WindowEvent::Scroll(_, y) = event {
+                if let Some(key) = map_scroll(y) {
+                    for action in [Action::Press, Action::Release] {
+                        self.pending_events.push(WindowEvent::Key { key, action });
+                    }
+                }
+                continue;
+            }
+            if let glfw::WindowEvent::CursorPos(x, y) = event {
+                // A disabled cursor reports unbounded virtual positions, so
+                // the difference is the motion.
+                if let Some((last_x, last_y)) = self.last_cursor.replace((x, y))
+                    && self.cursor_captured
+                {
+                    self.pending_events.push(WindowEvent::MouseMotion {
+                        dx: (x - last_x) as f32,
+                        dy: (y - last_y) as f32,
+                    });
+                }
+                if !self.cursor_captured {
+                    // GLFW reports screen coordinates, which HiDPI displays
+                    // scale up for the framebuffer.
+                    let (width, _) = self.window.get_size();
+                    let (fb_width, _) = self.window.get_framebuffer_size();
+                    let scale = fb_width as f64 / width.max(1) as f64;
+                    self.pending_events.push(WindowEvent::CursorMoved {
+                        x: (x * scale) as f32,
+                        y: (y * scale) as f32,
+                    });
+                }
+                continue;
+            }
             if let Some(mapped) = map_event(event) {
                 if let WindowEvent::Resized(width, height) = mapped {
                     self.config.width = width;
@@ -73,11 +115,38 @@ impl GlfwWindow {
         self.window.set_should_close(true);
     }
 
+    // Hides and locks the cursor for mouse look, with unaccelerated motion
+    // where the platform has it.
+    pub fn set_cursor_captured(&mut self, captured: bool) {
+        let mode = if captured {
+            glfw::CursorMode::Disabled
+        } else {
+            glfw::CursorMode::Normal
+        };
+        self.window.set_cursor_mode(mode);
+        // Setting it where it's unsupported is an error, which FAIL_ON_ERRORS
+        // turns into a panic.
+        if self.glfw.supports_raw_motion() {
+            self.window.set_raw_mouse_motion(captured);
+        }
+        self.cursor_captured = captured;
+        self.last_cursor = None;
+    }
+
+    pub fn cursor_captured(&self) -> bool {
+        self.cursor_captured
+    }
+
     pub fn size(&self) -> (u32, u32) {
         let (width, height) = self.window.get_framebuffer_size();
         (width.max(1) as u32, height.max(1) as u32)
     }
 
+    pub fn set_vsync(&mut self, vsync: bool) {
+        self.glfw.set_swap_interval(swap_interval(vsync));
+        self.config.vsync = vsync;
+    }
+
     pub fn set_title(&mut self, title: impl Into<String>) {
         let title = title.into();
         self.window.set_title(&title);
@@ -97,6 +166,14 @@ impl GlfwWindow {
     }
 }
 
+fn swap_interval(vsync: bool) -> glfw::SwapInterval {
+    if vsync {
+        glfw::SwapInterval::Sync(1)
+    } else {
+        glfw::SwapInterval::None
+    }
+}
+
 fn map_event(event: glfw::WindowEvent) -> Option<WindowEvent> {
     match event {
         glfw::WindowEvent::Close => Some(WindowEvent::CloseRequested),
@@ -109,6 +186,7 @@ fn map_event(event: glfw::WindowEvent) -> Option<WindowEvent> {
             key: map_key(key, scancode),
             action: map_action(action),
         }),
+        glfw::WindowEvent::Char(ch) => Some(WindowEvent::Char(ch)),
         glfw::WindowEvent::MouseButton(button, action, _) => {
             map_mouse_button(button).map(|key| WindowEvent::Key {
                 key,
@@ -130,7 +208,7 @@ fn map_action(action: glfw::Action) -> Action {
 fn map_key(key: glfw::Key, scancode: i32) -> Key {
     match key {
         glfw::Key::Escape => Key::Escape,
-        glfw::Key::Enter | glfw::Key::KpEnter => Key::Enter,
+        glfw::Key::Enter => Key::Enter,
         glfw::Key::Space => Key::Space,
         glfw::Key::Tab => Key::Tab,
         glfw::Key::Backspace => Key::Backspace,
@@ -141,8 +219,48 @@ fn map_key(key: glfw::Key, scancode: i32) -> Key {
         glfw::Key::Down => Key::Down,
         glfw::Key::Left => Key::Left,
         glfw::Key::Right => Key::Right,
+        glfw::Key::F1 => Key::F1,
+        glfw::Key::F2 => Key::F2,
+        glfw::Key::F3 => Key::F3,
+        glfw::Key::F4 => Key::F4,
+        glfw::Key::F5 => Key::F5,
+        glfw::Key::F6 => Key::F6,
+        glfw::Key::F7 => Key::F7,
+        glfw::Key::F8 => Key::F8,
+        glfw::Key::F9 => Key::F9,
+        glfw::Key::F10 => Key::F10,
+        glfw::Key::F11 => Key::F11,
+        glfw::Key::F12 => Key::F12,
+        glfw::Key::Insert => Key::Ins,
+        glfw::Key::Delete => Key::Del,
+        glfw::Key::PageDown => Key::PgDn,
+        glfw::Key::PageUp => Key::PgUp,
+        glfw::Key::Home => Key::Home,
+        glfw::Key::End => Key::End,
+        glfw::Key::Pause => Key::Pause,
+        glfw::Key::Kp7 => Key::KpHome,
+        glfw::Key::Kp8 => Key::KpUpArrow,
+        glfw::Key::Kp9 => Key::KpPgUp,
+        glfw::Key::Kp4 => Key::KpLeftArrow,
+        glfw::Key::Kp5 => Key::Kp5,
+        glfw::Key::Kp6 => Key::KpRightArrow,
+        glfw::Key::Kp1 => Key::KpEnd,
+        glfw::Key::Kp2 => Key::KpDownArrow,
+        glfw::Key::Kp3 => Key::KpPgDn,
+        glfw::Key::KpEnter => Key::KpEnter,
+        glfw::Key::Kp0 => Key::KpIns,
+        glfw::Key::KpDecimal => Key::KpDel,
+        glfw::Key::KpDivide => Key::KpSlash,
+        glfw::Key::KpSubtract => Key::KpMinus,
+        glfw::Key::KpAdd => Key::KpPlus,
+        glfw::Key::KpMultiply => Key::Char(b'*'),
+        glfw::Key::KpEqual => Key::Char(b'='),
         glfw::Key::Unknown => Key::Other(scancode.max(0) as u16),
-        other => Key::Other(other as u16),
+        // GLFW numbers the printable keys by their US-layout ASCII code.
+        other => match u8::try_from(other as i32) {
+            Ok(code) if code.is_ascii_graphic() => Key::Char(code.to_ascii_lowercase()),
+            _ => Key::Other(other as u16),
+        },
     }
 }
 
@@ -151,10 +269,22 @@ fn map_mouse_button(button: glfw::MouseButton) -> Option<Key> {
         glfw::MouseButton::Button1 => Some(Key::Mouse1),
         glfw::MouseButton::Button2 => Some(Key::Mouse2),
         glfw::MouseButton::Button3 => Some(Key::Mouse3),
+        glfw::MouseButton::Button4 => Some(Key::Mouse4),
+        glfw::MouseButton::Button5 => Some(Key::Mouse5),
         _ => None,
     }
 }
 
+fn map_scroll(y: f64) -> Option<Key> {
+    if y > 0.0 {
+        Some(Key::MWheelUp)
+    } else if y < 0.0 {
+        Some(Key::MWheelDown)
+    } else {
+        None
+    }
+}
+
 #[cfg(all(test, feature = "glfw"))]
 mod tests {
     use super::*;
@@ -164,6 +294,18 @@ mod tests {
         assert_eq!(map_key(glfw::Key::Escape, 0), Key::Escape);
         assert_eq!(map_key(glfw::Key::LeftShift, 0), Key::Shift);
         assert_eq!(map_key(glfw::Key::RightAlt, 0), Key::Alt);
+        assert_eq!(map_key(glfw::Key::A, 0), Key::Char(b'a'));
+        assert_eq!(map_key(glfw::Key::Num7, 0), Key::Char(b'7'));
+        assert_eq!(map_key(glfw::Key::Semicolon, 0), Key::Char(b';'));
+        assert_eq!(map_key(glfw::Key::Kp8, 0), Key::KpUpArrow);
+        assert_eq!(map_key(glfw::Key::F12, 0), Key::F12);
+    }
+
+    #[test]
+    fn maps_scroll_to_wheel_keys() {
+        assert_eq!(map_scroll(1.0), Some(Key::MWheelUp));
+        assert_eq!(map_scroll(-2.0), Some(Key::MWheelDown));
+        assert_eq!(map_scroll(0.0), None);
     }
 
     #[test]
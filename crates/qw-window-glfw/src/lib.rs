@@ -4,6 +4,7 @@ pub struct WindowConfig {
     pub height: u32,
     pub title: String,
     pub resizable: bool,
+    pub vsync: bool,
 }
 
 impl Default for WindowConfig {
@@ -13,12 +14,16 @@ impl Default for WindowConfig {
             height: 720,
             title: "RustQuake".to_string(),
             resizable: true,
+            vsync: true,
         }
     }
 }
 
+// Quake's key set. Printable keys are `Char` with letters lowercased, as
+// keys.c numbers them by their ASCII code.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Key {
+    Char(u8),
     Escape,
     Enter,
     Space,
@@ -31,12 +36,214 @@ pub enum Key {
     Down,
     Left,
     Right,
+    F1,
+    F2,
+    F3,
+    F4,
+    F5,
+    F6,
+    F7,
+    F8,
+    F9,
+    F10,
+    F11,
+    F12,
+    Ins,
+    Del,
+    PgDn,
+    PgUp,
+    Home,
+    End,
+    Pause,
+    KpHome,
+    KpUpArrow,
+    KpPgUp,
+    KpLeftArrow,
+    Kp5,
+    KpRightArrow,
+    KpEnd,
+    KpDownArrow,
+    KpPgDn,
+    KpEnter,
+    KpIns,
+    KpDel,
+    KpSlash,
+    KpMinus,
+    KpPlus,
     Mouse1,
     Mouse2,
     Mouse3,
+    Mouse4,
+    Mouse5,
+    MWheelUp,
+    MWheelDown,
+    // JOY1-JOY4 and AUX1-AUX32.
+    Joy(u8),
+    Aux(u8),
     Other(u16),
 }
 
+const KEY_NAMES: [(&str, Key); 54] = [
+    ("TAB", Key::Tab),
+    ("ENTER", Key::Enter),
+    ("ESCAPE", Key::Escape),
+    ("SPACE", Key::Space),
+    ("BACKSPACE", Key::Backspace),
+    ("UPARROW", Key::Up),
+    ("DOWNARROW", Key::Down),
+    ("LEFTARROW", Key::Left),
+    ("RIGHTARROW", Key::Right),
+    ("ALT", Key::Alt),
+    ("CTRL", Key::Ctrl),
+    ("SHIFT", Key::Shift),
+    ("F1", Key::F1),
+    ("F2", Key::F2),
+    ("F3", Key::F3),
+    ("F4", Key::F4),
+    ("F5", Key::F5),
+    ("F6", Key::F6),
+    ("F7", Key::F7),
+    ("F8", Key::F8),
+    ("F9", Key::F9),
+    ("F10", Key::F10),
+    ("F11", Key::F11),
+    ("F12", Key::F12),
+    ("INS", Key::Ins),
+    ("DEL", Key::Del),
+    ("PGDN", Key::PgDn),
+    ("PGUP", Key::PgUp),
+    ("HOME", Key::Home),
+    ("END", Key::End),
+    ("KP_HOME", Key::KpHome),
+    ("KP_UPARROW", Key::KpUpArrow),
+    ("KP_PGUP", Key::KpPgUp),
+    ("KP_LEFTARROW", Key::KpLeftArrow),
+    ("KP_5", Key::Kp5),
+    ("KP_RIGHTARROW", Key::KpRightArrow),
+    ("KP_END", Key::KpEnd),
+    ("KP_DOWNARROW", Key::KpDownArrow),
+    ("KP_PGDN", Key::KpPgDn),
+    ("KP_ENTER", Key::KpEnter),
+    ("KP_INS", Key::KpIns),
+    ("KP_DEL", Key::KpDel),
+    ("KP_SLASH", Key::KpSlash),
+    ("KP_MINUS", Key::KpMinus),
+    ("KP_PLUS", Key::KpPlus),
+    ("MOUSE1", Key::Mouse1),
+    ("MOUSE2", Key::Mouse2),
+    ("MOUSE3", Key::Mouse3),
+    ("MOUSE4", Key::Mouse4),
+    ("MOUSE5", Key::Mouse5),
+    ("PAUSE", Key::Pause),
+    ("MWHEELUP", Key::MWheelUp),
+    ("MWHEELDOWN", Key::MWheelDown),
+    ("SEMICOLON", Key::Char(b';')),
+];
+
+impl Key {
+    // Key_StringToKeynum: a single character is that key, anything longer
+    // is looked up by name.
+    pub fn from_name(name: &str) -> Option<Key> {
+        if let [c] = name.as_bytes() {
+            return c
+                .is_ascii_graphic()
+                .then_some(Key::Char(c.to_ascii_lowercase()));
+        }
+        let upper = name.to_ascii_uppercase();
+        if let Some(n) = upper.strip_prefix("JOY") {
+            return n.parse().ok().filter(|n| (1..=4).contains(n)).map(Key::Joy);
+        }
+        if let Some(n) = upper.strip_prefix("AUX") {
+            return n
+                .parse()
+                .ok()
+                .filter(|n| (1..=32).contains(n))
+                .map(Key::Aux);
+        }
+        KEY_NAMES
+            .iter()
+            .find(|(key_name, _)| *key_name == upper)
+            .map(|(_, key)| *key)
+    }
+
+    // Key_KeynumToString; keys the table doesn't know have no name to bind.
+    pub fn name(self) -> Option<String> {
+        if let Some((name, _)) = KEY_NAMES.iter().find(|(_, key)| *key == self) {
+            return Some(name.to_string());
+        }
+        match self {
+            Key::Char(c) if c.is_ascii_graphic() => Some((c as char).to_string()),
+            Key::Joy(n) => Some(format!("JOY{n}")),
+            Key::Aux(n) => Some(format!("AUX{n}")),
+            _ => None,
+        }
+    }
+
+    // The number keys.c gives the key, which `+command` bindings pass along
+    // so a button held by two keys stays down until both are released.
+    pub fn keynum(self) -> u32 {
+        match self {
+            Key::Char(c) => u32::from(c),
+            Key::Tab => 9,
+            Key::Enter => 13,
+            Key::Escape => 27,
+            Key::Space => 32,
+            Key::Backspace => 127,
+            Key::Up => 128,
+            Key::Down => 129,
+            Key::Left => 130,
+            Key::Right => 131,
+            Key::Alt => 132,
+            Key::Ctrl => 133,
+            Key::Shift => 134,
+            Key::F1 => 135,
+            Key::F2 => 136,
+            Key::F3 => 137,
+            Key::F4 => 138,
+            Key::F5 => 139,
+            Key::F6 => 140,
+            Key::F7 => 141,
+            Key::F8 => 142,
+            Key::F9 => 143,
+            Key::F10 => 144,
+            Key::F11 => 145,
+            Key::F12 => 146,
+            Key::Ins => 147,
+            Key::Del => 148,
+            Key::PgDn => 149,
+            Key::PgUp => 150,
+            Key::Home => 151,
+            Key::End => 152,
+            Key::KpHome => 160,
+            Key::KpUpArrow => 161,
+            Key::KpPgUp => 162,
+            Key::KpLeftArrow => 163,
+            Key::Kp5 => 164,
+            Key::KpRightArrow => 165,
+            Key::KpEnd => 166,
+            Key::KpDownArrow => 167,
+            Key::KpPgDn => 168,
+            Key::KpEnter => 169,
+            Key::KpIns => 170,
+            Key::KpDel => 171,
+            Key::KpSlash => 172,
+            Key::KpMinus => 173,
+            Key::KpPlus => 174,
+            Key::Mouse1 => 200,
+            Key::Mouse2 => 201,
+            Key::Mouse3 => 202,
+            Key::Joy(n) => 202 + u32::from(n),
+            Key::Aux(n) => 206 + u32::from(n),
+            Key::MWheelUp => 239,
+            Key::MWheelDown => 240,
+            Key::Mouse4 => 241,
+            Key::Mouse5 => 242,
+            Key::Pause => 255,
+            Key::Other(code) => 256 + u32::from(code),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Action {
     Press,
@@ -44,11 +251,17 @@ pub enum Action {
     Repeat,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum WindowEvent {
     CloseRequested,
     Resized(u32, u32),
     Key { key: Key, action: Action },
+    // Text as typed, after the keyboard layout and shift have had their say.
+    Char(char),
+    // Relative motion, only while the cursor is captured.
+    MouseMotion { dx: f32, dy: f32 },
+    // Where the cursor is in framebuffer pixels, only while it's free.
+    CursorMoved { x: f32, y: f32 },
 }
 
 #[cfg(feature = "glfw")]
@@ -80,6 +293,22 @@ mod tests {
         assert_eq!(window.config().title, "Unit");
     }
 
+    #[test]
+    fn tracks_cursor_capture() {
+        let mut window = GlfwWindow::new(WindowConfig::default());
+        assert!(!window.cursor_captured());
+        window.set_cursor_captured(true);
+        assert!(window.cursor_captured());
+    }
+
+    #[test]
+    fn tracks_vsync() {
+        let mut window = GlfwWindow::new(WindowConfig::default());
+        assert!(window.config().vsync);
+        window.set_vsync(false);
+        assert!(!window.config().vsync);
+    }
+
     #[test]
     fn collects_pending_events() {
         let mut window = GlfwWindow::new(WindowConfig::default());
@@ -88,4 +317,21 @@ mod tests {
         assert_eq!(events, vec![WindowEvent::CloseRequested]);
         assert!(window.poll_events().is_empty());
     }
+
+    #[test]
+    fn maps_key_names_both_ways() {
+        assert_eq!(Key::from_name("uparrow"), Some(Key::Up));
+        assert_eq!(Key::from_name("A"), Some(Key::Char(b'a')));
+        assert_eq!(Key::from_name("SEMICOLON"), Some(Key::Char(b';')));
+        assert_eq!(Key::from_name("aux32"), Some(Key::Aux(32)));
+        assert_eq!(Key::from_name("JOY5"), None);
+        assert_eq!(Key::from_name("nosuchkey"), None);
+        assert_eq!(Key::Char(b';').name().as_deref(), Some("SEMICOLON"));
+        assert_eq!(Key::Char(b'7').name().as_deref(), Some("7"));
+        assert_eq!(Key::KpEnter.name().as_deref(), Some("KP_ENTER"));
+        assert_eq!(Key::Joy(2).name().as_deref(), Some("JOY2"));
+        assert_eq!(Key::Other(5).name(), None);
+        assert_eq!(Key::Mouse1.keynum(), 200);
+        assert_eq!(Key::Aux(32).keynum(), 238);
+    }
 }
@@ -5,6 +5,7 @@ use std::ffi::c_void;
 pub struct GlfwWindow {
     config: WindowConfig,
     open: bool,
+    cursor_captured: bool,
     pending_events: Vec<WindowEvent>,
 }
 
@@ -13,6 +14,7 @@ impl GlfwWindow {
         Self {
             config,
             open: true,
+            cursor_captured: false,
             pending_events: Vec::new(),
         }
     }
@@ -33,10 +35,22 @@ impl GlfwWindow {
         self.open = false;
     }
 
+    pub fn set_cursor_captured(&mut self, captured: bool) {
+        self.cursor_captured = captured;
+    }
+
+    pub fn cursor_captured(&self) -> bool {
+        self.cursor_captured
+    }
+
     pub fn size(&self) -> (u32, u32) {
         (self.config.width, self.config.height)
     }
 
+    pub fn set_vsync(&mut self, vsync: bool) {
+        self.config.vsync = vsync;
+    }
+
     pub fn set_title(&mut self, title: impl Into<String>) {
         self.config.title = title.into();
     }